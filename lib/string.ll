@@ -0,0 +1,26 @@
+use win32::memory::_GetProcessHeap@0;
+use win32::memory::_HeapAlloc@12;
+
+/// Concatenate two strings into a newly allocated one.
+pub fun concat(left: str, right: str) -> str {
+    let len = left.len + right.len;
+    let ptr = _HeapAlloc@12(_GetProcessHeap@0(), 0, len);
+
+    copy_bytes(ptr, left.ptr, left.len);
+    copy_bytes(ptr + left.len, right.ptr, right.len);
+
+    let result: str;
+    result.ptr = ptr;
+    result.len = len;
+    return result;
+}
+
+pub fun operator+(left: str, right: str) -> str {
+    return concat(left, right);
+}
+
+fun copy_bytes(dest: &byte, src: &byte, len: int) {
+    for i in 0..len {
+        *(dest + i) = *(src + i);
+    }
+}
@@ -0,0 +1,13 @@
+pub extern fun _GetProcessHeap@0() -> int;
+
+pub extern fun _HeapAlloc@12(
+  hHeap: int,
+  dwFlags: int,
+  dwBytes: int,
+) -> &byte;
+
+pub extern fun _HeapFree@12(
+  hHeap: int,
+  dwFlags: int,
+  lpMem: &byte,
+) -> bool;
\ No newline at end of file
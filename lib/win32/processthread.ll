@@ -1,6 +1,6 @@
-extern fun _ExitProcess@4(exitCode: int);
+pub extern fun _ExitProcess@4(exitCode: int);
 
-extern fun _CreateThread@24(
+pub extern fun _CreateThread@24(
   lpThreadAttributes: &void,
   dwStackSize: int,
   lpStartAddress: (&int) -> int,
@@ -1,9 +1,9 @@
-extern fun _WaitForSingleObject@8(
+pub extern fun _WaitForSingleObject@8(
   hHandle: int,
   dwMilliseconds: int,
 ) -> int;
 
-extern fun _WaitForMultipleObjects@16(
+pub extern fun _WaitForMultipleObjects@16(
   nCount: int,
   lpHandles: &int,
   bWaitAll: bool,
@@ -0,0 +1,119 @@
+use std::collections::HashSet;
+
+use crate::mid::analyse::cfg::Cfg;
+use crate::mid::ir::{Block, Function, Program, Terminator};
+
+/// The order to emit the reachable blocks of `func` in, the entry block comes first. The backends don't emit jumps to
+/// the block that comes next, so this tries to put the likely successor of each block right after it:
+/// * successors inside the current loop are preferred over loop exits, so loop bodies follow their header
+/// * cold blocks, which can only end in `unreachable` like failed checks, are placed at the very end
+/// * otherwise the first successor in terminator order is preferred, which is the true target of branches
+pub fn block_order(prog: &Program, func: Function) -> Vec<Block> {
+    let cfg = Cfg::new(prog, func);
+    let cold = cold_blocks(prog, &cfg);
+
+    let mut order = vec![];
+    let mut placed = HashSet::new();
+    //deferred successors, the most recently deferred ones are placed first to keep related blocks together
+    let mut pending = vec![];
+    let mut pending_cold = vec![];
+
+    let mut next = Some(cfg.entry());
+    loop {
+        let block = match next.take() {
+            Some(block) => block,
+            None => match pending.pop().or_else(|| pending_cold.pop()) {
+                Some(block) => block,
+                None => break,
+            },
+        };
+        if !placed.insert(block) {
+            continue;
+        }
+        order.push(block);
+
+        let curr_loop = cfg.loop_of(block);
+        let mut candidates = vec![];
+        for succ in cfg.successors(block) {
+            if placed.contains(&succ) || candidates.contains(&succ) {
+                continue;
+            }
+            if cold.contains(&succ) {
+                pending_cold.push(succ);
+            } else {
+                candidates.push(succ);
+            }
+        }
+
+        if !candidates.is_empty() {
+            let best = candidates.iter()
+                .position(|succ| curr_loop.is_none_or(|l| l.blocks.contains(succ)))
+                .unwrap_or(0);
+            next = Some(candidates.remove(best));
+        }
+        pending.extend(candidates.into_iter().rev());
+    }
+
+    order
+}
+
+/// The blocks that can't reach a return, so every path trough them ends in `unreachable`.
+fn cold_blocks(prog: &Program, cfg: &Cfg) -> HashSet<Block> {
+    let mut cold = HashSet::new();
+
+    //blocks only become cold, so iterating in postorder until nothing changes finds the least fixpoint
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &block in cfg.reverse_postorder().iter().rev() {
+            if cold.contains(&block) {
+                continue;
+            }
+
+            let is_cold = match prog.get_block(block).terminator {
+                Terminator::Unreachable => true,
+                Terminator::Return { value: _ } => false,
+                Terminator::Jump { target: _ } | Terminator::Branch { cond: _, true_target: _, false_target: _ } | Terminator::Switch { value: _, cases: _, default: _ } =>
+                    cfg.successors(block).all(|succ| cold.contains(&succ)),
+            };
+            if is_cold {
+                cold.insert(block);
+                changed = true;
+            }
+        }
+    }
+
+    cold
+}
+
+#[cfg(test)]
+mod test {
+    use crate::back::block_order::block_order;
+    use crate::mid::ir::{Block, BlockInfo, Const, Program, Target, Terminator, Value};
+
+    fn target(block: Block) -> Target {
+        Target { block, phi_values: vec![] }
+    }
+
+    #[test]
+    fn loop_and_cold_path() {
+        //entry -> header, header -> check or exit, check -> body or fail, body -> header
+        let mut prog = Program::default();
+        let func = prog.main;
+        let entry = prog.get_func(func).entry.block;
+        let [header, check, fail, body, exit] = [(); 5].map(|_| prog.define_block(BlockInfo::new()));
+
+        let cond = Value::Const(Const::new(prog.ty_bool(), 1));
+        let ret = Terminator::Return { value: Value::Undef(prog.ty_void()) };
+
+        prog.get_block_mut(entry).terminator = Terminator::Jump { target: target(header) };
+        //the exit is the true target, but the loop body should still come first
+        prog.get_block_mut(header).terminator = Terminator::Branch { cond, true_target: target(exit), false_target: target(check) };
+        prog.get_block_mut(check).terminator = Terminator::Branch { cond, true_target: target(fail), false_target: target(body) };
+        prog.get_block_mut(fail).terminator = Terminator::Unreachable;
+        prog.get_block_mut(body).terminator = Terminator::Jump { target: target(header) };
+        prog.get_block_mut(exit).terminator = ret;
+
+        assert_eq!(vec![entry, header, check, body, exit, fail], block_order(&prog, func));
+    }
+}
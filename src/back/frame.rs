@@ -0,0 +1,368 @@
+use std::cmp::max;
+use std::collections::{HashMap, HashSet};
+
+use crate::back::layout::{Layout, next_multiple, TupleLayout};
+use crate::mid::analyse::use_info::{for_each_usage_in_instr, InstructionPos, Usage};
+use crate::mid::ir::{Block, Function, Instruction, InstructionInfo, Parameter, Phi, Program, StackSlot, Terminator, Type, Value};
+
+/// Something a backend stores in the frame of a function.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum FrameEntry {
+    /// The hidden pointer to store the return value at, stored in the prologue and used by every return.
+    ReturnPtr,
+    /// A parameter that is stored in the prologue.
+    Param(Parameter),
+    Slot(StackSlot),
+    /// The result of an instruction. Instructions without an entry are recomputed by their users, so their operands
+    /// are used there instead.
+    Instr(Instruction),
+    /// The value a jump passes to a phi, copied to `PhiPost` at the start of the block.
+    PhiPre(Phi),
+    PhiPost(Phi),
+}
+
+/// The layout of the frame entries of `func`, the offsets match the order of `entries`. Entries that are never live
+/// at the same time share memory, which keeps frames small even though every value gets its own location.
+///
+/// Stack slots are only tracked if their address is only used to access them, stack slots whose address escapes
+/// get memory of their own.
+pub fn frame_layout(prog: &Program, func: Function, entries: &[FrameEntry]) -> TupleLayout {
+    let layouts = entries.iter().map(|&entry| Layout::for_type(prog, entry_type(prog, entry))).collect::<Vec<_>>();
+    let interference = Interference::new(prog, func, entries);
+
+    let mut offsets: Vec<i32> = vec![];
+    let mut size = 0;
+    let mut alignment = 1;
+
+    for (index, layout) in layouts.iter().enumerate() {
+        //the memory taken by entries that are already placed and interfere with this one, sorted by start
+        let mut taken = (0..offsets.len())
+            .filter(|&other| interference.interferes(index, other))
+            .map(|other| (offsets[other], offsets[other] + layouts[other].size))
+            .collect::<Vec<_>>();
+        taken.sort_unstable();
+
+        let mut offset = 0;
+        if layout.size != 0 {
+            for (start, end) in taken {
+                if offset + layout.size <= start {
+                    break;
+                }
+                if end > offset {
+                    offset = next_multiple(end, layout.alignment);
+                }
+            }
+        }
+
+        offsets.push(offset);
+        size = max(size, offset + layout.size);
+        alignment = max(alignment, layout.alignment);
+    }
+
+    TupleLayout {
+        layout: Layout::new(next_multiple(size, alignment), alignment),
+        offsets,
+    }
+}
+
+fn entry_type(prog: &Program, entry: FrameEntry) -> Type {
+    match entry {
+        FrameEntry::ReturnPtr => prog.ty_ptr(),
+        FrameEntry::Param(param) => prog.get_param(param).ty,
+        FrameEntry::Slot(slot) => prog.get_slot(slot).inner_ty,
+        FrameEntry::Instr(instr) => prog.get_instr(instr).ty(prog),
+        FrameEntry::PhiPre(phi) | FrameEntry::PhiPost(phi) => prog.get_phi(phi).ty,
+    }
+}
+
+/// The stack slot `value` points into, if it's the address of a slot or a field or element of one.
+fn root_slot(prog: &Program, value: Value) -> Option<StackSlot> {
+    match value {
+        Value::Slot(slot) => Some(slot),
+        Value::Instr(instr) => match prog.get_instr(instr) {
+            InstructionInfo::TupleFieldPtr { base, index: _, tuple_ty: _ } |
+            InstructionInfo::PointerOffSet { base, index: _, ty: _ } => root_slot(prog, *base),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The frame entries that are read and written at some point in the function. Everything an instruction writes is
+/// considered to be written while the things it reads are still needed, since instructions are lowered to
+/// multiple steps.
+#[derive(Default)]
+struct Point {
+    uses: Vec<usize>,
+    defs: Vec<usize>,
+}
+
+struct Interference {
+    /// Entries that interfere with everything, slots whose address escapes.
+    pinned: Vec<bool>,
+    edges: Vec<HashSet<usize>>,
+}
+
+impl Interference {
+    fn new(prog: &Program, func: Function, entries: &[FrameEntry]) -> Self {
+        let builder = PointBuilder {
+            prog,
+            indices: entries.iter().enumerate().map(|(index, &entry)| (entry, index)).collect(),
+            escaping: escaping_slots(prog, func),
+        };
+
+        let mut blocks = vec![];
+        prog.visit_blocks(func, |block| blocks.push(block));
+        let points: HashMap<Block, Vec<Point>> = blocks.iter()
+            .map(|&block| (block, builder.block_points(func, block)))
+            .collect();
+
+        //backwards liveness, iterated until nothing changes
+        let mut live_in: HashMap<Block, HashSet<usize>> = HashMap::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in blocks.iter().rev() {
+                let mut live = live_out(prog, block, &live_in);
+                for point in points[&block].iter().rev() {
+                    step_back(&mut live, point);
+                }
+                if live_in.get(&block) != Some(&live) {
+                    live_in.insert(block, live);
+                    changed = true;
+                }
+            }
+        }
+
+        let mut result = Interference {
+            pinned: entries.iter()
+                .map(|entry| matches!(entry, FrameEntry::Slot(slot) if builder.escaping.contains(slot)))
+                .collect(),
+            edges: vec![HashSet::new(); entries.len()],
+        };
+
+        for &block in &blocks {
+            let mut live = live_out(prog, block, &live_in);
+            for point in points[&block].iter().rev() {
+                result.add_point(&live, point);
+                step_back(&mut live, point);
+            }
+        }
+
+        //the prologue stores the incoming values before the entry block starts, and anything that is still live
+        //  before that is read before it's written so it needs memory of its own for the whole function
+        let func_info = prog.get_func(func);
+        let mut prologue = Point::default();
+        for &value in &func_info.entry.phi_values {
+            builder.resolve(value, &mut prologue.uses);
+        }
+        let entry_phis = prog.get_block(func_info.entry.block).phis.iter().map(|&phi| FrameEntry::PhiPre(phi));
+        let params = func_info.params.iter().map(|&param| FrameEntry::Param(param));
+        for entry in std::iter::once(FrameEntry::ReturnPtr).chain(params).chain(entry_phis) {
+            prologue.defs.extend(builder.indices.get(&entry));
+        }
+
+        let mut live = live_in.remove(&func_info.entry.block).unwrap_or_default();
+        result.add_point(&live, &prologue);
+        step_back(&mut live, &prologue);
+        for &left in &live {
+            for &right in &live {
+                result.add_edge(left, right);
+            }
+        }
+
+        result
+    }
+
+    fn add_point(&mut self, live_after: &HashSet<usize>, point: &Point) {
+        for &def in &point.defs {
+            for &other in live_after.iter().chain(&point.uses).chain(&point.defs) {
+                self.add_edge(def, other);
+            }
+        }
+    }
+
+    fn add_edge(&mut self, left: usize, right: usize) {
+        if left != right {
+            self.edges[left].insert(right);
+            self.edges[right].insert(left);
+        }
+    }
+
+    fn interferes(&self, left: usize, right: usize) -> bool {
+        self.pinned[left] || self.pinned[right] || self.edges[left].contains(&right)
+    }
+}
+
+fn live_out(prog: &Program, block: Block, live_in: &HashMap<Block, HashSet<usize>>) -> HashSet<usize> {
+    let mut live = HashSet::new();
+    prog.get_block(block).terminator.for_each_successor(|succ| {
+        if let Some(succ_live) = live_in.get(&succ) {
+            live.extend(succ_live);
+        }
+    });
+    live
+}
+
+fn step_back(live: &mut HashSet<usize>, point: &Point) {
+    for def in &point.defs {
+        live.remove(def);
+    }
+    live.extend(&point.uses);
+}
+
+/// The slots whose address is used for anything other than accessing them.
+fn escaping_slots(prog: &Program, func: Function) -> HashSet<StackSlot> {
+    let mut escaping = HashSet::new();
+    let mut escape = |value: Value| escaping.extend(root_slot(prog, value));
+
+    prog.get_func(func).entry.phi_values.iter().for_each(|&value| escape(value));
+    prog.visit_blocks(func, |block| {
+        let block_info = prog.get_block(block);
+        for &instr in &block_info.instructions {
+            let pos = InstructionPos { func, block, instr };
+            for_each_usage_in_instr(pos, prog.get_instr(instr), |value, usage| {
+                match usage {
+                    Usage::LoadAddr { pos: _ } | Usage::StoreAddr { pos: _ } | Usage::MemDest { pos: _ } |
+                    Usage::MemCopySource { pos: _ } | Usage::TupleFieldPtrBase { pos: _ } | Usage::PointerOffSetBase { pos: _ } => {}
+                    _ => escape(value),
+                }
+            });
+        }
+        block_info.terminator.for_each_operand(&mut escape);
+    });
+
+    escaping
+}
+
+struct PointBuilder<'p> {
+    prog: &'p Program,
+    indices: HashMap<FrameEntry, usize>,
+    escaping: HashSet<StackSlot>,
+}
+
+impl PointBuilder<'_> {
+    fn block_points(&self, func: Function, block: Block) -> Vec<Point> {
+        let prog = self.prog;
+        let block_info = prog.get_block(block);
+        let mut points = vec![];
+
+        //the phi values are copied from pre to post one by one at the start of the block
+        if !block_info.phis.is_empty() {
+            let mut point = Point::default();
+            for &phi in &block_info.phis {
+                point.uses.extend(self.indices.get(&FrameEntry::PhiPre(phi)));
+                point.defs.extend(self.indices.get(&FrameEntry::PhiPost(phi)));
+            }
+            points.push(point);
+        }
+
+        for &instr in &block_info.instructions {
+            let index = match self.indices.get(&FrameEntry::Instr(instr)) {
+                Some(&index) => index,
+                None => continue,
+            };
+
+            let mut point = Point { uses: vec![], defs: vec![index] };
+            let pos = InstructionPos { func, block, instr };
+            for_each_usage_in_instr(pos, prog.get_instr(instr), |value, usage| {
+                self.resolve(value, &mut point.uses);
+
+                let slot = match root_slot(prog, value).and_then(|slot| self.indices.get(&FrameEntry::Slot(slot)).map(|&index| (slot, index))) {
+                    Some(slot) => slot,
+                    None => return,
+                };
+                match usage {
+                    Usage::LoadAddr { pos: _ } | Usage::MemCopySource { pos: _ } => point.uses.push(slot.1),
+                    Usage::StoreAddr { pos: _ } | Usage::MemDest { pos: _ } => {
+                        point.defs.push(slot.1);
+
+                        //only a store that overwrites the entire slot ends the lifetime of the previous contents
+                        let overwrites_slot = match prog.get_instr(instr) {
+                            InstructionInfo::Store { addr: _, ty, value: _ } => value == Value::Slot(slot.0) &&
+                                Layout::for_type(prog, *ty).size >= Layout::for_type(prog, prog.get_slot(slot.0).inner_ty).size,
+                            _ => false,
+                        };
+                        if !overwrites_slot {
+                            point.uses.push(slot.1);
+                        }
+                    }
+                    _ => {}
+                }
+            });
+            points.push(point);
+        }
+
+        let terminator = &block_info.terminator;
+        let mut point = Point::default();
+        terminator.for_each_operand(|value| self.resolve(value, &mut point.uses));
+        if let Terminator::Return { value: _ } = terminator {
+            point.uses.extend(self.indices.get(&FrameEntry::ReturnPtr));
+        }
+        terminator.for_each_successor(|succ| {
+            for &phi in &prog.get_block(succ).phis {
+                point.defs.extend(self.indices.get(&FrameEntry::PhiPre(phi)));
+            }
+        });
+        points.push(point);
+
+        points
+    }
+
+    /// Add the entries that are read to get `value` to `uses`.
+    fn resolve(&self, value: Value, uses: &mut Vec<usize>) {
+        let entry = match value {
+            Value::Param(param) => FrameEntry::Param(param),
+            Value::Phi(phi) => FrameEntry::PhiPost(phi),
+            Value::Instr(instr) => FrameEntry::Instr(instr),
+            _ => return,
+        };
+
+        match (self.indices.get(&entry), value) {
+            (Some(&index), _) => uses.push(index),
+            (None, Value::Instr(instr)) => self.prog.get_instr(instr).for_each_operand(|operand| self.resolve(operand, uses)),
+            (None, _) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::back::frame::{frame_layout, FrameEntry};
+    use crate::mid::ir::{ArithmeticOp, CastKind, Const, InstructionInfo, Program, StackSlotInfo, Terminator, Value};
+
+    #[test]
+    fn short_lived_values_share_memory() {
+        let mut prog = Program::default();
+        let func = prog.main;
+        let block = prog.get_func(func).entry.block;
+        let ty_int = prog.ty_int();
+        let one = Value::Const(Const::new(ty_int, 1));
+
+        let escaping = prog.define_slot(StackSlotInfo { inner_ty: ty_int });
+        let local = prog.define_slot(StackSlotInfo { inner_ty: ty_int });
+        prog.get_func_mut(func).slots.extend([escaping, local]);
+
+        //a = 1 + 1; b = a + 1; *local = b; c = *local; d = c + b; e = &escaping as int; return d
+        let add = |left, right| InstructionInfo::Arithmetic { kind: ArithmeticOp::Add, left, right };
+        let a = prog.define_instr(add(one, one));
+        let b = prog.define_instr(add(Value::Instr(a), one));
+        let store = prog.define_instr(InstructionInfo::Store { addr: Value::Slot(local), ty: ty_int, value: Value::Instr(b) });
+        let c = prog.define_instr(InstructionInfo::Load { addr: Value::Slot(local), ty: ty_int });
+        let d = prog.define_instr(add(Value::Instr(c), Value::Instr(b)));
+        let e = prog.define_instr(InstructionInfo::Cast { kind: CastKind::PointerToInt, ty: ty_int, value: Value::Slot(escaping) });
+
+        let instrs = [a, b, store, c, d, e];
+        prog.get_block_mut(block).instructions.extend(instrs);
+        prog.get_block_mut(block).terminator = Terminator::Return { value: Value::Instr(d) };
+
+        let mut entries = vec![FrameEntry::Slot(escaping), FrameEntry::Slot(local)];
+        entries.extend(instrs.iter().map(|&instr| FrameEntry::Instr(instr)));
+        let layout = frame_layout(&prog, func, &entries);
+
+        //the escaping slot gets its own memory, a is dead before local is written and d and e reuse the memory of
+        //  values that are dead by then
+        assert_eq!(vec![0, 4, 4, 8, 12, 12, 4, 8], layout.offsets);
+        assert_eq!(16, layout.layout.size);
+    }
+}
@@ -0,0 +1,69 @@
+use std::fmt::{Display, Formatter};
+
+/// The assembler the output is written for.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AsmSyntax {
+    /// Intel syntax for NASM.
+    Nasm,
+    /// The syntax of the GNU assembler. On x86 that's AT&T syntax.
+    Gas,
+}
+
+impl AsmSyntax {
+    /// The file extension used for assembly in this syntax.
+    pub fn extension(self) -> &'static str {
+        match self {
+            AsmSyntax::Nasm => "asm",
+            AsmSyntax::Gas => "s",
+        }
+    }
+}
+
+impl std::str::FromStr for AsmSyntax {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nasm" => Ok(AsmSyntax::Nasm),
+            "gas" => Ok(AsmSyntax::Gas),
+            _ => Err(format!("unknown assembly syntax '{}', expected one of nasm, gas", s)),
+        }
+    }
+}
+
+/// The object file format the assembler produces. The sections for read-only data are named differently in each.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ObjectFormat {
+    /// The format of Windows object files.
+    Coff,
+    /// The format of Linux object files.
+    Elf,
+}
+
+/// A number or symbol name, quoted if it contains characters GAS doesn't allow in plain symbol names, like the `@`
+/// of stdcall decorations.
+#[derive(Debug)]
+pub(super) struct Symbol<'a>(pub(super) &'a str);
+
+impl Display for Symbol<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let plain = self.0.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$' | '-'));
+        if plain {
+            write!(f, "{}", self.0)
+        } else {
+            write!(f, "\"{}\"", self.0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::back::gas::Symbol;
+
+    #[test]
+    fn quote_symbols() {
+        assert_eq!("func_0", Symbol("func_0").to_string());
+        assert_eq!("-4", Symbol("-4").to_string());
+        assert_eq!("\"_ExitProcess@4\"", Symbol("_ExitProcess@4").to_string());
+    }
+}
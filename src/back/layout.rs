@@ -1,6 +1,28 @@
 use std::cmp::max;
 
-use crate::mid::ir::{ArrayType, Program, TupleType, Type, TypeInfo};
+use crate::mid::ir::{ArrayType, Program, TupleType, Type, TypeInfo, UnionType};
+
+/// The parts of type layouts that depend on the target, see `back::target::Target::data_layout`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct DataLayout {
+    /// The size and alignment of pointers and function pointers.
+    pub ptr_size: i32,
+    /// The alignment of `f64`.
+    pub f64_alignment: i32,
+}
+
+impl DataLayout {
+    /// 32-bit x86, doubles are only aligned to 4 bytes.
+    pub const I386: DataLayout = DataLayout { ptr_size: 4, f64_alignment: 4 };
+    /// 64-bit pointers with naturally aligned doubles.
+    pub const LP64: DataLayout = DataLayout { ptr_size: 8, f64_alignment: 8 };
+}
+
+impl Default for DataLayout {
+    fn default() -> Self {
+        DataLayout::I386
+    }
+}
 
 //TODO cache all of this layout stuff somewhere
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -26,7 +48,10 @@ impl Layout {
         match prog.get_type(ty) {
             TypeInfo::Void => Layout::new(0, 1),
 
-            TypeInfo::Pointer { .. } | TypeInfo::Func(_) => Layout::new(4, 4),
+            TypeInfo::Pointer | TypeInfo::Func(_) => {
+                let ptr_size = prog.data_layout.ptr_size;
+                Layout::new(ptr_size, ptr_size)
+            }
 
             TypeInfo::Integer { bits: 32 } => Layout::new(4, 4),
             TypeInfo::Integer { bits: 16 } => Layout::new(2, 2),
@@ -34,15 +59,30 @@ impl Layout {
             TypeInfo::Integer { bits: 1 } => Layout::new(1, 1),
             TypeInfo::Integer { bits } => panic!("Integer with {} bits not yet supported", bits),
 
+            TypeInfo::Float { bits: 32 } => Layout::new(4, 4),
+            TypeInfo::Float { bits: 64 } => Layout::new(8, prog.data_layout.f64_alignment),
+            TypeInfo::Float { bits } => panic!("Float with {} bits not supported", bits),
+
             &TypeInfo::Array(ArrayType { inner, length }) => {
                 let inner = Layout::for_type(prog, inner);
                 Layout::new(inner.size * (length as i32), inner.alignment)
             }
-            TypeInfo::Tuple(TupleType { fields }) => {
-                TupleLayout::for_types(prog, fields.iter().copied()).layout
+            TypeInfo::Tuple(tuple_ty) => {
+                TupleLayout::for_tuple(prog, tuple_ty).layout
+            }
+            TypeInfo::Union(UnionType { variants }) => {
+                Layout::for_union(variants.iter().map(|&v| Layout::for_type(prog, v)))
             }
         }
     }
+
+    /// The layout of a union of the given variants: large and aligned enough for each of them.
+    pub fn for_union(variants: impl IntoIterator<Item=Layout>) -> Self {
+        let (size, alignment) = variants.into_iter()
+            .fold((0, 1), |(size, alignment), v| (max(size, v.size), max(alignment, v.alignment)));
+
+        Layout::new(next_multiple(size, alignment), alignment)
+    }
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -56,6 +96,15 @@ impl TupleLayout {
         TupleLayout::from_layouts(fields.into_iter().map(|f| Layout::for_type(prog, f)))
     }
 
+    /// The layout of the given tuple type, packed tuples don't have any padding and have alignment 1.
+    pub fn for_tuple(prog: &Program, tuple_ty: &TupleType) -> Self {
+        let fields = tuple_ty.fields.iter().map(|&f| {
+            let layout = Layout::for_type(prog, f);
+            if tuple_ty.packed { Layout::new(layout.size, 1) } else { layout }
+        });
+        TupleLayout::from_layouts(fields)
+    }
+
     pub fn from_layouts(fields: impl IntoIterator<Item=Layout>) -> Self {
         //TODO this can be optimized to pack tuple fields more compactly, right now this is just left-to-right
         //  when this is changed make sure to change usage sites that depend on the current behaviour (ie. parameters)
@@ -129,6 +178,18 @@ mod test {
         }, layout);
     }
 
+    #[test]
+    fn union() {
+        let layout = Layout::for_union([
+            Layout::new(3, 1),
+            Layout::new(2, 2),
+            Layout::new(0, 1),
+        ].iter().copied());
+
+        assert_eq!(Layout::new(4, 2), layout);
+        assert_eq!(Layout::new(0, 1), Layout::for_union(std::iter::empty()));
+    }
+
     #[test]
     fn single_byte() {
         let layout = TupleLayout::from_layouts([
@@ -140,4 +201,23 @@ mod test {
             offsets: vec![0],
         }, layout);
     }
+
+    #[test]
+    fn packed() {
+        let mut prog = Program::default();
+        let byte = prog.define_type_int(8);
+        let fields = vec![byte, prog.ty_int(), byte];
+
+        let tuple_ty = TupleType { fields: fields.clone(), packed: false };
+        assert_eq!(TupleLayout {
+            layout: Layout::new(12, 4),
+            offsets: vec![0, 4, 8],
+        }, TupleLayout::for_tuple(&prog, &tuple_ty));
+
+        let tuple_ty = TupleType { fields, packed: true };
+        assert_eq!(TupleLayout {
+            layout: Layout::new(6, 1),
+            offsets: vec![0, 1, 5],
+        }, TupleLayout::for_tuple(&prog, &tuple_ty));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,131 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The symbol the backend exports the main function as, the runtime calls it.
+pub const MAIN_SYMBOL: &str = "_lllang_main";
+
+/// The entry point of executables, defined by the runtime or by the program itself if it's freestanding.
+pub const START_SYMBOL: &str = "_start";
+
+/// The assembly of the runtime object that is linked into every executable. It provides the entry point `_start`,
+/// which calls main and exits the process with its return value.
+pub const RUNTIME_ASM: &str = include_str!("runtime.asm");
+/// The same runtime in GNU assembler syntax.
+pub const RUNTIME_GAS_ASM: &str = include_str!("runtime.s");
+/// The runtime for RISC-V, in GNU assembler syntax. It exits through the Linux system call instead.
+pub const RUNTIME_RISCV_ASM: &str = include_str!("runtime_riscv.s");
+/// The wrappers around the Linux system calls that the runtime functions use on RISC-V, see `front::runtime::Runtime`.
+/// They're assembled together with the runtime, or into the program itself if it's freestanding.
+pub const SYSCALLS_RISCV_ASM: &str = include_str!("syscalls_riscv.s");
+
+//TODO this is brittle, find these trough the registry or vswhere instead
+pub const DEFAULT_LINKER: &str = "C:\\Program Files (x86)\\Microsoft Visual Studio\\2019\\BuildTools\\VC\\Tools\\MSVC\\14.27.29110\\bin\\Hostx64\\x86\\link.exe";
+pub const DEFAULT_LIB_PATH: &str = "C:\\Program Files (x86)\\Windows Kits\\10\\Lib\\10.0.18362.0\\um\\x86";
+pub const DEFAULT_RISCV_LINKER: &str = "riscv64-linux-gnu-ld";
+
+/// How to invoke the linker. `command` uses the options understood by both `link.exe` and `lld-link`, `gnu_command`
+/// the ones of GNU `ld`.
+#[derive(Debug, Clone)]
+pub struct LinkSettings {
+    pub linker: PathBuf,
+    /// Libraries to link against, `.lib` is added to the ones without an extension.
+    pub libs: Vec<String>,
+    /// Folders to look for libraries in, in order.
+    pub lib_paths: Vec<PathBuf>,
+}
+
+impl LinkSettings {
+    /// The command that links `objects` into the executable `exe`. The runtime object should be one of the objects.
+    pub fn command(&self, objects: &[PathBuf], exe: &Path) -> Command {
+        let mut command = Command::new(&self.linker);
+        command
+            .arg("/nologo")
+            .arg("/debug")
+            .arg("/subsystem:console")
+            .arg("/nodefaultlib")
+            .arg("/entry:start")
+            .arg(format!("/out:{}", exe.display()));
+
+        for lib_path in &self.lib_paths {
+            command.arg(format!("/libpath:{}", lib_path.display()));
+        }
+        command.args(objects);
+
+        //the runtime itself needs kernel32 for ExitProcess
+        for lib in std::iter::once("kernel32").chain(self.libs.iter().map(String::as_str)) {
+            if Path::new(lib).extension().is_some() {
+                command.arg(lib);
+            } else {
+                command.arg(format!("{}.lib", lib));
+            }
+        }
+
+        command
+    }
+
+    /// The same as `command`, but for GNU `ld`. `_start` is the default entry point there.
+    pub fn gnu_command(&self, objects: &[PathBuf], exe: &Path) -> Command {
+        let mut command = Command::new(&self.linker);
+        command
+            .arg("-o")
+            .arg(exe);
+
+        for lib_path in &self.lib_paths {
+            command.arg(format!("-L{}", lib_path.display()));
+        }
+        command.args(objects);
+
+        for lib in &self.libs {
+            if Path::new(lib).extension().is_some() {
+                command.arg(lib);
+            } else {
+                command.arg(format!("-l{}", lib));
+            }
+        }
+
+        command
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::OsStr;
+    use std::path::{Path, PathBuf};
+
+    use crate::back::link::LinkSettings;
+
+    #[test]
+    fn link_arguments() {
+        let settings = LinkSettings {
+            linker: PathBuf::from("lld-link"),
+            libs: vec!["user32".to_owned(), "extra.obj".to_owned()],
+            lib_paths: vec![PathBuf::from("libs")],
+        };
+        let objects = vec![PathBuf::from("main.obj"), PathBuf::from("main.rt.obj")];
+        let command = settings.command(&objects, Path::new("main.exe"));
+
+        assert_eq!(OsStr::new("lld-link"), command.get_program());
+        let args: Vec<&OsStr> = command.get_args().collect();
+        let expected = [
+            "/nologo", "/debug", "/subsystem:console", "/nodefaultlib", "/entry:start", "/out:main.exe",
+            "/libpath:libs", "main.obj", "main.rt.obj", "kernel32.lib", "user32.lib", "extra.obj",
+        ];
+        assert_eq!(expected.iter().map(OsStr::new).collect::<Vec<_>>(), args);
+    }
+
+    #[test]
+    fn gnu_link_arguments() {
+        let settings = LinkSettings {
+            linker: PathBuf::from("riscv64-linux-gnu-ld"),
+            libs: vec!["c".to_owned(), "extra.o".to_owned()],
+            lib_paths: vec![PathBuf::from("libs")],
+        };
+        let objects = vec![PathBuf::from("main.o"), PathBuf::from("main.rt.o")];
+        let command = settings.gnu_command(&objects, Path::new("main"));
+
+        assert_eq!(OsStr::new("riscv64-linux-gnu-ld"), command.get_program());
+        let args: Vec<&OsStr> = command.get_args().collect();
+        let expected = ["-o", "main", "-Llibs", "main.o", "main.rt.o", "-lc", "extra.o"];
+        assert_eq!(expected.iter().map(OsStr::new).collect::<Vec<_>>(), args);
+    }
+}
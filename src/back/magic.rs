@@ -0,0 +1,107 @@
+/// Constants to replace a signed 32-bit division by a constant `d` with a multiplication, see Hacker's Delight
+/// chapter 10. The quotient is computed as
+/// ```text
+/// t = high 32 bits of (multiplier * x)
+/// t = t + x if d > 0 and multiplier < 0
+/// t = t - x if d < 0 and multiplier > 0
+/// t = t >> shift (arithmetic)
+/// q = t + (t >>> 31)
+/// ```
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct DivMagic {
+    pub multiplier: i32,
+    pub shift: u32,
+}
+
+/// Compute the magic numbers for dividing by `d`, which must not be `-1`, `0` or `1`.
+pub fn signed_div_magic(d: i32) -> DivMagic {
+    assert!(!(-1..=1).contains(&d), "no magic number for dividing by {}", d);
+
+    let two31: u32 = 1 << 31;
+    let ad = d.unsigned_abs();
+    let t = two31 + ((d as u32) >> 31);
+    let anc = t - 1 - t % ad;
+
+    let mut p = 31;
+    let mut q1 = two31 / anc;
+    let mut r1 = two31 - q1 * anc;
+    let mut q2 = two31 / ad;
+    let mut r2 = two31 - q2 * ad;
+
+    loop {
+        p += 1;
+        q1 = q1.wrapping_mul(2);
+        r1 = r1.wrapping_mul(2);
+        if r1 >= anc {
+            q1 = q1.wrapping_add(1);
+            r1 = r1.wrapping_sub(anc);
+        }
+        q2 = q2.wrapping_mul(2);
+        r2 = r2.wrapping_mul(2);
+        if r2 >= ad {
+            q2 = q2.wrapping_add(1);
+            r2 = r2.wrapping_sub(ad);
+        }
+
+        let delta = ad - r2;
+        if !(q1 < delta || (q1 == delta && r1 == 0)) { break; }
+    }
+
+    let multiplier = q2.wrapping_add(1) as i32;
+    DivMagic {
+        multiplier: if d < 0 { multiplier.wrapping_neg() } else { multiplier },
+        shift: p - 32,
+    }
+}
+
+/// If `|value|` is a power of two return its base two logarithm. `i32::MIN` counts as `2^31`.
+pub fn abs_log2(value: i32) -> Option<u32> {
+    let abs = value.unsigned_abs();
+    if abs.is_power_of_two() { Some(abs.trailing_zeros()) } else { None }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::back::magic::{abs_log2, DivMagic, signed_div_magic};
+
+    /// Evaluate the sequence of operations described on `DivMagic`.
+    fn magic_div(x: i32, d: i32, magic: DivMagic) -> i32 {
+        let mut t = ((magic.multiplier as i64 * x as i64) >> 32) as i32;
+        if d > 0 && magic.multiplier < 0 { t = t.wrapping_add(x); }
+        if d < 0 && magic.multiplier > 0 { t = t.wrapping_sub(x); }
+        t >>= magic.shift;
+        t.wrapping_add(((t as u32) >> 31) as i32)
+    }
+
+    #[test]
+    fn known_magic() {
+        //values from Hacker's Delight table 10-1
+        assert_eq!(DivMagic { multiplier: 0x55555556, shift: 0 }, signed_div_magic(3));
+        assert_eq!(DivMagic { multiplier: 0x66666667, shift: 1 }, signed_div_magic(5));
+        assert_eq!(DivMagic { multiplier: 0x92492493u32 as i32, shift: 2 }, signed_div_magic(7));
+        assert_eq!(DivMagic { multiplier: 0x99999999u32 as i32, shift: 1 }, signed_div_magic(-5));
+    }
+
+    #[test]
+    fn matches_division() {
+        let divisors = [2, 3, 5, 6, 7, 10, 12, 25, 100, 641, 1000, 65537, i32::MAX, -2, -3, -7, -10, -1000, i32::MIN];
+        let mut xs = vec![0, 1, -1, 2, -2, 99, -99, 100, -100, i32::MAX, i32::MIN, i32::MAX - 1, i32::MIN + 1];
+        xs.extend((0..1000).map(|i: i32| i.wrapping_mul(0x9E3779B9u32 as i32)));
+
+        for &d in &divisors {
+            let magic = signed_div_magic(d);
+            for &x in &xs {
+                assert_eq!(x.wrapping_div(d), magic_div(x, d, magic), "{} / {}", x, d);
+            }
+        }
+    }
+
+    #[test]
+    fn log2() {
+        assert_eq!(Some(0), abs_log2(1));
+        assert_eq!(Some(3), abs_log2(-8));
+        assert_eq!(Some(31), abs_log2(i32::MIN));
+        assert_eq!(None, abs_log2(0));
+        assert_eq!(None, abs_log2(12));
+    }
+}
@@ -1,2 +1,176 @@
 pub mod x86_asm;
 pub mod layout;
+pub mod magic;
+pub mod link;
+pub mod gas;
+pub mod block_order;
+pub mod frame;
+pub mod target;
+pub mod riscv;
+
+use std::cmp::max;
+use std::collections::HashMap;
+use std::path::Path;
+
+use indexmap::IndexMap;
+
+use crate::back::layout::Layout;
+use crate::mid::ir::{Data, Instruction, Program};
+
+/// Settings shared by all backends.
+#[derive(Debug, Clone, Default)]
+pub struct BackendSettings {
+    /// Keep a frame pointer in every function (`ebp` on x86, `s0` on RISC-V) so debuggers and profilers can walk the
+    /// stack. Values are always addressed relative to the stack pointer, so by default no frame pointer is set up.
+    pub frame_pointers: bool,
+    /// Put a comment with the source location and code before the instructions generated for each source line.
+    pub source_comments: bool,
+    /// Emit the entry point `_start` and the system call wrappers into the program itself, `_start` calls main and
+    /// exits with the Linux exit system call. The result doesn't need the runtime or any library. Only supported by
+    /// targets that use the Linux system calls, see `target::SystemApi`.
+    pub freestanding: bool,
+}
+
+/// The constant data used by a program, numbered in the order it is first used. Data with the same bytes, like
+/// repeated string literals, shares a number so it's only emitted once, with the strictest alignment of its users.
+#[derive(Default)]
+struct DataPool<'p> {
+    numbers: HashMap<Data, usize>,
+    /// The bytes and alignment of each number.
+    contents: IndexMap<&'p [u8], i32>,
+}
+
+impl<'p> DataPool<'p> {
+    fn number(&mut self, prog: &'p Program, data: Data) -> usize {
+        if let Some(&number) = self.numbers.get(&data) {
+            return number;
+        }
+
+        let data_info = prog.get_data(data);
+        let alignment = Layout::for_type(prog, data_info.inner_ty).alignment;
+
+        let entry = self.contents.entry(&data_info.bytes);
+        let number = entry.index();
+        let max_alignment = entry.or_insert(alignment);
+        *max_alignment = max(*max_alignment, alignment);
+
+        self.numbers.insert(data, number);
+        number
+    }
+
+    /// The number, bytes and alignment of all data that has been used.
+    fn iter(&self) -> impl Iterator<Item=(usize, &'p [u8], i32)> + '_ {
+        self.contents.iter().enumerate().map(|(number, (&bytes, &alignment))| (number, bytes, alignment))
+    }
+}
+
+/// Builds the comments for `BackendSettings::source_comments`, like `main.ll:42: x = a + b;`.
+struct SourceComments {
+    /// The file name and the lines of each source file.
+    files: Vec<(String, Vec<String>)>,
+    prev_line: Option<(usize, u32)>,
+}
+
+impl SourceComments {
+    /// Load the source files of `prog`, files that can't be read anymore only get their name and line number shown.
+    fn new(prog: &Program) -> Self {
+        let files = prog.source_files.iter()
+            .map(|path| {
+                let lines = std::fs::read_to_string(path)
+                    .map(|src| src.lines().map(|line| line.trim().to_owned()).collect())
+                    .unwrap_or_default();
+                (file_name(path), lines)
+            })
+            .collect();
+
+        SourceComments { files, prev_line: None }
+    }
+
+    /// Forget the previous line, so the next instruction gets a comment even if it comes from the same line.
+    fn reset(&mut self) {
+        self.prev_line = None;
+    }
+
+    /// The comment for `instr`, if its location is known and it comes from a different line than the previous one.
+    fn comment(&mut self, prog: &Program, instr: Instruction) -> Option<String> {
+        let location = prog.instr_locations.get(&instr)?;
+        let line = (location.file, location.line);
+        if self.prev_line.replace(line) == Some(line) {
+            return None;
+        }
+
+        let (name, lines) = self.files.get(location.file)?;
+        match location.line.checked_sub(1).and_then(|index| lines.get(index as usize)) {
+            Some(code) => Some(format!("{}:{}: {}", name, location.line, code)),
+            None => Some(format!("{}:{}", name, location.line)),
+        }
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    match path.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => path.display().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::back::{DataPool, SourceComments};
+    use crate::mid::ir::{DataInfo, Instruction, Program};
+    use crate::mid::opt::snapshot::lower_source;
+
+    #[test]
+    fn identical_data_is_shared() {
+        let mut prog = Program::default();
+        let ty_ptr = prog.ty_ptr();
+        let ty_byte = prog.define_type_int(8);
+        let ty_int = prog.define_type_int(32);
+
+        let hello = prog.define_data(DataInfo { ty: ty_ptr, inner_ty: ty_byte, bytes: b"hello".to_vec() });
+        let world = prog.define_data(DataInfo { ty: ty_ptr, inner_ty: ty_byte, bytes: b"world".to_vec() });
+        let hello_again = prog.define_data(DataInfo { ty: ty_ptr, inner_ty: ty_byte, bytes: b"hello".to_vec() });
+        let int = prog.define_data(DataInfo { ty: ty_ptr, inner_ty: ty_int, bytes: vec![1, 0, 0, 0] });
+        let bytes = prog.define_data(DataInfo { ty: ty_ptr, inner_ty: ty_byte, bytes: vec![1, 0, 0, 0] });
+
+        let mut pool = DataPool::default();
+        let numbers: Vec<usize> = [hello, world, hello_again, bytes, int, world].iter()
+            .map(|&data| pool.number(&prog, data))
+            .collect();
+        assert_eq!(vec![0, 1, 0, 2, 2, 1], numbers);
+
+        //the shared data is aligned for the int, even though it was first used as bytes
+        let expected: Vec<(usize, &[u8], i32)> = vec![(0, b"hello", 1), (1, b"world", 1), (2, &[1, 0, 0, 0], 4)];
+        assert_eq!(expected, pool.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn source_comments() {
+        let src = "fun main() -> int {\n    let x = 1;\n    x = x + 2;\n    return x;\n}\n";
+        let path = std::env::temp_dir().join("lllang_source_comments.ll");
+        std::fs::write(&path, src).unwrap();
+        let mut prog = lower_source(src);
+        prog.source_files = vec![path];
+
+        let instrs: Vec<Instruction> = prog.nodes.instrs.iter().map(|(instr, _)| instr).collect();
+        let comments = |prog: &Program| -> Vec<String> {
+            let mut comments = SourceComments::new(prog);
+            instrs.iter().filter_map(|&instr| comments.comment(prog, instr)).collect()
+        };
+
+        //only the first instruction of every line gets a comment
+        let expected = ["lllang_source_comments.ll:2: let x = 1;", "lllang_source_comments.ll:3: x = x + 2;", "lllang_source_comments.ll:4: return x;"];
+        assert_eq!(expected.to_vec(), comments(&prog));
+
+        //after a reset the same line is shown again
+        let mut source_comments = SourceComments::new(&prog);
+        assert!(source_comments.comment(&prog, instrs[0]).is_some());
+        assert!(source_comments.comment(&prog, instrs[0]).is_none());
+        source_comments.reset();
+        assert!(source_comments.comment(&prog, instrs[0]).is_some());
+
+        //files that can't be read anymore still get their name and line number
+        prog.source_files = vec![std::env::temp_dir().join("lllang_missing_source.ll")];
+        assert_eq!(vec!["lllang_missing_source.ll:2", "lllang_missing_source.ll:3", "lllang_missing_source.ll:4"], comments(&prog));
+    }
+}
@@ -0,0 +1,1492 @@
+use std::cmp::max;
+
+use indexmap::map::IndexMap;
+use itertools::Itertools;
+
+use crate::back::{BackendSettings, DataPool, SourceComments};
+use crate::back::block_order::block_order;
+use crate::back::frame::{frame_layout, FrameEntry};
+use crate::back::gas::Symbol;
+use crate::back::layout::{DataLayout, Layout, next_multiple, TupleLayout};
+use crate::back::link;
+use crate::back::x86_asm::{is_dense_switch, MEM_UNROLL_LIMIT, SWITCH_LINEAR_LIMIT};
+use crate::mid::intrinsic::IntrinsicKind;
+use crate::mid::ir::{ArithmeticOp, Block, CastKind, Data, Function, FunctionInfo, FunctionType, Global, Instruction, InstructionInfo, LogicalOp, Phi, Program, Signedness, StackSlot, Target, Terminator, Type, TypeInfo, Value};
+use crate::util::zip_eq;
+
+/// Generate RV64GC assembly for the GNU assembler. Pointers must be 8 bytes, see `ir::Program::data_layout`.
+///
+/// Like `x86_asm` every value lives in its own stack slot and registers are only used within a single instruction.
+/// All calling conventions are lowered to the standard RISC-V one for the LP64D ABI.
+pub fn lower(prog: &Program, settings: &BackendSettings) -> String {
+    assert_eq!(prog.data_layout, DataLayout::LP64, "RISC-V needs the LP64 data layout");
+
+    AsmBuilder {
+        prog,
+        settings,
+        next_label_number: Default::default(),
+        block_numbers: Default::default(),
+        func_numbers: Default::default(),
+        data_pool: Default::default(),
+        global_numbers: Default::default(),
+        source_comments: settings.source_comments.then(|| SourceComments::new(prog)),
+    }.lower()
+}
+
+const STACK_ALIGNMENT: i32 = 16;
+/// The size of the general purpose registers and of the words arguments are split into.
+const WORD_SIZE: i32 = 8;
+const INT_ARG_REGISTERS: [&str; 8] = ["a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7"];
+const FLOAT_ARG_REGISTERS: [&str; 8] = ["fa0", "fa1", "fa2", "fa3", "fa4", "fa5", "fa6", "fa7"];
+/// The registers inline assembly operands can be bound to. `t5` and `t6` are used as scratch registers while loading
+/// values, and the saved registers would have to be restored.
+const ASM_REGISTERS: [&str; 13] = ["a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7", "t0", "t1", "t2", "t3", "t4"];
+
+/// Where a word of an argument or return value is passed.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Location {
+    Register(&'static str),
+    /// At this offset from the stack pointer of the caller at the call.
+    Stack(i32),
+}
+
+/// How a single argument or return value is passed.
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Passing {
+    /// The value is split into words that are each passed in their own location, the last word may be smaller.
+    /// Zero-sized values don't have any words.
+    Words(Vec<Location>),
+    /// The value is copied to memory and the address of the copy is passed instead. For return values the caller
+    /// passes the address as a hidden first argument in `a0` and the callee returns it again.
+    Reference(Location),
+}
+
+/// The locations of the arguments and return value of a function.
+#[derive(Debug, Eq, PartialEq)]
+struct CallInfo {
+    ret: Passing,
+    params: Vec<Passing>,
+    /// The size of the arguments passed on the stack, rounded up to the stack alignment.
+    stack_size: i32,
+}
+
+impl CallInfo {
+    /// The standard calling convention: scalars and aggregates of up to two words are passed in `a0-a7`, floats in
+    /// `fa0-fa7`, larger aggregates by reference and whatever doesn't fit in registers on the stack.
+    //TODO tuples of floats are passed like integers, the hardware floating-point convention passes them in `fa` registers
+    fn new(prog: &Program, func_ty: &FunctionType) -> Self {
+        let mut next_int = 0;
+        let mut next_float = 0;
+        let mut stack_size = 0;
+
+        let mut int_word = || {
+            if next_int < INT_ARG_REGISTERS.len() {
+                next_int += 1;
+                Location::Register(INT_ARG_REGISTERS[next_int - 1])
+            } else {
+                stack_size += WORD_SIZE;
+                Location::Stack(stack_size - WORD_SIZE)
+            }
+        };
+
+        let ret = match classify(prog, func_ty.ret) {
+            Class::Reference => Passing::Reference(int_word()),
+            Class::Float => Passing::Words(vec![Location::Register("fa0")]),
+            Class::Words(count) => Passing::Words(INT_ARG_REGISTERS[..count].iter().map(|&reg| Location::Register(reg)).collect()),
+        };
+
+        let params = func_ty.params.iter().map(|&ty| match classify(prog, ty) {
+            Class::Reference => Passing::Reference(int_word()),
+            //floats fall back to the integer registers once the float registers run out
+            Class::Float if next_float < FLOAT_ARG_REGISTERS.len() => {
+                next_float += 1;
+                Passing::Words(vec![Location::Register(FLOAT_ARG_REGISTERS[next_float - 1])])
+            }
+            Class::Float => Passing::Words(vec![int_word()]),
+            Class::Words(count) => Passing::Words((0..count).map(|_| int_word()).collect()),
+        }).collect();
+
+        CallInfo { ret, params, stack_size: next_multiple(stack_size, STACK_ALIGNMENT) }
+    }
+
+    /// The stack space the caller needs for the call: the stack arguments followed by the copies of the arguments that
+    /// are passed by reference. Returns the total size and the offset of each copy.
+    fn outgoing_layout(&self, prog: &Program, func_ty: &FunctionType) -> (i32, Vec<Option<i32>>) {
+        let mut size = self.stack_size;
+        let offsets = zip_eq(&self.params, &func_ty.params)
+            .map(|(passing, &ty)| match passing {
+                Passing::Words(_) => None,
+                Passing::Reference(_) => {
+                    let layout = Layout::for_type(prog, ty);
+                    let offset = next_multiple(size, layout.alignment);
+                    size = offset + layout.size;
+                    Some(offset)
+                }
+            })
+            .collect();
+        (next_multiple(size, STACK_ALIGNMENT), offsets)
+    }
+}
+
+enum Class {
+    Float,
+    Words(usize),
+    Reference,
+}
+
+fn classify(prog: &Program, ty: Type) -> Class {
+    let size = Layout::for_type(prog, ty).size;
+    if prog.get_type(ty).unwrap_float().is_some() {
+        Class::Float
+    } else if size > 2 * WORD_SIZE {
+        Class::Reference
+    } else {
+        Class::Words(((size + WORD_SIZE - 1) / WORD_SIZE) as usize)
+    }
+}
+
+/// The size of word `index` of a value of `size` bytes.
+fn word_size(size: i32, index: usize) -> i32 {
+    std::cmp::min(WORD_SIZE, size - WORD_SIZE * index as i32)
+}
+
+#[derive(Default)]
+struct Output {
+    text: String,
+}
+
+impl Output {
+    fn append_ln(&mut self, line: &str) {
+        self.text.push_str(line);
+        self.text.push('\n');
+    }
+
+    fn append_instr(&mut self, instr: &str) {
+        self.text.push_str("    ");
+        self.append_ln(instr);
+    }
+}
+
+struct AsmBuilder<'p> {
+    prog: &'p Program,
+    settings: &'p BackendSettings,
+    next_label_number: usize,
+
+    block_numbers: IndexMap<Block, usize>,
+    func_numbers: IndexMap<Function, usize>,
+    data_pool: DataPool<'p>,
+    global_numbers: IndexMap<Global, usize>,
+
+    source_comments: Option<SourceComments>,
+}
+
+struct AsmFuncBuilder<'p, 'o, 'r> {
+    prog: &'p Program,
+
+    output: &'o mut Output,
+    parent: &'r mut AsmBuilder<'p>,
+    func: Function,
+    /// The block that is emitted after the current one, jumps to it can be left out.
+    next_block: Option<Block>,
+
+    /// The offset of each local from the stack pointer.
+    local_offsets: Vec<i32>,
+    frame_size: i32,
+    /// The registers saved at the top of the frame, in order from the top down.
+    saved_registers: Vec<&'static str>,
+
+    return_ptr_stack_index: Option<usize>,
+    param_stack_indices: Vec<usize>,
+    slot_stack_indices: IndexMap<StackSlot, usize>,
+    instr_stack_indices: IndexMap<Instruction, usize>,
+    phi_stack_indices: IndexMap<Phi, PhiIndices>,
+}
+
+struct PhiIndices {
+    pre: usize,
+    post: usize,
+}
+
+impl AsmBuilder<'_> {
+    pub fn lower(mut self) -> String {
+        let mut output = Output::default();
+
+        //the runtime calls main trough this symbol, see `link::RUNTIME_RISCV_ASM`
+        let main_func_number = self.func_number(self.prog.main);
+        output.append_ln(&format!("{}:", link::MAIN_SYMBOL));
+        output.append_instr(&format!("j func_{}", main_func_number));
+
+        //freestanding programs don't link the runtime, so they need their own entry point
+        if self.settings.freestanding {
+            output.append_ln(&format!("{}:", link::START_SYMBOL));
+            output.append_instr(&format!("call func_{}", main_func_number));
+            //the exit system call, main already left the exit code in a0
+            output.append_instr("li a7, 93");
+            output.append_instr("ecall");
+        }
+
+        for (func, func_info) in &self.prog.nodes.funcs {
+            self.append_func(&mut output, func, func_info)
+        };
+
+        let mut rodata_section = String::new();
+        for (data_num, bytes, alignment) in self.data_pool.iter() {
+            rodata_section.push_str(&format!(".balign {}\ndata_{}:\n", alignment, data_num));
+            if !bytes.is_empty() {
+                rodata_section.push_str(&format!("  .byte {}\n", bytes.iter().join(", ")));
+            }
+        }
+
+        //write out the globals, each into the section that matches its mutability and initializer
+        let mut data_section = String::new();
+        let mut bss_section = String::new();
+        for (&global, &global_num) in &self.global_numbers {
+            let global_info = self.prog.get_global(global);
+            let layout = Layout::for_type(self.prog, global_info.inner_ty);
+
+            let section = match (global_info.mutable, &global_info.init) {
+                (true, None) => &mut bss_section,
+                (true, Some(_)) => &mut data_section,
+                (false, _) => &mut rodata_section,
+            };
+
+            section.push_str(&format!(".balign {}\nglobal_{}:\n", layout.alignment, global_num));
+            match &global_info.init {
+                Some(init) if !init.is_empty() => section.push_str(&format!("  .byte {}\n", init.iter().join(", "))),
+                Some(_) => {}
+                None => section.push_str(&format!("  .zero {}\n", layout.size)),
+            }
+        }
+
+        let mut result = format!(".globl {}\n", link::MAIN_SYMBOL);
+        if self.settings.freestanding {
+            result.push_str(&format!(".globl {}\n", link::START_SYMBOL));
+        }
+        result.push_str(&format!("\n.text\n{}", output.text));
+        for (name, section) in [(".data", data_section), (".rodata", rodata_section), (".bss", bss_section)] {
+            if !section.is_empty() {
+                result.push_str(&format!("\n.section {}\n{}", name, section));
+            }
+        }
+
+        //there's no runtime to provide the system call wrappers either
+        if self.settings.freestanding {
+            result.push_str(&format!("\n{}", link::SYSCALLS_RISCV_ASM));
+        }
+        result
+    }
+
+    fn append_func(&mut self, output: &mut Output, func: Function, func_info: &FunctionInfo) {
+        let prog = self.prog;
+        let call_info = CallInfo::new(prog, &func_info.func_ty);
+
+        //collect all of the values that need to be stored on the stack, including the incoming parameters
+        let mut local_entries = Vec::new();
+
+        let return_ptr_stack_index = match call_info.ret {
+            Passing::Reference(_) => {
+                local_entries.push(FrameEntry::ReturnPtr);
+                Some(0)
+            }
+            Passing::Words(_) => None,
+        };
+
+        let mut param_stack_indices = Vec::new();
+        for &param in &func_info.params {
+            param_stack_indices.push(local_entries.len());
+            local_entries.push(FrameEntry::Param(param));
+        }
+
+        let mut slot_stack_indices = IndexMap::new();
+        for &slot in &func_info.slots {
+            slot_stack_indices.insert(slot, local_entries.len());
+            local_entries.push(FrameEntry::Slot(slot));
+        }
+
+        //the space for outgoing arguments is shared by all calls, inline assembly uses it to store its outputs
+        let mut outgoing_size = 0;
+        let mut is_leaf = true;
+        let mut instr_stack_indices = IndexMap::new();
+        let mut phi_stack_indices = IndexMap::new();
+        prog.visit_blocks(func, |block| {
+            let block_info = prog.get_block(block);
+
+            for &phi in &block_info.phis {
+                phi_stack_indices.insert(phi, PhiIndices { pre: local_entries.len(), post: local_entries.len() + 1 });
+                local_entries.push(FrameEntry::PhiPre(phi));
+                local_entries.push(FrameEntry::PhiPost(phi));
+            }
+
+            for &instr in &block_info.instructions {
+                let instr_info = prog.get_instr(instr);
+                instr_stack_indices.insert(instr, local_entries.len());
+                local_entries.push(FrameEntry::Instr(instr));
+
+                match instr_info {
+                    InstructionInfo::Call { target, args: _ } => {
+                        let func_ty = prog.get_type(prog.type_of_value(*target)).unwrap_func()
+                            .expect("Call target must have function type");
+                        let (size, _) = CallInfo::new(prog, func_ty).outgoing_layout(prog, func_ty);
+                        outgoing_size = max(outgoing_size, size);
+                        is_leaf = false;
+                    }
+                    InstructionInfo::InlineAsm { template: _, inputs: _, outputs } => {
+                        outgoing_size = max(outgoing_size, next_multiple(WORD_SIZE * outputs.len() as i32, STACK_ALIGNMENT));
+                        //inline assembly might call functions as well
+                        is_leaf = false;
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        let local_layout = frame_layout(prog, func, &local_entries);
+        if local_layout.layout.alignment > STACK_ALIGNMENT {
+            panic!("Cannot store type with alignment {} on stack with alignment {}", local_layout.layout.alignment, STACK_ALIGNMENT)
+        }
+        let local_offsets = local_layout.offsets.iter().map(|&offset| outgoing_size + offset).collect();
+
+        //the return address and frame pointer are stored at the top of the frame, leaf functions don't clobber `ra`
+        let mut saved_registers = vec![];
+        if !is_leaf {
+            saved_registers.push("ra");
+        }
+        if self.settings.frame_pointers {
+            saved_registers.push("s0");
+        }
+        let saved_size = WORD_SIZE * saved_registers.len() as i32;
+        let frame_size = next_multiple(outgoing_size + local_layout.layout.size + saved_size, STACK_ALIGNMENT);
+
+        let func_number = self.func_number(func);
+        if let Some(debug_name) = &func_info.debug_name {
+            output.append_ln(&format!("func_{}: # {}: {}", func_number, debug_name, prog.format_type(func_info.ty)));
+        } else {
+            output.append_ln(&format!("func_{}: # {}", func_number, prog.format_type(func_info.ty)));
+        }
+
+        let mut func_builder = AsmFuncBuilder {
+            prog,
+            output,
+            parent: self,
+            func,
+            next_block: None,
+            local_offsets,
+            frame_size,
+            saved_registers,
+            return_ptr_stack_index,
+            param_stack_indices,
+            slot_stack_indices,
+            instr_stack_indices,
+            phi_stack_indices,
+        };
+
+        //grow the stack, save registers and point the frame pointer at the top of the frame
+        func_builder.append_add_imm("sp", -frame_size as i64);
+        for (i, reg) in func_builder.saved_registers.clone().into_iter().enumerate() {
+            let mem = func_builder.mem("sp", frame_size - WORD_SIZE * (i as i32 + 1));
+            func_builder.append_instr(&format!("sd {}, {}", reg, mem));
+        }
+        if func_builder.parent.settings.frame_pointers {
+            func_builder.append_instr("mv s0, sp");
+            func_builder.append_add_imm("s0", frame_size as i64);
+        }
+
+        func_builder.append_store_params(&call_info);
+
+        //copy over initial phi values
+        for (phi, phi_value) in zip_eq(&prog.get_block(func_info.entry.block).phis, &func_info.entry.phi_values) {
+            let pre_pos = func_builder.local_offsets[func_builder.phi_stack_indices[phi].pre];
+            func_builder.append_value_to_mem("sp", pre_pos, phi_value);
+        }
+
+        //generate the main code, the entry block comes first so we don't even need to jump to it
+        let order = block_order(prog, func);
+        for (i, &block) in order.iter().enumerate() {
+            func_builder.next_block = order.get(i + 1).copied();
+            func_builder.append_block(block);
+        }
+    }
+
+    fn label_number(&mut self) -> usize {
+        let num = self.next_label_number;
+        self.next_label_number += 1;
+        num
+    }
+
+    fn block_number(&mut self, block: Block) -> usize {
+        let next_num = self.block_numbers.len();
+        *self.block_numbers.entry(block).or_insert(next_num)
+    }
+
+    fn func_number(&mut self, func: Function) -> usize {
+        let next_num = self.func_numbers.len();
+        *self.func_numbers.entry(func).or_insert(next_num)
+    }
+
+    fn data_number(&mut self, data: Data) -> usize {
+        self.data_pool.number(self.prog, data)
+    }
+
+    fn global_number(&mut self, global: Global) -> usize {
+        let next_num = self.global_numbers.len();
+        *self.global_numbers.entry(global).or_insert(next_num)
+    }
+}
+
+impl std::ops::Deref for AsmFuncBuilder<'_, '_, '_> {
+    type Target = Output;
+
+    fn deref(&self) -> &Self::Target {
+        self.output
+    }
+}
+
+impl std::ops::DerefMut for AsmFuncBuilder<'_, '_, '_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.output
+    }
+}
+
+impl AsmFuncBuilder<'_, '_, '_> {
+    /// The memory operand for `offset(base)`. Offsets that don't fit in the immediate are added to `base` in `t6`
+    /// first, so `base` can't be `t6`.
+    fn mem(&mut self, base: &str, offset: i32) -> String {
+        if fits_imm(offset as i64) {
+            format!("{}({})", offset, base)
+        } else {
+            self.append_instr(&format!("li t6, {}", offset));
+            self.append_instr(&format!("add t6, t6, {}", base));
+            "0(t6)".to_owned()
+        }
+    }
+
+    /// `reg = reg + imm`. Clobbers `t6`.
+    fn append_add_imm(&mut self, reg: &str, imm: i64) {
+        if imm == 0 {
+            //nothing to do
+        } else if fits_imm(imm) {
+            self.append_instr(&format!("addi {}, {}, {}", reg, reg, imm));
+        } else {
+            self.append_instr(&format!("li t6, {}", imm));
+            self.append_instr(&format!("add {}, {}, t6", reg, reg));
+        }
+    }
+
+    /// Load `size <= 8` bytes at `offset(base)` into `reg`, zero-extended. Floats registers are loaded with the
+    /// matching float load instead. Clobbers `t5` if `size` is not a power of two.
+    fn append_load(&mut self, reg: &str, base: &str, offset: i32, size: i32) {
+        if reg.starts_with('f') {
+            let mem = self.mem(base, offset);
+            self.append_instr(&format!("fl{} {}, {}", float_width(size), reg, mem));
+            return;
+        }
+
+        match size {
+            1 | 2 | 4 | 8 => {
+                let op = match size {
+                    1 => "lbu",
+                    2 => "lhu",
+                    4 => "lwu",
+                    _ => "ld",
+                };
+                let mem = self.mem(base, offset);
+                self.append_instr(&format!("{} {}, {}", op, reg, mem));
+            }
+            _ => {
+                //assemble the value byte by byte, starting from the most significant one
+                self.append_instr(&format!("li {}, 0", reg));
+                for i in (0..size).rev() {
+                    let mem = self.mem(base, offset + i);
+                    self.append_instr(&format!("lbu t5, {}", mem));
+                    self.append_instr(&format!("slli {}, {}, 8", reg, reg));
+                    self.append_instr(&format!("or {}, {}, t5", reg, reg));
+                }
+            }
+        }
+    }
+
+    /// Store the lowest `size <= 8` bytes of `reg` at `offset(base)`. Float registers are stored with the matching
+    /// float store instead. Clobbers `reg` if `size` is not a power of two.
+    fn append_store(&mut self, reg: &str, base: &str, offset: i32, size: i32) {
+        if reg.starts_with('f') {
+            let mem = self.mem(base, offset);
+            self.append_instr(&format!("fs{} {}, {}", float_width(size), reg, mem));
+            return;
+        }
+
+        match size {
+            0 => {}
+            1 | 2 | 4 | 8 => {
+                let op = match size {
+                    1 => "sb",
+                    2 => "sh",
+                    4 => "sw",
+                    _ => "sd",
+                };
+                let mem = self.mem(base, offset);
+                self.append_instr(&format!("{} {}, {}", op, reg, mem));
+            }
+            _ => {
+                for i in 0..size {
+                    let mem = self.mem(base, offset + i);
+                    self.append_instr(&format!("sb {}, {}", reg, mem));
+                    self.append_instr(&format!("srli {}, {}, 8", reg, reg));
+                }
+            }
+        }
+    }
+
+    /// Copy `size` bytes from `source_offset(source)` to `target_offset(target)`. Clobbers `t0`.
+    fn append_mem_copy(&mut self, target: &str, target_offset: i32, source: &str, source_offset: i32, size: i32) {
+        let mut done = 0;
+        for chunk in [8, 4, 2, 1] {
+            while size - done >= chunk {
+                self.append_load("t0", source, source_offset + done, chunk);
+                self.append_store("t0", target, target_offset + done, chunk);
+                done += chunk;
+            }
+        }
+    }
+
+    /// Store the incoming parameters and the hidden return pointer into their stack slots.
+    fn append_store_params(&mut self, call_info: &CallInfo) {
+        let func_info = self.prog.get_func(self.func);
+
+        if let (Some(index), Passing::Reference(location)) = (self.return_ptr_stack_index, &call_info.ret) {
+            let pos = self.local_offsets[index];
+            self.append_store_incoming(*location, pos, WORD_SIZE);
+        }
+
+        for (i, (&param, passing)) in zip_eq(&func_info.params, &call_info.params).enumerate() {
+            let pos = self.local_offsets[self.param_stack_indices[i]];
+            let size = Layout::for_type(self.prog, self.prog.get_param(param).ty).size;
+
+            match passing {
+                Passing::Words(locations) => {
+                    for (j, &location) in locations.iter().enumerate() {
+                        self.append_store_incoming(location, pos + WORD_SIZE * j as i32, word_size(size, j));
+                    }
+                }
+                Passing::Reference(location) => {
+                    //copy the value the caller passed a pointer to, it can't be modified in place
+                    match *location {
+                        Location::Register(reg) => self.append_instr(&format!("mv t1, {}", reg)),
+                        Location::Stack(offset) => self.append_load("t1", "sp", self.frame_size + offset, WORD_SIZE),
+                    }
+                    self.append_mem_copy("sp", pos, "t1", 0, size);
+                }
+            }
+        }
+    }
+
+    /// Store an incoming word of `size` bytes at `location` to `pos(sp)`.
+    fn append_store_incoming(&mut self, location: Location, pos: i32, size: i32) {
+        match location {
+            Location::Register(reg) => self.append_store(reg, "sp", pos, size),
+            Location::Stack(offset) => self.append_mem_copy("sp", pos, "sp", self.frame_size + offset, size),
+        }
+    }
+
+    /// The offset from the stack pointer of a value that lives on the stack.
+    fn stack_pos(&self, value: &Value) -> Option<i32> {
+        let index = match value {
+            Value::Param(param) => {
+                let param_index = self.prog.get_func(self.func).params.iter()
+                    .position(|x| x == param)
+                    .expect("param does not belong to this function");
+                self.param_stack_indices[param_index]
+            }
+            Value::Phi(phi) => self.phi_stack_indices[phi].post,
+            Value::Instr(instr) => self.instr_stack_indices[instr],
+            _ => return None,
+        };
+        Some(self.local_offsets[index])
+    }
+
+    fn size_of_value(&self, value: &Value) -> i32 {
+        Layout::for_type(self.prog, self.prog.type_of_value(*value)).size
+    }
+
+    /// Copy `value` into `reg`, zero-extended. Panics if `value` doesn't fit into a register.
+    /// Only clobbers `t5` and `t6`.
+    fn append_value_to_reg(&mut self, reg: &str, value: &Value) {
+        let size = self.size_of_value(value);
+        assert!(size <= WORD_SIZE, "Tried to put value {:?} with size {} into reg", value, size);
+
+        if let Some(pos) = self.stack_pos(value) {
+            self.append_load(reg, "sp", pos, size);
+            return;
+        }
+
+        match value {
+            Value::Undef(_) => {
+                //do nothing, just a comment for clarity
+                let str = format!("# {} = {}", reg, self.prog.format_value(*value));
+                self.append_instr(&str)
+            }
+            Value::Const(cst) => {
+                let bits = 8 * size as u32;
+                let value = if bits >= 32 { cst.value as u32 as i64 } else { cst.value as i64 & ((1 << bits) - 1) };
+                self.append_instr(&format!("li {}, {}", reg, value));
+            }
+            Value::Func(func) => {
+                let func_number = self.parent.func_number(*func);
+                self.append_instr(&format!("la {}, func_{}", reg, func_number));
+            }
+            Value::Slot(slot) => {
+                let pos = self.local_offsets[self.slot_stack_indices[slot]];
+                if fits_imm(pos as i64) {
+                    self.append_instr(&format!("addi {}, sp, {}", reg, pos));
+                } else {
+                    self.append_instr(&format!("li {}, {}", reg, pos));
+                    self.append_instr(&format!("add {}, {}, sp", reg, reg));
+                }
+            }
+            Value::Extern(ext) => {
+                let name = &self.prog.get_ext(*ext).name;
+                self.append_instr(&format!("la {}, {}", reg, Symbol(name)));
+            }
+            Value::Data(data) => {
+                let data_number = self.parent.data_number(*data);
+                self.append_instr(&format!("la {}, data_{}", reg, data_number));
+            }
+            Value::Global(global) => {
+                let global_number = self.parent.global_number(*global);
+                self.append_instr(&format!("la {}, global_{}", reg, global_number));
+            }
+            Value::Param(_) | Value::Phi(_) | Value::Instr(_) => unreachable!(),
+        }
+    }
+
+    /// Copy word `index` of `value` into `reg`, see `Passing::Words`.
+    fn append_word_to_reg(&mut self, reg: &str, value: &Value, index: usize) {
+        match self.stack_pos(value) {
+            Some(pos) => {
+                let size = word_size(self.size_of_value(value), index);
+                self.append_load(reg, "sp", pos + WORD_SIZE * index as i32, size);
+            }
+            None => {
+                assert_eq!(index, 0, "only values on the stack can be larger than a word");
+                self.append_value_to_reg(reg, value);
+            }
+        }
+    }
+
+    /// Copy `value` to `offset(base)`. Clobbers `t0`, `t5` and `t6`.
+    fn append_value_to_mem(&mut self, base: &str, offset: i32, value: &Value) {
+        let size = self.size_of_value(value);
+
+        if let Some(pos) = self.stack_pos(value) {
+            self.append_mem_copy(base, offset, "sp", pos, size);
+        } else if size != 0 {
+            self.append_value_to_reg("t0", value);
+            if !matches!(value, Value::Undef(_)) {
+                self.append_store("t0", base, offset, size);
+            }
+        }
+    }
+
+    /// The number of bits of an integer or pointer typed value.
+    fn int_bits(&self, value: &Value) -> u32 {
+        match self.prog.get_type(self.prog.type_of_value(*value)) {
+            &TypeInfo::Integer { bits } => bits,
+            TypeInfo::Pointer | TypeInfo::Func(_) => 8 * self.prog.data_layout.ptr_size as u32,
+            _ => panic!("expected integer value, got {:?}", value),
+        }
+    }
+
+    /// Sign-extend the lowest `bits` bits of `reg` to the full register.
+    fn append_sign_extend(&mut self, reg: &str, bits: u32) {
+        match bits {
+            64 => {}
+            32 => self.append_instr(&format!("sext.w {}, {}", reg, reg)),
+            _ => {
+                self.append_instr(&format!("slli {}, {}, {}", reg, reg, 64 - bits));
+                self.append_instr(&format!("srai {}, {}, {}", reg, reg, 64 - bits));
+            }
+        }
+    }
+
+    /// ```
+    /// t1 = popcount(t0)
+    /// ```
+    /// Clobbers `t0` and `t2`.
+    fn append_popcount(&mut self) {
+        let loop_label = self.parent.label_number();
+        let end_label = self.parent.label_number();
+
+        //clear the lowest set bit until there are none left
+        self.append_instr("li t1, 0");
+        self.append_instr(&format!("beqz t0, label_{}", end_label));
+        self.append_ln(&format!("  label_{}:", loop_label));
+        self.append_instr("addi t2, t0, -1");
+        self.append_instr("and t0, t0, t2");
+        self.append_instr("addi t1, t1, 1");
+        self.append_instr(&format!("bnez t0, label_{}", loop_label));
+        self.append_ln(&format!("  label_{}:", end_label));
+    }
+
+    /// Apply the unary integer intrinsic `kind` to `value` and store the result at `instr_pos(sp)`.
+    fn append_unary_intrinsic(&mut self, kind: IntrinsicKind, value: &Value, instr_pos: i32) {
+        //append_value_to_reg zero-extends the value to the full register
+        let bits = self.prog.get_type(self.prog.type_of_value(*value)).unwrap_int()
+            .expect("unary intrinsic needs an integer argument");
+        self.append_value_to_reg("t0", value);
+
+        match kind {
+            IntrinsicKind::Ctlz => {
+                //shift right until the value is zero, each shift means one less leading zero
+                let loop_label = self.parent.label_number();
+                let end_label = self.parent.label_number();
+
+                self.append_instr(&format!("li t1, {}", bits));
+                self.append_instr(&format!("beqz t0, label_{}", end_label));
+                self.append_ln(&format!("  label_{}:", loop_label));
+                self.append_instr("srli t0, t0, 1");
+                self.append_instr("addi t1, t1, -1");
+                self.append_instr(&format!("bnez t0, label_{}", loop_label));
+                self.append_ln(&format!("  label_{}:", end_label));
+            }
+            IntrinsicKind::Cttz => {
+                //set bit `bits` so zero gives `bits`, then count the bits below the lowest set one
+                self.append_instr("li t2, 1");
+                self.append_instr(&format!("slli t2, t2, {}", bits));
+                self.append_instr("or t0, t0, t2");
+                self.append_instr("neg t2, t0");
+                self.append_instr("and t0, t0, t2");
+                self.append_instr("addi t0, t0, -1");
+                self.append_popcount();
+            }
+            IntrinsicKind::Popcount => self.append_popcount(),
+            IntrinsicKind::Bswap => {
+                let bytes = bits / 8;
+                self.append_instr("li t1, 0");
+                for i in 0..bytes {
+                    self.append_instr(&format!("srli t2, t0, {}", 8 * i));
+                    self.append_instr("andi t2, t2, 0xff");
+                    self.append_instr(&format!("slli t2, t2, {}", 8 * (bytes - 1 - i)));
+                    self.append_instr("or t1, t1, t2");
+                }
+            }
+            IntrinsicKind::Trap => unreachable!(),
+        }
+
+        let size = self.size_of_value(value);
+        self.append_store("t1", "sp", instr_pos, size);
+    }
+
+    /// Jump to the label of the case matching the value in `t0` through a table indexed by the value,
+    /// or to `default_label` if there is no matching case. `cases` must be sorted and without duplicates.
+    fn append_jump_table(&mut self, cases: &[(i32, usize)], default_label: usize) {
+        let min = cases[0].0;
+        let max = cases[cases.len() - 1].0;
+        let table_label = self.parent.label_number();
+
+        //after subtracting min an unsigned comparison checks both bounds at once
+        self.append_instr("#  jump table");
+        self.append_add_imm("t0", -(min as i64));
+        self.append_instr(&format!("li t1, {}", max as i64 - min as i64));
+        self.append_instr(&format!("bgtu t0, t1, label_{}", default_label));
+        self.append_instr(&format!("la t1, label_{}", table_label));
+        self.append_instr("slli t0, t0, 3");
+        self.append_instr("add t1, t1, t0");
+        self.append_instr("ld t1, 0(t1)");
+        self.append_instr("jr t1");
+
+        self.append_instr(".balign 8");
+        self.append_ln(&format!("  label_{}:", table_label));
+        let mut cases = cases.iter().peekable();
+        for value in min..=max {
+            let label = match cases.next_if(|&&(case, _)| case == value) {
+                Some(&(_, label)) => label,
+                None => default_label,
+            };
+            self.append_instr(&format!(".dword label_{}", label));
+        }
+    }
+
+    /// Jump to the label of the case matching the value in `t0` with a binary search, or to `default_label` if there
+    /// is no matching case. `cases` must be sorted and without duplicates.
+    fn append_compare_tree(&mut self, cases: &[(i32, usize)], default_label: usize) {
+        if cases.len() <= SWITCH_LINEAR_LIMIT {
+            for &(value, label) in cases {
+                self.append_instr(&format!("li t1, {}", value));
+                self.append_instr(&format!("beq t0, t1, label_{}", label));
+            }
+            self.append_instr(&format!("j label_{}", default_label));
+            return;
+        }
+
+        //the value and the cases are sign-extended, so they're compared as signed
+        let mid = cases.len() / 2;
+        let (mid_value, mid_label) = cases[mid];
+        let upper_label = self.parent.label_number();
+
+        self.append_instr(&format!("li t1, {}", mid_value));
+        self.append_instr(&format!("beq t0, t1, label_{}", mid_label));
+        self.append_instr(&format!("bgt t0, t1, label_{}", upper_label));
+        self.append_compare_tree(&cases[..mid], default_label);
+        self.append_ln(&format!("  label_{}:", upper_label));
+        self.append_compare_tree(&cases[mid + 1..], default_label);
+    }
+
+    fn append_phi_copies(&mut self, target: &Target) {
+        let target_block_info = self.prog.get_block(target.block);
+
+        for (phi, phi_value) in zip_eq(&target_block_info.phis, &target.phi_values) {
+            let pre_pos = self.local_offsets[self.phi_stack_indices[phi].pre];
+            self.append_value_to_mem("sp", pre_pos, phi_value);
+        }
+    }
+
+    fn append_jump_to_target(&mut self, target: &Target) {
+        self.append_phi_copies(target);
+
+        let block_number = self.parent.block_number(target.block);
+        self.append_instr(&format!("j block_{}", block_number));
+    }
+
+    /// The same as `append_jump_to_target`, but the jump is left out if the target is the next block.
+    /// This has to be the last thing emitted for the current block.
+    fn append_final_jump_to_target(&mut self, target: &Target) {
+        if self.next_block == Some(target.block) {
+            self.append_phi_copies(target);
+        } else {
+            self.append_jump_to_target(target);
+        }
+    }
+    fn append_call(&mut self, target: &Value, args: &[Value], instr_pos: i32) {
+        let func_ty = self.prog.get_type(self.prog.type_of_value(*target)).unwrap_func()
+            .expect("Call target must have function type");
+        let call_info = CallInfo::new(self.prog, func_ty);
+        let (_, copy_offsets) = call_info.outgoing_layout(self.prog, func_ty);
+
+        //first everything that goes trough memory, this clobbers registers
+        for ((arg, passing), copy_offset) in zip_eq(zip_eq(args, &call_info.params), &copy_offsets) {
+            match passing {
+                Passing::Words(locations) => {
+                    for (j, &location) in locations.iter().enumerate() {
+                        if let Location::Stack(offset) = location {
+                            let size = word_size(self.size_of_value(arg), j);
+                            self.append_word_to_reg("t1", arg, j);
+                            self.append_store("t1", "sp", offset, size);
+                        }
+                    }
+                }
+                Passing::Reference(location) => {
+                    let copy_offset = copy_offset.unwrap();
+                    self.append_value_to_mem("sp", copy_offset, arg);
+                    if let Location::Stack(offset) = *location {
+                        self.append_instr(&format!("addi t1, sp, {}", copy_offset));
+                        self.append_store("t1", "sp", offset, WORD_SIZE);
+                    }
+                }
+            }
+        }
+
+        //then the arguments in registers
+        for ((arg, passing), copy_offset) in zip_eq(zip_eq(args, &call_info.params), &copy_offsets) {
+            match passing {
+                Passing::Words(locations) => {
+                    for (j, &location) in locations.iter().enumerate() {
+                        if let Location::Register(reg) = location {
+                            self.append_word_to_reg(reg, arg, j);
+                        }
+                    }
+                }
+                Passing::Reference(location) => {
+                    if let Location::Register(reg) = *location {
+                        self.append_instr(&format!("addi {}, sp, {}", reg, copy_offset.unwrap()));
+                    }
+                }
+            }
+        }
+        if let Passing::Reference(Location::Register(reg)) = call_info.ret {
+            //the callee writes the return value directly into the stack slot of this instruction
+            if fits_imm(instr_pos as i64) {
+                self.append_instr(&format!("addi {}, sp, {}", reg, instr_pos));
+            } else {
+                self.append_instr(&format!("li {}, {}", reg, instr_pos));
+                self.append_instr(&format!("add {}, {}, sp", reg, reg));
+            }
+        }
+
+        //the actual call
+        self.append_value_to_reg("t1", target);
+        self.append_instr("jalr t1");
+
+        //store the returned value
+        if let Passing::Words(locations) = &call_info.ret {
+            let size = Layout::for_type(self.prog, func_ty.ret).size;
+            for (j, &location) in locations.iter().enumerate() {
+                match location {
+                    Location::Register(reg) => self.append_store(reg, "sp", instr_pos + WORD_SIZE * j as i32, word_size(size, j)),
+                    Location::Stack(_) => unreachable!("return values are never passed on the stack"),
+                }
+            }
+        }
+    }
+
+    pub fn append_block(&mut self, block: Block) {
+        let block_number = self.parent.block_number(block);
+        self.append_ln(&format!("  block_{}:", block_number));
+        if let Some(comments) = &mut self.parent.source_comments {
+            comments.reset();
+        }
+
+        let block = self.prog.get_block(block);
+
+        //copy phi values from pre to post
+        if !block.phis.is_empty() {
+            self.append_instr("#Phi copy");
+            for phi in &block.phis {
+                let size = Layout::for_type(self.prog, self.prog.get_phi(*phi).ty).size;
+
+                let PhiIndices { pre, post } = self.phi_stack_indices[phi];
+                let pre_pos = self.local_offsets[pre];
+                let post_pos = self.local_offsets[post];
+
+                self.append_mem_copy("sp", post_pos, "sp", pre_pos, size);
+            }
+        }
+
+        //write out instructions
+        for instr in &block.instructions {
+            let prog = self.prog;
+            if let Some(comment) = self.parent.source_comments.as_mut().and_then(|comments| comments.comment(prog, *instr)) {
+                self.append_instr(&format!("# {}", comment));
+            }
+            let instr_pos = self.local_offsets[self.instr_stack_indices[instr]];
+
+            match self.prog.get_instr(*instr) {
+                InstructionInfo::Store { addr, ty, value } => {
+                    assert_eq!(*ty, self.prog.type_of_value(*value));
+                    self.append_instr("#Store");
+                    self.append_value_to_reg("t1", addr);
+                    self.append_value_to_mem("t1", 0, value);
+                }
+                InstructionInfo::Load { addr, ty } => {
+                    let size = Layout::for_type(self.prog, *ty).size;
+
+                    self.append_instr("#Load");
+                    self.append_value_to_reg("t1", addr);
+                    self.append_mem_copy("sp", instr_pos, "t1", 0, size);
+                }
+                InstructionInfo::Call { target, args } => {
+                    self.append_instr("#Call");
+                    self.append_call(target, args, instr_pos);
+                }
+                InstructionInfo::Arithmetic { kind, left, right } => {
+                    self.append_instr("#Arithmetic");
+
+                    let bits = self.int_bits(left);
+                    self.append_value_to_reg("t0", left);
+                    self.append_value_to_reg("t1", right);
+
+                    //the lower bits of the result only depend on the lower bits of the operands, except for division
+                    let op = match kind {
+                        ArithmeticOp::Add => "add",
+                        ArithmeticOp::Sub => "sub",
+                        ArithmeticOp::Mul => "mul",
+                        ArithmeticOp::Div(Signedness::Unsigned) => "divu",
+                        ArithmeticOp::Mod(Signedness::Unsigned) => "remu",
+                        ArithmeticOp::Div(Signedness::Signed) | ArithmeticOp::Mod(Signedness::Signed) => {
+                            self.append_sign_extend("t0", bits);
+                            self.append_sign_extend("t1", bits);
+                            if let ArithmeticOp::Div(_) = kind { "div" } else { "rem" }
+                        }
+                    };
+                    self.append_instr(&format!("{} t0, t0, t1", op));
+
+                    let size = self.size_of_value(left);
+                    self.append_store("t0", "sp", instr_pos, size);
+                }
+                InstructionInfo::Comparison { kind, left, right } => {
+                    self.append_instr("#Comparison");
+
+                    let bits = self.int_bits(left);
+                    self.append_value_to_reg("t0", left);
+                    self.append_value_to_reg("t1", right);
+
+                    //the values are zero-extended, which already works for unsigned comparisons
+                    let slt = match kind {
+                        LogicalOp::Eq | LogicalOp::Neq => "",
+                        LogicalOp::Gt(s) | LogicalOp::Gte(s) | LogicalOp::Lt(s) | LogicalOp::Lte(s) => match s {
+                            Signedness::Signed => {
+                                self.append_sign_extend("t0", bits);
+                                self.append_sign_extend("t1", bits);
+                                "slt"
+                            }
+                            Signedness::Unsigned => "sltu",
+                        },
+                    };
+
+                    match kind {
+                        LogicalOp::Eq => {
+                            self.append_instr("xor t0, t0, t1");
+                            self.append_instr("seqz t0, t0");
+                        }
+                        LogicalOp::Neq => {
+                            self.append_instr("xor t0, t0, t1");
+                            self.append_instr("snez t0, t0");
+                        }
+                        LogicalOp::Lt(_) => self.append_instr(&format!("{} t0, t0, t1", slt)),
+                        LogicalOp::Gt(_) => self.append_instr(&format!("{} t0, t1, t0", slt)),
+                        LogicalOp::Gte(_) => {
+                            self.append_instr(&format!("{} t0, t0, t1", slt));
+                            self.append_instr("xori t0, t0, 1");
+                        }
+                        LogicalOp::Lte(_) => {
+                            self.append_instr(&format!("{} t0, t1, t0", slt));
+                            self.append_instr("xori t0, t0, 1");
+                        }
+                    }
+
+                    self.append_store("t0", "sp", instr_pos, 1);
+                }
+                InstructionInfo::FloatArithmetic { kind, left, right } => {
+                    self.append_instr("#FloatArithmetic");
+
+                    let size = self.size_of_value(left);
+                    self.append_value_to_reg("ft0", left);
+                    self.append_value_to_reg("ft1", right);
+
+                    let op = match kind {
+                        ArithmeticOp::Add => "fadd",
+                        ArithmeticOp::Sub => "fsub",
+                        ArithmeticOp::Mul => "fmul",
+                        ArithmeticOp::Div(_) => "fdiv",
+                        ArithmeticOp::Mod(_) => panic!("float modulo is not supported"),
+                    };
+
+                    self.append_instr(&format!("{}.{} ft0, ft0, ft1", op, float_suffix(size)));
+                    self.append_store("ft0", "sp", instr_pos, size);
+                }
+                InstructionInfo::FloatComparison { kind, left, right } => {
+                    self.append_instr("#FloatComparison");
+
+                    let suffix = float_suffix(self.size_of_value(left));
+                    self.append_value_to_reg("ft0", left);
+                    self.append_value_to_reg("ft1", right);
+
+                    //these comparisons are all false for NaN, not equal is the inverse of equal
+                    match kind {
+                        LogicalOp::Eq => self.append_instr(&format!("feq.{} t0, ft0, ft1", suffix)),
+                        LogicalOp::Neq => {
+                            self.append_instr(&format!("feq.{} t0, ft0, ft1", suffix));
+                            self.append_instr("xori t0, t0, 1");
+                        }
+                        LogicalOp::Lt(_) => self.append_instr(&format!("flt.{} t0, ft0, ft1", suffix)),
+                        LogicalOp::Lte(_) => self.append_instr(&format!("fle.{} t0, ft0, ft1", suffix)),
+                        LogicalOp::Gt(_) => self.append_instr(&format!("flt.{} t0, ft1, ft0", suffix)),
+                        LogicalOp::Gte(_) => self.append_instr(&format!("fle.{} t0, ft1, ft0", suffix)),
+                    }
+
+                    self.append_store("t0", "sp", instr_pos, 1);
+                }
+                InstructionInfo::TupleFieldPtr { base, index, tuple_ty } => {
+                    let tuple_ty = self.prog.get_type(*tuple_ty).unwrap_tuple()
+                        .expect("TupleFieldPtr target should have tuple pointer type");
+                    let layout = TupleLayout::for_tuple(self.prog, tuple_ty);
+                    let field_offset = layout.offsets[*index as usize];
+
+                    self.append_instr("#TupleFieldPtr");
+                    self.append_value_to_reg("t0", base);
+                    self.append_add_imm("t0", field_offset as i64);
+                    self.append_store("t0", "sp", instr_pos, WORD_SIZE);
+                }
+                InstructionInfo::PointerOffSet { base, index, ty } => {
+                    let size = Layout::for_type(self.prog, *ty).size;
+
+                    self.append_instr("#PointerOffSet");
+                    self.append_value_to_reg("t0", base);
+                    match index {
+                        Value::Const(cst) => self.append_add_imm("t0", cst.value as i64 * size as i64),
+                        _ => {
+                            //32-bit indices can be negative, narrower ones are zero-extended like on x86
+                            let bits = self.int_bits(index);
+                            self.append_value_to_reg("t1", index);
+                            if bits == 32 {
+                                self.append_sign_extend("t1", bits);
+                            }
+
+                            match size {
+                                0 => {}
+                                _ if (size as u32).is_power_of_two() => {
+                                    if size != 1 {
+                                        self.append_instr(&format!("slli t1, t1, {}", size.trailing_zeros()));
+                                    }
+                                    self.append_instr("add t0, t0, t1");
+                                }
+                                _ => {
+                                    self.append_instr(&format!("li t2, {}", size));
+                                    self.append_instr("mul t1, t1, t2");
+                                    self.append_instr("add t0, t0, t1");
+                                }
+                            }
+                        }
+                    }
+                    self.append_store("t0", "sp", instr_pos, WORD_SIZE);
+                }
+                InstructionInfo::Cast { kind, ty, value } => {
+                    let result_size = Layout::for_type(self.prog, *ty).size;
+
+                    self.append_instr("#Cast");
+                    self.append_value_to_reg("t0", value);
+
+                    match kind {
+                        //truncating just stores the lower bytes and append_value_to_reg already cleared the upper bits
+                        CastKind::PointerToInt | CastKind::IntToPointer | CastKind::Truncate | CastKind::ZeroExtend => {}
+                        CastKind::SignExtend => {
+                            let bits = self.int_bits(value);
+                            self.append_sign_extend("t0", bits);
+                        }
+                    }
+
+                    self.append_store("t0", "sp", instr_pos, result_size);
+                }
+                InstructionInfo::Intrinsic { kind, args } => {
+                    self.append_instr(&format!("#Intrinsic {}", kind));
+
+                    if let IntrinsicKind::Trap = kind {
+                        self.append_instr("unimp");
+                    } else {
+                        self.append_unary_intrinsic(*kind, &args[0], instr_pos);
+                    }
+                }
+                InstructionInfo::MemCopy { dest, source, ty } => {
+                    self.append_instr("#MemCopy");
+                    let size = Layout::for_type(self.prog, *ty).size;
+
+                    self.append_value_to_reg("t1", source);
+                    self.append_value_to_reg("t2", dest);
+                    if size <= MEM_UNROLL_LIMIT {
+                        self.append_mem_copy("t2", 0, "t1", 0, size);
+                    } else {
+                        let loop_label = self.parent.label_number();
+                        self.append_instr(&format!("li t3, {}", size));
+                        self.append_ln(&format!("  label_{}:", loop_label));
+                        self.append_instr("lbu t0, 0(t1)");
+                        self.append_instr("sb t0, 0(t2)");
+                        self.append_instr("addi t1, t1, 1");
+                        self.append_instr("addi t2, t2, 1");
+                        self.append_instr("addi t3, t3, -1");
+                        self.append_instr(&format!("bnez t3, label_{}", loop_label));
+                    }
+                }
+                InstructionInfo::MemSet { dest, value, ty } => {
+                    self.append_instr("#MemSet");
+                    let size = Layout::for_type(self.prog, *ty).size;
+
+                    //repeat the byte in all eight bytes of t0
+                    self.append_value_to_reg("t0", value);
+                    self.append_instr("li t3, 0x0101010101010101");
+                    self.append_instr("mul t0, t0, t3");
+                    self.append_value_to_reg("t2", dest);
+                    if size <= MEM_UNROLL_LIMIT {
+                        let mut done = 0;
+                        for chunk in [8, 4, 2, 1] {
+                            while size - done >= chunk {
+                                self.append_store("t0", "t2", done, chunk);
+                                done += chunk;
+                            }
+                        }
+                    } else {
+                        let loop_label = self.parent.label_number();
+                        self.append_instr(&format!("li t3, {}", size));
+                        self.append_ln(&format!("  label_{}:", loop_label));
+                        self.append_instr("sb t0, 0(t2)");
+                        self.append_instr("addi t2, t2, 1");
+                        self.append_instr("addi t3, t3, -1");
+                        self.append_instr(&format!("bnez t3, label_{}", loop_label));
+                    }
+                }
+                InstructionInfo::Select { cond, true_value, false_value } => {
+                    self.append_instr("#Select");
+
+                    let size = self.size_of_value(true_value);
+                    let label_number = self.parent.label_number();
+                    if size <= WORD_SIZE {
+                        self.append_value_to_reg("t0", true_value);
+                        self.append_value_to_reg("t1", false_value);
+                        self.append_value_to_reg("t2", cond);
+                        self.append_instr(&format!("bnez t2, label_{}", label_number));
+                        self.append_instr("mv t0, t1");
+                        self.append_ln(&format!("  label_{}:", label_number));
+                        self.append_store("t0", "sp", instr_pos, size);
+                    } else {
+                        //too large for a register, copy the true value and overwrite it if the condition is false
+                        self.append_value_to_mem("sp", instr_pos, true_value);
+                        self.append_value_to_reg("t2", cond);
+                        self.append_instr(&format!("bnez t2, label_{}", label_number));
+                        self.append_value_to_mem("sp", instr_pos, false_value);
+                        self.append_ln(&format!("  label_{}:", label_number));
+                    }
+                }
+                InstructionInfo::InlineAsm { template, inputs, outputs } => {
+                    let register = |constraint: &str| *ASM_REGISTERS.iter()
+                        .find(|&&reg| reg == constraint)
+                        .unwrap_or_else(|| panic!("Unknown asm register '{}'", constraint));
+
+                    self.append_instr("#InlineAsm");
+                    for input in inputs {
+                        self.append_value_to_reg(register(&input.constraint), &input.value);
+                    }
+
+                    for line in template.lines() {
+                        self.append_instr(line.trim());
+                    }
+
+                    //storing an output needs registers that may hold other outputs, so save them all first
+                    for (i, output) in outputs.iter().enumerate() {
+                        self.append_store(register(&output.constraint), "sp", WORD_SIZE * i as i32, WORD_SIZE);
+                    }
+                    for (i, output) in outputs.iter().enumerate() {
+                        let size = Layout::for_type(self.prog, output.ty).size;
+                        self.append_load("t0", "sp", WORD_SIZE * i as i32, WORD_SIZE);
+                        self.append_value_to_reg("t1", &output.value);
+                        self.append_store("t0", "t1", 0, size);
+                    }
+                }
+            }
+        }
+
+        self.append_instr("#Terminator");
+        match &block.terminator {
+            Terminator::Jump { target } => {
+                self.append_final_jump_to_target(target);
+            }
+            Terminator::Branch { cond, true_target, false_target } => {
+                self.append_instr("#  cond");
+                self.append_value_to_reg("t0", cond);
+
+                //the target emitted last can fall trough into the next block, so the targets are swapped if the true
+                //  target comes next
+                let swap = self.next_block == Some(true_target.block);
+                let (first, second) = if swap { (false_target, true_target) } else { (true_target, false_target) };
+                let (first_name, second_name) = if swap { ("false", "true") } else { ("true", "false") };
+                let (jump_first, jump_second) = if swap { ("beqz", "bnez") } else { ("bnez", "beqz") };
+
+                if first.phi_values.is_empty() {
+                    //there are no phi values to copy, so jump straight to the block
+                    let block_number = self.parent.block_number(first.block);
+                    self.append_instr(&format!("{} t0, block_{}", jump_first, block_number));
+                } else {
+                    let label_number = self.parent.label_number();
+                    self.append_instr(&format!("{} t0, label_{}", jump_second, label_number));
+                    self.append_instr(&format!("#  {}", first_name));
+                    self.append_jump_to_target(first);
+                    self.append_ln(&format!("  label_{}:", label_number));
+                }
+
+                self.append_instr(&format!("#  {}", second_name));
+                self.append_final_jump_to_target(second);
+            }
+            Terminator::Switch { value, cases, default } => {
+                //first jump to a label per case, the jumps to the actual targets come after that since
+                //  they can clobber registers
+                let case_labels = cases.iter().map(|_| self.parent.label_number()).collect_vec();
+                let default_label = self.parent.label_number();
+
+                //sign-extend both the value and the cases, so they match however the cases are represented
+                let bits = self.int_bits(value);
+                self.append_value_to_reg("t0", value);
+                self.append_sign_extend("t0", bits);
+
+                let mut sorted = cases.iter()
+                    .map(|case| Signedness::Signed.extend(bits, case.value as i64) as i32)
+                    .zip(case_labels.iter().copied())
+                    .collect_vec();
+                sorted.sort_by_key(|&(value, _)| value);
+                sorted.dedup_by_key(|&mut (value, _)| value);
+
+                if is_dense_switch(&sorted) {
+                    self.append_jump_table(&sorted, default_label);
+                } else {
+                    self.append_compare_tree(&sorted, default_label);
+                }
+
+                for (case, label) in zip_eq(cases, case_labels) {
+                    self.append_ln(&format!("  label_{}:", label));
+                    self.append_instr(&format!("#  case {}", case.value));
+                    self.append_jump_to_target(&case.target);
+                }
+
+                self.append_ln(&format!("  label_{}:", default_label));
+                self.append_instr("#  default");
+                self.append_final_jump_to_target(default);
+            }
+            Terminator::Return { value } => {
+                let call_info = CallInfo::new(self.prog, &self.prog.get_func(self.func).func_ty);
+
+                match &call_info.ret {
+                    Passing::Words(locations) => {
+                        for (j, &location) in locations.iter().enumerate() {
+                            match location {
+                                Location::Register(reg) => self.append_word_to_reg(reg, value, j),
+                                Location::Stack(_) => unreachable!("return values are never passed on the stack"),
+                            }
+                        }
+                    }
+                    Passing::Reference(_) => {
+                        //copy the value to the hidden pointer and return that pointer
+                        let return_ptr_pos = self.local_offsets[self.return_ptr_stack_index.unwrap()];
+                        self.append_load("t1", "sp", return_ptr_pos, WORD_SIZE);
+                        self.append_value_to_mem("t1", 0, value);
+                        self.append_instr("mv a0, t1");
+                    }
+                }
+
+                let frame_size = self.frame_size;
+                for (i, reg) in self.saved_registers.clone().into_iter().enumerate() {
+                    let mem = self.mem("sp", frame_size - WORD_SIZE * (i as i32 + 1));
+                    self.append_instr(&format!("ld {}, {}", reg, mem));
+                }
+                self.append_add_imm("sp", frame_size as i64);
+                self.append_instr("ret");
+            }
+            Terminator::Unreachable => {
+                self.append_instr("unimp");
+            }
+        }
+    }
+}
+
+/// Whether `value` fits in the 12-bit signed immediate of I-type and S-type instructions.
+fn fits_imm(value: i64) -> bool {
+    (-2048..2048).contains(&value)
+}
+
+/// The suffix of float arithmetic instructions for floats of `size` bytes.
+fn float_suffix(size: i32) -> char {
+    match size {
+        4 => 's',
+        8 => 'd',
+        _ => panic!("float with {} bytes not supported", size),
+    }
+}
+
+/// The suffix of float loads and stores for floats of `size` bytes.
+fn float_width(size: i32) -> char {
+    match size {
+        4 => 'w',
+        8 => 'd',
+        _ => panic!("float with {} bytes not supported", size),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::back::BackendSettings;
+    use crate::back::layout::DataLayout;
+    use crate::back::riscv::{CallInfo, Location, lower, Passing};
+    use crate::back::target::RiscV64Linux;
+    use crate::mid::ir::{CallingConvention, FunctionType, Program, TupleType};
+    use crate::mid::opt::snapshot::lower_source_for;
+
+    #[test]
+    fn calling_convention() {
+        let mut prog = Program::default();
+        prog.data_layout = DataLayout::LP64;
+        let ty_int = prog.ty_int();
+        let ty_ptr = prog.ty_ptr();
+        let ty_double = prog.define_type_float(64);
+        let ty_pair = prog.define_type_tuple(TupleType { fields: vec![ty_ptr, ty_int], packed: false });
+        let ty_triple = prog.define_type_tuple(TupleType { fields: vec![ty_ptr, ty_ptr, ty_ptr], packed: false });
+
+        use Location::{Register as R, Stack as S};
+        use Passing::{Reference, Words};
+
+        //aggregates of two words take two registers, larger ones are passed by reference
+        let params = vec![ty_pair, ty_triple, ty_double, ty_int, ty_pair, ty_int, ty_pair, ty_double];
+        let func_ty = FunctionType { params, ret: ty_triple, conv: CallingConvention::default() };
+        let info = CallInfo::new(&prog, &func_ty);
+
+        assert_eq!(Reference(R("a0")), info.ret);
+        assert_eq!(vec![
+            Words(vec![R("a1"), R("a2")]),
+            Reference(R("a3")),
+            Words(vec![R("fa0")]),
+            Words(vec![R("a4")]),
+            Words(vec![R("a5"), R("a6")]),
+            Words(vec![R("a7")]),
+            Words(vec![S(0), S(8)]),
+            Words(vec![R("fa1")]),
+        ], info.params);
+        assert_eq!(16, info.stack_size);
+
+        let (size, copies) = info.outgoing_layout(&prog, &func_ty);
+        assert_eq!(48, size);
+        assert_eq!(Some(16), copies[1]);
+
+        //small aggregates are returned in registers
+        let func_ty = FunctionType { params: vec![], ret: ty_pair, conv: CallingConvention::default() };
+        assert_eq!(Words(vec![R("a0"), R("a1")]), CallInfo::new(&prog, &func_ty).ret);
+    }
+
+    #[test]
+    fn runtime_uses_linux_system_calls() {
+        let src = "
+            fun get(s: &[int], i: int) -> int { return s[i]; }
+            fun main() -> int {
+                let arr = [5; 3];
+                let p = new int;
+                free(p);
+                return get(&arr, 7);
+            }
+        ";
+        let prog = lower_source_for(src, &RiscV64Linux);
+        let asm = lower(&prog, &BackendSettings::default());
+
+        //the failed bounds check panics trough the system call wrappers of the runtime
+        for name in ["_lllang_write", "_lllang_exit", "_lllang_alloc", "_lllang_free"] {
+            assert!(asm.contains(name), "{} is not used in:\n{}", name, asm);
+        }
+        //and nothing refers to the Win32 functions, they're all stdcall symbols
+        assert!(!asm.contains('@'), "Win32 function used in:\n{}", asm);
+    }
+
+    #[test]
+    fn freestanding_programs_are_self_contained() {
+        let prog = lower_source_for("fun main() -> int { let p = new int; free(p); return 0; }", &RiscV64Linux);
+        let settings = BackendSettings { freestanding: true, ..Default::default() };
+        let asm = lower(&prog, &settings);
+
+        for label in ["_start:", "_lllang_alloc:", "_lllang_free:"] {
+            assert!(asm.lines().any(|line| line == label), "{} is not defined in:\n{}", label, asm);
+        }
+    }
+
+
+    #[test]
+    fn leaf_functions_and_frame_pointers() {
+        let src = "
+            fun leaf(a: int) -> int { return a + 1; }
+            fun main() -> int { return leaf(2); }
+        ";
+        let prog = lower_source_for(src, &RiscV64Linux);
+        let func = |asm: &str, name: &str| -> Vec<String> {
+            asm.lines()
+                .skip_while(|line| !line.contains(&format!("# {}:", name)))
+                .skip(1)
+                .take_while(|line| !line.starts_with("func_"))
+                .map(|line| line.trim().to_owned())
+                .collect()
+        };
+
+        //only functions that call something save the return address
+        let asm = lower(&prog, &BackendSettings::default());
+        assert!(!func(&asm, "leaf").iter().any(|line| line.contains("ra")), "{}", asm);
+        assert!(func(&asm, "main").contains(&"sd ra, 8(sp)".to_owned()), "{}", asm);
+        assert!(!asm.contains("s0"), "{}", asm);
+
+        let settings = BackendSettings { frame_pointers: true, ..Default::default() };
+        let asm = lower(&prog, &settings);
+        for name in ["leaf", "main"] {
+            let lines = func(&asm, name);
+            assert!(lines.contains(&"mv s0, sp".to_owned()), "{}", asm);
+            assert!(lines.iter().any(|line| line.starts_with("ld s0, ")), "{}", asm);
+        }
+        assert!(!func(&asm, "leaf").iter().any(|line| line.contains("ra")), "{}", asm);
+    }
+}
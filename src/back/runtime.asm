@@ -0,0 +1,13 @@
+; The entry point of every program: call main and exit the process with its return value.
+
+global _start
+extern _lllang_main
+extern _ExitProcess@4
+
+section .text
+_start:
+    ;System V calls expect a 16-byte aligned stack, see `call_alignment` in x86_asm
+    and esp, -16
+    call _lllang_main
+    push eax
+    call _ExitProcess@4
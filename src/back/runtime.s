@@ -0,0 +1,12 @@
+# The entry point of every program: call main and exit the process with its return value.
+# The same as runtime.asm, in the syntax of the GNU assembler.
+
+.globl _start
+
+.section .text
+_start:
+    #System V calls expect a 16-byte aligned stack, see `call_alignment` in x86_asm
+    and $-16, %esp
+    call _lllang_main
+    push %eax
+    call "_ExitProcess@4"
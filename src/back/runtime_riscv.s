@@ -0,0 +1,10 @@
+# The entry point of every RISC-V program: call main and exit the process with its return value.
+
+.globl _start
+
+.text
+_start:
+    call _lllang_main
+    # the exit system call on Linux
+    li a7, 93
+    ecall
@@ -0,0 +1,56 @@
+# The wrappers around the Linux system calls used by `front::runtime` on RISC-V.
+
+.globl _lllang_write
+.globl _lllang_exit
+.globl _lllang_alloc
+.globl _lllang_free
+
+.text
+# _lllang_write(fd: int, buffer: &byte, len: int) -> int
+_lllang_write:
+    # the length is unsigned
+    slli a2, a2, 32
+    srli a2, a2, 32
+    li a7, 64
+    ecall
+    ret
+
+# _lllang_exit(code: int)
+_lllang_exit:
+    li a7, 93
+    ecall
+
+# _lllang_alloc(size: int) -> &void, returns null if the memory can't be mapped
+_lllang_alloc:
+    # the size is unsigned, and 16 more bytes are mapped to keep it in front of the block for _lllang_free
+    slli a1, a0, 32
+    srli a1, a1, 32
+    addi a1, a1, 16
+    # mmap(null, size, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0)
+    li a0, 0
+    li a2, 3
+    li a3, 0x22
+    li a4, -1
+    li a5, 0
+    li a7, 222
+    ecall
+    # errors are returned as -4095..-1
+    li t0, -4096
+    bgtu a0, t0, 1f
+    sd a1, 0(a0)
+    addi a0, a0, 16
+    ret
+1:
+    li a0, 0
+    ret
+
+# _lllang_free(ptr: &void)
+_lllang_free:
+    beqz a0, 1f
+    # munmap(block, size), with the block and its size as stored by _lllang_alloc
+    addi a0, a0, -16
+    ld a1, 0(a0)
+    li a7, 215
+    ecall
+1:
+    ret
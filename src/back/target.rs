@@ -0,0 +1,218 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::back::BackendSettings;
+use crate::back::gas::{AsmSyntax, ObjectFormat};
+use crate::back::layout::DataLayout;
+use crate::back::link::{self, LinkSettings};
+use crate::back::{riscv, x86_asm};
+use crate::mid::ir::Program;
+
+/// Everything that depends on the platform the program runs on: type layouts, code generation, the runtime and how
+/// to assemble and link. Register sets and calling conventions are handled by the code generator behind `lower`.
+pub trait Target {
+    /// The canonical triple of this target, eg. `i686-pc-windows-msvc`.
+    fn triple(&self) -> &'static str;
+
+    /// The target dependent parts of type layouts, stored in `ir::Program::data_layout` during lowering.
+    fn data_layout(&self) -> DataLayout;
+
+    /// The operating system interface the runtime functions are built on, see `front::runtime::Runtime`.
+    fn system_api(&self) -> SystemApi;
+
+    /// The assembly syntaxes the code generator supports, the first one is the default.
+    fn syntaxes(&self) -> &'static [AsmSyntax];
+
+    /// Generate assembly for `prog` in `syntax`, which is one of `syntaxes`.
+    fn lower(&self, prog: &Program, syntax: AsmSyntax, settings: &BackendSettings) -> String;
+
+    /// The runtime in `syntax`, see `link::RUNTIME_ASM`.
+    fn runtime_asm(&self, syntax: AsmSyntax) -> String;
+
+    /// The command that assembles `asm` in `syntax` into the object file `obj`.
+    fn assemble_command(&self, asm: &Path, obj: &Path, syntax: AsmSyntax) -> Command;
+
+    /// The command that links `objects` into the executable `exe`.
+    fn link_command(&self, settings: &LinkSettings, objects: &[PathBuf], exe: &Path) -> Command;
+
+    /// The linker used if none is given.
+    fn default_linker(&self) -> &'static str;
+
+    /// Folders that are always searched for libraries, after the ones that are given.
+    fn default_lib_paths(&self) -> Vec<PathBuf>;
+
+    fn object_extension(&self) -> &'static str;
+
+    /// The extension of executables, empty if they don't have one.
+    fn exe_extension(&self) -> &'static str;
+}
+
+/// The interface to the operating system that the runtime functions called by the generated code use.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SystemApi {
+    /// The Win32 functions from kernel32.
+    Win32,
+    /// The Linux system calls, trough the wrappers in `link::SYSCALLS_RISCV_ASM`.
+    Linux,
+}
+
+/// The target used if none is given.
+pub const DEFAULT_TRIPLE: &str = "i686-pc-windows-msvc";
+
+/// Find the target for a triple `arch-vendor-os[-env]`. Only the architecture and the operating system matter, so eg.
+/// both `i386-windows` and `i686-pc-windows-gnu` select `I686Windows`.
+pub fn from_triple(triple: &str) -> Result<Box<dyn Target>, String> {
+    let mut parts = triple.split('-');
+    let arch = parts.next().unwrap_or("");
+    let rest: Vec<&str> = parts.collect();
+    let has_os = |os: &str| rest.contains(&os);
+
+    match arch {
+        "i386" | "i486" | "i586" | "i686" | "x86" if has_os("windows") => Ok(Box::new(I686Windows)),
+        "riscv64" | "riscv64gc" if has_os("linux") => Ok(Box::new(RiscV64Linux)),
+        _ => Err(format!(
+            "unsupported target '{}', expected one of {}, {}",
+            triple, I686Windows.triple(), RiscV64Linux.triple()
+        )),
+    }
+}
+
+/// 32-bit Windows, generated by `x86_asm` and linked with `link.exe` or `lld-link`.
+pub struct I686Windows;
+
+impl Target for I686Windows {
+    fn triple(&self) -> &'static str {
+        "i686-pc-windows-msvc"
+    }
+
+    fn data_layout(&self) -> DataLayout {
+        DataLayout::I386
+    }
+
+    fn system_api(&self) -> SystemApi {
+        SystemApi::Win32
+    }
+
+    fn syntaxes(&self) -> &'static [AsmSyntax] {
+        &[AsmSyntax::Nasm, AsmSyntax::Gas]
+    }
+
+    fn lower(&self, prog: &Program, syntax: AsmSyntax, settings: &BackendSettings) -> String {
+        x86_asm::lower(prog, settings, syntax, ObjectFormat::Coff)
+    }
+
+    fn runtime_asm(&self, syntax: AsmSyntax) -> String {
+        match syntax {
+            AsmSyntax::Nasm => link::RUNTIME_ASM.to_owned(),
+            AsmSyntax::Gas => link::RUNTIME_GAS_ASM.to_owned(),
+        }
+    }
+
+    fn assemble_command(&self, asm: &Path, obj: &Path, syntax: AsmSyntax) -> Command {
+        let mut command = match syntax {
+            AsmSyntax::Nasm => {
+                let mut command = Command::new("nasm");
+                command.arg("-O0").arg("-fwin32").arg("-g");
+                command
+            }
+            AsmSyntax::Gas => {
+                let mut command = Command::new("as");
+                command.arg("--32");
+                command
+            }
+        };
+        command.arg("-o").arg(obj).arg(asm);
+        command
+    }
+
+    fn link_command(&self, settings: &LinkSettings, objects: &[PathBuf], exe: &Path) -> Command {
+        settings.command(objects, exe)
+    }
+
+    fn default_linker(&self) -> &'static str {
+        link::DEFAULT_LINKER
+    }
+
+    fn default_lib_paths(&self) -> Vec<PathBuf> {
+        vec![PathBuf::from(link::DEFAULT_LIB_PATH)]
+    }
+
+    fn object_extension(&self) -> &'static str {
+        "obj"
+    }
+
+    fn exe_extension(&self) -> &'static str {
+        "exe"
+    }
+}
+
+/// 64-bit RISC-V Linux, generated by `riscv` and linked with GNU `ld`.
+pub struct RiscV64Linux;
+
+impl Target for RiscV64Linux {
+    fn triple(&self) -> &'static str {
+        "riscv64-unknown-linux-gnu"
+    }
+
+    fn data_layout(&self) -> DataLayout {
+        DataLayout::LP64
+    }
+
+    fn system_api(&self) -> SystemApi {
+        SystemApi::Linux
+    }
+
+    fn syntaxes(&self) -> &'static [AsmSyntax] {
+        &[AsmSyntax::Gas]
+    }
+
+    fn lower(&self, prog: &Program, _: AsmSyntax, settings: &BackendSettings) -> String {
+        riscv::lower(prog, settings)
+    }
+
+    fn runtime_asm(&self, _: AsmSyntax) -> String {
+        format!("{}\n{}", link::RUNTIME_RISCV_ASM, link::SYSCALLS_RISCV_ASM)
+    }
+
+    fn assemble_command(&self, asm: &Path, obj: &Path, _: AsmSyntax) -> Command {
+        let mut command = Command::new("riscv64-linux-gnu-as");
+        command.arg("-march=rv64gc").arg("-o").arg(obj).arg(asm);
+        command
+    }
+
+    fn link_command(&self, settings: &LinkSettings, objects: &[PathBuf], exe: &Path) -> Command {
+        settings.gnu_command(objects, exe)
+    }
+
+    fn default_linker(&self) -> &'static str {
+        link::DEFAULT_RISCV_LINKER
+    }
+
+    fn default_lib_paths(&self) -> Vec<PathBuf> {
+        vec![]
+    }
+
+    fn object_extension(&self) -> &'static str {
+        "o"
+    }
+
+    fn exe_extension(&self) -> &'static str {
+        ""
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::back::target::from_triple;
+
+    #[test]
+    fn parse_triples() {
+        assert_eq!("i686-pc-windows-msvc", from_triple("i686-pc-windows-msvc").unwrap().triple());
+        assert_eq!("i686-pc-windows-msvc", from_triple("i386-windows").unwrap().triple());
+        assert_eq!("riscv64-unknown-linux-gnu", from_triple("riscv64-linux-gnu").unwrap().triple());
+
+        assert!(from_triple("x86_64-pc-windows-msvc").is_err());
+        assert!(from_triple("i686-unknown-linux-gnu").is_err());
+        assert!(from_triple("").is_err());
+    }
+}
@@ -1,68 +1,101 @@
 use std::cmp::max;
+use std::collections::{HashMap, HashSet};
 
 use indexmap::map::IndexMap;
 use itertools::Itertools;
 
+use crate::back::{BackendSettings, DataPool, SourceComments};
+use crate::back::block_order::block_order;
+use crate::back::frame::{frame_layout, FrameEntry};
+use crate::back::gas::{AsmSyntax, ObjectFormat, Symbol};
 use crate::back::layout::{Layout, next_multiple, TupleLayout};
-use crate::mid::ir::{ArithmeticOp, Block, Data, Function, FunctionInfo, Instruction, InstructionInfo, LogicalOp, Phi, Program, StackSlot, Target, Terminator, Value};
+use crate::back::link;
+use crate::back::magic::{abs_log2, signed_div_magic};
+use crate::mid::analyse::use_info::{for_each_usage_in_instr, InstructionPos, Usage};
+use crate::mid::intrinsic::IntrinsicKind;
+use crate::mid::ir::{ArithmeticOp, Block, CallingConvention, CastKind, Data, Function, FunctionInfo, FunctionType, Global, Instruction, InstructionInfo, LogicalOp, Phi, Program, Signedness, StackSlot, Target, Terminator, Type, TypeInfo, Value};
 use crate::util::zip_eq;
 
-pub fn lower(prog: &Program) -> String {
+pub fn lower(prog: &Program, settings: &BackendSettings, syntax: AsmSyntax, format: ObjectFormat) -> String {
     AsmBuilder {
         prog,
+        settings,
+        printer: Printer { syntax, format },
+        externs: Default::default(),
+        call_alignment: call_alignment(prog),
         next_label_number: Default::default(),
         block_numbers: Default::default(),
         func_numbers: Default::default(),
-        data_numbers: Default::default(),
+        data_pool: Default::default(),
+        global_numbers: Default::default(),
+        source_comments: settings.source_comments.then(|| SourceComments::new(prog)),
     }.lower()
 }
 
 const STACK_ALIGNMENT: i32 = 4;
-
-#[derive(Default)]
-struct Output {
-    header: String,
-    text: String,
-}
-
-impl Output {
-    fn append_ln(&mut self, line: &str) {
-        self.text.push_str(line);
-        self.text.push('\n');
-    }
-
-    fn append_instr(&mut self, instr: &str) {
-        self.text.push_str("    ");
-        self.append_ln(instr);
-    }
-}
+/// The alignment System V requires for the stack pointer at calls.
+const SYSV_CALL_ALIGNMENT: i32 = 16;
+/// Switches with at least this many cases use a jump table if their values are dense enough.
+const SWITCH_TABLE_MIN_CASES: usize = 4;
+/// Ranges of switch cases up to this size are checked one by one instead of with a binary search.
+pub(super) const SWITCH_LINEAR_LIMIT: usize = 3;
+/// `MemCopy` and `MemSet` up to this many bytes are emitted as a sequence of moves, larger ones use `rep`.
+pub(super) const MEM_UNROLL_LIMIT: i32 = 32;
 
 struct AsmBuilder<'p> {
     prog: &'p Program,
+    settings: &'p BackendSettings,
+    printer: Printer,
+    /// The external symbols the code refers to, declared at the top of the output.
+    externs: Vec<String>,
+    /// The alignment of `esp` at every call instruction, see `call_alignment`.
+    call_alignment: i32,
     next_label_number: usize,
 
     //TODO make these match the indices in the IR debug format
     block_numbers: IndexMap<Block, usize>,
     func_numbers: IndexMap<Function, usize>,
-    data_numbers: IndexMap<Data, usize>,
+    data_pool: DataPool<'p>,
+    global_numbers: IndexMap<Global, usize>,
+
+    source_comments: Option<SourceComments>,
 }
 
-struct AsmFuncBuilder<'p, 'o, 'r> {
+struct AsmFuncBuilder<'p, 'r> {
     prog: &'p Program,
 
-    output: &'o mut Output,
+    /// The body of the function, only printed once the frame layout is known, see `print_lines`.
+    lines: Vec<Line>,
     parent: &'r mut AsmBuilder<'p>,
     func: Function,
+    /// The block that is emitted after the current one, jumps to it can be left out.
+    next_block: Option<Block>,
 
     local_layout: TupleLayout,
     param_layout: TupleLayout,
+    /// The alignment of the stack pointer after the prologue.
+    frame_alignment: i32,
+    /// The registers used by the instructions appended so far, see `append_instr`.
+    used_registers: HashSet<Register>,
+    /// The callee-saved registers that are pushed in the prologue, after the frame pointer.
+    saved_registers: Vec<Register>,
     param_offset: i32,
     local_stack_size: i32,
-    param_size: i32,
+    /// The number of parameter bytes the function itself pops off the stack when returning.
+    callee_pop_size: i32,
+    /// Whether the value is returned in memory, trough a hidden pointer right before the parameters.
+    return_in_memory: bool,
 
     slot_stack_indices: IndexMap<StackSlot, usize>,
     instr_stack_indices: IndexMap<Instruction, usize>,
     phi_stack_indices: IndexMap<Phi, PhiIndices>,
+    /// The `TupleFieldPtr` and `PointerOffSet` instructions that don't get a stack slot because they are recomputed
+    /// as part of the memory operand of every user.
+    folded_addresses: HashSet<Instruction>,
+    /// The comparisons that only set the flags for the branch that follows them, without a stack slot for the result.
+    fused_comparisons: HashSet<Instruction>,
+    /// The comparison the flags currently hold the result of, and the condition code that is true if its result is.
+    flags: Option<(Instruction, &'static str)>,
 }
 
 struct PhiIndices {
@@ -72,58 +105,90 @@ struct PhiIndices {
 
 impl AsmBuilder<'_> {
     pub fn lower(mut self) -> String {
-        let mut output = Output::default();
+        let printer = self.printer;
+        let mut text = String::new();
 
-        //call main function
+        //the runtime calls main trough this symbol, see `link::RUNTIME_ASM`
         let main_func_number = self.func_number(self.prog.main);
-        output.append_ln("_main:");
-        output.append_instr(&format!("call func_{}", main_func_number));
-        output.append_instr("push eax");
-        output.append_instr("call _ExitProcess@4");
-
-        //hardcode dependency TODO eventually remove this
-        output.header.push_str("extern _ExitProcess@4\n");
+        let jump_to_main = Instr::new("jmp", [Operand::Symbol(format!("func_{}", main_func_number))]);
+        text.push_str(&format!("{}\n    {}\n", printer.label(link::MAIN_SYMBOL), printer.instr(&jump_to_main)));
 
         //write out all of the functions
         for (func, func_info) in &self.prog.nodes.funcs {
-            self.append_func(&mut output, func, func_info)
+            self.append_func(&mut text, func, func_info)
         };
 
-        //write out all of the data
-        //TODO maybe write this to the data section instead of the text section
-        for (&data, &data_num) in &self.data_numbers {
-            output.text.push_str(&format!("data_{}:\n  db ", data_num));
+        //write out all of the data, it's never written to so it goes into the read-only section
+        let mut rdata_section = String::new();
+        for (data_num, bytes, alignment) in self.data_pool.iter() {
+            rdata_section.push_str(&format!("{}\n{}\n", printer.align(alignment), printer.label(&format!("data_{}", data_num))));
+            if !bytes.is_empty() {
+                rdata_section.push_str(&format!("  {}\n", printer.bytes(bytes)));
+            }
+        }
 
-            let data_info = self.prog.get_data(data);
-            for (i, b) in data_info.bytes.iter().enumerate() {
-                if i != 0 { output.text.push_str(", ") }
-                output.text.push_str(&format!("{}", b));
+        //write out the globals, each into the section that matches its mutability and initializer
+        let mut data_section = String::new();
+        let mut bss_section = String::new();
+        for (&global, &global_num) in &self.global_numbers {
+            let global_info = self.prog.get_global(global);
+            let layout = Layout::for_type(self.prog, global_info.inner_ty);
+
+            let label = printer.label(&format!("global_{}", global_num));
+            let section = match (global_info.mutable, &global_info.init) {
+                (true, None) => {
+                    let reserve = printer.reserve(layout.size);
+                    bss_section.push_str(&format!("{}\n{}\n  {}\n", printer.align_reserved(layout.alignment), label, reserve));
+                    continue;
+                }
+                (true, Some(_)) => &mut data_section,
+                (false, _) => &mut rdata_section,
+            };
+
+            section.push_str(&format!("{}\n{}\n", printer.align(layout.alignment), label));
+            match &global_info.init {
+                Some(init) if !init.is_empty() => section.push_str(&format!("  {}\n", printer.bytes(init))),
+                Some(_) => {}
+                None => section.push_str(&format!("  {}\n", printer.zeros(layout.size))),
             }
-            output.text.push('\n');
         }
 
         //format everything together
-        format!("global _main\n{}\nsection .text\n{}", output.header, output.text)
+        let mut result = format!("{}\n", printer.global(link::MAIN_SYMBOL));
+        for name in &self.externs {
+            result.push_str(&format!("{}\n", printer.external(name)));
+        }
+        result.push_str(&format!("\n{}\n{}", printer.section(Section::Text), text));
+        for (name, section) in [(Section::Data, data_section), (Section::ReadOnly, rdata_section), (Section::Bss, bss_section)] {
+            if !section.is_empty() {
+                result.push_str(&format!("\n{}\n{}", printer.section(name), section));
+            }
+        }
+        result
     }
 
-    fn append_func(&mut self, output: &mut Output, func: Function, func_info: &FunctionInfo) {
+    fn append_func(&mut self, text: &mut String, func: Function, func_info: &FunctionInfo) {
         let prog = self.prog;
 
         let param_types = func_info.params.iter()
             .map(|&param| self.prog.get_param(param).ty)
             .collect_vec();
-        let param_layout = TupleLayout::for_types(&self.prog, param_types.iter().copied());
+        let param_layout = param_layout(self.prog, func_info.func_ty.conv, param_types.iter().copied());
 
         //collect all of the values that need to be stored on the stack
         let mut slot_stack_indices = IndexMap::new();
         let mut instr_stack_indices = IndexMap::new();
         let mut phi_stack_indices = IndexMap::new();
 
-        let mut local_types = Vec::new();
+        let mut local_entries = Vec::new();
+        let mut is_leaf = true;
+        let mut instrs = vec![];
+        let mut non_address_uses: HashMap<Value, usize> = HashMap::new();
+        let mut branch_comparisons = vec![];
 
         for &slot in &func_info.slots {
-            slot_stack_indices.insert(slot, local_types.len());
-            local_types.push(prog.get_slot(slot).inner_ty);
+            slot_stack_indices.insert(slot, local_entries.len());
+            local_entries.push(FrameEntry::Slot(slot));
         }
 
         //TODO maybe figure out the stack size required for the largest call here and then get rid of stack_delta?
@@ -131,66 +196,117 @@ impl AsmBuilder<'_> {
             let block_info = prog.get_block(block);
 
             for &phi in &block_info.phis {
-                let ty = prog.get_phi(phi).ty;
-                phi_stack_indices.insert(phi, PhiIndices { pre: local_types.len(), post: local_types.len() + 1 });
-                local_types.push(ty);
-                local_types.push(ty);
+                phi_stack_indices.insert(phi, PhiIndices { pre: local_entries.len(), post: local_entries.len() + 1 });
+                local_entries.push(FrameEntry::PhiPre(phi));
+                local_entries.push(FrameEntry::PhiPost(phi));
             }
 
             for &instr in &block_info.instructions {
-                let ty = prog.get_instr(instr).ty(prog);
-                instr_stack_indices.insert(instr, local_types.len());
-                local_types.push(ty);
+                let instr_info = prog.get_instr(instr);
+                instrs.push(instr);
+
+                //inline assembly might call functions as well
+                if let InstructionInfo::Call { target: _, args: _ } | InstructionInfo::InlineAsm { template: _, inputs: _, outputs: _ } = instr_info {
+                    is_leaf = false;
+                }
+
+                let pos = InstructionPos { func, block, instr };
+                for_each_usage_in_instr(pos, instr_info, |value, usage| {
+                    match usage {
+                        Usage::LoadAddr { pos: _ } | Usage::StoreAddr { pos: _ } |
+                        Usage::TupleFieldPtrBase { pos: _ } | Usage::PointerOffSetBase { pos: _ } => {}
+                        _ => *non_address_uses.entry(value).or_default() += 1,
+                    }
+                });
+            }
+            block_info.terminator.for_each_operand(|value| *non_address_uses.entry(value).or_default() += 1);
+
+            if let Terminator::Branch { cond: Value::Instr(cond), true_target: _, false_target: _ } = block_info.terminator {
+                if block_info.instructions.last() == Some(&cond) {
+                    branch_comparisons.push(cond);
+                }
             }
         });
 
-        let local_layout = TupleLayout::for_types(&self.prog, local_types.iter().copied());
+        //address computations that are only used as addresses are folded into the addressing mode of their users
+        //  instead of being computed on their own, see `append_address`
+        let folded_addresses: HashSet<Instruction> = instrs.iter().copied()
+            .filter(|&instr| matches!(
+                prog.get_instr(instr),
+                InstructionInfo::TupleFieldPtr { base: _, index: _, tuple_ty: _ } | InstructionInfo::PointerOffSet { base: _, index: _, ty: _ }
+            ))
+            .filter(|&instr| !non_address_uses.contains_key(&Value::Instr(instr)))
+            .collect();
+
+        //comparisons that are only used by the branch right after them only need to set the flags
+        let fused_comparisons: HashSet<Instruction> = branch_comparisons.into_iter()
+            .filter(|&instr| comparison_condition_code(prog.get_instr(instr)).is_some())
+            .filter(|&instr| non_address_uses.get(&Value::Instr(instr)) == Some(&1))
+            .collect();
+
+        for &instr in &instrs {
+            if !folded_addresses.contains(&instr) && !fused_comparisons.contains(&instr) {
+                instr_stack_indices.insert(instr, local_entries.len());
+                local_entries.push(FrameEntry::Instr(instr));
+            }
+        }
+
+        let local_layout = frame_layout(prog, func, &local_entries);
 
         let func_number = self.func_number(func);
-        if let Some(debug_name) = &func_info.debug_name {
-            output.append_ln(&format!("func_{}: ; {}: {}", func_number, debug_name, self.prog.format_type(func_info.ty)));
-        } else {
-            output.append_ln(&format!("func_{}: ; {}", func_number, self.prog.format_type(func_info.ty)));
-        }
+        let comment = match &func_info.debug_name {
+            Some(debug_name) => format!(" {}: {}", debug_name, self.prog.format_type(func_info.ty)),
+            None => format!(" {}", self.prog.format_type(func_info.ty)),
+        };
+        text.push_str(&format!("{} {}\n", self.printer.label(&format!("func_{}", func_number)), self.printer.comment(&comment)));
 
-        //grow stack
         let required_stack_alignment = max(param_layout.layout.alignment, local_layout.layout.alignment);
         if required_stack_alignment > STACK_ALIGNMENT {
             panic!("Cannot store type with alignment {} on stack with alignment {}", required_stack_alignment, STACK_ALIGNMENT)
         }
-        let param_size = next_multiple(param_layout.layout.size, STACK_ALIGNMENT);
+        let callee_pop_size = callee_pop_size(prog, &func_info.func_ty, &param_layout);
 
-        let local_stack_size = next_multiple(local_layout.layout.size, STACK_ALIGNMENT);
-        if local_stack_size != 0 {
-            output.append_instr(&format!("sub esp, {}", local_stack_size));
-        }
+        //the return address is on top of an aligned stack, so the frame size is chosen to realign it before calls,
+        //  leaf functions don't call anything so they don't need the padding
+        let frame_alignment = if is_leaf { STACK_ALIGNMENT } else { self.call_alignment };
 
         let mut func_builder = AsmFuncBuilder {
             prog,
-            output,
+            lines: vec![],
             parent: self,
             func,
+            next_block: None,
             param_layout,
             local_layout,
-            param_offset: local_stack_size + 4,
-            local_stack_size,
-            param_size,
+            frame_alignment,
+            used_registers: HashSet::new(),
+            //set by set_saved_registers
+            saved_registers: vec![],
+            param_offset: 0,
+            local_stack_size: 0,
+            callee_pop_size,
+            return_in_memory: returns_in_memory(prog, &func_info.func_ty),
             slot_stack_indices,
             instr_stack_indices,
             phi_stack_indices,
+            folded_addresses,
+            fused_comparisons,
+            flags: None,
         };
 
-        //copy over initial phi values
-        for (phi, phi_value) in zip_eq(&prog.get_block(func_info.entry.block).phis, &func_info.entry.phi_values) {
-            let pre_pos = func_builder.local_layout.offsets[func_builder.phi_stack_indices[phi].pre];
-            func_builder.append_value_to_mem(MemRegOffset::stack(pre_pos), phi_value, 0);
-        }
+        //only the callee-saved registers the code actually uses have to be saved, but saving them moves the
+        //  parameters, so the operands for those and the epilogue are only resolved when printing
+        func_builder.append_body();
+        let saved_registers = CALLEE_SAVED_REGISTERS.iter().copied()
+            .filter(|&reg| reg != Register::BP || !func_builder.parent.settings.frame_pointers)
+            .filter(|reg| func_builder.used_registers.contains(reg))
+            .collect_vec();
+        func_builder.set_saved_registers(saved_registers);
 
-        // generate the main code
-        // the entry block is visited first so we don't even need to jump to it
-        prog.visit_blocks(func, |block| {
-            func_builder.append_block(block);
-        });
+        let body = std::mem::take(&mut func_builder.lines);
+        func_builder.append_prologue();
+        func_builder.lines.extend(body);
+        func_builder.print_lines(&func_builder.lines, text);
     }
 }
 
@@ -213,26 +329,201 @@ impl AsmBuilder<'_> {
     }
 
     fn data_number(&mut self, data: Data) -> usize {
-        let next_num = self.data_numbers.len();
-        *self.data_numbers.entry(data).or_insert(next_num)
+        self.data_pool.number(self.prog, data)
+    }
+
+    fn global_number(&mut self, global: Global) -> usize {
+        let next_num = self.global_numbers.len();
+        *self.global_numbers.entry(global).or_insert(next_num)
     }
 }
 
-impl std::ops::Deref for AsmFuncBuilder<'_, '_, '_> {
-    type Target = Output;
+impl AsmFuncBuilder<'_, '_> {
+    /// Compute the size of the frame and where the parameters end up when `saved_registers` are saved.
+    fn set_saved_registers(&mut self, saved_registers: Vec<Register>) {
+        let frame_pointer_size = if self.parent.settings.frame_pointers { 4 } else { 0 };
+        let saved_size = frame_pointer_size + 4 * saved_registers.len() as i32;
 
-    fn deref(&self) -> &Self::Target {
-        self.output
+        self.local_stack_size = next_multiple(self.local_layout.layout.size + saved_size + 4, self.frame_alignment) - saved_size - 4;
+        //the hidden return pointer is pushed last, so it sits between the return address and the parameters
+        self.param_offset = self.local_stack_size + saved_size + 4 + if self.return_in_memory { 4 } else { 0 };
+        self.saved_registers = saved_registers;
     }
-}
 
-impl std::ops::DerefMut for AsmFuncBuilder<'_, '_, '_> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        self.output
+    fn append_prologue(&mut self) {
+        if self.parent.settings.frame_pointers {
+            self.append_instr("push", [EBP]);
+            self.append_instr("mov", [EBP, ESP]);
+        }
+        for reg in self.saved_registers.clone() {
+            self.append_instr("push", [reg.operand(RegisterSize::S32)]);
+        }
+        if self.local_stack_size != 0 {
+            self.append_instr("sub", [ESP, self.local_stack_size.into()]);
+        }
+    }
+
+    /// The instructions that undo the prologue and return.
+    fn epilogue(&self) -> Vec<Line> {
+        let mut epilogue = vec![];
+        if self.local_stack_size != 0 {
+            epilogue.push(Line::Instr(Instr::new("add", [ESP, self.local_stack_size.into()])));
+        }
+        for reg in self.saved_registers.iter().rev() {
+            epilogue.push(Line::Instr(Instr::new("pop", [reg.operand(RegisterSize::S32)])));
+        }
+        if self.parent.settings.frame_pointers {
+            epilogue.push(Line::Instr(Instr::new("pop", [EBP])));
+        }
+        if self.callee_pop_size != 0 {
+            epilogue.push(Line::Instr(Instr::new("ret", [self.callee_pop_size.into()])));
+        } else {
+            epilogue.push(Line::Instr(Instr::new("ret", [])));
+        }
+        epilogue
+    }
+
+    /// Print `lines` in the output syntax, now that the registers to save and so the frame layout are known. That
+    /// fixes the offsets of the parameter operands and the epilogue.
+    fn print_lines(&self, lines: &[Line], text: &mut String) {
+        let printer = self.parent.printer;
+
+        for line in lines {
+            let printed = match line {
+                Line::Instr(instr) => {
+                    let operands = instr.operands.iter().map(|operand| operand.resolve_param(self.param_offset)).collect();
+                    printer.instr(&Instr { mnemonic: instr.mnemonic.clone(), operands })
+                }
+                Line::Label(name) => {
+                    text.push_str(&format!("  {}\n", printer.label(name)));
+                    continue;
+                }
+                Line::Comment(comment) => printer.comment(comment),
+                Line::Undef { target, value } => {
+                    let target = printer.operand(&target.resolve_param(self.param_offset));
+                    printer.comment(&format!(" {} = {}", target, value))
+                }
+                Line::Align(alignment) => printer.align(*alignment),
+                Line::Address(label) => printer.address(label),
+                Line::Verbatim(code) => code.clone(),
+                Line::Epilogue => {
+                    self.print_lines(&self.epilogue(), text);
+                    continue;
+                }
+            };
+            text.push_str(&format!("    {}\n", printed));
+        }
+    }
+
+    fn append_body(&mut self) {
+        let prog = self.prog;
+        let func_info = prog.get_func(self.func);
+
+        //copy over initial phi values
+        for (phi, phi_value) in zip_eq(&prog.get_block(func_info.entry.block).phis, &func_info.entry.phi_values) {
+            let pre_pos = self.local_layout.offsets[self.phi_stack_indices[phi].pre];
+            self.append_value_to_mem(MemRegOffset::stack(pre_pos), phi_value, 0);
+        }
+
+        //generate the main code, the entry block comes first so we don't even need to jump to it
+        let order = block_order(prog, self.func);
+        for (i, &block) in order.iter().enumerate() {
+            self.next_block = order.get(i + 1).copied();
+            self.append_block(block);
+        }
+    }
+
+    /// Append an instruction, forgetting the flags if it might change them and keeping track of the registers it uses.
+    fn append_instr(&mut self, mnemonic: &str, operands: impl IntoIterator<Item=Operand>) {
+        let instr = Instr::new(mnemonic, operands);
+        if !preserves_flags(&instr.mnemonic) {
+            self.flags = None;
+        }
+        self.used_registers.extend(instr.operands.iter().flat_map(Operand::registers));
+        self.lines.push(Line::Instr(instr));
+    }
+
+    fn append_comment(&mut self, comment: &str) {
+        self.lines.push(Line::Comment(comment.to_owned()));
+    }
+
+    /// Append a comment for the undefined `value` instead of copying it into `target`.
+    fn append_undef_comment(&mut self, target: Operand, value: &Value) {
+        let value = self.prog.format_value(*value).to_string();
+        self.lines.push(Line::Undef { target, value });
+    }
+
+    /// Append a label, which is jumped to from elsewhere so the flags are no longer known.
+    fn append_label(&mut self, name: String) {
+        self.flags = None;
+        self.lines.push(Line::Label(name));
+    }
+
+    /// Append a line of inline assembly. Nothing is known about what it does to the flags, but the registers it
+    /// mentions are tracked.
+    fn append_verbatim(&mut self, code: &str) {
+        self.flags = None;
+
+        //comments don't use anything, even if they mention registers
+        let code_only = code.split([';', '#']).next().unwrap();
+        self.used_registers.extend(code_only.split(|c: char| !c.is_ascii_alphanumeric()).filter_map(Register::from_any_name));
+
+        self.lines.push(Line::Verbatim(code.to_owned()));
+    }
+
+    /// The condition code that is true if the comparison `cond` is, if the flags still hold its result.
+    fn live_condition(&self, cond: &Value) -> Option<&'static str> {
+        match (cond, self.flags) {
+            (&Value::Instr(cond), Some((instr, code))) if cond == instr => Some(code),
+            _ => None,
+        }
+    }
+
+    /// Set the flags for the boolean `cond`, using `reg` if it has to be tested. Returns the condition codes that are
+    /// true if `cond` is true and false respectively.
+    fn append_test_condition(&mut self, cond: &Value, reg: Register) -> (&'static str, &'static str) {
+        if let Some(code) = self.live_condition(cond) {
+            return (code, negate_condition_code(code));
+        }
+
+        self.append_value_to_reg(reg, cond, 0);
+        let reg = reg.operand(RegisterSize::S8);
+        self.append_instr("test", [reg.clone(), reg]);
+        ("nz", "z")
+    }
+
+    /// Compare the operands of the `Comparison` or `FloatComparison` instruction `instr`, setting the flags. Returns
+    /// the condition code that is true if the comparison is, if there is a single one.
+    fn append_compare(&mut self, instr: Instruction) -> Option<&'static str> {
+        let instr_info = self.prog.get_instr(instr);
+        match instr_info {
+            InstructionInfo::Comparison { kind: _, left, right } => {
+                let size = self.append_value_to_reg(Register::A, left, 0);
+                self.append_value_to_reg(Register::B, right, 0);
+                self.append_instr("cmp", [Register::A.operand(size), Register::B.operand(size)]);
+            }
+            InstructionInfo::FloatComparison { kind, left, right } => {
+                let ty = self.prog.type_of_value(*left);
+                let suffix = float_suffix(self.prog.get_type(ty).unwrap_float().unwrap());
+
+                //ucomis sets ZF, PF and CF for unordered operands, so use the "above" conditions (which are false
+                //  in that case) and swap the operands for less-than comparisons
+                let (left, right) = match kind {
+                    LogicalOp::Lt(_) | LogicalOp::Lte(_) => (right, left),
+                    _ => (left, right),
+                };
+                self.append_value_to_xmm(0, left, 0);
+                self.append_value_to_xmm(1, right, 0);
+                self.append_instr(&format!("ucomis{}", suffix), [Operand::Xmm(0), Operand::Xmm(1)]);
+            }
+            _ => panic!("{:?} is not a comparison", instr),
+        }
+
+        let code = comparison_condition_code(instr_info);
+        self.flags = code.map(|code| (instr, code));
+        code
     }
-}
 
-impl AsmFuncBuilder<'_, '_, '_> {
     /// Copy a type with the given layout from `source` to `target`:
     /// `*target = *source`. Clobbers `eax`.
     fn append_mem_copy(&mut self, target: MemRegOffset, source: MemRegOffset, size: i32) {
@@ -240,52 +531,213 @@ impl AsmFuncBuilder<'_, '_, '_> {
 
         while left >= 4 {
             left -= 4;
-            self.append_instr(&format!("mov eax, dword {}", source + left));
-            self.append_instr(&format!("mov {}, eax", target + left));
+            self.append_instr("mov", [EAX, Operand::from(source + left).sized(OperandSize::Dword)]);
+            self.append_instr("mov", [(target + left).into(), EAX]);
         }
 
         while left >= 2 {
             left -= 2;
-            self.append_instr(&format!("movzx eax, word {}", source + left));
-            self.append_instr(&format!("mov {}, ax", target + left));
+            self.append_instr("movzx", [EAX, Operand::from(source + left).sized(OperandSize::Word)]);
+            self.append_instr("mov", [(target + left).into(), AX]);
         }
 
         while left >= 1 {
             left -= 1;
-            self.append_instr(&format!("movzx eax, byte {}", source + left));
-            self.append_instr(&format!("mov {}, al", target + left));
+            self.append_instr("movzx", [EAX, Operand::from(source + left).sized(OperandSize::Byte)]);
+            self.append_instr("mov", [(target + left).into(), AL]);
         }
 
         assert_eq!(left, 0);
     }
 
+    /// Apply the unary integer intrinsic `kind` to `value` and store the result at `[esp+instr_pos]`.
+    fn append_unary_intrinsic(&mut self, kind: IntrinsicKind, value: &Value, instr_pos: i32) {
+        //append_value_to_reg zero-extends the value to the full register
+        let bits = self.prog.get_type(self.prog.type_of_value(*value)).unwrap_int()
+            .expect("unary intrinsic needs an integer argument");
+        let size = self.append_value_to_reg(Register::A, value, 0);
+
+        match kind {
+            IntrinsicKind::Ctlz => {
+                //bsr gives the index of the highest set bit and leaves zero undefined
+                self.append_instr("mov", [ECX, (-1).into()]);
+                self.append_instr("bsr", [EAX, EAX]);
+                self.append_instr("cmovz", [EAX, ECX]);
+                self.append_instr("neg", [EAX]);
+                self.append_instr("add", [EAX, (bits as i32 - 1).into()]);
+            }
+            IntrinsicKind::Cttz => {
+                self.append_instr("mov", [ECX, (bits as i32).into()]);
+                self.append_instr("bsf", [EAX, EAX]);
+                self.append_instr("cmovz", [EAX, ECX]);
+            }
+            IntrinsicKind::Popcount => self.append_instr("popcnt", [EAX, EAX]),
+            IntrinsicKind::Bswap => match bits {
+                8 => {}
+                16 => self.append_instr("rol", [AX, 8.into()]),
+                32 => self.append_instr("bswap", [EAX]),
+                _ => panic!("bswap of {} bits not supported", bits),
+            },
+            IntrinsicKind::Trap => unreachable!(),
+        }
+
+        self.append_instr("mov", [MemRegOffset::stack(instr_pos).into(), Register::A.operand(size)]);
+    }
+
+    /// Jump to the label of the case matching the value in `eax` through a table indexed by the value,
+    /// or to `default_label` if there is no matching case. `cases` must be sorted and without duplicates.
+    fn append_jump_table(&mut self, cases: &[(i32, usize)], default_label: usize) {
+        let min = cases[0].0;
+        let max = cases[cases.len() - 1].0;
+        let table_label = self.parent.label_number();
+
+        //after subtracting min an unsigned comparison checks both bounds at once
+        self.append_comment("  jump table");
+        self.append_instr("sub", [EAX, min.into()]);
+        self.append_instr("cmp", [EAX, max.wrapping_sub(min).into()]);
+        self.append_instr("ja", [label_operand(default_label)]);
+        self.append_instr("jmp", [Operand::JumpTable(format!("label_{}", table_label), Register::A)]);
+
+        self.lines.push(Line::Align(4));
+        self.append_label(format!("label_{}", table_label));
+        let mut cases = cases.iter().peekable();
+        for value in min..=max {
+            let label = match cases.next_if(|&&(case, _)| case == value) {
+                Some(&(_, label)) => label,
+                None => default_label,
+            };
+            self.lines.push(Line::Address(format!("label_{}", label)));
+        }
+    }
+
+    /// Jump to the label of the case matching the value in `eax` with a binary search, or to `default_label` if there
+    /// is no matching case. `cases` must be sorted and without duplicates.
+    fn append_compare_tree(&mut self, cases: &[(i32, usize)], default_label: usize) {
+        if cases.len() <= SWITCH_LINEAR_LIMIT {
+            for &(value, label) in cases {
+                self.append_instr("cmp", [EAX, value.into()]);
+                self.append_instr("je", [label_operand(label)]);
+            }
+            self.append_instr("jmp", [label_operand(default_label)]);
+            return;
+        }
+
+        //the values are compared as signed, narrower integers are zero-extended so they're never negative
+        let mid = cases.len() / 2;
+        let (mid_value, mid_label) = cases[mid];
+        let upper_label = self.parent.label_number();
+
+        self.append_instr("cmp", [EAX, mid_value.into()]);
+        self.append_instr("je", [label_operand(mid_label)]);
+        self.append_instr("jg", [label_operand(upper_label)]);
+        self.append_compare_tree(&cases[..mid], default_label);
+        self.append_label(format!("label_{}", upper_label));
+        self.append_compare_tree(&cases[mid + 1..], default_label);
+    }
+
+    /// Get a memory operand that points to `addr`, putting the base in `ebx` and the index in `ecx` if they are
+    /// needed. Stack slots and folded address computations become part of the operand.
+    fn append_address(&mut self, addr: &Value) -> MemRegOffset {
+        match addr {
+            Value::Slot(slot) => MemRegOffset::stack(self.local_layout.offsets[self.slot_stack_indices[slot]]),
+            Value::Instr(instr) if self.folded_addresses.contains(instr) => self.append_address_instr(*instr),
+            _ => {
+                self.append_value_to_reg(Register::B, addr, 0);
+                Register::B.mem()
+            }
+        }
+    }
+
+    /// Get a memory operand that points to the result of the `TupleFieldPtr` or `PointerOffSet` instruction `instr`,
+    /// see `append_address`. Constant offsets become the displacement and a variable index becomes the scaled index.
+    fn append_address_instr(&mut self, instr: Instruction) -> MemRegOffset {
+        match self.prog.get_instr(instr) {
+            InstructionInfo::TupleFieldPtr { base, index, tuple_ty } => {
+                let tuple_ty = self.prog.get_type(*tuple_ty).unwrap_tuple()
+                    .expect("TupleFieldPtr target should have tuple pointer type");
+                let field_offset = TupleLayout::for_tuple(self.prog, tuple_ty).offsets[*index as usize];
+
+                self.append_address(base) + field_offset
+            }
+            InstructionInfo::PointerOffSet { base, index, ty } => {
+                let size = Layout::for_type(self.prog, *ty).size;
+                let mem = self.append_address(base);
+
+                match index {
+                    Value::Const(cst) => mem + cst.value.wrapping_mul(size),
+                    _ if size == 0 => mem,
+                    _ => {
+                        //there is only room for a single index, so fold an existing one into the base first
+                        let mut mem = match mem.index {
+                            Some(_) => {
+                                self.append_instr("lea", [EBX, mem.into()]);
+                                Register::B.mem()
+                            }
+                            None => mem,
+                        };
+
+                        self.append_value_to_reg(Register::C, index, 0);
+                        let scale = match size {
+                            //the scales the addressing mode supports directly
+                            1 | 2 | 4 | 8 => size,
+                            _ => {
+                                self.append_instr("imul", [ECX, ECX, size.into()]);
+                                1
+                            }
+                        };
+                        mem.index = Some((Register::C, scale));
+                        mem
+                    }
+                }
+            }
+            _ => panic!("{:?} is not an address computation", instr),
+        }
+    }
+
+    /// Fill `size` bytes at `target` with the byte that is repeated in all four bytes of `eax`.
+    fn append_mem_fill(&mut self, target: MemRegOffset, size: i32) {
+        let mut offset = 0;
+
+        while size - offset >= 4 {
+            self.append_instr("mov", [Operand::from(target + offset).sized(OperandSize::Dword), EAX]);
+            offset += 4;
+        }
+        if size - offset >= 2 {
+            self.append_instr("mov", [Operand::from(target + offset).sized(OperandSize::Word), AX]);
+            offset += 2;
+        }
+        if size - offset >= 1 {
+            self.append_instr("mov", [Operand::from(target + offset).sized(OperandSize::Byte), AL]);
+        }
+    }
+
     /// Copy `value` into at `target`: `*target = value`. Clobbers `eax`.
     fn append_value_to_mem(&mut self, target: MemRegOffset, value: &Value, stack_delta: i32) {
         //TODO deduplicate this code with append_value_to_reg
         //TODO redesign this stack_delta stuff, the current implementation is just one big minefield
 
         let ty = self.prog.type_of_value(*value);
-        let layout = Layout::for_type(&self.prog, ty);
+        let layout = Layout::for_type(self.prog, ty);
 
         match value {
             Value::Undef(_) => {
                 //do nothing, just a comment for clarity
-                let str = format!("; {} = {}", target, self.prog.format_value(*value));
-                self.append_instr(&str)
+                self.append_undef_comment(target.into(), value)
             }
             Value::Const(cst) => {
-                match layout.size {
-                    0 => {} //easy
-                    1 => self.append_instr(&format!("mov {}, byte {}", target, cst.value)),
-                    2 => self.append_instr(&format!("mov {}, word {}", target, cst.value)),
-                    4 => self.append_instr(&format!("mov {}, dword {}", target, cst.value)),
+                let size = match layout.size {
+                    0 => return, //easy
+                    1 => OperandSize::Byte,
+                    2 => OperandSize::Word,
+                    4 => OperandSize::Dword,
                     _ => panic!("only constants with power of two size <= 4 supported for now"),
-                }
+                };
+                self.append_instr("mov", [target.into(), Operand::from(cst.value).sized(size)]);
             }
             Value::Func(func) => {
                 assert_eq!(layout.size, 4);
                 let func_number = self.parent.func_number(*func);
-                self.append_instr(&format!("mov {}, dword func_{}", target, func_number));
+                self.append_instr("mov", [target.into(), Operand::Symbol(format!("func_{}", func_number)).sized(OperandSize::Dword)]);
             }
             Value::Param(param) => {
                 let param_index = self.prog.get_func(self.func).params.iter()
@@ -293,13 +745,12 @@ impl AsmFuncBuilder<'_, '_, '_> {
                     .expect("param does not belong to this function");
 
                 let stack_pos = self.param_layout.offsets[param_index];
-                let real_stack_pos = stack_delta + stack_pos + self.param_offset;
-                self.append_mem_copy(target, MemRegOffset::stack(real_stack_pos), layout.size);
+                self.append_mem_copy(target, MemRegOffset::param(stack_delta + stack_pos), layout.size);
             }
             Value::Slot(slot) => {
                 let stack_pos = self.local_layout.offsets[self.slot_stack_indices[slot]];
-                self.append_instr(&format!("lea eax, [esp+{}]", stack_delta + stack_pos));
-                self.append_instr(&format!("mov {}, eax", target));
+                self.append_instr("lea", [EAX, MemRegOffset::stack(stack_delta + stack_pos).into()]);
+                self.append_instr("mov", [target.into(), EAX]);
             }
             Value::Phi(phi) => {
                 let stack_pos = self.local_layout.offsets[self.phi_stack_indices[phi].post];
@@ -312,13 +763,18 @@ impl AsmFuncBuilder<'_, '_, '_> {
             Value::Extern(ext) => {
                 assert_eq!(layout.size, 4);
                 let name = &self.prog.get_ext(*ext).name;
-                self.append_instr(&format!("mov {}, dword {}", target, name));
-                self.header.push_str(&format!("extern {}\n", name))
+                self.append_instr("mov", [target.into(), Operand::Symbol(name.clone()).sized(OperandSize::Dword)]);
+                self.parent.externs.push(name.clone());
             }
             Value::Data(data) => {
                 assert_eq!(layout.size, 4);
                 let data_number = self.parent.data_number(*data);
-                self.append_instr(&format!("mov {}, dword data_{}", target, data_number));
+                self.append_instr("mov", [target.into(), Operand::Symbol(format!("data_{}", data_number)).sized(OperandSize::Dword)]);
+            }
+            Value::Global(global) => {
+                assert_eq!(layout.size, 4);
+                let global_number = self.parent.global_number(*global);
+                self.append_instr("mov", [target.into(), Operand::Symbol(format!("global_{}", global_number)).sized(OperandSize::Dword)]);
             }
         }
     }
@@ -331,27 +787,26 @@ impl AsmFuncBuilder<'_, '_, '_> {
         //  where source can then have a function to_MemRegOffset and to_register? (or either of them)
 
         let ty = self.prog.type_of_value(*value);
-        let layout = Layout::for_type(&self.prog, ty);
+        let layout = Layout::for_type(self.prog, ty);
 
         let register_size = RegisterSize::for_size(layout.size)
             .unwrap_or_else(|()| panic!("Tried to put value {:?} with size {} into reg", value, layout.size))
             .unwrap_or_else(|| panic!("Tried to put zero-sized value {:?} into reg", value));
-        let full_target = target.with_size(RegisterSize::S32);
-        let target = target.with_size(register_size);
+        let full_target = target.operand(RegisterSize::S32);
+        let target = target.operand(register_size);
 
         match value {
             Value::Undef(_) => {
                 //do nothing, just a comment for clarity
-                let str = format!("; {} = {}", target, self.prog.format_value(*value));
-                self.append_instr(&str)
+                self.append_undef_comment(target.clone(), value)
             }
             Value::Const(cst) => {
-                self.append_instr(&format!("mov {}, {}", target, cst.value))
+                self.append_instr("mov", [target.clone(), cst.value.into()])
             }
             Value::Func(func) => {
                 assert_eq!(layout.size, 4);
                 let func_number = self.parent.func_number(*func);
-                self.append_instr(&format!("mov {}, func_{}", target, func_number));
+                self.append_instr("mov", [target.clone(), Operand::Symbol(format!("func_{}", func_number))]);
             }
             Value::Param(param) => {
                 let param_index = self.prog.get_func(self.func).params.iter()
@@ -359,85 +814,279 @@ impl AsmFuncBuilder<'_, '_, '_> {
                     .expect("param does not belong to this function");
 
                 let stack_pos = self.param_layout.offsets[param_index];
-                let real_stack_pos = stack_delta + stack_pos + self.param_offset;
-                self.append_instr(&format!("mov {}, [esp+{}]", target, real_stack_pos));
+                self.append_instr("mov", [target.clone(), MemRegOffset::param(stack_delta + stack_pos).into()]);
             }
             Value::Slot(slot) => {
                 let stack_pos = self.local_layout.offsets[self.slot_stack_indices[slot]];
-                self.append_instr(&format!("lea {}, [esp+{}]", target, stack_delta + stack_pos));
+                self.append_instr("lea", [target.clone(), MemRegOffset::stack(stack_delta + stack_pos).into()]);
             }
             Value::Phi(phi) => {
                 let stack_pos = self.local_layout.offsets[self.phi_stack_indices[phi].post];
-                self.append_instr(&format!("mov {}, [esp+{}]", target, stack_delta + stack_pos));
+                self.append_instr("mov", [target.clone(), MemRegOffset::stack(stack_delta + stack_pos).into()]);
             }
             Value::Instr(instr) => {
                 let stack_pos = self.local_layout.offsets[self.instr_stack_indices[instr]];
-                self.append_instr(&format!("mov {}, [esp+{}]", target, stack_delta + stack_pos));
+                self.append_instr("mov", [target.clone(), MemRegOffset::stack(stack_delta + stack_pos).into()]);
             }
             Value::Extern(ext) => {
                 assert_eq!(layout.size, 4);
                 let name = &self.prog.get_ext(*ext).name;
-                self.append_instr(&format!("mov {}, {}", target, name));
-                self.header.push_str(&format!("extern {}\n", name))
+                self.append_instr("mov", [target.clone(), Operand::Symbol(name.clone())]);
+                self.parent.externs.push(name.clone());
             }
             Value::Data(data) => {
                 assert_eq!(layout.size, 4);
                 let data_number = self.parent.data_number(*data);
-                self.append_instr(&format!("mov {}, dword data_{}", target, data_number));
+                self.append_instr("mov", [target.clone(), Operand::Symbol(format!("data_{}", data_number)).sized(OperandSize::Dword)]);
+            }
+            Value::Global(global) => {
+                assert_eq!(layout.size, 4);
+                let global_number = self.parent.global_number(*global);
+                self.append_instr("mov", [target.clone(), Operand::Symbol(format!("global_{}", global_number)).sized(OperandSize::Dword)]);
             }
         }
 
         //clear upper bits
-        if full_target != target {
-            self.append_instr(&format!("movzx {}, {}", full_target, target));
+        if register_size != RegisterSize::S32 {
+            self.append_instr("movzx", [full_target, target]);
         }
 
         register_size
     }
 
+    /// Get the stack location of `value`, which must be a value that lives on the stack.
+    fn stack_value_mem(&self, value: &Value, stack_delta: i32) -> MemRegOffset {
+        match value {
+            Value::Param(param) => {
+                let param_index = self.prog.get_func(self.func).params.iter()
+                    .position(|x| x == param)
+                    .expect("param does not belong to this function");
+                MemRegOffset::param(stack_delta + self.param_layout.offsets[param_index])
+            }
+            Value::Phi(phi) => MemRegOffset::stack(stack_delta + self.local_layout.offsets[self.phi_stack_indices[phi].post]),
+            Value::Instr(instr) => MemRegOffset::stack(stack_delta + self.local_layout.offsets[self.instr_stack_indices[instr]]),
+            _ => panic!("value {:?} does not live on the stack", value),
+        }
+    }
+
+    /// Copy the float `value` into the sse register `xmm<target>`. Does not clobber any additional registers.
+    fn append_value_to_xmm(&mut self, target: u8, value: &Value, stack_delta: i32) {
+        let ty = self.prog.type_of_value(*value);
+        let suffix = float_suffix(self.prog.get_type(ty).unwrap_float()
+            .unwrap_or_else(|| panic!("Tried to put non-float value {:?} into xmm{}", value, target)));
+
+        match value {
+            Value::Undef(_) => {
+                //do nothing, just a comment for clarity
+                self.append_undef_comment(Operand::Xmm(target), value)
+            }
+            _ => {
+                let mem = self.stack_value_mem(value, stack_delta);
+                self.append_instr(&format!("movs{}", suffix), [Operand::Xmm(target), mem.into()]);
+            }
+        }
+    }
+
     /// ```
     /// A = A / B
     /// D = A % B
     /// ```
-    fn append_div(&mut self, size: RegisterSize) {
-        // the upper (unused) bits should be clear already, `append_value_to_reg` zero-extends,
-        // so we don't need to clear them here
-        match size {
-            RegisterSize::S8 => {
-                self.append_instr("cwd");
-                self.append_instr("idiv bx");
-                self.append_instr("mov edx, eax")
+    /// ```
+    /// A = A / B
+    /// D = A % B
+    /// ```
+    fn append_div(&mut self, size: RegisterSize, signedness: Signedness) {
+        //8 bit division puts the remainder in ah, so divide 16 bit values instead
+        let size = if size == RegisterSize::S8 {
+            // the upper (unused) bits are clear already, `append_value_to_reg` zero-extends
+            if signedness == Signedness::Signed {
+                self.append_instr("movsx", [AX, AL]);
+                self.append_instr("movsx", [BX, BL]);
+            }
+            RegisterSize::S16
+        } else {
+            size
+        };
+
+        let b = Register::B.operand(size);
+        match signedness {
+            Signedness::Signed => {
+                self.append_instr(if size == RegisterSize::S16 { "cwd" } else { "cdq" }, []);
+                self.append_instr("idiv", [b]);
             }
-            RegisterSize::S16 | RegisterSize::S32 => {
-                self.append_instr(&format!("xor {d}, {d}", d = Register::D.with_size(size)));
-                self.append_instr(&format!("idiv {}", Register::B.with_size(size)));
+            Signedness::Unsigned => {
+                let d = Register::D.operand(size);
+                self.append_instr("xor", [d.clone(), d]);
+                self.append_instr("div", [b]);
             }
         }
     }
 
-    fn append_jump_to_target(&mut self, target: &Target) {
+    /// ```
+    /// A = op(A, value)
+    /// ```
+    /// using shifts and masks instead of multiplication and division by powers of two, and multiplication by a magic
+    /// number instead of division by other constants. Returns false without emitting anything if there is no cheaper
+    /// sequence, `B` is not used. Clobbers `C` and `D`.
+    fn append_arithmetic_by_const(&mut self, kind: ArithmeticOp, size: RegisterSize, value: i32) -> bool {
+        let a = Register::A.operand(size);
+
+        match kind {
+            ArithmeticOp::Add | ArithmeticOp::Sub => false,
+            ArithmeticOp::Mul => {
+                //the lower bits of the product don't depend on the signedness
+                let k = match abs_log2(value) {
+                    Some(k) => k,
+                    None => return false,
+                };
+                if k != 0 {
+                    self.append_instr("shl", [a.clone(), (k as i32).into()]);
+                }
+                if value < 0 {
+                    self.append_instr("neg", [a]);
+                }
+                true
+            }
+            //leave division by zero to the division instruction
+            ArithmeticOp::Div(_) | ArithmeticOp::Mod(_) if value == 0 => false,
+            //the operands are zero-extended, so unsigned powers of two work the same for every size
+            ArithmeticOp::Div(Signedness::Unsigned) | ArithmeticOp::Mod(Signedness::Unsigned) => {
+                //TODO also use magic numbers for unsigned division
+                let value = value as u32;
+                if !value.is_power_of_two() { return false; }
+
+                if let ArithmeticOp::Div(_) = kind {
+                    let k = value.trailing_zeros();
+                    if k != 0 {
+                        self.append_instr("shr", [EAX, (k as i32).into()]);
+                    }
+                } else {
+                    self.append_instr("and", [EAX, ((value - 1) as i32).into()]);
+                }
+                true
+            }
+            //TODO also reduce signed 8 and 16 bit division
+            ArithmeticOp::Div(Signedness::Signed) | ArithmeticOp::Mod(Signedness::Signed) if size != RegisterSize::S32 => false,
+            ArithmeticOp::Div(Signedness::Signed) => {
+                if let Some(k) = abs_log2(value) {
+                    self.append_signed_div_pow2(k);
+                    if value < 0 {
+                        self.append_instr("neg", [EAX]);
+                    }
+                } else {
+                    self.append_signed_div_magic(value);
+                    self.append_instr("mov", [EAX, EDX]);
+                }
+                true
+            }
+            ArithmeticOp::Mod(Signedness::Signed) => {
+                if let Some(k) = abs_log2(value) {
+                    //the remainder has the sign of the dividend: x - ((x + bias) & -2^k)
+                    if k == 0 {
+                        self.append_instr("xor", [EAX, EAX]);
+                    } else {
+                        let mask = (1u32 << k) - 1;
+                        self.append_signed_div_bias(mask);
+                        self.append_instr("add", [EDX, EAX]);
+                        self.append_instr("and", [EDX, (!mask as i32).into()]);
+                        self.append_instr("sub", [EAX, EDX]);
+                    }
+                } else {
+                    self.append_signed_div_magic(value);
+                    self.append_instr("imul", [EDX, EDX, value.into()]);
+                    self.append_instr("mov", [EAX, ECX]);
+                    self.append_instr("sub", [EAX, EDX]);
+                }
+                true
+            }
+        }
+    }
+
+    /// ```
+    /// D = mask if A < 0 else 0
+    /// ```
+    /// Adding this to the dividend makes shifting round towards zero.
+    fn append_signed_div_bias(&mut self, mask: u32) {
+        self.append_instr("mov", [EDX, EAX]);
+        self.append_instr("sar", [EDX, 31.into()]);
+        self.append_instr("and", [EDX, (mask as i32).into()]);
+    }
+
+    /// ```
+    /// A = A / 2^k
+    /// ```
+    fn append_signed_div_pow2(&mut self, k: u32) {
+        if k == 0 { return; }
+        self.append_signed_div_bias((1u32 << k) - 1);
+        self.append_instr("add", [EAX, EDX]);
+        self.append_instr("sar", [EAX, (k as i32).into()]);
+    }
+
+    /// ```
+    /// C = A
+    /// D = A / value
+    /// ```
+    fn append_signed_div_magic(&mut self, value: i32) {
+        let magic = signed_div_magic(value);
+
+        self.append_instr("mov", [ECX, EAX]);
+        self.append_instr("mov", [EAX, magic.multiplier.into()]);
+        self.append_instr("imul", [ECX]);
+        if value > 0 && magic.multiplier < 0 {
+            self.append_instr("add", [EDX, ECX]);
+        }
+        if value < 0 && magic.multiplier > 0 {
+            self.append_instr("sub", [EDX, ECX]);
+        }
+        if magic.shift != 0 {
+            self.append_instr("sar", [EDX, (magic.shift as i32).into()]);
+        }
+
+        //round towards zero by adding one for negative quotients
+        self.append_instr("mov", [EAX, EDX]);
+        self.append_instr("shr", [EAX, 31.into()]);
+        self.append_instr("add", [EDX, EAX]);
+    }
+
+    fn append_phi_copies(&mut self, target: &Target) {
         let target_block_info = self.prog.get_block(target.block);
 
         for (phi, phi_value) in zip_eq(&target_block_info.phis, &target.phi_values) {
             let pre_pos = self.local_layout.offsets[self.phi_stack_indices[phi].pre];
             self.append_value_to_mem(MemRegOffset::stack(pre_pos), phi_value, 0);
         }
+    }
+
+    fn append_jump_to_target(&mut self, target: &Target) {
+        self.append_phi_copies(target);
 
         let block_number = self.parent.block_number(target.block);
-        self.append_instr(&format!("jmp block_{}", block_number));
+        self.append_instr("jmp", [Operand::Symbol(format!("block_{}", block_number))]);
     }
 
+    /// The same as `append_jump_to_target`, but the jump is left out if the target is the next block.
+    /// This has to be the last thing emitted for the current block.
+    fn append_final_jump_to_target(&mut self, target: &Target) {
+        if self.next_block == Some(target.block) {
+            self.append_phi_copies(target);
+        } else {
+            self.append_jump_to_target(target);
+        }
+    }
     pub fn append_block(&mut self, block: Block) {
         let block_number = self.parent.block_number(block);
-        self.append_ln(&format!("  block_{}:", block_number));
+        self.append_label(format!("block_{}", block_number));
+        if let Some(comments) = &mut self.parent.source_comments {
+            comments.reset();
+        }
 
         let block = self.prog.get_block(block);
 
         //copy phi values from pre to post
         if !block.phis.is_empty() {
-            self.append_instr(";Phi copy");
+            self.append_comment("Phi copy");
             for phi in &block.phis {
-                let size = Layout::for_type(&self.prog, self.prog.get_phi(*phi).ty).size;
+                let size = Layout::for_type(self.prog, self.prog.get_phi(*phi).ty).size;
 
                 let PhiIndices { pre, post } = self.phi_stack_indices[phi];
                 let pre_pos = self.local_layout.offsets[pre];
@@ -449,175 +1098,569 @@ impl AsmFuncBuilder<'_, '_, '_> {
 
         //write out instructions
         for instr in &block.instructions {
+            if self.folded_addresses.contains(instr) {
+                continue;
+            }
+            let prog = self.prog;
+            if let Some(comment) = self.parent.source_comments.as_mut().and_then(|comments| comments.comment(prog, *instr)) {
+                self.append_comment(&format!(" {}", comment));
+            }
+            if self.fused_comparisons.contains(instr) {
+                //the branch jumps on the flags directly
+                self.append_comment("Comparison");
+                self.append_compare(*instr);
+                continue;
+            }
             let instr_pos = self.local_layout.offsets[self.instr_stack_indices[instr]];
 
             match self.prog.get_instr(*instr) {
                 InstructionInfo::Store { addr, ty, value } => {
                     assert_eq!(*ty, self.prog.type_of_value(*value));
-                    self.append_instr(";Store");
-                    self.append_value_to_reg(Register::B, addr, 0);
-                    self.append_value_to_mem(Register::B.mem(), value, 0);
+                    self.append_comment("Store");
+                    let mem = self.append_address(addr);
+                    self.append_value_to_mem(mem, value, 0);
                 }
                 InstructionInfo::Load { addr, ty } => {
                     let result_layout = Layout::for_type(self.prog, *ty);
 
-                    self.append_instr(";Load");
-                    self.append_value_to_reg(Register::B, addr, 0);
-                    self.append_mem_copy(MemRegOffset::stack(instr_pos), Register::B.mem(), result_layout.size);
+                    self.append_comment("Load");
+                    let mem = self.append_address(addr);
+                    self.append_mem_copy(MemRegOffset::stack(instr_pos), mem, result_layout.size);
                 }
                 InstructionInfo::Call { target, args } => {
-                    self.append_instr(";Call");
+                    self.append_comment("Call");
 
                     let func_ty = self.prog.type_of_value(*target);
                     let func_ty = self.prog.get_type(func_ty).unwrap_func()
                         .expect("Call target must have function type");
 
                     //TODO check whether eg f(a: byte, b: byte) should indeed be packed in stdcall
-                    let param_layout = TupleLayout::for_types(&self.prog, func_ty.params.iter().copied());
+                    let param_layout = param_layout(self.prog, func_ty.conv, func_ty.params.iter().copied());
                     if param_layout.layout.alignment > STACK_ALIGNMENT {
                         panic!("Cannot use argument type with alignment {} on stack with alignment {}", param_layout.layout.alignment, STACK_ALIGNMENT)
                     }
+                    let return_in_memory = returns_in_memory(self.prog, func_ty);
+                    let return_ptr_size = if return_in_memory { 4 } else { 0 };
 
                     // increment SP and push the arguments
-                    let stack_delta = next_multiple(param_layout.layout.size, STACK_ALIGNMENT);
+                    let stack_delta = next_multiple(return_ptr_size + param_layout.layout.size, self.parent.call_alignment);
                     if stack_delta != 0 {
-                        self.append_instr(&format!("sub esp, {}", stack_delta));
+                        self.append_instr("sub", [ESP, stack_delta.into()]);
                     }
 
-                    for (arg, offset) in zip_eq(args, param_layout.offsets).rev() {
-                        self.append_value_to_mem(MemRegOffset::stack(offset), arg, stack_delta);
+                    for (arg, &offset) in zip_eq(args, &param_layout.offsets).rev() {
+                        self.append_value_to_mem(MemRegOffset::stack(return_ptr_size + offset), arg, stack_delta);
+                    }
+                    if return_in_memory {
+                        //the callee writes the return value directly into the stack slot of this instruction
+                        self.append_instr("lea", [EAX, MemRegOffset::stack(stack_delta + instr_pos).into()]);
+                        self.append_instr("mov", [MemRegOffset::stack(0).into(), EAX]);
                     }
 
                     //the actual call
                     self.append_value_to_reg(Register::A, target, stack_delta);
-                    self.append_instr("call eax");
+                    self.append_instr("call", [EAX]);
+
+                    //pop whatever the callee didn't pop itself
+                    let caller_pop_size = stack_delta - callee_pop_size(self.prog, func_ty, &param_layout);
+                    if caller_pop_size != 0 {
+                        self.append_instr("add", [ESP, caller_pop_size.into()]);
+                    }
 
                     //copy the return register to the stack
-                    let return_layout = Layout::for_type(&self.prog, func_ty.ret);
-                    let return_register_size = RegisterSize::for_size(return_layout.size)
-                        .unwrap_or_else(|()| panic!("Return value for {:?} size {} does not fit in register", instr, return_layout.size));
-
-                    if let Some(return_register_size) = return_register_size {
-                        self.append_instr(
-                            &format!("mov [esp+{}], {}", instr_pos, Register::A.with_size(return_register_size))
-                        );
+                    let return_layout = Layout::for_type(self.prog, func_ty.ret);
+                    if return_in_memory {
+                        //already stored by the callee
+                    } else if let Some(bits) = returns_on_x87(self.prog, func_ty) {
+                        self.append_instr("fstp", [Operand::from(MemRegOffset::stack(instr_pos)).sized(x87_size(bits))]);
+                    } else if return_layout.size == 8 {
+                        //8 byte values are returned in edx:eax
+                        self.append_instr("mov", [MemRegOffset::stack(instr_pos).into(), EAX]);
+                        self.append_instr("mov", [MemRegOffset::stack(instr_pos + 4).into(), EDX]);
+                    } else {
+                        let return_register_size = RegisterSize::for_size(return_layout.size)
+                            .unwrap_or_else(|()| panic!("Return value for {:?} size {} does not fit in register", instr, return_layout.size));
+
+                        if let Some(return_register_size) = return_register_size {
+                            self.append_instr("mov", [MemRegOffset::stack(instr_pos).into(), Register::A.operand(return_register_size)]);
+                        }
                     }
                 }
                 InstructionInfo::Arithmetic { kind, left, right } => {
-                    self.append_instr(";Arithmetic");
+                    self.append_comment("Arithmetic");
 
                     let size = self.append_value_to_reg(Register::A, left, 0);
-                    self.append_value_to_reg(Register::B, right, 0);
-
-                    let a = Register::A.with_size(size);
-                    let b = Register::B.with_size(size);
-                    let d = Register::D.with_size(size);
-
-                    //A = op(A, B)
-                    match kind {
-                        ArithmeticOp::Add => self.append_instr(&format!("add {}, {}", a, b)),
-                        ArithmeticOp::Sub => self.append_instr(&format!("sub {}, {}", a, b)),
-                        ArithmeticOp::Mul => {
-                            if size == RegisterSize::S8 {
-                                self.append_instr("imul bx");
-                            } else {
-                                self.append_instr(&format!("imul {}, {}", a, b));
+                    let a = Register::A.operand(size);
+
+                    let reduced = match *right {
+                        Value::Const(cst) => self.append_arithmetic_by_const(*kind, size, cst.value),
+                        _ => false,
+                    };
+
+                    if !reduced {
+                        self.append_value_to_reg(Register::B, right, 0);
+
+                        let b = Register::B.operand(size);
+                        let d = Register::D.operand(size);
+
+                        //A = op(A, B)
+                        match kind {
+                            ArithmeticOp::Add => self.append_instr("add", [a.clone(), b]),
+                            ArithmeticOp::Sub => self.append_instr("sub", [a.clone(), b]),
+                            ArithmeticOp::Mul => {
+                                if size == RegisterSize::S8 {
+                                    self.append_instr("imul", [BX]);
+                                } else {
+                                    self.append_instr("imul", [a.clone(), b]);
+                                }
+                            }
+                            ArithmeticOp::Div(signedness) => self.append_div(size, *signedness),
+                            ArithmeticOp::Mod(signedness) => {
+                                self.append_div(size, *signedness);
+                                self.append_instr("mov", [a.clone(), d]);
                             }
-                        }
-                        ArithmeticOp::Div => self.append_div(size),
-                        ArithmeticOp::Mod => {
-                            self.append_div(size);
-                            self.append_instr(&format!("mov {}, {}", a, d));
                         }
                     }
 
-                    self.append_instr(&format!("mov [esp+{}], {}", instr_pos, a));
+                    self.append_instr("mov", [MemRegOffset::stack(instr_pos).into(), a]);
                 }
-                InstructionInfo::Comparison { kind, left, right } => {
-                    self.append_instr(";Comparison");
+                InstructionInfo::Comparison { kind: _, left: _, right: _ } => {
+                    self.append_comment("Comparison");
 
-                    let size = self.append_value_to_reg(Register::A, left, 0);
-                    self.append_value_to_reg(Register::B, right, 0);
+                    let code = self.append_compare(*instr).expect("integer comparisons have a condition code");
+                    self.append_instr(&format!("set{}", code), [CL]);
+
+                    self.append_instr("mov", [MemRegOffset::stack(instr_pos).into(), CL]);
+                }
+                InstructionInfo::FloatArithmetic { kind, left, right } => {
+                    self.append_comment("FloatArithmetic");
+
+                    let ty = self.prog.type_of_value(*left);
+                    let suffix = float_suffix(self.prog.get_type(ty).unwrap_float().unwrap());
 
-                    self.append_instr("xor ecx, ecx");
-                    self.append_instr(&format!("cmp {}, {}", Register::A.with_size(size), Register::B.with_size(size)));
+                    self.append_value_to_xmm(0, left, 0);
+                    self.append_value_to_xmm(1, right, 0);
+
+                    let op = match kind {
+                        ArithmeticOp::Add => "add",
+                        ArithmeticOp::Sub => "sub",
+                        ArithmeticOp::Mul => "mul",
+                        ArithmeticOp::Div(_) => "div",
+                        ArithmeticOp::Mod(_) => panic!("float modulo is not supported"),
+                    };
+
+                    self.append_instr(&format!("{}s{}", op, suffix), [Operand::Xmm(0), Operand::Xmm(1)]);
+                    self.append_instr(&format!("movs{}", suffix), [MemRegOffset::stack(instr_pos).into(), Operand::Xmm(0)]);
+                }
+                InstructionInfo::FloatComparison { kind, left: _, right: _ } => {
+                    self.append_comment("FloatComparison");
+
+                    match self.append_compare(*instr) {
+                        Some(code) => self.append_instr(&format!("set{}", code), [CL]),
+                        //equality has to check the parity flag as well, which is set for unordered operands
+                        None => match kind {
+                            LogicalOp::Eq => {
+                                self.append_instr("sete", [CL]);
+                                self.append_instr("setnp", [DL]);
+                                self.append_instr("and", [CL, DL]);
+                            }
+                            LogicalOp::Neq => {
+                                self.append_instr("setne", [CL]);
+                                self.append_instr("setp", [DL]);
+                                self.append_instr("or", [CL, DL]);
+                            }
+                            _ => unreachable!(),
+                        },
+                    }
+
+                    self.append_instr("mov", [MemRegOffset::stack(instr_pos).into(), CL]);
+                }
+                InstructionInfo::TupleFieldPtr { base: _, index: _, tuple_ty: _ } => {
+                    self.append_comment("TupleFieldPtr");
+                    let mem = self.append_address_instr(*instr);
+                    self.append_instr("lea", [EAX, mem.into()]);
+                    self.append_instr("mov", [MemRegOffset::stack(instr_pos).into(), EAX]);
+                }
+                InstructionInfo::PointerOffSet { base: _, index: _, ty: _ } => {
+                    self.append_comment("PointerOffSet");
+                    let mem = self.append_address_instr(*instr);
+                    self.append_instr("lea", [EAX, mem.into()]);
+                    self.append_instr("mov", [MemRegOffset::stack(instr_pos).into(), EAX]);
+                }
+                InstructionInfo::Cast { kind, ty, value } => {
+                    let result_size = RegisterSize::for_size(Layout::for_type(self.prog, *ty).size)
+                        .unwrap().expect("cast result can't be zero-sized");
+
+                    self.append_comment("Cast");
+                    let size = self.append_value_to_reg(Register::A, value, 0);
+                    let source = Register::A.operand(size);
+                    let extended = Register::A.operand(result_size);
 
                     match kind {
-                        LogicalOp::Eq => self.append_instr("sete cl"),
-                        LogicalOp::Neq => self.append_instr("setne cl"),
-                        LogicalOp::Gte => self.append_instr("setae cl"),
-                        LogicalOp::Gt => self.append_instr("seta cl"),
-                        LogicalOp::Lte => self.append_instr("setbe cl"),
-                        LogicalOp::Lt => self.append_instr("setb cl"),
+                        //pointers and ints have the same size, truncating just keeps the lower part of the register
+                        // and append_value_to_reg already cleared the upper bits
+                        CastKind::PointerToInt | CastKind::IntToPointer | CastKind::Truncate | CastKind::ZeroExtend => {}
+                        CastKind::SignExtend => self.append_instr("movsx", [extended.clone(), source]),
                     }
 
-                    self.append_instr(&format!("mov [esp+{}], cl", instr_pos));
+                    self.append_instr("mov", [MemRegOffset::stack(instr_pos).into(), extended]);
                 }
-                InstructionInfo::TupleFieldPtr { base, index, tuple_ty } => {
-                    let tuple_ty = self.prog.get_type(*tuple_ty).unwrap_tuple()
-                        .expect("TupleFieldPtr target should have tuple pointer type");
-                    let layout = TupleLayout::for_types(self.prog, tuple_ty.fields.iter().copied());
-                    let field_offset = layout.offsets[*index as usize];
+                InstructionInfo::Intrinsic { kind, args } => {
+                    self.append_comment(&format!("Intrinsic {}", kind));
 
-                    self.append_instr(";TupleFieldPtr");
-                    self.append_value_to_reg(Register::A, base, 0);
-                    self.append_instr(&format!("add eax, {}", field_offset));
-                    self.append_instr(&format!("mov [esp+{}], eax", instr_pos));
+                    if let IntrinsicKind::Trap = kind {
+                        self.append_instr("ud2", []);
+                    } else {
+                        self.append_unary_intrinsic(*kind, &args[0], instr_pos);
+                    }
                 }
-                InstructionInfo::PointerOffSet { base, index, ty } => {
-                    let ty_layout = Layout::for_type(self.prog, *ty);
+                InstructionInfo::MemCopy { dest, source, ty } => {
+                    self.append_comment("MemCopy");
+                    let size = Layout::for_type(self.prog, *ty).size;
+
+                    self.append_value_to_reg(Register::C, source, 0);
+                    self.append_value_to_reg(Register::D, dest, 0);
+                    if size <= MEM_UNROLL_LIMIT {
+                        self.append_mem_copy(Register::D.mem(), Register::C.mem(), size);
+                    } else {
+                        //esi and edi are preserved for the caller
+                        self.append_instr("push", [ESI]);
+                        self.append_instr("push", [EDI]);
+                        self.append_instr("mov", [ESI, ECX]);
+                        self.append_instr("mov", [EDI, EDX]);
+                        self.append_instr("mov", [ECX, size.into()]);
+                        self.append_instr("rep movsb", []);
+                        self.append_instr("pop", [EDI]);
+                        self.append_instr("pop", [ESI]);
+                    }
+                }
+                InstructionInfo::MemSet { dest, value, ty } => {
+                    self.append_comment("MemSet");
+                    let size = Layout::for_type(self.prog, *ty).size;
 
-                    self.append_instr(";ArrayIndexPtr");
-                    self.append_value_to_reg(Register::A, index, 0);
-                    self.append_instr(&format!("imul eax, {}", ty_layout.size));
+                    //repeat the byte in all four bytes of eax
+                    self.append_value_to_reg(Register::A, value, 0);
+                    self.append_instr("imul", [EAX, EAX, 0x01010101.into()]);
+                    self.append_value_to_reg(Register::D, dest, 0);
+                    if size <= MEM_UNROLL_LIMIT {
+                        self.append_mem_fill(Register::D.mem(), size);
+                    } else {
+                        self.append_instr("push", [EDI]);
+                        self.append_instr("mov", [EDI, EDX]);
+                        self.append_instr("mov", [ECX, size.into()]);
+                        self.append_instr("rep stosb", []);
+                        self.append_instr("pop", [EDI]);
+                    }
+                }
+                InstructionInfo::Select { cond, true_value, false_value } => {
+                    self.append_comment("Select");
+
+                    let layout = Layout::for_type(self.prog, self.prog.type_of_value(*true_value));
+                    match RegisterSize::for_size(layout.size) {
+                        Ok(Some(size)) => {
+                            //the registers are zero-extended, so cmov can always work on the full registers
+                            self.append_value_to_reg(Register::A, true_value, 0);
+                            self.append_value_to_reg(Register::B, false_value, 0);
+                            let false_code = self.append_test_condition(cond, Register::C).1;
+                            self.append_instr(&format!("cmov{}", false_code), [EAX, EBX]);
+                            self.append_instr("mov", [MemRegOffset::stack(instr_pos).into(), Register::A.operand(size)]);
+                        }
+                        Ok(None) => {}
+                        Err(()) => {
+                            //too large for a register, copy the true value and overwrite it if the condition is false
+                            let label_number = self.parent.label_number();
+
+                            self.append_value_to_mem(MemRegOffset::stack(instr_pos), true_value, 0);
+                            let true_code = self.append_test_condition(cond, Register::C).0;
+                            self.append_instr(&format!("j{}", true_code), [label_operand(label_number)]);
+                            self.append_value_to_mem(MemRegOffset::stack(instr_pos), false_value, 0);
+                            self.append_label(format!("label_{}", label_number));
+                        }
+                    }
+                }
+                InstructionInfo::InlineAsm { template, inputs, outputs } => {
+                    let register = |constraint: &str| Register::from_name(constraint)
+                        .unwrap_or_else(|| panic!("Unknown asm register '{}'", constraint));
+
+                    self.append_comment("InlineAsm");
+                    for input in inputs {
+                        self.append_value_to_reg(register(&input.constraint), &input.value, 0);
+                    }
+
+                    for line in template.lines() {
+                        self.append_verbatim(line.trim());
+                    }
+
+                    //storing an output needs registers that may hold other outputs, so save them all on the stack first
+                    for output in outputs {
+                        self.append_instr("push", [register(&output.constraint).operand(RegisterSize::S32)]);
+                    }
+                    for (i, output) in outputs.iter().enumerate().rev() {
+                        let size = RegisterSize::for_size(Layout::for_type(self.prog, output.ty).size)
+                            .unwrap().expect("asm output can't be zero-sized");
 
-                    self.append_value_to_reg(Register::B, base, 0);
-                    self.append_instr("add eax, ebx");
-                    self.append_instr(&format!("mov [esp+{}], eax", instr_pos));
+                        self.append_instr("pop", [EAX]);
+                        self.append_value_to_reg(Register::C, &output.value, 4 * i as i32);
+                        self.append_instr("mov", [Register::C.mem().into(), Register::A.operand(size)]);
+                    }
                 }
             }
         }
 
-        self.append_instr(";Terminator");
+        self.append_comment("Terminator");
         match &block.terminator {
             Terminator::Jump { target } => {
-                self.append_jump_to_target(target);
+                self.append_final_jump_to_target(target);
             }
             Terminator::Branch { cond, true_target, false_target } => {
-                let label_number = self.parent.label_number();
+                self.append_comment("  cond");
+                let (true_code, false_code) = self.append_test_condition(cond, Register::A);
+
+                //the target emitted last can fall trough into the next block, so the targets are swapped if the true
+                //  target comes next
+                let swap = self.next_block == Some(true_target.block);
+                let (first, second) = if swap { (false_target, true_target) } else { (true_target, false_target) };
+                let (first_name, second_name) = if swap { ("false", "true") } else { ("true", "false") };
+                let (code_first, code_second) = if swap { (false_code, true_code) } else { (true_code, false_code) };
+
+                if first.phi_values.is_empty() {
+                    //there are no phi values to copy, so jump straight to the block
+                    let block_number = self.parent.block_number(first.block);
+                    self.append_instr(&format!("j{}", code_first), [Operand::Symbol(format!("block_{}", block_number))]);
+                } else {
+                    let label_number = self.parent.label_number();
+                    self.append_instr(&format!("j{}", code_second), [label_operand(label_number)]);
+                    self.append_comment(&format!("  {}", first_name));
+                    self.append_jump_to_target(first);
+                    self.append_label(format!("label_{}", label_number));
+                }
 
-                self.append_instr(";  cond");
-                self.append_value_to_reg(Register::A, cond, 0);
-                self.append_instr("test al, al");
-                self.append_instr(&format!("jz label_{}", label_number));
+                self.append_comment(&format!("  {}", second_name));
+                self.append_final_jump_to_target(second);
+            }
+            Terminator::Switch { value, cases, default } => {
+                //first jump to a label per case, the jumps to the actual targets come after that since
+                //  they can clobber registers
+                let case_labels = cases.iter().map(|_| self.parent.label_number()).collect_vec();
+                let default_label = self.parent.label_number();
+
+                //append_value_to_reg zero-extends, so the value can be compared as a full register
+                self.append_value_to_reg(Register::A, value, 0);
+
+                let mut sorted = cases.iter().map(|case| case.value).zip(case_labels.iter().copied()).collect_vec();
+                sorted.sort_by_key(|&(value, _)| value);
+                sorted.dedup_by_key(|&mut (value, _)| value);
+
+                if is_dense_switch(&sorted) {
+                    self.append_jump_table(&sorted, default_label);
+                } else {
+                    self.append_compare_tree(&sorted, default_label);
+                }
 
-                self.append_instr(";  true");
-                self.append_jump_to_target(true_target);
+                for (case, label) in zip_eq(cases, case_labels) {
+                    self.append_label(format!("label_{}", label));
+                    self.append_comment(&format!("  case {}", case.value));
+                    self.append_jump_to_target(&case.target);
+                }
 
-                self.append_instr(";  false");
-                self.append_ln(&format!("  label_{}:", label_number));
-                self.append_jump_to_target(false_target);
+                self.append_label(format!("label_{}", default_label));
+                self.append_comment("  default");
+                self.append_final_jump_to_target(default);
             }
             Terminator::Return { value } => {
-                let local_stack_size = self.local_stack_size;
-                let param_size = self.param_size;
+                let func_ty = &self.prog.get_func(self.func).func_ty;
 
-                if Layout::for_type(&self.prog, self.prog.type_of_value(*value)).size != 0 {
-                    self.append_value_to_reg(Register::A, value, 0);
+                if self.return_in_memory {
+                    //copy the value to the hidden pointer and return that pointer
+                    self.append_instr("mov", [EBX, MemRegOffset::param(-4).into()]);
+                    self.append_value_to_mem(Register::B.mem(), value, 0);
+                    self.append_instr("mov", [EAX, EBX]);
+                } else if let Some(bits) = returns_on_x87(self.prog, func_ty) {
+                    match value {
+                        //something has to be pushed to keep the x87 stack balanced
+                        Value::Undef(_) => self.append_instr("fldz", []),
+                        _ => {
+                            let mem = self.stack_value_mem(value, 0);
+                            self.append_instr("fld", [Operand::from(mem).sized(x87_size(bits))]);
+                        }
+                    }
+                } else {
+                    //values of 8 bytes are returned in edx:eax, smaller ones in eax
+                    match Layout::for_type(self.prog, self.prog.type_of_value(*value)).size {
+                        0 => {}
+                        8 => {
+                            match value {
+                                Value::Undef(_) => {}
+                                _ => {
+                                    let mem = self.stack_value_mem(value, 0);
+                                    self.append_instr("mov", [EAX, mem.into()]);
+                                    self.append_instr("mov", [EDX, (mem + 4).into()]);
+                                }
+                            }
+                        }
+                        _ => { self.append_value_to_reg(Register::A, value, 0); }
+                    }
                 }
 
-                if local_stack_size != 0 {
-                    self.append_instr(&format!("add esp, {}", local_stack_size));
-                }
-                self.append_instr(&format!("ret {}", param_size));
+                //the epilogue depends on the registers that have to be restored, so it's filled in at the end
+                self.lines.push(Line::Epilogue);
             }
             Terminator::Unreachable => {
-                self.append_instr("hlt");
+                self.append_instr("hlt", []);
             }
         }
     }
 }
 
+/// The registers functions have to preserve, see `AsmFuncBuilder::saved_registers`.
+const CALLEE_SAVED_REGISTERS: [Register; 4] = [Register::B, Register::SI, Register::DI, Register::BP];
+
+/// The alignment of `esp` at every call instruction. System V needs more than the stack alignment, and because every
+/// frame on the way to such a call has to keep that alignment, the whole program is aligned as soon as it calls a
+/// System V function anywhere.
+fn call_alignment(prog: &Program) -> i32 {
+    let calls_sysv = prog.nodes.instrs.iter().any(|(_, instr_info)| match instr_info {
+        InstructionInfo::Call { target, args: _ } => {
+            let target_ty = prog.get_type(prog.type_of_value(*target));
+            target_ty.unwrap_func().is_some_and(|func_ty| func_ty.conv == CallingConvention::SysV)
+        }
+        _ => false,
+    });
+
+    if calls_sysv { SYSV_CALL_ALIGNMENT } else { STACK_ALIGNMENT }
+}
+
+/// The layout of the parameters of a function on the stack, starting right after the return address and the hidden
+/// return pointer.
+fn param_layout(prog: &Program, conv: CallingConvention, params: impl IntoIterator<Item=Type>) -> TupleLayout {
+    TupleLayout::from_layouts(params.into_iter().map(|ty| {
+        let layout = Layout::for_type(prog, ty);
+        match conv {
+            CallingConvention::Stdcall | CallingConvention::Cdecl => layout,
+            //every argument gets its own stack slot
+            CallingConvention::SysV => Layout::new(next_multiple(layout.size, STACK_ALIGNMENT), STACK_ALIGNMENT),
+        }
+    }))
+}
+
+/// The number of argument bytes a function of type `func_ty` pops off the stack when returning.
+fn callee_pop_size(prog: &Program, func_ty: &FunctionType, param_layout: &TupleLayout) -> i32 {
+    match func_ty.conv {
+        CallingConvention::Stdcall => {
+            let return_ptr_size = if returns_in_memory(prog, func_ty) { 4 } else { 0 };
+            return_ptr_size + next_multiple(param_layout.layout.size, STACK_ALIGNMENT)
+        }
+        CallingConvention::Cdecl => 0,
+        //only the hidden return pointer
+        CallingConvention::SysV => if returns_in_memory(prog, func_ty) { 4 } else { 0 },
+    }
+}
+
+/// Whether the return value is stored at a pointer passed by the caller instead of being returned in registers.
+/// The pointer is passed as a hidden first argument and returned again in `eax`.
+///
+/// Stdcall and cdecl follow MSVC: aggregates of 1, 2, 4 or 8 bytes are returned in `eax` or `edx:eax` like integers,
+/// all other non-empty aggregates trough the hidden pointer.
+fn returns_in_memory(prog: &Program, func_ty: &FunctionType) -> bool {
+    let is_aggregate = matches!(prog.get_type(func_ty.ret), TypeInfo::Tuple(_) | TypeInfo::Array(_) | TypeInfo::Union(_));
+    let size = Layout::for_type(prog, func_ty.ret).size;
+
+    match func_ty.conv {
+        CallingConvention::Stdcall | CallingConvention::Cdecl => is_aggregate && !matches!(size, 0 | 1 | 2 | 4 | 8),
+        CallingConvention::SysV => is_aggregate && size != 0,
+    }
+}
+
+/// The size in bits of the float returned in `st0`, if the return value is returned that way. All i386 calling
+/// conventions return floats on the x87 stack, both for MSVC and System V.
+fn returns_on_x87(prog: &Program, func_ty: &FunctionType) -> Option<u32> {
+    prog.get_type(func_ty.ret).unwrap_float()
+}
+
+fn x87_size(bits: u32) -> OperandSize {
+    match bits {
+        32 => OperandSize::Dword,
+        64 => OperandSize::Qword,
+        _ => panic!("float with {} bits not supported", bits),
+    }
+}
+
+/// Whether a switch with the given sorted cases should use a jump table instead of a tree of comparisons.
+pub(super) fn is_dense_switch(cases: &[(i32, usize)]) -> bool {
+    match (cases.first(), cases.last()) {
+        (Some(&(min, _)), Some(&(max, _))) => {
+            let range = max as i64 - min as i64 + 1;
+            cases.len() >= SWITCH_TABLE_MIN_CASES && range <= 2 * cases.len() as i64
+        }
+        _ => false,
+    }
+}
+
+/// The condition code for `setcc` and `jcc` after `cmp left, right` for an integer comparison.
+fn condition_code(kind: LogicalOp) -> &'static str {
+    use Signedness::*;
+
+    match kind {
+        LogicalOp::Eq => "e",
+        LogicalOp::Neq => "ne",
+        LogicalOp::Gt(Signed) => "g",
+        LogicalOp::Gte(Signed) => "ge",
+        LogicalOp::Lt(Signed) => "l",
+        LogicalOp::Lte(Signed) => "le",
+        LogicalOp::Gt(Unsigned) => "a",
+        LogicalOp::Gte(Unsigned) => "ae",
+        LogicalOp::Lt(Unsigned) => "b",
+        LogicalOp::Lte(Unsigned) => "be",
+    }
+}
+
+/// The condition code that is true if the `Comparison` or `FloatComparison` `instr` is, after `append_compare`.
+/// Float equality depends on two flags, so it doesn't have one.
+fn comparison_condition_code(instr: &InstructionInfo) -> Option<&'static str> {
+    match instr {
+        InstructionInfo::Comparison { kind, left: _, right: _ } => Some(condition_code(*kind)),
+        InstructionInfo::FloatComparison { kind, left: _, right: _ } => match kind {
+            LogicalOp::Eq | LogicalOp::Neq => None,
+            LogicalOp::Gt(_) | LogicalOp::Lt(_) => Some("a"),
+            LogicalOp::Gte(_) | LogicalOp::Lte(_) => Some("ae"),
+        },
+        _ => None,
+    }
+}
+
+fn negate_condition_code(code: &str) -> &'static str {
+    match code {
+        "e" => "ne",
+        "ne" => "e",
+        "z" => "nz",
+        "nz" => "z",
+        "g" => "le",
+        "le" => "g",
+        "ge" => "l",
+        "l" => "ge",
+        "a" => "be",
+        "be" => "a",
+        "ae" => "b",
+        "b" => "ae",
+        _ => panic!("unknown condition code '{}'", code),
+    }
+}
+
+/// Whether the instruction `mnemonic` leaves the flags alone. Unknown instructions are assumed to change them.
+fn preserves_flags(mnemonic: &str) -> bool {
+    mnemonic.starts_with("mov") || mnemonic.starts_with("cmov") || mnemonic.starts_with("set")
+        || mnemonic.starts_with('j') || matches!(mnemonic, "lea" | "push" | "pop" | "fld" | "fstp")
+}
+
+/// The sse instruction suffix for a float with the given number of bits.
+fn float_suffix(bits: u32) -> char {
+    match bits {
+        32 => 's',
+        64 => 'd',
+        _ => panic!("float with {} bits not supported", bits),
+    }
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 enum RegisterSize {
     S8,
@@ -646,7 +1689,7 @@ impl RegisterSize {
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 enum Register {
     A,
     C,
@@ -660,7 +1703,27 @@ enum Register {
 
 impl Register {
     fn mem(self) -> MemRegOffset {
-        MemRegOffset { reg: self, offset: 0 }
+        MemRegOffset { reg: self, index: None, offset: 0, param: false }
+    }
+
+    /// Find the register with the given 32-bit name, eg. `eax`.
+    fn from_name(name: &str) -> Option<Register> {
+        use Register::*;
+
+        [A, C, D, B, SP, BP, SI, DI].iter().copied()
+            .find(|reg| reg.with_size(RegisterSize::S32) == name)
+    }
+
+    /// Find the register with the given name of any size, eg. `al`, `ax` or `eax`.
+    fn from_any_name(name: &str) -> Option<Register> {
+        use Register::*;
+
+        [A, C, D, B, SP, BP, SI, DI].iter().copied()
+            .find(|reg| [RegisterSize::S8, RegisterSize::S16, RegisterSize::S32].iter().any(|&size| reg.with_size(size) == name))
+    }
+
+    fn operand(self, size: RegisterSize) -> Operand {
+        Operand::Reg(self, size)
     }
 
     fn with_size(self, size: RegisterSize) -> &'static str {
@@ -681,15 +1744,39 @@ impl Register {
     }
 }
 
+const EAX: Operand = Operand::Reg(Register::A, RegisterSize::S32);
+const EBX: Operand = Operand::Reg(Register::B, RegisterSize::S32);
+const ECX: Operand = Operand::Reg(Register::C, RegisterSize::S32);
+const EDX: Operand = Operand::Reg(Register::D, RegisterSize::S32);
+const ESI: Operand = Operand::Reg(Register::SI, RegisterSize::S32);
+const EDI: Operand = Operand::Reg(Register::DI, RegisterSize::S32);
+const ESP: Operand = Operand::Reg(Register::SP, RegisterSize::S32);
+const EBP: Operand = Operand::Reg(Register::BP, RegisterSize::S32);
+const AX: Operand = Operand::Reg(Register::A, RegisterSize::S16);
+const BX: Operand = Operand::Reg(Register::B, RegisterSize::S16);
+const AL: Operand = Operand::Reg(Register::A, RegisterSize::S8);
+const BL: Operand = Operand::Reg(Register::B, RegisterSize::S8);
+const CL: Operand = Operand::Reg(Register::C, RegisterSize::S8);
+const DL: Operand = Operand::Reg(Register::D, RegisterSize::S8);
+
+/// The memory operand `[reg + index*scale + offset]`, the index is optional.
 #[derive(Debug, Copy, Clone)]
 struct MemRegOffset {
     reg: Register,
+    index: Option<(Register, i32)>,
     offset: i32,
+    /// Whether `offset` is relative to the first parameter instead of `reg`, it's only resolved once the frame layout
+    /// is known, see `Operand::resolve_param`.
+    param: bool,
 }
 
 impl MemRegOffset {
     fn stack(offset: i32) -> Self {
-        MemRegOffset { reg: Register::SP, offset }
+        MemRegOffset { reg: Register::SP, index: None, offset, param: false }
+    }
+
+    fn param(offset: i32) -> Self {
+        MemRegOffset { reg: Register::SP, index: None, offset, param: true }
     }
 }
 
@@ -697,7 +1784,7 @@ impl std::ops::Add<i32> for MemRegOffset {
     type Output = MemRegOffset;
 
     fn add(self, rhs: i32) -> Self::Output {
-        MemRegOffset { reg: self.reg, offset: self.offset + rhs }
+        MemRegOffset { offset: self.offset.wrapping_add(rhs), ..self }
     }
 }
 
@@ -705,17 +1792,813 @@ impl std::ops::Sub<i32> for MemRegOffset {
     type Output = MemRegOffset;
 
     fn sub(self, rhs: i32) -> Self::Output {
-        MemRegOffset { reg: self.reg, offset: self.offset - rhs }
+        MemRegOffset { offset: self.offset.wrapping_sub(rhs), ..self }
     }
 }
 
-impl std::fmt::Display for MemRegOffset {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let reg = self.reg.with_size(RegisterSize::S32);
+impl MemRegOffset {
+    /// `[reg+index*scale+offset]`
+    fn nasm(&self) -> String {
+        assert!(!self.param, "parameter offsets have to be resolved before printing");
+
+        let mut result = format!("[{}", self.reg.with_size(RegisterSize::S32));
+        if let Some((index, scale)) = self.index {
+            result.push_str(&format!("+{}*{}", index.with_size(RegisterSize::S32), scale));
+        }
         match self.offset {
-            off if off > 0 => write!(f, "[{}+{}]", reg, off),
-            off if off < 0 => write!(f, "[{}-{}]", reg, -off),
-            _ => write!(f, "[{}]", reg),
+            off if off > 0 => result.push_str(&format!("+{}]", off)),
+            off if off < 0 => result.push_str(&format!("-{}]", -(off as i64))),
+            _ => result.push(']'),
+        }
+        result
+    }
+
+    /// `offset(%reg,%index,scale)`
+    fn gas(&self) -> String {
+        assert!(!self.param, "parameter offsets have to be resolved before printing");
+
+        let offset = if self.offset == 0 { String::new() } else { self.offset.to_string() };
+        match self.index {
+            None => format!("{}(%{})", offset, self.reg.with_size(RegisterSize::S32)),
+            Some((index, scale)) => format!(
+                "{}(%{},%{},{})",
+                offset, self.reg.with_size(RegisterSize::S32), index.with_size(RegisterSize::S32), scale
+            ),
         }
     }
 }
+
+/// The size of a memory or immediate operand, for the instructions where the other operands don't determine it.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum OperandSize {
+    Byte,
+    Word,
+    Dword,
+    Qword,
+}
+
+impl OperandSize {
+    fn nasm(self) -> &'static str {
+        match self {
+            OperandSize::Byte => "byte",
+            OperandSize::Word => "word",
+            OperandSize::Dword => "dword",
+            OperandSize::Qword => "qword",
+        }
+    }
+
+    /// AT&T syntax puts the size in a suffix of the mnemonic instead.
+    fn gas_suffix(self) -> &'static str {
+        match self {
+            OperandSize::Byte => "b",
+            OperandSize::Word => "w",
+            OperandSize::Dword => "l",
+            OperandSize::Qword => "q",
+        }
+    }
+
+    /// The suffix for the memory operand of an x87 instruction, which is a float instead of an integer.
+    fn gas_x87_suffix(self) -> &'static str {
+        match self {
+            OperandSize::Dword => "s",
+            OperandSize::Qword => "l",
+            _ => panic!("no x87 instruction suffix for {:?}", self),
+        }
+    }
+}
+
+impl From<RegisterSize> for OperandSize {
+    fn from(size: RegisterSize) -> Self {
+        match size {
+            RegisterSize::S8 => OperandSize::Byte,
+            RegisterSize::S16 => OperandSize::Word,
+            RegisterSize::S32 => OperandSize::Dword,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    Reg(Register, RegisterSize),
+    /// The sse register with the given number.
+    Xmm(u8),
+    Imm(i64),
+    /// The address of a symbol, as an immediate or as the target of a direct jump.
+    Symbol(String),
+    Mem(MemRegOffset),
+    /// The entry for the value of the register in the jump table at the label, `[label + reg*4]`.
+    JumpTable(String, Register),
+    /// An operand with an explicit size.
+    Sized(OperandSize, Box<Operand>),
+}
+
+impl Operand {
+    fn sized(self, size: OperandSize) -> Operand {
+        Operand::Sized(size, Box::new(self))
+    }
+
+    fn size(&self) -> Option<OperandSize> {
+        match self {
+            Operand::Reg(_, size) => Some((*size).into()),
+            Operand::Sized(size, _) => Some(*size),
+            Operand::Xmm(_) | Operand::Imm(_) | Operand::Symbol(_) | Operand::Mem(_) | Operand::JumpTable(_, _) => None,
+        }
+    }
+
+    fn is_register(&self) -> bool {
+        matches!(self, Operand::Reg(_, _) | Operand::Xmm(_))
+    }
+
+    /// The general purpose registers used by this operand.
+    fn registers(&self) -> Vec<Register> {
+        match self {
+            Operand::Reg(reg, _) => vec![*reg],
+            Operand::Mem(mem) => std::iter::once(mem.reg).chain(mem.index.map(|(index, _)| index)).collect(),
+            Operand::JumpTable(_, index) => vec![*index],
+            Operand::Sized(_, inner) => inner.registers(),
+            Operand::Xmm(_) | Operand::Imm(_) | Operand::Symbol(_) => vec![],
+        }
+    }
+
+    /// Replace a memory operand relative to the first parameter by one relative to `esp`, the first parameter is
+    /// `param_offset` bytes above it.
+    fn resolve_param(&self, param_offset: i32) -> Operand {
+        match self {
+            Operand::Mem(mem) if mem.param => Operand::Mem(MemRegOffset { offset: param_offset + mem.offset, param: false, ..*mem }),
+            Operand::Sized(size, inner) => inner.resolve_param(param_offset).sized(*size),
+            _ => self.clone(),
+        }
+    }
+
+    fn nasm(&self) -> String {
+        match self {
+            Operand::Reg(reg, size) => reg.with_size(*size).to_owned(),
+            Operand::Xmm(index) => format!("xmm{}", index),
+            Operand::Imm(value) => value.to_string(),
+            Operand::Symbol(name) => name.clone(),
+            Operand::Mem(mem) => mem.nasm(),
+            Operand::JumpTable(label, index) => format!("[{} + {}*4]", label, index.with_size(RegisterSize::S32)),
+            Operand::Sized(size, inner) => format!("{} {}", size.nasm(), inner.nasm()),
+        }
+    }
+
+    fn gas(&self) -> String {
+        match self {
+            Operand::Reg(reg, size) => format!("%{}", reg.with_size(*size)),
+            Operand::Xmm(index) => format!("%xmm{}", index),
+            Operand::Imm(value) => format!("${}", value),
+            Operand::Symbol(name) => format!("${}", Symbol(name)),
+            Operand::Mem(mem) => mem.gas(),
+            Operand::JumpTable(label, index) => format!("{}(,%{},4)", Symbol(label), index.with_size(RegisterSize::S32)),
+            Operand::Sized(_, inner) => inner.gas(),
+        }
+    }
+
+    /// The operand of a jump or call in AT&T syntax, where indirect targets are marked with `*`.
+    fn gas_jump_target(&self) -> String {
+        match self {
+            Operand::Symbol(name) => Symbol(name).to_string(),
+            Operand::Sized(_, inner) => inner.gas_jump_target(),
+            _ => format!("*{}", self.gas()),
+        }
+    }
+}
+
+impl From<MemRegOffset> for Operand {
+    fn from(mem: MemRegOffset) -> Self {
+        Operand::Mem(mem)
+    }
+}
+
+impl From<i32> for Operand {
+    fn from(value: i32) -> Self {
+        Operand::Imm(value.into())
+    }
+}
+
+fn label_operand(label_number: usize) -> Operand {
+    Operand::Symbol(format!("label_{}", label_number))
+}
+
+#[derive(Debug, Clone)]
+struct Instr {
+    mnemonic: String,
+    /// In Intel order, the destination comes first.
+    operands: Vec<Operand>,
+}
+
+impl Instr {
+    fn new(mnemonic: &str, operands: impl IntoIterator<Item=Operand>) -> Self {
+        Instr { mnemonic: mnemonic.to_owned(), operands: operands.into_iter().collect() }
+    }
+
+    fn nasm(&self) -> String {
+        if self.operands.is_empty() {
+            return self.mnemonic.clone();
+        }
+        format!("{} {}", self.mnemonic, self.operands.iter().map(Operand::nasm).join(", "))
+    }
+
+    fn gas(&self) -> String {
+        let is_jump = self.mnemonic == "call" || self.mnemonic.starts_with('j');
+
+        let mnemonic = match (self.mnemonic.as_str(), self.operands.as_slice()) {
+            //the sizes of both operands are part of the mnemonic
+            (kind @ ("movzx" | "movsx"), [dest, source]) => {
+                let size = |operand: &Operand| operand.size().expect("extended operands need a size").gas_suffix();
+                format!("mov{}{}{}", &kind[3..4], size(source), size(dest))
+            }
+            //without a register operand the size has to be part of the mnemonic
+            (mnemonic, operands) if !is_jump && !operands.iter().any(Operand::is_register) => {
+                match operands.iter().find_map(Operand::size) {
+                    Some(size) if mnemonic.starts_with('f') => format!("{}{}", mnemonic, size.gas_x87_suffix()),
+                    Some(size) => format!("{}{}", mnemonic, size.gas_suffix()),
+                    None => mnemonic.to_owned(),
+                }
+            }
+            (mnemonic, _) => mnemonic.to_owned(),
+        };
+
+        if self.operands.is_empty() {
+            return mnemonic;
+        }
+
+        //AT&T puts the destination last
+        let operands = self.operands.iter().rev()
+            .map(|operand| if is_jump { operand.gas_jump_target() } else { operand.gas() })
+            .join(", ");
+        format!("{} {}", mnemonic, operands)
+    }
+}
+
+/// A line in the body of a function, see `AsmFuncBuilder::print_lines`.
+#[derive(Debug)]
+enum Line {
+    Instr(Instr),
+    /// A label that is jumped to from elsewhere in the function.
+    Label(String),
+    Comment(String),
+    /// The comment for an undefined value that isn't copied into `target`.
+    Undef { target: Operand, value: String },
+    /// Padding up to a multiple of the given alignment.
+    Align(i32),
+    /// An entry of a jump table, the address of the label.
+    Address(String),
+    /// Inline assembly, which is already in the output syntax.
+    Verbatim(String),
+    /// Stands in for the epilogue, which depends on the registers that have to be restored.
+    Epilogue,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Section {
+    Text,
+    Data,
+    ReadOnly,
+    Bss,
+}
+
+/// Prints instructions and directives in the output syntax, with the section names of the object format.
+#[derive(Debug, Copy, Clone)]
+struct Printer {
+    syntax: AsmSyntax,
+    format: ObjectFormat,
+}
+
+impl Printer {
+    fn instr(self, instr: &Instr) -> String {
+        match self.syntax {
+            AsmSyntax::Nasm => instr.nasm(),
+            AsmSyntax::Gas => instr.gas(),
+        }
+    }
+
+    fn operand(self, operand: &Operand) -> String {
+        match self.syntax {
+            AsmSyntax::Nasm => operand.nasm(),
+            AsmSyntax::Gas => operand.gas(),
+        }
+    }
+
+    fn label(self, name: &str) -> String {
+        match self.syntax {
+            AsmSyntax::Nasm => format!("{}:", name),
+            AsmSyntax::Gas => format!("{}:", Symbol(name)),
+        }
+    }
+
+    fn comment(self, comment: &str) -> String {
+        match self.syntax {
+            AsmSyntax::Nasm => format!(";{}", comment),
+            AsmSyntax::Gas => format!("#{}", comment),
+        }
+    }
+
+    fn global(self, name: &str) -> String {
+        match self.syntax {
+            AsmSyntax::Nasm => format!("global {}", name),
+            AsmSyntax::Gas => format!(".globl {}", Symbol(name)),
+        }
+    }
+
+    fn external(self, name: &str) -> String {
+        match self.syntax {
+            AsmSyntax::Nasm => format!("extern {}", name),
+            AsmSyntax::Gas => format!(".extern {}", Symbol(name)),
+        }
+    }
+
+    fn section(self, section: Section) -> String {
+        let name = match (section, self.format) {
+            (Section::Text, _) => ".text",
+            (Section::Data, _) => ".data",
+            (Section::ReadOnly, ObjectFormat::Coff) => ".rdata",
+            (Section::ReadOnly, ObjectFormat::Elf) => ".rodata",
+            (Section::Bss, _) => ".bss",
+        };
+
+        match (self.syntax, section, self.format) {
+            (AsmSyntax::Nasm, _, _) => format!("section {}", name),
+            //read-only data is not a section GAS knows by default on COFF, so it needs its flags
+            (AsmSyntax::Gas, Section::ReadOnly, ObjectFormat::Coff) => format!(".section {}, \"dr\"", name),
+            (AsmSyntax::Gas, _, _) => format!(".section {}", name),
+        }
+    }
+
+    fn align(self, alignment: i32) -> String {
+        match self.syntax {
+            AsmSyntax::Nasm => format!("align {}", alignment),
+            AsmSyntax::Gas => format!(".balign {}", alignment),
+        }
+    }
+
+    /// Alignment in a section that only reserves space, like `.bss`.
+    fn align_reserved(self, alignment: i32) -> String {
+        match self.syntax {
+            AsmSyntax::Nasm => format!("alignb {}", alignment),
+            AsmSyntax::Gas => format!(".balign {}", alignment),
+        }
+    }
+
+    fn bytes(self, bytes: &[u8]) -> String {
+        match self.syntax {
+            AsmSyntax::Nasm => format!("db {}", bytes.iter().join(", ")),
+            AsmSyntax::Gas => format!(".byte {}", bytes.iter().join(", ")),
+        }
+    }
+
+    fn zeros(self, count: i32) -> String {
+        match self.syntax {
+            AsmSyntax::Nasm => format!("times {} db 0", count),
+            AsmSyntax::Gas => format!(".zero {}", count),
+        }
+    }
+
+    /// Reserve space in a section without contents, like `.bss`.
+    fn reserve(self, count: i32) -> String {
+        match self.syntax {
+            AsmSyntax::Nasm => format!("resb {}", count),
+            AsmSyntax::Gas => format!(".skip {}", count),
+        }
+    }
+
+    /// The 32-bit address of a label.
+    fn address(self, label: &str) -> String {
+        match self.syntax {
+            AsmSyntax::Nasm => format!("dd {}", label),
+            AsmSyntax::Gas => format!(".long {}", Symbol(label)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::Command;
+
+    use crate::back::BackendSettings;
+    use crate::back::gas::{AsmSyntax, ObjectFormat};
+    use crate::back::layout::Layout;
+    use crate::back::x86_asm::{callee_pop_size, is_dense_switch, lower, param_layout, preserves_flags, returns_in_memory};
+    use crate::mid::builder::IrBuilder;
+    use crate::mid::ir::{ArithmeticOp, CallingConvention, Const, FunctionType, GlobalInfo, Program, SwitchCase, Target, Terminator, TupleType, Value};
+    use crate::mid::opt::snapshot::{lower_error, lower_source};
+
+    /// The lines of the function with the given label, without the label itself.
+    fn func_lines<'a>(asm: &'a str, label: &str) -> Vec<&'a str> {
+        let label = format!("{}:", label);
+        asm.lines()
+            .skip_while(|line| !line.starts_with(&label))
+            .skip(1)
+            .take_while(|line| !line.starts_with("func_"))
+            .map(str::trim)
+            .collect()
+    }
+
+    /// The lines of the function with the given debug name, see `func_lines`.
+    fn named_func_lines<'a>(asm: &'a str, name: &str) -> Vec<&'a str> {
+        let line = asm.lines().find(|line| line.contains(&format!("; {}:", name))).unwrap_or_else(|| panic!("{}", asm));
+        func_lines(asm, line.split(':').next().unwrap())
+    }
+
+    #[test]
+    fn floats_are_returned_on_x87() {
+        for conv in ["stdcall", "cdecl", "sysv"] {
+            let src = format!("
+                extern \"{conv}\" fun sqrt(x: f64) -> f64;
+                extern \"{conv}\" fun half(x: f32) -> f32 {{ return x / 2.0; }}
+                fun main() -> int {{
+                    let root = sqrt(4.0);
+                    let half = half(3.0);
+                    return (root > 1.0) as int + (half > 1.0) as int;
+                }}
+            ", conv = conv);
+            let asm = lower(&lower_source(&src), &BackendSettings::default(), AsmSyntax::Nasm, ObjectFormat::Coff);
+
+            //the caller pops the results of both calls off the x87 stack, right after popping the arguments
+            let main = func_lines(&asm, "func_0");
+            let results: Vec<&str> = main.iter().enumerate()
+                .filter(|&(_, &line)| line == "call eax")
+                .map(|(i, _)| *main[i + 1..].iter().find(|line| !line.starts_with("add esp")).unwrap())
+                .collect();
+            assert_eq!(2, results.len(), "{}: {}", conv, asm);
+            assert!(results.iter().any(|line| line.starts_with("fstp qword")), "{}: {}", conv, asm);
+            assert!(results.iter().any(|line| line.starts_with("fstp dword")), "{}: {}", conv, asm);
+
+            //and the callee pushes its result instead of moving it into eax
+            let half = func_lines(&asm, "func_2");
+            let ret = half.iter().position(|line| line.starts_with("ret")).unwrap();
+            let terminator = half.iter().rposition(|&line| line == ";Terminator").unwrap();
+            let return_sequence = &half[terminator..ret];
+            assert!(return_sequence.iter().any(|line| line.starts_with("fld dword")), "{}: {}", conv, asm);
+            assert!(!return_sequence.iter().any(|line| line.contains("eax")), "{}: {}", conv, asm);
+        }
+    }
+
+    /// A program where main switches on the value `7` with the given cases, each returning its own value.
+    fn switch_program(values: &[i32]) -> Program {
+        let mut prog = Program::default();
+        let main = prog.main;
+        let ty_int = prog.ty_int();
+        let mut builder = IrBuilder::at_entry(&mut prog, main);
+
+        let mut cases = vec![];
+        for &value in values {
+            let block = builder.new_block();
+            builder.position_at_end(block);
+            let value_const = builder.const_int(ty_int, value);
+            builder.build_return(value_const);
+            cases.push(SwitchCase { value, target: Target { block, phi_values: vec![] } });
+        }
+
+        let default = builder.new_block();
+        builder.position_at_end(default);
+        let zero = builder.const_int(ty_int, 0);
+        builder.build_return(zero);
+
+        let entry = prog.get_func(main).entry.block;
+        let value = Value::Const(Const::new(ty_int, 7));
+        prog.get_block_mut(entry).terminator = Terminator::Switch { value, cases, default: Target { block: default, phi_values: vec![] } };
+        prog
+    }
+
+    #[test]
+    fn dense_switch_uses_jump_table() {
+        let asm = lower(&switch_program(&[3, 4, 6, 7, 9]), &BackendSettings::default(), AsmSyntax::Nasm, ObjectFormat::Coff);
+        let main = func_lines(&asm, "func_0");
+
+        //one entry for each value from 3 to 9, the missing ones jump to the default block
+        assert!(main.contains(&"sub eax, 3"), "{}", asm);
+        assert!(main.contains(&"cmp eax, 6"), "{}", asm);
+        assert!(main.iter().any(|line| line.starts_with("jmp [label_")), "{}", asm);
+        assert_eq!(7, main.iter().filter(|line| line.starts_with("dd label_")).count(), "{}", asm);
+    }
+
+    #[test]
+    fn sparse_switch_uses_compare_tree() {
+        let values = [-1000, 1, 7, 100, 1000, 10000];
+        let asm = lower(&switch_program(&values), &BackendSettings::default(), AsmSyntax::Nasm, ObjectFormat::Coff);
+        let main = func_lines(&asm, "func_0");
+
+        //more cases than SWITCH_LINEAR_LIMIT, so they're split by a binary search first
+        assert!(!main.iter().any(|line| line.starts_with("jmp [label_") || line.starts_with("dd ")), "{}", asm);
+        assert!(main.iter().any(|line| line.starts_with("jg label_")), "{}", asm);
+        for value in values {
+            assert!(main.contains(&format!("cmp eax, {}", value).as_str()), "{}", asm);
+        }
+    }
+
+    #[test]
+    fn dense_switch_boundaries() {
+        let cases = |values: &[i32]| values.iter().map(|&value| (value, 0)).collect::<Vec<_>>();
+
+        //at least 4 cases, spanning at most twice as many values
+        assert!(!is_dense_switch(&cases(&[])));
+        assert!(!is_dense_switch(&cases(&[1, 2, 3])));
+        assert!(is_dense_switch(&cases(&[1, 2, 3, 4])));
+        assert!(is_dense_switch(&cases(&[1, 2, 3, 8])));
+        assert!(!is_dense_switch(&cases(&[1, 2, 3, 9])));
+        assert!(!is_dense_switch(&cases(&[i32::MIN, 0, 1, i32::MAX])));
+    }
+
+    #[test]
+    fn saves_only_registers_used_by_instructions() {
+        let src = "
+            fun add(esi: int, edi: int) -> int { return esi + edi; }
+            fun swap(a: int) -> int { let r: int; asm(\"mov esi, eax\\nmov eax, esi\", in(\"eax\") a, out(\"eax\") r); return r; }
+            fun main() -> int { return add(1, 2) + swap(3); }
+        ";
+
+        //the source comments mention esi and edi, but only the inline assembly uses them
+        let path = std::env::temp_dir().join("lllang_saved_registers.ll");
+        std::fs::write(&path, src).unwrap();
+        let mut prog = lower_source(src);
+        prog.source_files = vec![path];
+
+        let settings = BackendSettings { source_comments: true, ..BackendSettings::default() };
+        let asm = lower(&prog, &settings, AsmSyntax::Nasm, ObjectFormat::Coff);
+
+        let add = func_lines(&asm, "func_2");
+        assert!(add.iter().any(|line| line.starts_with(';') && line.contains("esi + edi")), "{}", asm);
+        assert!(!add.iter().any(|&line| line == "push esi" || line == "push edi"), "{}", asm);
+
+        let swap = func_lines(&asm, "func_3");
+        assert_eq!(Some(&"push esi"), swap.first(), "{}", asm);
+        assert_eq!(1, swap.iter().filter(|&&line| line == "pop esi").count(), "{}", asm);
+    }
+
+    #[test]
+    fn small_tuple_returns() {
+        use CallingConvention::*;
+
+        let mut prog = Program::default();
+        let ty_byte = prog.define_type_int(8);
+        let ty_int = prog.ty_int();
+        let mut tuple = |fields: Vec<_>| prog.define_type_tuple(TupleType { fields, packed: false });
+        let tuple_3 = tuple(vec![ty_byte; 3]);
+        let tuple_8 = tuple(vec![ty_int; 2]);
+        let tuple_12 = tuple(vec![ty_int; 3]);
+
+        //(return type, convention, returned in memory, bytes popped by the callee with a single int param)
+        let cases = [
+            (tuple_3, Stdcall, true, 8),
+            (tuple_3, Cdecl, true, 0),
+            (tuple_3, SysV, true, 4),
+            (tuple_8, Stdcall, false, 4),
+            (tuple_8, Cdecl, false, 0),
+            (tuple_8, SysV, true, 4),
+            (tuple_12, Stdcall, true, 8),
+            (tuple_12, Cdecl, true, 0),
+            (tuple_12, SysV, true, 4),
+        ];
+
+        for (ret, conv, in_memory, pop_size) in cases {
+            let func_ty = FunctionType { params: vec![ty_int], ret, conv };
+            let layout = param_layout(&prog, conv, func_ty.params.iter().copied());
+
+            let size = Layout::for_type(&prog, ret).size;
+            assert_eq!(in_memory, returns_in_memory(&prog, &func_ty), "{} byte tuple, {:?}", size, conv);
+            assert_eq!(pop_size, callee_pop_size(&prog, &func_ty, &layout), "{} byte tuple, {:?}", size, conv);
+        }
+    }
+
+    /// A program that uses a global of each kind: zero-initialized, initialized and read-only.
+    fn globals_program() -> Program {
+        let mut prog = Program::default();
+        let main = prog.main;
+        let ty_int = prog.ty_int();
+        let mut global = |mutable, init| Value::Global(prog.define_global(GlobalInfo { inner_ty: ty_int, mutable, init, debug_name: None }));
+        let zeroed = global(true, None);
+        let counter = global(true, Some(1i32.to_le_bytes().to_vec()));
+        let step = global(false, Some(5i32.to_le_bytes().to_vec()));
+
+        let mut builder = IrBuilder::at_entry(&mut prog, main);
+        let zeroed = builder.build_load(ty_int, zeroed);
+        let counter = builder.build_load(ty_int, counter);
+        let step = builder.build_load(ty_int, step);
+        let sum = builder.build_binary(ArithmeticOp::Add, zeroed, counter);
+        let sum = builder.build_binary(ArithmeticOp::Add, sum, step);
+        builder.build_return(sum);
+        prog
+    }
+
+    /// Assemble `asm` with the GNU assembler into a 32-bit ELF object. Returns false if the assembler isn't installed.
+    fn assemble_gas(name: &str, asm: &str) -> bool {
+        let asm_path = std::env::temp_dir().join(format!("lllang_{}.s", name));
+        let obj_path = asm_path.with_extension("o");
+        std::fs::write(&asm_path, asm).unwrap();
+
+        match Command::new("as").arg("--32").arg("-o").arg(&obj_path).arg(&asm_path).output() {
+            Ok(output) => {
+                assert!(output.status.success(), "{}\n{}", String::from_utf8_lossy(&output.stderr), asm);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    #[test]
+    fn gas_syntax() {
+        let settings = BackendSettings::default();
+
+        let asm = lower(&switch_program(&[3, 4, 6, 7, 9]), &settings, AsmSyntax::Gas, ObjectFormat::Elf);
+        let main = func_lines(&asm, "func_0");
+        assert!(main.contains(&"sub $3, %eax"), "{}", asm);
+        assert!(main.iter().any(|line| line.starts_with("jmp *label_") && line.ends_with("(,%eax,4)")), "{}", asm);
+        assert_eq!(7, main.iter().filter(|line| line.starts_with(".long label_")).count(), "{}", asm);
+
+        //stores of constants don't have a register operand to get their size from
+        let asm = lower(&globals_program(), &settings, AsmSyntax::Gas, ObjectFormat::Elf);
+        assert!(asm.contains("\n.section .rodata\n"), "{}", asm);
+        assert!(asm.contains("\n.section .bss\n"), "{}", asm);
+        assert!(!asm.contains("rdata"), "{}", asm);
+
+        let asm = lower(&globals_program(), &settings, AsmSyntax::Gas, ObjectFormat::Coff);
+        assert!(asm.contains("\n.section .rdata, \"dr\"\n"), "{}", asm);
+        let asm = lower(&globals_program(), &settings, AsmSyntax::Nasm, ObjectFormat::Coff);
+        assert!(asm.contains("\nsection .rdata\n"), "{}", asm);
+    }
+
+    #[test]
+    fn gas_output_assembles() {
+        let src = "
+            fun div(a: int, b: int) -> int { return a / b + a % 7; }
+            fun half(x: f64) -> f64 { return x / 2.0; }
+            fun swap(a: int) -> int { let r: int; asm(\"bswap %eax\", in(\"eax\") a, out(\"eax\") r); return r; }
+            fun main() -> int {
+                let f: (int, int) -> int = div;
+                let text = \"text\";
+                return f(swap(3), 3) + (half(3.0) > 1.0) as int + text.len as int;
+            }
+        ";
+        let programs = [
+            ("source", lower_source(src)),
+            ("switch", switch_program(&[3, 4, 6, 7, 9])),
+            ("globals", globals_program()),
+        ];
+
+        for frame_pointers in [false, true] {
+            let settings = BackendSettings { frame_pointers, ..BackendSettings::default() };
+            for (name, prog) in &programs {
+                let asm = lower(prog, &settings, AsmSyntax::Gas, ObjectFormat::Elf);
+                if !assemble_gas(name, &asm) {
+                    eprintln!("skipping, GNU as is not installed");
+                    return;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn inline_asm() {
+        let src = "
+            fun main() -> int {
+                let x = 5;
+                let r: int;
+                asm(\"lea ecx, [ebx+ebx]\", in(\"ebx\") x, out(\"ecx\") r);
+                return r;
+            }
+        ";
+        let asm = lower(&lower_source(src), &BackendSettings::default(), AsmSyntax::Nasm, ObjectFormat::Coff);
+        let main = func_lines(&asm, "func_0");
+
+        //the template is emitted verbatim, between moving the inputs in and the outputs out
+        let template = main.iter().position(|&line| line == "lea ecx, [ebx+ebx]").unwrap_or_else(|| panic!("{}", asm));
+        assert!(main[..template].iter().any(|line| line.starts_with("mov ebx, ")), "{}", asm);
+        assert!(main[template + 1..].iter().any(|line| line.contains("ecx")), "{}", asm);
+
+        let error = lower_error("fun main() -> int { let r: int; asm(\"\", out(\"rax\") r); return r; }");
+        assert!(error.starts_with("error: unknown register `rax`"), "{}", error);
+        let error = lower_error("fun main() -> int { let t = (1, 2); asm(\"\", in(\"eax\") t); return 0; }");
+        assert!(error.starts_with("error: type `(int, int)` can't be used as an asm operand"), "{}", error);
+    }
+
+    #[test]
+    fn calling_convention_stack_cleanup() {
+        let src = "
+            extern \"stdcall\" fun callee_pops(a: int, b: int) -> int { return a - b; }
+            extern \"cdecl\" fun caller_pops(a: int, b: int) -> int { return a - b; }
+            fun main() -> int { return callee_pops(5, 3) + caller_pops(5, 3); }
+        ";
+        let asm = lower(&lower_source(src), &BackendSettings::default(), AsmSyntax::Nasm, ObjectFormat::Coff);
+        assert!(named_func_lines(&asm, "callee_pops").contains(&"ret 8"), "{}", asm);
+        assert!(named_func_lines(&asm, "caller_pops").contains(&"ret"), "{}", asm);
+
+        //only the cdecl call is followed by the caller popping the arguments
+        let main = named_func_lines(&asm, "main");
+        let after_calls: Vec<&str> = main.iter().enumerate()
+            .filter(|&(_, line)| line.starts_with("call "))
+            .map(|(i, _)| main[i + 1])
+            .collect();
+        assert_eq!(1, after_calls.iter().filter(|&&line| line == "add esp, 8").count(), "{}", asm);
+
+        let error = lower_error("extern \"fastcall\" fun f(); fun main() -> int { return 0; }");
+        assert!(error.starts_with("error: unknown calling convention \"fastcall\""), "{}", error);
+    }
+
+
+    #[test]
+    fn pointer_offset_addressing() {
+        let src = "
+            struct Big { a: int, b: int, c: int }
+            fun scaled(p: &int, i: int) -> int { return *(p + i); }
+            fun big(p: &Big, i: int) -> int { return (*(p + i)).c; }
+            fun fixed(p: &int) -> int { return *(p + 3); }
+            fun main() -> int { let x = 5; let b = Big { a: 1, b: 2, c: 3 }; return scaled(&x, 0) + fixed(&x - 3) + big(&b, 0); }
+        ";
+        let asm = lower(&lower_source(src), &BackendSettings::default(), AsmSyntax::Nasm, ObjectFormat::Coff);
+        let func = |name: &str| named_func_lines(&asm, name).join("\n");
+
+        //sizes the addressing mode supports become the scale, others are multiplied first
+        assert!(func("scaled").contains("dword [ebx+ecx*4]"), "{}", asm);
+        let big = func("big");
+        assert!(big.contains("imul ecx, ecx, 12\nmov eax, dword [ebx+ecx*1+8]"), "{}", asm);
+        assert!(!big.contains("*12"), "{}", asm);
+
+        //a constant index is just a displacement
+        let fixed = func("fixed");
+        assert!(fixed.contains("dword [ebx+12]"), "{}", asm);
+        assert!(!fixed.contains("ecx"), "{}", asm);
+    }
+
+
+    #[test]
+    fn leaf_functions_and_frame_pointers() {
+        let src = "
+            extern \"sysv\" fun leaf(a: int) -> int { return a + 1; }
+            fun main() -> int { return leaf(2); }
+        ";
+        let prog = lower_source(src);
+
+        //calling a sysv function realigns the stack to 16 bytes, but only in the function that makes the call
+        let asm = lower(&prog, &BackendSettings::default(), AsmSyntax::Nasm, ObjectFormat::Coff);
+        assert_eq!(["push ebx", "sub esp, 8"], named_func_lines(&asm, "leaf")[..2], "{}", asm);
+        assert_eq!(["sub esp, 12"], named_func_lines(&asm, "main")[..1], "{}", asm);
+        assert!(!asm.contains("ebp"), "{}", asm);
+
+        //the frame pointer takes part of the padding and shifts the parameters
+        let settings = BackendSettings { frame_pointers: true, ..Default::default() };
+        let asm = lower(&prog, &settings, AsmSyntax::Nasm, ObjectFormat::Coff);
+        let leaf = named_func_lines(&asm, "leaf");
+        assert_eq!(["push ebp", "mov ebp, esp", "push ebx", "sub esp, 8"], leaf[..4], "{}", asm);
+        assert!(leaf.contains(&"mov eax, dword [esp+20]"), "{}", asm);
+        let main = named_func_lines(&asm, "main");
+        assert_eq!(["push ebp", "mov ebp, esp", "sub esp, 8"], main[..3], "{}", asm);
+        assert_eq!(["pop ebp", "ret"], main[main.len() - 2..], "{}", asm);
+    }
+
+
+    #[test]
+    fn fold_addresses_into_memory_operands() {
+        let src = "
+            struct Pair { a: int, b: int }
+            struct Outer { x: int, pair: Pair }
+            fun load(p: &Outer) -> int { return (*p).pair.b; }
+            fun store(p: &Outer) { (*p).pair.b = 7; }
+            fun address(p: &Outer) -> &int { return &(*p).pair.b; }
+            fun main() -> int { let o = Outer { x: 1, pair: Pair { a: 2, b: 3 } }; store(&o); return load(&o) + *address(&o); }
+        ";
+        let asm = lower(&lower_source(src), &BackendSettings::default(), AsmSyntax::Nasm, ObjectFormat::Coff);
+
+        //nested field offsets add up into a single displacement of the load or store
+        for (name, access) in [("load", "mov eax, dword [ebx+8]"), ("store", "mov [ebx+8], dword 7")] {
+            let lines = named_func_lines(&asm, name);
+            assert!(lines.contains(&access), "{}", asm);
+            assert!(!lines.iter().any(|line| line.starts_with("lea ")), "{}", asm);
+        }
+
+        //an address that is used as a value is still computed on its own
+        let address = named_func_lines(&asm, "address").join("\n");
+        assert!(address.contains("lea eax, [ebx+8]\nmov [esp], eax"), "{}", asm);
+    }
+
+
+    #[test]
+    fn branch_on_comparison_flags() {
+        let src = "
+            fun fused(a: int, b: int) -> int { if (a < b) { return 1; } return 2; }
+            fun reused(a: int, b: int) -> int { let c = a < b; if (c) { return 1; } if (c) { return 3; } return 2; }
+            fun float_eq(a: f64, b: f64) -> int { if (a == b) { return 1; } return 2; }
+            fun main() -> int { return fused(1, 2) + reused(1, 2) + float_eq(1.0, 2.0); }
+        ";
+        let asm = lower(&lower_source(src), &BackendSettings::default(), AsmSyntax::Nasm, ObjectFormat::Coff);
+        let code = |name: &str| -> Vec<&str> {
+            named_func_lines(&asm, name).into_iter().filter(|line| !line.starts_with(';')).collect()
+        };
+
+        //a comparison only used by the branch right after it jumps on the flags
+        let fused = code("fused");
+        let cmp = fused.iter().position(|&line| line == "cmp eax, ebx").unwrap_or_else(|| panic!("{}", asm));
+        assert_eq!("jge block_2", fused[cmp + 1], "{}", asm);
+        assert!(!fused.iter().any(|line| line.starts_with("set") || line.starts_with("test")), "{}", asm);
+
+        //a comparison that is used elsewhere is materialized, as is float equality which needs two flags
+        for name in ["reused", "float_eq"] {
+            let lines = code(name);
+            assert!(lines.iter().any(|line| line.starts_with("set")), "{}", asm);
+            assert!(lines.contains(&"test al, al"), "{}", asm);
+        }
+
+        assert!(preserves_flags("mov") && preserves_flags("setl") && preserves_flags("jz") && preserves_flags("lea"));
+        assert!(!preserves_flags("add") && !preserves_flags("test") && !preserves_flags("call") && !preserves_flags("and"));
+    }
+}
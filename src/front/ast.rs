@@ -14,20 +14,32 @@ pub enum TypeKind {
     Bool,
     Byte,
     Int,
+    Float { bits: u32 },
+    /// A string, represented as a pointer to its bytes together with its length.
+    Str,
 
     Path(Path),
 
     Ref(Box<Type>),
+    /// A pointer that can be null, it has to be checked against null before it can be dereferenced.
+    OptionalRef(Box<Type>),
+    /// A pointer to a number of consecutive values together with that number, eg. `&[int]`.
+    Slice(Box<Type>),
     Func {
         params: Vec<Type>,
         ret: Box<Type>,
     },
+    Closure {
+        params: Vec<Type>,
+        ret: Box<Type>,
+    },
     Tuple {
         fields: Vec<Type>
     },
     Array {
         inner: Box<Type>,
-        length: u32,
+        /// Evaluated as a constant expression during type resolution.
+        length: Box<Expression>,
     },
 }
 
@@ -53,19 +65,53 @@ pub struct Path {
 #[derive(Debug)]
 pub struct ModuleContent {
     pub items: Vec<Item>,
+    /// The modules declared inline with `mod name { ... }`, these become child modules of this module.
+    pub modules: Vec<InlineModule>,
+}
+
+#[derive(Debug)]
+pub struct InlineModule {
+    pub span: Span,
+    pub id: Identifier,
+    pub content: ModuleContent,
 }
 
 #[derive(Debug)]
 pub enum Item {
     UseDecl(UseDecl),
     Struct(Struct),
+    Enum(Enum),
     Function(Function),
     Const(Const),
+    StaticAssert(StaticAssert),
+}
+
+impl Item {
+    /// The content of the `///` comment before this item, without the slashes.
+    pub fn doc(&self) -> Option<&str> {
+        match self {
+            Item::Struct(item) => item.doc.as_deref(),
+            Item::Enum(item) => item.doc.as_deref(),
+            Item::Function(item) => item.doc.as_deref(),
+            Item::Const(item) => item.doc.as_deref(),
+            Item::UseDecl(_) | Item::StaticAssert(_) => None,
+        }
+    }
+}
+
+/// A condition that is evaluated at compile time, compilation fails if it's false.
+#[derive(Debug)]
+pub struct StaticAssert {
+    pub span: Span,
+    pub cond: Expression,
+    pub message: Option<String>,
 }
 
 #[derive(Debug)]
 pub struct Const {
     pub span: Span,
+    pub public: bool,
+    pub doc: Option<String>,
     pub id: Identifier,
     pub ty: Type,
     pub init: Expression,
@@ -74,12 +120,26 @@ pub struct Const {
 #[derive(Debug)]
 pub struct UseDecl {
     pub span: Span,
+    pub public: bool,
+    /// For glob imports this is the path of the module to import all items from.
     pub path: Path,
+    pub glob: bool,
+}
+
+/// An attribute like `@inline` or `@name(args)` attached to the item that follows it.
+#[derive(Debug)]
+pub struct Attribute {
+    pub span: Span,
+    pub id: Identifier,
+    pub args: Vec<Expression>,
 }
 
 #[derive(Debug)]
 pub struct Struct {
     pub span: Span,
+    pub public: bool,
+    pub attrs: Vec<Attribute>,
+    pub doc: Option<String>,
     pub id: Identifier,
     pub fields: Vec<StructField>,
 }
@@ -91,16 +151,52 @@ pub struct StructField {
     pub ty: Type,
 }
 
+#[derive(Debug)]
+pub struct Enum {
+    pub span: Span,
+    pub public: bool,
+    pub doc: Option<String>,
+    pub id: Identifier,
+    pub variants: Vec<EnumVariant>,
+}
+
+#[derive(Debug)]
+pub struct EnumVariant {
+    pub span: Span,
+    pub id: Identifier,
+    pub fields: Vec<Type>,
+}
+
 #[derive(Debug)]
 pub struct Function {
     pub span: Span,
+    pub public: bool,
+    pub attrs: Vec<Attribute>,
+    pub doc: Option<String>,
     pub ext: bool,
+    /// The calling convention given after `extern`, eg. `extern "cdecl" fun`.
+    pub conv: Option<CallConv>,
     pub id: Identifier,
+    /// The operator this function overloads, in that case `id` is `operator` followed by the operator.
+    pub operator: Option<BinaryOp>,
     pub ret_ty: Option<Type>,
     pub params: Vec<Parameter>,
     pub body: Option<Block>,
 }
 
+#[derive(Debug)]
+pub struct CallConv {
+    pub span: Span,
+    pub name: String,
+}
+
+#[derive(Debug)]
+pub struct CallArgument {
+    pub span: Span,
+    pub name: Option<Identifier>,
+    pub value: Expression,
+}
+
 #[derive(Debug)]
 pub struct Parameter {
     pub span: Span,
@@ -112,6 +208,8 @@ pub struct Parameter {
 pub struct Block {
     pub span: Span,
     pub statements: Vec<Statement>,
+    /// The final expression without a semicolon, which is the value of the block when used as an expression.
+    pub value: Option<Box<Expression>>,
 }
 
 #[derive(Debug)]
@@ -127,19 +225,63 @@ pub enum StatementKind {
     Expression(Box<Expression>),
     If(IfStatement),
     While(WhileStatement),
+    Loop(LoopStatement),
+    DoWhile(WhileStatement),
     For(ForStatement),
+    Match(MatchStatement),
+    /// `assert(cond);`, panics if `cond` is false.
+    Assert(Box<Expression>),
     Block(Block),
+    /// A nested function, only visible in the enclosing block.
+    Function(Box<Function>),
 }
 
 #[derive(Debug)]
 pub struct Declaration {
     pub span: Span,
     pub mutable: bool,
-    pub id: MaybeIdentifier,
+    pub pattern: DeclarationPattern,
     pub ty: Option<Type>,
     pub init: Option<Box<Expression>>,
 }
 
+/// The variables declared by a `let` statement.
+#[derive(Debug)]
+pub enum DeclarationPattern {
+    /// A single variable, eg. `let a`.
+    Single(MaybeIdentifier),
+    /// The elements of a tuple, eg. `let (a, b)`. The pattern must have an element for each tuple field.
+    Tuple {
+        span: Span,
+        elements: Vec<MaybeIdentifier>,
+    },
+    /// Some fields of a struct, eg. `let Point { x, y: other }`. Fields that aren't listed are ignored.
+    Struct {
+        span: Span,
+        path: Box<Path>,
+        fields: Vec<StructPatternField>,
+    },
+}
+
+impl DeclarationPattern {
+    /// The variables bound by this pattern, in order.
+    pub fn bindings(&self) -> Vec<&MaybeIdentifier> {
+        match self {
+            DeclarationPattern::Single(id) => vec![id],
+            DeclarationPattern::Tuple { span: _, elements } => elements.iter().collect(),
+            DeclarationPattern::Struct { span: _, path: _, fields } => fields.iter().map(|field| &field.binding).collect(),
+        }
+    }
+}
+
+/// A field in a struct pattern, `field` on its own binds a variable with the same name.
+#[derive(Debug)]
+pub struct StructPatternField {
+    pub span: Span,
+    pub field: Identifier,
+    pub binding: MaybeIdentifier,
+}
+
 #[derive(Debug)]
 pub struct Assignment {
     pub span: Span,
@@ -162,16 +304,59 @@ pub struct WhileStatement {
     pub body: Block,
 }
 
+#[derive(Debug)]
+pub struct LoopStatement {
+    pub span: Span,
+    pub body: Block,
+}
+
 #[derive(Debug)]
 pub struct ForStatement {
     pub span: Span,
     pub index: MaybeIdentifier,
     pub index_ty: Option<Type>,
-    pub start: Box<Expression>,
-    pub end: Box<Expression>,
+    pub range: ForRange,
     pub body: Block,
 }
 
+#[derive(Debug)]
+pub enum ForRange {
+    /// Iterate over the integers `start..end`, counting down towards `end` if `step` is negative.
+    Range {
+        start: Box<Expression>,
+        end: Box<Expression>,
+        step: Option<Box<Expression>>,
+    },
+    /// Iterate over the elements of an array.
+    Array(Box<Expression>),
+}
+
+#[derive(Debug)]
+pub struct MatchStatement {
+    pub span: Span,
+    pub value: Box<Expression>,
+    pub arms: Vec<MatchArm>,
+}
+
+#[derive(Debug)]
+pub struct MatchArm {
+    pub span: Span,
+    pub pattern: MatchPattern,
+    pub body: Block,
+}
+
+#[derive(Debug)]
+pub enum MatchPattern {
+    Wildcard(Span),
+    /// An integer literal, stored as an expression so it can be typed and lowered like one.
+    Literal(Box<Expression>),
+    Variant {
+        span: Span,
+        path: Path,
+        fields: Vec<MaybeIdentifier>,
+    },
+}
+
 #[derive(Debug)]
 pub struct Expression {
     pub span: Span,
@@ -181,21 +366,52 @@ pub struct Expression {
 #[derive(Debug)]
 pub enum ExpressionKind {
     IntLit { value: String },
+    FloatLit { value: String },
     BoolLit { value: bool },
-    StringLit { value: String },
+    StringLit { value: Vec<u8> },
     Null,
 
     Path(Path),
 
+    ArrayRepeat {
+        value: Box<Expression>,
+        length: u32,
+    },
+
+    /// A tuple literal with at least one element, eg. `(a, b)` or `(a,)`.
+    /// As the target of an assignment each element is assigned separately, eg. `(a, b) = (b, a)`.
+    Tuple { values: Vec<Expression> },
+
+    /// A struct literal, the fields that aren't given are copied from `base`, eg. `Point { x: 3, ..other }`.
+    StructLit {
+        path: Path,
+        fields: Vec<StructLitField>,
+        base: Option<Box<Expression>>,
+    },
+
     Call {
         target: Box<Expression>,
-        args: Vec<Expression>,
+        args: Vec<CallArgument>,
+    },
+
+    Block(Box<Block>),
+
+    Closure {
+        params: Vec<Parameter>,
+        ret_ty: Option<Type>,
+        body: Box<Block>,
     },
 
     ArrayIndex {
         target: Box<Expression>,
         index: Box<Expression>,
     },
+    /// Slicing an array or slice, eg. `arr[a..b]`. Missing bounds default to the start and end.
+    Slice {
+        target: Box<Expression>,
+        start: Option<Box<Expression>>,
+        end: Option<Box<Expression>>,
+    },
     DotIndex {
         target: Box<Expression>,
         index: DotIndexIndex,
@@ -221,12 +437,54 @@ pub enum ExpressionKind {
         inner: Box<Expression>,
     },
 
+    /// Allocate `size` bytes on the heap, eg. `alloc(16)`. The result is an uninitialized `&byte`.
+    Alloc { size: Box<Expression> },
+    /// Free memory that was allocated with `alloc` or `new`, eg. `free(ptr)`.
+    Free { ptr: Box<Expression> },
+    /// Allocate a value of type `ty` on the heap, eg. `new [int; 4]`. The result is an uninitialized `&ty`.
+    New { ty: Type },
+
+    /// Print `message` together with the source location and exit the process, eg. `panic("oops")`.
+    Panic { message: Box<Expression> },
+    /// `unreachable()`, tells the optimizer that this point can never be reached. Reaching it anyway is undefined.
+    Unreachable,
+
+    /// Inline assembly, `template` is emitted verbatim in the output syntax with the operands bound to registers
+    /// around it.
+    InlineAsm {
+        template: String,
+        operands: Vec<AsmOperand>,
+    },
+
     Return { value: Option<Box<Expression>> },
     Continue,
     Break,
 }
 
+#[derive(Debug)]
+pub struct StructLitField {
+    pub span: Span,
+    pub id: Identifier,
+    pub value: Expression,
+}
+
+#[derive(Debug)]
+pub struct AsmOperand {
+    pub span: Span,
+    pub kind: AsmOperandKind,
+    /// The register the operand is bound to, eg. `eax`.
+    pub constraint: String,
+    /// The value for inputs, the place to store the result in for outputs.
+    pub value: Expression,
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AsmOperandKind {
+    In,
+    Out,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum BinaryOp {
     Add,
     Sub,
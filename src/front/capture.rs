@@ -0,0 +1,246 @@
+use crate::front::ast;
+
+/// Find the identifiers used in a closure that may refer to variables of the enclosing function: the single-segment
+/// paths in `body` that don't refer to the closure parameters or to variables declared within the closure itself.
+/// Each identifier is only listed once, in order of first use. Whether an identifier actually refers to a local
+/// variable and not to an item (eg. a function) is decided by the caller.
+pub fn find_captures<'a>(params: &'a [ast::Parameter], body: &'a ast::Block) -> Vec<&'a ast::Identifier> {
+    let mut state = CaptureState { declared: vec![], captures: vec![] };
+
+    for param in params {
+        state.declare(&param.id);
+    }
+    state.visit_block(body);
+
+    state.captures
+}
+
+struct CaptureState<'a> {
+    /// The variables declared within the closure that are currently in scope.
+    declared: Vec<&'a str>,
+    captures: Vec<&'a ast::Identifier>,
+}
+
+impl<'a> CaptureState<'a> {
+    fn declare(&mut self, id: &'a ast::MaybeIdentifier) {
+        if let ast::MaybeIdentifier::Identifier(id) = id {
+            self.declared.push(&id.string);
+        }
+    }
+
+    fn visit_path(&mut self, path: &'a ast::Path) {
+        //paths with multiple elements always refer to items
+        if !path.parents.is_empty() { return; }
+
+        let id = &path.id;
+        let declared = self.declared.contains(&id.string.as_str());
+        let captured = self.captures.iter().any(|c| c.string == id.string);
+
+        if !declared && !captured {
+            self.captures.push(id);
+        }
+    }
+
+    fn visit_block(&mut self, block: &'a ast::Block) {
+        let declared_len = self.declared.len();
+        for stmt in &block.statements {
+            self.visit_statement(stmt);
+        }
+        if let Some(value) = &block.value {
+            self.visit_expr(value);
+        }
+        self.declared.truncate(declared_len);
+    }
+
+    fn visit_statement(&mut self, stmt: &'a ast::Statement) {
+        match &stmt.kind {
+            ast::StatementKind::Declaration(decl) => {
+                //visit the initializer first, it can still refer to a shadowed variable
+                if let Some(init) = &decl.init {
+                    self.visit_expr(init);
+                }
+                for id in decl.pattern.bindings() {
+                    self.declare(id);
+                }
+            }
+            ast::StatementKind::Assignment(assign) => {
+                self.visit_expr(&assign.left);
+                self.visit_expr(&assign.right);
+            }
+            ast::StatementKind::Expression(expr) | ast::StatementKind::Assert(expr) => {
+                self.visit_expr(expr);
+            }
+            ast::StatementKind::If(if_stmt) => {
+                self.visit_expr(&if_stmt.cond);
+                self.visit_block(&if_stmt.then_block);
+                if let Some(else_block) = &if_stmt.else_block {
+                    self.visit_block(else_block);
+                }
+            }
+            ast::StatementKind::While(while_stmt) | ast::StatementKind::DoWhile(while_stmt) => {
+                self.visit_expr(&while_stmt.cond);
+                self.visit_block(&while_stmt.body);
+            }
+            ast::StatementKind::Loop(loop_stmt) => {
+                self.visit_block(&loop_stmt.body);
+            }
+            ast::StatementKind::For(for_stmt) => {
+                match &for_stmt.range {
+                    ast::ForRange::Range { start, end, step } => {
+                        self.visit_expr(start);
+                        self.visit_expr(end);
+                        if let Some(step) = step {
+                            self.visit_expr(step);
+                        }
+                    }
+                    ast::ForRange::Array(array) => self.visit_expr(array),
+                }
+
+                let declared_len = self.declared.len();
+                self.declare(&for_stmt.index);
+                self.visit_block(&for_stmt.body);
+                self.declared.truncate(declared_len);
+            }
+            ast::StatementKind::Match(match_stmt) => {
+                self.visit_expr(&match_stmt.value);
+
+                for arm in &match_stmt.arms {
+                    let declared_len = self.declared.len();
+                    if let ast::MatchPattern::Variant { fields, .. } = &arm.pattern {
+                        for field in fields {
+                            self.declare(field);
+                        }
+                    }
+                    self.visit_block(&arm.body);
+                    self.declared.truncate(declared_len);
+                }
+            }
+            ast::StatementKind::Block(block) => {
+                self.visit_block(block);
+            }
+            ast::StatementKind::Function(func) => {
+                //nested functions can't capture anything themselves
+                self.declared.push(&func.id.string);
+            }
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &'a ast::Expression) {
+        match &expr.kind {
+            ast::ExpressionKind::IntLit { .. } | ast::ExpressionKind::FloatLit { .. } |
+            ast::ExpressionKind::BoolLit { .. } | ast::ExpressionKind::StringLit { .. } |
+            ast::ExpressionKind::Null | ast::ExpressionKind::New { .. } | ast::ExpressionKind::Unreachable |
+            ast::ExpressionKind::Continue | ast::ExpressionKind::Break => {}
+
+            ast::ExpressionKind::Path(path) => self.visit_path(path),
+            ast::ExpressionKind::Block(block) => self.visit_block(block),
+
+            ast::ExpressionKind::ArrayRepeat { value, length: _ } => self.visit_expr(value),
+            ast::ExpressionKind::Tuple { values } => {
+                for value in values {
+                    self.visit_expr(value);
+                }
+            }
+            ast::ExpressionKind::StructLit { path: _, fields, base } => {
+                for field in fields {
+                    self.visit_expr(&field.value);
+                }
+                if let Some(base) = base {
+                    self.visit_expr(base);
+                }
+            }
+            ast::ExpressionKind::Call { target, args } => {
+                self.visit_expr(target);
+                for arg in args {
+                    self.visit_expr(&arg.value);
+                }
+            }
+            ast::ExpressionKind::Closure { params, ret_ty: _, body } => {
+                //variables captured by a nested closure need to be captured by this closure too
+                let declared_len = self.declared.len();
+                for param in params {
+                    self.declare(&param.id);
+                }
+                self.visit_block(body);
+                self.declared.truncate(declared_len);
+            }
+            ast::ExpressionKind::ArrayIndex { target, index } => {
+                self.visit_expr(target);
+                self.visit_expr(index);
+            }
+            ast::ExpressionKind::Slice { target, start, end } => {
+                self.visit_expr(target);
+                for bound in start.iter().chain(end) {
+                    self.visit_expr(bound);
+                }
+            }
+            ast::ExpressionKind::DotIndex { target, index: _ } => self.visit_expr(target),
+            ast::ExpressionKind::Cast { value, ty: _ } => self.visit_expr(value),
+            ast::ExpressionKind::Alloc { size: inner } | ast::ExpressionKind::Free { ptr: inner } |
+            ast::ExpressionKind::Panic { message: inner } => self.visit_expr(inner),
+            ast::ExpressionKind::Ternary { condition, then_value, else_value } => {
+                self.visit_expr(condition);
+                self.visit_expr(then_value);
+                self.visit_expr(else_value);
+            }
+            ast::ExpressionKind::Binary { kind: _, left, right } => {
+                self.visit_expr(left);
+                self.visit_expr(right);
+            }
+            ast::ExpressionKind::Unary { kind: _, inner } => self.visit_expr(inner),
+            ast::ExpressionKind::InlineAsm { template: _, operands } => {
+                for operand in operands {
+                    self.visit_expr(&operand.value);
+                }
+            }
+            ast::ExpressionKind::Return { value } => {
+                if let Some(value) = value {
+                    self.visit_expr(value);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::front::parser::parse_module;
+    use crate::front::pos::FileId;
+
+    use super::*;
+
+    /// Parse `fun main() { <stmt> }` and find the captures of the closure declared by the first statement.
+    fn captures_of(stmt: &str) -> Vec<String> {
+        let src = format!("fun main() {{ {} }}", stmt);
+        let module = parse_module(FileId(0), &src).unwrap();
+
+        let func = match &module.items[0] {
+            ast::Item::Function(func) => func,
+            _ => unreachable!(),
+        };
+        let decl = match &func.body.as_ref().unwrap().statements[0].kind {
+            ast::StatementKind::Declaration(decl) => decl,
+            _ => unreachable!(),
+        };
+
+        match &decl.init.as_ref().unwrap().kind {
+            ast::ExpressionKind::Closure { params, ret_ty: _, body } =>
+                find_captures(params, body).iter().map(|id| id.string.clone()).collect(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn captures_free_variables() {
+        assert_eq!(vec!["b", "c"], captures_of("let f = |a: int| { a = b + c + b; };"));
+        assert_eq!(vec!["f"], captures_of("let g = || { f(); m::f(); };"));
+    }
+
+    #[test]
+    fn ignores_local_variables() {
+        assert_eq!(Vec::<String>::new(), captures_of("let f = |a: int| { let b = a; b = 2; };"));
+        assert_eq!(vec!["x"], captures_of("let f = || { let x = x; };"));
+        assert_eq!(vec!["i"], captures_of("let f = || { for i in 0..10 {} i = 1; };"));
+        assert_eq!(vec!["y"], captures_of("let f = || { let g = |x: int| { x = y; }; };"));
+    }
+}
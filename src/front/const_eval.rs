@@ -0,0 +1,537 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::front::ast;
+use crate::front::cst::{self, ItemStore, ScopedItem, ScopedValue, ScopeKind, Type, TypeInfo, TypeStore};
+use crate::front::error::{Error, Result};
+use crate::front::literal::{parse_float_literal, parse_int_literal};
+use crate::front::lower_func::signedness;
+use crate::front::scope::Scope;
+use crate::mid::ir::Signedness;
+
+/// The result of evaluating a constant expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConstValue {
+    /// An integer of type `int` or `byte`, stored as its bit pattern.
+    Int { ty: Type, value: u32 },
+    Bool(bool),
+    Float { ty: Type, value: f64 },
+    /// A pointer to the given bytes, eg. a string literal.
+    Bytes { ty: Type, bytes: Vec<u8> },
+    Null { ty: Type },
+}
+
+impl ConstValue {
+    pub fn ty(&self, types: &TypeStore) -> Type {
+        match *self {
+            ConstValue::Int { ty, .. } | ConstValue::Float { ty, .. } |
+            ConstValue::Bytes { ty, .. } | ConstValue::Null { ty } => ty,
+            ConstValue::Bool(_) => types.type_bool(),
+        }
+    }
+}
+
+/// Evaluates the initializers of `const` items, including references to other constants.
+pub struct ConstEvalState<'ast, 'cst> {
+    items: &'cst ItemStore<'ast>,
+    const_modules: HashMap<cst::Const, cst::Module>,
+
+    values: HashMap<cst::Const, ConstValue>,
+    in_progress: HashSet<cst::Const>,
+}
+
+impl<'ast, 'cst> ConstEvalState<'ast, 'cst> {
+    pub fn new(items: &'cst ItemStore<'ast>) -> Self {
+        let const_modules = items.modules.iter()
+            .flat_map(|(module, info)| info.consts.iter().map(move |&cst| (cst, module)))
+            .collect();
+
+        ConstEvalState { items, const_modules, values: Default::default(), in_progress: Default::default() }
+    }
+
+    /// Evaluate the given constant, checking that the value matches the declared type.
+    pub fn eval_const(&mut self, types: &mut TypeStore<'ast>, cst: cst::Const) -> Result<'ast, ConstValue> {
+        if let Some(value) = self.values.get(&cst) {
+            return Ok(value.clone());
+        }
+
+        let decl = &self.items.consts[cst];
+        if !self.in_progress.insert(cst) {
+            return Err(Error::ConstCycle(decl.ast));
+        }
+
+        let module = *self.const_modules.get(&cst).unwrap();
+        let scope = &self.items.modules[module].scope;
+
+        let value = self.eval(types, scope, &decl.ast.init, Some(decl.ty))?;
+        check_type_match(types, &decl.ast.init, decl.ty, value.ty(types))?;
+
+        self.in_progress.remove(&cst);
+        self.values.insert(cst, value.clone());
+        Ok(value)
+    }
+
+    /// Evaluate the length of an array type, which must be a non-negative `int`.
+    pub fn eval_array_length(
+        &mut self,
+        types: &mut TypeStore<'ast>,
+        scope: &Scope<ScopedItem>,
+        expr: &'ast ast::Expression,
+    ) -> Result<'ast, u32> {
+        let ty_int = types.type_int();
+
+        match self.eval(types, scope, expr, Some(ty_int))? {
+            ConstValue::Int { ty, value } if ty == ty_int => {
+                if (value as i32) < 0 {
+                    Err(Error::NegativeArrayLength(expr))
+                } else {
+                    Ok(value)
+                }
+            }
+            value => Err(type_mismatch(types, expr, ty_int, value.ty(types))),
+        }
+    }
+
+    /// Evaluate a condition, which must be a `bool`.
+    pub fn eval_condition(
+        &mut self,
+        types: &mut TypeStore<'ast>,
+        scope: &Scope<ScopedItem>,
+        expr: &'ast ast::Expression,
+    ) -> Result<'ast, bool> {
+        let ty_bool = types.type_bool();
+
+        match self.eval(types, scope, expr, Some(ty_bool))? {
+            ConstValue::Bool(value) => Ok(value),
+            value => Err(type_mismatch(types, expr, ty_bool, value.ty(types))),
+        }
+    }
+
+    /// Evaluate `expr`, using `expected` as the type for literals if it is known.
+    fn eval(
+        &mut self,
+        types: &mut TypeStore<'ast>,
+        scope: &Scope<ScopedItem>,
+        expr: &'ast ast::Expression,
+        expected: Option<Type>,
+    ) -> Result<'ast, ConstValue> {
+        let value = match &expr.kind {
+            ast::ExpressionKind::IntLit { value } => eval_int_literal(types, expr, value, expected, false)?,
+            ast::ExpressionKind::FloatLit { value } => {
+                let ty = expected.unwrap_or_else(|| types.define_type(TypeInfo::Float { bits: 64 }));
+                let bits = float_bits(types, expr, ty)?;
+                let bytes = parse_float_literal(value, bits)
+                    .ok_or_else(|| invalid_literal(types, expr, value, ty))?;
+                ConstValue::Float { ty, value: float_from_bytes(&bytes) }
+            }
+            &ast::ExpressionKind::BoolLit { value } => ConstValue::Bool(value),
+            ast::ExpressionKind::StringLit { value } => {
+                //TODO support `str` constants, data items can't contain pointers to other data yet
+                let ty_byte = types.type_byte();
+                ConstValue::Bytes { ty: types.define_type_ptr(ty_byte), bytes: value.clone() }
+            }
+            ast::ExpressionKind::Null => {
+                //only optional pointers can be null, without an expected type `?&void` is used
+                let ty = expected.unwrap_or_else(|| {
+                    let ty_void = types.type_void();
+                    types.define_type(TypeInfo::OptionalPointer(ty_void))
+                });
+                match types[ty] {
+                    TypeInfo::OptionalPointer(_) => {}
+                    _ => return Err(Error::ExpectOptionalPointerType { expression: expr, actual: types.format_type(ty).to_string() }),
+                }
+                ConstValue::Null { ty }
+            }
+            ast::ExpressionKind::Path(path) => {
+                match self.items.resolve_path(ScopeKind::Real, scope, path)? {
+                    ScopedItem::Value(ScopedValue::Const(cst)) => self.eval_const(types, cst)?,
+                    _ => return Err(Error::NotConstant(expr)),
+                }
+            }
+            ast::ExpressionKind::Cast { value: inner, ty } => {
+                let ty = self.items.resolve_type(ScopeKind::Real, scope, types, ty)?;
+                let value = self.eval(types, scope, inner, None)?;
+
+                //the same conversions as when lowering the cast
+                match (&types[ty], value) {
+                    //only the type of a pointer changes
+                    (TypeInfo::Pointer(_) | TypeInfo::OptionalPointer(_), ConstValue::Bytes { ty: _, bytes }) =>
+                        ConstValue::Bytes { ty, bytes },
+                    (TypeInfo::Pointer(_) | TypeInfo::OptionalPointer(_), ConstValue::Null { ty: _ }) =>
+                        ConstValue::Null { ty },
+                    (TypeInfo::Pointer(_) | TypeInfo::OptionalPointer(_), value) => return Err(Error::ExpectPointerType {
+                        expression: inner,
+                        actual: types.format_type(value.ty(types)).to_string(),
+                    }),
+                    //values are stored zero extended, so only truncation changes them
+                    (TypeInfo::Int | TypeInfo::Byte, ConstValue::Int { ty: _, value }) => {
+                        let bits = int_bits(types, expr, ty)?;
+                        ConstValue::Int { ty, value: truncate(value as i64, bits) }
+                    }
+                    (TypeInfo::Int | TypeInfo::Byte, ConstValue::Bool(value)) =>
+                        ConstValue::Int { ty, value: value as u32 },
+                    (TypeInfo::Int | TypeInfo::Byte, value) => return Err(Error::ExpectIntegerType {
+                        expression: inner,
+                        actual: types.format_type(value.ty(types)).to_string(),
+                    }),
+                    _ => return Err(Error::InvalidCastTarget { expression: expr, target: types.format_type(ty).to_string() }),
+                }
+            }
+            ast::ExpressionKind::Ternary { condition, then_value, else_value } => {
+                let ty_bool = types.type_bool();
+                let condition_value = self.eval(types, scope, condition, Some(ty_bool))?;
+                let condition_value = match condition_value {
+                    ConstValue::Bool(value) => value,
+                    _ => return Err(type_mismatch(types, condition, ty_bool, condition_value.ty(types))),
+                };
+
+                let expected = expected.or_else(|| self.infer_type(types, scope, then_value));
+                let then_value = self.eval(types, scope, then_value, expected)?;
+                let then_ty = then_value.ty(types);
+                let else_value = self.eval(types, scope, else_value, Some(then_ty))?;
+                check_type_match(types, expr, then_ty, else_value.ty(types))?;
+
+                if condition_value { then_value } else { else_value }
+            }
+            ast::ExpressionKind::Binary { kind, left, right } => {
+                let operand_ty = if is_comparison(*kind) { None } else { expected }
+                    .or_else(|| self.infer_type(types, scope, left))
+                    .or_else(|| self.infer_type(types, scope, right));
+
+                let left = self.eval(types, scope, left, operand_ty)?;
+                let left_ty = left.ty(types);
+                let right_value = self.eval(types, scope, right, Some(left_ty))?;
+                check_type_match(types, right, left_ty, right_value.ty(types))?;
+
+                self.eval_binary(types, expr, *kind, left, right_value)?
+            }
+            ast::ExpressionKind::Unary { kind: ast::UnaryOp::Neg, inner } => {
+                //only negated literals can be the minimum value of `int`
+                if let ast::ExpressionKind::IntLit { value } = &inner.kind {
+                    return eval_int_literal(types, inner, value, expected, true);
+                }
+
+                match self.eval(types, scope, inner, expected)? {
+                    ConstValue::Int { ty, value } => {
+                        let bits = int_bits(types, expr, ty)?;
+                        ConstValue::Int { ty, value: truncate(-sign_extend(value, bits), bits) }
+                    }
+                    ConstValue::Float { ty, value } => ConstValue::Float { ty, value: round_float(types, ty, -value) },
+                    value => return Err(Error::ExpectIntegerType {
+                        expression: inner,
+                        actual: types.format_type(value.ty(types)).to_string(),
+                    }),
+                }
+            }
+
+            ast::ExpressionKind::Unary { .. } | ast::ExpressionKind::Call { .. } | ast::ExpressionKind::Block(_) |
+            ast::ExpressionKind::Closure { .. } | ast::ExpressionKind::ArrayIndex { .. } |
+            ast::ExpressionKind::ArrayRepeat { .. } | ast::ExpressionKind::Tuple { .. } | ast::ExpressionKind::StructLit { .. } | ast::ExpressionKind::DotIndex { .. } |
+            ast::ExpressionKind::Slice { .. } |
+            ast::ExpressionKind::Alloc { .. } | ast::ExpressionKind::Free { .. } | ast::ExpressionKind::New { .. } |
+            ast::ExpressionKind::Panic { .. } | ast::ExpressionKind::Unreachable | ast::ExpressionKind::InlineAsm { .. } | ast::ExpressionKind::Return { .. } | ast::ExpressionKind::Continue | ast::ExpressionKind::Break =>
+                return Err(Error::NotConstant(expr)),
+        };
+
+        Ok(value)
+    }
+
+    fn eval_binary(
+        &mut self,
+        types: &mut TypeStore<'ast>,
+        expr: &'ast ast::Expression,
+        kind: ast::BinaryOp,
+        left: ConstValue,
+        right: ConstValue,
+    ) -> Result<'ast, ConstValue> {
+        let value = match (left, right) {
+            (ConstValue::Int { ty, value: left }, ConstValue::Int { ty: _, value: right }) => {
+                let bits = int_bits(types, expr, ty)?;
+                match eval_int_binary(kind, bits, signedness(&types[ty]), left, right) {
+                    Some(IntResult::Int(value)) => ConstValue::Int { ty, value },
+                    Some(IntResult::Bool(value)) => ConstValue::Bool(value),
+                    None => return Err(Error::ConstDivisionByZero(expr)),
+                }
+            }
+            (ConstValue::Float { ty, value: left }, ConstValue::Float { ty: _, value: right }) => {
+                let arithmetic = |value: f64| ConstValue::Float { ty, value: round_float(types, ty, value) };
+
+                match kind {
+                    ast::BinaryOp::Add => arithmetic(left + right),
+                    ast::BinaryOp::Sub => arithmetic(left - right),
+                    ast::BinaryOp::Mul => arithmetic(left * right),
+                    ast::BinaryOp::Div => arithmetic(left / right),
+                    ast::BinaryOp::Mod => return Err(Error::NotConstant(expr)),
+                    ast::BinaryOp::Eq => ConstValue::Bool(left == right),
+                    ast::BinaryOp::Neq => ConstValue::Bool(left != right),
+                    ast::BinaryOp::Gte => ConstValue::Bool(left >= right),
+                    ast::BinaryOp::Gt => ConstValue::Bool(left > right),
+                    ast::BinaryOp::Lte => ConstValue::Bool(left <= right),
+                    ast::BinaryOp::Lt => ConstValue::Bool(left < right),
+                }
+            }
+            (ConstValue::Bool(left), ConstValue::Bool(right)) => {
+                match kind {
+                    ast::BinaryOp::Eq => ConstValue::Bool(left == right),
+                    ast::BinaryOp::Neq => ConstValue::Bool(left != right),
+                    _ => return Err(Error::ExpectIntegerType {
+                        expression: expr,
+                        actual: types.format_type(types.type_bool()).to_string(),
+                    }),
+                }
+            }
+            (left, _) => return Err(Error::ExpectIntegerType {
+                expression: expr,
+                actual: types.format_type(left.ty(types)).to_string(),
+            }),
+        };
+
+        Ok(value)
+    }
+
+    /// Try to find the type of `expr` without evaluating it. Returns `None` for untyped literals.
+    fn infer_type(&mut self, types: &mut TypeStore<'ast>, scope: &Scope<ScopedItem>, expr: &'ast ast::Expression) -> Option<Type> {
+        match &expr.kind {
+            ast::ExpressionKind::BoolLit { .. } => Some(types.type_bool()),
+            ast::ExpressionKind::Path(path) => {
+                match self.items.resolve_path(ScopeKind::Real, scope, path) {
+                    Ok(ScopedItem::Value(ScopedValue::Const(cst))) => Some(self.items.consts[cst].ty),
+                    _ => None,
+                }
+            }
+            ast::ExpressionKind::Cast { value: _, ty } =>
+                self.items.resolve_type(ScopeKind::Real, scope, types, ty).ok(),
+            ast::ExpressionKind::Binary { kind, left, right } => {
+                if is_comparison(*kind) {
+                    Some(types.type_bool())
+                } else {
+                    self.infer_type(types, scope, left).or_else(|| self.infer_type(types, scope, right))
+                }
+            }
+            ast::ExpressionKind::Unary { kind: ast::UnaryOp::Neg, inner } => self.infer_type(types, scope, inner),
+            ast::ExpressionKind::Ternary { condition: _, then_value, else_value } =>
+                self.infer_type(types, scope, then_value).or_else(|| self.infer_type(types, scope, else_value)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum IntResult {
+    Int(u32),
+    Bool(bool),
+}
+
+/// Evaluate a binary operation on two integers of `bits` bits with the given signedness, using the same wrapping
+/// semantics as the generated code. Returns `None` when dividing by zero.
+fn eval_int_binary(kind: ast::BinaryOp, bits: u32, signedness: Signedness, left: u32, right: u32) -> Option<IntResult> {
+    let (left, right) = (signedness.extend(bits, left as i64), signedness.extend(bits, right as i64));
+
+    let int = |value: i64| Some(IntResult::Int(truncate(value, bits)));
+    let bool = |value: bool| Some(IntResult::Bool(value));
+
+    match kind {
+        ast::BinaryOp::Add => int(left + right),
+        ast::BinaryOp::Sub => int(left - right),
+        ast::BinaryOp::Mul => int(left * right),
+        ast::BinaryOp::Div => if right == 0 { None } else { int(left / right) },
+        ast::BinaryOp::Mod => if right == 0 { None } else { int(left % right) },
+        ast::BinaryOp::Eq => bool(left == right),
+        ast::BinaryOp::Neq => bool(left != right),
+        ast::BinaryOp::Gte => bool(left >= right),
+        ast::BinaryOp::Gt => bool(left > right),
+        ast::BinaryOp::Lte => bool(left <= right),
+        ast::BinaryOp::Lt => bool(left < right),
+    }
+}
+
+fn is_comparison(kind: ast::BinaryOp) -> bool {
+    match kind {
+        ast::BinaryOp::Add | ast::BinaryOp::Sub | ast::BinaryOp::Mul | ast::BinaryOp::Div | ast::BinaryOp::Mod => false,
+        ast::BinaryOp::Eq | ast::BinaryOp::Neq | ast::BinaryOp::Gte | ast::BinaryOp::Gt |
+        ast::BinaryOp::Lte | ast::BinaryOp::Lt => true,
+    }
+}
+
+fn sign_extend(value: u32, bits: u32) -> i64 {
+    let shift = 64 - bits;
+    ((value as i64) << shift) >> shift
+}
+
+/// Evaluate the integer literal `lit`, which has type `expected` or `int` if that's not known. If `negated` is true
+/// the literal is the operand of a unary minus and the negated value is returned.
+fn eval_int_literal<'ast>(
+    types: &TypeStore<'ast>,
+    expr: &'ast ast::Expression,
+    lit: &str,
+    expected: Option<Type>,
+    negated: bool,
+) -> Result<'ast, ConstValue> {
+    let ty = expected.unwrap_or(types.type_int());
+    let bits = int_bits(types, expr, ty)?;
+    let value = parse_int_literal(lit, bits, signedness(&types[ty]), negated)
+        .ok_or_else(|| invalid_literal(types, expr, lit, ty))?;
+
+    let value = if negated { truncate(-(value as i64), bits) } else { value };
+    Ok(ConstValue::Int { ty, value })
+}
+
+fn truncate(value: i64, bits: u32) -> u32 {
+    (value as u64 & ((1u64 << bits) - 1)) as u32
+}
+
+fn float_from_bytes(bytes: &[u8]) -> f64 {
+    match bytes.len() {
+        4 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+        8 => f64::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7]]),
+        _ => unreachable!(),
+    }
+}
+
+/// Round `value` to the precision of the float type `ty`.
+fn round_float(types: &TypeStore, ty: Type, value: f64) -> f64 {
+    match types[ty] {
+        TypeInfo::Float { bits: 32 } => value as f32 as f64,
+        _ => value,
+    }
+}
+
+fn int_bits<'ast>(types: &TypeStore, expr: &'ast ast::Expression, ty: Type) -> Result<'ast, u32> {
+    match types[ty] {
+        TypeInfo::Byte => Ok(8),
+        TypeInfo::Int => Ok(32),
+        _ => Err(Error::ExpectIntegerType { expression: expr, actual: types.format_type(ty).to_string() }),
+    }
+}
+
+fn float_bits<'ast>(types: &TypeStore, expr: &'ast ast::Expression, ty: Type) -> Result<'ast, u32> {
+    match types[ty] {
+        TypeInfo::Float { bits } => Ok(bits),
+        _ => Err(Error::ExpectFloatType { expression: expr, actual: types.format_type(ty).to_string() }),
+    }
+}
+
+fn invalid_literal<'ast>(types: &TypeStore, expr: &ast::Expression, lit: &str, ty: Type) -> Error<'ast> {
+    Error::InvalidLiteral { span: expr.span, lit: lit.to_owned(), ty: types.format_type(ty).to_string() }
+}
+
+fn type_mismatch<'ast>(types: &TypeStore, expr: &'ast ast::Expression, expected: Type, actual: Type) -> Error<'ast> {
+    Error::TypeMismatch {
+        span: expr.span,
+        expected: types.format_type(expected).to_string(),
+        actual: types.format_type(actual).to_string(),
+        expected_origin: None,
+    }
+}
+
+fn check_type_match<'ast>(types: &TypeStore, expr: &'ast ast::Expression, expected: Type, actual: Type) -> Result<'ast, ()> {
+    if expected != actual {
+        return Err(type_mismatch(types, expr, expected, actual));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mid::interp::interpret;
+    use crate::mid::opt::snapshot::{lower_error, lower_source};
+
+    fn eval(kind: ast::BinaryOp, bits: u32, left: i64, right: i64) -> Option<IntResult> {
+        //only `int` is signed
+        let signedness = if bits == 32 { Signedness::Signed } else { Signedness::Unsigned };
+        eval_int_binary(kind, bits, signedness, truncate(left, bits), truncate(right, bits))
+    }
+
+    #[test]
+    fn int_arithmetic() {
+        assert_eq!(Some(IntResult::Int(4096)), eval(ast::BinaryOp::Mul, 32, 4, 1024));
+        assert_eq!(Some(IntResult::Int(truncate(-2, 32))), eval(ast::BinaryOp::Div, 32, -7, 3));
+        assert_eq!(Some(IntResult::Int(truncate(-1, 32))), eval(ast::BinaryOp::Mod, 32, -7, 3));
+        assert_eq!(None, eval(ast::BinaryOp::Div, 32, 1, 0));
+        assert_eq!(None, eval(ast::BinaryOp::Mod, 32, 1, 0));
+    }
+
+    #[test]
+    fn int_wrapping() {
+        assert_eq!(Some(IntResult::Int(0)), eval(ast::BinaryOp::Add, 8, 255, 1));
+        assert_eq!(Some(IntResult::Int(0x8000_0000)), eval(ast::BinaryOp::Add, 32, 0x7fff_ffff, 1));
+        assert_eq!(Some(IntResult::Int(0x8000_0000)), eval(ast::BinaryOp::Div, 32, i32::MIN as i64, -1));
+    }
+
+    #[test]
+    fn int_comparison() {
+        assert_eq!(Some(IntResult::Bool(true)), eval(ast::BinaryOp::Lt, 32, -1, 0));
+        assert_eq!(Some(IntResult::Bool(true)), eval(ast::BinaryOp::Neq, 32, 1, 2));
+    }
+
+    #[test]
+    fn byte_is_unsigned() {
+        assert_eq!(Some(IntResult::Bool(true)), eval(ast::BinaryOp::Gt, 8, 200, 100));
+        assert_eq!(Some(IntResult::Bool(false)), eval(ast::BinaryOp::Lte, 8, 255, 0));
+        assert_eq!(Some(IntResult::Int(66)), eval(ast::BinaryOp::Div, 8, 200, 3));
+        assert_eq!(Some(IntResult::Int(2)), eval(ast::BinaryOp::Mod, 8, 200, 3));
+        assert_eq!(Some(IntResult::Int(88)), eval(ast::BinaryOp::Mul, 8, 200, 3));
+    }
+
+    #[test]
+    fn int_casts() {
+        //the same truncation and extension as the lowered casts
+        let prog = lower_source("
+            const X: byte = 300 as byte;
+            const Y: int = true as int;
+            static_assert((200 as byte) > 100, \"byte casts are unsigned\");
+            static_assert((-1 as byte) as int == 255);
+            fun main() -> int { return X as int + Y; }
+        ");
+
+        assert_eq!(Ok(45), interpret(&prog));
+    }
+
+    #[test]
+    fn int_minimum() {
+        let prog = lower_source("
+            const MIN: int = -2147483648;
+            static_assert(MIN < 0);
+            fun main() -> int { return (MIN == -2147483648) as int + (-128 as byte) as int; }
+        ");
+
+        assert_eq!(Ok(129), interpret(&prog));
+    }
+
+    #[test]
+    fn array_length_expressions() {
+        let prog = lower_source("
+            const N: int = 3;
+            struct S { a: [int; 2 * N] }
+            fun main() -> int {
+                let b: [byte; N + 1] = [1 as byte; 4];
+                let s = S { a: [5; 6] };
+                return s.a[5] + b[3] as int;
+            }
+        ");
+        assert_eq!(Ok(6), interpret(&prog));
+
+        let error = lower_error("const N: int = 3; fun main() -> int { let a: [int; 1 - N]; return 0; }");
+        assert!(error.starts_with("error: array length is negative"), "{}", error);
+        let error = lower_error("fun main() -> int { let n = 3; let a: [int; n]; return 0; }");
+        assert!(error.starts_with("error: expression is not constant"), "{}", error);
+    }
+
+    #[test]
+    fn static_assert() {
+        let prog = lower_source("
+            const SIZE: int = 16;
+            static_assert(SIZE % 4 == 0, \"size must be aligned\");
+            static_assert(SIZE > 0);
+            fun main() -> int { return SIZE; }
+        ");
+        assert_eq!(Ok(16), interpret(&prog));
+
+        let error = lower_error("const SIZE: int = 15; static_assert(SIZE % 4 == 0, \"size must be aligned\"); fun main() -> int { return 0; }");
+        assert!(error.starts_with("error: static assertion failed: size must be aligned\n --> main.ll:1:37"), "{}", error);
+        let error = lower_error("static_assert(1 > 2); fun main() -> int { return 0; }");
+        assert!(error.starts_with("error: static assertion failed\n"), "{}", error);
+        let error = lower_error("fun f() -> bool { return true; } static_assert(f()); fun main() -> int { return 0; }");
+        assert!(error.starts_with("error: expression is not constant"), "{}", error);
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Display, Formatter};
 use std::hash::{Hash, Hasher};
 use std::ops::Index;
@@ -5,9 +6,11 @@ use std::ops::Index;
 use itertools::Itertools;
 
 use crate::front::{ast, error};
+use crate::front::const_eval::ConstEvalState;
 use crate::front::error::{Error, Result};
 use crate::front::lower::LRValue;
 use crate::front::scope::Scope;
+use crate::mid::ir::CallingConvention;
 use crate::front::type_solver::TypeVar;
 use crate::util::arena::{Arena, ArenaSet};
 
@@ -33,6 +36,9 @@ pub struct TypeStore<'a> {
     ty_bool: Type,
     ty_byte: Type,
     ty_int: Type,
+    ty_f32: Type,
+    ty_f64: Type,
+    ty_str: Type,
 }
 
 impl<'a> Debug for TypeStore<'a> {
@@ -54,7 +60,10 @@ impl<'a> Default for TypeStore<'a> {
         let ty_bool = types.push(TypeInfo::Bool);
         let ty_byte = types.push(TypeInfo::Byte);
         let ty_int = types.push(TypeInfo::Int);
-        Self { types, ty_wildcard, ty_void, ty_bool, ty_byte, ty_int }
+        let ty_f32 = types.push(TypeInfo::Float { bits: 32 });
+        let ty_f64 = types.push(TypeInfo::Float { bits: 64 });
+        let ty_str = types.push(TypeInfo::Str);
+        Self { types, ty_wildcard, ty_void, ty_bool, ty_byte, ty_int, ty_f32, ty_f64, ty_str }
     }
 }
 
@@ -75,6 +84,40 @@ impl<'a> TypeStore<'a> {
         self.ty_int
     }
 
+    pub fn type_str(&self) -> Type {
+        self.ty_str
+    }
+
+    pub fn type_float(&self, bits: u32) -> Type {
+        match bits {
+            32 => self.ty_f32,
+            64 => self.ty_f64,
+            _ => panic!("float with {} bits does not exist", bits),
+        }
+    }
+
+    /// Whether values of type `ty` contain a closure. Closures point to the local variables of the function that
+    /// created them, so they can only be stored in local variables and parameters, possibly inside tuples and arrays.
+    /// Other types never contain a closure.
+    pub fn contains_closure(&self, ty: Type) -> bool {
+        match &self[ty] {
+            TypeInfo::Closure(_) => true,
+            TypeInfo::Tuple(info) => info.fields.iter().any(|&field| self.contains_closure(field)),
+            TypeInfo::Array(info) => self.contains_closure(info.inner),
+            _ => false,
+        }
+    }
+
+    /// Check that values of type `ty`, written as `ty_ast`, can be stored in a place that may outlive the current
+    /// function, eg. behind a pointer, in a struct field or as a return value.
+    pub fn check_outlives_func(&self, ty_ast: &'a ast::Type, ty: Type) -> Result<'a, ()> {
+        if self.contains_closure(ty) {
+            Err(Error::EscapingClosure(ty_ast))
+        } else {
+            Ok(())
+        }
+    }
+
     pub fn new_placeholder(&mut self) -> Type {
         self.types.push(TypeInfo::Placeholder(self.types.len()))
     }
@@ -92,6 +135,21 @@ impl<'a> TypeStore<'a> {
         self.define_type(TypeInfo::Pointer(inner))
     }
 
+    /// The index and type of the field `name` of a `str` or slice type `ty`, which have the fields `ptr` and `len`.
+    pub fn fat_pointer_field(&mut self, ty: Type, name: &str) -> Option<(u32, Type)> {
+        let inner = match self[ty] {
+            TypeInfo::Str => self.ty_byte,
+            TypeInfo::Slice(inner) => inner,
+            _ => return None,
+        };
+
+        match name {
+            "ptr" => Some((0, self.define_type_ptr(inner))),
+            "len" => Some((1, self.ty_int)),
+            _ => None,
+        }
+    }
+
     pub fn format_type(&self, ty: Type) -> impl Display + '_ {
         struct Wrapped<'s> {
             store: &'s TypeStore<'s>,
@@ -113,17 +171,32 @@ impl<'a> TypeStore<'a> {
                     TypeInfo::Placeholder(i) => write!(f, "placeholder({})", i),
                     TypeInfo::Wildcard => write!(f, "_"),
                     TypeInfo::Void => write!(f, "void"),
+                    TypeInfo::Never => write!(f, "never"),
                     TypeInfo::Bool => write!(f, "bool"),
                     TypeInfo::Byte => write!(f, "byte"),
                     TypeInfo::Int => write!(f, "int"),
+                    TypeInfo::Float { bits } => write!(f, "f{}", bits),
+                    TypeInfo::Str => write!(f, "str"),
                     TypeInfo::Pointer(inner) => write!(f, "&{}", self.store.format_type(*inner)),
-                    TypeInfo::Tuple(info) => write_tuple(&self.store, f, &info.fields),
+                    TypeInfo::OptionalPointer(inner) => write!(f, "?&{}", self.store.format_type(*inner)),
+                    TypeInfo::Slice(inner) => write!(f, "&[{}]", self.store.format_type(*inner)),
+                    TypeInfo::Tuple(info) => write_tuple(self.store, f, &info.fields),
                     TypeInfo::Function(info) => {
-                        write_tuple(&self.store, f, &info.params)?;
+                        if info.conv != CallingConvention::default() { write!(f, "extern \"{}\" ", info.conv.name())?; }
+                        write_tuple(self.store, f, &info.params)?;
                         write!(f, " -> {}", self.store.format_type(info.ret))
                     }
+                    TypeInfo::Closure(info) => {
+                        write!(f, "|")?;
+                        for (i, &param_ty) in info.params.iter().enumerate() {
+                            if i > 0 { write!(f, ", ")?; }
+                            write!(f, "{}", self.store.format_type(param_ty))?;
+                        }
+                        write!(f, "| -> {}", self.store.format_type(info.ret))
+                    }
                     TypeInfo::Array(info) => write!(f, "[{}; {}]", self.store.format_type(info.inner), info.length),
                     TypeInfo::Struct(info) => write!(f, "{}", info.decl.id.string),
+                    TypeInfo::Enum(info) => write!(f, "{}", info.decl.id.string),
                 }
             }
         }
@@ -149,6 +222,20 @@ pub struct ItemStore<'a> {
     pub modules: Arena<Module, CollectedModule>,
     pub funcs: Arena<Function, FunctionDecl<'a>>,
     pub consts: Arena<Const, ConstDecl<'a>>,
+
+    /// The scopes containing the items associated with a type, eg. the variants of an enum.
+    pub type_scopes: HashMap<Type, Scope<'static, ScopedItem>>,
+
+    /// The functions declared within other functions. These are not part of any scope, instead they're declared when
+    /// the statement is encountered during type checking and lowering.
+    pub nested_funcs: HashMap<*const ast::Function, Function>,
+
+    /// For each nested function, the functions declared directly in the same block, including itself. These are in
+    /// scope within the body, so sibling functions can call each other regardless of their order.
+    pub nested_siblings: HashMap<*const ast::Function, Vec<Function>>,
+
+    /// The operator overloads, keyed by the operator and the type of the left operand.
+    pub operators: HashMap<(ast::BinaryOp, Type), Function>,
 }
 
 
@@ -163,6 +250,12 @@ pub struct CollectedModule {
 
     /// The set of functions defined in this module that need to have code generated
     pub codegen_funcs: Vec<Function>,
+
+    /// The constants defined in this module, their initializers are evaluated in the module scope
+    pub consts: Vec<Const>,
+
+    /// The names of the items that can be accessed from other modules, including `pub use` imports.
+    pub public_items: HashSet<String>,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -179,30 +272,118 @@ impl<'a> ItemStore<'a> {
         scope: &Scope<ScopedItem>,
         path: &'p ast::Path,
     ) -> Result<'p, ScopedItem> {
+        //the module the current scope belongs to, if it was reached through the path
+        let mut module = None;
+
         //real paths
         let scope = path.parents.iter().try_fold(scope, |scope, id| {
-            let &item = scope.find(Some(&self.root_scope), id)?;
-
-            if let ScopedItem::Module(module) = item {
-                let module = &self.modules[module];
-                let next_scope = match scope_kind {
-                    ScopeKind::Local => &module.local_scope,
-                    ScopeKind::Real => &module.scope,
-                };
-                Ok(next_scope)
-            } else {
-                Err(item.err_unexpected_kind(error::ItemType::Module, path))
+            let item = self.find_visible(scope, module, id)?;
+
+            match item {
+                ScopedItem::Module(next_module) => {
+                    module = Some(next_module);
+                    let next_module = &self.modules[next_module];
+                    let next_scope = match scope_kind {
+                        ScopeKind::Local => &next_module.local_scope,
+                        ScopeKind::Real => &next_module.scope,
+                    };
+                    Ok(next_scope)
+                }
+                ScopedItem::Type(ty) if self.type_scopes.contains_key(&ty) => {
+                    //items associated with a type are always public
+                    module = None;
+                    Ok(&self.type_scopes[&ty])
+                }
+                _ => Err(item.err_unexpected_kind(error::ItemType::Module, path)),
             }
         })?;
 
-        scope.find(Some(&self.root_scope), &path.id).map(|&v| v)
+        self.find_visible(scope, module, &path.id)
+    }
+
+    /// Find `id` in `scope`. If `scope` belongs to `module` the item must be public, since private items are only
+    /// visible from within their own module. Modules themselves are always visible.
+    fn find_visible<'p>(&self, scope: &Scope<ScopedItem>, module: Option<Module>, id: &'p ast::Identifier) -> Result<'p, ScopedItem> {
+        let &item = scope.find(Some(&self.root_scope), id)?;
+
+        if let Some(module) = module {
+            let is_module = matches!(item, ScopedItem::Module(_));
+            if !is_module && !self.modules[module].public_items.contains(&id.string) {
+                return Err(Error::PrivateItem(id));
+            }
+        }
+
+        Ok(item)
+    }
+
+    /// Match the arguments of a call to the parameters of the target and return the values in parameter order.
+    /// Named arguments are only allowed when the target is a function item, since only those have parameter names.
+    pub fn order_call_args<'p>(
+        &self,
+        scope: &Scope<ScopedItem>,
+        call: &'p ast::Expression,
+        target: &'p ast::Expression,
+        args: &'p [ast::CallArgument],
+    ) -> Result<'p, Vec<&'p ast::Expression>> {
+        if args.iter().all(|arg| arg.name.is_none()) {
+            return Ok(args.iter().map(|arg| &arg.value).collect());
+        }
+
+        let func = match &target.kind {
+            ast::ExpressionKind::Path(path) => match self.resolve_path(ScopeKind::Real, scope, path)? {
+                ScopedItem::Value(ScopedValue::Function(func)) => Some(func),
+                _ => None,
+            },
+            _ => None,
+        };
+        let func = func.ok_or(Error::NamedArgumentsNotAllowed(call))?;
+        let params = &self.funcs[func].ast.params;
+
+        let mut ordered = vec![None; params.len()];
+        let mut seen_named = false;
+
+        for (i, arg) in args.iter().enumerate() {
+            let index = match &arg.name {
+                None => {
+                    if seen_named {
+                        return Err(Error::PositionalArgumentAfterNamed(arg));
+                    }
+                    if i >= params.len() {
+                        return Err(Error::TooManyArguments(arg));
+                    }
+                    i
+                }
+                Some(name) => {
+                    seen_named = true;
+                    params.iter()
+                        .position(|param| matches!(&param.id, ast::MaybeIdentifier::Identifier(id) if id.string == name.string))
+                        .ok_or(Error::UnknownArgumentName(name))?
+                }
+            };
+
+            if ordered[index].replace(&arg.value).is_some() {
+                return Err(Error::ArgumentGivenTwice(arg));
+            }
+        }
+
+        ordered.into_iter().enumerate()
+            .map(|(index, arg)| arg.ok_or(Error::MissingArgument { call, index }))
+            .collect()
+    }
+
+    /// Resolve a given path to an enum variant, returns the enum type and the variant index.
+    pub fn resolve_enum_variant<'p>(&self, scope: &Scope<ScopedItem>, path: &'p ast::Path) -> Result<'p, (Type, u32)> {
+        match self.resolve_path(ScopeKind::Real, scope, path)? {
+            ScopedItem::Value(ScopedValue::EnumVariant { ty, variant }) => Ok((ty, variant)),
+            _ => Err(Error::ExpectEnumVariant(path)),
+        }
     }
 
     pub fn resolve_type(
         &self,
         scope_kind: ScopeKind,
         scope: &Scope<ScopedItem>,
-        types: &mut TypeStore,
+        types: &mut TypeStore<'a>,
         ty: &'a ast::Type,
     ) -> Result<'a, Type> {
         match &ty.kind {
@@ -211,6 +392,8 @@ impl<'a> ItemStore<'a> {
             ast::TypeKind::Bool => Ok(types.ty_bool),
             ast::TypeKind::Byte => Ok(types.ty_byte),
             ast::TypeKind::Int => Ok(types.ty_int),
+            &ast::TypeKind::Float { bits } => Ok(types.type_float(bits)),
+            ast::TypeKind::Str => Ok(types.ty_str),
             ast::TypeKind::Path(path) => {
                 let item = self.resolve_path(scope_kind, scope, path)?;
                 if let ScopedItem::Type(ty) = item {
@@ -219,10 +402,21 @@ impl<'a> ItemStore<'a> {
                     Err(item.err_unexpected_kind(error::ItemType::Type, path))
                 }
             }
-            ast::TypeKind::Ref(inner) => {
-                let inner = self.resolve_type(scope_kind, scope, types, &*inner)?;
+            ast::TypeKind::Ref(inner_ast) => {
+                let inner = self.resolve_type(scope_kind, scope, types, inner_ast)?;
+                types.check_outlives_func(inner_ast, inner)?;
                 Ok(types.types.push(TypeInfo::Pointer(inner)))
             }
+            ast::TypeKind::OptionalRef(inner_ast) => {
+                let inner = self.resolve_type(scope_kind, scope, types, inner_ast)?;
+                types.check_outlives_func(inner_ast, inner)?;
+                Ok(types.types.push(TypeInfo::OptionalPointer(inner)))
+            }
+            ast::TypeKind::Slice(inner_ast) => {
+                let inner = self.resolve_type(scope_kind, scope, types, inner_ast)?;
+                types.check_outlives_func(inner_ast, inner)?;
+                Ok(types.types.push(TypeInfo::Slice(inner)))
+            }
             ast::TypeKind::Tuple { fields } => {
                 let fields = fields.iter()
                     .map(|field| self.resolve_type(scope_kind, scope, types, field))
@@ -230,17 +424,28 @@ impl<'a> ItemStore<'a> {
 
                 Ok(types.types.push(TypeInfo::Tuple(TupleTypeInfo { fields })))
             }
-            ast::TypeKind::Func { params, ret } => {
+            ast::TypeKind::Func { params, ret: ret_ast } => {
                 let params = params.iter()
                     .map(|param| self.resolve_type(scope_kind, scope, types, param))
                     .try_collect()?;
-                let ret = self.resolve_type(scope_kind, scope, types, ret)?;
+                let ret = self.resolve_type(scope_kind, scope, types, ret_ast)?;
+                types.check_outlives_func(ret_ast, ret)?;
 
-                Ok(types.types.push(TypeInfo::Function(FunctionTypeInfo { params, ret })))
+                Ok(types.types.push(TypeInfo::Function(FunctionTypeInfo { params, ret, conv: CallingConvention::default() })))
+            }
+            ast::TypeKind::Closure { params, ret: ret_ast } => {
+                let params = params.iter()
+                    .map(|param| self.resolve_type(scope_kind, scope, types, param))
+                    .try_collect()?;
+                let ret = self.resolve_type(scope_kind, scope, types, ret_ast)?;
+                types.check_outlives_func(ret_ast, ret)?;
+
+                Ok(types.types.push(TypeInfo::Closure(FunctionTypeInfo { params, ret, conv: CallingConvention::default() })))
             }
             ast::TypeKind::Array { inner, length } => {
                 let inner = self.resolve_type(scope_kind, scope, types, inner)?;
-                Ok(types.types.push(TypeInfo::Array(ArrayTypeInfo { inner, length: *length })))
+                let length = ConstEvalState::new(self).eval_array_length(types, scope, length)?;
+                Ok(types.types.push(TypeInfo::Array(ArrayTypeInfo { inner, length })))
             }
         }
     }
@@ -263,6 +468,8 @@ pub enum ScopedValue {
     Const(Const),
     Immediate(LRValue),
     TypeVar(TypeVar),
+    /// The variant with index `variant` of the enum type `ty`.
+    EnumVariant { ty: Type, variant: u32 },
 }
 
 impl ScopedItem {
@@ -290,20 +497,39 @@ pub enum TypeInfo<'ast, T> {
     Wildcard,
 
     Void,
+    /// The type of expressions that never produce a value, eg. `return`. Matches any other type.
+    Never,
     Bool,
     Byte,
     Int,
+    Float { bits: u32 },
+    /// A string, represented as `(&byte, int)`: a pointer to the bytes and the length.
+    Str,
 
     Pointer(T),
+    /// A pointer that can be null, see `ast::TypeKind::OptionalRef`.
+    OptionalPointer(T),
+    /// A pointer to a number of values together with that number, represented as `(&T, int)`.
+    Slice(T),
 
     Tuple(TupleTypeInfo<T>),
     Function(FunctionTypeInfo<T>),
+    /// A function together with the environment containing its captured variables.
+    Closure(FunctionTypeInfo<T>),
     Array(ArrayTypeInfo<T>),
 
     Struct(StructTypeInfo<'ast>),
+    Enum(EnumTypeInfo<'ast>),
 }
 
 impl<'ast, T: Copy> TypeInfo<'ast, T> {
+    pub fn unwrap_slice(&self) -> Option<T> {
+        match self {
+            TypeInfo::Slice(inner) => Some(*inner),
+            _ => None,
+        }
+    }
+
     pub fn unwrap_ptr(&self) -> Option<T> {
         match self {
             TypeInfo::Pointer(inner) => Some(*inner),
@@ -319,6 +545,45 @@ impl<'ast, T> TypeInfo<'ast, T> {
             _ => None,
         }
     }
+
+    pub fn unwrap_closure(&self) -> Option<&FunctionTypeInfo<T>> {
+        match self {
+            TypeInfo::Closure(inner) => Some(inner),
+            _ => None,
+        }
+    }
+
+    pub fn unwrap_tuple(&self) -> Option<&TupleTypeInfo<T>> {
+        match self {
+            TypeInfo::Tuple(inner) => Some(inner),
+            _ => None,
+        }
+    }
+
+    pub fn unwrap_struct(&self) -> Option<&StructTypeInfo<'ast>> {
+        match self {
+            TypeInfo::Struct(inner) => Some(inner),
+            _ => None,
+        }
+    }
+
+    pub fn unwrap_enum(&self) -> Option<&EnumTypeInfo<'ast>> {
+        match self {
+            TypeInfo::Enum(inner) => Some(inner),
+            _ => None,
+        }
+    }
+
+    /// Whether a value of this type can be cast to `target` with `as`.
+    pub fn is_castable_to(&self, target: &TypeInfo<'ast, T>) -> bool {
+        matches!(
+            (self, target),
+            (TypeInfo::Pointer(_) | TypeInfo::OptionalPointer(_), TypeInfo::Pointer(_) | TypeInfo::OptionalPointer(_)) |
+            (TypeInfo::Pointer(_) | TypeInfo::OptionalPointer(_), TypeInfo::Int) |
+            (TypeInfo::Int, TypeInfo::Pointer(_) | TypeInfo::OptionalPointer(_)) |
+            (TypeInfo::Bool | TypeInfo::Byte | TypeInfo::Int, TypeInfo::Byte | TypeInfo::Int)
+        )
+    }
 }
 
 impl<'ast, T> TypeInfo<'ast, T> {
@@ -328,22 +593,34 @@ impl<'ast, T> TypeInfo<'ast, T> {
             TypeInfo::Placeholder(_) => unreachable!(),
             TypeInfo::Wildcard => TypeInfo::Wildcard,
             TypeInfo::Void => TypeInfo::Void,
+            TypeInfo::Never => TypeInfo::Never,
             TypeInfo::Bool => TypeInfo::Bool,
             TypeInfo::Byte => TypeInfo::Byte,
             TypeInfo::Int => TypeInfo::Int,
+            &TypeInfo::Float { bits } => TypeInfo::Float { bits },
+            TypeInfo::Str => TypeInfo::Str,
             TypeInfo::Pointer(inner) => TypeInfo::Pointer(f(inner)),
+            TypeInfo::OptionalPointer(inner) => TypeInfo::OptionalPointer(f(inner)),
+            TypeInfo::Slice(inner) => TypeInfo::Slice(f(inner)),
             TypeInfo::Tuple(info) => TypeInfo::Tuple(TupleTypeInfo {
                 fields: info.fields.iter().map(f).collect()
             }),
             TypeInfo::Function(info) => TypeInfo::Function(FunctionTypeInfo {
                 ret: f(&info.ret),
                 params: info.params.iter().map(f).collect(),
+                conv: info.conv,
+            }),
+            TypeInfo::Closure(info) => TypeInfo::Closure(FunctionTypeInfo {
+                ret: f(&info.ret),
+                params: info.params.iter().map(f).collect(),
+                conv: info.conv,
             }),
             TypeInfo::Array(info) => TypeInfo::Array(ArrayTypeInfo {
                 inner: f(&info.inner),
                 length: info.length,
             }),
             TypeInfo::Struct(info) => TypeInfo::Struct(info.clone()),
+            TypeInfo::Enum(info) => TypeInfo::Enum(info.clone()),
         }
     }
 }
@@ -357,6 +634,7 @@ pub struct TupleTypeInfo<T> {
 pub struct FunctionTypeInfo<T> {
     pub params: Vec<T>,
     pub ret: T,
+    pub conv: CallingConvention,
 }
 
 #[derive(Debug, Eq, PartialEq, Hash, Clone)]
@@ -369,6 +647,8 @@ pub struct ArrayTypeInfo<T> {
 pub struct StructTypeInfo<'ast> {
     pub decl: &'ast ast::Struct,
     pub fields: Vec<StructFieldInfo<'ast>>,
+    /// Set by `@packed`, the fields are laid out without any padding.
+    pub packed: bool,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -399,13 +679,51 @@ impl<'ast> PartialEq for StructTypeInfo<'ast> {
 
 impl<'ast> Eq for StructTypeInfo<'ast> {}
 
+#[derive(Debug, Clone)]
+pub struct EnumTypeInfo<'ast> {
+    pub decl: &'ast ast::Enum,
+    pub variants: Vec<EnumVariantInfo<'ast>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct EnumVariantInfo<'ast> {
+    pub id: &'ast str,
+    pub fields: Vec<Type>,
+}
+
+impl<'ast> Hash for EnumTypeInfo<'ast> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::ptr::hash(self.decl, state)
+    }
+}
+
+impl<'ast> PartialEq for EnumTypeInfo<'ast> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.decl, other.decl)
+    }
+}
+
+impl<'ast> Eq for EnumTypeInfo<'ast> {}
+
 #[derive(Debug)]
 pub struct FunctionDecl<'ast> {
+    /// The name used in the generated code, nested functions are prefixed with the name of the enclosing function.
+    pub name: String,
     pub ty: Type,
     pub func_ty: FunctionTypeInfo<Type>,
+    pub attrs: FunctionAttributes,
     pub ast: &'ast ast::Function,
 }
 
+/// The recognized attributes of a function.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct FunctionAttributes {
+    /// `@inline`: the function should be inlined into its callers.
+    pub inline: bool,
+    /// `@noreturn`: calls to the function never return.
+    pub noreturn: bool,
+}
+
 #[derive(Debug)]
 pub struct ConstDecl<'ast> {
     pub ty: Type,
@@ -0,0 +1,477 @@
+use std::fmt::Write;
+
+use crate::front::ast;
+use crate::front::error::{Diagnostic, DiagnosticKind, Error, ItemType, Severity, Warning};
+use crate::front::parser::ParseError;
+use crate::front::pos::{Files, Span};
+
+/// A message for the user together with the places in the source code it's about.
+#[derive(Debug)]
+pub struct Report {
+    pub severity: Severity,
+    pub message: String,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+/// A span in the source code that's underlined in a report, primary labels point at the actual problem and
+/// secondary labels add context.
+#[derive(Debug)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+    pub primary: bool,
+}
+
+impl Report {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Report { severity, message: message.into(), labels: vec![], notes: vec![] }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Report::new(Severity::Error, message)
+    }
+
+    pub fn primary(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label { span, message: message.into(), primary: true });
+        self
+    }
+
+    pub fn secondary(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label { span, message: message.into(), primary: false });
+        self
+    }
+
+    pub fn note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+}
+
+impl From<&ParseError> for Report {
+    fn from(error: &ParseError) -> Self {
+        match error {
+            ParseError::Char { pos, char } =>
+                Report::error(format!("unexpected character {:?}", char))
+                    .primary(Span::empty_at(*pos), ""),
+            ParseError::Token { pos, ty, description, allowed } => {
+                let allowed = allowed.iter().map(|ty| format!("{:?}", ty)).collect::<Vec<_>>();
+                Report::error(format!("unexpected token {:?}, expected {}", ty, description))
+                    .primary(Span::empty_at(*pos), format!("expected {}", description))
+                    .note(format!("expected one of {}", allowed.join(", ")))
+            }
+            ParseError::Eof { after, expected } =>
+                Report::error(format!("unexpected end of file, expected {:?}", expected))
+                    .primary(Span::empty_at(*after), "unterminated starting here"),
+            ParseError::Escape { pos, sequence } =>
+                Report::error(format!("invalid escape sequence {:?}", sequence))
+                    .primary(Span::empty_at(*pos), ""),
+            ParseError::IntLit { span, lit, bits } =>
+                Report::error(format!("integer literal {} doesn't fit in {} bits", lit, bits))
+                    .primary(*span, ""),
+        }
+    }
+}
+
+impl From<&Error<'_>> for Report {
+    fn from(error: &Error) -> Self {
+        match error {
+            //types
+            Error::TypeMismatch { span, expected, actual, expected_origin } => {
+                let report = Report::error(format!("type mismatch: expected `{}`, found `{}`", expected, actual))
+                    .primary(*span, format!("expected `{}`, found `{}`", expected, actual));
+                match expected_origin {
+                    Some(origin) => report.secondary(origin.span(), origin.note()),
+                    None => report,
+                }
+            }
+            Error::ExpectIntegerType { expression, actual } =>
+                expected_type(expression, "an integer", actual),
+            Error::ExpectFloatType { expression, actual } =>
+                expected_type(expression, "a float", actual),
+            Error::ExpectPointerType { expression, actual } =>
+                expected_type(expression, "a pointer", actual),
+            Error::ExpectOptionalPointerType { expression, actual } =>
+                expected_type(expression, "an optional pointer", actual),
+            Error::ExpectStructOrTupleType { expression, actual } =>
+                expected_type(expression, "a struct or tuple", actual),
+            Error::InvalidCastTarget { expression, target } =>
+                Report::error(format!("cannot cast to `{}`", target))
+                    .primary(expression.span, ""),
+            Error::InvalidCast { expression, source, target } =>
+                Report::error(format!("cannot cast `{}` to `{}`", source, target))
+                    .primary(expression.span, ""),
+            Error::InvalidOperandType { expression, ty } => {
+                let symbol = match &expression.kind {
+                    ast::ExpressionKind::Binary { kind, .. } => binary_op_symbol(*kind),
+                    kind => panic!("expected binary expression, got {:?}", kind),
+                };
+                Report::error(format!("operator `{}` can't be applied to type `{}`", symbol, ty))
+                    .primary(expression.span, "")
+            }
+            Error::InfiniteType { span, ty } =>
+                Report::error(format!("infinite type: `_` would have to be `{}`, which contains itself", ty))
+                    .primary(*span, ""),
+            Error::InfiniteSizeType(id) =>
+                Report::error(format!("recursive type `{}` has infinite size", id.string))
+                    .primary(id.span, "")
+                    .note("the cycle can be broken with a pointer"),
+
+            //dot indexing
+            Error::WrongDotIndexType { target, target_type, index } => {
+                let (span, index) = match index {
+                    ast::DotIndexIndex::Tuple { span, index } => (*span, index.to_string()),
+                    ast::DotIndexIndex::Struct(id) => (id.span, id.string.clone()),
+                };
+                Report::error(format!("cannot index type `{}` with `.{}`", target_type, index))
+                    .primary(span, "")
+                    .secondary(target.span, format!("this has type `{}`", target_type))
+            }
+            Error::StructFieldNotFound { target, target_type, index } =>
+                Report::error(format!("no field `{}` on type `{}`", index.string, target_type))
+                    .primary(index.span, "unknown field")
+                    .secondary(target.span, format!("this has type `{}`", target_type)),
+            Error::NotIndexable { target, target_type } =>
+                Report::error(format!("cannot index type `{}`", target_type))
+                    .primary(target.span, format!("this has type `{}`", target_type)),
+            Error::NotIterable { target, target_type } =>
+                Report::error(format!("cannot iterate over type `{}`", target_type))
+                    .primary(target.span, format!("this has type `{}`", target_type)),
+
+            //struct literals
+            Error::StructLitFieldGivenTwice(field) =>
+                Report::error(format!("field `{}` given twice", field.id.string))
+                    .primary(field.span, ""),
+            Error::StructLitMissingField { expression, field } =>
+                Report::error(format!("missing field `{}` in struct literal", field))
+                    .primary(expression.span, ""),
+
+            //destructuring
+            Error::PatternExpectStructType { path, actual } =>
+                Report::error(format!("expected a struct type in pattern, found `{}`", actual))
+                    .primary(path.span, ""),
+            Error::PatternFieldNotFound { target_type, field } =>
+                Report::error(format!("no field `{}` on type `{}`", field.field.string, target_type))
+                    .primary(field.field.span, "unknown field"),
+
+            //enums
+            Error::ExpectEnumVariant(path) =>
+                Report::error("expected an enum variant")
+                    .primary(path.span, ""),
+            Error::WrongVariantFieldCount { path, expected, actual } =>
+                Report::error(format!("enum variant has {} fields, but the pattern has {}", expected, actual))
+                    .primary(path.span, ""),
+            Error::EnumVariantNotCalled(path) =>
+                Report::error("enum variant with fields must be called with its values")
+                    .primary(path.span, ""),
+            Error::NonExhaustiveMatch { statement, missing } =>
+                Report::error(format!("non-exhaustive match, missing {}", missing.join(", ")))
+                    .primary(statement.span, ""),
+
+            //constants
+            Error::ConstCycle(cst) =>
+                Report::error(format!("cycle while evaluating constant `{}`", cst.id.string))
+                    .primary(cst.span, ""),
+            Error::NotConstant(expr) =>
+                Report::error("expression is not constant")
+                    .primary(expr.span, ""),
+            Error::ConstDivisionByZero(expr) =>
+                Report::error("division by zero in constant expression")
+                    .primary(expr.span, ""),
+            Error::NegativeArrayLength(expr) =>
+                Report::error("array length is negative")
+                    .primary(expr.span, ""),
+            Error::StaticAssertFailed(assert) => {
+                let message = match &assert.message {
+                    Some(message) => format!("static assertion failed: {}", message),
+                    None => "static assertion failed".to_owned(),
+                };
+                Report::error(message).primary(assert.cond.span, "")
+            }
+
+            //literals
+            Error::InvalidLiteral { span, lit, ty } =>
+                Report::error(format!("invalid literal {} for type `{}`", lit, ty))
+                    .primary(*span, ""),
+
+            //lrvalue
+            Error::ExpectedLValue(expr) =>
+                Report::error("expected an assignable expression")
+                    .primary(expr.span, ""),
+            Error::ReferenceOfRValue(expr) =>
+                Report::error("cannot take a reference to a temporary value")
+                    .primary(expr.span, ""),
+
+            //identifier
+            Error::UndeclaredIdentifier(id) =>
+                Report::error(format!("undeclared identifier `{}`", id.string))
+                    .primary(id.span, ""),
+            Error::IdentifierDeclaredTwice(id) =>
+                Report::error(format!("identifier `{}` declared twice", id.string))
+                    .primary(id.span, ""),
+            Error::PrivateItem(id) =>
+                Report::error(format!("`{}` is private", id.string))
+                    .primary(id.span, ""),
+            Error::GlobImportConflict { use_decl, id } =>
+                Report::error(format!("`{}` is imported by multiple glob imports", id))
+                    .primary(use_decl.span, ""),
+
+            //main
+            Error::NoMainModule => Report::error("no main module"),
+            Error::NoMainFunction => Report::error("no main function"),
+            Error::MainWrongItem => Report::error("main should be a function"),
+            Error::MainFunctionWrongType { expected, actual } =>
+                Report::error(format!("main function has type `{}`, expected `{}`", actual, expected)),
+            Error::MainFunctionMustHaveBody => Report::error("main function must have a body"),
+
+            //functions
+            Error::MissingReturn(id) =>
+                Report::error(format!("missing return in function `{}`", id.string))
+                    .primary(id.span, ""),
+            Error::MissingClosureReturn(expr) =>
+                Report::error("missing return in closure")
+                    .primary(expr.span, ""),
+            Error::EscapingClosure(ty) =>
+                Report::error("closures can't outlive the function that created them")
+                    .primary(ty.span, "this type contains a closure")
+                    .note("closures can only be stored in local variables and parameters"),
+            Error::MissingFunctionBody(func) =>
+                Report::error(format!("function `{}` must have a body", func.id.string))
+                    .primary(func.id.span, ""),
+            Error::ExternTypeConflict { first, first_ty, second, second_ty } =>
+                Report::error(format!("extern function `{}` declared with conflicting types", second.id.string))
+                    .primary(second.id.span, format!("declared here as `{}`", second_ty))
+                    .secondary(first.id.span, format!("previously declared as `{}`", first_ty)),
+            Error::InvalidOperatorFunction(func) =>
+                Report::error(format!("invalid operator function `{}`", func.id.string))
+                    .primary(func.id.span, ""),
+            Error::UnknownCallingConvention(conv) =>
+                Report::error(format!("unknown calling convention {:?}", conv.name))
+                    .primary(conv.span, ""),
+            Error::OperatorDeclaredTwice(func) =>
+                Report::error(format!("operator `{}` declared twice for these types", func.id.string))
+                    .primary(func.id.span, ""),
+
+            //calls
+            Error::NamedArgumentsNotAllowed(expr) =>
+                Report::error("named arguments are only allowed when calling a function directly")
+                    .primary(expr.span, ""),
+            Error::PositionalArgumentAfterNamed(arg) =>
+                Report::error("positional argument after named argument")
+                    .primary(arg.span, ""),
+            Error::UnknownArgumentName(id) =>
+                Report::error(format!("unknown argument `{}`", id.string))
+                    .primary(id.span, ""),
+            Error::ArgumentGivenTwice(arg) =>
+                Report::error("argument given twice")
+                    .primary(arg.span, ""),
+            Error::TooManyArguments(arg) =>
+                Report::error("too many arguments")
+                    .primary(arg.span, ""),
+            Error::MissingArgument { call, index } =>
+                Report::error(format!("missing argument for parameter {}", index))
+                    .primary(call.span, ""),
+
+            //attributes
+            Error::UnknownAttribute(attr) =>
+                Report::error(format!("unknown attribute `@{}`", attr.id.string))
+                    .primary(attr.span, ""),
+            Error::AttributeGivenTwice(attr) =>
+                Report::error(format!("attribute `@{}` given twice", attr.id.string))
+                    .primary(attr.span, ""),
+            Error::UnexpectedAttributeArguments(attr) =>
+                Report::error(format!("attribute `@{}` doesn't take arguments", attr.id.string))
+                    .primary(attr.span, ""),
+
+            //inline asm
+            Error::UnknownAsmRegister(operand) =>
+                Report::error(format!("unknown register `{}`", operand.constraint))
+                    .primary(operand.span, ""),
+            Error::InvalidAsmOperandType { operand, ty } =>
+                Report::error(format!("type `{}` can't be used as an asm operand", ty))
+                    .primary(operand.span, ""),
+
+            //other
+            Error::NotInLoop { expr } =>
+                Report::error("`break` or `continue` outside of a loop")
+                    .primary(expr.span, ""),
+            Error::UnexpectedItemType { expected, actual, path } =>
+                Report::error(format!("expected {}, found {}", item_type_name(expected), item_type_name(actual)))
+                    .primary(path.span, ""),
+        }
+    }
+}
+
+impl From<&Diagnostic<'_>> for Report {
+    fn from(diagnostic: &Diagnostic) -> Self {
+        match &diagnostic.kind {
+            DiagnosticKind::Warning(warning) => match warning {
+                Warning::UnusedVariable(id) =>
+                    Report::new(diagnostic.severity, format!("unused variable `{}`", id.string))
+                        .primary(id.span, ""),
+                Warning::UnusedParameter(id) =>
+                    Report::new(diagnostic.severity, format!("unused parameter `{}`", id.string))
+                        .primary(id.span, ""),
+                Warning::UnreachableCode { span, after } =>
+                    Report::new(diagnostic.severity, "unreachable code")
+                        .primary(*span, "")
+                        .secondary(after.span, "any code following this expression is unreachable"),
+                Warning::TypeHole { ty, inferred } =>
+                    Report::new(diagnostic.severity, format!("type hole: inferred `{}`", inferred))
+                        .primary(ty.span, format!("this is `{}`", inferred)),
+            },
+            DiagnosticKind::Note(note) => Report::new(diagnostic.severity, note.as_str()),
+        }
+    }
+}
+
+fn binary_op_symbol(kind: ast::BinaryOp) -> &'static str {
+    match kind {
+        ast::BinaryOp::Add => "+",
+        ast::BinaryOp::Sub => "-",
+        ast::BinaryOp::Mul => "*",
+        ast::BinaryOp::Div => "/",
+        ast::BinaryOp::Mod => "%",
+        ast::BinaryOp::Eq => "==",
+        ast::BinaryOp::Neq => "!=",
+        ast::BinaryOp::Gte => ">=",
+        ast::BinaryOp::Gt => ">",
+        ast::BinaryOp::Lte => "<=",
+        ast::BinaryOp::Lt => "<",
+    }
+}
+
+fn expected_type(expr: &ast::Expression, expected: &str, actual: &str) -> Report {
+    Report::error(format!("expected {} type, found `{}`", expected, actual))
+        .primary(expr.span, format!("this has type `{}`", actual))
+}
+
+fn item_type_name(ty: &ItemType) -> &'static str {
+    match ty {
+        ItemType::Module => "a module",
+        ItemType::Type => "a type",
+        ItemType::Value => "a value",
+    }
+}
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const CYAN: &str = "\x1b[1;36m";
+const BLUE: &str = "\x1b[1;34m";
+
+/// Renders reports as text, showing the source line of each label with the span underlined.
+pub struct Renderer<'f> {
+    pub files: &'f Files,
+    /// Whether to use ANSI escape codes for colors.
+    pub color: bool,
+}
+
+impl Renderer<'_> {
+    fn paint(&self, color: &str, text: &str) -> String {
+        if self.color && !text.is_empty() {
+            format!("{}{}{}", color, text, RESET)
+        } else {
+            text.to_owned()
+        }
+    }
+
+    pub fn render(&self, report: &Report) -> String {
+        let (severity_name, severity_color) = match report.severity {
+            Severity::Error => ("error", RED),
+            Severity::Warning => ("warning", YELLOW),
+            Severity::Note => ("note", CYAN),
+        };
+
+        let mut result = String::new();
+        let message = self.paint(BOLD, &format!(": {}", report.message));
+        writeln!(&mut result, "{}{}", self.paint(severity_color, severity_name), message).unwrap();
+
+        //all line numbers are right aligned in the same gutter
+        let width = report.labels.iter()
+            .map(|label| label.span.start.line.to_string().len())
+            .max().unwrap_or(0);
+        let gutter = self.paint(BLUE, &format!("{} |", " ".repeat(width)));
+
+        let mut prev_file = None;
+        for (i, label) in report.labels.iter().enumerate() {
+            let start = label.span.start;
+            let file = &self.files[start.file];
+
+            if prev_file != Some(start.file) {
+                let arrow = if i == 0 { "-->" } else { ":::" };
+                let location = format!("{}:{}:{}", file.path.display(), start.line, start.col);
+                writeln!(&mut result, "{}{} {}", " ".repeat(width), self.paint(BLUE, arrow), location).unwrap();
+                prev_file = Some(start.file);
+            }
+
+            //only the first line of multi-line spans is shown
+            let line = file.src.lines().nth(start.line - 1).unwrap_or("");
+            let end_col = if label.span.end.line == start.line { label.span.end.col } else { line.len() + 1 };
+            let offset = char_count(line, start.col - 1);
+            let len = (char_count(line, end_col - 1).saturating_sub(offset)).max(1);
+
+            let (marker, color) = if label.primary { ("^", severity_color) } else { ("-", BLUE) };
+            let underline = format!("{}{}", marker.repeat(len), if label.message.is_empty() { "" } else { " " });
+
+            writeln!(&mut result, "{}", gutter).unwrap();
+            let line_number = self.paint(BLUE, &format!("{:>width$} |", start.line, width = width));
+            writeln!(&mut result, "{} {}", line_number, line).unwrap();
+            let underline = self.paint(color, &format!("{}{}", underline, label.message));
+            writeln!(&mut result, "{} {}{}", gutter, " ".repeat(offset), underline).unwrap();
+        }
+
+        for note in &report.notes {
+            writeln!(&mut result, "{}{} note: {}", " ".repeat(width), self.paint(BLUE, " ="), note).unwrap();
+        }
+
+        result
+    }
+}
+
+/// The number of characters in the first `bytes` bytes of `line`, positions in spans count bytes.
+fn char_count(line: &str, bytes: usize) -> usize {
+    let bytes = bytes.min(line.len());
+    match line.get(..bytes) {
+        Some(prefix) => prefix.chars().count(),
+        None => bytes,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use crate::front::pos::{Pos, Span};
+
+    use super::*;
+
+    #[test]
+    fn render_labels() {
+        let mut files = Files::default();
+        let src = "fun f() -> int {\n    return b;\n}\n";
+        let file = files.add(PathBuf::from("main.ll"), src.to_owned());
+
+        let span = |line, start, end| Span::new(Pos { file, line, col: start }, Pos { file, line, col: end });
+        let report = Report::error("type mismatch")
+            .primary(span(2, 12, 13), "found `bool`")
+            .secondary(span(1, 12, 15), "expected because of this")
+            .note("a note");
+
+        let rendered = Renderer { files: &files, color: false }.render(&report);
+        let expected = [
+            "error: type mismatch",
+            " --> main.ll:2:12",
+            "  |",
+            "2 |     return b;",
+            "  |            ^ found `bool`",
+            "  |",
+            "1 | fun f() -> int {",
+            "  |            --- expected because of this",
+            "  = note: a note",
+            "",
+        ];
+        assert_eq!(expected.join("\n"), rendered);
+    }
+}
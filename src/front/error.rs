@@ -1,3 +1,5 @@
+use std::collections::{HashMap, HashSet};
+
 use crate::front::ast;
 use crate::front::pos::Span;
 
@@ -8,22 +10,54 @@ type TypeString = String;
 pub enum Error<'a> {
     //types
     TypeMismatch {
-        expression: &'a ast::Expression,
+        span: Span,
         expected: TypeString,
         actual: TypeString,
+        /// Where the expected type comes from, if that's somewhere else than `span`.
+        expected_origin: Option<TypeOrigin<'a>>,
     },
     ExpectIntegerType {
         expression: &'a ast::Expression,
         actual: TypeString,
     },
+    ExpectFloatType {
+        expression: &'a ast::Expression,
+        actual: TypeString,
+    },
     ExpectPointerType {
         expression: &'a ast::Expression,
         actual: TypeString,
     },
+    ExpectOptionalPointerType {
+        expression: &'a ast::Expression,
+        actual: TypeString,
+    },
     ExpectStructOrTupleType {
         expression: &'a ast::Expression,
         actual: TypeString,
     },
+    InvalidCastTarget {
+        expression: &'a ast::Expression,
+        target: TypeString,
+    },
+    InvalidCast {
+        expression: &'a ast::Expression,
+        source: TypeString,
+        target: TypeString,
+    },
+    /// A builtin binary operator applied to a type it doesn't support, and that has no matching overload.
+    InvalidOperandType {
+        expression: &'a ast::Expression,
+        ty: TypeString,
+    },
+    /// Inference requires a type that contains itself, eg. a pointer that points to itself.
+    InfiniteType {
+        span: Span,
+        ty: TypeString,
+    },
+    /// A recursive struct or enum that contains itself by value and would have an infinite size,
+    /// the cycle can be broken with a pointer.
+    InfiniteSizeType(&'a ast::Identifier),
 
     //dot indexing
     WrongDotIndexType {
@@ -36,6 +70,53 @@ pub enum Error<'a> {
         target_type: TypeString,
         index: &'a ast::Identifier,
     },
+    /// Array indexing or slicing a value that isn't an array, slice or pointer.
+    NotIndexable {
+        target: &'a ast::Expression,
+        target_type: TypeString,
+    },
+    /// A for loop over a value that isn't an array or slice.
+    NotIterable {
+        target: &'a ast::Expression,
+        target_type: TypeString,
+    },
+
+    //struct literals
+    StructLitFieldGivenTwice(&'a ast::StructLitField),
+    StructLitMissingField {
+        expression: &'a ast::Expression,
+        field: String,
+    },
+
+    //destructuring
+    PatternExpectStructType {
+        path: &'a ast::Path,
+        actual: TypeString,
+    },
+    PatternFieldNotFound {
+        target_type: TypeString,
+        field: &'a ast::StructPatternField,
+    },
+
+    //enums
+    ExpectEnumVariant(&'a ast::Path),
+    WrongVariantFieldCount {
+        path: &'a ast::Path,
+        expected: usize,
+        actual: usize,
+    },
+    EnumVariantNotCalled(&'a ast::Path),
+    NonExhaustiveMatch {
+        statement: &'a ast::MatchStatement,
+        missing: Vec<String>,
+    },
+
+    //constants
+    ConstCycle(&'a ast::Const),
+    NotConstant(&'a ast::Expression),
+    ConstDivisionByZero(&'a ast::Expression),
+    NegativeArrayLength(&'a ast::Expression),
+    StaticAssertFailed(&'a ast::StaticAssert),
 
     //literals
     InvalidLiteral {
@@ -51,6 +132,11 @@ pub enum Error<'a> {
     //identifier
     UndeclaredIdentifier(&'a ast::Identifier),
     IdentifierDeclaredTwice(&'a ast::Identifier),
+    PrivateItem(&'a ast::Identifier),
+    GlobImportConflict {
+        use_decl: &'a ast::UseDecl,
+        id: String,
+    },
 
     //main
     NoMainModule,
@@ -64,7 +150,43 @@ pub enum Error<'a> {
 
     //functions
     MissingReturn(&'a ast::Identifier),
+    MissingClosureReturn(&'a ast::Expression),
+    /// A type containing a closure in a place that can outlive the function that created the closure.
+    EscapingClosure(&'a ast::Type),
     MissingFunctionBody(&'a ast::Function),
+    InvalidOperatorFunction(&'a ast::Function),
+    UnknownCallingConvention(&'a ast::CallConv),
+    OperatorDeclaredTwice(&'a ast::Function),
+    /// Two extern functions with the same name but different types.
+    ExternTypeConflict {
+        first: &'a ast::Function,
+        first_ty: TypeString,
+        second: &'a ast::Function,
+        second_ty: TypeString,
+    },
+
+    //calls
+    NamedArgumentsNotAllowed(&'a ast::Expression),
+    PositionalArgumentAfterNamed(&'a ast::CallArgument),
+    UnknownArgumentName(&'a ast::Identifier),
+    ArgumentGivenTwice(&'a ast::CallArgument),
+    TooManyArguments(&'a ast::CallArgument),
+    MissingArgument {
+        call: &'a ast::Expression,
+        index: usize,
+    },
+
+    //attributes
+    UnknownAttribute(&'a ast::Attribute),
+    AttributeGivenTwice(&'a ast::Attribute),
+    UnexpectedAttributeArguments(&'a ast::Attribute),
+
+    //inline asm
+    UnknownAsmRegister(&'a ast::AsmOperand),
+    InvalidAsmOperandType {
+        operand: &'a ast::AsmOperand,
+        ty: TypeString,
+    },
 
     //other
     NotInLoop {
@@ -78,10 +200,216 @@ pub enum Error<'a> {
     },
 }
 
+/// Problems that don't stop compilation but are probably mistakes.
+#[derive(Debug)]
+pub enum Warning<'a> {
+    UnusedVariable(&'a ast::Identifier),
+    UnusedParameter(&'a ast::Identifier),
+    /// Statements that can never run because they follow the diverging expression `after` in the same block.
+    UnreachableCode {
+        span: Span,
+        after: &'a ast::Expression,
+    },
+    /// A `_` in an explicit type, used to show the type that was inferred for it.
+    TypeHole {
+        ty: &'a ast::Type,
+        inferred: TypeString,
+    },
+}
+
+/// The kind of a `Warning`, used to configure its level.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum WarningKind {
+    UnusedVariable,
+    UnusedParameter,
+    UnreachableCode,
+    TypeHole,
+}
+
+impl Warning<'_> {
+    pub fn kind(&self) -> WarningKind {
+        match self {
+            Warning::UnusedVariable(_) => WarningKind::UnusedVariable,
+            Warning::UnusedParameter(_) => WarningKind::UnusedParameter,
+            Warning::UnreachableCode { .. } => WarningKind::UnreachableCode,
+            Warning::TypeHole { .. } => WarningKind::TypeHole,
+        }
+    }
+}
+
+impl WarningKind {
+    pub const ALL: &'static [WarningKind] = &[
+        WarningKind::UnusedVariable, WarningKind::UnusedParameter, WarningKind::UnreachableCode, WarningKind::TypeHole,
+    ];
+
+    /// The name used for this kind on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            WarningKind::UnusedVariable => "unused-variable",
+            WarningKind::UnusedParameter => "unused-parameter",
+            WarningKind::UnreachableCode => "unreachable-code",
+            WarningKind::TypeHole => "type-hole",
+        }
+    }
+}
+
+impl std::str::FromStr for WarningKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        WarningKind::ALL.iter().copied()
+            .find(|kind| kind.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = WarningKind::ALL.iter().map(|kind| kind.name()).collect();
+                format!("unknown warning '{}', expected one of {}", s, names.join(", "))
+            })
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+/// A message for the user together with its severity, diagnostics with `Severity::Error` stop compilation.
+#[derive(Debug)]
+pub struct Diagnostic<'a> {
+    pub severity: Severity,
+    pub kind: DiagnosticKind<'a>,
+}
+
+#[derive(Debug)]
+pub enum DiagnosticKind<'a> {
+    Warning(Warning<'a>),
+    /// Extra information about the previous diagnostic.
+    Note(String),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WarningLevel {
+    Allow,
+    Warn,
+    Deny,
+}
+
+/// The level of each kind of warning, kinds that are not configured explicitly are `WarningLevel::Warn`.
+#[derive(Debug, Default)]
+pub struct WarningLevels {
+    levels: HashMap<WarningKind, WarningLevel>,
+}
+
+impl WarningLevels {
+    pub fn set(&mut self, kind: WarningKind, level: WarningLevel) {
+        self.levels.insert(kind, level);
+    }
+
+    pub fn level(&self, kind: WarningKind) -> WarningLevel {
+        self.levels.get(&kind).copied().unwrap_or(WarningLevel::Warn)
+    }
+
+    /// Turn warnings into diagnostics with the configured severity, allowed warnings are dropped.
+    pub fn diagnose<'a>(&self, warnings: Vec<Warning<'a>>) -> Vec<Diagnostic<'a>> {
+        let mut diagnostics = vec![];
+        let mut explained = HashSet::new();
+
+        for warning in warnings {
+            let kind = warning.kind();
+            let severity = match self.level(kind) {
+                WarningLevel::Allow => continue,
+                WarningLevel::Warn => Severity::Warning,
+                WarningLevel::Deny => Severity::Error,
+            };
+
+            diagnostics.push(Diagnostic { severity, kind: DiagnosticKind::Warning(warning) });
+
+            //explain why a warning is an error, but only once for each kind
+            if severity == Severity::Error && explained.insert(kind) {
+                let note = format!("'{}' is denied by '-D {}'", kind.name(), kind.name());
+                diagnostics.push(Diagnostic { severity: Severity::Note, kind: DiagnosticKind::Note(note) });
+            }
+        }
+
+        diagnostics
+    }
+}
+
+/// A place in the source code that determined a type, used to point at the other side of a type mismatch.
+#[derive(Debug, Copy, Clone)]
+pub enum TypeOrigin<'a> {
+    /// The type of this expression.
+    Expression(&'a ast::Expression),
+    /// The type of the variable declared here.
+    Declaration(&'a ast::Declaration),
+    /// The type of the index variable of this loop.
+    ForIndex(&'a ast::ForStatement),
+    /// A type written explicitly, eg. for a variable or a parameter.
+    Type(&'a ast::Type),
+    /// The declared return type of the surrounding function.
+    ReturnType(&'a ast::Type),
+}
+
+impl TypeOrigin<'_> {
+    pub fn span(self) -> Span {
+        match self {
+            TypeOrigin::Expression(expr) => expr.span,
+            TypeOrigin::Declaration(decl) => decl.span,
+            TypeOrigin::ForIndex(for_stmt) => for_stmt.span,
+            TypeOrigin::Type(ty) | TypeOrigin::ReturnType(ty) => ty.span,
+        }
+    }
+
+    /// A note explaining why the type was expected.
+    pub fn note(self) -> &'static str {
+        match self {
+            TypeOrigin::Expression(_) => "expected because of the type of this expression",
+            TypeOrigin::Declaration(_) | TypeOrigin::ForIndex(_) => "expected because of the type of this variable",
+            TypeOrigin::Type(_) => "expected because of the type declared here",
+            TypeOrigin::ReturnType(_) => "expected because of the return type declared here",
+        }
+    }
+}
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum ItemType {
     Module,
     Type,
     Value,
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use crate::front::pos::{FileId, Pos};
+
+    use super::*;
+
+    fn id(name: &str) -> ast::Identifier {
+        let pos = Pos { file: FileId(0), line: 1, col: 1 };
+        ast::Identifier { span: Span::new(pos, pos), string: name.to_owned() }
+    }
+
+    #[test]
+    fn warning_levels() {
+        let (a, b, c) = (id("a"), id("b"), id("c"));
+        let warnings = || vec![Warning::UnusedVariable(&a), Warning::UnusedParameter(&b), Warning::UnusedVariable(&c)];
+
+        let mut levels = WarningLevels::default();
+        let severities = |levels: &WarningLevels| levels.diagnose(warnings()).iter().map(|d| d.severity).collect::<Vec<_>>();
+        assert_eq!(vec![Severity::Warning; 3], severities(&levels));
+
+        //denied warnings are explained once per kind
+        levels.set(WarningKind::UnusedVariable, WarningLevel::Deny);
+        levels.set(WarningKind::UnusedParameter, WarningLevel::Allow);
+        assert_eq!(vec![Severity::Error, Severity::Note, Severity::Error], severities(&levels));
+    }
+
+    #[test]
+    fn warning_kind_names() {
+        for &kind in WarningKind::ALL {
+            assert_eq!(Ok(kind), kind.name().parse());
+        }
+
+        let error = "unused".parse::<WarningKind>().unwrap_err();
+        assert!(error.starts_with("unknown warning 'unused', expected one of unused-variable"), "{}", error);
+    }
+}
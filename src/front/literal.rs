@@ -0,0 +1,99 @@
+use crate::mid::ir::Signedness;
+
+/// Parse the decimal integer literal `lit` and check that it fits in an integer of `bits` bits with the given
+/// signedness. `negated` is true if the literal is the operand of a unary minus, that allows one more value for
+/// signed integers so the minimum value can be written, eg. `-2147483648` for `int`.
+/// Returns the bit pattern of the (non-negated) value, or `None` if the literal is malformed or out of range.
+pub fn parse_int_literal(lit: &str, bits: u32, signedness: Signedness, negated: bool) -> Option<u32> {
+    assert!(bits <= 32, "integers with {} bits are not supported", bits);
+
+    if lit.is_empty() || !lit.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let max = match signedness {
+        Signedness::Unsigned => (1u64 << bits) - 1,
+        Signedness::Signed if negated => 1u64 << (bits - 1),
+        Signedness::Signed => (1u64 << (bits - 1)) - 1,
+    };
+
+    let value = lit.parse::<u64>().ok()?;
+    if value <= max {
+        Some(value as u32)
+    } else {
+        None
+    }
+}
+
+/// Parse the decimal float literal `lit` as a float of `bits` bits and return its little-endian representation.
+/// Returns `None` if the literal is malformed or too large to be represented as a finite value.
+pub fn parse_float_literal(lit: &str, bits: u32) -> Option<Vec<u8>> {
+    //only allow the `123.456` form the tokenizer produces, not everything `f64::from_str` accepts (eg. `inf`)
+    if !lit.bytes().all(|b| b.is_ascii_digit() || b == b'.') {
+        return None;
+    }
+
+    match bits {
+        32 => lit.parse::<f32>().ok().filter(|v| v.is_finite()).map(|v| v.to_le_bytes().to_vec()),
+        64 => lit.parse::<f64>().ok().filter(|v| v.is_finite()).map(|v| v.to_le_bytes().to_vec()),
+        _ => panic!("floats with {} bits are not supported", bits),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_unsigned(lit: &str, bits: u32) -> Option<u32> {
+        parse_int_literal(lit, bits, Signedness::Unsigned, false)
+    }
+
+    #[test]
+    fn int_literal_in_range() {
+        assert_eq!(Some(0), parse_unsigned("0", 8));
+        assert_eq!(Some(255), parse_unsigned("255", 8));
+        assert_eq!(Some(1), parse_unsigned("1", 1));
+        assert_eq!(Some(u32::MAX), parse_unsigned("4294967295", 32));
+        assert_eq!(Some(12), parse_unsigned("00012", 32));
+    }
+
+    #[test]
+    fn int_literal_out_of_range() {
+        assert_eq!(None, parse_unsigned("256", 8));
+        assert_eq!(None, parse_unsigned("2", 1));
+        assert_eq!(None, parse_unsigned("4294967296", 32));
+        assert_eq!(None, parse_unsigned("99999999999999999999999", 32));
+    }
+
+    #[test]
+    fn signed_int_literal() {
+        assert_eq!(Some(i32::MAX as u32), parse_int_literal("2147483647", 32, Signedness::Signed, false));
+        assert_eq!(None, parse_int_literal("2147483648", 32, Signedness::Signed, false));
+        assert_eq!(None, parse_int_literal("4294967295", 32, Signedness::Signed, false));
+
+        //only the operand of a unary minus can be the magnitude of the minimum value
+        assert_eq!(Some(0x8000_0000), parse_int_literal("2147483648", 32, Signedness::Signed, true));
+        assert_eq!(None, parse_int_literal("2147483649", 32, Signedness::Signed, true));
+
+        //negating doesn't extend the range of unsigned integers
+        assert_eq!(Some(255), parse_int_literal("255", 8, Signedness::Unsigned, true));
+        assert_eq!(None, parse_int_literal("256", 8, Signedness::Unsigned, true));
+    }
+
+    #[test]
+    fn float_literal() {
+        assert_eq!(Some(1.5f32.to_le_bytes().to_vec()), parse_float_literal("1.5", 32));
+        assert_eq!(Some(0.1f64.to_le_bytes().to_vec()), parse_float_literal("0.1", 64));
+        assert_eq!(None, parse_float_literal("1e40", 32));
+        assert_eq!(None, parse_float_literal("999999999999999999999999999999999999999.0", 32));
+        assert_eq!(None, parse_float_literal("inf", 64));
+    }
+
+    #[test]
+    fn int_literal_malformed() {
+        assert_eq!(None, parse_unsigned("", 32));
+        assert_eq!(None, parse_unsigned("-1", 32));
+        assert_eq!(None, parse_unsigned("+1", 32));
+        assert_eq!(None, parse_unsigned("1a", 32));
+    }
+}
@@ -3,12 +3,17 @@ use std::ops::{Deref, DerefMut};
 
 use itertools::Itertools;
 
+use crate::back::layout::DataLayout;
+use crate::back::target::SystemApi;
 use crate::front::{ast, cst};
-use crate::front::ast::ExpressionKind;
-use crate::front::cst::{ArrayTypeInfo, FunctionTypeInfo, ScopedValue, StructTypeInfo, TupleTypeInfo, Type, TypeInfo, TypeStore};
-use crate::front::error::{Error, Result};
-use crate::front::lower_func::LowerFuncState;
-use crate::front::type_func::TypeFuncState;
+use crate::front::const_eval::{ConstEvalState, ConstValue};
+use crate::front::cst::{ArrayTypeInfo, EnumTypeInfo, FunctionTypeInfo, ScopedValue, StructTypeInfo, TupleTypeInfo, Type, TypeInfo, TypeStore};
+use crate::front::error::{Error, Result, Warning};
+use crate::front::literal::{parse_float_literal, parse_int_literal};
+use crate::front::lower_func::{LowerFuncState, signedness};
+use crate::front::pos::Files;
+use crate::front::runtime::Runtime;
+use crate::front::type_func::{report_type_holes, TypeFuncState};
 use crate::mid::ir;
 use crate::mid::ir::ArrayType;
 
@@ -74,7 +79,15 @@ impl<'a> MappingTypeStore<'a> {
             .collect();
         let ret = self.map_type(prog, ty.ret);
 
-        ir::FunctionType { params, ret }
+        ir::FunctionType { params, ret, conv: ty.conv }
+    }
+
+    /// Map the type of the function implementing a closure, which takes a pointer to the environment as an extra
+    /// first parameter.
+    pub fn map_type_closure_func(&mut self, prog: &mut ir::Program, ty: &FunctionTypeInfo<cst::Type>) -> ir::FunctionType {
+        let mut func_ty = self.map_type_func(prog, ty);
+        func_ty.params.insert(0, prog.ty_ptr());
+        func_ty
     }
 
     pub fn map_type(&mut self, prog: &mut ir::Program, ty: cst::Type) -> ir::Type {
@@ -85,32 +98,58 @@ impl<'a> MappingTypeStore<'a> {
         let ir_ty = match &self.inner[ty] {
             ph @ TypeInfo::Placeholder(_) => panic!("tried to map type {:?}", ph),
             TypeInfo::Wildcard => panic!("tried to map wildcard to IR"),
-            TypeInfo::Void => prog.ty_ptr(),
+            TypeInfo::Void | TypeInfo::Never => prog.ty_ptr(),
             TypeInfo::Bool => prog.ty_bool(),
             TypeInfo::Byte => prog.define_type_int(8),
-            TypeInfo::Int => prog.define_type_int(32),
-            TypeInfo::Pointer(_) => prog.ty_ptr(),
+            TypeInfo::Int => prog.ty_int(),
+            &TypeInfo::Float { bits } => prog.define_type_float(bits),
+            TypeInfo::Str | TypeInfo::Slice(_) => {
+                //represented as (pointer, length)
+                let fields = vec![prog.ty_ptr(), prog.ty_int()];
+                prog.define_type_tuple(ir::TupleType { fields, packed: false })
+            }
+            TypeInfo::Pointer(_) | TypeInfo::OptionalPointer(_) => prog.ty_ptr(),
             TypeInfo::Tuple(TupleTypeInfo { fields }) => {
                 let fields = fields.clone().iter()
                     .map(|&f_ty| self.map_type(prog, f_ty))
                     .collect();
-                prog.define_type_tuple(ir::TupleType { fields })
+                prog.define_type_tuple(ir::TupleType { fields, packed: false })
             }
             TypeInfo::Function(info) => {
                 let info = info.clone();
                 let func_ty = self.map_type_func(prog, &info);
                 prog.define_type_func(func_ty)
             }
-            TypeInfo::Struct(StructTypeInfo { decl: _, fields }) => {
+            TypeInfo::Closure(info) => {
+                //represented as (function, environment pointer)
+                let info = info.clone();
+                let func_ty = self.map_type_closure_func(prog, &info);
+                let func_ty = prog.define_type_func(func_ty);
+                prog.define_type_tuple(ir::TupleType { fields: vec![func_ty, prog.ty_ptr()], packed: false })
+            }
+            &TypeInfo::Struct(StructTypeInfo { decl: _, ref fields, packed }) => {
                 let fields = fields.clone().iter()
                     .map(|field| self.map_type(prog, field.ty))
                     .collect();
-                prog.define_type_tuple(ir::TupleType { fields })
+                prog.define_type_tuple(ir::TupleType { fields, packed })
             }
             &TypeInfo::Array(ArrayTypeInfo { inner, length }) => {
                 let inner = self.map_type(prog, inner);
                 prog.define_type_array(ArrayType { inner, length })
             }
+            TypeInfo::Enum(EnumTypeInfo { decl: _, variants }) => {
+                //represented as (discriminant, payload) where the payload is a union of the variant field tuples
+                let variants = variants.clone().iter()
+                    .map(|variant| {
+                        let fields = variant.fields.iter()
+                            .map(|&f_ty| self.map_type(prog, f_ty))
+                            .collect();
+                        prog.define_type_tuple(ir::TupleType { fields, packed: false })
+                    })
+                    .collect();
+                let payload = prog.define_type_union(ir::UnionType { variants });
+                prog.define_type_tuple(ir::TupleType { fields: vec![prog.ty_int(), payload], packed: false })
+            }
         };
 
         self.map.insert(ty, ir_ty);
@@ -118,24 +157,44 @@ impl<'a> MappingTypeStore<'a> {
     }
 }
 
+/// Settings that influence the code generated by `lower`.
+#[derive(Debug)]
+pub struct LowerSettings<'a> {
+    /// The source files, used for the locations reported by panics.
+    pub files: &'a Files,
+    /// Whether `assert` statements are checked, otherwise they're removed entirely.
+    pub check_asserts: bool,
+    /// Print the statistics of the type solver for each function.
+    pub solver_stats: bool,
+    /// The type layout of the target, see `ir::Program::data_layout`.
+    pub data_layout: DataLayout,
+    /// The operating system interface the runtime functions use.
+    pub system_api: SystemApi,
+}
+
 /// The main entry point of the lowering pass that generates the `ir` code for a given `ResolvedProgram`.
-pub fn lower(prog: cst::ResolvedProgram) -> Result<ir::Program> {
+/// Lower the resolved program to IR, also returns the warnings found along the way.
+pub fn lower<'a>(prog: cst::ResolvedProgram<'a>, settings: &LowerSettings) -> Result<'a, (ir::Program, Vec<Warning<'a>>)> {
     let mut types = MappingTypeStore::wrap(prog.types);
 
     let mut ir_prog = ir::Program::default();
+    ir_prog.data_layout = settings.data_layout;
+    ir_prog.source_files = settings.files.iter().map(|file| file.path.clone()).collect();
 
-    //create ir function for each cst function
+    //create ir function for each cst function, externs with the same name share a single ir extern
+    let mut externs = HashMap::new();
     let all_funcs: HashMap<cst::Function, (Option<ir::Function>, LRValue)> = prog.items.funcs.iter()
         .map(|(cst_func, decl)| {
-            let r = map_function(&mut types, &mut ir_prog, &decl)?;
+            let r = map_function(&mut types, &mut ir_prog, &mut externs, decl)?;
             Ok((cst_func, r))
         }).try_collect()?;
 
-    //create ir data for each cst const
+    //evaluate each cst const and create the corresponding ir value
+    let mut const_eval = ConstEvalState::new(&prog.items);
     let all_consts: HashMap<cst::Const, LRValue> = prog.items.consts.iter()
-        .map(|(cst_const, decl)| {
-            let lr = map_constant(&mut types, &mut ir_prog, decl)?;
-            Ok((cst_const, lr))
+        .map(|(cst_const, _)| {
+            let value = const_eval.eval_const(&mut types, cst_const)?;
+            Ok((cst_const, map_constant(&mut types, &mut ir_prog, value)))
         }).try_collect()?;
 
     //set main function
@@ -148,10 +207,13 @@ pub fn lower(prog: cst::ResolvedProgram) -> Result<ir::Program> {
             ScopedValue::Const(cst) => *all_consts.get(&cst).unwrap(),
             ScopedValue::Immediate(value) => value,
             ScopedValue::TypeVar(_) => panic!("tried to map TypeVar value to placeholder"),
+            ScopedValue::EnumVariant { .. } => panic!("enum variants should be constructed by the caller"),
         }
     };
 
     //type inference and code generation
+    let mut runtime = Runtime::new(settings.system_api);
+    let mut warnings = vec![];
     for (_, module) in &prog.items.modules {
         for &cst_func in &module.codegen_funcs {
             let func_decl = &prog.items.funcs[cst_func];
@@ -167,22 +229,36 @@ pub fn lower(prog: cst::ResolvedProgram) -> Result<ir::Program> {
                     map_value,
 
                     ret_ty: func_decl.func_ty.ret,
+                    ret_ty_ast: func_decl.ast.ret_ty.as_ref(),
 
                     expr_type_map: Default::default(),
                     decl_type_map: Default::default(),
                     problem: Default::default(),
+
+                    bindings: Default::default(),
+                    used_vars: Default::default(),
+                    type_holes: Default::default(),
+                    warnings: Default::default(),
                 };
-                type_state.visit_func(func_decl)?;
+                warnings.extend(type_state.visit_func(func_decl)?);
 
                 let TypeFuncState {
                     problem,
                     expr_type_map,
                     decl_type_map,
+                    type_holes,
                     ..
                 } = type_state;
 
                 //solve the problem
-                let solution = problem.solve(&mut *types);
+                let solution = problem.solve(&mut types)?;
+                if settings.solver_stats {
+                    println!("{}: {:?}", func_decl.name, solution.stats());
+                }
+
+                for (ty_ast, var) in type_holes {
+                    report_type_holes(&types, ty_ast, solution[var], &mut warnings);
+                }
 
                 //actually generate code
                 LowerFuncState {
@@ -193,25 +269,29 @@ pub fn lower(prog: cst::ResolvedProgram) -> Result<ir::Program> {
 
                     module_scope: &module.scope,
                     map_value,
+                    runtime: &mut runtime,
+                    settings,
 
                     ret_ty: func_decl.func_ty.ret,
                     ir_func,
                     loop_stack: vec![],
+                    current_span: None,
 
                     expr_type_map: &expr_type_map,
                     decl_type_map: &decl_type_map,
-                    type_solution: solution,
+                    type_solution: &solution,
                 }.lower_func(func_decl)?;
             }
         }
     }
 
-    Ok(ir_prog)
+    Ok((ir_prog, warnings))
 }
 
 fn map_function<'a>(
     store: &mut MappingTypeStore,
     prog: &mut ir::Program,
+    externs: &mut HashMap<&'a str, (&'a ast::Function, cst::Type, ir::Extern)>,
     decl: &cst::FunctionDecl<'a>,
 ) -> Result<'a, (Option<ir::Function>, LRValue)> {
     let ty_func_ir = store.map_type_func(prog, &decl.func_ty);
@@ -219,17 +299,33 @@ fn map_function<'a>(
     let (func_ir, value_ir) = match (decl.ast.ext, decl.ast.body.is_some()) {
         (false, false) => Err(Error::MissingFunctionBody(decl.ast)),
         (true, false) => {
-            let ir_ty = prog.define_type_func(ty_func_ir);
-            let ext = ir::ExternInfo {
-                name: decl.ast.id.string.clone(),
-                ty: ir_ty,
-            };
-            Ok((None, ir::Value::Extern(prog.define_ext(ext))))
+            let name = &decl.ast.id.string;
+            match externs.get(name.as_str()) {
+                Some(&(_, ty, ext)) if ty == decl.ty => Ok((None, ir::Value::Extern(ext))),
+                Some(&(first, ty, _)) => Err(Error::ExternTypeConflict {
+                    first,
+                    first_ty: store.format_type(ty).to_string(),
+                    second: decl.ast,
+                    second_ty: store.format_type(decl.ty).to_string(),
+                }),
+                None => {
+                    let ir_ty = prog.define_type_func(ty_func_ir);
+                    let ext = ir::ExternInfo {
+                        name: name.clone(),
+                        ty: ir_ty,
+                    };
+                    let ext = prog.define_ext(ext);
+                    externs.insert(name, (decl.ast, decl.ty, ext));
+                    Ok((None, ir::Value::Extern(ext)))
+                }
+            }
         }
         (ext, true) => {
             let mut func_ir = ir::FunctionInfo::new(ty_func_ir, prog);
 
-            func_ir.debug_name = Some(decl.ast.id.string.clone());
+            func_ir.debug_name = Some(decl.name.clone());
+            func_ir.inline = decl.attrs.inline;
+            func_ir.noreturn = decl.attrs.noreturn;
             if ext {
                 func_ir.global_name = Some(decl.ast.id.string.clone())
             }
@@ -242,85 +338,135 @@ fn map_function<'a>(
     Ok((func_ir, LRValue::Right(TypedValue { ty: decl.ty, ir: value_ir })))
 }
 
-fn map_constant<'a>(
-    store: &mut MappingTypeStore<'a>,
+fn map_constant(
+    store: &mut MappingTypeStore,
     ir_prog: &mut ir::Program,
-    decl: &cst::ConstDecl<'a>,
-) -> Result<'a, LRValue> {
-    let ty = decl.ty;
-    let init = &decl.ast.init;
-
-    let lr = match &init.kind {
-        ExpressionKind::IntLit { value } => {
-            check_integer_type(&store, init, ty)?;
-            let value = value.parse::<i32>()
-                .map_err(|_| Error::InvalidLiteral {
-                    span: init.span,
-                    lit: value.clone(),
-                    ty: store.format_type(ty).to_string(),
-                })?;
+    value: ConstValue,
+) -> LRValue {
+    match value {
+        ConstValue::Int { ty, value } => {
             let ty_ir = store.map_type(ir_prog, ty);
-            LRValue::Right(TypedValue { ty, ir: ir::Value::Const(ir::Const { ty: ty_ir, value }) })
+            LRValue::Right(TypedValue { ty, ir: ir::Value::Const(ir::Const { ty: ty_ir, value: value as i32 }) })
         }
-        ExpressionKind::BoolLit { value } => {
-            check_type_match(&store, init, store.type_bool(), ty)?;
+        ConstValue::Bool(value) => {
+            let ty = store.type_bool();
             let ty_bool_ir = ir_prog.ty_bool();
-            let value = *value as i32;
-            LRValue::Right(TypedValue { ty, ir: ir::Value::Const(ir::Const { ty: ty_bool_ir, value }) })
+            LRValue::Right(TypedValue { ty, ir: ir::Value::Const(ir::Const { ty: ty_bool_ir, value: value as i32 }) })
         }
-        ExpressionKind::StringLit { value } => {
+        ConstValue::Float { ty, value } => {
+            let bytes = match store[ty] {
+                TypeInfo::Float { bits: 32 } => (value as f32).to_le_bytes().to_vec(),
+                TypeInfo::Float { bits: 64 } => value.to_le_bytes().to_vec(),
+                _ => unreachable!(),
+            };
+            map_float_data(store, ir_prog, ty, bytes)
+        }
+        ConstValue::Bytes { ty, bytes } => {
             let ty_byte = store.type_byte();
-            let ty_byte_ptr = store.define_type_ptr(ty_byte);
-            check_type_match(&store, init, ty_byte_ptr, ty)?;
-
             let ty_byte_ir = store.map_type(ir_prog, ty_byte);
-            let ty_byte_ptr_ir = store.map_type(ir_prog, ty_byte_ptr);
+            let ty_ptr_ir = store.map_type(ir_prog, ty);
 
-            let bytes = value.bytes().collect_vec();
-            let data = ir::DataInfo { ty: ty_byte_ptr_ir, inner_ty: ty_byte_ir, bytes };
+            let data = ir::DataInfo { ty: ty_ptr_ir, inner_ty: ty_byte_ir, bytes };
             let data = ir_prog.define_data(data);
             LRValue::Right(TypedValue { ty, ir: ir::Value::Data(data) })
         }
-        ExpressionKind::Null => {
-            check_ptr_type(&store, init, ty)?;
+        ConstValue::Null { ty } => {
             let ty_ir = store.map_type(ir_prog, ty);
-
-            let cst = ir::Const { ty: ty_ir, value: 0 };
-            LRValue::Right(TypedValue { ty, ir: ir::Value::Const(cst) })
+            LRValue::Right(TypedValue { ty, ir: ir::Value::Const(ir::Const { ty: ty_ir, value: 0 }) })
         }
-        _ => panic!("for now only simple literal constants are supported"),
-    };
-
-    Ok(lr)
-}
-
-fn check_type_match<'ast>(store: &TypeStore, expr: &'ast ast::Expression, expected: cst::Type, actual: cst::Type) -> Result<'ast, ()> {
-    if expected != actual {
-        return Err(Error::TypeMismatch {
-            expression: expr,
-            expected: store.format_type(expected).to_string(),
-            actual: store.format_type(actual).to_string(),
-        });
     }
-    Ok(())
 }
 
-fn check_integer_type<'ast>(store: &TypeStore, expr: &'ast ast::Expression, actual: cst::Type) -> Result<'ast, ()> {
-    match &store[actual] {
-        TypeInfo::Byte | TypeInfo::Int => Ok(()),
-        _ => Err(Error::ExpectIntegerType {
+/// Map the integer literal `lit` of type `ty` to an `ir::Const`, checking that the value fits in the type.
+/// If `negated` is true the literal is the operand of a unary minus and the negated value is returned.
+pub fn map_int_literal<'a>(
+    store: &mut MappingTypeStore<'a>,
+    ir_prog: &mut ir::Program,
+    expr: &'a ast::Expression,
+    lit: &str,
+    ty: cst::Type,
+    negated: bool,
+) -> Result<'a, ir::Const> {
+    let bits = match store[ty] {
+        TypeInfo::Byte => 8,
+        TypeInfo::Int => 32,
+        _ => return Err(Error::ExpectIntegerType {
             expression: expr,
-            actual: store.format_type(actual).to_string(),
+            actual: store.format_type(ty).to_string(),
         }),
-    }
+    };
+
+    let value = parse_int_literal(lit, bits, signedness(&store[ty]), negated)
+        .ok_or_else(|| Error::InvalidLiteral {
+            span: expr.span,
+            lit: lit.to_owned(),
+            ty: store.format_type(ty).to_string(),
+        })?;
+    //keep the bit pattern within `bits`, like the non-negated literals
+    let value = if negated { value.wrapping_neg() & (u32::MAX >> (32 - bits)) } else { value };
+
+    let ty_ir = store.map_type(ir_prog, ty);
+    Ok(ir::Const::new(ty_ir, value as i32))
 }
 
-fn check_ptr_type<'ast>(store: &TypeStore, expr: &'ast ast::Expression, actual: cst::Type) -> Result<'ast, ()> {
-    match &store[actual] {
-        TypeInfo::Pointer(_) => Ok(()),
-        _ => Err(Error::ExpectPointerType {
+/// Map the float literal `lit` of type `ty` to a `LRValue::Left` pointing to data containing the value.
+//TODO use proper float constants once `ir::Const` can represent them
+pub fn map_float_literal<'a>(
+    store: &mut MappingTypeStore<'a>,
+    ir_prog: &mut ir::Program,
+    expr: &'a ast::Expression,
+    lit: &str,
+    ty: cst::Type,
+) -> Result<'a, LRValue> {
+    let bits = match store[ty] {
+        TypeInfo::Float { bits } => bits,
+        _ => return Err(Error::ExpectFloatType {
             expression: expr,
-            actual: store.format_type(actual).to_string(),
+            actual: store.format_type(ty).to_string(),
         }),
+    };
+
+    let bytes = parse_float_literal(lit, bits)
+        .ok_or_else(|| Error::InvalidLiteral {
+            span: expr.span,
+            lit: lit.to_owned(),
+            ty: store.format_type(ty).to_string(),
+        })?;
+
+    Ok(map_float_data(store, ir_prog, ty, bytes))
+}
+
+/// Store the float of type `ty` with representation `bytes` in a data item, since `ir::Const` can only hold integers.
+fn map_float_data(store: &mut MappingTypeStore, ir_prog: &mut ir::Program, ty: cst::Type, bytes: Vec<u8>) -> LRValue {
+    let ty_ptr = store.define_type_ptr(ty);
+    let data = ir::DataInfo {
+        ty: store.map_type(ir_prog, ty_ptr),
+        inner_ty: store.map_type(ir_prog, ty),
+        bytes,
+    };
+    let data = ir_prog.define_data(data);
+
+    LRValue::Left(TypedValue { ty: ty_ptr, ir: ir::Value::Data(data) })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::back::target::I686Windows;
+    use crate::mid::opt::snapshot::{lower_modules, lower_modules_error};
+
+    const MAIN: &str = "use a::first; use b::second; fun main() -> int { return first() + second(); }";
+
+    #[test]
+    fn duplicate_externs() {
+        let a = "extern fun foo(x: int) -> int; pub fun first() -> int { return foo(1); }";
+        let b = "extern fun foo(y: int) -> int; pub fun second() -> int { return foo(2); }";
+        let prog = lower_modules(&[("main", MAIN), ("a", a), ("b", b)], &I686Windows);
+        assert_eq!(1, prog.nodes.exts.iter().filter(|(_, ext)| ext.name == "foo").count());
+
+        let b = "extern fun foo(x: byte) -> int; pub fun second() -> int { return foo(2); }";
+        let error = lower_modules_error(&[("main", MAIN), ("a", a), ("b", b)]);
+        assert!(error.starts_with("error: extern function `foo` declared with conflicting types"), "{}", error);
+        assert!(error.contains("declared here as `(byte) -> int`"), "{}", error);
+        assert!(error.contains("previously declared as `(int) -> int`"), "{}", error);
     }
-}
\ No newline at end of file
+}
@@ -1,20 +1,28 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
+use crate::back::layout::Layout;
 use crate::front::{ast, cst};
-use crate::front::cst::{ItemStore, ScopedItem, ScopedValue, ScopeKind, TypeInfo};
+use crate::front::capture::find_captures;
+use crate::front::cst::{ArrayTypeInfo, FunctionTypeInfo, ItemStore, ScopedItem, ScopedValue, ScopeKind, TypeInfo};
 use crate::front::error::{Error, Result};
-use crate::front::lower::{LRValue, MappingTypeStore, TypedValue};
+use crate::front::lower::{LowerSettings, LRValue, map_float_literal, map_int_literal, MappingTypeStore, TypedValue};
+use crate::front::pos::Span;
+use crate::front::runtime::Runtime;
 use crate::front::scope::Scope;
+use crate::front::type_func::null_check;
 use crate::front::type_solver::{TypeSolution, TypeVar};
 use crate::mid::ir;
+use crate::util::zip_eq;
 
 /// The state necessary to lower a single function.
-pub struct LowerFuncState<'ir, 'ast, 'cst, 'ts, F: Fn(ScopedValue) -> LRValue> {
+pub struct LowerFuncState<'ir, 'ast, 'cst, 'ts, F: Fn(ScopedValue) -> LRValue + Copy> {
     pub prog: &'ir mut ir::Program,
 
     pub items: &'cst ItemStore<'ast>,
     pub types: &'cst mut MappingTypeStore<'ast>,
     pub map_value: F,
+    pub runtime: &'cst mut Runtime,
+    pub settings: &'cst LowerSettings<'cst>,
 
     pub module_scope: &'cst Scope<'static, ScopedItem>,
 
@@ -23,9 +31,11 @@ pub struct LowerFuncState<'ir, 'ast, 'cst, 'ts, F: Fn(ScopedValue) -> LRValue> {
 
     pub expr_type_map: &'ts HashMap<*const ast::Expression, TypeVar>,
     pub decl_type_map: &'ts HashMap<*const ast::Declaration, TypeVar>,
-    pub type_solution: TypeSolution,
+    pub type_solution: &'ts TypeSolution,
 
     pub loop_stack: Vec<LoopInfo>,
+    /// The span of the innermost statement or expression being lowered, recorded as the location of new instructions.
+    pub current_span: Option<Span>,
 }
 
 /// Information about the innermost loop, used for `break` and `continue` statements.
@@ -35,22 +45,55 @@ pub struct LoopInfo {
     end_needs_return: bool,
 }
 
-fn binary_op_to_instr(ast_kind: ast::BinaryOp, left: ir::Value, right: ir::Value) -> ir::InstructionInfo {
+/// The signedness of integer operations on values of type `ty`, only `int` is signed.
+pub fn signedness<T>(ty: &TypeInfo<T>) -> ir::Signedness {
+    match ty {
+        TypeInfo::Int => ir::Signedness::Signed,
+        _ => ir::Signedness::Unsigned,
+    }
+}
+
+fn binary_op_to_instr(ast_kind: ast::BinaryOp, operand_ty: &TypeInfo<cst::Type>, left: ir::Value, right: ir::Value) -> ir::InstructionInfo {
+    if let TypeInfo::Float { .. } = operand_ty {
+        //the signedness of float division and comparisons is ignored
+        let s = ir::Signedness::Signed;
+        return match ast_kind {
+            ast::BinaryOp::Add => ir::InstructionInfo::FloatArithmetic { kind: ir::ArithmeticOp::Add, left, right },
+            ast::BinaryOp::Sub => ir::InstructionInfo::FloatArithmetic { kind: ir::ArithmeticOp::Sub, left, right },
+            ast::BinaryOp::Mul => ir::InstructionInfo::FloatArithmetic { kind: ir::ArithmeticOp::Mul, left, right },
+            ast::BinaryOp::Div => ir::InstructionInfo::FloatArithmetic { kind: ir::ArithmeticOp::Div(s), left, right },
+            ast::BinaryOp::Mod => panic!("modulo is not supported for floats"),
+            ast::BinaryOp::Eq => ir::InstructionInfo::FloatComparison { kind: ir::LogicalOp::Eq, left, right },
+            ast::BinaryOp::Neq => ir::InstructionInfo::FloatComparison { kind: ir::LogicalOp::Neq, left, right },
+            ast::BinaryOp::Gte => ir::InstructionInfo::FloatComparison { kind: ir::LogicalOp::Gte(s), left, right },
+            ast::BinaryOp::Gt => ir::InstructionInfo::FloatComparison { kind: ir::LogicalOp::Gt(s), left, right },
+            ast::BinaryOp::Lte => ir::InstructionInfo::FloatComparison { kind: ir::LogicalOp::Lte(s), left, right },
+            ast::BinaryOp::Lt => ir::InstructionInfo::FloatComparison { kind: ir::LogicalOp::Lt(s), left, right },
+        };
+    }
+
+    let s = signedness(operand_ty);
     match ast_kind {
         ast::BinaryOp::Add => ir::InstructionInfo::Arithmetic { kind: ir::ArithmeticOp::Add, left, right },
         ast::BinaryOp::Sub => ir::InstructionInfo::Arithmetic { kind: ir::ArithmeticOp::Sub, left, right },
         ast::BinaryOp::Mul => ir::InstructionInfo::Arithmetic { kind: ir::ArithmeticOp::Mul, left, right },
-        ast::BinaryOp::Div => ir::InstructionInfo::Arithmetic { kind: ir::ArithmeticOp::Div, left, right },
-        ast::BinaryOp::Mod => ir::InstructionInfo::Arithmetic { kind: ir::ArithmeticOp::Mod, left, right },
+        ast::BinaryOp::Div => ir::InstructionInfo::Arithmetic { kind: ir::ArithmeticOp::Div(s), left, right },
+        ast::BinaryOp::Mod => ir::InstructionInfo::Arithmetic { kind: ir::ArithmeticOp::Mod(s), left, right },
         ast::BinaryOp::Eq => ir::InstructionInfo::Comparison { kind: ir::LogicalOp::Eq, left, right },
         ast::BinaryOp::Neq => ir::InstructionInfo::Comparison { kind: ir::LogicalOp::Neq, left, right },
-        ast::BinaryOp::Gte => ir::InstructionInfo::Comparison { kind: ir::LogicalOp::Gte, left, right },
-        ast::BinaryOp::Gt => ir::InstructionInfo::Comparison { kind: ir::LogicalOp::Gt, left, right },
-        ast::BinaryOp::Lte => ir::InstructionInfo::Comparison { kind: ir::LogicalOp::Lte, left, right },
-        ast::BinaryOp::Lt => ir::InstructionInfo::Comparison { kind: ir::LogicalOp::Lt, left, right },
+        ast::BinaryOp::Gte => ir::InstructionInfo::Comparison { kind: ir::LogicalOp::Gte(s), left, right },
+        ast::BinaryOp::Gt => ir::InstructionInfo::Comparison { kind: ir::LogicalOp::Gt(s), left, right },
+        ast::BinaryOp::Lte => ir::InstructionInfo::Comparison { kind: ir::LogicalOp::Lte(s), left, right },
+        ast::BinaryOp::Lt => ir::InstructionInfo::Comparison { kind: ir::LogicalOp::Lt(s), left, right },
     }
 }
 
+/// Array repeat expressions up to this length are initialized with a sequence of stores, longer ones with a loop.
+const ARRAY_REPEAT_UNROLL_LIMIT: u32 = 8;
+
+/// Match statements with at least this many distinct cases can be lowered to a `Switch` if the cases are dense enough.
+const MATCH_SWITCH_MIN_CASES: usize = 4;
+
 fn new_target(block: ir::Block) -> ir::Target {
     ir::Target { block, phi_values: Vec::new() }
 }
@@ -63,6 +106,81 @@ fn new_branch(cond: ir::Value, true_block: ir::Block, false_block: ir::Block) ->
     }
 }
 
+/// Whether `expr` is cheap to evaluate and can't have side effects or fail, so it can be evaluated unconditionally.
+fn is_simple_value(expr: &ast::Expression) -> bool {
+    matches!(
+        expr.kind,
+        ast::ExpressionKind::IntLit { .. } | ast::ExpressionKind::BoolLit { .. } |
+        ast::ExpressionKind::Null | ast::ExpressionKind::Path(_)
+    )
+}
+
+/// Whether a match with the given case values should be lowered to a `Switch` instead of a chain of branches.
+fn is_dense_switch(cases: &[i32]) -> bool {
+    let cases: HashSet<i32> = cases.iter().copied().collect();
+
+    match (cases.iter().min(), cases.iter().max()) {
+        (Some(&min), Some(&max)) => {
+            let range = max as i64 - min as i64 + 1;
+            cases.len() >= MATCH_SWITCH_MIN_CASES && range <= 2 * cases.len() as i64
+        }
+        _ => false,
+    }
+}
+
+/// If `cond` has the form `path == literal` or `literal == path` for an integer literal, return the path
+/// expression and the literal.
+fn int_equality(cond: &ast::Expression) -> Option<(&ast::Expression, &ast::Expression)> {
+    if let ast::ExpressionKind::Binary { kind: ast::BinaryOp::Eq, left, right } = &cond.kind {
+        match (&left.kind, &right.kind) {
+            (ast::ExpressionKind::Path(_), ast::ExpressionKind::IntLit { .. }) => return Some((left, right)),
+            (ast::ExpressionKind::IntLit { .. }, ast::ExpressionKind::Path(_)) => return Some((right, left)),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn same_path(left: &ast::Expression, right: &ast::Expression) -> bool {
+    match (&left.kind, &right.kind) {
+        (ast::ExpressionKind::Path(left), ast::ExpressionKind::Path(right)) => {
+            left.id.string == right.id.string &&
+                left.parents.len() == right.parents.len() &&
+                left.parents.iter().zip(&right.parents).all(|(l, r)| l.string == r.string)
+        }
+        _ => false,
+    }
+}
+
+/// The arms of an if-else chain where every condition compares the same path against an integer literal,
+/// as the path, the literal and body of each arm, and the final else block.
+type IfChainArms<'ast> = (&'ast ast::Expression, Vec<(&'ast ast::Expression, &'ast ast::Block)>, Option<&'ast ast::Block>);
+
+fn if_chain_arms(if_stmt: &ast::IfStatement) -> Option<IfChainArms<'_>> {
+    let mut key = None;
+    let mut arms = Vec::new();
+    let mut current = if_stmt;
+
+    loop {
+        let (path, lit) = int_equality(&current.cond)?;
+        match key {
+            Some(key) if !same_path(key, path) => return None,
+            _ => key = Some(path),
+        }
+        arms.push((lit, &current.then_block));
+
+        match &current.else_block {
+            Some(ast::Block { span: _, statements, value: None }) if statements.len() == 1 => {
+                match &statements[0].kind {
+                    ast::StatementKind::If(next) => current = next,
+                    _ => return Some((key?, arms, current.else_block.as_ref())),
+                }
+            }
+            else_block => return Some((key?, arms, else_block.as_ref())),
+        }
+    }
+}
+
 enum ContinueOrBreak {
     Break,
     Continue,
@@ -75,7 +193,7 @@ struct Flow {
     needs_return: bool,
 }
 
-impl<'ir, 'ast, 'cst, 'ts, F: Fn(ScopedValue) -> LRValue> LowerFuncState<'ir, 'ast, 'cst, 'ts, F> {
+impl<'ir, 'ast, 'cst, 'ts, F: Fn(ScopedValue) -> LRValue + Copy> LowerFuncState<'ir, 'ast, 'cst, 'ts, F> {
     fn expr_type(&self, expr: &ast::Expression) -> cst::Type {
         self.type_solution[*self.expr_type_map.get(&(expr as *const _)).unwrap()]
     }
@@ -99,12 +217,78 @@ impl<'ir, 'ast, 'cst, 'ts, F: Fn(ScopedValue) -> LRValue> LowerFuncState<'ir, 'a
     fn append_instr(&mut self, block: ir::Block, instr: ir::InstructionInfo) -> ir::Instruction {
         let instr = self.prog.define_instr(instr);
         self.prog.get_block_mut(block).instructions.push(instr);
+        if let Some(span) = self.current_span {
+            let location = ir::SourceLocation { file: span.start.file.0, line: span.start.line as u32, col: span.start.col as u32 };
+            self.prog.instr_locations.insert(instr, location);
+        }
         instr
     }
 
+    /// Run `f` with `span` as the current span, restoring the previous one afterwards.
+    fn with_span<R>(&mut self, span: Span, f: impl FnOnce(&mut Self) -> R) -> R {
+        let prev = self.current_span.replace(span);
+        let result = f(self);
+        self.current_span = prev;
+        result
+    }
+
+    /// Allocate `size` bytes on the heap, returns the (uninitialized) pointer.
+    fn append_alloc(&mut self, block: ir::Block, size: ir::Value) -> ir::Value {
+        let alloc = self.runtime.alloc(self.prog);
+        let call = ir::InstructionInfo::Call { target: alloc, args: vec![size] };
+        ir::Value::Instr(self.append_instr(block, call))
+    }
+
+    /// Define a constant string, returns the pointer to its first byte and its length.
+    fn define_string(&mut self, bytes: Vec<u8>) -> (ir::Value, ir::Value) {
+        let ty_byte = self.types.type_byte();
+        let ty_byte_ptr = self.types.define_type_ptr(ty_byte);
+
+        let len = ir::Value::Const(ir::Const::new(self.prog.ty_int(), bytes.len() as i32));
+        let data = ir::DataInfo {
+            ty: self.types.map_type(self.prog, ty_byte_ptr),
+            inner_ty: self.types.map_type(self.prog, ty_byte),
+            bytes,
+        };
+
+        (ir::Value::Data(self.prog.define_data(data)), len)
+    }
+
+    /// Call the runtime panic function with the location of `span` and the message given by its pointer and length.
+    /// The block keeps its default unreachable terminator, nothing can follow a panic.
+    fn append_panic(&mut self, block: ir::Block, span: Span, message_ptr: ir::Value, message_len: ir::Value) {
+        let start = span.start;
+        let location = format!("panicked at {}:{}:{}: ", self.settings.files[start.file].path.display(), start.line, start.col);
+        let (location_ptr, location_len) = self.define_string(location.into_bytes());
+
+        let panic = self.runtime.panic(self.prog);
+        let call = ir::InstructionInfo::Call { target: panic, args: vec![location_ptr, location_len, message_ptr, message_len] };
+        self.append_instr(block, call);
+    }
+
     #[must_use]
     fn append_negate(&mut self, block: ir::Block, value: ir::Value) -> ir::Value {
         let ty_ir = self.prog.type_of_value(value);
+
+        if let Some(bits) = self.prog.get_type(ty_ir).unwrap_float() {
+            //-0.0 - x instead of 0.0 - x so the sign of zero is flipped correctly
+            let bytes = match bits {
+                32 => (-0.0f32).to_le_bytes().to_vec(),
+                64 => (-0.0f64).to_le_bytes().to_vec(),
+                _ => panic!("float with {} bits not supported", bits),
+            };
+            let data = self.prog.define_data(ir::DataInfo { ty: self.prog.ty_ptr(), inner_ty: ty_ir, bytes });
+            let zero = ir::InstructionInfo::Load { addr: ir::Value::Data(data), ty: ty_ir };
+            let zero = self.append_instr(block, zero);
+
+            let instr = ir::InstructionInfo::FloatArithmetic {
+                kind: ir::ArithmeticOp::Sub,
+                left: ir::Value::Instr(zero),
+                right: value,
+            };
+            return ir::Value::Instr(self.append_instr(block, instr));
+        }
+
         let instr = ir::InstructionInfo::Arithmetic {
             kind: ir::ArithmeticOp::Sub,
             left: ir::Value::Const(ir::Const::new(ty_ir, 0)),
@@ -131,6 +315,46 @@ impl<'ir, 'ast, 'cst, 'ts, F: Fn(ScopedValue) -> LRValue> LowerFuncState<'ir, 'a
         }
     }
 
+    /// Apply the implicit conversion needed to use `value` where a value of type `ty` is expected, if any. This
+    /// mirrors the coercions allowed by the type solver: pointers to arrays become slices or pointers to their first
+    /// element and bytes are widened.
+    #[must_use]
+    fn append_coerce(&mut self, block: ir::Block, value: TypedValue, ty: cst::Type) -> TypedValue {
+        if let &TypeInfo::Pointer(inner) = &self.types[value.ty] {
+            if let &TypeInfo::Array(ArrayTypeInfo { inner: element, length }) = &self.types[inner] {
+                match self.types[ty] {
+                    TypeInfo::Slice(_) => {
+                        let len = ir::Value::Const(ir::Const::new(self.prog.ty_int(), length as i32));
+                        return self.append_fat_pointer_value(block, ty, value.ir, len);
+                    }
+                    TypeInfo::Pointer(target) if target == element => {
+                        let element_ir = self.types.map_type(self.prog, element);
+                        let zero = ir::Value::Const(ir::Const::new(self.prog.ty_int(), 0));
+                        let first = ir::InstructionInfo::PointerOffSet { ty: element_ir, base: value.ir, index: zero };
+                        return TypedValue { ty, ir: ir::Value::Instr(self.append_instr(block, first)) };
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        self.append_widen(block, value, ty)
+    }
+
+    /// Widen `value` to `int` if it's a `byte` and `ty` is `int`, otherwise return it unchanged.
+    /// This mirrors the implicit widening done by the type solver for operands and arguments.
+    #[must_use]
+    fn append_widen(&mut self, block: ir::Block, value: TypedValue, ty: cst::Type) -> TypedValue {
+        match (&self.types[value.ty], &self.types[ty]) {
+            (TypeInfo::Byte, TypeInfo::Int) => {
+                let ty_ir = self.types.map_type(self.prog, ty);
+                let cast = ir::InstructionInfo::Cast { kind: ir::CastKind::ZeroExtend, ty: ty_ir, value: value.ir };
+                TypedValue { ty, ir: ir::Value::Instr(self.append_instr(block, cast)) }
+            }
+            _ => value,
+        }
+    }
+
     //Return the "never" value returned by expressions like break, continue and return
     #[must_use]
     fn never_value(&mut self, ty: cst::Type) -> LRValue {
@@ -172,6 +396,15 @@ impl<'ir, 'ast, 'cst, 'ts, F: Fn(ScopedValue) -> LRValue> LowerFuncState<'ir, 'a
         flow: Flow,
         scope: &Scope<ScopedItem>,
         expr: &'ast ast::Expression,
+    ) -> Result<'ast, (Flow, LRValue)> {
+        self.with_span(expr.span, |s| s.append_expr_inner(flow, scope, expr))
+    }
+
+    fn append_expr_inner(
+        &mut self,
+        flow: Flow,
+        scope: &Scope<ScopedItem>,
+        expr: &'ast ast::Expression,
     ) -> Result<'ast, (Flow, LRValue)> {
         let result: (Flow, LRValue) = match &expr.kind {
             ast::ExpressionKind::Null => {
@@ -190,51 +423,34 @@ impl<'ir, 'ast, 'cst, 'ts, F: Fn(ScopedValue) -> LRValue> LowerFuncState<'ir, 'a
             }
             ast::ExpressionKind::IntLit { value } => {
                 let ty = self.expr_type(expr);
-
-                let size_in_bits = match self.types[ty] {
-                    TypeInfo::Byte => Ok(8),
-                    TypeInfo::Int => Ok(32),
-                    _ => Err(Error::ExpectIntegerType {
-                        expression: expr,
-                        actual: self.types.format_type(ty).to_string(),
-                    }),
-                }?;
-
-                let ty_ir = self.prog.define_type_int(size_in_bits);
-
-                //TODO this is not correct, what about negative values? also disallow byte overflow
-                let value = value.parse::<i32>()
-                    .map_err(|_| Error::InvalidLiteral {
-                        span: expr.span,
-                        lit: value.clone(),
-                        ty: self.types.format_type(ty).to_string(),
-                    })?;
-
-                let cst = ir::Value::Const(ir::Const { ty: ty_ir, value });
-
-                (flow, LRValue::Right(TypedValue { ty, ir: cst }))
+                let cst = map_int_literal(self.types, self.prog, expr, value, ty, false)?;
+                (flow, LRValue::Right(TypedValue { ty, ir: ir::Value::Const(cst) }))
+            }
+            ast::ExpressionKind::FloatLit { value } => {
+                let ty = self.expr_type(expr);
+                let value = map_float_literal(self.types, self.prog, expr, value, ty)?;
+                (flow, value)
             }
             ast::ExpressionKind::StringLit { value } => {
-                let ty_byte = self.types.type_byte();
-                let ty_byte_ptr = self.types.define_type_ptr(ty_byte);
+                let (ptr, len) = self.define_string(value.clone());
 
-                let data = ir::DataInfo {
-                    ty: self.types.map_type(self.prog, ty_byte_ptr),
-                    inner_ty: self.types.map_type(self.prog, ty_byte),
-                    bytes: value.bytes().collect(),
-                };
-                let data = self.prog.define_data(data);
-                let data = ir::Value::Data(data);
-
-                (flow, LRValue::Right(TypedValue { ty: ty_byte_ptr, ir: data }))
+                let ty_str = self.types.type_str();
+                let value = self.append_fat_pointer_value(flow.block, ty_str, ptr, len);
+                (flow, LRValue::Right(value))
             }
             ast::ExpressionKind::Path(path) => {
                 let item = self.items.resolve_path(ScopeKind::Real, scope, path)?;
 
-                let value = if let ScopedItem::Value(value) = item {
-                    (self.map_value)(value)
-                } else {
-                    unreachable!()
+                let value = match item {
+                    ScopedItem::Value(ScopedValue::EnumVariant { ty, variant }) => {
+                        let info = self.types[ty].unwrap_enum().unwrap();
+                        if !info.variants[variant as usize].fields.is_empty() {
+                            return Err(Error::EnumVariantNotCalled(path));
+                        }
+                        self.append_enum_value(flow.block, ty, variant, &[])
+                    }
+                    ScopedItem::Value(value) => (self.map_value)(value),
+                    _ => unreachable!(),
                 };
 
                 (flow, value)
@@ -243,42 +459,63 @@ impl<'ir, 'ast, 'cst, 'ts, F: Fn(ScopedValue) -> LRValue> LowerFuncState<'ir, 'a
                 let ty = self.expr_type(expr);
                 let ty_ir = self.types.map_type(self.prog, ty);
 
-                let result_slot = self.define_slot(ty_ir);
                 let (after_cond, cond) =
                     self.append_expr_loaded(flow, scope, condition)?;
-
-                //TODO is it possible to do append_expr here instead? is an LValue ternary operator useful?
-                //  and how does this interact with LRValue? we need to propagate the LR-ness
-                //  eg (c ? a : b)[6] = 3
-                let end_start = self.append_if(
-                    after_cond,
-                    cond.ir,
-                    //TODO any way to remove this code duplication?
-                    |s: &mut Self, then_start: Flow| {
-                        let (then_end, then_value) =
-                            s.append_expr_loaded(then_start, scope, then_value)?;
-
-                        let store = ir::InstructionInfo::Store { addr: ir::Value::Slot(result_slot), ty: ty_ir, value: then_value.ir };
-                        s.append_instr(then_end.block, store);
-
-                        Ok(then_end)
-                    },
-                    |s: &mut Self, else_start: Flow| {
-                        let (else_end, else_value) =
-                            s.append_expr_loaded(else_start, scope, else_value)?;
-
-                        let store = ir::InstructionInfo::Store { addr: ir::Value::Slot(result_slot), ty: ty_ir, value: else_value.ir };
-                        s.append_instr(else_end.block, store);
-
-                        Ok(else_end)
-                    },
-                )?;
-
-                let load = ir::InstructionInfo::Load { ty: ty_ir, addr: ir::Value::Slot(result_slot) };
-                let load = self.append_instr(end_start.block, load);
-                let result_value = ir::Value::Instr(load);
-
-                (end_start, LRValue::Right(TypedValue { ty, ir: result_value }))
+                let (then_scope, else_scope) = self.narrowed_scopes(scope, condition)?;
+
+                //evaluate both sides and pick one without branching if that's cheap and safe
+                let ty_ir_info = self.prog.get_type(ty_ir);
+                let register_sized = ty_ir_info.unwrap_int().is_some() || ty_ir_info.is_ptr();
+                if register_sized && is_simple_value(then_value) && is_simple_value(else_value) {
+                    let (after_then, then_value) =
+                        self.append_expr_loaded(after_cond, &then_scope, then_value)?;
+                    let then_value = self.append_coerce(after_then.block, then_value, ty);
+                    let (after_else, else_value) =
+                        self.append_expr_loaded(after_then, &else_scope, else_value)?;
+                    let else_value = self.append_coerce(after_else.block, else_value, ty);
+
+                    let select = ir::InstructionInfo::Select { cond: cond.ir, true_value: then_value.ir, false_value: else_value.ir };
+                    let select = self.append_instr(after_else.block, select);
+
+                    (after_else, LRValue::Right(TypedValue { ty, ir: ir::Value::Instr(select) }))
+                } else {
+                    let result_slot = self.define_slot(ty_ir);
+
+                    //TODO is it possible to do append_expr here instead? is an LValue ternary operator useful?
+                    //  and how does this interact with LRValue? we need to propagate the LR-ness
+                    //  eg (c ? a : b)[6] = 3
+                    let end_start = self.append_if(
+                        after_cond,
+                        cond.ir,
+                        //TODO any way to remove this code duplication?
+                        |s: &mut Self, then_start: Flow| {
+                            let (then_end, then_value) =
+                                s.append_expr_loaded(then_start, &then_scope, then_value)?;
+                            let then_value = s.append_coerce(then_end.block, then_value, ty);
+
+                            let store = ir::InstructionInfo::Store { addr: ir::Value::Slot(result_slot), ty: ty_ir, value: then_value.ir };
+                            s.append_instr(then_end.block, store);
+
+                            Ok(then_end)
+                        },
+                        |s: &mut Self, else_start: Flow| {
+                            let (else_end, else_value) =
+                                s.append_expr_loaded(else_start, &else_scope, else_value)?;
+                            let else_value = s.append_coerce(else_end.block, else_value, ty);
+
+                            let store = ir::InstructionInfo::Store { addr: ir::Value::Slot(result_slot), ty: ty_ir, value: else_value.ir };
+                            s.append_instr(else_end.block, store);
+
+                            Ok(else_end)
+                        },
+                    )?;
+
+                    let load = ir::InstructionInfo::Load { ty: ty_ir, addr: ir::Value::Slot(result_slot) };
+                    let load = self.append_instr(end_start.block, load);
+                    let result_value = ir::Value::Instr(load);
+
+                    (end_start, LRValue::Right(TypedValue { ty, ir: result_value }))
+                }
             }
             ast::ExpressionKind::Binary { kind, left, right } => {
                 let (after_left, value_left) =
@@ -287,7 +524,15 @@ impl<'ir, 'ast, 'cst, 'ts, F: Fn(ScopedValue) -> LRValue> LowerFuncState<'ir, 'a
                     self.append_expr_loaded(after_left, scope, right)?;
 
                 let result_ty = self.expr_type(expr);
-                let result = if let Some(inner_ty) = self.types[result_ty].unwrap_ptr() {
+                let result = if let Some(&func) = self.items.operators.get(&(*kind, value_left.ty)) {
+                    //overloaded operator
+                    let target = match (self.map_value)(ScopedValue::Function(func)) {
+                        LRValue::Right(target) => target.ir,
+                        LRValue::Left(_) => panic!("Functions should be rvalues"),
+                    };
+                    let call = ir::InstructionInfo::Call { target, args: vec![value_left.ir, value_right.ir] };
+                    ir::Value::Instr(self.append_instr(after_right.block, call))
+                } else if let Some(inner_ty) = self.types[result_ty].unwrap_ptr() {
                     //pointer offset
                     let offset_ir = match kind {
                         ast::BinaryOp::Add =>
@@ -301,8 +546,11 @@ impl<'ir, 'ast, 'cst, 'ts, F: Fn(ScopedValue) -> LRValue> LowerFuncState<'ir, 'a
                     let instr = ir::InstructionInfo::PointerOffSet { base: value_left.ir, ty: inner_ty_ir, index: offset_ir };
                     ir::Value::Instr(self.append_instr(after_right.block, instr))
                 } else {
-                    //basic binary operation
-                    let instr = binary_op_to_instr(*kind, value_left.ir, value_right.ir);
+                    //basic binary operation, mixed byte and int operands are widened to int first
+                    let value_left = self.append_widen(after_right.block, value_left, value_right.ty);
+                    let value_right = self.append_widen(after_right.block, value_right, value_left.ty);
+
+                    let instr = binary_op_to_instr(*kind, &self.types[value_left.ty], value_left.ir, value_right.ir);
                     ir::Value::Instr(self.append_instr(after_right.block, instr))
                 };
 
@@ -329,6 +577,13 @@ impl<'ir, 'ast, 'cst, 'ts, F: Fn(ScopedValue) -> LRValue> LowerFuncState<'ir, 'a
                         (after_value, LRValue::Left(value))
                     }
                     ast::UnaryOp::Neg => {
+                        //negated literals are folded immediately, only they can be the minimum value of `int`
+                        if let ast::ExpressionKind::IntLit { value } = &inner.kind {
+                            let ty = self.expr_type(inner);
+                            let cst = map_int_literal(self.types, self.prog, inner, value, ty, true)?;
+                            return Ok((flow, LRValue::Right(TypedValue { ty, ir: ir::Value::Const(cst) })));
+                        }
+
                         let (after_inner, inner) =
                             self.append_expr_loaded(flow, scope, inner)?;
                         let ty = inner.ty;
@@ -338,18 +593,103 @@ impl<'ir, 'ast, 'cst, 'ts, F: Fn(ScopedValue) -> LRValue> LowerFuncState<'ir, 'a
                     }
                 }
             }
+            ast::ExpressionKind::ArrayRepeat { value, length } => {
+                let ty = self.expr_type(expr);
+                let ty_ir = self.types.map_type(self.prog, ty);
+
+                let (after_value, value) = self.append_expr_loaded(flow, scope, value)?;
+                let inner_ty_ir = self.types.map_type(self.prog, value.ty);
+
+                //fill a temporary slot and load the result from it
+                let slot = ir::Value::Slot(self.define_slot(ty_ir));
+                let after_fill = self.append_array_fill(after_value, slot, inner_ty_ir, value.ir, *length)?;
+
+                let load = ir::InstructionInfo::Load { ty: ty_ir, addr: slot };
+                let load = self.append_instr(after_fill.block, load);
+
+                (after_fill, LRValue::Right(TypedValue { ty, ir: ir::Value::Instr(load) }))
+            }
+            ast::ExpressionKind::Tuple { values } => {
+                let ty = self.expr_type(expr);
+                let ty_ir = self.types.map_type(self.prog, ty);
+
+                //store each value in a temporary slot and load the result from it
+                let slot = ir::Value::Slot(self.define_slot(ty_ir));
+                let mut curr = flow;
+                for (index, value) in values.iter().enumerate() {
+                    let (after_value, value) = self.append_expr_loaded(curr, scope, value)?;
+                    let value_ty_ir = self.types.map_type(self.prog, value.ty);
+
+                    let field_ptr = self.append_instr(after_value.block, ir::InstructionInfo::TupleFieldPtr { base: slot, index: index as u32, tuple_ty: ty_ir });
+                    self.append_instr(after_value.block, ir::InstructionInfo::Store { addr: ir::Value::Instr(field_ptr), ty: value_ty_ir, value: value.ir });
+                    curr = after_value;
+                }
+
+                let load = self.append_instr(curr.block, ir::InstructionInfo::Load { ty: ty_ir, addr: slot });
+                (curr, LRValue::Right(TypedValue { ty, ir: ir::Value::Instr(load) }))
+            }
+            ast::ExpressionKind::StructLit { path: _, fields, base } => {
+                let ty = self.expr_type(expr);
+                let ty_ir = self.types.map_type(self.prog, ty);
+                let info = self.types[ty].unwrap_struct().unwrap().clone();
+
+                //evaluate the fields in order, followed by the base
+                let mut values = Vec::with_capacity(fields.len());
+                let mut flow = flow;
+                for field in fields {
+                    let (after_value, value) = self.append_expr_loaded(flow, scope, &field.value)?;
+                    let index = info.find_field_index(&field.id.string).unwrap();
+                    values.push((index, value));
+                    flow = after_value;
+                }
+
+                //fill a temporary slot, starting from the base if there is one, and load the result from it
+                let slot = ir::Value::Slot(self.define_slot(ty_ir));
+                if let Some(base) = base {
+                    let (after_base, base) = self.append_expr_loaded(flow, scope, base)?;
+                    self.append_instr(after_base.block, ir::InstructionInfo::Store { addr: slot, ty: ty_ir, value: base.ir });
+                    flow = after_base;
+                }
+
+                for (index, value) in values {
+                    let field_ty = info.fields[index as usize].ty;
+                    let field_ty_ir = self.types.map_type(self.prog, field_ty);
+                    let value = self.append_coerce(flow.block, value, field_ty);
+                    let field_ptr = self.append_instr(flow.block, ir::InstructionInfo::TupleFieldPtr { base: slot, index, tuple_ty: ty_ir });
+                    self.append_instr(flow.block, ir::InstructionInfo::Store { addr: ir::Value::Instr(field_ptr), ty: field_ty_ir, value: value.ir });
+                }
+
+                let load = self.append_instr(flow.block, ir::InstructionInfo::Load { ty: ty_ir, addr: slot });
+                (flow, LRValue::Right(TypedValue { ty, ir: ir::Value::Instr(load) }))
+            }
             ast::ExpressionKind::Call { target, args } => {
+                let args = &self.items.order_call_args(scope, expr, target, args)?;
+
+                //enum variants with a payload are constructed directly instead of called
+                if let ast::ExpressionKind::Path(path) = &target.kind {
+                    let item = self.items.resolve_path(ScopeKind::Real, scope, path)?;
+                    if let ScopedItem::Value(ScopedValue::EnumVariant { ty, variant }) = item {
+                        let fields = self.types[ty].unwrap_enum().unwrap().variants[variant as usize].fields.clone();
+                        let (after_args, ir_args) = self.append_args(flow, scope, args, &fields)?;
+                        let value = self.append_enum_value(after_args.block, ty, variant, &ir_args);
+                        return Ok((after_args, value));
+                    }
+                }
+
+                let target_ty = self.expr_type(target);
+                if let Some(info) = self.types[target_ty].unwrap_closure() {
+                    let info = info.clone();
+                    return self.append_closure_call(flow, scope, target, args, &info);
+                }
+
                 //evaluate target
                 let (after_target, target_value) = self.append_expr_loaded(flow, scope, target)?;
-                let ret_ty = self.types[target_value.ty].unwrap_func().unwrap().ret;
+                let info = self.types[target_value.ty].unwrap_func().unwrap();
+                let ret_ty = info.ret;
+                let params = info.params.clone();
 
                 // evaluate args
-                let mut ir_args = Vec::with_capacity(args.len());
-                let after_args = args.iter().try_fold(after_target, |flow, arg| {
-                    let (after_value, value) = self.append_expr_loaded(flow, scope, arg)?;
-                    ir_args.push(value.ir);
-                    Ok(after_value)
-                })?;
+                let (after_args, ir_args) = self.append_args(after_target, scope, args, &params)?;
 
                 //actual call
                 let call = ir::InstructionInfo::Call {
@@ -360,6 +700,22 @@ impl<'ir, 'ast, 'cst, 'ts, F: Fn(ScopedValue) -> LRValue> LowerFuncState<'ir, 'a
 
                 (after_args, LRValue::Right(TypedValue { ty: ret_ty, ir: ir::Value::Instr(call) }))
             }
+            ast::ExpressionKind::Block(block) => {
+                let (after_block, value) = self.append_block_value(flow, scope, block)?;
+                //blocks without a value are either void or never
+                let value = value.unwrap_or_else(|| {
+                    let ty = self.expr_type(expr);
+                    TypedValue { ty, ir: ir::Value::Undef(self.types.map_type(self.prog, ty)) }
+                });
+                (after_block, LRValue::Right(value))
+            }
+            ast::ExpressionKind::Closure { params, ret_ty: _, body } => {
+                self.append_closure(flow, scope, expr, params, body)?
+            }
+            ast::ExpressionKind::DotIndex { target, index: ast::DotIndexIndex::Struct(id) }
+            if matches!(self.types[self.expr_type(target)], TypeInfo::Str | TypeInfo::Slice(_)) => {
+                self.append_fat_pointer_field(flow, scope, target, id)?
+            }
             ast::ExpressionKind::DotIndex { target, index } => {
                 //TODO currently we only allow LValue(&Struct),
                 //  but we could add support for RValue(Struct) and RValue(&Struct) as well
@@ -405,7 +761,45 @@ impl<'ir, 'ast, 'cst, 'ts, F: Fn(ScopedValue) -> LRValue> LowerFuncState<'ir, 'a
 
                 (after_target, LRValue::Left(TypedValue { ty: result_ty_ptr, ir: ir::Value::Instr(struct_sub_ptr) }))
             }
+            ast::ExpressionKind::ArrayIndex { target, index } if matches!(self.types[self.expr_type(target)], TypeInfo::Slice(_)) => {
+                let (after_target, target_value) = self.append_expr(flow, scope, target)?;
+                let (after_index, index) = self.append_expr_loaded(after_target, scope, index)?;
+
+                let result_ty = self.expr_type(expr);
+                let result_ty_ir = self.types.map_type(self.prog, result_ty);
+                let result_ty_ptr = self.types.define_type_ptr(result_ty);
+
+                let (ptr, len) = self.append_fat_pointer_parts(after_index.block, target_value);
+                let after_check = self.append_bounds_check(after_index, expr.span, index.ir, len, ir::LogicalOp::Lt(ir::Signedness::Signed));
+
+                let ptr = ir::InstructionInfo::PointerOffSet { ty: result_ty_ir, base: ptr, index: index.ir };
+                let ptr = self.append_instr(after_check.block, ptr);
+
+                (after_check, LRValue::Left(TypedValue { ty: result_ty_ptr, ir: ir::Value::Instr(ptr) }))
+            }
+            ast::ExpressionKind::ArrayIndex { target, index } if matches!(self.types[self.expr_type(target)], TypeInfo::Pointer(_)) => {
+                //indexing through a pointer treats it as pointing to the first element of an array, unchecked
+                let (after_target, target_value) = self.append_expr_loaded(flow, scope, target)?;
+                let (after_index, index) = self.append_expr_loaded(after_target, scope, index)?;
+
+                let result_ty = self.expr_type(expr);
+                let result_ty_ir = self.types.map_type(self.prog, result_ty);
+                let result_ty_ptr = self.types.define_type_ptr(result_ty);
+
+                let ptr = ir::InstructionInfo::PointerOffSet { ty: result_ty_ir, base: target_value.ir, index: index.ir };
+                let ptr = self.append_instr(after_index.block, ptr);
+
+                (after_index, LRValue::Left(TypedValue { ty: result_ty_ptr, ir: ir::Value::Instr(ptr) }))
+            }
+            ast::ExpressionKind::Slice { target, start, end } => {
+                self.append_slice(flow, scope, expr, target, start.as_deref(), end.as_deref())?
+            }
             ast::ExpressionKind::ArrayIndex { target, index } => {
+                let length = match self.types[self.expr_type(target)] {
+                    TypeInfo::Array(ArrayTypeInfo { inner: _, length }) => length,
+                    _ => panic!("indexing non-array type"),
+                };
+
                 let (after_target, target_value) = self.append_expr_lvalue(flow, scope, target)?;
                 let (after_index, index) = self.append_expr_loaded(after_target, scope, index)?;
 
@@ -413,25 +807,125 @@ impl<'ir, 'ast, 'cst, 'ts, F: Fn(ScopedValue) -> LRValue> LowerFuncState<'ir, 'a
                 let result_ty_ir = self.types.map_type(self.prog, result_ty);
                 let result_ty_ptr = self.types.define_type_ptr(result_ty);
 
+                let len = ir::Value::Const(ir::Const::new(self.prog.ty_int(), length as i32));
+                let after_check = self.append_bounds_check(after_index, expr.span, index.ir, len, ir::LogicalOp::Lt(ir::Signedness::Signed));
+
                 let array_index_ptr = ir::InstructionInfo::PointerOffSet {
                     ty: result_ty_ir,
                     base: target_value.ir,
                     index: index.ir,
                 };
-                let array_index_ptr = self.append_instr(after_index.block, array_index_ptr);
+                let array_index_ptr = self.append_instr(after_check.block, array_index_ptr);
 
-                (after_index, LRValue::Left(TypedValue { ty: result_ty_ptr, ir: ir::Value::Instr(array_index_ptr) }))
+                (after_check, LRValue::Left(TypedValue { ty: result_ty_ptr, ir: ir::Value::Instr(array_index_ptr) }))
             }
             ast::ExpressionKind::Cast { value, ty: _ } => {
                 let (after_value, value) = self.append_expr_loaded(flow, scope, value)?;
                 let result_ty = self.expr_type(expr);
 
-                // only the type changes, the (untyped) pointer value stays the same
-                (after_value, LRValue::Right(TypedValue { ty: result_ty, ir: value.ir }))
+                let kind = match (&self.types[value.ty], &self.types[result_ty]) {
+                    (TypeInfo::Pointer(_) | TypeInfo::OptionalPointer(_), TypeInfo::Int) => Some(ir::CastKind::PointerToInt),
+                    (TypeInfo::Int, TypeInfo::Pointer(_) | TypeInfo::OptionalPointer(_)) => Some(ir::CastKind::IntToPointer),
+                    (TypeInfo::Int, TypeInfo::Byte) => Some(ir::CastKind::Truncate),
+                    (TypeInfo::Bool | TypeInfo::Byte, TypeInfo::Int) | (TypeInfo::Bool, TypeInfo::Byte) => Some(ir::CastKind::ZeroExtend),
+                    _ => None,
+                };
+
+                let result = match kind {
+                    Some(kind) => {
+                        let ty = self.types.map_type(self.prog, result_ty);
+                        let cast = ir::InstructionInfo::Cast { kind, ty, value: value.ir };
+                        ir::Value::Instr(self.append_instr(after_value.block, cast))
+                    }
+                    // only the type changes, the (untyped) pointer value stays the same
+                    None => value.ir,
+                };
+
+                (after_value, LRValue::Right(TypedValue { ty: result_ty, ir: result }))
+            }
+            ast::ExpressionKind::Alloc { size } => {
+                let (after_size, size) = self.append_expr_loaded(flow, scope, size)?;
+                let ptr = self.append_alloc(after_size.block, size.ir);
+                (after_size, LRValue::Right(TypedValue { ty: self.expr_type(expr), ir: ptr }))
+            }
+            ast::ExpressionKind::Free { ptr } => {
+                let (after_ptr, ptr) = self.append_expr_loaded(flow, scope, ptr)?;
+
+                let free = self.runtime.free(self.prog);
+                let call = ir::InstructionInfo::Call { target: free, args: vec![ptr.ir] };
+                self.append_instr(after_ptr.block, call);
+
+                let ty_void = self.types.type_void();
+                (after_ptr, LRValue::Right(TypedValue { ty: ty_void, ir: ir::Value::Undef(self.prog.ty_ptr()) }))
+            }
+            ast::ExpressionKind::New { ty: _ } => {
+                let ty = self.expr_type(expr);
+                let inner_ty = self.types[ty].unwrap_ptr().unwrap();
+                let inner_ty_ir = self.types.map_type(self.prog, inner_ty);
+
+                let size = Layout::for_type(self.prog, inner_ty_ir).size;
+                let size = ir::Value::Const(ir::Const::new(self.prog.ty_int(), size));
+                let ptr = self.append_alloc(flow.block, size);
+                (flow, LRValue::Right(TypedValue { ty, ir: ptr }))
+            }
+            ast::ExpressionKind::Panic { message } => {
+                let (after_message, message) = self.append_expr(flow, scope, message)?;
+                let (ptr, len) = self.append_fat_pointer_parts(after_message.block, message);
+                self.append_panic(after_message.block, expr.span, ptr, len);
+
+                //continue writing dead code
+                (self.new_flow(false), self.never_value(self.expr_type(expr)))
+            }
+            ast::ExpressionKind::InlineAsm { template, operands } => {
+                let mut inputs = vec![];
+                let mut outputs = vec![];
+                let mut curr = flow;
+
+                for operand in operands {
+                    //outputs are stored through a pointer, so they need to be lvalues
+                    let (after_value, ty, value) = match operand.kind {
+                        ast::AsmOperandKind::In => {
+                            let (after_value, value) = self.append_expr_loaded(curr, scope, &operand.value)?;
+                            (after_value, value.ty, value.ir)
+                        }
+                        ast::AsmOperandKind::Out => {
+                            let (after_value, addr) = self.append_expr_lvalue(curr, scope, &operand.value)?;
+                            let inner_ty = self.types[addr.ty].unwrap_ptr()
+                                .expect("lvalue should have pointer type");
+                            (after_value, inner_ty, addr.ir)
+                        }
+                    };
+
+                    //only values that fit in a single register are allowed
+                    match self.types[ty] {
+                        TypeInfo::Int | TypeInfo::Byte | TypeInfo::Bool | TypeInfo::Pointer(_) | TypeInfo::OptionalPointer(_) => {}
+                        _ => return Err(Error::InvalidAsmOperandType {
+                            operand,
+                            ty: self.types.format_type(ty).to_string(),
+                        }),
+                    }
+
+                    let ty = self.types.map_type(self.prog, ty);
+                    let operand_ir = ir::AsmOperand { constraint: operand.constraint.clone(), ty, value };
+                    match operand.kind {
+                        ast::AsmOperandKind::In => inputs.push(operand_ir),
+                        ast::AsmOperandKind::Out => outputs.push(operand_ir),
+                    }
+
+                    curr = after_value;
+                }
+
+                let asm = ir::InstructionInfo::InlineAsm { template: template.clone(), inputs, outputs };
+                self.append_instr(curr.block, asm);
+
+                let ty_void = self.types.type_void();
+                (curr, LRValue::Right(TypedValue { ty: ty_void, ir: ir::Value::Undef(self.prog.ty_ptr()) }))
             }
             ast::ExpressionKind::Return { value } => {
                 let (after_value, value) = if let Some(value) = value {
-                    self.append_expr_loaded(flow, scope, value)?
+                    let (after_value, value) = self.append_expr_loaded(flow, scope, value)?;
+                    let value = self.append_coerce(after_value.block, value, self.ret_ty);
+                    (after_value, value)
                 } else {
                     //check that function return type is indeed void
                     let ty_void = self.types.type_void();
@@ -444,6 +938,10 @@ impl<'ir, 'ast, 'cst, 'ts, F: Fn(ScopedValue) -> LRValue> LowerFuncState<'ir, 'a
                 //continue writing dead code
                 (self.new_flow(false), self.never_value(self.expr_type(expr)))
             }
+            ast::ExpressionKind::Unreachable => {
+                //the current block keeps its default unreachable terminator, continue writing dead code
+                (self.new_flow(false), self.never_value(self.expr_type(expr)))
+            }
             ast::ExpressionKind::Continue =>
                 self.append_break_or_continue(flow, expr, ContinueOrBreak::Continue)?,
             ast::ExpressionKind::Break =>
@@ -453,7 +951,7 @@ impl<'ir, 'ast, 'cst, 'ts, F: Fn(ScopedValue) -> LRValue> LowerFuncState<'ir, 'a
         //check that the returned value's type is indeed expect_ty
         if cfg!(debug_assertions) {
             let expect_ty = self.expr_type(expr);
-            let actual_ty = result.1.ty(&self.types);
+            let actual_ty = result.1.ty(self.types);
 
             assert_eq!(
                 expect_ty, actual_ty,
@@ -503,6 +1001,24 @@ impl<'ir, 'ast, 'cst, 'ts, F: Fn(ScopedValue) -> LRValue> LowerFuncState<'ir, 'a
         Ok((after_value, loaded_value))
     }
 
+    fn append_args(
+        &mut self,
+        flow: Flow,
+        scope: &Scope<ScopedItem>,
+        args: &[&'ast ast::Expression],
+        params: &[cst::Type],
+    ) -> Result<'ast, (Flow, Vec<ir::Value>)> {
+        let mut ir_args = Vec::with_capacity(args.len());
+        let after_args = zip_eq(args, params).try_fold(flow, |flow, (arg, &param)| {
+            let (after_value, value) = self.append_expr_loaded(flow, scope, arg)?;
+            let value = self.append_coerce(after_value.block, value, param);
+            ir_args.push(value.ir);
+            Ok(after_value)
+        })?;
+
+        Ok((after_args, ir_args))
+    }
+
     fn append_expr_lvalue(
         &mut self,
         flow: Flow,
@@ -517,8 +1033,10 @@ impl<'ir, 'ast, 'cst, 'ts, F: Fn(ScopedValue) -> LRValue> LowerFuncState<'ir, 'a
         }
     }
 
+    /// Append a loop that runs `body` as long as the condition is true. A condition of `None` means the loop can
+    /// only be exited through `break`, so the code after it is unreachable otherwise.
     fn append_loop<
-        C: FnOnce(&mut Self, Flow) -> Result<'ast, (Flow, ir::Value)>,
+        C: FnOnce(&mut Self, Flow) -> Result<'ast, (Flow, Option<ir::Value>)>,
         B: FnOnce(&mut Self, Flow) -> Result<'ast, Flow>
     >(&mut self, flow: Flow, cond: C, body: B) -> Result<'ast, Flow> {
         //condition
@@ -546,8 +1064,13 @@ impl<'ir, 'ast, 'cst, 'ts, F: Fn(ScopedValue) -> LRValue> LowerFuncState<'ir, 'a
         end_start.needs_return |= loop_info.end_needs_return;
 
         //connect everything
-        let branch = new_branch(cond, body_start_block, end_start.block);
-        end_start.needs_return |= cond_end.needs_return;
+        let branch = match cond {
+            Some(cond) => {
+                end_start.needs_return |= cond_end.needs_return;
+                new_branch(cond, body_start_block, end_start.block)
+            }
+            None => ir::Terminator::Jump { target: new_target(body_start_block) },
+        };
         let jump_cond = ir::Terminator::Jump { target: new_target(cond_start_block) };
 
         self.prog.get_block_mut(flow.block).terminator = jump_cond.clone();
@@ -558,64 +1081,979 @@ impl<'ir, 'ast, 'cst, 'ts, F: Fn(ScopedValue) -> LRValue> LowerFuncState<'ir, 'a
         Ok(end_start)
     }
 
-    fn append_statement(&mut self, flow: Flow, scope: &mut Scope<ScopedItem>, stmt: &'ast ast::Statement) -> Result<'ast, Flow> {
-        match &stmt.kind {
-            ast::StatementKind::Declaration(decl) => {
-                assert!(!decl.mutable, "everything is mutable for now");
-
-                let (after_value, value) = if let Some(init) = &decl.init {
-                    let (after_value, value) = self.append_expr_loaded(flow, scope, &init)?;
-                    (after_value, Some(value))
-                } else {
-                    (flow, None)
+    /// Append a for loop over the integers in `start..end`, in increments of `step` if there is one.
+    fn append_range_for(
+        &mut self,
+        flow: Flow,
+        scope: &Scope<ScopedItem>,
+        for_stmt: &'ast ast::ForStatement,
+        start: &'ast ast::Expression,
+        end: &'ast ast::Expression,
+        step: Option<&'ast ast::Expression>,
+    ) -> Result<'ast, Flow> {
+        //figure out the index type
+        let index_ty = self.expr_type(start);
+        let index_ty_ptr = self.types.define_type_ptr(index_ty);
+        let index_ty_ir = self.types.map_type(self.prog, index_ty);
+        let index_signedness = signedness(&self.types[index_ty]);
+
+        //evaluate the range
+        let (flow, start_value) =
+            self.append_expr_loaded(flow, scope, start)?;
+        let (flow, end_value) =
+            self.append_expr_loaded(flow, scope, end)?;
+
+        //evaluate the step and its direction
+        let (flow, step_value, ascending) = match step {
+            None => (flow, ir::Value::Const(ir::Const { ty: index_ty_ir, value: 1 }), None),
+            Some(step) => {
+                let (flow, step_value) = self.append_expr_loaded(flow, scope, step)?;
+                let ascending = ir::InstructionInfo::Comparison {
+                    kind: ir::LogicalOp::Gt(index_signedness),
+                    left: step_value.ir,
+                    right: ir::Value::Const(ir::Const { ty: index_ty_ir, value: 0 }),
                 };
+                let ascending = self.append_instr(flow.block, ascending);
+                (flow, step_value.ir, Some(ir::Value::Instr(ascending)))
+            }
+        };
 
-                //construct the types
-                let ty = self.type_solution[*self.decl_type_map.get(&(decl as *const _)).unwrap()];
-                let ty_ptr = self.types.define_type_ptr(ty);
-                let ty_ir = self.types.map_type(self.prog, ty);
-
-                //define the slot
-                let slot = self.define_slot(ty_ir);
-                let slot_value = LRValue::Left(TypedValue { ty: ty_ptr, ir: ir::Value::Slot(slot) });
-                let item = ScopedItem::Value(ScopedValue::Immediate(slot_value));
-                scope.maybe_declare(&decl.id, item)?;
+        //declare slot for index
+        let mut index_scope = scope.nest();
+        let index_slot = self.define_slot(index_ty_ir);
+        let index_slot = ir::Value::Slot(index_slot);
+
+        //TODO this allows the index to be mutated, which is fine for now, but it should be marked immutable when that is implemented
+        //TODO maybe consider changing the increment to use the index loaded at the beginning so it can't really be mutated after all
+        let index_slot_value = LRValue::Left(TypedValue { ty: index_ty_ptr, ir: index_slot });
+        let item = ScopedItem::Value(ScopedValue::Immediate(index_slot_value));
+        index_scope.maybe_declare(&for_stmt.index, item)?;
+
+        //index = start
+        self.append_instr(flow.block, ir::InstructionInfo::Store { addr: index_slot, ty: index_ty_ir, value: start_value.ir });
+
+        //index < end, or index > end when counting down
+        let cond = |s: &mut Self, cond_start: Flow| {
+            let compare = |s: &mut Self, flow: Flow, kind: ir::LogicalOp| {
+                let load = ir::InstructionInfo::Load { ty: index_ty_ir, addr: index_slot };
+                let load = s.append_instr(flow.block, load);
+
+                let cond = ir::InstructionInfo::Comparison { kind, left: ir::Value::Instr(load), right: end_value.ir };
+                let cond = s.append_instr(flow.block, cond);
+                (flow, ir::Value::Instr(cond))
+            };
 
-                //optionally store the value
-                if let Some(value) = value {
-                    let store = ir::InstructionInfo::Store { addr: ir::Value::Slot(slot), ty: ty_ir, value: value.ir };
-                    self.append_instr(after_value.block, store);
+            let ascending = match ascending {
+                None => {
+                    let (cond_end, cond) = compare(s, cond_start, ir::LogicalOp::Lt(index_signedness));
+                    return Ok((cond_end, Some(cond)));
                 }
+                Some(ascending) => ascending,
+            };
 
-                Ok(after_value)
-            }
-            ast::StatementKind::Assignment(assign) => {
-                let (after_addr, addr) = self.append_expr_lvalue(flow, scope, &assign.left)?;
-                let (after_value, value) =
-                    self.append_expr_loaded(after_addr, scope, &assign.right)?;
+            let ty_bool_ir = s.prog.ty_bool();
+            let cond_slot = ir::Value::Slot(s.define_slot(ty_bool_ir));
+
+            let cond_end = s.append_if(
+                cond_start,
+                ascending,
+                |s: &mut Self, then_start: Flow| {
+                    let (then_end, cond) = compare(s, then_start, ir::LogicalOp::Lt(index_signedness));
+                    s.append_instr(then_end.block, ir::InstructionInfo::Store { addr: cond_slot, ty: ty_bool_ir, value: cond });
+                    Ok(then_end)
+                },
+                |s: &mut Self, else_start: Flow| {
+                    let (else_end, cond) = compare(s, else_start, ir::LogicalOp::Gt(index_signedness));
+                    s.append_instr(else_end.block, ir::InstructionInfo::Store { addr: cond_slot, ty: ty_bool_ir, value: cond });
+                    Ok(else_end)
+                },
+            )?;
+
+            let cond = s.append_instr(cond_end.block, ir::InstructionInfo::Load { ty: ty_bool_ir, addr: cond_slot });
+            Ok((cond_end, Some(ir::Value::Instr(cond))))
+        };
 
-                let ty_ir = self.types.map_type(self.prog, value.ty);
-                let store = ir::InstructionInfo::Store { addr: addr.ir, ty: ty_ir, value: value.ir };
-                self.append_instr(after_value.block, store);
+        //body; index = index + step
+        let body = |s: &mut Self, body_start: Flow| {
+            let body_end = s.append_nested_block(body_start, &index_scope, &for_stmt.body)?;
 
-                Ok(after_value)
+            let load = ir::InstructionInfo::Load { ty: index_ty_ir, addr: index_slot };
+            let load = s.append_instr(body_end.block, load);
+
+            let inc = ir::InstructionInfo::Arithmetic {
+                kind: ir::ArithmeticOp::Add,
+                left: ir::Value::Instr(load),
+                right: step_value,
+            };
+            let inc = s.append_instr(body_end.block, inc);
+
+            let store = ir::InstructionInfo::Store {
+                addr: index_slot,
+                ty: index_ty_ir,
+                value: ir::Value::Instr(inc),
+            };
+            s.append_instr(body_end.block, store);
+
+            Ok(body_end)
+        };
+
+        self.append_loop(flow, cond, body)
+    }
+
+    /// Append a for loop over the elements of an array or slice, the index variable is a copy of the current element.
+    fn append_array_for(
+        &mut self,
+        flow: Flow,
+        scope: &Scope<ScopedItem>,
+        for_stmt: &'ast ast::ForStatement,
+        array: &'ast ast::Expression,
+    ) -> Result<'ast, Flow> {
+        let array_ty = self.expr_type(array);
+        let element_ty = match &self.types[array_ty] {
+            TypeInfo::Array(info) => info.inner,
+            &TypeInfo::Slice(inner) => inner,
+            _ => panic!("For loop target should have an array or slice type"),
+        };
+        let element_ty_ptr = self.types.define_type_ptr(element_ty);
+        let element_ty_ir = self.types.map_type(self.prog, element_ty);
+        let array_ty_ir = self.types.map_type(self.prog, array_ty);
+        let ty_int_ir = self.prog.ty_int();
+
+        //get the address of the first element so the elements can be loaded, and the number of elements
+        let (flow, array_value) = self.append_expr(flow, scope, array)?;
+        let (addr, length) = match self.types[array_ty] {
+            TypeInfo::Array(ArrayTypeInfo { inner: _, length }) => {
+                let addr = match array_value {
+                    LRValue::Left(value) => value.ir,
+                    LRValue::Right(value) => {
+                        let slot = ir::Value::Slot(self.define_slot(array_ty_ir));
+                        self.append_instr(flow.block, ir::InstructionInfo::Store { addr: slot, ty: array_ty_ir, value: value.ir });
+                        slot
+                    }
+                };
+                (addr, ir::Value::Const(ir::Const { ty: ty_int_ir, value: length as i32 }))
             }
-            ast::StatementKind::If(if_stmt) => {
-                let (cond_end, cond) =
-                    self.append_expr_loaded(flow, scope, &if_stmt.cond)?;
+            _ => self.append_fat_pointer_parts(flow.block, array_value),
+        };
 
-                self.append_if(
-                    cond_end,
-                    cond.ir,
-                    |s: &mut Self, then_flow: Flow| {
-                        s.append_nested_block(then_flow, scope, &if_stmt.then_block)
-                    },
-                    |s: &mut Self, else_flow: Flow| {
-                        if let Some(else_block) = &if_stmt.else_block {
-                            s.append_nested_block(else_flow, scope, else_block)
-                        } else {
-                            Ok(else_flow)
-                        }
+        //counter = 0
+        let counter_slot = ir::Value::Slot(self.define_slot(ty_int_ir));
+        let zero = ir::Value::Const(ir::Const { ty: ty_int_ir, value: 0 });
+        self.append_instr(flow.block, ir::InstructionInfo::Store { addr: counter_slot, ty: ty_int_ir, value: zero });
+
+        //declare slot for the element
+        let mut index_scope = scope.nest();
+        let element_slot = ir::Value::Slot(self.define_slot(element_ty_ir));
+        let element_slot_value = LRValue::Left(TypedValue { ty: element_ty_ptr, ir: element_slot });
+        index_scope.maybe_declare(&for_stmt.index, ScopedItem::Value(ScopedValue::Immediate(element_slot_value)))?;
+
+        //counter < length
+        let cond = |s: &mut Self, cond_start: Flow| {
+            let counter = s.append_instr(cond_start.block, ir::InstructionInfo::Load { ty: ty_int_ir, addr: counter_slot });
+
+            let cond = ir::InstructionInfo::Comparison {
+                kind: ir::LogicalOp::Lt(ir::Signedness::Signed),
+                left: ir::Value::Instr(counter),
+                right: length,
+            };
+            let cond = s.append_instr(cond_start.block, cond);
+
+            Ok((cond_start, Some(ir::Value::Instr(cond))))
+        };
+
+        //element = array[counter]; counter = counter + 1; body
+        //the counter is incremented before the body so `continue` doesn't skip it
+        let body = |s: &mut Self, body_start: Flow| {
+            let counter = s.append_instr(body_start.block, ir::InstructionInfo::Load { ty: ty_int_ir, addr: counter_slot });
+            let counter = ir::Value::Instr(counter);
+
+            let element_ptr = ir::InstructionInfo::PointerOffSet { ty: element_ty_ir, base: addr, index: counter };
+            let element_ptr = s.append_instr(body_start.block, element_ptr);
+            let element = ir::InstructionInfo::Load { ty: element_ty_ir, addr: ir::Value::Instr(element_ptr) };
+            let element = s.append_instr(body_start.block, element);
+            s.append_instr(body_start.block, ir::InstructionInfo::Store { addr: element_slot, ty: element_ty_ir, value: ir::Value::Instr(element) });
+
+            let inc = ir::InstructionInfo::Arithmetic {
+                kind: ir::ArithmeticOp::Add,
+                left: counter,
+                right: ir::Value::Const(ir::Const { ty: ty_int_ir, value: 1 }),
+            };
+            let inc = s.append_instr(body_start.block, inc);
+            s.append_instr(body_start.block, ir::InstructionInfo::Store { addr: counter_slot, ty: ty_int_ir, value: ir::Value::Instr(inc) });
+
+            s.append_nested_block(body_start, &index_scope, &for_stmt.body)
+        };
+
+        self.append_loop(flow, cond, body)
+    }
+
+    /// Append a loop that runs its body once before checking the condition, `continue` jumps to the condition.
+    fn append_do_while(&mut self, flow: Flow, scope: &Scope<ScopedItem>, stmt: &'ast ast::WhileStatement) -> Result<'ast, Flow> {
+        let cond_start = self.new_flow(flow.needs_return);
+        let cond_start_block = cond_start.block;
+
+        //needs_return will be set incrementally by all blocks that jump to end
+        let mut end_start = self.new_flow(false);
+
+        let loop_info = LoopInfo {
+            cond: cond_start_block,
+            end: end_start.block,
+            end_needs_return: false,
+        };
+        self.loop_stack.push(loop_info);
+
+        //body
+        let body_start = self.new_flow(flow.needs_return);
+        let body_start_block = body_start.block;
+        let body_end = self.append_nested_block(body_start, scope, &stmt.body)?;
+
+        let loop_info = self.loop_stack.pop().unwrap();
+        end_start.needs_return |= loop_info.end_needs_return;
+
+        //condition
+        let (cond_end, cond) = self.append_expr_loaded(cond_start, scope, &stmt.cond)?;
+        end_start.needs_return |= cond_end.needs_return;
+
+        //connect everything
+        let jump_body = ir::Terminator::Jump { target: new_target(body_start_block) };
+        let jump_cond = ir::Terminator::Jump { target: new_target(cond_start_block) };
+
+        self.prog.get_block_mut(flow.block).terminator = jump_body;
+        self.prog.get_block_mut(body_end.block).terminator = jump_cond;
+        self.prog.get_block_mut(cond_end.block).terminator = new_branch(cond.ir, body_start_block, end_start.block);
+
+        Ok(end_start)
+    }
+
+    /// Store `value` into each of the `length` elements of the array at `array`.
+    fn append_array_fill(&mut self, flow: Flow, array: ir::Value, inner_ty_ir: ir::Type, value: ir::Value, length: u32) -> Result<'ast, Flow> {
+        let ty_int_ir = self.prog.ty_int();
+
+        //constants that consist of a single repeated byte can be filled in one go
+        if let ir::Value::Const(cst) = value {
+            let size = Layout::for_type(self.prog, inner_ty_ir).size;
+            let bytes = (cst.value as u32).to_le_bytes();
+            if (1..=4).contains(&size) && bytes[..size as usize].iter().all(|&b| b == bytes[0]) {
+                let byte = ir::Value::Const(ir::Const::new(self.prog.define_type_int(8), bytes[0] as i32));
+                let array_ty = self.prog.define_type_array(ir::ArrayType { inner: inner_ty_ir, length });
+                self.append_instr(flow.block, ir::InstructionInfo::MemSet { dest: array, value: byte, ty: array_ty });
+
+                return Ok(flow);
+            }
+        }
+
+        if length <= ARRAY_REPEAT_UNROLL_LIMIT {
+            for i in 0..length {
+                let index = ir::Value::Const(ir::Const::new(ty_int_ir, i as i32));
+                let ptr = ir::InstructionInfo::PointerOffSet { ty: inner_ty_ir, base: array, index };
+                let ptr = self.append_instr(flow.block, ptr);
+
+                let store = ir::InstructionInfo::Store { addr: ir::Value::Instr(ptr), ty: inner_ty_ir, value };
+                self.append_instr(flow.block, store);
+            }
+
+            return Ok(flow);
+        }
+
+        //index = 0
+        let index_slot = ir::Value::Slot(self.define_slot(ty_int_ir));
+        let zero = ir::Value::Const(ir::Const::new(ty_int_ir, 0));
+        self.append_instr(flow.block, ir::InstructionInfo::Store { addr: index_slot, ty: ty_int_ir, value: zero });
+
+        //index < length
+        let cond = |s: &mut Self, cond_start: Flow| {
+            let load = s.append_instr(cond_start.block, ir::InstructionInfo::Load { ty: ty_int_ir, addr: index_slot });
+            let cond = ir::InstructionInfo::Comparison {
+                kind: ir::LogicalOp::Lt(ir::Signedness::Signed),
+                left: ir::Value::Instr(load),
+                right: ir::Value::Const(ir::Const::new(ty_int_ir, length as i32)),
+            };
+            let cond = s.append_instr(cond_start.block, cond);
+
+            Ok((cond_start, Some(ir::Value::Instr(cond))))
+        };
+
+        //array[index] = value; index = index + 1
+        let body = |s: &mut Self, body_start: Flow| {
+            let index = s.append_instr(body_start.block, ir::InstructionInfo::Load { ty: ty_int_ir, addr: index_slot });
+            let index = ir::Value::Instr(index);
+
+            let ptr = ir::InstructionInfo::PointerOffSet { ty: inner_ty_ir, base: array, index };
+            let ptr = s.append_instr(body_start.block, ptr);
+            let store = ir::InstructionInfo::Store { addr: ir::Value::Instr(ptr), ty: inner_ty_ir, value };
+            s.append_instr(body_start.block, store);
+
+            let inc = ir::InstructionInfo::Arithmetic {
+                kind: ir::ArithmeticOp::Add,
+                left: index,
+                right: ir::Value::Const(ir::Const::new(ty_int_ir, 1)),
+            };
+            let inc = s.append_instr(body_start.block, inc);
+            let store = ir::InstructionInfo::Store { addr: index_slot, ty: ty_int_ir, value: ir::Value::Instr(inc) };
+            s.append_instr(body_start.block, store);
+
+            Ok(body_start)
+        };
+
+        self.append_loop(flow, cond, body)
+    }
+
+    /// The ir type of the payload of the given variant of the enum with ir type `enum_ty_ir`.
+    fn enum_variant_ty_ir(&self, enum_ty_ir: ir::Type, variant: u32) -> ir::Type {
+        let payload_ty_ir = self.prog.get_type(enum_ty_ir).unwrap_tuple().unwrap().fields[1];
+        self.prog.get_type(payload_ty_ir).unwrap_union().unwrap().variants[variant as usize]
+    }
+
+    /// Construct a value of the enum type `ty` with the given variant and payload fields.
+    #[must_use]
+    fn append_enum_value(&mut self, block: ir::Block, ty: cst::Type, variant: u32, fields: &[ir::Value]) -> LRValue {
+        let ty_ir = self.types.map_type(self.prog, ty);
+        let ty_int_ir = self.prog.ty_int();
+        let slot = ir::Value::Slot(self.define_slot(ty_ir));
+
+        //discriminant
+        let tag_ptr = self.append_instr(block, ir::InstructionInfo::TupleFieldPtr { base: slot, index: 0, tuple_ty: ty_ir });
+        let tag = ir::Value::Const(ir::Const::new(ty_int_ir, variant as i32));
+        self.append_instr(block, ir::InstructionInfo::Store { addr: ir::Value::Instr(tag_ptr), ty: ty_int_ir, value: tag });
+
+        //payload
+        if !fields.is_empty() {
+            let variant_ty_ir = self.enum_variant_ty_ir(ty_ir, variant);
+            let field_tys_ir = self.prog.get_type(variant_ty_ir).unwrap_tuple().unwrap().fields.clone();
+
+            let payload_ptr = self.append_instr(block, ir::InstructionInfo::TupleFieldPtr { base: slot, index: 1, tuple_ty: ty_ir });
+            for (i, (&field, field_ty_ir)) in fields.iter().zip(field_tys_ir).enumerate() {
+                let field_ptr = ir::InstructionInfo::TupleFieldPtr {
+                    base: ir::Value::Instr(payload_ptr),
+                    index: i as u32,
+                    tuple_ty: variant_ty_ir,
+                };
+                let field_ptr = self.append_instr(block, field_ptr);
+                self.append_instr(block, ir::InstructionInfo::Store { addr: ir::Value::Instr(field_ptr), ty: field_ty_ir, value: field });
+            }
+        }
+
+        let load = self.append_instr(block, ir::InstructionInfo::Load { ty: ty_ir, addr: slot });
+        LRValue::Right(TypedValue { ty, ir: ir::Value::Instr(load) })
+    }
+
+    /// Lower a closure expression to a new function and an environment containing pointers to the captured variables.
+    /// The environment lives on the stack of the current function, so the closure must not outlive it.
+    fn append_closure(
+        &mut self,
+        flow: Flow,
+        scope: &Scope<ScopedItem>,
+        expr: &'ast ast::Expression,
+        params: &'ast [ast::Parameter],
+        body: &'ast ast::Block,
+    ) -> Result<'ast, (Flow, LRValue)> {
+        let ty = self.expr_type(expr);
+        let info = self.types[ty].unwrap_closure().unwrap().clone();
+        let ty_ir = self.types.map_type(self.prog, ty);
+        let ty_ptr_ir = self.prog.ty_ptr();
+
+        //only local variables need to be captured, everything else can be found in the module scope
+        let captures: Vec<(&ast::Identifier, TypedValue)> = find_captures(params, body).into_iter()
+            .filter_map(|id| match scope.find(None, id) {
+                Ok(&ScopedItem::Value(ScopedValue::Immediate(LRValue::Left(value)))) => Some((id, value)),
+                _ => None,
+            })
+            .collect();
+
+        //build the environment
+        let env_ty_ir = self.prog.define_type_tuple(ir::TupleType { fields: vec![ty_ptr_ir; captures.len()], packed: false });
+        let env = ir::Value::Slot(self.define_slot(env_ty_ir));
+        for (i, (_, value)) in captures.iter().enumerate() {
+            let field_ptr = ir::InstructionInfo::TupleFieldPtr { base: env, index: i as u32, tuple_ty: env_ty_ir };
+            let field_ptr = self.append_instr(flow.block, field_ptr);
+            self.append_instr(flow.block, ir::InstructionInfo::Store { addr: ir::Value::Instr(field_ptr), ty: ty_ptr_ir, value: value.ir });
+        }
+
+        //create the function itself
+        let func_ty_ir = self.types.map_type_closure_func(self.prog, &info);
+        let func_value_ty_ir = self.prog.define_type_func(func_ty_ir.clone());
+        let mut func_ir = ir::FunctionInfo::new(func_ty_ir, self.prog);
+        let parent_name = self.prog.get_func(self.ir_func).debug_name.as_deref().unwrap_or("<unknown>");
+        func_ir.debug_name = Some(format!("{}::closure", parent_name));
+        let func_ir = self.prog.define_func(func_ir);
+
+        let mut closure_state = LowerFuncState {
+            prog: &mut *self.prog,
+            items: self.items,
+            types: &mut *self.types,
+            map_value: self.map_value,
+            runtime: &mut *self.runtime,
+            settings: self.settings,
+            module_scope: self.module_scope,
+            ir_func: func_ir,
+            ret_ty: info.ret,
+            expr_type_map: self.expr_type_map,
+            decl_type_map: self.decl_type_map,
+            type_solution: self.type_solution,
+            loop_stack: vec![],
+            current_span: self.current_span,
+        };
+        let start = closure_state.new_flow(true);
+
+        //the environment pointer is passed as the first parameter, make the captured variables available from it
+        let env_param = closure_state.prog.define_param(ir::ParameterInfo { ty: ty_ptr_ir });
+        closure_state.prog.get_func_mut(func_ir).params.push(env_param);
+
+        //the enclosing scopes still provide nested functions and consts, captured variables shadow their originals
+        let mut closure_scope = scope.nest();
+        for (i, (id, value)) in captures.iter().enumerate() {
+            let field_ptr = ir::InstructionInfo::TupleFieldPtr { base: ir::Value::Param(env_param), index: i as u32, tuple_ty: env_ty_ir };
+            let field_ptr = closure_state.append_instr(start.block, field_ptr);
+            let ptr = closure_state.append_instr(start.block, ir::InstructionInfo::Load { ty: ty_ptr_ir, addr: ir::Value::Instr(field_ptr) });
+
+            let captured = LRValue::Left(TypedValue { ty: value.ty, ir: ir::Value::Instr(ptr) });
+            closure_scope.declare(id, ScopedItem::Value(ScopedValue::Immediate(captured)))?;
+        }
+
+        if closure_state.append_func_body(start, closure_scope, params, &info.params, body)? {
+            return Err(Error::MissingClosureReturn(expr));
+        }
+
+        //combine the function and environment into the closure value
+        let slot = ir::Value::Slot(self.define_slot(ty_ir));
+        let parts = [(ir::Value::Func(func_ir), func_value_ty_ir), (env, ty_ptr_ir)];
+        for (i, (value, value_ty)) in parts.iter().enumerate() {
+            let field_ptr = self.append_instr(flow.block, ir::InstructionInfo::TupleFieldPtr { base: slot, index: i as u32, tuple_ty: ty_ir });
+            self.append_instr(flow.block, ir::InstructionInfo::Store { addr: ir::Value::Instr(field_ptr), ty: *value_ty, value: *value });
+        }
+        let load = self.append_instr(flow.block, ir::InstructionInfo::Load { ty: ty_ir, addr: slot });
+
+        Ok((flow, LRValue::Right(TypedValue { ty, ir: ir::Value::Instr(load) })))
+    }
+
+    /// Construct a value of the `str` or slice type `ty` from a pointer to its first element and its length.
+    #[must_use]
+    fn append_fat_pointer_value(&mut self, block: ir::Block, ty: cst::Type, ptr: ir::Value, len: ir::Value) -> TypedValue {
+        let ty_ir = self.types.map_type(self.prog, ty);
+        let slot = ir::Value::Slot(self.define_slot(ty_ir));
+
+        let parts = [(ptr, self.prog.ty_ptr()), (len, self.prog.ty_int())];
+        for (i, (value, value_ty)) in parts.iter().enumerate() {
+            let field_ptr = self.append_instr(block, ir::InstructionInfo::TupleFieldPtr { base: slot, index: i as u32, tuple_ty: ty_ir });
+            self.append_instr(block, ir::InstructionInfo::Store { addr: ir::Value::Instr(field_ptr), ty: *value_ty, value: *value });
+        }
+        let load = self.append_instr(block, ir::InstructionInfo::Load { ty: ty_ir, addr: slot });
+
+        TypedValue { ty, ir: ir::Value::Instr(load) }
+    }
+
+    /// Get the address of the `str` or slice `value` so its fields can be indexed, storing it in a temporary slot
+    /// first if it's an rvalue.
+    fn append_fat_pointer_addr(&mut self, block: ir::Block, value: LRValue) -> ir::Value {
+        let ty_ir = self.types.map_type(self.prog, value.ty(self.types));
+
+        match value {
+            LRValue::Left(value) => value.ir,
+            LRValue::Right(value) => {
+                let slot = ir::Value::Slot(self.define_slot(ty_ir));
+                self.append_instr(block, ir::InstructionInfo::Store { addr: slot, ty: ty_ir, value: value.ir });
+                slot
+            }
+        }
+    }
+
+    /// Load the pointer and length of the `str` or slice `value`.
+    fn append_fat_pointer_parts(&mut self, block: ir::Block, value: LRValue) -> (ir::Value, ir::Value) {
+        let ty_ir = self.types.map_type(self.prog, value.ty(self.types));
+        let addr = self.append_fat_pointer_addr(block, value);
+
+        let parts = [self.prog.ty_ptr(), self.prog.ty_int()].iter().enumerate().map(|(i, &part_ty)| {
+            let part_ptr = self.append_instr(block, ir::InstructionInfo::TupleFieldPtr { base: addr, index: i as u32, tuple_ty: ty_ir });
+            ir::Value::Instr(self.append_instr(block, ir::InstructionInfo::Load { ty: part_ty, addr: ir::Value::Instr(part_ptr) }))
+        }).collect::<Vec<_>>();
+
+        (parts[0], parts[1])
+    }
+
+    /// Access the field `id` of the `str` or slice value `target`, which doesn't have to be an lvalue.
+    fn append_fat_pointer_field(
+        &mut self,
+        flow: Flow,
+        scope: &Scope<ScopedItem>,
+        target: &'ast ast::Expression,
+        id: &'ast ast::Identifier,
+    ) -> Result<'ast, (Flow, LRValue)> {
+        let (after_target, target_value) = self.append_expr(flow, scope, target)?;
+        let ty = target_value.ty(self.types);
+        let ty_ir = self.types.map_type(self.prog, ty);
+
+        let (index, field_ty) = self.types.fat_pointer_field(ty, &id.string)
+            .ok_or_else(|| Error::StructFieldNotFound {
+                target,
+                target_type: self.types.format_type(ty).to_string(),
+                index: id,
+            })?;
+
+        let addr = self.append_fat_pointer_addr(after_target.block, target_value);
+        let field_ptr = self.append_instr(after_target.block, ir::InstructionInfo::TupleFieldPtr { base: addr, index, tuple_ty: ty_ir });
+        let field_ty_ptr = self.types.define_type_ptr(field_ty);
+
+        Ok((after_target, LRValue::Left(TypedValue { ty: field_ty_ptr, ir: ir::Value::Instr(field_ptr) })))
+    }
+
+    /// Check that `0 <= index` and `index kind bound`, panicking at `span` otherwise. Returns the flow to continue in.
+    #[must_use]
+    fn append_bounds_check(&mut self, flow: Flow, span: Span, index: ir::Value, bound: ir::Value, kind: ir::LogicalOp) -> Flow {
+        let zero = ir::Value::Const(ir::Const::new(self.prog.ty_int(), 0));
+        let checks = [(ir::LogicalOp::Gte(ir::Signedness::Signed), index, zero), (kind, index, bound)];
+
+        checks.iter().fold(flow, |flow, &(kind, left, right)| {
+            let cond = self.append_instr(flow.block, ir::InstructionInfo::Comparison { kind, left, right });
+            self.append_check(flow, span, ir::Value::Instr(cond), b"index out of bounds")
+        })
+    }
+
+    /// Continue in a new flow if `cond` is true, panic at `span` with `message` otherwise.
+    #[must_use]
+    fn append_check(&mut self, flow: Flow, span: Span, cond: ir::Value, message: &[u8]) -> Flow {
+        let success = self.new_flow(flow.needs_return);
+        let failure = self.new_flow(false);
+        self.prog.get_block_mut(flow.block).terminator = new_branch(cond, success.block, failure.block);
+
+        let (message_ptr, message_len) = self.define_string(message.to_vec());
+        self.append_panic(failure.block, span, message_ptr, message_len);
+
+        success
+    }
+
+    /// Slice the array or slice `target` from `start` to `end`, which default to the start and the end of `target`.
+    fn append_slice(
+        &mut self,
+        flow: Flow,
+        scope: &Scope<ScopedItem>,
+        expr: &'ast ast::Expression,
+        target: &'ast ast::Expression,
+        start: Option<&'ast ast::Expression>,
+        end: Option<&'ast ast::Expression>,
+    ) -> Result<'ast, (Flow, LRValue)> {
+        let ty_int_ir = self.prog.ty_int();
+        let result_ty = self.expr_type(expr);
+        let inner_ty = self.types[result_ty].unwrap_slice().unwrap();
+        let inner_ty_ir = self.types.map_type(self.prog, inner_ty);
+
+        //the pointer to the first element and the length of the target
+        let (mut flow, ptr, len) = match self.types[self.expr_type(target)] {
+            TypeInfo::Array(ArrayTypeInfo { inner: _, length }) => {
+                let (after_target, target_value) = self.append_expr_lvalue(flow, scope, target)?;
+                (after_target, target_value.ir, ir::Value::Const(ir::Const::new(ty_int_ir, length as i32)))
+            }
+            _ => {
+                let (after_target, target_value) = self.append_expr(flow, scope, target)?;
+                let (ptr, len) = self.append_fat_pointer_parts(after_target.block, target_value);
+                (after_target, ptr, len)
+            }
+        };
+
+        let bound = |s: &mut Self, flow: Flow, bound: Option<&'ast ast::Expression>, default: ir::Value| {
+            match bound {
+                None => Ok((flow, default)),
+                Some(bound) => s.append_expr_loaded(flow, scope, bound).map(|(flow, value)| (flow, value.ir)),
+            }
+        };
+        let (after_start, start) = bound(self, flow, start, ir::Value::Const(ir::Const::new(ty_int_ir, 0)))?;
+        let (after_end, end) = bound(self, after_start, end, len)?;
+        flow = after_end;
+
+        //check 0 <= start <= end <= len
+        flow = self.append_bounds_check(flow, expr.span, start, end, ir::LogicalOp::Lte(ir::Signedness::Signed));
+        flow = self.append_bounds_check(flow, expr.span, end, len, ir::LogicalOp::Lte(ir::Signedness::Signed));
+
+        let ptr = self.append_instr(flow.block, ir::InstructionInfo::PointerOffSet { ty: inner_ty_ir, base: ptr, index: start });
+        let len = self.append_instr(flow.block, ir::InstructionInfo::Arithmetic { kind: ir::ArithmeticOp::Sub, left: end, right: start });
+
+        let value = self.append_fat_pointer_value(flow.block, result_ty, ir::Value::Instr(ptr), ir::Value::Instr(len));
+        Ok((flow, LRValue::Right(value)))
+    }
+
+    /// Call a closure, passing its environment as the first argument.
+    fn append_closure_call(
+        &mut self,
+        flow: Flow,
+        scope: &Scope<ScopedItem>,
+        target: &'ast ast::Expression,
+        args: &[&'ast ast::Expression],
+        info: &FunctionTypeInfo<cst::Type>,
+    ) -> Result<'ast, (Flow, LRValue)> {
+        let (after_target, target_value) = self.append_expr(flow, scope, target)?;
+        let ty_ir = self.types.map_type(self.prog, target_value.ty(self.types));
+        let ty_ptr_ir = self.prog.ty_ptr();
+
+        //get the address of the closure so the fields can be loaded
+        let addr = match target_value {
+            LRValue::Left(value) => value.ir,
+            LRValue::Right(value) => {
+                let slot = ir::Value::Slot(self.define_slot(ty_ir));
+                self.append_instr(after_target.block, ir::InstructionInfo::Store { addr: slot, ty: ty_ir, value: value.ir });
+                slot
+            }
+        };
+
+        let func_ty_ir = self.prog.get_type(ty_ir).unwrap_tuple().unwrap().fields[0];
+        let func_ptr = self.append_instr(after_target.block, ir::InstructionInfo::TupleFieldPtr { base: addr, index: 0, tuple_ty: ty_ir });
+        let func = self.append_instr(after_target.block, ir::InstructionInfo::Load { ty: func_ty_ir, addr: ir::Value::Instr(func_ptr) });
+        let env_ptr = self.append_instr(after_target.block, ir::InstructionInfo::TupleFieldPtr { base: addr, index: 1, tuple_ty: ty_ir });
+        let env = self.append_instr(after_target.block, ir::InstructionInfo::Load { ty: ty_ptr_ir, addr: ir::Value::Instr(env_ptr) });
+
+        let (after_args, mut ir_args) = self.append_args(after_target, scope, args, &info.params)?;
+        ir_args.insert(0, ir::Value::Instr(env));
+
+        let call = ir::InstructionInfo::Call { target: ir::Value::Instr(func), args: ir_args };
+        let call = self.append_instr(after_args.block, call);
+
+        Ok((after_args, LRValue::Right(TypedValue { ty: info.ret, ir: ir::Value::Instr(call) })))
+    }
+
+    /// The value an arm with the given pattern matches on, or `None` for wildcards.
+    fn match_pattern_case(&mut self, scope: &Scope<ScopedItem>, pattern: &'ast ast::MatchPattern) -> Result<'ast, Option<i32>> {
+        match pattern {
+            ast::MatchPattern::Wildcard(_) => Ok(None),
+            ast::MatchPattern::Literal(lit) => {
+                let value = match &lit.kind {
+                    ast::ExpressionKind::IntLit { value } => value,
+                    _ => unreachable!("only int literals are parsed as patterns"),
+                };
+                let cst = map_int_literal(self.types, self.prog, lit, value, self.expr_type(lit), false)?;
+                Ok(Some(cst.value))
+            }
+            ast::MatchPattern::Variant { path, .. } => {
+                let (_, variant) = self.items.resolve_enum_variant(scope, path)?;
+                Ok(Some(variant as i32))
+            }
+        }
+    }
+
+    /// Append the body of a match arm, with the payload fields of enum variant patterns declared as pointers into
+    /// the matched value at `addr`.
+    fn append_match_arm_body(
+        &mut self,
+        flow: Flow,
+        scope: &Scope<ScopedItem>,
+        addr: Option<ir::Value>,
+        ty: cst::Type,
+        arm: &'ast ast::MatchArm,
+    ) -> Result<'ast, Flow> {
+        let mut arm_scope = scope.nest();
+
+        if let ast::MatchPattern::Variant { span: _, path, fields } = &arm.pattern {
+            let (_, variant) = self.items.resolve_enum_variant(scope, path)?;
+            let addr = addr.expect("enum match should have an address");
+            let ty_ir = self.types.map_type(self.prog, ty);
+            let variant_ty_ir = self.enum_variant_ty_ir(ty_ir, variant);
+            let payload_ptr = self.append_instr(flow.block, ir::InstructionInfo::TupleFieldPtr { base: addr, index: 1, tuple_ty: ty_ir });
+
+            for (i, field) in fields.iter().enumerate() {
+                let field_ty = self.types[ty].unwrap_enum().unwrap().variants[variant as usize].fields[i];
+                let field_ty_ptr = self.types.define_type_ptr(field_ty);
+
+                let field_ptr = ir::InstructionInfo::TupleFieldPtr {
+                    base: ir::Value::Instr(payload_ptr),
+                    index: i as u32,
+                    tuple_ty: variant_ty_ir,
+                };
+                let field_ptr = self.append_instr(flow.block, field_ptr);
+
+                let value = LRValue::Left(TypedValue { ty: field_ty_ptr, ir: ir::Value::Instr(field_ptr) });
+                arm_scope.maybe_declare(field, ScopedItem::Value(ScopedValue::Immediate(value)))?;
+            }
+        }
+
+        self.append_nested_block(flow, &arm_scope, &arm.body)
+    }
+
+    /// Append the arms of a match statement as a chain of comparisons of `key` against each arm.
+    fn append_match_chain(
+        &mut self,
+        flow: Flow,
+        scope: &Scope<ScopedItem>,
+        key: ir::Value,
+        addr: Option<ir::Value>,
+        ty: cst::Type,
+        arms: &'ast [ast::MatchArm],
+    ) -> Result<'ast, Flow> {
+        let (arm, rest) = match arms.split_first() {
+            //without a wildcard arm the match is exhaustive, so the current block keeps its unreachable terminator
+            None => return Ok(self.new_flow(false)),
+            Some(split) => split,
+        };
+
+        match self.match_pattern_case(scope, &arm.pattern)? {
+            //later arms can't be reached any more
+            None => self.append_match_arm_body(flow, scope, addr, ty, arm),
+            Some(case) => {
+                let key_ty_ir = self.prog.type_of_value(key);
+                let cond = ir::InstructionInfo::Comparison {
+                    kind: ir::LogicalOp::Eq,
+                    left: key,
+                    right: ir::Value::Const(ir::Const::new(key_ty_ir, case)),
+                };
+                let cond = self.append_instr(flow.block, cond);
+
+                self.append_if(
+                    flow,
+                    ir::Value::Instr(cond),
+                    |s: &mut Self, then_flow: Flow| {
+                        s.append_match_arm_body(then_flow, scope, addr, ty, arm)
+                    },
+                    |s: &mut Self, else_flow: Flow| {
+                        s.append_match_chain(else_flow, scope, key, addr, ty, rest)
+                    },
+                )
+            }
+        }
+    }
+
+    /// Append the arms of a match statement as a single `Switch` terminator on `key`.
+    fn append_match_switch(
+        &mut self,
+        flow: Flow,
+        scope: &Scope<ScopedItem>,
+        key: ir::Value,
+        addr: Option<ir::Value>,
+        ty: cst::Type,
+        arms: &'ast [ast::MatchArm],
+    ) -> Result<'ast, Flow> {
+        //needs_return will be set incrementally by all arms that jump to end
+        let mut end_start = self.new_flow(false);
+        let jump_end = ir::Terminator::Jump { target: new_target(end_start.block) };
+
+        let mut cases = Vec::new();
+        let mut seen = HashSet::new();
+        let mut default_arm = None;
+
+        for arm in arms {
+            match self.match_pattern_case(scope, &arm.pattern)? {
+                None => {
+                    //later arms can't be reached any more
+                    default_arm = Some(arm);
+                    break;
+                }
+                Some(case) => {
+                    //duplicate arms can't be reached either
+                    if !seen.insert(case) { continue; }
+
+                    let arm_start = self.new_flow(flow.needs_return);
+                    let arm_start_block = arm_start.block;
+                    let arm_end = self.append_match_arm_body(arm_start, scope, addr, ty, arm)?;
+
+                    end_start.needs_return |= arm_end.needs_return;
+                    self.prog.get_block_mut(arm_end.block).terminator = jump_end.clone();
+                    cases.push(ir::SwitchCase { value: case, target: new_target(arm_start_block) });
+                }
+            }
+        }
+
+        //without a wildcard arm the match is exhaustive, so the default block is left unreachable
+        let default_start = self.new_flow(flow.needs_return);
+        let default_start_block = default_start.block;
+        if let Some(arm) = default_arm {
+            let default_end = self.append_match_arm_body(default_start, scope, addr, ty, arm)?;
+            end_start.needs_return |= default_end.needs_return;
+            self.prog.get_block_mut(default_end.block).terminator = jump_end;
+        }
+
+        self.prog.get_block_mut(flow.block).terminator = ir::Terminator::Switch {
+            value: key,
+            cases,
+            default: new_target(default_start_block),
+        };
+
+        Ok(end_start)
+    }
+
+    /// If `if_stmt` is an if-else chain that compares a single integer variable against dense literal cases,
+    /// return the arms of the chain and the value of each case, so it can be lowered as a `Switch`.
+    fn if_chain_switch_cases(&mut self, if_stmt: &'ast ast::IfStatement) -> Result<'ast, Option<(IfChainArms<'ast>, Vec<i32>)>> {
+        let chain = match if_chain_arms(if_stmt) {
+            Some(chain) => chain,
+            None => return Ok(None),
+        };
+
+        let key_ty = self.expr_type(chain.0);
+        if !matches!(self.types[key_ty], TypeInfo::Int | TypeInfo::Byte) {
+            return Ok(None);
+        }
+
+        let mut cases = Vec::new();
+        for &(lit, _) in &chain.1 {
+            let value = match &lit.kind {
+                ast::ExpressionKind::IntLit { value } => value,
+                _ => unreachable!("if_chain_arms only returns int literals"),
+            };
+            cases.push(map_int_literal(self.types, self.prog, lit, value, self.expr_type(lit), false)?.value);
+        }
+
+        Ok(if is_dense_switch(&cases) { Some((chain, cases)) } else { None })
+    }
+
+    /// Append an if-else chain found by `if_chain_switch_cases` as a single `Switch` terminator.
+    fn append_if_switch(
+        &mut self,
+        flow: Flow,
+        scope: &Scope<ScopedItem>,
+        (key_expr, arms, else_block): IfChainArms<'ast>,
+        cases: Vec<i32>,
+    ) -> Result<'ast, Flow> {
+        let (after_key, key) = self.append_expr_loaded(flow, scope, key_expr)?;
+
+        //needs_return will be set incrementally by all arms that jump to end
+        let mut end_start = self.new_flow(false);
+        let jump_end = ir::Terminator::Jump { target: new_target(end_start.block) };
+
+        let mut switch_cases = Vec::new();
+        let mut seen = HashSet::new();
+        for (case, (_, body)) in zip_eq(cases, arms) {
+            //only the first arm with a given value can be reached
+            if !seen.insert(case) { continue; }
+
+            let arm_start = self.new_flow(after_key.needs_return);
+            let arm_start_block = arm_start.block;
+            let arm_end = self.append_nested_block(arm_start, scope, body)?;
+
+            end_start.needs_return |= arm_end.needs_return;
+            self.prog.get_block_mut(arm_end.block).terminator = jump_end.clone();
+            switch_cases.push(ir::SwitchCase { value: case, target: new_target(arm_start_block) });
+        }
+
+        let default_start = self.new_flow(after_key.needs_return);
+        let default_start_block = default_start.block;
+        let default_end = match else_block {
+            Some(else_block) => self.append_nested_block(default_start, scope, else_block)?,
+            None => default_start,
+        };
+        end_start.needs_return |= default_end.needs_return;
+        self.prog.get_block_mut(default_end.block).terminator = jump_end;
+
+        self.prog.get_block_mut(after_key.block).terminator = ir::Terminator::Switch {
+            value: key.ir,
+            cases: switch_cases,
+            default: new_target(default_start_block),
+        };
+
+        Ok(end_start)
+    }
+
+    fn append_statement(&mut self, flow: Flow, scope: &mut Scope<ScopedItem>, stmt: &'ast ast::Statement) -> Result<'ast, Flow> {
+        self.with_span(stmt.span, |s| s.append_statement_inner(flow, scope, stmt))
+    }
+
+    fn append_statement_inner(&mut self, flow: Flow, scope: &mut Scope<ScopedItem>, stmt: &'ast ast::Statement) -> Result<'ast, Flow> {
+        match &stmt.kind {
+            ast::StatementKind::Declaration(decl) => {
+                assert!(!decl.mutable, "everything is mutable for now");
+
+                let (after_value, value) = if let Some(init) = &decl.init {
+                    let (after_value, value) = self.append_expr_loaded(flow, scope, init)?;
+                    (after_value, Some(value))
+                } else {
+                    (flow, None)
+                };
+
+                let ty = self.type_solution[*self.decl_type_map.get(&(decl as *const _)).unwrap()];
+                let ty_ir = self.types.map_type(self.prog, ty);
+                let value = value.map(|value| self.append_coerce(after_value.block, value, ty));
+
+                let fields = match &decl.pattern {
+                    ast::DeclarationPattern::Single(id) => {
+                        //define the slot and optionally store the value
+                        let slot = self.declare_variable(scope, id, ty)?;
+                        if let Some(value) = value {
+                            let store = ir::InstructionInfo::Store { addr: slot, ty: ty_ir, value: value.ir };
+                            self.append_instr(after_value.block, store);
+                        }
+                        return Ok(after_value);
+                    }
+                    ast::DeclarationPattern::Tuple { span: _, elements } => {
+                        let field_tys = self.types[ty].unwrap_tuple().unwrap().fields.clone();
+                        elements.iter().zip(field_tys).enumerate()
+                            .map(|(index, (id, field_ty))| (id, index as u32, field_ty))
+                            .collect::<Vec<_>>()
+                    }
+                    ast::DeclarationPattern::Struct { span: _, path: _, fields } => {
+                        let info = self.types[ty].unwrap_struct().unwrap();
+                        fields.iter().map(|field| {
+                            let index = info.find_field_index(&field.field.string).unwrap();
+                            (&field.binding, index, info.fields[index as usize].ty)
+                        }).collect::<Vec<_>>()
+                    }
+                };
+
+                //store the entire value in a temporary slot and copy each bound field into its own variable
+                let value_slot = value.map(|value| {
+                    let slot = ir::Value::Slot(self.define_slot(ty_ir));
+                    self.append_instr(after_value.block, ir::InstructionInfo::Store { addr: slot, ty: ty_ir, value: value.ir });
+                    slot
+                });
+
+                for (id, index, field_ty) in fields {
+                    let slot = self.declare_variable(scope, id, field_ty)?;
+
+                    if let Some(value_slot) = value_slot {
+                        let field_ty_ir = self.types.map_type(self.prog, field_ty);
+                        let field_ptr = self.append_instr(after_value.block, ir::InstructionInfo::TupleFieldPtr { base: value_slot, index, tuple_ty: ty_ir });
+                        let field = self.append_instr(after_value.block, ir::InstructionInfo::Load { ty: field_ty_ir, addr: ir::Value::Instr(field_ptr) });
+                        self.append_instr(after_value.block, ir::InstructionInfo::Store { addr: slot, ty: field_ty_ir, value: ir::Value::Instr(field) });
+                    }
+                }
+
+                Ok(after_value)
+            }
+            ast::StatementKind::Assignment(assign) if matches!(assign.left.kind, ast::ExpressionKind::Tuple { .. }) => {
+                //evaluate the entire value first, so eg. `(a, b) = (b, a)` swaps the variables
+                let (after_value, value) = self.append_expr_loaded(flow, scope, &assign.right)?;
+                self.append_assign_target(after_value, scope, &assign.left, value)
+            }
+            ast::StatementKind::Assignment(assign) => {
+                let (after_addr, addr) = self.append_expr_lvalue(flow, scope, &assign.left)?;
+                let (after_value, value) = self.append_expr(after_addr, scope, &assign.right)?;
+                let target_ty = self.types[addr.ty].unwrap_ptr().unwrap();
+
+                //copy aggregates from memory to memory directly instead of loading them as a whole
+                if let LRValue::Left(source) = value {
+                    if self.types[source.ty].unwrap_ptr() == Some(target_ty) {
+                        let ty_ir = self.types.map_type(self.prog, target_ty);
+                        if matches!(self.prog.get_type(ty_ir), ir::TypeInfo::Tuple(_) | ir::TypeInfo::Array(_) | ir::TypeInfo::Union(_)) {
+                            let copy = ir::InstructionInfo::MemCopy { dest: addr.ir, source: source.ir, ty: ty_ir };
+                            self.append_instr(after_value.block, copy);
+                            return Ok(after_value);
+                        }
+                    }
+                }
+
+                let value = self.append_load(after_value.block, value);
+                let value = self.append_coerce(after_value.block, value, target_ty);
+
+                let ty_ir = self.types.map_type(self.prog, value.ty);
+                let store = ir::InstructionInfo::Store { addr: addr.ir, ty: ty_ir, value: value.ir };
+                self.append_instr(after_value.block, store);
+
+                Ok(after_value)
+            }
+            ast::StatementKind::If(if_stmt) => {
+                if let Some((chain, cases)) = self.if_chain_switch_cases(if_stmt)? {
+                    return self.append_if_switch(flow, scope, chain, cases);
+                }
+
+                let (cond_end, cond) =
+                    self.append_expr_loaded(flow, scope, &if_stmt.cond)?;
+                let (then_scope, else_scope) = self.narrowed_scopes(scope, &if_stmt.cond)?;
+
+                self.append_if(
+                    cond_end,
+                    cond.ir,
+                    |s: &mut Self, then_flow: Flow| {
+                        s.append_nested_block(then_flow, &then_scope, &if_stmt.then_block)
+                    },
+                    |s: &mut Self, else_flow: Flow| {
+                        if let Some(else_block) = &if_stmt.else_block {
+                            s.append_nested_block(else_flow, &else_scope, else_block)
+                        } else {
+                            Ok(else_flow)
+                        }
                     },
                 )
             }
@@ -625,109 +2063,212 @@ impl<'ir, 'ast, 'cst, 'ts, F: Fn(ScopedValue) -> LRValue> LowerFuncState<'ir, 'a
                     |s: &mut Self, cond_start: Flow| {
                         let (flow, cond) =
                             s.append_expr_loaded(cond_start, scope, &while_stmt.cond)?;
-                        Ok((flow, cond.ir))
+                        Ok((flow, Some(cond.ir)))
                     },
                     |s: &mut Self, body_start: Flow| {
                         s.append_nested_block(body_start, scope, &while_stmt.body)
                     },
                 )
             }
-            ast::StatementKind::For(for_stmt) => {
-                //figure out the index type
-                let index_ty = self.expr_type(&for_stmt.start);
-                let index_ty_ptr = self.types.define_type_ptr(index_ty);
-                let index_ty_ir = self.types.map_type(self.prog, index_ty);
-
-                //evaluate the range
-                let (flow, start_value) =
-                    self.append_expr_loaded(flow, scope, &for_stmt.start)?;
-                let (flow, end_value) =
-                    self.append_expr_loaded(flow, scope, &for_stmt.end)?;
-
-                //declare slot for index
-                let mut index_scope = scope.nest();
-                let index_slot = self.define_slot(index_ty_ir);
-                let index_slot = ir::Value::Slot(index_slot);
-
-                //TODO this allows the index to be mutated, which is fine for now, but it should be marked immutable when that is implemented
-                //TODO maybe consider changing the increment to use the index loaded at the beginning so it can't really be mutated after all
-                let index_slot_value = LRValue::Left(TypedValue { ty: index_ty_ptr, ir: index_slot });
-                let item = ScopedItem::Value(ScopedValue::Immediate(index_slot_value));
-                index_scope.maybe_declare(&for_stmt.index, item)?;
-
-                //index = start
-                self.append_instr(flow.block, ir::InstructionInfo::Store { addr: index_slot, ty: index_ty_ir, value: start_value.ir });
-
-                //index < end
-                let cond = |s: &mut Self, cond_start: Flow| {
-                    let load = ir::InstructionInfo::Load { ty: index_ty_ir, addr: index_slot };
-                    let load = s.append_instr(cond_start.block, load);
-
-                    let cond = ir::InstructionInfo::Comparison {
-                        kind: ir::LogicalOp::Lt,
-                        left: ir::Value::Instr(load),
-                        right: end_value.ir,
+            ast::StatementKind::DoWhile(while_stmt) => {
+                self.append_do_while(flow, scope, while_stmt)
+            }
+            ast::StatementKind::Loop(loop_stmt) => {
+                self.append_loop(
+                    flow,
+                    |_: &mut Self, cond_start: Flow| Ok((cond_start, None)),
+                    |s: &mut Self, body_start: Flow| {
+                        s.append_nested_block(body_start, scope, &loop_stmt.body)
+                    },
+                )
+            }
+            ast::StatementKind::For(for_stmt) => match &for_stmt.range {
+                ast::ForRange::Range { start, end, step } =>
+                    self.append_range_for(flow, scope, for_stmt, start, end, step.as_deref()),
+                ast::ForRange::Array(array) => self.append_array_for(flow, scope, for_stmt, array),
+            },
+            ast::StatementKind::Match(match_stmt) => {
+                let ty = self.expr_type(&match_stmt.value);
+                let (after_value, value) = self.append_expr(flow, scope, &match_stmt.value)?;
+
+                //enums are matched on their discriminant, the payload is accessed trough a pointer later
+                let (key, addr) = if self.types[ty].unwrap_enum().is_some() {
+                    let ty_ir = self.types.map_type(self.prog, ty);
+                    let addr = match value {
+                        LRValue::Left(value) => value.ir,
+                        LRValue::Right(value) => {
+                            let slot = ir::Value::Slot(self.define_slot(ty_ir));
+                            self.append_instr(after_value.block, ir::InstructionInfo::Store { addr: slot, ty: ty_ir, value: value.ir });
+                            slot
+                        }
                     };
-                    let cond = s.append_instr(cond_start.block, cond);
 
-                    Ok((cond_start, ir::Value::Instr(cond)))
+                    let ty_int_ir = self.prog.ty_int();
+                    let tag_ptr = self.append_instr(after_value.block, ir::InstructionInfo::TupleFieldPtr { base: addr, index: 0, tuple_ty: ty_ir });
+                    let tag = self.append_instr(after_value.block, ir::InstructionInfo::Load { ty: ty_int_ir, addr: ir::Value::Instr(tag_ptr) });
+                    (ir::Value::Instr(tag), Some(addr))
+                } else {
+                    (self.append_load(after_value.block, value).ir, None)
                 };
 
-                //body; index = index + 1
-                let body = |s: &mut Self, body_start: Flow| {
-                    let body_end = s.append_nested_block(body_start, &index_scope, &for_stmt.body)?;
-
-                    let load = ir::InstructionInfo::Load { ty: index_ty_ir, addr: index_slot };
-                    let load = s.append_instr(body_end.block, load);
-
-                    let inc = ir::InstructionInfo::Arithmetic {
-                        kind: ir::ArithmeticOp::Add,
-                        left: ir::Value::Instr(load),
-                        right: ir::Value::Const(ir::Const { ty: index_ty_ir, value: 1 }),
-                    };
-                    let inc = s.append_instr(body_end.block, inc);
-
-                    let store = ir::InstructionInfo::Store {
-                        addr: index_slot,
-                        ty: index_ty_ir,
-                        value: ir::Value::Instr(inc),
-                    };
-                    s.append_instr(body_end.block, store);
-
-                    Ok(body_end)
-                };
+                //collect the cases that can be reached
+                let mut cases = Vec::new();
+                for arm in &match_stmt.arms {
+                    match self.match_pattern_case(scope, &arm.pattern)? {
+                        None => break,
+                        Some(case) => cases.push(case),
+                    }
+                }
 
-                self.append_loop(flow, cond, body)
+                if is_dense_switch(&cases) {
+                    self.append_match_switch(after_value, scope, key, addr, ty, &match_stmt.arms)
+                } else {
+                    self.append_match_chain(after_value, scope, key, addr, ty, &match_stmt.arms)
+                }
             }
             ast::StatementKind::Block(block) => {
                 self.append_nested_block(flow, scope, block)
             }
+            ast::StatementKind::Function(func) => {
+                //the body is lowered separately, like any other function
+                let func = *self.items.nested_funcs.get(&(&**func as *const _)).unwrap();
+                let id = &self.items.funcs[func].ast.id;
+                scope.declare(id, ScopedItem::Value(ScopedValue::Function(func)))?;
+                Ok(flow)
+            }
             ast::StatementKind::Expression(expr) => {
                 let (after_value, _) = self.append_expr(flow, scope, expr)?;
                 Ok(after_value)
             }
+            ast::StatementKind::Assert(cond) => {
+                //disabled asserts don't evaluate their condition at all
+                if !self.settings.check_asserts {
+                    return Ok(flow);
+                }
+
+                let (after_cond, cond) = self.append_expr_loaded(flow, scope, cond)?;
+                Ok(self.append_check(after_cond, stmt.span, cond.ir, b"assertion failed"))
+            }
         }
     }
 
+    /// Assign `value` to `target`, each element of a tuple target is assigned the corresponding field of `value`.
+    fn append_assign_target(&mut self, flow: Flow, scope: &Scope<ScopedItem>, target: &'ast ast::Expression, value: TypedValue) -> Result<'ast, Flow> {
+        match &target.kind {
+            ast::ExpressionKind::Tuple { values } => {
+                let ty_ir = self.types.map_type(self.prog, value.ty);
+                let field_tys = self.types[value.ty].unwrap_tuple().unwrap().fields.clone();
+
+                let slot = ir::Value::Slot(self.define_slot(ty_ir));
+                self.append_instr(flow.block, ir::InstructionInfo::Store { addr: slot, ty: ty_ir, value: value.ir });
+
+                let mut curr = flow;
+                for (index, (target, field_ty)) in values.iter().zip(field_tys).enumerate() {
+                    let field_ty_ir = self.types.map_type(self.prog, field_ty);
+                    let field_ptr = self.append_instr(curr.block, ir::InstructionInfo::TupleFieldPtr { base: slot, index: index as u32, tuple_ty: ty_ir });
+                    let field = self.append_instr(curr.block, ir::InstructionInfo::Load { ty: field_ty_ir, addr: ir::Value::Instr(field_ptr) });
+
+                    let field = TypedValue { ty: field_ty, ir: ir::Value::Instr(field) };
+                    curr = self.append_assign_target(curr, scope, target, field)?;
+                }
+
+                Ok(curr)
+            }
+            _ => {
+                let (after_addr, addr) = self.append_expr_lvalue(flow, scope, target)?;
+                let value = self.append_coerce(after_addr.block, value, self.types[addr.ty].unwrap_ptr().unwrap());
+
+                let ty_ir = self.types.map_type(self.prog, value.ty);
+                self.append_instr(after_addr.block, ir::InstructionInfo::Store { addr: addr.ir, ty: ty_ir, value: value.ir });
+
+                Ok(after_addr)
+            }
+        }
+    }
+
+    /// Define the slot for a local variable of type `ty` and declare it as `id`, returns the slot.
+    fn declare_variable(&mut self, scope: &mut Scope<ScopedItem>, id: &'ast ast::MaybeIdentifier, ty: cst::Type) -> Result<'ast, ir::Value> {
+        let ty_ptr = self.types.define_type_ptr(ty);
+        let ty_ir = self.types.map_type(self.prog, ty);
+
+        let slot = ir::Value::Slot(self.define_slot(ty_ir));
+        let slot_value = LRValue::Left(TypedValue { ty: ty_ptr, ir: slot });
+        scope.maybe_declare(id, ScopedItem::Value(ScopedValue::Immediate(slot_value)))?;
+
+        Ok(slot)
+    }
+
+    /// Create the scopes for the branches of a condition, mirroring `TypeFuncState::narrowed_scopes`. The narrowed
+    /// variable shares its slot with the original optional pointer, only the type changes.
+    fn narrowed_scopes<'s>(
+        &mut self,
+        scope: &'s Scope<ScopedItem>,
+        cond: &'ast ast::Expression,
+    ) -> Result<'ast, (Scope<'s, ScopedItem>, Scope<'s, ScopedItem>)> {
+        let mut then_scope = scope.nest();
+        let mut else_scope = scope.nest();
+
+        if let Some((path, then_non_null)) = null_check(cond) {
+            if let ScopedItem::Value(ScopedValue::Immediate(LRValue::Left(value))) = self.items.resolve_path(ScopeKind::Real, scope, path)? {
+                let optional_ty = self.types[value.ty].unwrap_ptr().expect("Left should have pointer type");
+                if let TypeInfo::OptionalPointer(inner) = self.types[optional_ty] {
+                    let narrowed_ty = self.types.define_type_ptr(inner);
+                    let narrowed_ty_ptr = self.types.define_type_ptr(narrowed_ty);
+                    let narrowed = LRValue::Left(TypedValue { ty: narrowed_ty_ptr, ir: value.ir });
+
+                    let narrowed_scope = if then_non_null { &mut then_scope } else { &mut else_scope };
+                    narrowed_scope.declare(&path.id, ScopedItem::Value(ScopedValue::Immediate(narrowed)))?;
+                }
+            }
+        }
+
+        Ok((then_scope, else_scope))
+    }
+
     fn append_nested_block(&mut self, flow: Flow, scope: &Scope<ScopedItem>, block: &'ast ast::Block) -> Result<'ast, Flow> {
+        let (flow, _) = self.append_block_value(flow, scope, block)?;
+        Ok(flow)
+    }
+
+    /// Lower a block and return its value, or `None` if it doesn't have one.
+    fn append_block_value(
+        &mut self,
+        flow: Flow,
+        scope: &Scope<ScopedItem>,
+        block: &'ast ast::Block,
+    ) -> Result<'ast, (Flow, Option<TypedValue>)> {
         let mut inner_scope = scope.nest();
 
-        block.statements.iter()
+        let flow = block.statements.iter()
             .try_fold(flow, |flow, stmt| {
                 self.append_statement(flow, &mut inner_scope, stmt)
-            })
+            })?;
+
+        match &block.value {
+            Some(value) => {
+                let (flow, value) = self.append_expr_loaded(flow, &inner_scope, value)?;
+                Ok((flow, Some(value)))
+            }
+            None => Ok((flow, None)),
+        }
     }
 
-    pub fn lower_func(&mut self, decl: &'cst cst::FunctionDecl<'ast>) -> Result<'ast, ()> {
-        let start = self.new_flow(true);
+    /// Declare the parameters and lower the body of the current function, starting at `start`.
+    /// Returns whether the end of the body is reachable without a return statement.
+    fn append_func_body(
+        &mut self,
+        start: Flow,
+        mut scope: Scope<ScopedItem>,
+        params: &'ast [ast::Parameter],
+        param_tys: &[cst::Type],
+        body: &'ast ast::Block,
+    ) -> Result<'ast, bool> {
         self.prog.get_func_mut(self.ir_func).entry = ir::Target { block: start.block, phi_values: vec![] };
 
-        let mut scope = self.module_scope.nest();
-
-        for (i, param) in decl.ast.params.iter().enumerate() {
-            // get all of the types
-            let ty = decl.func_ty.params[i];
-            let ty_ir = self.prog.get_func(self.ir_func).func_ty.params[i];
+        for (param, &ty) in params.iter().zip(param_tys) {
+            //the ir function may already have params that don't correspond to an ast param
+            let ir_index = self.prog.get_func(self.ir_func).params.len();
+            let ty_ir = self.prog.get_func(self.ir_func).func_ty.params[ir_index];
             let ty_ptr = self.types.define_type_ptr(ty);
 
             //create the param
@@ -750,8 +2291,6 @@ impl<'ir, 'ast, 'cst, 'ts, F: Fn(ScopedValue) -> LRValue> LowerFuncState<'ir, 'a
             scope.maybe_declare(&param.id, item)?;
         }
 
-        let body = decl.ast.body.as_ref().
-            expect("can only generate code for functions with a body");
         let end = self.append_nested_block(start, &scope, body)?;
 
         if end.needs_return {
@@ -760,10 +2299,32 @@ impl<'ir, 'ast, 'cst, 'ts, F: Fn(ScopedValue) -> LRValue> LowerFuncState<'ir, 'a
                 let ret = ir::Terminator::Return { value: ir::Value::Undef(self.prog.ty_ptr()) };
                 self.prog.get_block_mut(end.block).terminator = ret;
             } else {
-                return Err(Error::MissingReturn(&decl.ast.id));
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    pub fn lower_func(&mut self, decl: &'cst cst::FunctionDecl<'ast>) -> Result<'ast, ()> {
+        let start = self.new_flow(true);
+        let mut scope = self.module_scope.nest();
+
+        //nested functions can call themselves and the other functions declared in the same block
+        if let Some(siblings) = self.items.nested_siblings.get(&(decl.ast as *const _)) {
+            for &func in siblings {
+                let id = &self.items.funcs[func].ast.id;
+                scope.declare(id, ScopedItem::Value(ScopedValue::Function(func)))?;
             }
         }
 
+        let body = decl.ast.body.as_ref().
+            expect("can only generate code for functions with a body");
+
+        if self.append_func_body(start, scope, &decl.ast.params, &decl.func_ty.params, body)? {
+            return Err(Error::MissingReturn(&decl.ast.id));
+        }
+
         Ok(())
     }
 }
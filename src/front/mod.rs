@@ -8,12 +8,17 @@ pub mod ast;
 pub mod cst;
 
 pub mod error;
+pub mod diag;
 pub mod scope;
 pub mod type_solver;
 
+pub mod literal;
+pub mod const_eval;
 pub mod parser;
 pub mod resolve;
 pub mod lower;
+pub mod runtime;
+pub mod capture;
 pub mod type_func;
 pub mod lower_func;
 
@@ -29,6 +34,28 @@ pub struct Module<C> {
     pub submodules: IndexMap<String, Module<C>>,
 }
 
+impl Program<Option<ast::ModuleContent>> {
+    /// Add a module with the given path and content, inline modules declared within it are added as child modules.
+    /// Returns the path of the first module that already had content as error.
+    pub fn add_module(&mut self, path: Vec<String>, mut content: ast::ModuleContent) -> Result<(), Vec<String>> {
+        let inline_modules = std::mem::take(&mut content.modules);
+
+        let module = self.find_or_create_module(path.clone());
+        if module.content.is_some() {
+            return Err(path);
+        }
+        module.content = Some(content);
+
+        for inline in inline_modules {
+            let mut inline_path = path.clone();
+            inline_path.push(inline.id.string);
+            self.add_module(inline_path, inline.content)?;
+        }
+
+        Ok(())
+    }
+}
+
 impl<C> Program<C> {
     pub fn find_or_create_module(&mut self, path: Vec<String>) -> &mut Module<C> where C: Default {
         path.into_iter().fold(&mut self.root, |a, elem|
@@ -3,7 +3,9 @@ use std::mem::swap;
 use TokenType as TT;
 
 use crate::front::ast;
+use crate::front::literal::parse_int_literal;
 use crate::front::pos::{FileId, Pos, Span};
+use crate::mid::ir::Signedness;
 
 type Result<T> = std::result::Result<T, ParseError>;
 
@@ -23,6 +25,15 @@ pub enum ParseError {
         after: Pos,
         expected: &'static str,
     },
+    Escape {
+        pos: Pos,
+        sequence: String,
+    },
+    IntLit {
+        span: Span,
+        lit: String,
+        bits: u32,
+    },
 }
 
 macro_rules! declare_tokens {
@@ -41,36 +52,56 @@ macro_rules! declare_tokens {
 declare_tokens![
     Id,
     IntLit,
+    FloatLit,
     StringLit,
 
     Void("void"),
     Bool("bool"),
     Byte("byte"),
     Int("int"),
+    F32("f32"),
+    F64("f64"),
+    Str("str"),
 
     True("true"),
     False("false"),
     Null("null"),
 
+    Pub("pub"),
     Extern("extern"),
     Use("use"),
+    Mod("mod"),
     Struct("struct"),
+    Enum("enum"),
     Fun("fun"),
     Return("return"),
     Let("let"),
     Const("const"),
+    StaticAssert("static_assert"),
     Mut("mut"),
     If("if"),
     Else("else"),
     While("while"),
+    Loop("loop"),
+    Do("do"),
+    Step("step"),
     For("for"),
+    Match("match"),
     In("in"),
     As("as"),
+    Asm("asm"),
+    Alloc("alloc"),
+    Free("free"),
+    New("new"),
+    Panic("panic"),
+    Assert("assert"),
+    Unreachable("unreachable"),
     Break("break"),
     Continue("continue"),
 
     Underscore("_"),
     Arrow("->"),
+    DoubleArrow("=>"),
     DoubleDot(".."),
 
     NotEq("!="),
@@ -94,6 +125,8 @@ declare_tokens![
     Eq("="),
     Ampersand("&"),
     Star("*"),
+    Pipe("|"),
+    At("@"),
 
     OpenB("("),
     CloseB(")"),
@@ -105,11 +138,13 @@ declare_tokens![
     Eof,
 ];
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Token {
     ty: TT,
     string: String,
     span: Span,
+    /// The `///` doc comment right before this token, if any.
+    doc: Option<String>,
 }
 
 impl Token {
@@ -118,10 +153,12 @@ impl Token {
             ty: TT::Eof,
             string: "".to_string(),
             span: Span::empty_at(pos),
+            doc: None,
         }
     }
 }
 
+#[derive(Clone)]
 struct Tokenizer<'s> {
     left: &'s str,
     pos: Pos,
@@ -179,24 +216,49 @@ impl<'s> Tokenizer<'s> {
         }
     }
 
-    fn skip_whitespace_and_comments(&mut self) -> Result<()> {
+    /// Skip whitespace and comments, returning the lines of the `///` doc comments that were skipped.
+    fn skip_whitespace_and_comments(&mut self) -> Result<Option<String>> {
+        let mut doc: Option<String> = None;
+
         loop {
             let prev_left = self.left;
             self.skip_count(self.left.len() - self.left.trim_start().len());
 
             if self.left.starts_with("//") {
+                let comment_start = self.left;
                 self.skip_past("\n", true)?;
+
+                //`////` is just a regular comment
+                if comment_start.starts_with("///") && !comment_start.starts_with("////") {
+                    let comment = &comment_start[3..comment_start.len() - self.left.len()];
+                    let line = comment.trim_end_matches(&['\r', '\n'][..]);
+                    let line = line.strip_prefix(' ').unwrap_or(line);
+
+                    match &mut doc {
+                        None => doc = Some(line.to_owned()),
+                        Some(doc) => {
+                            doc.push('\n');
+                            doc.push_str(line);
+                        }
+                    }
+                }
             }
             if self.left.starts_with("/*") {
                 self.skip_past("*/", false)?;
             }
 
-            if prev_left == self.left { return Ok(()); }
+            if prev_left == self.left { return Ok(doc); }
         }
     }
 
     fn parse_next(&mut self) -> Result<Token> {
-        self.skip_whitespace_and_comments()?;
+        let doc = self.skip_whitespace_and_comments()?;
+        let mut token = self.parse_token()?;
+        token.doc = doc;
+        Ok(token)
+    }
+
+    fn parse_token(&mut self) -> Result<Token> {
         let start_pos = self.pos;
 
         let peek = if let Some(peek) = self.left.chars().next() {
@@ -207,15 +269,31 @@ impl<'s> Tokenizer<'s> {
 
         //number
         if peek.is_ascii_digit() {
-            let end = self.left
+            let int_end = self.left
                 .find(|c: char| !c.is_ascii_digit())
                 .unwrap_or(self.left.len());
+
+            //a fractional part makes this a float literal, except after a dot so `a.0.1` stays a nested tuple index
+            let after_int = &self.left[int_end..];
+            let is_float = self.next.ty != TT::Dot
+                && after_int.starts_with('.')
+                && after_int[1..].starts_with(|c: char| c.is_ascii_digit());
+
+            let (ty, end) = if is_float {
+                let frac_len = after_int[1..]
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(after_int.len() - 1);
+                (TT::FloatLit, int_end + 1 + frac_len)
+            } else {
+                (TT::IntLit, int_end)
+            };
             let string = self.skip_count(end).to_owned();
 
             return Ok(Token {
-                ty: TT::IntLit,
+                ty,
                 string,
                 span: Span::new(start_pos, self.pos),
+                doc: None,
             });
         }
 
@@ -236,19 +314,27 @@ impl<'s> Tokenizer<'s> {
                 ty,
                 string,
                 span: Span::new(start_pos, self.pos),
+                doc: None,
             });
         }
 
         //string literal
         if peek == '"' {
-            let end = 1 + self.left[1..].find('"')
-                .ok_or(ParseError::Eof { after: self.pos, expected: "\"" })?;
+            //find the closing quote, skipping over escaped characters
+            //  the escapes themselves are only decoded by the parser
+            let mut escaped = false;
+            let end = 1 + self.left[1..].find(|c: char| {
+                let is_end = !escaped && c == '"';
+                escaped = !escaped && c == '\\';
+                is_end
+            }).ok_or(ParseError::Eof { after: self.pos, expected: "\"" })?;
             let content = self.skip_count(end + 1)[1..end].to_owned();
 
             return Ok(Token {
                 ty: TT::StringLit,
                 string: content,
                 span: Span::new(start_pos, self.pos),
+                doc: None,
             });
         }
 
@@ -261,6 +347,7 @@ impl<'s> Tokenizer<'s> {
                     ty: *ty,
                     string: pattern.to_string(),
                     span: Span::new(start_pos, end_pos),
+                    doc: None,
                 });
             }
         }
@@ -290,15 +377,26 @@ struct Parser<'a> {
 }
 
 const EXPR_START_TOKENS: &[TT] = &[
+    TT::OpenC,
+    TT::If,
     TT::Return,
     TT::Ampersand,
     TT::Star,
     TT::Minus,
     TT::IntLit,
+    TT::FloatLit,
     TT::True,
     TT::False,
     TT::Id,
     TT::OpenB,
+    TT::OpenS,
+    TT::Pipe,
+    TT::Asm,
+    TT::Alloc,
+    TT::Free,
+    TT::New,
+    TT::Panic,
+    TT::Unreachable,
 ];
 
 const TYPE_START_TOKENS: &[TT] = &[
@@ -307,12 +405,22 @@ const TYPE_START_TOKENS: &[TT] = &[
     TT::Bool,
     TT::Byte,
     TT::Int,
+    TT::F32,
+    TT::F64,
+    TT::Str,
     TT::Ampersand,
+    TT::QuestionMark,
     TT::Id,
     TT::OpenB,
-    TT::OpenS
+    TT::OpenS,
+    TT::Pipe,
 ];
 
+enum BlockElement {
+    Statement(ast::Statement),
+    Value(ast::Expression),
+}
+
 struct BinOpInfo {
     level: u8,
     token: TT,
@@ -383,6 +491,8 @@ impl PostFixState {
                 ast::ExpressionKind::Call { target: inner, args },
             PostFixStateKind::ArrayIndex { index } =>
                 ast::ExpressionKind::ArrayIndex { target: inner, index },
+            PostFixStateKind::Slice { start, end } =>
+                ast::ExpressionKind::Slice { target: inner, start, end },
             PostFixStateKind::DotIndex { index } =>
                 ast::ExpressionKind::DotIndex { target: inner, index },
             PostFixStateKind::Cast { ty } =>
@@ -394,8 +504,9 @@ impl PostFixState {
 }
 
 enum PostFixStateKind {
-    Call { args: Vec<ast::Expression> },
+    Call { args: Vec<ast::CallArgument> },
     ArrayIndex { index: Box<ast::Expression> },
+    Slice { start: Option<Box<ast::Expression>>, end: Option<Box<ast::Expression>> },
     DotIndex { index: ast::DotIndexIndex },
     Cast { ty: ast::Type },
 }
@@ -413,10 +524,22 @@ impl<'s> Parser<'s> {
         &self.tokenizer.curr
     }
 
+    /// Take the doc comment attached to the next token.
+    fn take_doc(&mut self) -> Option<String> {
+        self.tokenizer.curr.doc.take()
+    }
+
     fn lookahead(&self) -> &Token {
         &self.tokenizer.next
     }
 
+    /// The token after `lookahead`, only used where a single token of lookahead isn't enough.
+    fn lookahead_second(&self) -> Result<Token> {
+        let mut tokenizer = self.tokenizer.clone();
+        tokenizer.advance()?;
+        Ok(tokenizer.next)
+    }
+
     fn at(&mut self, ty: TT) -> bool {
         self.peek().ty == ty
     }
@@ -464,7 +587,7 @@ impl<'s> Parser<'s> {
         ParseError::Token {
             ty: token.ty,
             pos: token.span.start,
-            allowed: allowed.iter().copied().collect(),
+            allowed: allowed.to_vec(),
             description,
         }
     }
@@ -495,24 +618,83 @@ impl<'s> Parser<'s> {
 }
 
 impl<'s> Parser<'s> {
+    /// Parse items and inline modules until `end` is found.
+    fn module_content(&mut self, end: TT) -> Result<ast::ModuleContent> {
+        let mut items = Vec::new();
+        let mut modules = Vec::new();
+
+        while self.accept(end)?.is_none() {
+            if self.at(TT::Mod) {
+                modules.push(self.inline_module()?);
+            } else {
+                items.push(self.item()?);
+            }
+        }
+
+        Ok(ast::ModuleContent { items, modules })
+    }
+
     fn module(&mut self) -> Result<ast::ModuleContent> {
-        let (_, items) = self.list(TT::Eof, None, Self::item)?;
-        Ok(ast::ModuleContent { items })
+        self.module_content(TT::Eof)
+    }
+
+    fn inline_module(&mut self) -> Result<ast::InlineModule> {
+        let start_pos = self.expect(TT::Mod, "start of module")?.span.start;
+        let id = self.identifier("module name")?;
+        self.expect(TT::OpenC, "start of module content")?;
+        let content = self.module_content(TT::CloseC)?;
+
+        let span = Span::new(start_pos, self.last_popped_end);
+        Ok(ast::InlineModule { span, id, content })
     }
 
     fn item(&mut self) -> Result<ast::Item> {
+        let doc = self.take_doc();
+        let attrs = self.attributes()?;
+        let public = self.accept(TT::Pub)?.is_some();
         let token = self.peek();
 
         match token.ty {
-            TT::Struct => self.struct_().map(ast::Item::Struct),
-            TT::Fun | TT::Extern => self.function().map(ast::Item::Function),
-            TT::Const => self.const_().map(ast::Item::Const),
-            TT::Use => self.use_decl().map(ast::Item::UseDecl),
-            _ => Err(Self::unexpected_token(token, &[TT::Struct, TT::Fun, TT::Extern, TT::Const, TT::Use], "start of item"))
+            TT::Struct => self.struct_(public, attrs, doc).map(ast::Item::Struct),
+            TT::Fun | TT::Extern => self.function(public, attrs, doc).map(ast::Item::Function),
+            _ if !attrs.is_empty() => {
+                Err(Self::unexpected_token(token, &[TT::Struct, TT::Fun, TT::Extern], "item with attributes"))
+            }
+            TT::Enum => self.enum_(public, doc).map(ast::Item::Enum),
+            TT::Const => self.const_(public, doc).map(ast::Item::Const),
+            TT::Use => self.use_decl(public).map(ast::Item::UseDecl),
+            TT::StaticAssert if !public => self.static_assert().map(ast::Item::StaticAssert),
+            _ => {
+                let allowed: &[TT] = if public {
+                    &[TT::Struct, TT::Enum, TT::Fun, TT::Extern, TT::Const, TT::Use]
+                } else {
+                    &[TT::Pub, TT::Struct, TT::Enum, TT::Fun, TT::Extern, TT::Const, TT::Use, TT::StaticAssert, TT::Mod]
+                };
+                Err(Self::unexpected_token(token, allowed, "start of item"))
+            }
+        }
+    }
+
+    /// Parse any number of attributes of the form `@name` or `@name(args)`.
+    fn attributes(&mut self) -> Result<Vec<ast::Attribute>> {
+        let mut attrs = Vec::new();
+
+        while let Some(start) = self.accept(TT::At)? {
+            let id = self.identifier("attribute name")?;
+            let args = if self.accept(TT::OpenB)?.is_some() {
+                self.list(TT::CloseB, Some(TT::Comma), Self::expression)?.1
+            } else {
+                Vec::new()
+            };
+
+            let span = Span::new(start.span.start, self.last_popped_end);
+            attrs.push(ast::Attribute { span, id, args });
         }
+
+        Ok(attrs)
     }
 
-    fn const_(&mut self) -> Result<ast::Const> {
+    fn const_(&mut self, public: bool, doc: Option<String>) -> Result<ast::Const> {
         let start_pos = self.expect(TT::Const, "start of const item")?.span.start;
         let id = self.identifier("const name")?;
         self.expect(TT::Colon, "const type")?;
@@ -522,19 +704,55 @@ impl<'s> Parser<'s> {
         self.expect(TT::Semi, "end of item")?;
 
         let span = Span::new(start_pos, self.last_popped_end);
-        Ok(ast::Const { span, id, ty, init })
+        Ok(ast::Const { span, public, doc, id, ty, init })
+    }
+
+    fn static_assert(&mut self) -> Result<ast::StaticAssert> {
+        let start_pos = self.expect(TT::StaticAssert, "start of static assert")?.span.start;
+        self.expect(TT::OpenB, "start of static assert arguments")?;
+        let cond = self.expression()?;
+
+        let message = if self.accept(TT::Comma)?.is_some() {
+            let token = self.expect(TT::StringLit, "static assert message")?;
+            let content_start = Pos { col: token.span.start.col + 1, ..token.span.start };
+            Some(String::from_utf8_lossy(&unescape_string(content_start, &token.string)?).into_owned())
+        } else {
+            None
+        };
+
+        self.expect(TT::CloseB, "end of static assert arguments")?;
+        self.expect(TT::Semi, "end of item")?;
+
+        let span = Span::new(start_pos, self.last_popped_end);
+        Ok(ast::StaticAssert { span, cond, message })
     }
 
-    fn use_decl(&mut self) -> Result<ast::UseDecl> {
+    fn use_decl(&mut self, public: bool) -> Result<ast::UseDecl> {
         let start_pos = self.expect(TT::Use, "start of use decl")?.span.start;
-        let path = self.path()?;
+
+        //like a normal path, but it can end in `::*`
+        let mut parents = Vec::new();
+        let mut id = self.identifier("identifier")?;
+        let mut glob = false;
+
+        while self.accept(TT::DoubleColon)?.is_some() {
+            if self.accept(TT::Star)?.is_some() {
+                glob = true;
+                break;
+            }
+
+            parents.push(id);
+            id = self.identifier("path element")?;
+        }
+
+        let path = ast::Path { span: Span::new(id.span.start, self.last_popped_end), parents, id };
         self.expect(TT::Semi, "end of item")?;
 
-        let span = Span::new(start_pos, path.span.end);
-        Ok(ast::UseDecl { span, path })
+        let span = Span::new(start_pos, self.last_popped_end);
+        Ok(ast::UseDecl { span, public, path, glob })
     }
 
-    fn struct_(&mut self) -> Result<ast::Struct> {
+    fn struct_(&mut self, public: bool, attrs: Vec<ast::Attribute>, doc: Option<String>) -> Result<ast::Struct> {
         let start = self.expect(TT::Struct, "start of struct declaration")?.span.start;
         let id = self.identifier("struct name")?;
         self.expect(TT::OpenC, "start of struct fields")?;
@@ -542,7 +760,7 @@ impl<'s> Parser<'s> {
         let (_, fields) = self.list(TT::CloseC, Some(TT::Comma), Self::struct_field)?;
 
         let span = Span::new(start, self.last_popped_end);
-        Ok(ast::Struct { span, id, fields })
+        Ok(ast::Struct { span, public, attrs, doc, id, fields })
     }
 
     fn struct_field(&mut self) -> Result<ast::StructField> {
@@ -554,12 +772,57 @@ impl<'s> Parser<'s> {
         Ok(ast::StructField { span, id, ty })
     }
 
-    fn function(&mut self) -> Result<ast::Function> {
+    fn enum_(&mut self, public: bool, doc: Option<String>) -> Result<ast::Enum> {
+        let start = self.expect(TT::Enum, "start of enum declaration")?.span.start;
+        let id = self.identifier("enum name")?;
+        self.expect(TT::OpenC, "start of enum variants")?;
+
+        let (_, variants) = self.list(TT::CloseC, Some(TT::Comma), Self::enum_variant)?;
+
+        let span = Span::new(start, self.last_popped_end);
+        Ok(ast::Enum { span, public, doc, id, variants })
+    }
+
+    fn enum_variant(&mut self) -> Result<ast::EnumVariant> {
+        let id = self.identifier("variant name")?;
+
+        let fields = if self.accept(TT::OpenB)?.is_some() {
+            self.list(TT::CloseB, Some(TT::Comma), Self::type_decl)?.1
+        } else {
+            Vec::new()
+        };
+
+        let span = Span::new(id.span.start, self.last_popped_end);
+        Ok(ast::EnumVariant { span, id, fields })
+    }
+
+    fn function(&mut self, public: bool, attrs: Vec<ast::Attribute>, doc: Option<String>) -> Result<ast::Function> {
         let start_pos = self.peek().span.start;
 
         let ext = self.accept(TT::Extern)?.is_some();
+        let conv = if ext {
+            self.accept(TT::StringLit)?.map(|token| ast::CallConv { span: token.span, name: token.string })
+        } else {
+            None
+        };
         self.expect(TT::Fun, "function declaration")?;
-        let id = self.identifier("function name")?;
+        let mut id = self.identifier("function name")?;
+
+        //operator overloads are named after the operator they implement, eg. `operator+`
+        let next_ty = self.peek().ty;
+        let operator = if id.string == "operator" {
+            match BINARY_OPERATOR_INFO.iter().find(|info| info.token == next_ty) {
+                Some(info) => {
+                    let token = self.pop()?;
+                    id.string.push_str(&token.string);
+                    id.span = Span::new(id.span.start, token.span.end);
+                    Some(info.op)
+                }
+                None => None,
+            }
+        } else {
+            None
+        };
 
         self.expect(TT::OpenB, "start of parameters")?;
         let (_, params) = self.list(TT::CloseB, Some(TT::Comma), Self::parameter)?;
@@ -578,7 +841,7 @@ impl<'s> Parser<'s> {
         };
 
         let span = Span::new(start_pos, self.last_popped_end);
-        Ok(ast::Function { span, ext, id, ret_ty, params, body })
+        Ok(ast::Function { span, public, attrs, doc, ext, conv, id, operator, ret_ty, params, body })
     }
 
     fn parameter(&mut self) -> Result<ast::Parameter> {
@@ -591,14 +854,43 @@ impl<'s> Parser<'s> {
         Ok(ast::Parameter { span, id, ty })
     }
 
+    fn call_argument(&mut self) -> Result<ast::CallArgument> {
+        let start = self.peek().span.start;
+        let name = if self.at(TT::Id) && self.lookahead().ty == TT::Colon {
+            let name = self.identifier("argument name")?;
+            self.expect(TT::Colon, "argument value")?;
+            Some(name)
+        } else {
+            None
+        };
+        let value = self.expression()?;
+
+        let span = Span::new(start, value.span.end);
+        Ok(ast::CallArgument { span, name, value })
+    }
+
     fn block(&mut self) -> Result<ast::Block> {
         let start_pos = self.expect(TT::OpenC, "start of block")?.span.start;
-        let (span, statements) = self.list(TT::CloseC, None, Self::statement)?;
 
-        Ok(ast::Block { span: Span::new(start_pos, span.end), statements })
+        let mut statements = Vec::new();
+        let mut value = None;
+
+        while self.accept(TT::CloseC)?.is_none() {
+            match self.statement()? {
+                BlockElement::Statement(statement) => statements.push(statement),
+                BlockElement::Value(expr) => {
+                    value = Some(Box::new(expr));
+                    self.expect(TT::CloseC, "end of block")?;
+                    break;
+                }
+            }
+        }
+
+        let span = Span::new(start_pos, self.last_popped_end);
+        Ok(ast::Block { span, statements, value })
     }
 
-    fn statement(&mut self) -> Result<ast::Statement> {
+    fn statement(&mut self) -> Result<BlockElement> {
         let token = self.peek();
         let start_pos = token.span.start;
 
@@ -633,6 +925,23 @@ impl<'s> Parser<'s> {
                 let span = Span::new(start_pos, self.last_popped_end);
                 (ast::StatementKind::While(ast::WhileStatement { span, cond, body }), false)
             }
+            TT::Loop => {
+                self.pop()?;
+                let body = self.block()?;
+
+                let span = Span::new(start_pos, self.last_popped_end);
+                (ast::StatementKind::Loop(ast::LoopStatement { span, body }), false)
+            }
+            TT::Do => {
+                self.pop()?;
+                let body = self.block()?;
+
+                self.expect(TT::While, "while")?;
+                let cond = Box::new(self.expression()?);
+
+                let span = Span::new(start_pos, self.last_popped_end);
+                (ast::StatementKind::DoWhile(ast::WhileStatement { span, cond, body }), true)
+            }
             TT::For => {
                 self.pop()?;
 
@@ -641,17 +950,46 @@ impl<'s> Parser<'s> {
 
                 self.expect(TT::In, "in")?;
                 let start = Box::new(self.expression()?);
-                self.expect(TT::DoubleDot, "range separator")?;
-                let end = Box::new(self.expression()?);
+                let range = if self.accept(TT::DoubleDot)?.is_some() {
+                    let end = Box::new(self.expression()?);
+                    let step = self.accept(TT::Step)?
+                        .map(|_| self.expression().map(Box::new))
+                        .transpose()?;
+                    ast::ForRange::Range { start, end, step }
+                } else {
+                    ast::ForRange::Array(start)
+                };
 
                 let body = self.block()?;
 
                 let span = Span::new(start_pos, self.last_popped_end);
-                (ast::StatementKind::For(ast::ForStatement { span, index, index_ty, start, end, body }), false)
+                (ast::StatementKind::For(ast::ForStatement { span, index, index_ty, range, body }), false)
+            }
+            TT::Match => {
+                self.pop()?;
+
+                let value = Box::new(self.expression()?);
+                self.expect(TT::OpenC, "start of match arms")?;
+                let (_, arms) = self.list(TT::CloseC, None, Self::match_arm)?;
+
+                let span = Span::new(start_pos, self.last_popped_end);
+                (ast::StatementKind::Match(ast::MatchStatement { span, value, arms }), false)
+            }
+            TT::Assert => {
+                self.pop()?;
+                self.expect(TT::OpenB, "start of assert condition")?;
+                let cond = self.expression()?;
+                self.expect(TT::CloseB, "end of assert condition")?;
+
+                (ast::StatementKind::Assert(Box::new(cond)), true)
             }
             TT::OpenC => {
                 (ast::StatementKind::Block(self.block()?), false)
             }
+            TT::Fun => {
+                let doc = self.take_doc();
+                (ast::StatementKind::Function(Box::new(self.function(false, Vec::new(), doc)?)), false)
+            }
             _ => {
                 let left = self.expression()?;
 
@@ -663,6 +1001,9 @@ impl<'s> Parser<'s> {
                         left: Box::new(left),
                         right: Box::new(right),
                     })
+                } else if self.at(TT::CloseC) {
+                    //an expression without semicolon at the end of a block is the value of that block
+                    return Ok(BlockElement::Value(left));
                 } else {
                     //expression
                     ast::StatementKind::Expression(Box::new(left))
@@ -677,20 +1018,86 @@ impl<'s> Parser<'s> {
         }
 
         let span = Span::new(start_pos, self.last_popped_end);
-        Ok(ast::Statement { span, kind })
+        Ok(BlockElement::Statement(ast::Statement { span, kind }))
+    }
+
+    fn match_arm(&mut self) -> Result<ast::MatchArm> {
+        let start_pos = self.peek().span.start;
+        let pattern = self.match_pattern()?;
+        self.expect(TT::DoubleArrow, "match arm body")?;
+        let body = self.block()?;
+
+        let span = Span::new(start_pos, self.last_popped_end);
+        //the separating comma is optional since arm bodies are always blocks
+        self.accept(TT::Comma)?;
+
+        Ok(ast::MatchArm { span, pattern, body })
+    }
+
+    fn match_pattern(&mut self) -> Result<ast::MatchPattern> {
+        if self.at(TT::Underscore) {
+            return Ok(ast::MatchPattern::Wildcard(self.pop()?.span));
+        }
+        if self.at(TT::IntLit) {
+            let token = self.pop()?;
+            let kind = ast::ExpressionKind::IntLit { value: token.string };
+            return Ok(ast::MatchPattern::Literal(Box::new(ast::Expression { span: token.span, kind })));
+        }
+
+        let path = self.path()?;
+        let fields = if self.accept(TT::OpenB)?.is_some() {
+            self.list(TT::CloseB, Some(TT::Comma), |s| s.maybe_identifier("payload binding"))?.1
+        } else {
+            Vec::new()
+        };
+
+        let span = Span::new(path.span.start, self.last_popped_end);
+        Ok(ast::MatchPattern::Variant { span, path, fields })
     }
 
     fn variable_declaration(&mut self, ty: TT) -> Result<ast::Declaration> {
         let start_pos = self.expect(ty, "variable declaration")?.span.start;
         let mutable = self.accept(TT::Mut)?.is_some();
-        let id = self.maybe_identifier("variable name")?;
+        let pattern = self.declaration_pattern()?;
 
         let ty = self.maybe_type_decl()?;
         let init = self.accept(TT::Eq)?
             .map(|_| self.expression().map(Box::new))
             .transpose()?;
 
-        Ok(ast::Declaration { span: Span::new(start_pos, self.last_popped_end), mutable, ty, id, init })
+        Ok(ast::Declaration { span: Span::new(start_pos, self.last_popped_end), mutable, ty, pattern, init })
+    }
+
+    fn declaration_pattern(&mut self) -> Result<ast::DeclarationPattern> {
+        let start_pos = self.peek().span.start;
+
+        if self.accept(TT::OpenB)?.is_some() {
+            let (_, elements) = self.list(TT::CloseB, Some(TT::Comma), |s| s.maybe_identifier("tuple element binding"))?;
+            let span = Span::new(start_pos, self.last_popped_end);
+            return Ok(ast::DeclarationPattern::Tuple { span, elements });
+        }
+
+        let is_struct = self.at(TT::Id) && matches!(self.lookahead().ty, TT::OpenC | TT::DoubleColon);
+        if !is_struct {
+            return Ok(ast::DeclarationPattern::Single(self.maybe_identifier("variable name")?));
+        }
+
+        let path = Box::new(self.path()?);
+        self.expect(TT::OpenC, "start of struct pattern")?;
+        let (_, fields) = self.list(TT::CloseC, Some(TT::Comma), |s| {
+            let field = s.identifier("struct field")?;
+            let binding = if s.accept(TT::Colon)?.is_some() {
+                s.maybe_identifier("struct field binding")?
+            } else {
+                ast::MaybeIdentifier::Identifier(ast::Identifier { span: field.span, string: field.string.clone() })
+            };
+
+            let span = Span::new(field.span.start, s.last_popped_end);
+            Ok(ast::StructPatternField { span, field, binding })
+        })?;
+
+        let span = Span::new(start_pos, self.last_popped_end);
+        Ok(ast::DeclarationPattern::Struct { span, path, fields })
     }
 
     fn expression(&mut self) -> Result<ast::Expression> {
@@ -814,17 +1221,26 @@ impl<'s> Parser<'s> {
                 TT::OpenB => {
                     //call
                     self.pop()?;
-                    let (_, args) = self.list(TT::CloseB, Some(TT::Comma), Self::expression)?;
+                    let (_, args) = self.list(TT::CloseB, Some(TT::Comma), Self::call_argument)?;
 
                     (POSTFIX_DEFAULT_LEVEL, PostFixStateKind::Call { args })
                 }
                 TT::OpenS => {
-                    //array indexing
+                    //array indexing or slicing, both bounds of a slice are optional
                     self.pop()?;
-                    let index = Box::new(self.expression()?);
-                    self.expect(TT::CloseS, "")?;
+                    let start = if self.at(TT::DoubleDot) { None } else { Some(Box::new(self.expression()?)) };
+
+                    let kind = match start {
+                        Some(index) if self.at(TT::CloseS) => PostFixStateKind::ArrayIndex { index },
+                        start => {
+                            self.expect(TT::DoubleDot, "slice range")?;
+                            let end = if self.at(TT::CloseS) { None } else { Some(Box::new(self.expression()?)) };
+                            PostFixStateKind::Slice { start, end }
+                        }
+                    };
+                    self.expect(TT::CloseS, "end of index")?;
 
-                    (POSTFIX_DEFAULT_LEVEL, PostFixStateKind::ArrayIndex { index })
+                    (POSTFIX_DEFAULT_LEVEL, kind)
                 }
                 TT::Dot => {
                     //dot indexing
@@ -832,10 +1248,9 @@ impl<'s> Parser<'s> {
 
                     let index = self.expect_any(&[TT::IntLit, TT::Id], "dot index index")?;
                     let index = match index.ty {
-                        //TODO proper IntLit parsing
                         TT::IntLit => ast::DotIndexIndex::Tuple {
                             span: index.span,
-                            index: index.string.parse().unwrap(),
+                            index: parse_int_token(&index, 32)?,
                         },
                         TT::Id => ast::DotIndexIndex::Struct(ast::Identifier {
                             span: index.span,
@@ -862,6 +1277,15 @@ impl<'s> Parser<'s> {
         Ok(result)
     }
 
+    /// Parse a block as an expression.
+    fn atomic_block(&mut self) -> Result<ast::Expression> {
+        let block = self.block()?;
+        Ok(ast::Expression {
+            span: block.span,
+            kind: ast::ExpressionKind::Block(Box::new(block)),
+        })
+    }
+
     fn atomic(&mut self) -> Result<ast::Expression> {
         let start_pos = self.peek().span.start;
 
@@ -873,6 +1297,13 @@ impl<'s> Parser<'s> {
                     kind: ast::ExpressionKind::IntLit { value: token.string },
                 })
             }
+            TT::FloatLit => {
+                let token = self.pop()?;
+                Ok(ast::Expression {
+                    span: token.span,
+                    kind: ast::ExpressionKind::FloatLit { value: token.string },
+                })
+            }
             TT::True | TT::False => {
                 let token = self.pop()?;
                 Ok(ast::Expression {
@@ -889,15 +1320,21 @@ impl<'s> Parser<'s> {
             }
             TT::StringLit => {
                 let token = self.pop()?;
+                //the content starts after the opening quote
+                let content_start = Pos { col: token.span.start.col + 1, ..token.span.start };
                 Ok(ast::Expression {
                     span: token.span,
                     kind: ast::ExpressionKind::StringLit {
-                        value: token.string
+                        value: unescape_string(content_start, &token.string)?
                     },
                 })
             }
             TT::Id => {
                 let path = self.path()?;
+                if self.at_struct_literal()? {
+                    return self.struct_literal(path);
+                }
+
                 Ok(ast::Expression {
                     span: Span::new(start_pos, self.last_popped_end),
                     kind: ast::ExpressionKind::Path(path),
@@ -906,14 +1343,79 @@ impl<'s> Parser<'s> {
             TT::OpenB => {
                 self.pop()?;
                 let expr = self.expression()?;
-                self.expect(TT::CloseB, "closing parenthesis")?;
-                Ok(expr)
+
+                //a comma turns the parenthesized expression into a tuple
+                if self.accept(TT::Comma)?.is_none() {
+                    self.expect(TT::CloseB, "closing parenthesis")?;
+                    return Ok(expr);
+                }
+
+                let mut values = vec![expr];
+                values.extend(self.list(TT::CloseB, Some(TT::Comma), Self::expression)?.1);
+
+                Ok(ast::Expression {
+                    span: Span::new(start_pos, self.last_popped_end),
+                    kind: ast::ExpressionKind::Tuple { values },
+                })
+            }
+            TT::OpenS => {
+                //array repeat
+                self.pop()?;
+                let value = self.expression()?;
+                self.expect(TT::Semi, "array repeat delimiter")?;
+                let length = parse_int_token(&self.expect(TT::IntLit, "array length")?, 32)?;
+                self.expect(TT::CloseS, "end of array repeat")?;
+
+                Ok(ast::Expression {
+                    span: Span::new(start_pos, self.last_popped_end),
+                    kind: ast::ExpressionKind::ArrayRepeat { value: Box::new(value), length },
+                })
+            }
+            TT::OpenC => self.atomic_block(),
+            TT::If => {
+                //if expression, lowered just like a ternary with block values
+                self.pop()?;
+                let condition = self.expression()?;
+                let then_value = self.atomic_block()?;
+
+                self.expect(TT::Else, "else branch of if expression")?;
+                let else_value = if self.at(TT::If) {
+                    self.atomic()?
+                } else {
+                    self.atomic_block()?
+                };
+
+                Ok(ast::Expression {
+                    span: Span::new(start_pos, self.last_popped_end),
+                    kind: ast::ExpressionKind::Ternary {
+                        condition: Box::new(condition),
+                        then_value: Box::new(then_value),
+                        else_value: Box::new(else_value),
+                    },
+                })
+            }
+            TT::Pipe => {
+                //closure
+                self.pop()?;
+                let (_, params) = self.list(TT::Pipe, Some(TT::Comma), Self::parameter)?;
+
+                let ret_ty = if self.accept(TT::Arrow)?.is_some() {
+                    Some(self.type_decl()?)
+                } else {
+                    None
+                };
+                let body = self.block()?;
+
+                Ok(ast::Expression {
+                    span: Span::new(start_pos, self.last_popped_end),
+                    kind: ast::ExpressionKind::Closure { params, ret_ty, body: Box::new(body) },
+                })
             }
             TT::Return => {
                 //TODO think about whether this is the right spot to parse a return
                 self.pop()?;
 
-                let value = if self.peek().ty == TT::Semi {
+                let value = if self.at(TT::Semi) || self.at(TT::CloseC) {
                     None
                 } else {
                     Some(Box::new(self.expression()?))
@@ -924,6 +1426,78 @@ impl<'s> Parser<'s> {
                     kind: ast::ExpressionKind::Return { value },
                 })
             }
+            TT::Asm => {
+                self.pop()?;
+                self.expect(TT::OpenB, "start of asm arguments")?;
+
+                let token = self.expect(TT::StringLit, "asm template")?;
+                let content_start = Pos { col: token.span.start.col + 1, ..token.span.start };
+                let template = String::from_utf8_lossy(&unescape_string(content_start, &token.string)?).into_owned();
+
+                let operands = if self.accept(TT::Comma)?.is_some() {
+                    self.list(TT::CloseB, Some(TT::Comma), Self::asm_operand)?.1
+                } else {
+                    self.expect(TT::CloseB, "end of asm arguments")?;
+                    Vec::new()
+                };
+
+                Ok(ast::Expression {
+                    span: Span::new(start_pos, self.last_popped_end),
+                    kind: ast::ExpressionKind::InlineAsm { template, operands },
+                })
+            }
+            TT::Alloc => {
+                self.pop()?;
+                self.expect(TT::OpenB, "start of alloc argument")?;
+                let size = self.expression()?;
+                self.expect(TT::CloseB, "end of alloc argument")?;
+
+                Ok(ast::Expression {
+                    span: Span::new(start_pos, self.last_popped_end),
+                    kind: ast::ExpressionKind::Alloc { size: Box::new(size) },
+                })
+            }
+            TT::Free => {
+                self.pop()?;
+                self.expect(TT::OpenB, "start of free argument")?;
+                let ptr = self.expression()?;
+                self.expect(TT::CloseB, "end of free argument")?;
+
+                Ok(ast::Expression {
+                    span: Span::new(start_pos, self.last_popped_end),
+                    kind: ast::ExpressionKind::Free { ptr: Box::new(ptr) },
+                })
+            }
+            TT::New => {
+                self.pop()?;
+                let ty = self.type_decl()?;
+
+                Ok(ast::Expression {
+                    span: Span::new(start_pos, self.last_popped_end),
+                    kind: ast::ExpressionKind::New { ty },
+                })
+            }
+            TT::Panic => {
+                self.pop()?;
+                self.expect(TT::OpenB, "start of panic message")?;
+                let message = self.expression()?;
+                self.expect(TT::CloseB, "end of panic message")?;
+
+                Ok(ast::Expression {
+                    span: Span::new(start_pos, self.last_popped_end),
+                    kind: ast::ExpressionKind::Panic { message: Box::new(message) },
+                })
+            }
+            TT::Unreachable => {
+                self.pop()?;
+                self.expect(TT::OpenB, "start of unreachable arguments")?;
+                self.expect(TT::CloseB, "end of unreachable arguments")?;
+
+                Ok(ast::Expression {
+                    span: Span::new(start_pos, self.last_popped_end),
+                    kind: ast::ExpressionKind::Unreachable,
+                })
+            }
             TT::Continue => {
                 Ok(ast::Expression {
                     span: self.pop()?.span,
@@ -940,6 +1514,69 @@ impl<'s> Parser<'s> {
         }
     }
 
+    /// Parse an asm operand of the form `in("reg") value` or `out("reg") place`.
+    fn asm_operand(&mut self) -> Result<ast::AsmOperand> {
+        let start_pos = self.peek().span.start;
+
+        let kind = if self.accept(TT::In)?.is_some() {
+            ast::AsmOperandKind::In
+        } else if self.at(TT::Id) && self.peek().string == "out" {
+            self.pop()?;
+            ast::AsmOperandKind::Out
+        } else {
+            return Err(Self::unexpected_token(self.peek(), &[TT::In, TT::Id], "asm operand direction"));
+        };
+
+        self.expect(TT::OpenB, "start of asm operand constraint")?;
+        let constraint = self.expect(TT::StringLit, "asm operand constraint")?.string;
+        self.expect(TT::CloseB, "end of asm operand constraint")?;
+        let value = self.expression()?;
+
+        let span = Span::new(start_pos, self.last_popped_end);
+        Ok(ast::AsmOperand { span, kind, constraint, value })
+    }
+
+    /// Whether a struct literal follows a path. To avoid confusion with blocks, eg. in `if done { .. }`, a struct
+    /// literal must start with either a field `id:` or the base `..`.
+    fn at_struct_literal(&mut self) -> Result<bool> {
+        if !self.at(TT::OpenC) { return Ok(false); }
+
+        Ok(match self.lookahead().ty {
+            TT::DoubleDot => true,
+            TT::Id => self.lookahead_second()?.ty == TT::Colon,
+            _ => false,
+        })
+    }
+
+    fn struct_literal(&mut self, path: ast::Path) -> Result<ast::Expression> {
+        let start_pos = path.span.start;
+        self.expect(TT::OpenC, "start of struct literal")?;
+
+        let mut fields = Vec::new();
+        let mut base = None;
+
+        while !self.at(TT::CloseC) {
+            if self.accept(TT::DoubleDot)?.is_some() {
+                base = Some(Box::new(self.expression()?));
+                break;
+            }
+
+            let id = self.identifier("struct literal field")?;
+            self.expect(TT::Colon, "struct literal field separator")?;
+            let value = self.expression()?;
+            fields.push(ast::StructLitField { span: Span::new(id.span.start, value.span.end), id, value });
+
+            if self.accept(TT::Comma)?.is_none() { break; }
+        }
+
+        self.expect(TT::CloseC, "end of struct literal")?;
+
+        Ok(ast::Expression {
+            span: Span::new(start_pos, self.last_popped_end),
+            kind: ast::ExpressionKind::StructLit { path, fields, base },
+        })
+    }
+
     fn path(&mut self) -> Result<ast::Path> {
         let mut parents = Vec::new();
         let mut id = self.identifier("identifier")?;
@@ -981,14 +1618,46 @@ impl<'s> Parser<'s> {
             TT::Bool => Ok(ast::Type { span: self.pop()?.span, kind: ast::TypeKind::Bool }),
             TT::Byte => Ok(ast::Type { span: self.pop()?.span, kind: ast::TypeKind::Byte }),
             TT::Int => Ok(ast::Type { span: self.pop()?.span, kind: ast::TypeKind::Int }),
+            TT::F32 => Ok(ast::Type { span: self.pop()?.span, kind: ast::TypeKind::Float { bits: 32 } }),
+            TT::F64 => Ok(ast::Type { span: self.pop()?.span, kind: ast::TypeKind::Float { bits: 64 } }),
+            TT::Str => Ok(ast::Type { span: self.pop()?.span, kind: ast::TypeKind::Str }),
             TT::Ampersand => {
                 self.pop()?;
+
+                //`&[T]` is a slice, while `&[T; N]` is a pointer to an array
+                if self.at(TT::OpenS) {
+                    let array_start = self.pop()?.span.start;
+                    let inner = self.type_decl()?;
+
+                    if self.accept(TT::CloseS)?.is_some() {
+                        return Ok(ast::Type {
+                            span: Span::new(start_pos, self.last_popped_end),
+                            kind: ast::TypeKind::Slice(Box::new(inner)),
+                        });
+                    }
+
+                    let array = self.array_type_rest(array_start, inner)?;
+                    return Ok(ast::Type {
+                        span: Span::new(start_pos, array.span.end),
+                        kind: ast::TypeKind::Ref(Box::new(array)),
+                    });
+                }
+
                 let inner = self.type_decl()?;
                 Ok(ast::Type {
                     span: Span::new(start_pos, inner.span.end),
                     kind: ast::TypeKind::Ref(Box::new(inner)),
                 })
             }
+            TT::QuestionMark => {
+                self.pop()?;
+                self.expect(TT::Ampersand, "optional pointer type")?;
+                let inner = self.type_decl()?;
+                Ok(ast::Type {
+                    span: Span::new(start_pos, inner.span.end),
+                    kind: ast::TypeKind::OptionalRef(Box::new(inner)),
+                })
+            }
             TT::Id => {
                 let path = self.path()?;
                 Ok(ast::Type {
@@ -1019,24 +1688,39 @@ impl<'s> Parser<'s> {
                     kind,
                 })
             }
-            TT::OpenS => {
-                //array
+            TT::Pipe => {
+                //closure
                 self.pop()?;
-                let inner = self.type_decl()?;
-                self.expect(TT::Semi, "array type delimiter")?;
-                //TODO proper IntLit parsing
-                let length: u32 = self.expect(TT::IntLit, "array length")?.string
-                    .parse().unwrap();
-                self.expect(TT::CloseS, "end of array type")?;
+                let (_, params) = self.list(TT::Pipe, Some(TT::Comma), Self::type_decl)?;
+                self.expect(TT::Arrow, "closure return type")?;
+                let ret = self.type_decl()?;
 
                 Ok(ast::Type {
                     span: Span::new(start_pos, self.last_popped_end),
-                    kind: ast::TypeKind::Array { inner: Box::new(inner), length },
+                    kind: ast::TypeKind::Closure { params, ret: Box::new(ret) },
                 })
             }
+            TT::OpenS => {
+                //array
+                self.pop()?;
+                let inner = self.type_decl()?;
+                self.array_type_rest(start_pos, inner)
+            }
             _ => Err(Self::unexpected_token(self.peek(), TYPE_START_TOKENS, "type declaration")),
         }
     }
+
+    /// Parse the rest of an array type `[inner; length]` after the inner type.
+    fn array_type_rest(&mut self, start_pos: Pos, inner: ast::Type) -> Result<ast::Type> {
+        self.expect(TT::Semi, "array type delimiter")?;
+        let length = Box::new(self.expression()?);
+        self.expect(TT::CloseS, "end of array type")?;
+
+        Ok(ast::Type {
+            span: Span::new(start_pos, self.last_popped_end),
+            kind: ast::TypeKind::Array { inner: Box::new(inner), length },
+        })
+    }
 }
 
 pub fn parse_module(file: FileId, input: &str) -> Result<ast::ModuleContent> {
@@ -1046,3 +1730,189 @@ pub fn parse_module(file: FileId, input: &str) -> Result<ast::ModuleContent> {
     };
     parser.module()
 }
+
+fn parse_int_token(token: &Token, bits: u32) -> Result<u32> {
+    parse_int_literal(&token.string, bits, Signedness::Unsigned, false).ok_or_else(|| ParseError::IntLit {
+        span: token.span,
+        lit: token.string.clone(),
+        bits,
+    })
+}
+
+/// Decode the escape sequences in the raw content of a string literal into the bytes it represents.
+/// `start` is the position of the first character of `raw`, used for error reporting.
+fn unescape_string(start: Pos, raw: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(raw.len());
+    let mut pos = start;
+    let mut chars = raw.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let c_pos = pos;
+        if c == '\n' {
+            pos.line += 1;
+            pos.col = 1;
+        } else {
+            pos.col += 1;
+        }
+
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+
+        let invalid = |sequence: String| ParseError::Escape { pos: c_pos, sequence };
+
+        let e = chars.next().ok_or_else(|| invalid("\\".to_owned()))?;
+        pos.col += 1;
+
+        let byte = match e {
+            'n' => b'\n',
+            't' => b'\t',
+            '"' => b'"',
+            '\\' => b'\\',
+            '0' => b'\0',
+            'x' => {
+                let digits: String = (0..2).filter_map(|_| chars.next_if(|d| d.is_ascii_hexdigit())).collect();
+                pos.col += digits.len();
+
+                if digits.len() != 2 {
+                    return Err(invalid(format!("\\x{}", digits)));
+                }
+                u8::from_str_radix(&digits, 16).expect("two hex digits should always fit in a byte")
+            }
+            _ => return Err(invalid(format!("\\{}", e))),
+        };
+        bytes.push(byte);
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn unescape(raw: &str) -> Result<Vec<u8>> {
+        unescape_string(Pos { file: FileId(0), line: 1, col: 1 }, raw)
+    }
+
+    #[test]
+    fn unescape_valid() {
+        assert_eq!(b"plain".to_vec(), unescape("plain").unwrap());
+        assert_eq!(b"a\nb\tc\"d\\e\0".to_vec(), unescape(r#"a\nb\tc\"d\\e\0"#).unwrap());
+        assert_eq!(vec![0x00, 0x7f, 0xff, b'g'], unescape(r"\x00\x7F\xffg").unwrap());
+    }
+
+    #[test]
+    fn unescape_invalid() {
+        match unescape(r"ab\q") {
+            Err(ParseError::Escape { pos, sequence }) => {
+                assert_eq!((1, 3), (pos.line, pos.col));
+                assert_eq!(r"\q", sequence);
+            }
+            r => panic!("expected escape error, got {:?}", r),
+        }
+
+        assert!(matches!(unescape(r"\x4"), Err(ParseError::Escape { .. })));
+        assert!(matches!(unescape(r"\xg0"), Err(ParseError::Escape { .. })));
+    }
+
+    #[test]
+    fn doc_comments() {
+        let input = "/// Adds things.\n///\n///  Indented.\nfun add() {}\n//// not a doc\nconst A: int = 1;\n/// Last.\n// plain\npub struct S {}";
+        let module = parse_module(FileId(0), input).unwrap();
+        let docs = module.items.iter().map(|item| item.doc()).collect::<Vec<_>>();
+        assert_eq!(vec![Some("Adds things.\n\n Indented."), None, Some("Last.")], docs);
+    }
+
+    #[test]
+    fn struct_literals() {
+        let input = "fun f() { if done { x; } let a = P { x: 1, ..q }; let b = P { ..q }; }";
+        let module = parse_module(FileId(0), input).unwrap();
+        let body = match &module.items[0] {
+            ast::Item::Function(func) => func.body.as_ref().unwrap(),
+            item => panic!("expected function, got {:?}", item),
+        };
+
+        //the block after `if done` is not a struct literal
+        assert!(matches!(body.statements[0].kind, ast::StatementKind::If(_)));
+
+        for stmt in &body.statements[1..] {
+            match &stmt.kind {
+                ast::StatementKind::Declaration(decl) => {
+                    let init = &decl.init.as_ref().unwrap().kind;
+                    assert!(matches!(init, ast::ExpressionKind::StructLit { base: Some(_), .. }), "got {:?}", init);
+                }
+                kind => panic!("expected declaration, got {:?}", kind),
+            }
+        }
+    }
+
+    #[test]
+    fn heap_builtins() {
+        let input = "fun f() { let a = new [int; 4]; let b = alloc(16); free(a); }";
+        let module = parse_module(FileId(0), input).unwrap();
+        let body = match &module.items[0] {
+            ast::Item::Function(func) => func.body.as_ref().unwrap(),
+            item => panic!("expected function, got {:?}", item),
+        };
+
+        let kinds = body.statements.iter().map(|stmt| match &stmt.kind {
+            ast::StatementKind::Declaration(decl) => &decl.init.as_ref().unwrap().kind,
+            ast::StatementKind::Expression(expr) => &expr.kind,
+            kind => panic!("expected declaration or expression, got {:?}", kind),
+        }).collect::<Vec<_>>();
+
+        assert!(matches!(kinds[0], ast::ExpressionKind::New { ty: ast::Type { kind: ast::TypeKind::Array { .. }, .. } }), "got {:?}", kinds[0]);
+        assert!(matches!(kinds[1], ast::ExpressionKind::Alloc { .. }), "got {:?}", kinds[1]);
+        assert!(matches!(kinds[2], ast::ExpressionKind::Free { .. }), "got {:?}", kinds[2]);
+    }
+
+    #[test]
+    fn declaration_patterns() {
+        let input = "fun f() { let a = t; let (b, _) = t; let m::P { x, y: _ } = p; }";
+        let module = parse_module(FileId(0), input).unwrap();
+        let body = match &module.items[0] {
+            ast::Item::Function(func) => func.body.as_ref().unwrap(),
+            item => panic!("expected function, got {:?}", item),
+        };
+
+        let patterns = body.statements.iter().map(|stmt| match &stmt.kind {
+            ast::StatementKind::Declaration(decl) => &decl.pattern,
+            kind => panic!("expected declaration, got {:?}", kind),
+        }).collect::<Vec<_>>();
+
+        assert!(matches!(patterns[0], ast::DeclarationPattern::Single(_)), "got {:?}", patterns[0]);
+        assert!(matches!(patterns[1], ast::DeclarationPattern::Tuple { .. }), "got {:?}", patterns[1]);
+        assert!(matches!(patterns[2], ast::DeclarationPattern::Struct { .. }), "got {:?}", patterns[2]);
+
+        let names = patterns.iter().map(|pattern| {
+            pattern.bindings().iter().map(|id| match id {
+                ast::MaybeIdentifier::Identifier(id) => id.string.as_str(),
+                ast::MaybeIdentifier::Placeholder(_) => "_",
+            }).collect::<Vec<_>>()
+        }).collect::<Vec<_>>();
+        assert_eq!(vec![vec!["a"], vec!["b", "_"], vec!["x", "_"]], names);
+    }
+
+    #[test]
+    fn tuple_expressions() {
+        let input = "fun f() { (a, b) = (b, a); x = (y); z = (w,); }";
+        let module = parse_module(FileId(0), input).unwrap();
+        let body = match &module.items[0] {
+            ast::Item::Function(func) => func.body.as_ref().unwrap(),
+            item => panic!("expected function, got {:?}", item),
+        };
+
+        let lens = body.statements.iter().map(|stmt| match &stmt.kind {
+            ast::StatementKind::Assignment(assign) => match (&assign.left.kind, &assign.right.kind) {
+                (ast::ExpressionKind::Tuple { values: left }, ast::ExpressionKind::Tuple { values: right }) => (left.len(), right.len()),
+                (_, ast::ExpressionKind::Tuple { values: right }) => (0, right.len()),
+                _ => (0, 0),
+            },
+            kind => panic!("expected assignment, got {:?}", kind),
+        }).collect::<Vec<_>>();
+        assert_eq!(vec![(2, 2), (0, 0), (0, 1)], lens);
+    }
+}
@@ -1,4 +1,5 @@
 use std::fmt::{Debug, Formatter};
+use std::path::PathBuf;
 
 #[derive(Copy, Clone, Eq, PartialEq)]
 pub struct FileId(pub usize);
@@ -9,7 +10,7 @@ impl Debug for FileId {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Eq, PartialEq)]
 pub struct Pos {
     pub file: FileId,
     pub line: usize,
@@ -22,7 +23,7 @@ impl Debug for Pos {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Eq, PartialEq)]
 pub struct Span {
     //inclusive
     pub start: Pos,
@@ -49,4 +50,36 @@ impl Span {
     pub fn empty_at(at: Pos) -> Self {
         Self::new(at, at)
     }
-}
\ No newline at end of file
+}
+/// A loaded source file, kept around to show the source code in diagnostics.
+#[derive(Debug)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub src: String,
+}
+
+/// The loaded source files, indexed by `FileId`.
+#[derive(Debug, Default)]
+pub struct Files {
+    files: Vec<SourceFile>,
+}
+
+impl Files {
+    pub fn add(&mut self, path: PathBuf, src: String) -> FileId {
+        let id = FileId(self.files.len());
+        self.files.push(SourceFile { path, src });
+        id
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&SourceFile> {
+        self.files.iter()
+    }
+}
+
+impl std::ops::Index<FileId> for Files {
+    type Output = SourceFile;
+
+    fn index(&self, index: FileId) -> &Self::Output {
+        &self.files[index.0]
+    }
+}
@@ -1,21 +1,27 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use itertools::Itertools;
 
 use crate::front;
 use crate::front::{ast, cst};
 use crate::front::ast::{Item, ModuleContent};
-use crate::front::cst::{CollectedModule, ConstDecl, FunctionDecl, FunctionTypeInfo, ItemStore, ResolvedProgram, ScopedItem, ScopedValue, ScopeKind, StructFieldInfo, StructTypeInfo, TypeInfo, TypeStore};
-use crate::front::error::{Error, Result};
+use crate::front::const_eval::ConstEvalState;
+use crate::front::cst::{CollectedModule, ConstDecl, EnumTypeInfo, EnumVariantInfo, FunctionAttributes, FunctionDecl, FunctionTypeInfo, ItemStore, ResolvedProgram, ScopedItem, ScopedValue, ScopeKind, StructFieldInfo, StructTypeInfo, TypeInfo, TypeStore};
+use crate::front::error::{Error, ItemType, Result};
+use crate::front::scope::Scope;
+use crate::mid::ir::CallingConvention;
 
 type AstProgram = front::Program<Option<ast::ModuleContent>>;
 type CstProgram<'a> = front::Program<(&'a Option<ModuleContent>, cst::Module)>;
 
 /// Resolve all items in the program into a format more suitable for codegen.
-pub fn resolve(ast: &front::Program<Option<ast::ModuleContent>>) -> Result<ResolvedProgram> {
+pub fn resolve(ast: &front::Program<Option<ast::ModuleContent>>) -> Result<'_, ResolvedProgram<'_>> {
     let (mut state, mapped) = first_pass(ast)?;
     second_pass(&mut state, &mapped)?;
     third_pass(&mut state, &mapped)?;
+    fourth_pass(&mut state, &mapped)?;
+    check_infinite_types(&state, &mapped)?;
+    check_static_asserts(&mut state, &mapped)?;
 
     let main_func = find_main_function(&mut state, &mapped)?;
 
@@ -34,10 +40,14 @@ struct ResolveState<'a> {
     func_map: HashMap<*const ast::Function, cst::Function>,
     const_map: HashMap<*const ast::Const, cst::Const>,
     struct_map: HashMap<*const ast::Struct, cst::Type>,
+    enum_map: HashMap<*const ast::Enum, cst::Type>,
+
+    /// The functions declared within other functions, per module.
+    nested_funcs: HashMap<cst::Module, Vec<&'a ast::Function>>,
 }
 
 /// Collect all declared items into local_scope and populate the maps.
-fn first_pass<'a>(ast: &'a AstProgram) -> Result<(ResolveState<'a>, CstProgram<'a>)> {
+fn first_pass<'a>(ast: &'a AstProgram) -> Result<'a, (ResolveState<'a>, CstProgram<'a>)> {
     let mut store = TypeStore::default();
     let common_ph_type = store.new_placeholder();
 
@@ -46,48 +56,124 @@ fn first_pass<'a>(ast: &'a AstProgram) -> Result<(ResolveState<'a>, CstProgram<'
     let mut func_map: HashMap<*const ast::Function, cst::Function> = Default::default();
     let mut cst_map: HashMap<*const ast::Const, cst::Const> = Default::default();
     let mut struct_map: HashMap<*const ast::Struct, cst::Type> = Default::default();
+    let mut enum_map: HashMap<*const ast::Enum, cst::Type> = Default::default();
+    let mut nested_funcs: HashMap<cst::Module, Vec<&ast::Function>> = Default::default();
 
     let mapped = ast.try_map(&mut |module| {
         let mut collected_module = CollectedModule::default();
+        let mut module_nested_funcs = vec![];
 
         if let Some(content) = &module.content {
             for item in &content.items {
                 match item {
                     Item::Struct(struct_ast) => {
+                        if struct_ast.public {
+                            collected_module.public_items.insert(struct_ast.id.string.clone());
+                        }
+
                         let ph = store.new_placeholder();
                         collected_module.local_scope.declare(&struct_ast.id, ScopedItem::Type(ph))?;
                         struct_map.insert(struct_ast, ph);
                     }
+                    Item::Enum(enum_ast) => {
+                        if enum_ast.public {
+                            collected_module.public_items.insert(enum_ast.id.string.clone());
+                        }
+
+                        let ph = store.new_placeholder();
+                        collected_module.local_scope.declare(&enum_ast.id, ScopedItem::Type(ph))?;
+                        enum_map.insert(enum_ast, ph);
+
+                        //the variants are known already, so they can be resolved even before the type is filled in
+                        let mut variant_scope = Scope::default();
+                        for (i, variant) in enum_ast.variants.iter().enumerate() {
+                            let value = ScopedValue::EnumVariant { ty: ph, variant: i as u32 };
+                            variant_scope.declare(&variant.id, ScopedItem::Value(value))?;
+                        }
+                        cst.type_scopes.insert(ph, variant_scope);
+                    }
                     Item::Function(func_ast) => {
+                        if func_ast.public && func_ast.operator.is_none() {
+                            collected_module.public_items.insert(func_ast.id.string.clone());
+                        }
+
                         //construct a decl with placeholder types, will be filled in during the second pass
                         let decl = FunctionDecl {
+                            name: func_ast.id.string.clone(),
                             ty: common_ph_type,
-                            func_ty: FunctionTypeInfo { params: vec![], ret: common_ph_type },
+                            func_ty: FunctionTypeInfo { params: vec![], ret: common_ph_type, conv: CallingConvention::default() },
+                            attrs: function_attributes(func_ast)?,
                             ast: func_ast,
                         };
 
                         let func = cst.funcs.push(decl);
                         collected_module.codegen_funcs.push(func);
-                        collected_module.local_scope.declare(&func_ast.id, ScopedItem::Value(ScopedValue::Function(func)))?;
+                        //operator overloads can't be referred to by name, they're registered once their types are known
+                        if func_ast.operator.is_none() {
+                            collected_module.local_scope.declare(&func_ast.id, ScopedItem::Value(ScopedValue::Function(func)))?;
+                        }
                         func_map.insert(func_ast, func);
+
+                        //nested functions are lowered like any other function, they're just not part of the module scope
+                        if let Some(body) = &func_ast.body {
+                            let mut nested = NestedFuncs::default();
+                            collect_nested_funcs(&func_ast.id.string, body, &mut nested);
+
+                            for (name, nested_ast) in nested.funcs {
+                                let decl = FunctionDecl {
+                                    name,
+                                    ty: common_ph_type,
+                                    func_ty: FunctionTypeInfo { params: vec![], ret: common_ph_type, conv: CallingConvention::default() },
+                                    attrs: function_attributes(nested_ast)?,
+                                    ast: nested_ast,
+                                };
+
+                                let func = cst.funcs.push(decl);
+                                collected_module.codegen_funcs.push(func);
+                                cst.nested_funcs.insert(nested_ast, func);
+                                func_map.insert(nested_ast, func);
+                                module_nested_funcs.push(nested_ast);
+                            }
+
+                            for siblings in nested.siblings {
+                                let funcs = siblings.iter()
+                                    .map(|&sibling| *func_map.get(&(sibling as *const _)).unwrap())
+                                    .collect::<Vec<_>>();
+                                for sibling in siblings {
+                                    cst.nested_siblings.insert(sibling, funcs.clone());
+                                }
+                            }
+                        }
                     }
                     Item::Const(cst_ast) => {
+                        if cst_ast.public {
+                            collected_module.public_items.insert(cst_ast.id.string.clone());
+                        }
+
                         let decl = ConstDecl {
                             ty: common_ph_type,
                             ast: cst_ast,
                         };
 
                         let cst = cst.consts.push(decl);
+                        collected_module.consts.push(cst);
                         collected_module.local_scope.declare(&cst_ast.id, ScopedItem::Value(ScopedValue::Const(cst)))?;
                         cst_map.insert(cst_ast, cst);
                     }
-                    //handled in a later pass
-                    Item::UseDecl(_) => {}
+                    //handled in a later pass, except for the visibility
+                    Item::UseDecl(use_ast) => {
+                        if use_ast.public {
+                            collected_module.public_items.insert(use_ast.path.id.string.clone());
+                        }
+                    }
+                    //checked once all types are known
+                    Item::StaticAssert(_) => {}
                 }
             }
         }
 
         let module_id = cst.modules.push(collected_module);
+        nested_funcs.insert(module_id, module_nested_funcs);
         Ok((&module.content, module_id))
     })?;
 
@@ -97,6 +183,8 @@ fn first_pass<'a>(ast: &'a AstProgram) -> Result<(ResolveState<'a>, CstProgram<'
         func_map,
         const_map: cst_map,
         struct_map,
+        enum_map,
+        nested_funcs,
     };
     Ok((state, mapped))
 }
@@ -113,14 +201,14 @@ fn second_pass<'a>(state: &mut ResolveState<'a>, mapped: &CstProgram<'a>) -> Res
 
         for (name, child) in &module.submodules {
             let child_id = child.content.1;
-            scope.declare_str(&*name, ScopedItem::Module(child_id));
+            scope.declare_str(name, ScopedItem::Module(child_id));
         }
 
         Ok(())
     })
 }
 
-/// Replace the placeholder types for declared items with the real types.
+/// Build the real module scopes, containing the child modules, imports and declared items.
 fn third_pass<'a>(state: &mut ResolveState<'a>, mapped: &CstProgram<'a>) -> Result<'a, ()> {
     mapped.try_for_each(&mut |module| {
         let (content, module_id) = module.content;
@@ -130,16 +218,19 @@ fn third_pass<'a>(state: &mut ResolveState<'a>, mapped: &CstProgram<'a>) -> Resu
         let scope = &mut state.items.modules[module_id].scope;
         for (name, child) in &module.submodules {
             let child_id = child.content.1;
-            scope.declare_str(&*name, ScopedItem::Module(child_id));
+            scope.declare_str(name, ScopedItem::Module(child_id));
         }
 
         let items = &mut state.items;
-        let types = &mut state.types;
 
         if let Some(content) = content {
             //add items to scope, in order of appearance for nicer error messages
             for item in &content.items {
                 let (id, item) = match item {
+                    Item::UseDecl(use_ast) if use_ast.glob => {
+                        declare_glob_import(items, module_id, use_ast)?;
+                        continue;
+                    }
                     Item::UseDecl(use_ast) => {
                         let item = items.resolve_path(ScopeKind::Local, &items.root_scope, &use_ast.path)?;
                         (&use_ast.path.id, item)
@@ -148,6 +239,12 @@ fn third_pass<'a>(state: &mut ResolveState<'a>, mapped: &CstProgram<'a>) -> Resu
                         let item = ScopedItem::Type(*state.struct_map.get(&(struct_ast as *const _)).unwrap());
                         (&struct_ast.id, item)
                     }
+                    Item::Enum(enum_ast) => {
+                        let item = ScopedItem::Type(*state.enum_map.get(&(enum_ast as *const _)).unwrap());
+                        (&enum_ast.id, item)
+                    }
+                    //operator overloads can't be referred to by name
+                    Item::Function(func_ast) if func_ast.operator.is_some() => continue,
                     Item::Function(func_ast) => {
                         let func = *state.func_map.get(&(func_ast as *const _)).unwrap();
                         let item = ScopedItem::Value(ScopedValue::Function(func));
@@ -158,14 +255,78 @@ fn third_pass<'a>(state: &mut ResolveState<'a>, mapped: &CstProgram<'a>) -> Resu
                         let item = ScopedItem::Value(ScopedValue::Const(cst));
                         (&cst_ast.id, item)
                     }
+                    //not declared in any scope
+                    Item::StaticAssert(_) => continue,
                 };
 
                 items.modules[module_id].scope.declare(id, item)?;
             }
+        }
+
+        Ok(())
+    })
+}
+
+/// Declare all public items of the module `use_ast` refers to in the scope of `module_id`.
+fn declare_glob_import<'a>(items: &mut ItemStore<'a>, module_id: cst::Module, use_ast: &'a ast::UseDecl) -> Result<'a, ()> {
+    let target = match items.resolve_path(ScopeKind::Local, &items.root_scope, &use_ast.path)? {
+        ScopedItem::Module(target) => target,
+        item => return Err(item.err_unexpected_kind(ItemType::Module, &use_ast.path)),
+    };
+
+    let target_module = &items.modules[target];
+    let imported: Vec<(String, ScopedItem)> = target_module.local_scope.immediate_values()
+        .filter(|&(id, item)| matches!(item, ScopedItem::Module(_)) || target_module.public_items.contains(id))
+        .map(|(id, &item)| (id.to_owned(), item))
+        .collect();
+
+    let module = &mut items.modules[module_id];
+    for (id, item) in imported {
+        if module.scope.find_immediate_str(&id).is_some() {
+            return Err(Error::GlobImportConflict { use_decl: use_ast, id });
+        }
+        if use_ast.public {
+            module.public_items.insert(id.clone());
+        }
+        module.scope.declare_str(&id, item);
+    }
+
+    Ok(())
+}
+
+/// Replace the placeholder types for declared items with the real types.
+/// The types of constants are resolved first, since array lengths in other types can refer to them.
+fn fourth_pass<'a>(state: &mut ResolveState<'a>, mapped: &CstProgram<'a>) -> Result<'a, ()> {
+    mapped.try_for_each(&mut |module| {
+        let (content, module_id) = module.content;
+        let items = &mut state.items;
+        let types = &mut state.types;
+
+        if let Some(content) = content {
+            let module_scope = &items.modules[module_id].scope;
+
+            for item in &content.items {
+                if let Item::Const(cst_ast) = item {
+                    let ty = items.resolve_type(ScopeKind::Real, module_scope, types, &cst_ast.ty)?;
+                    types.check_outlives_func(&cst_ast.ty, ty)?;
+
+                    let cst = *state.const_map.get(&(cst_ast as *const _)).unwrap();
+                    items.consts[cst].ty = ty;
+                }
+            }
+        }
+
+        Ok(())
+    })?;
+
+    mapped.try_for_each(&mut |module| {
+        let (content, module_id) = module.content;
+        let items = &mut state.items;
+        let types = &mut state.types;
 
+        if let Some(content) = content {
             let module_scope = &items.modules[module_id].scope;
 
-            // fill in placeholder types
             for item in &content.items {
                 match item {
                     //already handled
@@ -173,26 +334,35 @@ fn third_pass<'a>(state: &mut ResolveState<'a>, mapped: &CstProgram<'a>) -> Resu
                     Item::Struct(struct_ast) => {
                         let fields = struct_ast.fields.iter().map(|field| {
                             let ty = items.resolve_type(ScopeKind::Real, module_scope, types, &field.ty)?;
-                            Ok(StructFieldInfo { id: &*field.id.string, ty })
+                            types.check_outlives_func(&field.ty, ty)?;
+                            Ok(StructFieldInfo { id: &field.id.string, ty })
                         }).try_collect()?;
 
-                        let info = TypeInfo::Struct(StructTypeInfo { decl: struct_ast, fields });
+                        let packed = check_attributes(&struct_ast.attrs, &["packed"])?.contains(&"packed");
+                        let info = TypeInfo::Struct(StructTypeInfo { decl: struct_ast, fields, packed });
 
                         let ph = *state.struct_map.get(&(struct_ast as *const _)).unwrap();
                         types.replace_placeholder(ph, info)
                     }
-                    Item::Function(func_ast) => {
-                        let params: Vec<cst::Type> = func_ast.params.iter().map(|param| {
-                            items.resolve_type(ScopeKind::Real, module_scope, types, &param.ty)
+                    Item::Enum(enum_ast) => {
+                        let variants = enum_ast.variants.iter().map(|variant| {
+                            let fields = variant.fields.iter()
+                                .map(|field| {
+                                    let ty = items.resolve_type(ScopeKind::Real, module_scope, types, field)?;
+                                    types.check_outlives_func(field, ty)?;
+                                    Ok(ty)
+                                })
+                                .try_collect()?;
+                            Ok(EnumVariantInfo { id: &variant.id.string, fields })
                         }).try_collect()?;
 
-                        let ret = func_ast.ret_ty.as_ref()
-                            .map(|ret| {
-                                items.resolve_type(ScopeKind::Real, module_scope, types, ret)
-                            }).transpose()?
-                            .unwrap_or(types.type_void());
+                        let info = TypeInfo::Enum(EnumTypeInfo { decl: enum_ast, variants });
 
-                        let info = FunctionTypeInfo { params, ret };
+                        let ph = *state.enum_map.get(&(enum_ast as *const _)).unwrap();
+                        types.replace_placeholder(ph, info)
+                    }
+                    Item::Function(func_ast) => {
+                        let info = resolve_func_type(items, types, module_scope, func_ast)?;
 
                         let func = *state.func_map.get(&(func_ast as *const _)).unwrap();
                         let func = &mut items.funcs[func];
@@ -200,13 +370,47 @@ fn third_pass<'a>(state: &mut ResolveState<'a>, mapped: &CstProgram<'a>) -> Resu
                         func.func_ty = info.clone();
                         func.ty = types.define_type(TypeInfo::Function(info));
                     }
-                    Item::Const(cst_ast) => {
-                        let ty = items.resolve_type(ScopeKind::Real, module_scope, types, &cst_ast.ty)?;
+                    //already handled
+                    Item::Const(_) => {}
+                    //checked later
+                    Item::StaticAssert(_) => {}
+                };
+            }
 
-                        let cst = *state.const_map.get(&(cst_ast as *const _)).unwrap();
-                        items.consts[cst].ty = ty;
-                    }
+            //nested functions can only refer to module level types
+            for &func_ast in &state.nested_funcs[&module_id] {
+                let info = resolve_func_type(items, types, module_scope, func_ast)?;
+
+                let func = *state.func_map.get(&(func_ast as *const _)).unwrap();
+                let func = &mut items.funcs[func];
+
+                func.func_ty = info.clone();
+                func.ty = types.define_type(TypeInfo::Function(info));
+            }
+        }
+
+        Ok(())
+    })?;
+
+    register_operators(&mut state.items, &state.types)
+}
+
+/// Check that no struct or enum contains itself by value, such a type would have an infinite size.
+fn check_infinite_types<'a>(state: &ResolveState<'a>, mapped: &CstProgram<'a>) -> Result<'a, ()> {
+    mapped.try_for_each(&mut |module| {
+        let (content, _) = module.content;
+
+        if let Some(content) = content {
+            for item in &content.items {
+                let (ty, id) = match item {
+                    Item::Struct(struct_ast) => (*state.struct_map.get(&(struct_ast as *const _)).unwrap(), &struct_ast.id),
+                    Item::Enum(enum_ast) => (*state.enum_map.get(&(enum_ast as *const _)).unwrap(), &enum_ast.id),
+                    _ => continue,
                 };
+
+                if contains_by_value(&state.types, ty, ty, &mut HashSet::new()) {
+                    return Err(Error::InfiniteSizeType(id));
+                }
             }
         }
 
@@ -214,6 +418,275 @@ fn third_pass<'a>(state: &mut ResolveState<'a>, mapped: &CstProgram<'a>) -> Resu
     })
 }
 
+/// Whether a value of type `ty` contains a value of type `target` without any indirection in between.
+fn contains_by_value(types: &TypeStore, target: cst::Type, ty: cst::Type, visited: &mut HashSet<cst::Type>) -> bool {
+    let inner: Vec<cst::Type> = match &types[ty] {
+        TypeInfo::Tuple(info) => info.fields.clone(),
+        TypeInfo::Array(info) => vec![info.inner],
+        TypeInfo::Struct(info) => info.fields.iter().map(|field| field.ty).collect(),
+        TypeInfo::Enum(info) => info.variants.iter().flat_map(|variant| variant.fields.iter().copied()).collect(),
+        //pointers and functions don't contain their inner types, and the other types are not recursive
+        _ => vec![],
+    };
+
+    inner.into_iter().any(|inner| {
+        inner == target || (visited.insert(inner) && contains_by_value(types, target, inner, visited))
+    })
+}
+
+/// Evaluate the `static_assert` items, now that all types and constants can be resolved.
+fn check_static_asserts<'a>(state: &mut ResolveState<'a>, mapped: &CstProgram<'a>) -> Result<'a, ()> {
+    let items = &state.items;
+    let types = &mut state.types;
+    let mut const_eval = ConstEvalState::new(items);
+
+    mapped.try_for_each(&mut |module| {
+        let (content, module_id) = module.content;
+
+        if let Some(content) = content {
+            let module_scope = &items.modules[module_id].scope;
+
+            for item in &content.items {
+                if let Item::StaticAssert(assert_ast) = item {
+                    if !const_eval.eval_condition(types, module_scope, &assert_ast.cond)? {
+                        return Err(Error::StaticAssertFailed(assert_ast));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    })
+}
+
+/// Register the operator overloads by the operator and the type of their left operand, which must be a struct or
+/// `str`.
+fn register_operators<'a>(items: &mut ItemStore<'a>, types: &TypeStore<'a>) -> Result<'a, ()> {
+    for (func, decl) in items.funcs.iter() {
+        let kind = match decl.ast.operator {
+            Some(kind) => kind,
+            None => continue,
+        };
+
+        let params = &decl.func_ty.params;
+        if params.len() != 2 || !matches!(types[params[0]], TypeInfo::Struct(_) | TypeInfo::Str) {
+            return Err(Error::InvalidOperatorFunction(decl.ast));
+        }
+
+        if items.operators.insert((kind, params[0]), func).is_some() {
+            return Err(Error::OperatorDeclaredTwice(decl.ast));
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolve the type of the given function in the scope of its module.
+fn resolve_func_type<'a>(
+    items: &ItemStore<'a>,
+    types: &mut TypeStore<'a>,
+    module_scope: &Scope<ScopedItem>,
+    func_ast: &'a ast::Function,
+) -> Result<'a, FunctionTypeInfo<cst::Type>> {
+    let params: Vec<cst::Type> = func_ast.params.iter().map(|param| {
+        items.resolve_type(ScopeKind::Real, module_scope, types, &param.ty)
+    }).try_collect()?;
+
+    let ret = func_ast.ret_ty.as_ref()
+        .map(|ret| {
+            let ty = items.resolve_type(ScopeKind::Real, module_scope, types, ret)?;
+            types.check_outlives_func(ret, ty)?;
+            Ok(ty)
+        }).transpose()?
+        .unwrap_or(types.type_void());
+
+    let conv = match &func_ast.conv {
+        None => CallingConvention::default(),
+        Some(conv) => CallingConvention::from_name(&conv.name)
+            .ok_or(Error::UnknownCallingConvention(conv))?,
+    };
+
+    Ok(FunctionTypeInfo { params, ret, conv })
+}
+
+fn function_attributes(func_ast: &ast::Function) -> Result<'_, FunctionAttributes> {
+    let names = check_attributes(&func_ast.attrs, &["inline", "noreturn"])?;
+    Ok(FunctionAttributes {
+        inline: names.contains(&"inline"),
+        noreturn: names.contains(&"noreturn"),
+    })
+}
+
+/// Check that the given attributes are all in `known`, don't take arguments and appear only once.
+/// Returns the names of the attributes.
+fn check_attributes<'a>(attrs: &'a [ast::Attribute], known: &[&str]) -> Result<'a, Vec<&'a str>> {
+    let mut names: Vec<&str> = Vec::new();
+
+    for attr in attrs {
+        let name = attr.id.string.as_str();
+        if !known.contains(&name) {
+            return Err(Error::UnknownAttribute(attr));
+        }
+        if names.contains(&name) {
+            return Err(Error::AttributeGivenTwice(attr));
+        }
+        if !attr.args.is_empty() {
+            return Err(Error::UnexpectedAttributeArguments(attr));
+        }
+        names.push(name);
+    }
+
+    Ok(names)
+}
+
+#[derive(Default)]
+struct NestedFuncs<'a> {
+    /// Every nested function together with its mangled name.
+    funcs: Vec<(String, &'a ast::Function)>,
+    /// The groups of functions declared directly in the same block.
+    siblings: Vec<Vec<&'a ast::Function>>,
+}
+
+/// Collect the functions declared anywhere within `block`, including those nested in other nested functions.
+fn collect_nested_funcs<'a>(prefix: &str, block: &'a ast::Block, result: &mut NestedFuncs<'a>) {
+    for stmt in &block.statements {
+        match &stmt.kind {
+            ast::StatementKind::Declaration(decl) => {
+                if let Some(init) = &decl.init {
+                    collect_nested_funcs_expr(prefix, init, result);
+                }
+            }
+            ast::StatementKind::Assignment(assign) => {
+                collect_nested_funcs_expr(prefix, &assign.left, result);
+                collect_nested_funcs_expr(prefix, &assign.right, result);
+            }
+            ast::StatementKind::Expression(expr) | ast::StatementKind::Assert(expr) =>
+                collect_nested_funcs_expr(prefix, expr, result),
+            ast::StatementKind::If(if_stmt) => {
+                collect_nested_funcs_expr(prefix, &if_stmt.cond, result);
+                collect_nested_funcs(prefix, &if_stmt.then_block, result);
+                if let Some(else_block) = &if_stmt.else_block {
+                    collect_nested_funcs(prefix, else_block, result);
+                }
+            }
+            ast::StatementKind::While(while_stmt) | ast::StatementKind::DoWhile(while_stmt) => {
+                collect_nested_funcs_expr(prefix, &while_stmt.cond, result);
+                collect_nested_funcs(prefix, &while_stmt.body, result);
+            }
+            ast::StatementKind::Loop(loop_stmt) => collect_nested_funcs(prefix, &loop_stmt.body, result),
+            ast::StatementKind::For(for_stmt) => {
+                match &for_stmt.range {
+                    ast::ForRange::Range { start, end, step } => {
+                        collect_nested_funcs_expr(prefix, start, result);
+                        collect_nested_funcs_expr(prefix, end, result);
+                        if let Some(step) = step {
+                            collect_nested_funcs_expr(prefix, step, result);
+                        }
+                    }
+                    ast::ForRange::Array(array) => collect_nested_funcs_expr(prefix, array, result),
+                }
+                collect_nested_funcs(prefix, &for_stmt.body, result);
+            }
+            ast::StatementKind::Match(match_stmt) => {
+                collect_nested_funcs_expr(prefix, &match_stmt.value, result);
+                for arm in &match_stmt.arms {
+                    collect_nested_funcs(prefix, &arm.body, result);
+                }
+            }
+            ast::StatementKind::Block(block) => collect_nested_funcs(prefix, block, result),
+            ast::StatementKind::Function(func) => {
+                let name = format!("{}::{}", prefix, func.id.string);
+                if let Some(body) = &func.body {
+                    collect_nested_funcs(&name, body, result);
+                }
+                result.funcs.push((name, func));
+            }
+        }
+    }
+
+    let siblings = block.statements.iter()
+        .filter_map(|stmt| match &stmt.kind {
+            ast::StatementKind::Function(func) => Some(&**func),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if !siblings.is_empty() {
+        result.siblings.push(siblings);
+    }
+
+    if let Some(value) = &block.value {
+        collect_nested_funcs_expr(prefix, value, result);
+    }
+}
+
+/// Collect the nested functions declared within closures in `expr`.
+fn collect_nested_funcs_expr<'a>(prefix: &str, expr: &'a ast::Expression, result: &mut NestedFuncs<'a>) {
+    match &expr.kind {
+        ast::ExpressionKind::IntLit { .. } | ast::ExpressionKind::FloatLit { .. } |
+        ast::ExpressionKind::BoolLit { .. } | ast::ExpressionKind::StringLit { .. } |
+        ast::ExpressionKind::Null | ast::ExpressionKind::Path(_) |
+        ast::ExpressionKind::New { .. } | ast::ExpressionKind::Unreachable |
+        ast::ExpressionKind::Continue | ast::ExpressionKind::Break => {}
+
+        ast::ExpressionKind::Closure { params: _, ret_ty: _, body } => collect_nested_funcs(prefix, body, result),
+        ast::ExpressionKind::Block(block) => collect_nested_funcs(prefix, block, result),
+
+        ast::ExpressionKind::ArrayRepeat { value: inner, length: _ } |
+        ast::ExpressionKind::DotIndex { target: inner, index: _ } |
+        ast::ExpressionKind::Cast { value: inner, ty: _ } |
+        ast::ExpressionKind::Alloc { size: inner } |
+        ast::ExpressionKind::Free { ptr: inner } |
+        ast::ExpressionKind::Panic { message: inner } |
+        ast::ExpressionKind::Unary { kind: _, inner } => collect_nested_funcs_expr(prefix, inner, result),
+
+        ast::ExpressionKind::Tuple { values } => {
+            for value in values {
+                collect_nested_funcs_expr(prefix, value, result);
+            }
+        }
+        ast::ExpressionKind::Call { target, args } => {
+            collect_nested_funcs_expr(prefix, target, result);
+            for arg in args {
+                collect_nested_funcs_expr(prefix, &arg.value, result);
+            }
+        }
+        ast::ExpressionKind::StructLit { path: _, fields, base } => {
+            for field in fields {
+                collect_nested_funcs_expr(prefix, &field.value, result);
+            }
+            if let Some(base) = base {
+                collect_nested_funcs_expr(prefix, base, result);
+            }
+        }
+        ast::ExpressionKind::ArrayIndex { target: left, index: right } |
+        ast::ExpressionKind::Binary { kind: _, left, right } => {
+            collect_nested_funcs_expr(prefix, left, result);
+            collect_nested_funcs_expr(prefix, right, result);
+        }
+        ast::ExpressionKind::Slice { target, start, end } => {
+            collect_nested_funcs_expr(prefix, target, result);
+            for bound in start.iter().chain(end) {
+                collect_nested_funcs_expr(prefix, bound, result);
+            }
+        }
+        ast::ExpressionKind::Ternary { condition, then_value, else_value } => {
+            collect_nested_funcs_expr(prefix, condition, result);
+            collect_nested_funcs_expr(prefix, then_value, result);
+            collect_nested_funcs_expr(prefix, else_value, result);
+        }
+        ast::ExpressionKind::InlineAsm { template: _, operands } => {
+            for operand in operands {
+                collect_nested_funcs_expr(prefix, &operand.value, result);
+            }
+        }
+        ast::ExpressionKind::Return { value } => {
+            if let Some(value) = value {
+                collect_nested_funcs_expr(prefix, value, result);
+            }
+        }
+    }
+}
+
 /// Find the main function, the function called `main` in the root module `main` that must have type `() -> int`.
 fn find_main_function<'a>(state: &mut ResolveState<'a>, mapped: &CstProgram<'a>) -> Result<'a, cst::Function> {
     let main_module = mapped.root.submodules.get("main")
@@ -227,6 +700,7 @@ fn find_main_function<'a>(state: &mut ResolveState<'a>, mapped: &CstProgram<'a>)
         let expected_ty = state.types.define_type(TypeInfo::Function(FunctionTypeInfo {
             params: vec![],
             ret: state.types.type_int(),
+            conv: CallingConvention::default(),
         }));
 
         if actual_ty != expected_ty {
@@ -241,3 +715,83 @@ fn find_main_function<'a>(state: &mut ResolveState<'a>, mapped: &CstProgram<'a>)
         Err(Error::MainWrongItem)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::back::target::I686Windows;
+    use crate::mid::interp::interpret;
+    use crate::mid::opt::snapshot::{lower_error, lower_modules, lower_modules_error, lower_source};
+
+    #[test]
+    fn private_items() {
+        let util = "pub fun get() -> int { return helper() + 1; } fun helper() -> int { return 41; }";
+
+        let prog = lower_modules(&[("main", "use util::get; fun main() -> int { return get(); }"), ("util", util)], &I686Windows);
+        assert_eq!(Ok(42), interpret(&prog));
+
+        let error = lower_modules_error(&[("main", "use util::helper; fun main() -> int { return helper(); }"), ("util", util)]);
+        assert!(error.starts_with("error: `helper` is private"), "{}", error);
+    }
+
+    #[test]
+    fn glob_imports() {
+        let a = "pub fun one() -> int { return 1; } pub const TWO: int = 2; fun hidden() -> int { return 3; }";
+        let b = "pub fun one() -> int { return 10; }";
+
+        let main = "use a::*; fun main() -> int { return one() + TWO; }";
+        let prog = lower_modules(&[("main", main), ("a", a), ("b", b)], &I686Windows);
+        assert_eq!(Ok(3), interpret(&prog));
+
+        //private items are not imported
+        let main = "use a::*; fun main() -> int { return hidden(); }";
+        let error = lower_modules_error(&[("main", main), ("a", a), ("b", b)]);
+        assert!(error.starts_with("error: undeclared identifier `hidden`"), "{}", error);
+
+        let main = "use a::*; use b::*; fun main() -> int { return one(); }";
+        let error = lower_modules_error(&[("main", main), ("a", a), ("b", b)]);
+        assert!(error.starts_with("error: `one` is imported by multiple glob imports"), "{}", error);
+    }
+
+    #[test]
+    fn inline_modules() {
+        let prog = lower_source("
+            mod math {
+                pub fun double(x: int) -> int { return 2 * x; }
+                mod inner { pub const K: int = 3; }
+            }
+            use main::math::inner::K;
+            fun main() -> int { return math::double(K); }
+        ");
+        assert_eq!(Ok(6), interpret(&prog));
+
+        let error = lower_error("mod math { fun double(x: int) -> int { return 2 * x; } } fun main() -> int { return math::double(1); }");
+        assert!(error.starts_with("error: `double` is private"), "{}", error);
+    }
+
+    #[test]
+    fn infinite_size_types() {
+        //recursion through a pointer is fine
+        let prog = lower_source("
+            struct Node { value: int, next: &Node }
+            fun main() -> int {
+                let first: Node;
+                let last = Node { value: 2, next: &first };
+                first = Node { value: 1, next: &last };
+                return first.value + (*first.next).value;
+            }
+        ");
+        assert_eq!(Ok(3), interpret(&prog));
+
+        let cases = [
+            ("struct A { a: A }", "A"),
+            ("struct A { b: [B; 2] } struct B { t: (int, A) }", "A"),
+            ("enum E { Leaf, Node(int, E) }", "E"),
+        ];
+        for (items, name) in cases {
+            let error = lower_error(&format!("{} fun main() -> int {{ return 0; }}", items));
+            let expected = format!("error: recursive type `{}` has infinite size", name);
+            assert!(error.starts_with(&expected), "{}", error);
+            assert!(error.contains("note: the cycle can be broken with a pointer"), "{}", error);
+        }
+    }
+}
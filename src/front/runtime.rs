@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+
+use crate::back::target::SystemApi;
+use crate::mid::ir;
+
+/// The exit code of the process after a panic.
+pub const PANIC_EXIT_CODE: i32 = 101;
+
+/// `STD_ERROR_HANDLE` as passed to `GetStdHandle`.
+const STD_ERROR_HANDLE: i32 = -12;
+/// The file descriptor of stderr on Linux.
+const STDERR_FILENO: i32 = 2;
+
+/// The runtime functions that the lowering pass calls implicitly, eg. for `alloc` and `free`. The C runtime isn't
+/// linked in, so these are built on top of the operating system interface of the target: the Win32 functions from
+/// kernel32, or the Linux system call wrappers from `link::SYSCALLS_RISCV_ASM`. They're only declared once they're
+/// used.
+#[derive(Debug)]
+pub struct Runtime {
+    system_api: SystemApi,
+    externs: HashMap<&'static str, ir::Value>,
+    panic: Option<ir::Function>,
+    alloc: Option<ir::Function>,
+    free: Option<ir::Function>,
+}
+
+impl Runtime {
+    pub fn new(system_api: SystemApi) -> Self {
+        Runtime { system_api, externs: Default::default(), panic: None, alloc: None, free: None }
+    }
+
+    /// Get the extern with the given name, declaring it with the given signature the first time.
+    fn get_extern(&mut self, prog: &mut ir::Program, name: &'static str, params: Vec<ir::Type>, ret: ir::Type) -> ir::Value {
+        let conv = match self.system_api {
+            SystemApi::Win32 => ir::CallingConvention::Stdcall,
+            SystemApi::Linux => ir::CallingConvention::default(),
+        };
+
+        *self.externs.entry(name).or_insert_with(|| {
+            let func_ty = ir::FunctionType { params, ret, conv };
+            let ty = prog.define_type_func(func_ty);
+            ir::Value::Extern(prog.define_ext(ir::ExternInfo { name: name.to_owned(), ty }))
+        })
+    }
+
+    /// `GetProcessHeap() -> HANDLE`
+    fn get_process_heap(&mut self, prog: &mut ir::Program) -> ir::Value {
+        let ty_int = prog.ty_int();
+        self.get_extern(prog, "_GetProcessHeap@0", vec![], ty_int)
+    }
+
+    /// `HeapAlloc(hHeap: HANDLE, dwFlags: DWORD, dwBytes: SIZE_T) -> LPVOID`
+    fn heap_alloc(&mut self, prog: &mut ir::Program) -> ir::Value {
+        let ty_int = prog.ty_int();
+        let ty_ptr = prog.ty_ptr();
+        self.get_extern(prog, "_HeapAlloc@12", vec![ty_int, ty_int, ty_int], ty_ptr)
+    }
+
+    /// `HeapFree(hHeap: HANDLE, dwFlags: DWORD, lpMem: LPVOID) -> BOOL`
+    fn heap_free(&mut self, prog: &mut ir::Program) -> ir::Value {
+        let ty_int = prog.ty_int();
+        let ty_ptr = prog.ty_ptr();
+        let ty_bool = prog.ty_bool();
+        self.get_extern(prog, "_HeapFree@12", vec![ty_int, ty_int, ty_ptr], ty_bool)
+    }
+
+    /// `GetStdHandle(nStdHandle: DWORD) -> HANDLE`
+    fn get_std_handle(&mut self, prog: &mut ir::Program) -> ir::Value {
+        let ty_int = prog.ty_int();
+        self.get_extern(prog, "_GetStdHandle@4", vec![ty_int], ty_int)
+    }
+
+    /// `WriteFile(hFile: HANDLE, lpBuffer: LPCVOID, nNumberOfBytesToWrite: DWORD, lpNumberOfBytesWritten: LPDWORD,
+    /// lpOverlapped: LPOVERLAPPED) -> BOOL`
+    fn write_file(&mut self, prog: &mut ir::Program) -> ir::Value {
+        let ty_int = prog.ty_int();
+        let ty_ptr = prog.ty_ptr();
+        let ty_bool = prog.ty_bool();
+        self.get_extern(prog, "_WriteFile@20", vec![ty_int, ty_ptr, ty_int, ty_ptr, ty_ptr], ty_bool)
+    }
+
+    /// `ExitProcess(uExitCode: UINT)`
+    fn exit_process(&mut self, prog: &mut ir::Program) -> ir::Value {
+        let ty_int = prog.ty_int();
+        let ty_void = prog.ty_void();
+        self.get_extern(prog, "_ExitProcess@4", vec![ty_int], ty_void)
+    }
+
+    /// `_lllang_write(fd: int, buffer: &byte, len: int) -> int`, the Linux `write` system call.
+    fn linux_write(&mut self, prog: &mut ir::Program) -> ir::Value {
+        let ty_int = prog.ty_int();
+        let ty_ptr = prog.ty_ptr();
+        self.get_extern(prog, "_lllang_write", vec![ty_int, ty_ptr, ty_int], ty_int)
+    }
+
+    /// `_lllang_exit(code: int)`, the Linux `exit` system call.
+    fn linux_exit(&mut self, prog: &mut ir::Program) -> ir::Value {
+        let ty_int = prog.ty_int();
+        let ty_void = prog.ty_void();
+        self.get_extern(prog, "_lllang_exit", vec![ty_int], ty_void)
+    }
+
+    /// Define a runtime function with the given parameter types, returns it together with its parameters and entry
+    /// block. The caller fills in the body.
+    fn define_func(prog: &mut ir::Program, name: &str, params: &[ir::Type], ret: ir::Type) -> (ir::Function, Vec<ir::Parameter>, ir::Block) {
+        let func_ty = ir::FunctionType { params: params.to_vec(), ret, conv: Default::default() };
+        let mut func_info = ir::FunctionInfo::new(func_ty, prog);
+        func_info.debug_name = Some(format!("runtime::{}", name));
+
+        let params = params.iter()
+            .map(|&ty| prog.define_param(ir::ParameterInfo { ty }))
+            .collect::<Vec<_>>();
+        func_info.params.extend_from_slice(&params);
+
+        let block = func_info.entry.block;
+        (prog.define_func(func_info), params, block)
+    }
+
+    /// `alloc(size: int) -> &void`: allocate `size` bytes on the heap, returns null if that fails.
+    pub fn alloc(&mut self, prog: &mut ir::Program) -> ir::Value {
+        let ty_int = prog.ty_int();
+        let ty_ptr = prog.ty_ptr();
+
+        match self.system_api {
+            SystemApi::Linux => self.get_extern(prog, "_lllang_alloc", vec![ty_int], ty_ptr),
+            SystemApi::Win32 => {
+                if let Some(func) = self.alloc {
+                    return ir::Value::Func(func);
+                }
+
+                let (func, params, block) = Runtime::define_func(prog, "alloc", &[ty_int], ty_ptr);
+                self.alloc = Some(func);
+
+                let get_process_heap = self.get_process_heap(prog);
+                let heap = prog.define_instr(ir::InstructionInfo::Call { target: get_process_heap, args: vec![] });
+                let heap_alloc = self.heap_alloc(prog);
+                let flags = ir::Value::Const(ir::Const::new(ty_int, 0));
+                let args = vec![ir::Value::Instr(heap), flags, ir::Value::Param(params[0])];
+                let ptr = prog.define_instr(ir::InstructionInfo::Call { target: heap_alloc, args });
+
+                let block_info = prog.get_block_mut(block);
+                block_info.instructions.extend([heap, ptr]);
+                block_info.terminator = ir::Terminator::Return { value: ir::Value::Instr(ptr) };
+
+                ir::Value::Func(func)
+            }
+        }
+    }
+
+    /// `free(ptr: &void) -> void`: free memory allocated by `alloc`.
+    pub fn free(&mut self, prog: &mut ir::Program) -> ir::Value {
+        let ty_int = prog.ty_int();
+        let ty_ptr = prog.ty_ptr();
+        let ty_void = prog.ty_void();
+
+        match self.system_api {
+            SystemApi::Linux => self.get_extern(prog, "_lllang_free", vec![ty_ptr], ty_void),
+            SystemApi::Win32 => {
+                if let Some(func) = self.free {
+                    return ir::Value::Func(func);
+                }
+
+                let (func, params, block) = Runtime::define_func(prog, "free", &[ty_ptr], ty_void);
+                self.free = Some(func);
+
+                let get_process_heap = self.get_process_heap(prog);
+                let heap = prog.define_instr(ir::InstructionInfo::Call { target: get_process_heap, args: vec![] });
+                let heap_free = self.heap_free(prog);
+                let flags = ir::Value::Const(ir::Const::new(ty_int, 0));
+                let args = vec![ir::Value::Instr(heap), flags, ir::Value::Param(params[0])];
+                let call = prog.define_instr(ir::InstructionInfo::Call { target: heap_free, args });
+
+                let block_info = prog.get_block_mut(block);
+                block_info.instructions.extend([heap, call]);
+                block_info.terminator = ir::Terminator::Return { value: ir::Value::Undef(ty_void) };
+
+                ir::Value::Func(func)
+            }
+        }
+    }
+
+    /// `panic(location: &byte, location_len: int, message: &byte, message_len: int) -> void`: write the location
+    /// and the message to stderr, followed by a newline, and exit the process with `PANIC_EXIT_CODE`. Never returns.
+    pub fn panic(&mut self, prog: &mut ir::Program) -> ir::Value {
+        if let Some(func) = self.panic {
+            return ir::Value::Func(func);
+        }
+
+        let ty_int = prog.ty_int();
+        let ty_ptr = prog.ty_ptr();
+        let ty_byte = prog.define_type_int(8);
+        let ty_void = prog.ty_void();
+
+        let (func, params, block) = Runtime::define_func(prog, "panic", &[ty_ptr, ty_int, ty_ptr, ty_int], ty_void);
+        prog.get_func_mut(func).noreturn = true;
+        self.panic = Some(func);
+
+        let newline = prog.define_data(ir::DataInfo { ty: ty_ptr, inner_ty: ty_byte, bytes: b"\n".to_vec() });
+        let parts = [
+            (ir::Value::Param(params[0]), ir::Value::Param(params[1])),
+            (ir::Value::Param(params[2]), ir::Value::Param(params[3])),
+            (ir::Value::Data(newline), int(ty_int, 1)),
+        ];
+
+        let mut calls = vec![];
+        match self.system_api {
+            SystemApi::Win32 => {
+                let written = prog.define_slot(ir::StackSlotInfo { inner_ty: ty_int });
+                prog.get_func_mut(func).slots.push(written);
+                let null = ir::Value::Const(ir::Const::new(ty_ptr, 0));
+
+                let get_std_handle = self.get_std_handle(prog);
+                let handle = prog.define_instr(ir::InstructionInfo::Call { target: get_std_handle, args: vec![int(ty_int, STD_ERROR_HANDLE)] });
+                calls.push(handle);
+
+                let write_file = self.write_file(prog);
+                for &(ptr, len) in &parts {
+                    let args = vec![ir::Value::Instr(handle), ptr, len, ir::Value::Slot(written), null];
+                    calls.push(prog.define_instr(ir::InstructionInfo::Call { target: write_file, args }));
+                }
+
+                let exit_process = self.exit_process(prog);
+                calls.push(prog.define_instr(ir::InstructionInfo::Call { target: exit_process, args: vec![int(ty_int, PANIC_EXIT_CODE)] }));
+            }
+            SystemApi::Linux => {
+                let write = self.linux_write(prog);
+                for &(ptr, len) in &parts {
+                    let args = vec![int(ty_int, STDERR_FILENO), ptr, len];
+                    calls.push(prog.define_instr(ir::InstructionInfo::Call { target: write, args }));
+                }
+
+                let exit = self.linux_exit(prog);
+                calls.push(prog.define_instr(ir::InstructionInfo::Call { target: exit, args: vec![int(ty_int, PANIC_EXIT_CODE)] }));
+            }
+        }
+
+        //the block keeps its default unreachable terminator
+        prog.get_block_mut(block).instructions.extend(calls);
+
+        ir::Value::Func(func)
+    }
+}
+
+fn int(ty_int: ir::Type, value: i32) -> ir::Value {
+    ir::Value::Const(ir::Const::new(ty_int, value))
+}
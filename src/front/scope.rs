@@ -12,7 +12,7 @@ pub struct Scope<'p, V> {
 }
 
 impl<V: Debug> Scope<'_, V> {
-    pub fn nest(&self) -> Scope<V> {
+    pub fn nest(&self) -> Scope<'_, V> {
         Scope { parent: Some(self), values: Default::default() }
     }
 
@@ -45,7 +45,7 @@ impl<V: Debug> Scope<'_, V> {
     /// Find the given identifier in this scope.
     /// Walks up into the parent scopes until a scope without a parent is found,
     /// then looks in the `root` scope. If no value is found returns `Err`.
-    pub fn find<'a, 's>(&'s self, root: Option<&'s Self>, id: &'a ast::Identifier) -> Result<'a, &V> {
+    pub fn find<'a, 's>(&'s self, root: Option<&'s Self>, id: &'a ast::Identifier) -> Result<'a, &'s V> {
         if let Some(s) = self.values.get(&id.string) {
             Ok(s)
         } else if let Some(p) = self.parent {
@@ -62,6 +62,11 @@ impl<V: Debug> Scope<'_, V> {
         self.values.get(id)
     }
 
+    /// Iterate over the values declared in this scope without taking the parent scope into account.
+    pub fn immediate_values(&self) -> impl Iterator<Item=(&str, &V)> {
+        self.values.iter().map(|(id, value)| (id.as_str(), value))
+    }
+
     /// The amount of values declared in this scope without taking the parent scope into account.
     pub fn size(&self) -> usize {
         self.values.len()
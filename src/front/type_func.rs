@@ -1,14 +1,19 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use itertools::Itertools;
 
 use crate::front::{ast, cst, error};
-use crate::front::ast::{BinaryOp, DotIndexIndex};
-use crate::front::cst::{FunctionTypeInfo, ItemStore, ScopedItem, ScopedValue, ScopeKind, TypeInfo};
-use crate::front::error::Result;
+use crate::front::ast::DotIndexIndex;
+use crate::front::cst::{ArrayTypeInfo, FunctionTypeInfo, ItemStore, ScopedItem, ScopedValue, ScopeKind, TupleTypeInfo, TypeInfo};
+use crate::front::error::{Result, Warning};
 use crate::front::lower::{LRValue, MappingTypeStore};
+use crate::front::pos::Span;
 use crate::front::scope::Scope;
-use crate::front::type_solver::{Origin, TypeProblem, TypeVar};
+use crate::front::type_solver::{OperatorOverload, Origin, TypeProblem, TypeVar};
+use crate::mid::ir::CallingConvention;
+
+/// The registers inline asm operands can be bound to.
+const ASM_REGISTERS: &[&str] = &["eax", "ebx", "ecx", "edx", "esi", "edi"];
 
 /// The state necessary to lower a single function.
 pub struct TypeFuncState<'ast, 'cst, F: Fn(ScopedValue) -> LRValue> {
@@ -19,11 +24,22 @@ pub struct TypeFuncState<'ast, 'cst, F: Fn(ScopedValue) -> LRValue> {
     pub module_scope: &'cst Scope<'static, ScopedItem>,
 
     pub ret_ty: cst::Type,
+    /// The declared return type, `None` if it's implicitly void.
+    pub ret_ty_ast: Option<&'ast ast::Type>,
 
     pub expr_type_map: HashMap<*const ast::Expression, TypeVar>,
     pub decl_type_map: HashMap<*const ast::Declaration, TypeVar>,
 
     pub problem: TypeProblem<'ast>,
+
+    /// Local bindings with the warning to emit if they turn out to be unused.
+    pub bindings: Vec<(TypeVar, Warning<'ast>)>,
+    /// The local bindings that have been read at least once.
+    pub used_vars: HashSet<TypeVar>,
+    /// The explicit types of declarations, the `_` holes in them are reported once the types are inferred.
+    pub type_holes: Vec<(&'ast ast::Type, TypeVar)>,
+    /// Warnings that are already known while visiting the function, eg. for unreachable code.
+    pub warnings: Vec<Warning<'ast>>,
 }
 
 impl<'ast, 'cst, F: Fn(ScopedValue) -> LRValue> TypeFuncState<'ast, 'cst, F> {
@@ -40,9 +56,9 @@ impl<'ast, 'cst, F: Fn(ScopedValue) -> LRValue> TypeFuncState<'ast, 'cst, F> {
 
         let result: TypeVar = match &expr.kind {
             ast::ExpressionKind::Null => {
-                // null can take on any pointer type
+                // null can take on any optional pointer type
                 let inner_ty = self.problem.unknown(expr_origin);
-                self.problem.known(expr_origin, TypeInfo::Pointer(inner_ty))
+                self.problem.known(expr_origin, TypeInfo::OptionalPointer(inner_ty))
             }
             ast::ExpressionKind::BoolLit { .. } => {
                 self.problem.ty_bool()
@@ -50,18 +66,39 @@ impl<'ast, 'cst, F: Fn(ScopedValue) -> LRValue> TypeFuncState<'ast, 'cst, F> {
             ast::ExpressionKind::IntLit { .. } => {
                 self.problem.unknown_int(expr_origin)
             }
+            ast::ExpressionKind::FloatLit { .. } => {
+                self.problem.unknown_float(expr_origin)
+            }
             ast::ExpressionKind::StringLit { .. } => {
-                self.problem.known(expr_origin, TypeInfo::Pointer(self.problem.ty_byte()))
+                self.problem.known(expr_origin, TypeInfo::Str)
             }
             ast::ExpressionKind::Path(path) => {
                 let item = self.items.resolve_path(ScopeKind::Real, scope, path)?;
 
                 if let ScopedItem::Value(value) = item {
                     match value {
-                        ScopedValue::TypeVar(var) => var,
+                        ScopedValue::TypeVar(var) => {
+                            self.used_vars.insert(var);
+
+                            //use a separate var for each use, so type mismatches point at the use itself
+                            let ty = self.problem.unknown(expr_origin);
+                            self.problem.equal(var, ty);
+                            ty
+                        }
                         ScopedValue::Function(_) | ScopedValue::Const(_) | ScopedValue::Immediate(_) => {
-                            let ty = (self.map_value)(value).ty(&self.types);
-                            self.problem.fully_known(&self.types, ty)
+                            let ty = (self.map_value)(value).ty(self.types);
+                            self.problem.fully_known(expr_origin, self.types, ty)
+                        }
+                        ScopedValue::EnumVariant { ty, variant } => {
+                            //variants with a payload act like a constructor function
+                            let fields = &self.types[ty].unwrap_enum().unwrap().variants[variant as usize].fields;
+                            let value_ty = if fields.is_empty() {
+                                ty
+                            } else {
+                                let info = FunctionTypeInfo { params: fields.clone(), ret: ty, conv: CallingConvention::default() };
+                                self.types.define_type(TypeInfo::Function(info))
+                            };
+                            self.problem.fully_known(expr_origin, self.types, value_ty)
                         }
                     }
                 } else {
@@ -69,39 +106,32 @@ impl<'ast, 'cst, F: Fn(ScopedValue) -> LRValue> TypeFuncState<'ast, 'cst, F> {
                 }
             }
             ast::ExpressionKind::Ternary { condition, then_value, else_value } => {
-                let cond_ty = self.visit_expr(&scope, &*condition)?;
+                let cond_ty = self.visit_expr(scope, condition)?;
                 self.problem.equal(cond_ty, self.problem.ty_bool());
 
+                let (then_scope, else_scope) = self.narrowed_scopes(scope, condition)?;
+
                 let value_ty = self.problem.unknown(expr_origin);
-                let then_ty = self.visit_expr(&scope, then_value)?;
-                let else_ty = self.visit_expr(&scope, else_value)?;
-                self.problem.equal(value_ty, then_ty);
-                self.problem.equal(value_ty, else_ty);
+                let then_ty = self.visit_expr(&then_scope, then_value)?;
+                let else_ty = self.visit_expr(&else_scope, else_value)?;
+                self.problem.coerce(then_ty, value_ty);
+                self.problem.coerce(else_ty, value_ty);
 
                 value_ty
             }
             ast::ExpressionKind::Binary { kind, left, right } => {
-                let left_ty = self.visit_expr(&scope, left)?;
-                let right_ty = self.visit_expr(&scope, right)?;
-
-                match kind {
-                    BinaryOp::Add | BinaryOp::Sub => {
-                        self.problem.add_sub_constraint(left_ty, right_ty);
-                        left_ty
-                    }
-                    BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => {
-                        let value_ty = self.problem.unknown_int(expr_origin);
-                        self.problem.equal(value_ty, left_ty);
-                        self.problem.equal(value_ty, right_ty);
-                        value_ty
-                    }
-                    BinaryOp::Eq | BinaryOp::Neq | BinaryOp::Gte | BinaryOp::Gt | BinaryOp::Lte | BinaryOp::Lt => {
-                        let value_ty = self.problem.unknown_int(expr_origin);
-                        self.problem.equal(value_ty, left_ty);
-                        self.problem.equal(value_ty, right_ty);
-                        self.problem.ty_bool()
-                    }
-                }
+                let left_ty = self.visit_expr(scope, left)?;
+                let right_ty = self.visit_expr(scope, right)?;
+
+                let overloads = self.items.operators.iter()
+                    .filter(|&(&(overload_kind, _), _)| overload_kind == *kind)
+                    .map(|(&(_, left), &func)| {
+                        let func_ty = &self.items.funcs[func].func_ty;
+                        OperatorOverload { left, right: func_ty.params[1], ret: func_ty.ret }
+                    })
+                    .collect_vec();
+
+                self.problem.binary(expr_origin, *kind, left_ty, right_ty, overloads)
             }
             ast::ExpressionKind::Unary { kind, inner } => {
                 match kind {
@@ -119,27 +149,108 @@ impl<'ast, 'cst, F: Fn(ScopedValue) -> LRValue> TypeFuncState<'ast, 'cst, F> {
                         deref_ty
                     }
                     ast::UnaryOp::Neg => {
-                        let value_ty = self.problem.unknown_int(expr_origin);
+                        let value_ty = self.problem.unknown_number(expr_origin);
                         let inner_ty = self.visit_expr(scope, inner)?;
                         self.problem.equal(value_ty, inner_ty);
                         value_ty
                     }
                 }
             }
+            ast::ExpressionKind::ArrayRepeat { value, length } => {
+                let inner = self.visit_expr(scope, value)?;
+                self.problem.known(expr_origin, TypeInfo::Array(ArrayTypeInfo { inner, length: *length }))
+            }
+            ast::ExpressionKind::Tuple { values } => {
+                let fields = values.iter().map(|value| self.visit_expr(scope, value)).try_collect()?;
+                self.problem.known(expr_origin, TypeInfo::Tuple(TupleTypeInfo { fields }))
+            }
+            ast::ExpressionKind::StructLit { path, fields, base } => {
+                let ty = match self.items.resolve_path(ScopeKind::Real, scope, path)? {
+                    ScopedItem::Type(ty) => ty,
+                    item => return Err(item.err_unexpected_kind(error::ItemType::Type, path)),
+                };
+                let info = self.types[ty].unwrap_struct().ok_or_else(|| error::Error::ExpectStructOrTupleType {
+                    expression: expr,
+                    actual: self.types.format_type(ty).to_string(),
+                })?.clone();
+
+                let mut given = HashSet::new();
+                for field in fields {
+                    let index = info.find_field_index(&field.id.string)
+                        .ok_or_else(|| error::Error::StructFieldNotFound {
+                            target: expr,
+                            target_type: self.types.format_type(ty).to_string(),
+                            index: &field.id,
+                        })?;
+                    if !given.insert(index) {
+                        return Err(error::Error::StructLitFieldGivenTwice(field));
+                    }
+
+                    let field_ty = self.problem.fully_known(expr_origin, self.types, info.fields[index as usize].ty);
+                    let value_ty = self.visit_expr(scope, &field.value)?;
+                    self.problem.coerce(value_ty, field_ty);
+                }
+
+                let ty_var = self.problem.fully_known(expr_origin, self.types, ty);
+                match base {
+                    //the remaining fields are copied from the base
+                    Some(base) => {
+                        let base_ty = self.visit_expr(scope, base)?;
+                        self.problem.equal(base_ty, ty_var);
+                    }
+                    None => {
+                        let missing = (0..info.fields.len() as u32).find(|index| !given.contains(index));
+                        if let Some(missing) = missing {
+                            return Err(error::Error::StructLitMissingField {
+                                expression: expr,
+                                field: info.fields[missing as usize].id.to_owned(),
+                            });
+                        }
+                    }
+                }
+
+                ty_var
+            }
             ast::ExpressionKind::Call { target, args } => {
+                let args = self.items.order_call_args(scope, expr, target, args)?;
                 let target_ty = self.visit_expr(scope, target)?;
 
-                let arg_tys = args.iter().map(|arg| {
+                let arg_tys = args.iter().map(|&arg| {
                     self.visit_expr(scope, arg)
                 }).try_collect()?;
-                let ret_ty = self.problem.unknown(expr_origin);
-                let template = self.problem.known(expr_origin, TypeInfo::Function(FunctionTypeInfo {
-                    params: arg_tys,
-                    ret: ret_ty,
-                }));
 
-                self.problem.equal(target_ty, template);
-                ret_ty
+                self.problem.call(expr_origin, target_ty, arg_tys)
+            }
+            ast::ExpressionKind::Block(block) => {
+                self.visit_block_value(scope, block)?
+            }
+            ast::ExpressionKind::Closure { params, ret_ty, body } => {
+                let mut closure_scope = scope.nest();
+
+                let params = params.iter().map(|param| {
+                    let ty = self.resolve_type(scope, &param.ty)?;
+                    let var = self.problem.fully_known(Origin::Type(&param.ty), self.types, ty);
+                    self.declare_binding(&mut closure_scope, &param.id, var, Warning::UnusedParameter)?;
+                    Ok(ty)
+                }).try_collect()?;
+                let ret = ret_ty.as_ref()
+                    .map(|ret_ty| {
+                        let ty = self.resolve_type(scope, ret_ty)?;
+                        self.types.check_outlives_func(ret_ty, ty)?;
+                        Ok(ty)
+                    })
+                    .transpose()?
+                    .unwrap_or(self.types.type_void());
+
+                //return statements in the body refer to the closure itself
+                let outer_ret_ty = std::mem::replace(&mut self.ret_ty, ret);
+                let outer_ret_ty_ast = std::mem::replace(&mut self.ret_ty_ast, ret_ty.as_ref());
+                self.visit_nested_block(&closure_scope, body)?;
+                self.ret_ty = outer_ret_ty;
+                self.ret_ty_ast = outer_ret_ty_ast;
+
+                let ty = self.types.define_type(TypeInfo::Closure(FunctionTypeInfo { params, ret, conv: CallingConvention::default() }));
+                self.problem.fully_known(expr_origin, self.types, ty)
             }
             ast::ExpressionKind::DotIndex { target, index } => {
                 //TODO allow reference to struct too? again, how to propagate the LR-ness?
@@ -162,16 +273,32 @@ impl<'ast, 'cst, F: Fn(ScopedValue) -> LRValue> TypeFuncState<'ast, 'cst, F> {
                 self.problem.equal(self.problem.ty_int(), index_ty);
                 self.problem.array_index(expr_origin, target_ty)
             }
+            ast::ExpressionKind::Slice { target, start, end } => {
+                let target_ty = self.visit_expr(scope, target)?;
+                for bound in start.iter().chain(end) {
+                    let bound_ty = self.visit_expr(scope, bound)?;
+                    self.problem.equal(self.problem.ty_int(), bound_ty);
+                }
+
+                self.problem.slice(expr_origin, target_ty)
+            }
             ast::ExpressionKind::Cast { value, ty } => {
                 let before_ty = self.visit_expr(scope, value)?;
+                let after_ty = self.resolve_type(scope, ty)?;
 
-                //require that the value expression has a pointer type
-                let before_inner_ty = self.problem.unknown(expr_origin);
-                let before_ty_match = self.problem.known(expr_origin, TypeInfo::Pointer(before_inner_ty));
+                //only pointers and integers can be cast to
+                match self.types[after_ty] {
+                    TypeInfo::Pointer(_) | TypeInfo::OptionalPointer(_) | TypeInfo::Int | TypeInfo::Byte => {}
+                    _ => return Err(error::Error::InvalidCastTarget {
+                        expression: expr,
+                        target: self.types.format_type(after_ty).to_string(),
+                    }),
+                };
+
+                let before_ty_match = self.problem.unknown_castable_to(expr_origin, after_ty);
                 self.problem.equal(before_ty, before_ty_match);
 
-                let after_ty = self.resolve_type(scope, ty)?;
-                self.problem.fully_known(self.types, after_ty)
+                self.problem.fully_known(expr_origin, self.types, after_ty)
             }
             ast::ExpressionKind::Return { value } => {
                 let value_ty = if let Some(value) = value {
@@ -180,14 +307,52 @@ impl<'ast, 'cst, F: Fn(ScopedValue) -> LRValue> TypeFuncState<'ast, 'cst, F> {
                     self.problem.ty_void()
                 };
 
-                let ret_ty = self.problem.fully_known(&self.types, self.ret_ty);
-                self.problem.equal(ret_ty, value_ty);
+                let ret_origin = self.ret_ty_ast.map_or(Origin::FullyKnown, Origin::ReturnType);
+                let ret_ty = self.problem.fully_known(ret_origin, self.types, self.ret_ty);
+                self.problem.coerce(value_ty, ret_ty);
 
-                //TODO use "never" type once that exists instead, also for break and continue
-                self.problem.unknown_default_void(expr_origin)
+                self.problem.ty_never()
             }
-            ast::ExpressionKind::Continue => self.problem.unknown_default_void(expr_origin),
-            ast::ExpressionKind::Break => self.problem.unknown_default_void(expr_origin),
+            ast::ExpressionKind::Alloc { size } => {
+                let size_ty = self.visit_expr(scope, size)?;
+                self.problem.equal(self.problem.ty_int(), size_ty);
+
+                self.problem.known(expr_origin, TypeInfo::Pointer(self.problem.ty_byte()))
+            }
+            ast::ExpressionKind::Free { ptr } => {
+                //any pointer can be freed
+                let ptr_ty = self.visit_expr(scope, ptr)?;
+                let inner_ty = self.problem.unknown(expr_origin);
+                let expected_ty = self.problem.known(expr_origin, TypeInfo::Pointer(inner_ty));
+                self.problem.equal(ptr_ty, expected_ty);
+
+                self.problem.ty_void()
+            }
+            ast::ExpressionKind::New { ty } => {
+                let ty = self.resolve_type(scope, ty)?;
+                let inner_ty = self.problem.fully_known(expr_origin, self.types, ty);
+                self.problem.known(expr_origin, TypeInfo::Pointer(inner_ty))
+            }
+            ast::ExpressionKind::Panic { message } => {
+                let message_ty = self.visit_expr(scope, message)?;
+                let str_ty = self.problem.known(expr_origin, TypeInfo::Str);
+                self.problem.equal(message_ty, str_ty);
+
+                self.problem.ty_never()
+            }
+            ast::ExpressionKind::InlineAsm { template: _, operands } => {
+                for operand in operands {
+                    if !ASM_REGISTERS.contains(&operand.constraint.as_str()) {
+                        return Err(error::Error::UnknownAsmRegister(operand));
+                    }
+                    self.visit_expr(scope, &operand.value)?;
+                }
+
+                self.problem.ty_void()
+            }
+            ast::ExpressionKind::Unreachable => self.problem.ty_never(),
+            ast::ExpressionKind::Continue => self.problem.ty_never(),
+            ast::ExpressionKind::Break => self.problem.ty_never(),
         };
 
         let prev = self.expr_type_map.insert(expr as *const _, result);
@@ -204,9 +369,11 @@ impl<'ast, 'cst, F: Fn(ScopedValue) -> LRValue> TypeFuncState<'ast, 'cst, F> {
 
                 let expect_ty = match &decl.ty {
                     None => self.problem.unknown(decl_origin),
-                    Some(ty) => {
-                        let ty = self.resolve_type(scope, ty);
-                        self.problem.fully_known(&self.types, ty?)
+                    Some(ty_ast) => {
+                        let ty = self.resolve_type(scope, ty_ast)?;
+                        let var = self.problem.fully_known(Origin::Type(ty_ast), self.types, ty);
+                        self.type_holes.push((ty_ast, var));
+                        var
                     }
                 };
 
@@ -215,92 +382,465 @@ impl<'ast, 'cst, F: Fn(ScopedValue) -> LRValue> TypeFuncState<'ast, 'cst, F> {
                     Some(init) => self.visit_expr(scope, init)?
                 };
 
-                self.problem.equal(expect_ty, value_ty);
+                //only an explicit type can make the value optional
+                if decl.ty.is_some() {
+                    self.problem.coerce(value_ty, expect_ty);
+                } else {
+                    self.problem.equal(expect_ty, value_ty);
+                }
                 self.decl_type_map.insert(decl as *const _, expect_ty);
 
-                scope.maybe_declare(&decl.id, ScopedItem::Value(ScopedValue::TypeVar(expect_ty)))?;
-
-                Ok(())
+                self.visit_declaration_pattern(scope, decl, expect_ty)
             }
             ast::StatementKind::Assignment(assign) => {
-                let addr_ty = self.visit_expr(scope, &assign.left)?;
-                let value_ty = self.visit_expr(scope, &assign.right)?;
-                self.problem.equal(addr_ty, value_ty);
-                Ok(())
+                match &assign.left.kind {
+                    ast::ExpressionKind::Tuple { .. } => {
+                        let value_ty = self.visit_expr(scope, &assign.right)?;
+                        self.visit_assign_target(scope, &assign.left, value_ty)
+                    }
+                    _ => {
+                        let addr_ty = self.visit_expr(scope, &assign.left)?;
+                        let value_ty = self.visit_expr(scope, &assign.right)?;
+                        self.problem.coerce(value_ty, addr_ty);
+                        Ok(())
+                    }
+                }
             }
             ast::StatementKind::If(if_stmt) => {
                 let cond_ty = self.visit_expr(scope, &if_stmt.cond)?;
                 self.problem.equal(cond_ty, self.problem.ty_bool());
 
-                self.visit_nested_block(scope, &if_stmt.then_block)?;
+                let (then_scope, else_scope) = self.narrowed_scopes(scope, &if_stmt.cond)?;
+                self.visit_nested_block(&then_scope, &if_stmt.then_block)?;
                 if let Some(else_block) = &if_stmt.else_block {
-                    self.visit_nested_block(scope, else_block)?;
+                    self.visit_nested_block(&else_scope, else_block)?;
                 }
 
                 Ok(())
             }
-            ast::StatementKind::While(while_stmt) => {
+            ast::StatementKind::While(while_stmt) | ast::StatementKind::DoWhile(while_stmt) => {
                 let cond_ty = self.visit_expr(scope, &while_stmt.cond)?;
                 self.problem.equal(cond_ty, self.problem.ty_bool());
 
                 self.visit_nested_block(scope, &while_stmt.body)?;
                 Ok(())
             }
+            ast::StatementKind::Loop(loop_stmt) => {
+                self.visit_nested_block(scope, &loop_stmt.body)?;
+                Ok(())
+            }
             ast::StatementKind::For(for_stmt) => {
                 let index_ty = for_stmt.index_ty.as_ref()
                     .map(|ty| self.resolve_type(scope, ty))
                     .transpose()?;
                 let index_ty = match index_ty {
-                    Some(index_ty) => self.problem.fully_known(&self.types, index_ty),
+                    Some(index_ty) => self.problem.fully_known(Origin::ForIndex(for_stmt), self.types, index_ty),
                     None => self.problem.unknown(Origin::ForIndex(for_stmt)),
                 };
 
-                let start_ty = self.visit_expr(scope, &for_stmt.start)?;
-                let end_ty = self.visit_expr(scope, &for_stmt.end)?;
+                match &for_stmt.range {
+                    ast::ForRange::Range { start, end, step } => {
+                        let start_ty = self.visit_expr(scope, start)?;
+                        let end_ty = self.visit_expr(scope, end)?;
+                        if let Some(step) = step {
+                            let step_ty = self.visit_expr(scope, step)?;
+                            self.problem.equal(index_ty, step_ty);
+                        }
 
-                let unknown_int = self.problem.unknown_int(Origin::ForIndex(for_stmt));
-                self.problem.equal(index_ty, unknown_int);
-                self.problem.equal(index_ty, start_ty);
-                self.problem.equal(index_ty, end_ty);
+                        let unknown_int = self.problem.unknown_int(Origin::ForIndex(for_stmt));
+                        self.problem.equal(index_ty, unknown_int);
+                        self.problem.equal(index_ty, start_ty);
+                        self.problem.equal(index_ty, end_ty);
+                    }
+                    ast::ForRange::Array(array) => {
+                        let array_ty = self.visit_expr(scope, array)?;
+                        let element_ty = self.problem.element(Origin::ForIndex(for_stmt), array_ty);
+                        self.problem.equal(index_ty, element_ty);
+                    }
+                }
 
                 let mut index_scope = scope.nest();
-                index_scope.maybe_declare(&for_stmt.index, ScopedItem::Value(ScopedValue::TypeVar(index_ty)))?;
+                self.declare_binding(&mut index_scope, &for_stmt.index, index_ty, Warning::UnusedVariable)?;
 
                 self.visit_nested_block(&index_scope, &for_stmt.body)?;
 
                 Ok(())
             }
+            ast::StatementKind::Match(match_stmt) => {
+                let value_ty = self.visit_expr(scope, &match_stmt.value)?;
+
+                let mut has_wildcard = false;
+                let mut matched_enum = None;
+                let mut covered_variants = HashSet::new();
+
+                for arm in &match_stmt.arms {
+                    let mut arm_scope = scope.nest();
+
+                    match &arm.pattern {
+                        ast::MatchPattern::Wildcard(_) => has_wildcard = true,
+                        ast::MatchPattern::Literal(lit) => {
+                            let lit_ty = self.visit_expr(scope, lit)?;
+                            self.problem.equal(value_ty, lit_ty);
+                        }
+                        ast::MatchPattern::Variant { span: _, path, fields } => {
+                            let (enum_ty, variant) = self.items.resolve_enum_variant(scope, path)?;
+                            matched_enum = Some(enum_ty);
+                            covered_variants.insert(variant);
+
+                            let enum_ty_var = self.problem.fully_known(Origin::FullyKnown, self.types, enum_ty);
+                            self.problem.equal(value_ty, enum_ty_var);
+
+                            let field_tys = self.types[enum_ty].unwrap_enum().unwrap().variants[variant as usize].fields.clone();
+                            if field_tys.len() != fields.len() {
+                                return Err(error::Error::WrongVariantFieldCount {
+                                    path,
+                                    expected: field_tys.len(),
+                                    actual: fields.len(),
+                                });
+                            }
+
+                            for (field, field_ty) in fields.iter().zip(field_tys) {
+                                let field_ty_var = self.problem.fully_known(Origin::FullyKnown, self.types, field_ty);
+                                self.declare_binding(&mut arm_scope, field, field_ty_var, Warning::UnusedVariable)?;
+                            }
+                        }
+                    }
+
+                    self.visit_nested_block(&arm_scope, &arm.body)?;
+                }
+
+                //a wildcard covers everything, otherwise each variant of the enum needs its own arm
+                if !has_wildcard {
+                    let missing: Vec<String> = match matched_enum {
+                        Some(enum_ty) => {
+                            self.types[enum_ty].unwrap_enum().unwrap().variants.iter().enumerate()
+                                .filter(|&(i, _)| !covered_variants.contains(&(i as u32)))
+                                .map(|(_, variant)| variant.id.to_owned())
+                                .collect()
+                        }
+                        None => vec!["_".to_owned()],
+                    };
+
+                    if !missing.is_empty() {
+                        return Err(error::Error::NonExhaustiveMatch { statement: match_stmt, missing });
+                    }
+                }
+
+                Ok(())
+            }
             ast::StatementKind::Block(block) => {
                 self.visit_nested_block(scope, block)
             }
+            ast::StatementKind::Function(func) => {
+                //the body is visited separately, like any other function
+                let func = *self.items.nested_funcs.get(&(&**func as *const _)).unwrap();
+                let id = &self.items.funcs[func].ast.id;
+                scope.declare(id, ScopedItem::Value(ScopedValue::Function(func)))?;
+                Ok(())
+            }
             ast::StatementKind::Expression(expr) => {
                 self.visit_expr(scope, expr)?;
                 Ok(())
             }
+            ast::StatementKind::Assert(cond) => {
+                let cond_ty = self.visit_expr(scope, cond)?;
+                self.problem.equal(cond_ty, self.problem.ty_bool());
+                Ok(())
+            }
+        }
+    }
+
+    /// Declare a local binding in `scope`. Unless it's the `_` placeholder `warning` is emitted if it's never used.
+    /// Visit the target of an assignment with a value of type `value_ty`. The elements of a tuple target are
+    /// assigned the corresponding fields of the value.
+    fn visit_assign_target(&mut self, scope: &Scope<ScopedItem>, target: &'ast ast::Expression, value_ty: TypeVar) -> Result<'ast, ()> {
+        match &target.kind {
+            ast::ExpressionKind::Tuple { values } => {
+                let origin = Origin::Expression(target);
+                let fields = values.iter().map(|_| self.problem.unknown(origin)).collect_vec();
+                let tuple_ty = self.problem.known(origin, TypeInfo::Tuple(TupleTypeInfo { fields: fields.clone() }));
+                self.problem.equal(value_ty, tuple_ty);
+
+                for (value, field_ty) in values.iter().zip(fields) {
+                    self.visit_assign_target(scope, value, field_ty)?;
+                }
+            }
+            _ => {
+                let addr_ty = self.visit_expr(scope, target)?;
+                self.problem.coerce(value_ty, addr_ty);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Declare the variables bound by the pattern of `decl`, where `ty` is the type of the entire value.
+    fn visit_declaration_pattern(&mut self, scope: &mut Scope<ScopedItem>, decl: &'ast ast::Declaration, ty: TypeVar) -> Result<'ast, ()> {
+        let decl_origin = Origin::Declaration(decl);
+
+        match &decl.pattern {
+            ast::DeclarationPattern::Single(id) => {
+                self.declare_binding(scope, id, ty, Warning::UnusedVariable)?;
+            }
+            ast::DeclarationPattern::Tuple { span: _, elements } => {
+                //the tuple type must have exactly as many fields as the pattern
+                let fields = elements.iter().map(|_| self.problem.unknown(decl_origin)).collect_vec();
+                let tuple_ty = self.problem.known(decl_origin, TypeInfo::Tuple(TupleTypeInfo { fields: fields.clone() }));
+                self.problem.equal(ty, tuple_ty);
+
+                for (id, field_ty) in elements.iter().zip(fields) {
+                    self.declare_binding(scope, id, field_ty, Warning::UnusedVariable)?;
+                }
+            }
+            ast::DeclarationPattern::Struct { span: _, path, fields } => {
+                let struct_ty = match self.items.resolve_path(ScopeKind::Real, scope, path)? {
+                    ScopedItem::Type(ty) => ty,
+                    item => return Err(item.err_unexpected_kind(error::ItemType::Type, path)),
+                };
+                let info = self.types[struct_ty].unwrap_struct().ok_or_else(|| error::Error::PatternExpectStructType {
+                    path,
+                    actual: self.types.format_type(struct_ty).to_string(),
+                })?.clone();
+
+                let struct_ty_var = self.problem.fully_known(decl_origin, self.types, struct_ty);
+                self.problem.equal(ty, struct_ty_var);
+
+                for field in fields {
+                    let index = info.find_field_index(&field.field.string)
+                        .ok_or_else(|| error::Error::PatternFieldNotFound {
+                            target_type: self.types.format_type(struct_ty).to_string(),
+                            field,
+                        })?;
+
+                    let field_ty = self.problem.fully_known(decl_origin, self.types, info.fields[index as usize].ty);
+                    self.declare_binding(scope, &field.binding, field_ty, Warning::UnusedVariable)?;
+                }
+            }
         }
+
+        Ok(())
+    }
+
+    fn declare_binding(
+        &mut self,
+        scope: &mut Scope<ScopedItem>,
+        id: &'ast ast::MaybeIdentifier,
+        var: TypeVar,
+        warning: impl FnOnce(&'ast ast::Identifier) -> Warning<'ast>,
+    ) -> Result<'ast, ()> {
+        scope.maybe_declare(id, ScopedItem::Value(ScopedValue::TypeVar(var)))?;
+        if let ast::MaybeIdentifier::Identifier(id) = id {
+            self.bindings.push((var, warning(id)));
+        }
+        Ok(())
+    }
+
+    /// Create the scopes for the branches of a condition. If the condition checks an optional pointer against null
+    /// the branch where it's known to be non-null gets a narrowed pointer variable with the same name.
+    fn narrowed_scopes<'s>(
+        &mut self,
+        scope: &'s Scope<ScopedItem>,
+        cond: &'ast ast::Expression,
+    ) -> Result<'ast, (Scope<'s, ScopedItem>, Scope<'s, ScopedItem>)> {
+        let mut then_scope = scope.nest();
+        let mut else_scope = scope.nest();
+
+        if let Some((path, then_non_null)) = null_check(cond) {
+            if let ScopedItem::Value(ScopedValue::TypeVar(var)) = self.items.resolve_path(ScopeKind::Real, scope, path)? {
+                let origin = Origin::Expression(cond);
+                let inner = self.problem.unknown(origin);
+                let optional = self.problem.known(origin, TypeInfo::OptionalPointer(inner));
+                self.problem.equal(var, optional);
+                let narrowed = self.problem.known(origin, TypeInfo::Pointer(inner));
+
+                let narrowed_scope = if then_non_null { &mut then_scope } else { &mut else_scope };
+                narrowed_scope.declare(&path.id, ScopedItem::Value(ScopedValue::TypeVar(narrowed)))?;
+            }
+        }
+
+        Ok((then_scope, else_scope))
     }
 
     fn visit_nested_block(&mut self, scope: &Scope<ScopedItem>, block: &'ast ast::Block) -> Result<'ast, ()> {
+        self.visit_block_value(scope, block)?;
+        Ok(())
+    }
+
+    /// Visit a block and return the type of its value, void if it doesn't have one.
+    fn visit_block_value(&mut self, scope: &Scope<ScopedItem>, block: &'ast ast::Block) -> Result<'ast, TypeVar> {
         let mut inner_scope = scope.nest();
 
-        block.statements.iter()
-            .try_for_each(|stmt| self.visit_statement(&mut inner_scope, stmt))
+        for stmt in &block.statements {
+            self.visit_statement(&mut inner_scope, stmt)?;
+        }
+        self.check_unreachable(block);
+
+        if let Some(value) = &block.value {
+            return self.visit_expr(&inner_scope, value);
+        }
+
+        //a block that ends with a diverging expression doesn't produce a value either
+        let diverges = block.statements.last().and_then(|stmt| self.diverging_expr(stmt)).is_some();
+        Ok(if diverges { self.problem.ty_never() } else { self.problem.ty_void() })
+    }
+
+    /// The expression `stmt` consists of if it's known to diverge, eg. a `return`, `break` or `continue`.
+    fn diverging_expr(&self, stmt: &'ast ast::Statement) -> Option<&'ast ast::Expression> {
+        match &stmt.kind {
+            ast::StatementKind::Expression(expr) if self.expr_type_map[&(&**expr as *const _)] == self.problem.ty_never() =>
+                Some(expr),
+            _ => None,
+        }
     }
 
-    pub fn visit_func(&mut self, decl: &'cst cst::FunctionDecl<'ast>) -> Result<'ast, ()> {
+    /// Warn about the code in `block` that follows the first diverging statement, if any.
+    fn check_unreachable(&mut self, block: &'ast ast::Block) {
+        let first_diverging = block.statements.iter().enumerate()
+            .find_map(|(i, stmt)| self.diverging_expr(stmt).map(|expr| (i, expr)));
+
+        if let Some((i, after)) = first_diverging {
+            let dead = &block.statements[i + 1..];
+            let start = dead.first().map(|stmt| stmt.span.start)
+                .or_else(|| block.value.as_ref().map(|value| value.span.start));
+            let end = block.value.as_ref().map(|value| value.span.end)
+                .or_else(|| dead.last().map(|stmt| stmt.span.end));
+
+            if let (Some(start), Some(end)) = (start, end) {
+                self.warnings.push(Warning::UnreachableCode { span: Span::new(start, end), after });
+            }
+        }
+    }
+
+    /// Visit the body of a function and return the warnings for it, eg. for bindings that are never used.
+    pub fn visit_func(&mut self, decl: &'cst cst::FunctionDecl<'ast>) -> Result<'ast, Vec<Warning<'ast>>> {
         let mut scope = self.module_scope.nest();
 
+        //nested functions can call themselves and the other functions declared in the same block
+        if let Some(siblings) = self.items.nested_siblings.get(&(decl.ast as *const _)) {
+            for &func in siblings {
+                let id = &self.items.funcs[func].ast.id;
+                scope.declare(id, ScopedItem::Value(ScopedValue::Function(func)))?;
+            }
+        }
+
         for (i, param) in decl.ast.params.iter().enumerate() {
             let ty = decl.func_ty.params[i];
-            let ty_var = self.problem.fully_known(&self.types, ty);
+            let ty_var = self.problem.fully_known(Origin::Type(&param.ty), self.types, ty);
 
-            scope.maybe_declare(&param.id, ScopedItem::Value(ScopedValue::TypeVar(ty_var)))?;
+            self.declare_binding(&mut scope, &param.id, ty_var, Warning::UnusedParameter)?;
         }
 
         let body = decl.ast.body.as_ref().
             expect("can only generate code for functions with a body");
         self.visit_nested_block(&scope, body)?;
 
-        Ok(())
+        let used_vars = &self.used_vars;
+        let warnings = self.warnings.drain(..)
+            .chain(self.bindings.drain(..)
+                .filter(|(var, _)| !used_vars.contains(var))
+                .map(|(_, warning)| warning))
+            .collect();
+        Ok(warnings)
+    }
+}
+
+/// Report the type inferred for each `_` in `ty_ast`, where `ty` is the inferred type of the entire `ty_ast`.
+pub fn report_type_holes<'ast>(types: &cst::TypeStore, ty_ast: &'ast ast::Type, ty: cst::Type, warnings: &mut Vec<Warning<'ast>>) {
+    match (&ty_ast.kind, &types[ty]) {
+        (ast::TypeKind::Wildcard, _) => {
+            let inferred = types.format_type(ty).to_string();
+            warnings.push(Warning::TypeHole { ty: ty_ast, inferred });
+        }
+        (ast::TypeKind::Ref(inner_ast), &TypeInfo::Pointer(inner)) |
+        (ast::TypeKind::OptionalRef(inner_ast), &TypeInfo::OptionalPointer(inner)) |
+        (ast::TypeKind::Slice(inner_ast), &TypeInfo::Slice(inner)) => {
+            report_type_holes(types, inner_ast, inner, warnings);
+        }
+        (ast::TypeKind::Array { inner: inner_ast, length: _ }, TypeInfo::Array(info)) => {
+            report_type_holes(types, inner_ast, info.inner, warnings);
+        }
+        (ast::TypeKind::Tuple { fields: fields_ast }, TypeInfo::Tuple(info)) => {
+            for (field_ast, &field) in fields_ast.iter().zip(&info.fields) {
+                report_type_holes(types, field_ast, field, warnings);
+            }
+        }
+        (ast::TypeKind::Func { params: params_ast, ret: ret_ast }, TypeInfo::Function(info)) |
+        (ast::TypeKind::Closure { params: params_ast, ret: ret_ast }, TypeInfo::Closure(info)) => {
+            for (param_ast, &param) in params_ast.iter().zip(&info.params) {
+                report_type_holes(types, param_ast, param, warnings);
+            }
+            report_type_holes(types, ret_ast, info.ret, warnings);
+        }
+        _ => {}
+    }
+}
+
+/// Check whether `cond` compares a local variable against null, like `p != null` or `null == p`. Returns the path of
+/// the variable and whether it's known to be non-null in the then branch (`!=`) or in the else branch (`==`).
+pub fn null_check(cond: &ast::Expression) -> Option<(&ast::Path, bool)> {
+    let (kind, left, right) = match &cond.kind {
+        ast::ExpressionKind::Binary { kind, left, right } => (kind, left, right),
+        _ => return None,
+    };
+
+    let then_non_null = match kind {
+        ast::BinaryOp::Neq => true,
+        ast::BinaryOp::Eq => false,
+        _ => return None,
+    };
+
+    let path = match (&left.kind, &right.kind) {
+        (ast::ExpressionKind::Path(path), ast::ExpressionKind::Null) |
+        (ast::ExpressionKind::Null, ast::ExpressionKind::Path(path)) => path,
+        _ => return None,
+    };
+
+    if path.parents.is_empty() {
+        Some((path, then_non_null))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mid::opt::snapshot::lower_warnings;
+
+    fn holes(src: &str) -> Vec<String> {
+        lower_warnings(src).into_iter().filter(|w| w.contains("type hole")).collect()
+    }
+
+    #[test]
+    fn type_holes() {
+        //a hole nested in a type reports just the part it stands for
+        let warnings = holes("fun main() -> int { let x: _ = 5; let b = true; let p: &_ = &b; return x; }");
+        assert_eq!(2, warnings.len(), "{:?}", warnings);
+        assert!(warnings[0].contains("type hole: inferred `int`\n --> main.ll:1:28"), "{:?}", warnings);
+        assert!(warnings[1].contains("type hole: inferred `bool`\n --> main.ll:1:57"), "{:?}", warnings);
+
+        //fully written out or omitted types are not reported
+        assert!(holes("fun main() -> int { let x: int = 5; let y = x; return y; }").is_empty());
+    }
+
+
+    fn unreachable_warnings(src: &str) -> Vec<String> {
+        lower_warnings(src).into_iter().filter(|w| w.contains("unreachable code")).collect()
+    }
+
+    #[test]
+    fn unreachable_code() {
+        //one warning covering all dead statements, for return, break and continue
+        let src = "fun main() -> int { let x = 1; return x; x = 2; x = 3; }";
+        let warnings = unreachable_warnings(src);
+        assert_eq!(1, warnings.len(), "{:?}", warnings);
+        assert!(warnings[0].contains("--> main.ll:1:42"), "{:?}", warnings);
+        assert!(warnings[0].contains("^^^^^^^^^^^^^\n"), "{:?}", warnings);
+
+        let src = "fun main() -> int { loop { break; let a = 1; } while (true) { continue; let b = 2; } return 0; }";
+        assert_eq!(2, unreachable_warnings(src).len());
+
+        //diverging at the end of a block or only in a nested block is fine
+        let src = "fun main() -> int { let x = 1; if (x == 1) { return 2; } loop { break; } return x; }";
+        assert!(unreachable_warnings(src).is_empty());
     }
 }
@@ -1,3 +1,4 @@
+use std::cell::Cell;
 use std::collections::VecDeque;
 use std::fmt::Formatter;
 
@@ -5,25 +6,120 @@ use itertools::Itertools;
 
 use crate::front::{ast, cst};
 use crate::front::cst::{Type, TypeInfo, TypeStore};
+use crate::front::error::{Error, Result, TypeOrigin};
 use crate::util::zip_eq;
 
 type VarTypeInfo<'ast> = cst::TypeInfo<'ast, TypeVar>;
 
 /// Represents the type of an expression in the program.
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub struct TypeVar(usize);
 
 #[derive(Debug)]
 struct VarState<'ast> {
     origin: Origin<'ast>,
     constraint: Constraint,
+
+    /// The parent in the union-find forest, the var itself if it's the root of its class.
+    parent: Cell<usize>,
+
+    //the fields below are only used for roots, they describe the entire class
+    /// The number of vars in the class.
+    size: usize,
     info: Option<VarTypeInfo<'ast>>,
+    /// Whether the class gets the void type if it's not inferred otherwise.
+    default_void: bool,
+}
+
+/// The TypeVars of a problem, grouped into classes of vars that are known to have the same type. This is a union-find
+/// structure with union by size and path compression, so merging and looking up classes takes near-constant time.
+#[derive(Debug, Default)]
+struct Vars<'ast> {
+    state: Vec<VarState<'ast>>,
+    unions: usize,
+}
+
+impl<'ast> Vars<'ast> {
+    fn push(&mut self, origin: Origin<'ast>, constraint: Constraint, info: Option<VarTypeInfo<'ast>>) -> TypeVar {
+        let i = self.state.len();
+        let default_void = constraint == Constraint::DefaultVoid;
+        self.state.push(VarState { origin, constraint, parent: Cell::new(i), size: 1, info, default_void });
+        TypeVar(i)
+    }
+
+    /// Find the root of the class of `var`.
+    fn find(&self, var: TypeVar) -> TypeVar {
+        let mut root = var.0;
+        while self.state[root].parent.get() != root {
+            root = self.state[root].parent.get();
+        }
+
+        //path compression: point everything on the way directly to the root
+        let mut curr = var.0;
+        while curr != root {
+            curr = self.state[curr].parent.replace(root);
+        }
+
+        TypeVar(root)
+    }
+
+    /// The info of the class of `var`.
+    fn info(&self, var: TypeVar) -> &Option<VarTypeInfo<'ast>> {
+        &self.state[self.find(var).0].info
+    }
+
+    fn set_info(&mut self, var: TypeVar, info: VarTypeInfo<'ast>) {
+        let root = self.find(var);
+        self.state[root.0].info = Some(info);
+    }
+
+    /// Merge the classes of `left` and `right`. If both have an info they must already have been unified, the info of
+    /// `left` is kept in that case.
+    fn union(&mut self, left: TypeVar, right: TypeVar) {
+        let left = self.find(left);
+        let right = self.find(right);
+        if left == right { return; }
+
+        let info = self.state[left.0].info.take().or_else(|| self.state[right.0].info.take());
+        let default_void = self.state[left.0].default_void || self.state[right.0].default_void;
+
+        //attach the smaller tree to the larger one to keep the trees shallow
+        let (root, child) = if self.state[left.0].size >= self.state[right.0].size { (left, right) } else { (right, left) };
+        self.state[child.0].parent.set(root.0);
+        self.state[child.0].info = None;
+
+        let child_size = self.state[child.0].size;
+        let root_state = &mut self.state[root.0];
+        root_state.size += child_size;
+        root_state.info = info;
+        root_state.default_void = default_void;
+
+        self.unions += 1;
+    }
+
+    fn is_root(&self, var: TypeVar) -> bool {
+        self.state[var.0].parent.get() == var.0
+    }
+}
+
+impl<'ast> std::ops::Index<TypeVar> for Vars<'ast> {
+    type Output = VarState<'ast>;
+
+    fn index(&self, index: TypeVar) -> &Self::Output {
+        &self.state[index.0]
+    }
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 enum Constraint {
     None,
     AnyInt,
+    AnyFloat,
+    AnyNumber,
+    /// Any type that can be compared with `==` and `!=`: numbers, bools and (optional) pointers.
+    AnyEq,
+    /// Any type that can be cast to the given type, see `TypeInfo::is_castable_to`.
+    CastableTo(Type),
     DefaultVoid,
 }
 
@@ -33,6 +129,34 @@ pub enum Origin<'ast> {
     Expression(&'ast ast::Expression),
     Declaration(&'ast ast::Declaration),
     ForIndex(&'ast ast::ForStatement),
+    /// A type written explicitly, eg. for a variable or a parameter.
+    Type(&'ast ast::Type),
+    /// The declared return type of the function.
+    ReturnType(&'ast ast::Type),
+}
+
+impl<'ast> Origin<'ast> {
+    /// The place in the source code this origin corresponds to, if any.
+    fn type_origin(self) -> Option<TypeOrigin<'ast>> {
+        match self {
+            Origin::FullyKnown => None,
+            Origin::Expression(expr) => Some(TypeOrigin::Expression(expr)),
+            Origin::Declaration(decl) => Some(TypeOrigin::Declaration(decl)),
+            Origin::ForIndex(for_stmt) => Some(TypeOrigin::ForIndex(for_stmt)),
+            Origin::Type(ty) => Some(TypeOrigin::Type(ty)),
+            Origin::ReturnType(ty) => Some(TypeOrigin::ReturnType(ty)),
+        }
+    }
+
+    /// How likely it is that a type with this origin is the one that's wrong in a mismatch. Written types and types
+    /// decided by the language are expected, the types of expressions are what actually happened.
+    fn actual_rank(self) -> u8 {
+        match self {
+            Origin::FullyKnown | Origin::Type(_) | Origin::ReturnType(_) => 0,
+            Origin::Declaration(_) | Origin::ForIndex(_) => 1,
+            Origin::Expression(_) => 2,
+        }
+    }
 }
 
 impl std::fmt::Debug for Origin<'_> {
@@ -42,6 +166,8 @@ impl std::fmt::Debug for Origin<'_> {
             Origin::Expression(a) => write!(f, "Origin::Expression({:?})", a.span),
             Origin::Declaration(a) => write!(f, "Origin::Declaration({:?})", a.span),
             Origin::ForIndex(a) => write!(f, "Origin::ForIndex({:?})", a.span),
+            Origin::Type(a) => write!(f, "Origin::Type({:?})", a.span),
+            Origin::ReturnType(a) => write!(f, "Origin::ReturnType({:?})", a.span),
         }
     }
 }
@@ -49,15 +175,21 @@ impl std::fmt::Debug for Origin<'_> {
 //TODO don't assert anywhere, return an error instead. look at unwrap, expect, panic, ...
 //TODO print out an instance once, to see how much duplicate noise there is
 pub struct TypeProblem<'ast> {
-    state: Vec<VarState<'ast>>,
+    vars: Vars<'ast>,
+    /// The number of solver iterations so far, see `SolverStats`.
+    iterations: usize,
 
     //constraints
     matches: VecDeque<(TypeVar, TypeVar)>,
     index_constraints: VecDeque<IndexConstraint<'ast>>,
+    call_constraints: VecDeque<CallConstraint>,
     add_sub_constraints: VecDeque<AddSubConstraint>,
+    operator_constraints: VecDeque<OperatorConstraint<'ast>>,
+    coerce_constraints: VecDeque<CoerceConstraint>,
 
     //basic types
     ty_void: TypeVar,
+    ty_never: TypeVar,
     ty_bool: TypeVar,
     ty_byte: TypeVar,
     ty_int: TypeVar,
@@ -65,15 +197,85 @@ pub struct TypeProblem<'ast> {
 
 pub struct TypeSolution {
     state: Vec<Type>,
+    stats: SolverStats,
+}
+
+/// Statistics about solving a `TypeProblem`, useful for profiling the solver.
+#[derive(Debug, Copy, Clone)]
+pub struct SolverStats {
+    /// The number of TypeVars.
+    pub vars: usize,
+    /// The number of classes of TypeVars with the same type that remained at the end.
+    pub classes: usize,
+    /// The number of times two classes were merged.
+    pub unions: usize,
+    /// The number of times the solver went through all constraints.
+    pub iterations: usize,
+}
+
+/// A match that can't be satisfied, turned into an `Error` once the solver has stopped.
+#[derive(Debug, Copy, Clone)]
+enum Conflict {
+    /// Two types that should match but don't, the left side of a match is the expected type.
+    Mismatch { expected: TypeVar, actual: TypeVar },
+    /// The type of `var` would have to contain itself to match `ty`.
+    Infinite { var: TypeVar, ty: TypeVar },
+    /// The type of `target` can't be indexed by the expression with type `result`, eg. because the field doesn't exist.
+    Index { target: TypeVar, result: TypeVar },
+    /// The builtin operator with result type `result` can't be applied to an operand of type `operand`.
+    Operand { operand: TypeVar, result: TypeVar },
+}
+
+impl Conflict {
+    /// A mismatch between inner types is reported as a mismatch of the outer types `outer` instead.
+    fn or_outer(self, outer: Conflict) -> Conflict {
+        match self {
+            Conflict::Mismatch { .. } => outer,
+            Conflict::Infinite { .. } | Conflict::Index { .. } | Conflict::Operand { .. } => self,
+        }
+    }
+}
+
+struct CallConstraint {
+    target: TypeVar,
+    args: Vec<TypeVar>,
+    result: TypeVar,
+}
+
+/// `from` must be usable as `to`, which is the case if they're equal or if `from` is a pointer and `to` is an
+/// optional pointer to the same type. If `widen` is set `from` can also be `byte` when `to` is `int`.
+struct CoerceConstraint {
+    from: TypeVar,
+    to: TypeVar,
+    widen: bool,
 }
 
 struct AddSubConstraint {
     left: TypeVar,
     right: TypeVar,
+    result: TypeVar,
+}
+
+/// A user defined implementation of a binary operator, see `ItemStore::operators`.
+#[derive(Debug, Copy, Clone)]
+pub struct OperatorOverload {
+    pub left: Type,
+    pub right: Type,
+    pub ret: Type,
+}
+
+struct OperatorConstraint<'ast> {
+    origin: Origin<'ast>,
+    kind: ast::BinaryOp,
+    left: TypeVar,
+    right: TypeVar,
+    result: TypeVar,
+    overloads: Vec<OperatorOverload>,
 }
 
 #[derive(Debug, Copy, Clone)]
 struct IndexConstraint<'ast> {
+    origin: Origin<'ast>,
     target: TypeVar,
     result: TypeVar,
     index: IndexKind<'ast>,
@@ -84,33 +286,33 @@ enum IndexKind<'ast> {
     Tuple(u32),
     Array,
     Struct(&'ast str),
-}
-
-impl IndexKind<'_> {
-    fn name(self) -> &'static str {
-        match self {
-            IndexKind::Tuple(_) => "tuple",
-            IndexKind::Array => "array",
-            IndexKind::Struct(_) => "struct",
-        }
-    }
+    /// Slicing an array or slice, the result is a slice.
+    Slice,
+    /// Iterating over an array or slice, the result is the element type.
+    Element,
 }
 
 impl<'ast> Default for TypeProblem<'ast> {
     fn default() -> Self {
         let mut problem = TypeProblem {
-            state: vec![],
+            vars: Default::default(),
+            iterations: 0,
             matches: Default::default(),
             index_constraints: Default::default(),
+            call_constraints: Default::default(),
             add_sub_constraints: Default::default(),
+            operator_constraints: Default::default(),
+            coerce_constraints: Default::default(),
 
             ty_void: TypeVar(usize::MAX),
+            ty_never: TypeVar(usize::MAX),
             ty_bool: TypeVar(usize::MAX),
             ty_byte: TypeVar(usize::MAX),
             ty_int: TypeVar(usize::MAX),
         };
 
         problem.ty_void = problem.known(Origin::FullyKnown, TypeInfo::Void);
+        problem.ty_never = problem.known(Origin::FullyKnown, TypeInfo::Never);
         problem.ty_bool = problem.known(Origin::FullyKnown, TypeInfo::Bool);
         problem.ty_byte = problem.known(Origin::FullyKnown, TypeInfo::Byte);
         problem.ty_int = problem.known(Origin::FullyKnown, TypeInfo::Int);
@@ -121,23 +323,25 @@ impl<'ast> Default for TypeProblem<'ast> {
 
 impl<'ast> TypeProblem<'ast> {
     /// The current amount of `TypeVar`s defined in this problem.
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
-        self.state.len()
+        self.vars.state.len()
     }
 
     fn new_var(&mut self, origin: Origin<'ast>, constraint: Constraint, info: Option<VarTypeInfo<'ast>>) -> TypeVar {
         // Some(Wildcard) means that we don't know anything about a type, so convert it to None
         let info = info.filter(|info| info != &VarTypeInfo::Wildcard);
-
-        let i = self.state.len();
-        self.state.push(VarState { origin, constraint, info });
-        TypeVar(i)
+        self.vars.push(origin, constraint, info)
     }
 
     pub fn ty_void(&self) -> TypeVar {
         self.ty_void
     }
 
+    pub fn ty_never(&self) -> TypeVar {
+        self.ty_never
+    }
+
     pub fn ty_bool(&self) -> TypeVar {
         self.ty_bool
     }
@@ -166,132 +370,410 @@ impl<'ast> TypeProblem<'ast> {
         self.new_var(origin, Constraint::AnyInt, None)
     }
 
+    /// Create a new TypeVar that can be assigned any float type.
+    pub fn unknown_float(&mut self, origin: Origin<'ast>) -> TypeVar {
+        self.new_var(origin, Constraint::AnyFloat, None)
+    }
+
+    /// Create a new TypeVar that can be assigned any integer or float type.
+    pub fn unknown_number(&mut self, origin: Origin<'ast>) -> TypeVar {
+        self.new_var(origin, Constraint::AnyNumber, None)
+    }
+
+    /// Create a new TypeVar that can be assigned any type that supports equality comparisons.
+    pub fn unknown_eq(&mut self, origin: Origin<'ast>) -> TypeVar {
+        self.new_var(origin, Constraint::AnyEq, None)
+    }
+
+    /// Create a new TypeVar that can be assigned any type that can be cast to `target`.
+    pub fn unknown_castable_to(&mut self, origin: Origin<'ast>, target: Type) -> TypeVar {
+        self.new_var(origin, Constraint::CastableTo(target), None)
+    }
+
     /// Create a new TypeVar with a known type pattern
     pub fn known(&mut self, origin: Origin<'ast>, info: VarTypeInfo<'ast>) -> TypeVar {
         self.new_var(origin, Constraint::None, Some(info))
     }
 
-    /// Create a new TypeVar with a fully known type.
-    pub fn fully_known(&mut self, types: &cst::TypeStore<'ast>, ty: Type) -> TypeVar {
+    /// Create a new TypeVar with a fully known type, the vars for the inner types share the same origin.
+    pub fn fully_known(&mut self, origin: Origin<'ast>, types: &cst::TypeStore<'ast>, ty: Type) -> TypeVar {
         let info = types[ty].map_ty(&mut |&child_ty| {
-            self.fully_known(types, child_ty)
+            self.fully_known(origin, types, child_ty)
         });
-        self.known(Origin::FullyKnown, info)
+        self.known(origin, info)
     }
 
     /// Create a new TypeVar representing the type of a tuple index expression.
     pub fn tuple_index(&mut self, origin: Origin<'ast>, target: TypeVar, index: u32) -> TypeVar {
         let result = self.unknown(origin);
-        self.index_constraints.push_back(IndexConstraint { target, result, index: IndexKind::Tuple(index) });
+        self.index_constraints.push_back(IndexConstraint { origin, target, result, index: IndexKind::Tuple(index) });
         result
     }
 
     /// Create a new TypeVar representing the type of a struct index expression.
     pub fn struct_index(&mut self, origin: Origin<'ast>, target: TypeVar, index: &'ast str) -> TypeVar {
         let result = self.unknown(origin);
-        self.index_constraints.push_back(IndexConstraint { target, result, index: IndexKind::Struct(index) });
+        self.index_constraints.push_back(IndexConstraint { origin, target, result, index: IndexKind::Struct(index) });
+        result
+    }
+
+    /// Create a new TypeVar representing the result type of a slice expression.
+    pub fn slice(&mut self, origin: Origin<'ast>, target: TypeVar) -> TypeVar {
+        let result = self.unknown(origin);
+        self.index_constraints.push_back(IndexConstraint { origin, target, result, index: IndexKind::Slice });
         result
     }
 
     /// Create a new TypeVar representing the result type of an array index expression.
     pub fn array_index(&mut self, origin: Origin<'ast>, target: TypeVar) -> TypeVar {
         let result = self.unknown(origin);
-        self.index_constraints.push_back(IndexConstraint { target, result, index: IndexKind::Array });
+        self.index_constraints.push_back(IndexConstraint { origin, target, result, index: IndexKind::Array });
         result
     }
 
-    /// Require that two types match
+    /// Create a new TypeVar representing the element type of the array or slice a for loop iterates over.
+    pub fn element(&mut self, origin: Origin<'ast>, target: TypeVar) -> TypeVar {
+        let result = self.unknown(origin);
+        self.index_constraints.push_back(IndexConstraint { origin, target, result, index: IndexKind::Element });
+        result
+    }
+
+    /// Create a new TypeVar representing the result type of calling `target`, which can be a function or a closure.
+    pub fn call(&mut self, origin: Origin<'ast>, target: TypeVar, args: Vec<TypeVar>) -> TypeVar {
+        let result = self.unknown(origin);
+        self.call_constraints.push_back(CallConstraint { target, args, result });
+        result
+    }
+
+    /// Require that two types match. If they don't `left` is reported as the expected type, unless only `right` has
+    /// a written or builtin type.
     pub fn equal(&mut self, left: TypeVar, right: TypeVar) {
         self.matches.push_back((left, right))
     }
 
-    /// Require the following:
-    /// * if `left` is an integer type `right` should be the same type
-    /// * if `left` is a pointer type `right` should be the type Int
-    pub fn add_sub_constraint(&mut self, left: TypeVar, right: TypeVar) {
-        self.add_sub_constraints.push_back(AddSubConstraint { left, right });
+    /// Require that a value of type `from` can be used where a value of type `to` is expected. This is the same as
+    /// `equal` except that pointers implicitly convert to optional pointers and pointers to arrays decay to slices
+    /// and to pointers to their first element.
+    pub fn coerce(&mut self, from: TypeVar, to: TypeVar) {
+        self.coerce_constraints.push_back(CoerceConstraint { from, to, widen: false })
+    }
+
+    /// The same as `coerce`, but additionally allows `byte` values to be implicitly widened to `int`.
+    fn widen(&mut self, from: TypeVar, to: TypeVar) {
+        self.coerce_constraints.push_back(CoerceConstraint { from, to, widen: true })
+    }
+
+    /// Create a new TypeVar representing the result of adding or subtracting `left` and `right`:
+    /// * if `left` is an integer or float type `right` should be the same type, except that `byte` is widened to `int`
+    /// * if `left` is a pointer type `right` should be the type Int and the result is the pointer type
+    pub fn add_sub_constraint(&mut self, origin: Origin<'ast>, left: TypeVar, right: TypeVar) -> TypeVar {
+        let result = self.unknown(origin);
+        self.add_sub_constraints.push_back(AddSubConstraint { left, right, result });
+        result
+    }
+
+    /// Create a new TypeVar with the given constraint that both `left` and `right` are widened to.
+    fn widened_operands(&mut self, origin: Origin<'ast>, constraint: Constraint, left: TypeVar, right: TypeVar) -> TypeVar {
+        let value_ty = self.new_var(origin, constraint, None);
+        self.widen(left, value_ty);
+        self.widen(right, value_ty);
+        value_ty
+    }
+
+    /// Create a new TypeVar representing the result type of a binary operator expression. If `left` turns out to be
+    /// a struct or `str` type with a matching overload that one is used, otherwise the builtin operator is.
+    pub fn binary(
+        &mut self,
+        origin: Origin<'ast>,
+        kind: ast::BinaryOp,
+        left: TypeVar,
+        right: TypeVar,
+        overloads: Vec<OperatorOverload>,
+    ) -> TypeVar {
+        if overloads.is_empty() {
+            return self.builtin_binary(origin, kind, left, right);
+        }
+
+        let result = self.unknown(origin);
+        self.operator_constraints.push_back(OperatorConstraint { origin, kind, left, right, result, overloads });
+        result
+    }
+
+    fn builtin_binary(&mut self, origin: Origin<'ast>, kind: ast::BinaryOp, left: TypeVar, right: TypeVar) -> TypeVar {
+        match kind {
+            ast::BinaryOp::Add | ast::BinaryOp::Sub => {
+                self.add_sub_constraint(origin, left, right)
+            }
+            ast::BinaryOp::Mul | ast::BinaryOp::Div => {
+                self.widened_operands(origin, Constraint::AnyNumber, left, right)
+            }
+            ast::BinaryOp::Mod => {
+                self.widened_operands(origin, Constraint::AnyInt, left, right)
+            }
+            ast::BinaryOp::Eq | ast::BinaryOp::Neq => {
+                self.widened_operands(origin, Constraint::AnyEq, left, right);
+                self.ty_bool()
+            }
+            ast::BinaryOp::Gte | ast::BinaryOp::Gt | ast::BinaryOp::Lte | ast::BinaryOp::Lt => {
+                self.widened_operands(origin, Constraint::AnyNumber, left, right);
+                self.ty_bool()
+            }
+        }
     }
 }
 
 /// Solver implementation
 impl<'ast> TypeProblem<'ast> {
-    pub fn solve(mut self, types: &mut TypeStore<'ast>) -> TypeSolution {
+    pub fn solve(mut self, types: &mut TypeStore<'ast>) -> Result<'ast, TypeSolution> {
         //main solver loop
         loop {
-            let progress = self.solve_iter(types);
-            if !progress { break; }
+            self.iterations += 1;
+            let progress = self.solve_iter(types)
+                .map_err(|conflict| self.conflict_error(types, conflict))?;
+            if !progress
+                && !self.default_coerce_constraints()
+                && !self.default_operator_constraints()
+                && !self.default_never_matches()
+                && !self.default_number_constraints() { break; }
         }
 
         //map types back to cst types (and check that all types were indeed inferred)
-        let state = (0..self.state.len()).map(|i| {
+        let mut solved_roots = vec![None; self.len()];
+        let state = (0..self.len()).map(|i| -> Result<'ast, Type> {
             let var = TypeVar(i);
-            let ty = self.get_solution(types, var);
-
-            //check that integer requirements are satisfied
-            if self.state[i].constraint == Constraint::AnyInt {
-                let info = &types[ty];
-
-                match info {
-                    TypeInfo::Byte | TypeInfo::Int => {}
-                    _ => panic!(
-                        "Type for {:?} with origin \n{:?}\nshould be an integer, but was\n{:?}\n",
-                        var, self.state[var.0].origin, info,
-                    ),
+            let ty = self.get_solution(types, &mut solved_roots, var);
+
+            //check that integer and float requirements are satisfied
+            let info = &types[ty];
+            let (satisfied, expected) = match self.vars[var].constraint {
+                _ if matches!(info, TypeInfo::Never) => (true, ""),
+                Constraint::None | Constraint::DefaultVoid => (true, ""),
+                Constraint::AnyInt => (matches!(info, TypeInfo::Byte | TypeInfo::Int), "an integer"),
+                Constraint::AnyFloat => (matches!(info, TypeInfo::Float { .. }), "a float"),
+                Constraint::AnyNumber => (matches!(info, TypeInfo::Byte | TypeInfo::Int | TypeInfo::Float { .. }), "a number"),
+                Constraint::AnyEq => (
+                    matches!(info, TypeInfo::Bool | TypeInfo::Byte | TypeInfo::Int | TypeInfo::Float { .. } | TypeInfo::Pointer(_) | TypeInfo::OptionalPointer(_)),
+                    "comparable",
+                ),
+                Constraint::CastableTo(target) => (info.is_castable_to(&types[target]), "castable"),
+            };
+
+            if !satisfied {
+                //casts and builtin operators on the wrong type are only detected here, once the types are known
+                match (self.vars[var].constraint, self.vars[var].origin) {
+                    (Constraint::CastableTo(target), Origin::Expression(expression)) => return Err(Error::InvalidCast {
+                        expression,
+                        source: types.format_type(ty).to_string(),
+                        target: types.format_type(target).to_string(),
+                    }),
+                    (_, Origin::Expression(expression)) if matches!(expression.kind, ast::ExpressionKind::Binary { .. }) =>
+                        return Err(Error::InvalidOperandType { expression, ty: types.format_type(ty).to_string() }),
+                    _ => {}
                 }
+
+                panic!(
+                    "Type for {:?} with origin \n{:?}\nshould be {}, but was\n{:?}\n",
+                    var, self.vars[var].origin, expected, info,
+                )
+            }
+
+            Ok(ty)
+        }).try_collect()?;
+
+        let stats = SolverStats {
+            vars: self.len(),
+            classes: (0..self.len()).filter(|&i| self.vars.is_root(TypeVar(i))).count(),
+            unions: self.vars.unions,
+            iterations: self.iterations,
+        };
+
+        Ok(TypeSolution { state, stats })
+    }
+
+    fn conflict_error(&self, types: &mut TypeStore<'ast>, conflict: Conflict) -> Error<'ast> {
+        let (mut expected, mut actual) = match conflict {
+            Conflict::Mismatch { expected, actual } => (expected, actual),
+            Conflict::Infinite { var, ty } => {
+                let span = match (self.vars[var].origin.type_origin(), self.vars[ty].origin.type_origin()) {
+                    (Some(origin), _) | (None, Some(origin)) => origin.span(),
+                    (None, None) => panic!("Infinite type without origin: {:?}, {:?}={:?}", var, ty, self.vars.info(ty)),
+                };
+                return Error::InfiniteType { span, ty: self.format_var(types, ty) };
+            }
+            Conflict::Index { target, result } => return self.index_error(types, target, result),
+            Conflict::Operand { operand, result } => {
+                let expression = match self.vars[result].origin {
+                    Origin::Expression(expression) => expression,
+                    origin => panic!("Operator without expression origin: {:?}", origin),
+                };
+                return Error::InvalidOperandType { expression, ty: self.format_var(types, operand) };
             }
+        };
+
+        if self.vars[expected].origin.actual_rank() > self.vars[actual].origin.actual_rank() {
+            std::mem::swap(&mut expected, &mut actual);
+        }
 
-            ty
-        }).collect_vec();
+        let expected_origin = self.vars[expected].origin.type_origin();
+        let actual_origin = self.vars[actual].origin.type_origin();
+
+        //point at the actual type if possible, and at the expected type as well if it's somewhere else
+        let (span, expected_origin) = match (actual_origin, expected_origin) {
+            (Some(actual), expected) => (actual.span(), expected.filter(|expected| expected.span() != actual.span())),
+            (None, Some(expected)) => (expected.span(), None),
+            (None, None) => panic!(
+                "Type mismatch without origin: {:?}={:?}, {:?}={:?}",
+                expected, self.vars.info(expected), actual, self.vars.info(actual),
+            ),
+        };
+
+        Error::TypeMismatch {
+            span,
+            expected: self.format_var(types, expected),
+            actual: self.format_var(types, actual),
+            expected_origin,
+        }
+    }
+
+    /// The error for an index expression with type `result` that doesn't fit the type of its target `target`.
+    fn index_error(&self, types: &mut TypeStore<'ast>, target: TypeVar, result: TypeVar) -> Error<'ast> {
+        let has_fields = matches!(self.vars.info(target), Some(TypeInfo::Struct(_) | TypeInfo::Str | TypeInfo::Slice(_)));
+        let target_type = self.format_var(types, target);
+
+        match self.vars[result].origin {
+            Origin::Expression(expr) => match &expr.kind {
+                ast::ExpressionKind::DotIndex { target, index: ast::DotIndexIndex::Struct(id) } if has_fields =>
+                    Error::StructFieldNotFound { target, target_type, index: id },
+                ast::ExpressionKind::DotIndex { target, index } =>
+                    Error::WrongDotIndexType { target, target_type, index },
+                ast::ExpressionKind::ArrayIndex { target, index: _ } |
+                ast::ExpressionKind::Slice { target, start: _, end: _ } =>
+                    Error::NotIndexable { target, target_type },
+                _ => panic!("Index constraint for non-index expression {:?}", expr),
+            },
+            Origin::ForIndex(ast::ForStatement { range: ast::ForRange::Array(target), .. }) =>
+                Error::NotIterable { target, target_type },
+            origin => panic!("Index constraint without expression origin: {:?}", origin),
+        }
+    }
 
-        TypeSolution { state }
+    /// Format the type inferred so far for the given TypeVar, with `_` for the parts that are still unknown.
+    fn format_var(&self, types: &mut TypeStore<'ast>, var: TypeVar) -> String {
+        let ty = self.partial_solution(types, var);
+        types.format_type(ty).to_string()
+    }
+
+    fn partial_solution(&self, types: &mut TypeStore<'ast>, var: TypeVar) -> Type {
+        match self.vars.info(var) {
+            Some(info) => {
+                let info = info.map_ty(&mut |&var| self.partial_solution(types, var));
+                types.define_type(info)
+            }
+            None => types.define_type(TypeInfo::Wildcard),
+        }
     }
 
     /// Run a single iteration of the solver, returns whether any progress was made.
-    fn solve_iter(&mut self, types: &mut TypeStore<'ast>) -> bool {
-        self.apply_index_constraints(types);
-        self.apply_add_sub_constraints();
+    fn solve_iter(&mut self, types: &mut TypeStore<'ast>) -> std::result::Result<bool, Conflict> {
+        self.apply_index_constraints(types)?;
+        self.apply_call_constraints();
+        self.apply_add_sub_constraints()?;
+        self.apply_operator_constraints(types);
+        let mut progress = self.apply_coerce_constraints();
 
         //process all currently known matches
         // new ones (or ones that need to be kept) are appended to self.matches
         // they will be processed during the next iteration
         let matches = std::mem::take(&mut self.matches);
-        let mut progress = false;
         for (left, right) in matches {
-            progress |= self.unify_var(left, right);
+            progress |= self.unify_var(left, right)?;
         }
-        progress
+        Ok(progress)
     }
 
-    fn apply_index_constraints(&mut self, types: &mut TypeStore<'ast>) {
+    fn apply_index_constraints(&mut self, types: &mut TypeStore<'ast>) -> std::result::Result<(), Conflict> {
         let mut temp = std::mem::take(&mut self.index_constraints);
+        let mut conflict = None;
 
-        temp.retain(|&IndexConstraint { target, result, index }| {
-            let target = if let Some(target) = &self.state[target.0].info {
+        temp.retain(|&IndexConstraint { origin, target: target_var, result, index }| {
+            let target = if let Some(target) = self.vars.info(target_var) {
                 target
             } else {
                 //we don't know the target type yet, so we can't make progress
                 return true;
             };
 
+            //keep the constraint around if it can't be applied, the solver stops with the conflict anyway
+            let index_conflict = Conflict::Index { target: target_var, result };
+
             match (target, index) {
                 (TypeInfo::Tuple(target), IndexKind::Tuple(index)) => {
-                    let target_result = target.fields.get(index as usize)
-                        .expect("Tuple index out of bounds");
-                    self.matches.push_back((*target_result, result))
+                    match target.fields.get(index as usize) {
+                        Some(&target_result) => self.matches.push_back((target_result, result)),
+                        None => {
+                            conflict.get_or_insert(index_conflict);
+                            return true;
+                        }
+                    }
                 }
                 (TypeInfo::Array(target), IndexKind::Array) => {
                     let target_result = target.inner;
                     self.matches.push_back((target_result, result))
                 }
+                (TypeInfo::Array(target), IndexKind::Element) => {
+                    let target_result = target.inner;
+                    self.matches.push_back((target_result, result))
+                }
+                (&TypeInfo::Slice(inner), IndexKind::Array | IndexKind::Element) |
+                (&TypeInfo::Pointer(inner), IndexKind::Array) => {
+                    self.matches.push_back((inner, result))
+                }
+                (TypeInfo::Array(target), IndexKind::Slice) => {
+                    let slice = self.known(origin, TypeInfo::Slice(target.inner));
+                    self.matches.push_back((slice, result))
+                }
+                (TypeInfo::Slice(_), IndexKind::Slice) => {
+                    self.matches.push_back((target_var, result))
+                }
                 (TypeInfo::Struct(target), IndexKind::Struct(index)) => {
-                    let field_idx = target.find_field_index(index)
-                        .unwrap_or_else(|| panic!("Struct {:?} does not have field {}", target, index));
-                    let field_ty = target.fields[field_idx as usize].ty;
-
-                    let known_ty = self.fully_known(types, field_ty);
+                    let field_ty = match target.find_field_index(index) {
+                        Some(field_idx) => target.fields[field_idx as usize].ty,
+                        None => {
+                            conflict.get_or_insert(index_conflict);
+                            return true;
+                        }
+                    };
+
+                    let known_ty = self.fully_known(origin, types, field_ty);
+                    self.matches.push_back((result, known_ty));
+                }
+                (TypeInfo::Str, IndexKind::Struct(index)) => {
+                    let field_ty = match types.fat_pointer_field(types.type_str(), index) {
+                        Some((_, field_ty)) => field_ty,
+                        None => {
+                            conflict.get_or_insert(index_conflict);
+                            return true;
+                        }
+                    };
+
+                    let known_ty = self.fully_known(origin, types, field_ty);
                     self.matches.push_back((result, known_ty));
                 }
-                (_, _) => panic!("Expected {} type, got {:?}", index.name(), target),
+                (&TypeInfo::Slice(inner), IndexKind::Struct(index)) => {
+                    let field_ty = match index {
+                        "ptr" => self.known(origin, TypeInfo::Pointer(inner)),
+                        "len" => self.ty_int,
+                        _ => {
+                            conflict.get_or_insert(index_conflict);
+                            return true;
+                        }
+                    };
+                    self.matches.push_back((result, field_ty));
+                }
+                (_, _) => {
+                    conflict.get_or_insert(index_conflict);
+                    return true;
+                }
             }
 
             //we applied this constraint, it can now be removed
@@ -300,83 +782,302 @@ impl<'ast> TypeProblem<'ast> {
 
         assert!(self.index_constraints.is_empty());
         self.index_constraints = temp;
+        conflict.map_or(Ok(()), Err)
     }
 
-    fn apply_add_sub_constraints(&mut self) {
+    fn apply_call_constraints(&mut self) {
+        let mut temp = std::mem::take(&mut self.call_constraints);
+
+        temp.retain(|CallConstraint { target, args, result }| {
+            let info = match self.vars.info(*target) {
+                Some(TypeInfo::Function(info)) | Some(TypeInfo::Closure(info)) => info,
+                Some(info) => panic!("Expected function or closure type for call target, got {:?}", info),
+                //we don't know the target type yet, so we can't make progress
+                None => return true,
+            };
+
+            assert_eq!(info.params.len(), args.len(), "call must have the same number of arguments as parameters");
+            for (&param, &arg) in zip_eq(&info.params, args) {
+                self.coerce_constraints.push_back(CoerceConstraint { from: arg, to: param, widen: true });
+            }
+            self.matches.push_back((info.ret, *result));
+
+            false
+        });
+
+        assert!(self.call_constraints.is_empty());
+        self.call_constraints = temp;
+    }
+
+    fn apply_add_sub_constraints(&mut self) -> std::result::Result<(), Conflict> {
         let mut temp = std::mem::take(&mut self.add_sub_constraints);
+        let mut conflict = None;
+
+        temp.retain(|&AddSubConstraint { left, right, result }| {
+            //the left type decides, but a known result type also tells us whether this is pointer arithmetic
+            let left_info = match (self.vars.info(left), self.vars.info(result)) {
+                (Some(info), _) | (None, Some(info)) => info,
+                (None, None) => return true,
+            };
 
-        temp.retain(|&AddSubConstraint { left, right }| {
-            let left_info = if let Some(left) = &self.state[left.0].info {
+            match *left_info {
+                TypeInfo::Int | TypeInfo::Byte | TypeInfo::Float { .. } => {
+                    self.widen(left, result);
+                    self.widen(right, result);
+                }
+                TypeInfo::Pointer(_) => {
+                    self.matches.push_back((right, self.ty_int));
+                    self.matches.push_back((left, result));
+                }
+                //keep the constraint around, the solver stops with the conflict anyway
+                _ => {
+                    conflict.get_or_insert(Conflict::Operand { operand: left, result });
+                    return true;
+                }
+            };
+
+            false
+        });
+
+        assert!(self.add_sub_constraints.is_empty());
+        self.add_sub_constraints = temp;
+        conflict.map_or(Ok(()), Err)
+    }
+
+    fn apply_operator_constraints(&mut self, types: &TypeStore<'ast>) {
+        let mut temp = std::mem::take(&mut self.operator_constraints);
+
+        temp.retain(|constraint| {
+            let left_info = if let Some(left) = self.vars.info(constraint.left) {
                 left
             } else {
                 return true;
             };
 
-            let required_right_ty = match left_info {
-                &TypeInfo::Int => TypeInfo::Int,
-                &TypeInfo::Byte => TypeInfo::Byte,
-                &TypeInfo::Pointer(_) => TypeInfo::Int,
-                _ => panic!(
-                    "Expected either pointer type or integer type for {:?} at {:?}, got {:?}",
-                    left, self.state[0].origin, left_info
-                )
+            let overload = match left_info {
+                TypeInfo::Struct(left_info) => constraint.overloads.iter().copied().find(|overload| {
+                    matches!(&types[overload.left], TypeInfo::Struct(info) if info == left_info)
+                }),
+                TypeInfo::Str => constraint.overloads.iter().copied().find(|overload| {
+                    matches!(&types[overload.left], TypeInfo::Str)
+                }),
+                _ => None,
             };
 
-            let right_match = self.known(Origin::FullyKnown, required_right_ty);
-            self.matches.push_back((right, right_match));
+            match overload {
+                Some(overload) => {
+                    let right = self.fully_known(constraint.origin, types, overload.right);
+                    let ret = self.fully_known(constraint.origin, types, overload.ret);
+                    self.matches.push_back((constraint.right, right));
+                    self.matches.push_back((constraint.result, ret));
+                }
+                None => self.apply_builtin_operator(constraint),
+            }
 
             false
         });
 
-        assert!(self.add_sub_constraints.is_empty());
-        self.add_sub_constraints = temp;
+        assert!(self.operator_constraints.is_empty());
+        self.operator_constraints = temp;
+    }
+
+    /// Apply the coerce constraints for which enough is known to decide between a plain match and a pointer to
+    /// optional pointer conversion. Returns whether any constraints were applied.
+    fn apply_coerce_constraints(&mut self) -> bool {
+        let mut temp = std::mem::take(&mut self.coerce_constraints);
+        let before = temp.len();
+
+        temp.retain(|&CoerceConstraint { from, to, widen }| {
+            //whether the type can be the target or the source of a conversion
+            let convert_to = |info: &VarTypeInfo| matches!(info, TypeInfo::Pointer(_) | TypeInfo::OptionalPointer(_) | TypeInfo::Slice(_)) || (widen && info == &TypeInfo::Int);
+            let convert_from = |info: &VarTypeInfo| matches!(info, TypeInfo::Pointer(_)) || (widen && info == &TypeInfo::Byte);
+
+            match (self.vars.info(from), self.vars.info(to)) {
+                (&Some(TypeInfo::Pointer(from_inner)), &Some(TypeInfo::OptionalPointer(to_inner))) =>
+                    self.matches.push_back((to_inner, from_inner)),
+                //a pointer to an array converts to a slice
+                (&Some(TypeInfo::Pointer(from_inner)), &Some(TypeInfo::Slice(to_inner))) => {
+                    match self.vars.info(from_inner) {
+                        Some(TypeInfo::Array(from_info)) => self.matches.push_back((to_inner, from_info.inner)),
+                        Some(_) => self.matches.push_back((to, from)),
+                        None => return true,
+                    }
+                }
+                //a pointer to an array decays to a pointer to its first element
+                (&Some(TypeInfo::Pointer(from_inner)), &Some(TypeInfo::Pointer(to_inner))) => {
+                    match (self.vars.info(from_inner), self.vars.info(to_inner)) {
+                        (Some(TypeInfo::Array(from_info)), Some(to_info)) if !matches!(to_info, TypeInfo::Array(_)) =>
+                            self.matches.push_back((to_inner, from_info.inner)),
+                        (None, _) | (Some(TypeInfo::Array(_)), None) => return true,
+                        _ => self.matches.push_back((to, from)),
+                    }
+                }
+                (Some(TypeInfo::Byte), Some(TypeInfo::Int)) if widen => {}
+                (_, Some(to_info)) if !convert_to(to_info) =>
+                    self.matches.push_back((to, from)),
+                (Some(from_info), _) if !convert_from(from_info) =>
+                    self.matches.push_back((to, from)),
+                //we don't know whether a conversion is needed yet
+                _ => return true,
+            }
+
+            false
+        });
+
+        let progress = temp.len() != before;
+        assert!(self.coerce_constraints.is_empty());
+        self.coerce_constraints = temp;
+        progress
+    }
+
+    /// Coercions that are still undecided when nothing else can be inferred become plain matches.
+    /// Returns whether there were any such coercions.
+    fn default_coerce_constraints(&mut self) -> bool {
+        let temp = std::mem::take(&mut self.coerce_constraints);
+        let any = !temp.is_empty();
+
+        for CoerceConstraint { from, to, widen: _ } in temp {
+            self.matches.push_back((to, from));
+        }
+
+        any
+    }
+
+    /// Fall back to the builtin operators for operands whose type could not be inferred otherwise, these can't be
+    /// structs since struct types are never inferred from nothing. Returns whether there were any such operators.
+    fn default_operator_constraints(&mut self) -> bool {
+        let temp = std::mem::take(&mut self.operator_constraints);
+        let any = !temp.is_empty();
+
+        for constraint in &temp {
+            self.apply_builtin_operator(constraint);
+        }
+
+        any
+    }
+
+    fn apply_builtin_operator(&mut self, constraint: &OperatorConstraint<'ast>) {
+        let result = self.builtin_binary(constraint.origin, constraint.kind, constraint.left, constraint.right);
+        self.matches.push_back((constraint.result, result));
     }
 
     /// Get the type inferred for the given TypeVar.
-    fn get_solution(&self, types: &mut TypeStore<'ast>, var: TypeVar) -> Type {
-        let state = &self.state[var.0];
-        if let Some(info) = &state.info {
-            let info = info.map_ty(&mut |&var| self.get_solution(types, var));
+    /// `solved_roots` caches the type of each class, indexed by its root.
+    fn get_solution(&self, types: &mut TypeStore<'ast>, solved_roots: &mut Vec<Option<Type>>, var: TypeVar) -> Type {
+        let root = self.vars.find(var);
+        if let Some(ty) = solved_roots[root.0] {
+            return ty;
+        }
+
+        let state = &self.vars[root];
+        let ty = if let Some(info) = &state.info {
+            let info = info.map_ty(&mut |&var| self.get_solution(types, solved_roots, var));
             types.define_type(info)
-        } else if state.constraint == Constraint::DefaultVoid {
+        } else if state.default_void {
             types.type_void()
         } else {
-            panic!("Failed to infer type for {:?} with origin {:?}", var, self.state[var.0].origin)
+            panic!("Failed to infer type for {:?} with origin {:?}", var, self.vars[var].origin)
+        };
+
+        solved_roots[root.0] = Some(ty);
+        ty
+    }
+
+    /// Vars that are only matched with never types can't be inferred otherwise, so they're never types themselves.
+    /// Returns whether any such vars were found.
+    fn default_never_matches(&mut self) -> bool {
+        let mut progress = false;
+
+        for &(left, right) in &self.matches {
+            let unknown = match (self.vars.info(left), self.vars.info(right)) {
+                (Some(TypeInfo::Never), None) => right,
+                (None, Some(TypeInfo::Never)) => left,
+                _ => continue,
+            };
+
+            self.vars.set_info(unknown, TypeInfo::Never);
+            progress = true;
         }
+
+        progress
+    }
+
+    /// Integer and float vars that could not be inferred otherwise, eg. literals that are only assigned to an untyped
+    /// local or compared with each other, default to `int` and `f64`. Returns whether any such vars were found.
+    fn default_number_constraints(&mut self) -> bool {
+        let mut progress = false;
+
+        for i in 0..self.len() {
+            let var = TypeVar(i);
+            if self.vars.info(var).is_some() { continue; }
+
+            let info = match self.vars[var].constraint {
+                Constraint::AnyInt => TypeInfo::Int,
+                Constraint::AnyFloat => TypeInfo::Float { bits: 64 },
+                _ => continue,
+            };
+            self.vars.set_info(var, info);
+            progress = true;
+        }
+
+        progress
     }
 
     /// Apply the requirement that both TypeVars match. Returns whether any progress was made.
-    fn unify_var(&mut self, left: TypeVar, right: TypeVar) -> bool {
-        //nothing to do, skip. also doesn't count as progress.
-        if left == right { return false; }
-
-        match (&self.state[left.0].info, &self.state[right.0].info) {
-            (None, None) => {
-                // we don't know enough to apply this match, so just keep it
-                self.matches.push_back((left, right));
-                false
-            }
-            (Some(left), None) => {
-                self.state[right.0].info = Some(left.clone());
-                true
-            }
-            (None, Some(right)) => {
-                self.state[left.0].info = Some(right.clone());
-                true
-            }
-            (Some(_), Some(_)) => {
-                self.unify_both_known(left, right);
-                true
+    fn unify_var(&mut self, left: TypeVar, right: TypeVar) -> std::result::Result<bool, Conflict> {
+        //already in the same class, nothing to do. also doesn't count as progress.
+        if self.vars.find(left) == self.vars.find(right) { return Ok(false); }
+
+        match (self.vars.info(left), self.vars.info(right)) {
+            //never matches anything, so it doesn't tell us anything about the other type
+            (Some(TypeInfo::Never), other) | (other, Some(TypeInfo::Never)) => {
+                if other.is_none() {
+                    //keep the match around in case the other type is never inferred otherwise
+                    self.matches.push_back((left, right));
+                }
+                return Ok(false);
             }
+            (Some(_), Some(_)) => self.unify_both_known(left, right)?,
+            //the class without info can't end up containing itself
+            (None, Some(_)) => self.occurs_check(left, right)?,
+            (Some(_), None) => self.occurs_check(right, left)?,
+            (None, None) => {}
+        }
+
+        self.vars.union(left, right);
+        Ok(true)
+    }
+
+    /// Check that the class of `var` doesn't occur within the type of `ty`, otherwise matching them would create an
+    /// infinite type, eg. a pointer to itself.
+    fn occurs_check(&self, var: TypeVar, ty: TypeVar) -> std::result::Result<(), Conflict> {
+        if self.occurs(self.vars.find(var), ty) {
+            Err(Conflict::Infinite { var, ty })
+        } else {
+            Ok(())
         }
     }
 
-    /// Util function for `unify_var` that assumes both vars have known infos.
-    fn unify_both_known(&mut self, left: TypeVar, right: TypeVar) {
+    fn occurs(&self, root: TypeVar, ty: TypeVar) -> bool {
+        if self.vars.find(ty) == root {
+            return true;
+        }
+
+        let mut found = false;
+        if let Some(info) = self.vars.info(ty) {
+            info.map_ty(&mut |&child| found = found || self.occurs(root, child));
+        }
+        found
+    }
+
+    /// Util function for `unify_var` that assumes both vars have known infos. A mismatch between inner types is
+    /// reported as a mismatch between `left` and `right` themselves.
+    fn unify_both_known(&mut self, left: TypeVar, right: TypeVar) -> std::result::Result<(), Conflict> {
         //TODO how to avoid cloning in this function?
 
-        let left_info = self.state[left.0].info.as_ref().unwrap();
-        let right_info = self.state[right.0].info.as_ref().unwrap();
+        let mismatch = Conflict::Mismatch { expected: left, actual: right };
+        let left_info = self.vars.info(left).as_ref().unwrap();
+        let right_info = self.vars.info(right).as_ref().unwrap();
 
         match (left_info, right_info) {
             (TypeInfo::Placeholder(_), _) | (_, TypeInfo::Placeholder(_)) => panic!("placeholder"),
@@ -385,47 +1086,61 @@ impl<'ast> TypeProblem<'ast> {
             (TypeInfo::Bool, TypeInfo::Bool) => {}
             (TypeInfo::Byte, TypeInfo::Byte) => {}
             (TypeInfo::Int, TypeInfo::Int) => {}
+            (TypeInfo::Str, TypeInfo::Str) => {}
+            (TypeInfo::Float { bits: left_bits }, TypeInfo::Float { bits: right_bits }) if left_bits == right_bits => {}
 
-            (&TypeInfo::Pointer(left), &TypeInfo::Pointer(right)) => {
-                self.unify_var(left, right);
+            (&TypeInfo::Pointer(left), &TypeInfo::Pointer(right)) |
+            (&TypeInfo::OptionalPointer(left), &TypeInfo::OptionalPointer(right)) |
+            (&TypeInfo::Slice(left), &TypeInfo::Slice(right)) => {
+                self.unify_var(left, right).map_err(|conflict| conflict.or_outer(mismatch))?;
             }
             (TypeInfo::Tuple(left), TypeInfo::Tuple(right)) => {
-                assert_eq!(left.fields.len(), right.fields.len(), "tuples must have the same size");
+                if left.fields.len() != right.fields.len() {
+                    return Err(mismatch);
+                }
                 for (left, right) in zip_eq(left.fields.clone(), right.fields.clone()) {
-                    self.unify_var(left, right);
+                    self.unify_var(left, right).map_err(|conflict| conflict.or_outer(mismatch))?;
                 }
             }
-            (TypeInfo::Function(left), TypeInfo::Function(right)) => {
-                assert_eq!(left.params.len(), right.params.len(), "functions must have the same number of parameters");
+            (TypeInfo::Function(left), TypeInfo::Function(right)) |
+            (TypeInfo::Closure(left), TypeInfo::Closure(right)) => {
+                if left.params.len() != right.params.len() || left.conv != right.conv {
+                    return Err(mismatch);
+                }
                 let left_ret = left.ret;
                 let right_ret = right.ret;
 
                 for (left, right) in zip_eq(left.params.clone(), right.params.clone()) {
-                    self.unify_var(left, right);
+                    self.unify_var(left, right).map_err(|conflict| conflict.or_outer(mismatch))?;
                 }
 
                 //do this last so error messages appear more in order
-                self.unify_var(left_ret, right_ret);
+                self.unify_var(left_ret, right_ret).map_err(|conflict| conflict.or_outer(mismatch))?;
             }
             (TypeInfo::Array(left), TypeInfo::Array(right)) => {
-                assert_eq!(left.length, right.length, "arrays must have the same length");
+                if left.length != right.length {
+                    return Err(mismatch);
+                }
                 let left_inner = left.inner;
                 let right_inner = right.inner;
-                self.unify_var(left_inner, right_inner);
+                self.unify_var(left_inner, right_inner).map_err(|conflict| conflict.or_outer(mismatch))?;
             }
 
             (TypeInfo::Struct(left), TypeInfo::Struct(right)) => {
-                assert_eq!(left, right)
+                if left != right {
+                    return Err(mismatch);
+                }
             }
-
-            _ => {
-                panic!(
-                    "Type mismatch: \n{:?}={:?} ({:?})\n{:?}={:?} ({:?})\n",
-                    left, left_info, self.state[left.0].origin,
-                    right, right_info, self.state[right.0].origin,
-                )
+            (TypeInfo::Enum(left), TypeInfo::Enum(right)) => {
+                if left != right {
+                    return Err(mismatch);
+                }
             }
+
+            _ => return Err(mismatch),
         }
+
+        Ok(())
     }
 }
 
@@ -437,21 +1152,36 @@ impl std::ops::Index<TypeVar> for TypeSolution {
     }
 }
 
+impl TypeSolution {
+    pub fn stats(&self) -> SolverStats {
+        self.stats
+    }
+}
+
 impl<'ast> std::fmt::Debug for TypeProblem<'ast> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "TypeProblem {{\n    vars: [")?;
 
-        for i in 0..self.state.len() {
+        for i in 0..self.len() {
             let var = TypeVar(i);
-            let state = &self.state[i];
+            let state = &self.vars[var];
 
             let constraint = match state.constraint {
                 Constraint::None => "",
                 Constraint::AnyInt => "int",
+                Constraint::AnyFloat => "float",
+                Constraint::AnyNumber => "number",
+                Constraint::AnyEq => "eq",
+                Constraint::CastableTo(_) => "cast",
                 Constraint::DefaultVoid => "->void",
             };
 
-            writeln!(f, "        {:?}[{}]: {:?}, {:?}", var, constraint, state.info, state.origin)?;
+            let root = self.vars.find(var);
+            if root == var {
+                writeln!(f, "        {:?}[{}]: {:?}, {:?}", var, constraint, state.info, state.origin)?;
+            } else {
+                writeln!(f, "        {:?}[{}]: -> {:?}, {:?}", var, constraint, root, state.origin)?;
+            }
         }
 
         writeln!(f, "    ],\n    constraints: [")?;
@@ -460,7 +1190,7 @@ impl<'ast> std::fmt::Debug for TypeProblem<'ast> {
         }
 
         writeln!(f, "    ],\n    index constraints: [")?;
-        for &IndexConstraint { target, result, index } in &self.index_constraints {
+        for &IndexConstraint { origin: _, target, result, index } in &self.index_constraints {
             writeln!(f, "        {:?}[{:?}] == {:?}", target, index, result)?;
         }
         writeln!(f, "    ],")?;
@@ -489,7 +1219,7 @@ impl std::fmt::Debug for TypeSolution {
 #[cfg(test)]
 mod test {
     use crate::front::ast::ExpressionKind;
-    use crate::front::cst::TupleTypeInfo;
+    use crate::front::cst::{ArrayTypeInfo, TupleTypeInfo};
     use crate::front::pos::{FileId, Pos, Span};
 
     use super::*;
@@ -513,12 +1243,78 @@ mod test {
         problem.equal(b, c);
         problem.equal(c, d);
 
-        let sol = problem.solve(&mut types);
+        let sol = problem.solve(&mut types).unwrap();
         for &var in &[a, b, c, d] {
             assert_eq!(types.type_int(), sol[var]);
         }
     }
 
+    #[test]
+    fn default_int() {
+        let expr = dummy_expr();
+        let origin = Origin::Expression(&expr);
+
+        let mut types = TypeStore::default();
+        let mut problem = TypeProblem::default();
+        let (a, b) = (problem.unknown_int(origin), problem.unknown(origin));
+        let (c, d) = (problem.unknown_int(origin), problem.known(origin, TypeInfo::Byte));
+
+        problem.equal(a, b);
+        problem.equal(c, d);
+
+        let sol = problem.solve(&mut types).unwrap();
+        assert_eq!(types.type_int(), sol[a]);
+        assert_eq!(types.type_int(), sol[b]);
+        assert_eq!(types.type_byte(), sol[c]);
+    }
+
+    #[test]
+    fn default_float() {
+        let expr = dummy_expr();
+        let origin = Origin::Expression(&expr);
+
+        let mut types = TypeStore::default();
+        let mut problem = TypeProblem::default();
+        let (a, b) = (problem.unknown_float(origin), problem.unknown_float(origin));
+        let (c, d) = (problem.unknown_float(origin), problem.known(origin, TypeInfo::Float { bits: 32 }));
+
+        problem.equal(a, b);
+        problem.equal(c, d);
+
+        let sol = problem.solve(&mut types).unwrap();
+        assert_eq!(types.type_float(64), sol[a]);
+        assert_eq!(types.type_float(64), sol[b]);
+        assert_eq!(types.type_float(32), sol[c]);
+    }
+
+    #[test]
+    fn long_chain() {
+        let expr = dummy_expr();
+        let origin = Origin::Expression(&expr);
+
+        let mut types = TypeStore::default();
+        let mut problem = TypeProblem::default();
+
+        //the chain is matched back to front, so every match is between two unknown vars
+        let vars = (0..1000).map(|_| problem.unknown(origin)).collect_vec();
+        for pair in vars.windows(2).rev() {
+            problem.equal(pair[0], pair[1]);
+        }
+        let int = problem.known(origin, TypeInfo::Int);
+        problem.equal(vars[0], int);
+
+        let before = problem.len();
+        let sol = problem.solve(&mut types).unwrap();
+        for &var in &vars {
+            assert_eq!(types.type_int(), sol[var]);
+        }
+
+        let stats = sol.stats();
+        assert_eq!(before, stats.vars);
+        assert_eq!(before - vars.len(), stats.classes);
+        assert_eq!(vars.len(), stats.unions);
+    }
+
     #[test]
     fn tuple() {
         let expr = dummy_expr();
@@ -533,7 +1329,7 @@ mod test {
         let t2 = problem.known(origin, TypeInfo::Tuple(TupleTypeInfo { fields: vec![c, d] }));
         problem.equal(t1, t2);
 
-        let sol = problem.solve(&mut types);
+        let sol = problem.solve(&mut types).unwrap();
 
         let tuple_info = TupleTypeInfo { fields: vec![types.type_int(), types.type_bool()] };
         let type_tuple = types.define_type(TypeInfo::Tuple(tuple_info));
@@ -545,6 +1341,136 @@ mod test {
         assert_eq!(type_tuple, sol[t2]);
     }
 
+    #[test]
+    fn never() {
+        let expr = dummy_expr();
+        let origin = Origin::Expression(&expr);
+
+        let mut types = TypeStore::default();
+        let mut problem = TypeProblem::default();
+        let never = problem.ty_never();
+        let (a, b) = (problem.unknown(origin), problem.unknown(origin));
+        let int = problem.known(origin, TypeInfo::Int);
+
+        //a matches both never and int, b only never
+        problem.equal(a, never);
+        problem.equal(a, int);
+        problem.equal(b, never);
+
+        let sol = problem.solve(&mut types).unwrap();
+        let type_never = types.define_type(TypeInfo::Never);
+        assert_eq!(types.type_int(), sol[a]);
+        assert_eq!(type_never, sol[b]);
+    }
+
+    #[test]
+    fn coerce_optional_ptr() {
+        let expr = dummy_expr();
+        let origin = Origin::Expression(&expr);
+
+        let mut types = TypeStore::default();
+        let mut problem = TypeProblem::default();
+
+        //a pointer with an unknown inner type coerced to an optional int pointer
+        let a = problem.unknown(origin);
+        let a_ptr = problem.known(origin, TypeInfo::Pointer(a));
+        let opt = problem.known(origin, TypeInfo::OptionalPointer(problem.ty_int()));
+        problem.coerce(a_ptr, opt);
+
+        //without any other information a coercion is a plain match
+        let b = problem.unknown(origin);
+        problem.coerce(a_ptr, b);
+
+        let sol = problem.solve(&mut types).unwrap();
+        let type_int_ptr = types.define_type_ptr(types.type_int());
+        assert_eq!(types.type_int(), sol[a]);
+        assert_eq!(type_int_ptr, sol[a_ptr]);
+        assert_eq!(type_int_ptr, sol[b]);
+    }
+
+    #[test]
+    fn coerce_array_ptr_to_slice() {
+        let expr = dummy_expr();
+        let origin = Origin::Expression(&expr);
+
+        let mut types = TypeStore::default();
+        let mut problem = TypeProblem::default();
+
+        //a pointer to an array with unknown element type coerced to an int slice
+        let inner = problem.unknown(origin);
+        let array = problem.known(origin, TypeInfo::Array(ArrayTypeInfo { inner, length: 4 }));
+        let array_ptr = problem.known(origin, TypeInfo::Pointer(array));
+        let slice = problem.known(origin, TypeInfo::Slice(problem.ty_int()));
+        problem.coerce(array_ptr, slice);
+
+        //slicing the array gives a slice too
+        let sliced = problem.slice(origin, array);
+
+        let sol = problem.solve(&mut types).unwrap();
+        let type_slice = types.define_type(TypeInfo::Slice(types.type_int()));
+        assert_eq!(types.type_int(), sol[inner]);
+        assert_eq!(type_slice, sol[slice]);
+        assert_eq!(type_slice, sol[sliced]);
+    }
+
+    #[test]
+    fn coerce_array_ptr_to_ptr() {
+        let expr = dummy_expr();
+        let origin = Origin::Expression(&expr);
+
+        let mut types = TypeStore::default();
+        let mut problem = TypeProblem::default();
+
+        //a pointer to an array with unknown element type decays to an int pointer
+        let inner = problem.unknown(origin);
+        let array = problem.known(origin, TypeInfo::Array(ArrayTypeInfo { inner, length: 4 }));
+        let array_ptr = problem.known(origin, TypeInfo::Pointer(array));
+        let ptr = problem.known(origin, TypeInfo::Pointer(problem.ty_int()));
+        problem.coerce(array_ptr, ptr);
+
+        //indexing the pointer gives the element type
+        let element = problem.array_index(origin, ptr);
+
+        let sol = problem.solve(&mut types).unwrap();
+        let type_ptr = types.define_type_ptr(types.type_int());
+        assert_eq!(types.type_int(), sol[inner]);
+        assert_eq!(type_ptr, sol[ptr]);
+        assert_eq!(types.type_int(), sol[element]);
+    }
+
+    #[test]
+    fn float_add_sub() {
+        let expr = dummy_expr();
+        let origin = Origin::Expression(&expr);
+
+        let mut types = TypeStore::default();
+        let mut problem = TypeProblem::default();
+
+        let left = problem.known(origin, TypeInfo::Float { bits: 64 });
+        let right = problem.unknown_float(origin);
+        problem.add_sub_constraint(origin, left, right);
+
+        let sol = problem.solve(&mut types).unwrap();
+        assert_eq!(types.type_float(64), sol[right]);
+    }
+
+    #[test]
+    fn widen_byte_int() {
+        let expr = dummy_expr();
+        let origin = Origin::Expression(&expr);
+
+        let mut types = TypeStore::default();
+        let mut problem = TypeProblem::default();
+
+        //byte + int is widened to int, byte + byte stays byte
+        let mixed = problem.add_sub_constraint(origin, problem.ty_byte(), problem.ty_int());
+        let bytes = problem.add_sub_constraint(origin, problem.ty_byte(), problem.ty_byte());
+
+        let sol = problem.solve(&mut types).unwrap();
+        assert_eq!(types.type_int(), sol[mixed]);
+        assert_eq!(types.type_byte(), sol[bytes]);
+    }
+
     #[test]
     fn ptr_ptr() {
         let expr = dummy_expr();
@@ -561,11 +1487,128 @@ mod test {
         problem.equal(a_ptr, b_ptr);
         problem.equal(problem.ty_byte(), b);
 
-        let sol = problem.solve(&mut types);
+        let sol = problem.solve(&mut types).unwrap();
 
         assert_eq!(types.type_byte(), sol[a]);
         assert_eq!(types.type_byte(), sol[b]);
         assert_eq!(types.define_type_ptr(types.type_byte()), sol[a_ptr]);
         assert_eq!(types.define_type_ptr(types.type_byte()), sol[b_ptr]);
     }
+
+    #[test]
+    fn str_fields() {
+        let expr = dummy_expr();
+        let origin = Origin::Expression(&expr);
+
+        let mut types = TypeStore::default();
+        let mut problem = TypeProblem::default();
+
+        let s = problem.unknown(origin);
+        let len = problem.struct_index(origin, s, "len");
+        let ptr = problem.struct_index(origin, s, "ptr");
+        let known_str = problem.known(origin, TypeInfo::Str);
+        problem.equal(s, known_str);
+
+        let sol = problem.solve(&mut types).unwrap();
+        assert_eq!(types.type_str(), sol[s]);
+        assert_eq!(types.type_int(), sol[len]);
+        assert_eq!(types.define_type_ptr(types.type_byte()), sol[ptr]);
+    }
+
+    #[test]
+    fn mismatch_origins() {
+        let expr = dummy_expr();
+        let pos = Pos { file: FileId(0), line: 1, col: 4 };
+        let ty = ast::Type { span: Span { start: pos, end: pos }, kind: ast::TypeKind::Wildcard };
+
+        let mut types = TypeStore::default();
+        let mut problem = TypeProblem::default();
+
+        let inner = problem.known(Origin::Expression(&expr), TypeInfo::Bool);
+        let actual = problem.known(Origin::Expression(&expr), TypeInfo::Pointer(inner));
+        let int_ptr = types.define_type_ptr(types.type_int());
+        let expected = problem.fully_known(Origin::Type(&ty), &types, int_ptr);
+        problem.equal(actual, expected);
+
+        match problem.solve(&mut types) {
+            Err(Error::TypeMismatch { span, expected, actual, expected_origin }) => {
+                assert_eq!(expr.span, span);
+                assert_eq!("&int", expected);
+                assert_eq!("&bool", actual);
+                assert!(matches!(expected_origin, Some(TypeOrigin::Type(origin)) if std::ptr::eq(origin, &ty)));
+            }
+            other => panic!("expected type mismatch, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn infinite_type() {
+        let expr = dummy_expr();
+        let origin = Origin::Expression(&expr);
+
+        let mut types = TypeStore::default();
+        let mut problem = TypeProblem::default();
+
+        //a == &(a, int)
+        let a = problem.unknown(origin);
+        let int = problem.ty_int();
+        let tuple = problem.known(origin, TypeInfo::Tuple(TupleTypeInfo { fields: vec![a, int] }));
+        let ptr = problem.known(origin, TypeInfo::Pointer(tuple));
+        problem.equal(a, ptr);
+
+        match problem.solve(&mut types) {
+            Err(Error::InfiniteType { span, ty }) => {
+                assert_eq!(expr.span, span);
+                assert_eq!("&(_, int)", ty);
+            }
+            other => panic!("expected infinite type, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    fn field_expr(field: &str) -> ast::Expression {
+        let span = dummy_expr().span;
+        let index = ast::DotIndexIndex::Struct(ast::Identifier { span, string: field.to_owned() });
+        ast::Expression { span, kind: ExpressionKind::DotIndex { target: Box::new(dummy_expr()), index } }
+    }
+
+    #[test]
+    fn unknown_field() {
+        let expr = field_expr("foo");
+        let origin = Origin::Expression(&expr);
+
+        //str and slices have fields, just not this one
+        for target in [TypeInfo::Str, TypeInfo::Slice(()), TypeInfo::Int] {
+            let mut types = TypeStore::default();
+            let mut problem = TypeProblem::default();
+
+            let target = target.map_ty(&mut |_| problem.ty_int());
+            let is_int = matches!(target, TypeInfo::Int);
+            let target = problem.known(origin, target);
+            problem.struct_index(origin, target, "foo");
+
+            match problem.solve(&mut types) {
+                Err(Error::StructFieldNotFound { index, .. }) if !is_int => assert_eq!("foo", index.string),
+                Err(Error::WrongDotIndexType { .. }) if is_int => {}
+                other => panic!("expected unknown field error, got {:?}", other.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn index_non_array() {
+        let expr = ast::Expression {
+            span: dummy_expr().span,
+            kind: ExpressionKind::ArrayIndex { target: Box::new(dummy_expr()), index: Box::new(dummy_expr()) },
+        };
+        let origin = Origin::Expression(&expr);
+
+        let mut types = TypeStore::default();
+        let mut problem = TypeProblem::default();
+        problem.array_index(origin, problem.ty_int());
+
+        match problem.solve(&mut types) {
+            Err(Error::NotIndexable { target_type, .. }) => assert_eq!("int", target_type),
+            other => panic!("expected not indexable error, got {:?}", other.map(|_| ())),
+        }
+    }
 }
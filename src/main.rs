@@ -1,19 +1,23 @@
 #![deny(unused_must_use)]
 
-use std::ffi::{OsStr, OsString};
+use std::collections::HashSet;
+use std::ffi::OsString;
 use std::fs::{File, read_to_string};
-use std::io::Write;
+use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
 use clap::Clap;
 use derive_more::From;
-use itertools::Itertools;
-use walkdir::{DirEntry, WalkDir};
 
 use crate::front::ast;
+use crate::front::diag::{Renderer, Report};
+use crate::front::error::{Severity, WarningKind, WarningLevel, WarningLevels};
 use crate::front::parser::ParseError;
-use crate::front::pos::FileId;
+use crate::front::pos::Files;
+use crate::back::gas::AsmSyntax;
+use crate::back::target::{SystemApi, Target};
+use crate::mid::opt::{OptLevel, OptSettings, PassKind, Pipeline};
 
 #[macro_use]
 mod util;
@@ -21,175 +25,320 @@ pub mod front;
 pub mod back;
 pub mod mid;
 
+#[allow(dead_code)]
 #[derive(Debug, From)]
 enum CompileError {
     IO(std::io::Error),
-    Walk(walkdir::Error),
     InvalidFileName(OsString),
     DuplicateModule(String),
+    /// An unsupported target triple or a syntax the target doesn't support.
+    #[from(ignore)]
+    Target(String),
     Parse(ParseError),
+    /// Errors in the source code, these have already been reported.
+    Front,
+    DeniedWarnings,
     Assemble,
     Link,
+    Interpret,
 }
 
 type Result<T> = std::result::Result<T, CompileError>;
 
-fn parse_and_add_module_if_ll(
-    prog: &mut front::Program<Option<ast::ModuleContent>>,
-    file_count: &mut usize,
-    entry: DirEntry,
-    skip_path_components: usize,
-) -> Result<()> {
-    let path = entry.path();
-
-    //check that this is a .ll file
-    if !entry.metadata()?.is_file() || path.extension() != Some(OsStr::new("ll")) {
-        return Ok(());
-    }
+/// Parses the modules of a program on demand, starting from the main file and following `use` declarations.
+/// The module `a::b` is loaded from the file `a/b.ll` relative to one of the search roots.
+struct ModuleLoader<'f> {
+    roots: Vec<PathBuf>,
+    prog: front::Program<Option<ast::ModuleContent>>,
+    files: &'f mut Files,
+    visited: HashSet<Vec<String>>,
+}
 
-    //convert the file path to a proper module path
-    let clean_path = path.with_extension("");
-    let path_vec: Vec<_> = clean_path.components().skip(skip_path_components)
-        .map(|c| {
-            let s = c.as_os_str();
-            s.to_str()
-                .map(|s| s.to_string())
-                .ok_or_else(|| CompileError::InvalidFileName(s.to_os_string()))
-        })
-        .try_collect()?;
-
-    //find the module
-    let module_name = path_vec.last().unwrap().clone();
-    let module = prog.find_or_create_module(path_vec);
-
-    //if this module already has content that means it's declared twice
-    if module.content.is_some() {
-        return Err(CompileError::DuplicateModule(module_name));
+impl<'f> ModuleLoader<'f> {
+    fn new(roots: Vec<PathBuf>, files: &'f mut Files) -> Self {
+        ModuleLoader { roots, prog: Default::default(), files, visited: Default::default() }
     }
 
-    //increment the file id
-    let id = FileId(*file_count);
-    *file_count += 1;
+    /// Load the module with the given path and the modules it uses, if a file for it exists.
+    /// Each module is only loaded once.
+    fn load(&mut self, path_vec: Vec<String>) -> Result<()> {
+        if !self.visited.insert(path_vec.clone()) {
+            return Ok(());
+        }
 
-    println!("{:?}: {:?}", id, path);
+        let files: Vec<PathBuf> = self.roots.iter()
+            .map(|root| {
+                let mut file = root.clone();
+                file.extend(&path_vec);
+                file.with_extension("ll")
+            })
+            .filter(|file| file.is_file())
+            .collect();
+
+        let file = match files.as_slice() {
+            //this path might not refer to a module at all, that's checked during resolution
+            [] => return Ok(()),
+            [file] => file,
+            _ => return Err(CompileError::DuplicateModule(path_vec.join("::"))),
+        };
+
+        //load and parse the source code, the file is kept around for diagnostics
+        let src = read_to_string(file)?;
+        let id = self.files.add(file.clone(), src);
+        println!("{:?}: {:?}", id, file);
+
+        let module_ast = front::parser::parse_module(id, &self.files[id].src)?;
+
+        let mut used_paths = vec![];
+        self.visit_inline_modules(&path_vec, &module_ast, &mut used_paths);
+
+        self.prog.add_module(path_vec, module_ast)
+            .map_err(|path| CompileError::DuplicateModule(path.join("::")))?;
+
+        for used_path in used_paths {
+            self.load(used_path)?;
+        }
 
-    //load and parse the source code
-    let src = read_to_string(path)?;
-    let module_ast = front::parser::parse_module(id, &src)?;
+        Ok(())
+    }
 
-    module.content = Some(module_ast);
-    Ok(())
-}
+    /// Mark the inline modules declared in `content` as visited, so they're not loaded from a file as well, and
+    /// collect the paths used in `content` and its inline modules. Any prefix of a used path can be a module.
+    fn visit_inline_modules(&mut self, path_vec: &[String], content: &ast::ModuleContent, used_paths: &mut Vec<Vec<String>>) {
+        for item in &content.items {
+            if let ast::Item::UseDecl(use_decl) = item {
+                let path = &use_decl.path;
+                let elements: Vec<String> = path.parents.iter().chain(std::iter::once(&path.id))
+                    .map(|id| id.string.clone())
+                    .collect();
+                used_paths.extend((1..=elements.len()).map(|len| elements[..len].to_vec()));
+            }
+        }
 
-/// Parse the main file and all of the lib files into a single program
-fn parse_all(ll_path: &Path, include_std: bool) -> Result<front::Program<Option<ast::ModuleContent>>> {
-    let mut prog = front::Program::default();
-    let mut file_count: usize = 0;
+        for inline in &content.modules {
+            let mut inline_path = path_vec.to_vec();
+            inline_path.push(inline.id.string.clone());
 
-    //add stdlib files
-    if include_std {
-        //TODO this is brittle, ship the lib files with the exe instead
-        for file in WalkDir::new("lib") {
-            parse_and_add_module_if_ll(&mut prog, &mut file_count, file?, 1)?;
+            self.visited.insert(inline_path.clone());
+            self.visit_inline_modules(&inline_path, &inline.content, used_paths);
         }
     }
+}
 
-    //add project files
+/// Parse the main file and all of the modules it (indirectly) uses into a single program.
+/// The loaded files are added to `files`.
+fn parse_all(ll_path: &Path, include_std: bool, search_paths: &[PathBuf], files: &mut Files) -> Result<front::Program<Option<ast::ModuleContent>>> {
+    //the project folder comes first, then the extra search paths and finally the stdlib
     let parent = ll_path.parent().expect("input file should be in folder");
-    let parent_component_count = parent.components().count();
-
-    for file in WalkDir::new(parent) {
-        parse_and_add_module_if_ll(&mut prog, &mut file_count, file?, parent_component_count)?;
+    let mut roots = vec![parent.to_path_buf()];
+    roots.extend(search_paths.iter().cloned());
+    if include_std {
+        //TODO this is brittle, ship the lib files with the exe instead
+        roots.push(PathBuf::from("lib"));
     }
 
-    Ok(prog)
+    let main_name = ll_path.file_stem()
+        .and_then(|s| s.to_str())
+        .ok_or_else(|| CompileError::InvalidFileName(ll_path.as_os_str().to_os_string()))?;
+
+    let mut loader = ModuleLoader::new(roots, files);
+    loader.load(vec![main_name.to_owned()])?;
+    Ok(loader.prog)
 }
 
-fn run_optimizations(prog: &mut mid::ir::Program) {
-    loop {
-        let mut changed = false;
+/// Print a report to stderr, showing the relevant source code.
+fn report(files: &Files, color: bool, report: &Report) {
+    eprint!("{}", Renderer { files, color }.render(report));
+}
 
-        changed |= mid::opt::gc::gc(prog);
-        changed |= mid::opt::slot_to_phi::slot_to_phi(prog);
-        changed |= mid::opt::gc::gc(prog);
-        changed |= mid::opt::sccp::sccp(prog);
-        changed |= mid::opt::flow_simplify::flow_simplify(prog);
+/// Panic if the IR is malformed, `stage` describes what happened to the program last.
+fn verify_ir(prog: &mid::ir::Program, stage: &str) {
+    if let Err(e) = mid::verify::verify(prog) {
+        panic!("IR verification failed {}: {}", stage, e);
+    }
+}
 
-        if !changed { break; }
+/// Write the control flow graph of every function in `prog` to `path` as Graphviz digraphs.
+fn dump_cfg(prog: &mid::ir::Program, path: &Path) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    for (func, _) in &prog.nodes.funcs {
+        prog.write_dot(func, &mut file)?;
     }
+    file.flush()?;
+    Ok(())
 }
 
-fn compile_ll_to_asm(ll_path: &Path, include_std: bool, optimize: bool) -> Result<PathBuf> {
+/// The passes selected by `--passes` if given, otherwise the ones for the optimization level.
+fn optimization_pipeline(opts: &Opts) -> Pipeline {
+    let kinds = if !opts.passes.is_empty() {
+        &opts.passes
+    } else if opts.no_opt {
+        OptLevel::O0.passes()
+    } else {
+        opts.opt_level.passes()
+    };
+
+    let settings = OptSettings { unroll_budget: opts.unroll_budget };
+    Pipeline::new(kinds, &settings, opts.verify_ir)
+}
+
+fn compile_ll_to_ir(ll_path: &Path, opts: &Opts, target: &dyn Target, warning_levels: &WarningLevels) -> Result<mid::ir::Program> {
+    let color = !opts.no_color;
+
     println!("----Parse------");
-    let ast_program = parse_all(ll_path, include_std)?;
+    let mut files = Files::default();
+    let ast_program = match parse_all(ll_path, !opts.no_std, &opts.search_paths, &mut files) {
+        Ok(ast_program) => ast_program,
+        Err(CompileError::Parse(e)) => {
+            report(&files, color, &Report::from(&e));
+            return Err(CompileError::Front);
+        }
+        Err(e) => return Err(e),
+    };
     let ast_file = ll_path.with_extension("ast");
     File::create(&ast_file)?
         .write_fmt(format_args!("{:#?}", ast_program))?;
 
     println!("----Collect----");
-    let resolved = front::resolve::resolve(&ast_program)
-        .expect("failed to collect"); //TODO ? instead of panic here
+    let resolved = front::resolve::resolve(&ast_program).map_err(|e| {
+        report(&files, color, &Report::from(&e));
+        CompileError::Front
+    })?;
     let cst_file = ll_path.with_extension("cst");
     File::create(&cst_file)?
         .write_fmt(format_args!("{:#?}", resolved))?;
 
     println!("----Lower------");
-    let mut ir_program = front::lower::lower(resolved)
-        .expect("failed to lower"); //TODO ? instead of panic here
+    let settings = front::lower::LowerSettings {
+        files: &files,
+        check_asserts: !opts.no_assert,
+        solver_stats: opts.solver_stats,
+        data_layout: target.data_layout(),
+        system_api: target.system_api(),
+    };
+    let (mut ir_program, warnings) = front::lower::lower(resolved, &settings).map_err(|e| {
+        report(&files, color, &Report::from(&e));
+        CompileError::Front
+    })?;
+
+    let diagnostics = warning_levels.diagnose(warnings);
+    for diagnostic in &diagnostics {
+        report(&files, color, &Report::from(diagnostic));
+    }
+    if diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error) {
+        return Err(CompileError::DeniedWarnings);
+    }
+
     let ir_file = ll_path.with_extension("ir");
     File::create(&ir_file)?
         .write_fmt(format_args!("{}", ir_program))?;
+    if opts.verify_ir {
+        verify_ir(&ir_program, "after lowering");
+    }
+    if opts.dump_cfg {
+        dump_cfg(&ir_program, &ll_path.with_extension("dot"))?;
+    }
 
     println!("----Optimize---");
     let ir_opt_file = ll_path.with_extension("ir_opt");
-    if optimize {
-        run_optimizations(&mut ir_program);
+    let pipeline = optimization_pipeline(opts);
+    if !pipeline.passes.is_empty() {
+        let stats = pipeline.run(&mut ir_program);
+        if opts.pass_stats {
+            print!("{}", stats);
+        }
         File::create(&ir_opt_file)?
             .write_fmt(format_args!("{}", ir_program))?;
+        if opts.dump_cfg {
+            dump_cfg(&ir_program, &ll_path.with_extension("opt.dot"))?;
+        }
     } else {
         //clear file
         File::create(&ir_opt_file)?.write_all(&[])?;
     }
 
+    Ok(ir_program)
+}
+
+fn compile_ll_to_asm(ll_path: &Path, opts: &Opts, target: &dyn Target, syntax: AsmSyntax, warning_levels: &WarningLevels) -> Result<PathBuf> {
+    let ir_program = compile_ll_to_ir(ll_path, opts, target, warning_levels)?;
+
     println!("----Backend----");
-    let asm = back::x86_asm::lower(&ir_program);
-    let asm_file = ll_path.with_extension("asm");
+    let settings = back::BackendSettings {
+        frame_pointers: opts.frame_pointers,
+        source_comments: opts.source_comments,
+        freestanding: opts.freestanding,
+    };
+    let asm = target.lower(&ir_program, syntax, &settings);
+    let asm_file = ll_path.with_extension(syntax.extension());
     File::create(&asm_file)?
         .write_all(asm.as_bytes())?;
 
     Ok(asm_file)
 }
 
-fn compile_asm_to_exe(asm_path: &Path) -> Result<PathBuf> {
-    println!("----Assemble---");
-    let result = Command::new("nasm")
-        .current_dir(asm_path.parent().unwrap())
-        .arg("-O0")
-        .arg("-fwin32")
-        .arg("-g")
-        .arg(asm_path.file_name().unwrap())
-        .status()?;
+/// Assemble `asm_path` into an object file next to it, with the assembler of the target for `syntax`.
+fn assemble(asm_path: &Path, target: &dyn Target, syntax: AsmSyntax) -> Result<PathBuf> {
+    let obj_path = asm_path.with_extension(target.object_extension());
+    let result = target.assemble_command(asm_path, &obj_path, syntax).status()?;
 
     if !result.success() {
         return Err(CompileError::Assemble);
     }
 
-    let result = Command::new("C:\\Program Files (x86)\\Microsoft Visual Studio\\2019\\BuildTools\\VC\\Tools\\MSVC\\14.27.29110\\bin\\Hostx64\\x86\\link.exe")
-        .current_dir(asm_path.parent().unwrap())
-        .arg("/nologo")
-        .arg("/debug")
-        .arg("/subsystem:console")
-        .arg("/nodefaultlib")
-        .arg("/entry:main")
-        .arg(asm_path.with_extension("obj").file_name().unwrap())
-        .arg("C:\\Program Files (x86)\\Windows Kits\\10\\Lib\\10.0.18362.0\\um\\x86\\kernel32.lib")
-        .status()?;
+    Ok(obj_path)
+}
+
+/// Assemble `asm_path` together with the runtime and link them into an executable.
+/// Freestanding programs have their own entry point and are linked on their own.
+fn compile_asm_to_exe(asm_path: &Path, target: &dyn Target, syntax: AsmSyntax, opts: &Opts) -> Result<PathBuf> {
+    println!("----Assemble---");
+    let mut objects = vec![assemble(asm_path, target, syntax)?];
+    if !opts.freestanding {
+        let rt_asm_path = asm_path.with_extension(format!("rt.{}", syntax.extension()));
+        File::create(&rt_asm_path)?
+            .write_all(target.runtime_asm(syntax).as_bytes())?;
+        objects.push(assemble(&rt_asm_path, target, syntax)?);
+    }
+
+    println!("----Link-------");
+    let mut lib_paths = opts.lib_paths.clone();
+    lib_paths.extend(target.default_lib_paths());
+    let settings = back::link::LinkSettings {
+        linker: opts.linker.clone().unwrap_or_else(|| PathBuf::from(target.default_linker())),
+        libs: opts.libs.clone(),
+        lib_paths,
+    };
+
+    let exe_path = asm_path.with_extension(target.exe_extension());
+    let result = target.link_command(&settings, &objects, &exe_path).status()?;
 
     if !result.success() {
         return Err(CompileError::Link);
     }
 
-    Ok(asm_path.with_extension("exe"))
+    Ok(exe_path)
+}
+
+/// Run the program in the IR interpreter instead of assembling it, printing its output and exit code.
+fn interpret_ll(ll_path: &Path, opts: &Opts, target: &dyn Target, warning_levels: &WarningLevels) -> Result<()> {
+    let ir_program = compile_ll_to_ir(ll_path, opts, target, warning_levels)?;
+
+    println!("----Interpret--");
+    let mut interpreter = mid::interp::Interpreter::new(&ir_program);
+    let result = interpreter.run_main();
+
+    std::io::stdout().write_all(&interpreter.stdout)?;
+    std::io::stderr().write_all(&interpreter.stderr)?;
+    match result {
+        Ok(code) => println!("exit code: {}", code),
+        Err(e) => {
+            println!("error: {}", e);
+            return Err(CompileError::Interpret);
+        }
+    }
+
+    Ok(())
 }
 
 fn run_exe(exe_path: &Path) -> std::io::Result<()> {
@@ -205,9 +354,99 @@ struct Opts {
     #[clap(long)]
     no_std: bool,
 
+    /// Don't optimize at all, the same as `-O0`.
     #[clap(long)]
     no_opt: bool,
 
+    /// The optimization level: 0 doesn't optimize, 1 runs the basic cleanup passes and 2 runs everything.
+    #[clap(short = 'O', default_value = "2")]
+    opt_level: OptLevel,
+
+    /// Run exactly this comma-separated list of passes to fixpoint instead of the ones for the optimization level.
+    #[clap(long, use_delimiter = true, number_of_values = 1)]
+    passes: Vec<PassKind>,
+
+    /// Don't check `assert` statements, they're removed entirely.
+    #[clap(long)]
+    no_assert: bool,
+
+    /// Don't use colors when printing errors and warnings.
+    #[clap(long)]
+    no_color: bool,
+
+    /// Print statistics about type inference for each function.
+    #[clap(long)]
+    solver_stats: bool,
+
+    /// Print how often each optimization pass ran, changed something and how long it took.
+    #[clap(long)]
+    pass_stats: bool,
+
+    /// Check that the IR is well-formed before and after each optimization pass.
+    #[clap(long)]
+    verify_ir: bool,
+
+    /// Write the control flow graph of every function to a Graphviz file, both before (`.dot`) and after
+    /// optimization (`.opt.dot`).
+    #[clap(long)]
+    dump_cfg: bool,
+
+    /// The maximum number of instructions a loop with a constant trip count may be fully unrolled into.
+    #[clap(long, default_value = "64")]
+    unroll_budget: usize,
+
+    /// Additional folders to look for used modules in.
+    #[clap(short = 'I', long = "search-path", number_of_values = 1)]
+    search_paths: Vec<PathBuf>,
+
+    /// The target triple to generate code for, `i686-pc-windows-msvc` or `riscv64-unknown-linux-gnu`.
+    #[clap(long, default_value = back::target::DEFAULT_TRIPLE)]
+    target: String,
+
+    /// The assembly syntax to generate, `nasm` or `gas` for the GNU assembler. By default the first one the target
+    /// supports, RISC-V only supports `gas`.
+    #[clap(long)]
+    syntax: Option<AsmSyntax>,
+
+    /// Keep a frame pointer in every function, so debuggers and profilers can walk the stack.
+    #[clap(long)]
+    frame_pointers: bool,
+
+    /// Put a comment with the source location and code before the assembly generated for each source line.
+    #[clap(long)]
+    source_comments: bool,
+
+    /// Don't link the runtime or any system library, the program gets its own `_start` that calls main and exits
+    /// with the Linux exit system call instead. For small programs that run without a C runtime. Only supported by
+    /// targets that use the Linux system calls, currently `riscv64-unknown-linux-gnu`.
+    #[clap(long)]
+    freestanding: bool,
+
+    /// The linker to produce executables with. For Windows either `link.exe` or `lld-link`, by default the one of
+    /// Visual Studio, for Linux GNU `ld`, by default `riscv64-linux-gnu-ld`.
+    #[clap(long)]
+    linker: Option<PathBuf>,
+
+    /// Additional libraries to link against, eg. `user32`. Object files can be given here too.
+    #[clap(short = 'l', long = "lib", number_of_values = 1)]
+    libs: Vec<String>,
+
+    /// Additional folders to look for libraries in.
+    #[clap(short = 'L', long = "lib-path", number_of_values = 1)]
+    lib_paths: Vec<PathBuf>,
+
+    /// Report the given kind of warning, this is the default.
+    #[clap(short = 'W', long = "warn", number_of_values = 1)]
+    warn: Vec<WarningKind>,
+
+    /// Don't report the given kind of warning.
+    #[clap(short = 'A', long = "allow", number_of_values = 1)]
+    allow: Vec<WarningKind>,
+
+    /// Report the given kind of warning as an error.
+    #[clap(short = 'D', long = "deny", number_of_values = 1)]
+    deny: Vec<WarningKind>,
+
     #[clap(subcommand)]
     command: SubCommand,
 }
@@ -220,45 +459,135 @@ enum SubCommand {
     Build {
         file: String,
     },
+    /// Run the program in the IR interpreter, without assembling or linking it.
+    Interp {
+        file: String,
+    },
 }
 
 #[derive(Debug)]
 enum Level {
-    LL,
-    ASM,
+    Ll,
+    Asm(AsmSyntax),
 }
 
 fn main() -> Result<()> {
     let opts: Opts = Opts::parse();
 
-    let (file, do_run) = match opts.command {
-        SubCommand::Run { file } => (file, true),
-        SubCommand::Build { file } => (file, false),
+    let (file, do_run, do_interp) = match &opts.command {
+        SubCommand::Run { file } => (file, true, false),
+        SubCommand::Build { file } => (file, false, false),
+        SubCommand::Interp { file } => (file, false, true),
     };
 
-    let path = Path::new(&file).to_path_buf();
+    let path = Path::new(file).to_path_buf();
+
+    //when a kind is given multiple times the strictest level wins
+    let mut warning_levels = WarningLevels::default();
+    for (kinds, level) in [(&opts.allow, WarningLevel::Allow), (&opts.warn, WarningLevel::Warn), (&opts.deny, WarningLevel::Deny)] {
+        for &kind in kinds {
+            warning_levels.set(kind, level);
+        }
+    }
+
+    let target = back::target::from_triple(&opts.target).map_err(CompileError::Target)?;
+    let target = target.as_ref();
+    if opts.freestanding && target.system_api() != SystemApi::Linux {
+        return Err(CompileError::Target(format!("--freestanding needs Linux system calls, which target '{}' doesn't have", target.triple())));
+    }
 
     //TODO change main so you have to pass the project folder instead of the source name
     //  hmm, that's not entirely great, maybe add a mode for single-file projects too?
     let level = match path.extension().and_then(|os| os.to_str()) {
-        Some("ll") => Level::LL,
-        Some("asm") => Level::ASM,
+        Some("ll") => Level::Ll,
+        Some("asm") => Level::Asm(AsmSyntax::Nasm),
+        Some("s") => Level::Asm(AsmSyntax::Gas),
         _ => {
-            eprintln!("Expected either .ll or .asm file as input");
+            eprintln!("Expected either .ll, .asm or .s file as input");
             return Ok(());
         }
     };
 
+    if do_interp {
+        return match level {
+            Level::Ll => interpret_ll(&path, &opts, target, &warning_levels),
+            Level::Asm(_) => {
+                eprintln!("Only .ll files can be interpreted");
+                Ok(())
+            }
+        };
+    }
+
+    let syntax = match level {
+        Level::Ll => opts.syntax.unwrap_or(target.syntaxes()[0]),
+        Level::Asm(syntax) => syntax,
+    };
+    if !target.syntaxes().contains(&syntax) {
+        return Err(CompileError::Target(format!("target '{}' does not support {:?} syntax", target.triple(), syntax)));
+    }
+
     let asm_path = match level {
-        Level::LL => compile_ll_to_asm(&path, !opts.no_std, !opts.no_opt)?,
-        Level::ASM => path,
+        Level::Ll => compile_ll_to_asm(&path, &opts, target, syntax, &warning_levels)?,
+        Level::Asm(_) => path,
     };
 
-    let exe_path = compile_asm_to_exe(&asm_path)?;
+    let exe_path = compile_asm_to_exe(&asm_path, target, syntax, &opts)?;
 
     if do_run {
         run_exe(&exe_path)?;
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use std::fs::{create_dir_all, write};
+    use std::path::PathBuf;
+
+    use crate::{CompileError, parse_all};
+    use crate::front::pos::Files;
+
+    /// Create a fresh folder in the temp dir with the given files in it.
+    fn project(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("lllang_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for &(path, src) in files {
+            let path = dir.join(path);
+            create_dir_all(path.parent().unwrap()).unwrap();
+            write(path, src).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn load_used_modules() {
+        let dir = project("load_used", &[
+            ("main.ll", "use util::shapes::area; fun main() -> int { return area(); }"),
+            ("util/shapes.ll", "use util::math; pub fun area() -> int { return 0; }"),
+            ("util/math.ll", ""),
+            ("unused.ll", "this doesn't even parse"),
+        ]);
+
+        let mut files = Files::default();
+        let prog = parse_all(&dir.join("main.ll"), false, &[], &mut files).unwrap();
+
+        let util = &prog.root.submodules["util"];
+        assert!(prog.root.submodules["main"].content.is_some());
+        assert!(util.submodules["shapes"].content.is_some());
+        assert!(util.submodules["math"].content.is_some());
+        assert!(!prog.root.submodules.contains_key("unused"));
+    }
+
+    #[test]
+    fn module_in_multiple_roots() {
+        let dir = project("multiple_roots", &[
+            ("main.ll", "use util; fun main() -> int { return 0; }"),
+            ("util.ll", ""),
+            ("extra/util.ll", ""),
+        ]);
+
+        let mut files = Files::default();
+        let result = parse_all(&dir.join("main.ll"), false, &[dir.join("extra")], &mut files);
+        assert!(matches!(result, Err(CompileError::DuplicateModule(ref name)) if name == "util"), "{:?}", result);
+    }
+}
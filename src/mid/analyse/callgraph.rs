@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+
+use crate::mid::analyse::use_info::{UseInfo, Usage};
+use crate::mid::ir::{Function, Program, Value};
+
+/// The call graph of the functions reachable from main.
+///
+/// A function has unknown callers if it's main or if its address is used for anything other than the target of a
+/// direct call, eg. stored, passed as an argument or returned. Indirect calls (calls trough a value that isn't a
+/// function or an extern) can reach any of those functions, so for the SCCs they count as edges to all of them.
+/// This is a snapshot, it has to be rebuilt when calls are added or removed.
+#[derive(Debug)]
+pub struct CallGraph {
+    funcs: Vec<Function>,
+    func_index: HashMap<Function, usize>,
+    /// The distinct functions called directly.
+    callees: Vec<Vec<usize>>,
+    /// The distinct functions that call this function directly.
+    callers: Vec<Vec<usize>>,
+    unknown_callers: Vec<bool>,
+    indirect_calls: Vec<bool>,
+    /// The strongly connected components in bottom-up order.
+    sccs: Vec<Vec<Function>>,
+    scc_index: Vec<usize>,
+    recursive: Vec<bool>,
+}
+
+impl CallGraph {
+    pub fn new(prog: &Program, use_info: &UseInfo) -> Self {
+        let funcs: Vec<Function> = use_info.iter()
+            .filter_map(|(value, _)| if let Value::Func(func) = value { Some(func) } else { None })
+            .collect();
+        let func_index: HashMap<Function, usize> = funcs.iter().enumerate().map(|(i, &func)| (func, i)).collect();
+        assert!(func_index.contains_key(&prog.main));
+
+        let mut callees = vec![vec![]; funcs.len()];
+        let mut callers = vec![vec![]; funcs.len()];
+        let mut unknown_callers = vec![false; funcs.len()];
+        let mut indirect_calls = vec![false; funcs.len()];
+
+        for (value, usages) in use_info.iter() {
+            for usage in usages {
+                match (value, usage) {
+                    (Value::Func(callee), Usage::CallTarget { pos }) => {
+                        let (fi, ci) = (func_index[&pos.func], func_index[&callee]);
+                        if !callees[fi].contains(&ci) {
+                            callees[fi].push(ci);
+                            callers[ci].push(fi);
+                        }
+                    }
+                    (Value::Func(func), _) => unknown_callers[func_index[&func]] = true,
+                    (Value::Extern(_), Usage::CallTarget { .. }) => {}
+                    (_, Usage::CallTarget { pos }) => indirect_calls[func_index[&pos.func]] = true,
+                    _ => {}
+                }
+            }
+        }
+
+        //indirect calls can reach any function with unknown callers, main included to be safe
+        let escaping: Vec<usize> = (0..funcs.len()).filter(|&i| unknown_callers[i]).collect();
+        let possible_callees: Vec<Vec<usize>> = (0..funcs.len())
+            .map(|fi| {
+                let mut result = callees[fi].clone();
+                if indirect_calls[fi] {
+                    result.extend(escaping.iter().filter(|ei| !callees[fi].contains(ei)));
+                }
+                result
+            })
+            .collect();
+
+        let scc_indices = strongly_connected_components(&possible_callees);
+        let mut scc_index = vec![0; funcs.len()];
+        for (si, scc) in scc_indices.iter().enumerate() {
+            for &fi in scc {
+                scc_index[fi] = si;
+            }
+        }
+        let recursive = (0..funcs.len())
+            .map(|fi| scc_indices[scc_index[fi]].len() > 1 || possible_callees[fi].contains(&fi))
+            .collect();
+        let sccs = scc_indices.iter()
+            .map(|scc| scc.iter().map(|&fi| funcs[fi]).collect())
+            .collect();
+
+        CallGraph { funcs, func_index, callees, callers, unknown_callers, indirect_calls, sccs, scc_index, recursive }
+    }
+
+    /// The reachable functions, main comes first.
+    pub fn funcs(&self) -> &[Function] {
+        &self.funcs
+    }
+
+    pub fn contains(&self, func: Function) -> bool {
+        self.func_index.contains_key(&func)
+    }
+
+    /// The distinct functions `func` calls directly.
+    pub fn callees(&self, func: Function) -> impl Iterator<Item=Function> + '_ {
+        self.callees[self.func_index[&func]].iter().map(move |&i| self.funcs[i])
+    }
+
+    /// The distinct functions that call `func` directly. If `has_unknown_callers` is true this list is incomplete.
+    pub fn callers(&self, func: Function) -> impl Iterator<Item=Function> + '_ {
+        self.callers[self.func_index[&func]].iter().map(move |&i| self.funcs[i])
+    }
+
+    /// Whether `func` can be called from somewhere other than the direct calls in `callers`.
+    pub fn has_unknown_callers(&self, func: Function) -> bool {
+        self.unknown_callers[self.func_index[&func]]
+    }
+
+    /// Whether `func` contains calls to targets that are not known statically.
+    pub fn has_indirect_calls(&self, func: Function) -> bool {
+        self.indirect_calls[self.func_index[&func]]
+    }
+
+    /// Whether `func` can end up calling itself, directly or trough other functions.
+    pub fn is_recursive(&self, func: Function) -> bool {
+        self.recursive[self.func_index[&func]]
+    }
+
+    /// The strongly connected components of the call graph in bottom-up order: every component comes after all of
+    /// the components it calls into.
+    pub fn sccs(&self) -> &[Vec<Function>] {
+        &self.sccs
+    }
+
+    /// The component `func` belongs to.
+    pub fn scc_of(&self, func: Function) -> &[Function] {
+        &self.sccs[self.scc_index[self.func_index[&func]]]
+    }
+
+    /// The reachable functions in bottom-up order: callees come before their callers, except within recursive cycles.
+    /// This is the order interprocedural passes like inlining want to visit functions in.
+    pub fn bottom_up(&self) -> impl Iterator<Item=Function> + '_ {
+        self.sccs.iter().flatten().copied()
+    }
+}
+
+/// Tarjan's algorithm, made iterative to avoid overflowing the stack on deep call chains. The components are returned
+/// in reverse topological order, so a component comes after all components reachable from it.
+fn strongly_connected_components(successors: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    const UNVISITED: usize = usize::MAX;
+
+    let n = successors.len();
+    let mut index = vec![UNVISITED; n];
+    let mut low_link = vec![0; n];
+    let mut on_stack = vec![false; n];
+    let mut stack = vec![];
+    let mut next_index = 0;
+    let mut result = vec![];
+
+    for root in 0..n {
+        if index[root] != UNVISITED { continue; }
+
+        index[root] = next_index;
+        low_link[root] = next_index;
+        next_index += 1;
+        stack.push(root);
+        on_stack[root] = true;
+        let mut work = vec![(root, 0)];
+
+        while let Some(&(v, next)) = work.last() {
+            if let Some(&w) = successors[v].get(next) {
+                work.last_mut().unwrap().1 += 1;
+
+                if index[w] == UNVISITED {
+                    index[w] = next_index;
+                    low_link[w] = next_index;
+                    next_index += 1;
+                    stack.push(w);
+                    on_stack[w] = true;
+                    work.push((w, 0));
+                } else if on_stack[w] {
+                    low_link[v] = low_link[v].min(index[w]);
+                }
+            } else {
+                work.pop();
+                if let Some(&(parent, _)) = work.last() {
+                    low_link[parent] = low_link[parent].min(low_link[v]);
+                }
+
+                if low_link[v] == index[v] {
+                    let mut scc = vec![];
+                    loop {
+                        let w = stack.pop().unwrap();
+                        on_stack[w] = false;
+                        scc.push(w);
+                        if w == v { break; }
+                    }
+                    result.push(scc);
+                }
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mid::analyse::callgraph::CallGraph;
+    use crate::mid::analyse::use_info::UseInfo;
+    use crate::mid::ir::{CallingConvention, Function, FunctionInfo, FunctionType, InstructionInfo, Program, Terminator, Value};
+
+    fn define_void_func(prog: &mut Program) -> Function {
+        let func_ty = FunctionType { params: vec![], ret: prog.ty_void(), conv: CallingConvention::default() };
+        let info = FunctionInfo::new(func_ty, prog);
+        prog.define_func(info)
+    }
+
+    fn add_call(prog: &mut Program, func: Function, target: Value) -> Value {
+        let entry = prog.get_func(func).entry.block;
+        let call = prog.define_instr(InstructionInfo::Call { target, args: vec![] });
+        prog.get_block_mut(entry).instructions.push(call);
+        Value::Instr(call)
+    }
+
+    #[test]
+    fn recursion_and_address_taken() {
+        //main -> a -> b -> a, main -> d -> a, c -> c
+        //a returns the address of c and main calls a value that isn't known statically
+        let mut prog = Program::default();
+        let main = prog.main;
+        let ty_void = prog.ty_void();
+        let [a, b, c, d, dead] = [(); 5].map(|_| define_void_func(&mut prog));
+        for func in [b, c, d, dead] {
+            let entry = prog.get_func(func).entry.block;
+            prog.get_block_mut(entry).terminator = Terminator::Return { value: Value::Undef(ty_void) };
+        }
+        let a_entry = prog.get_func(a).entry.block;
+        prog.get_block_mut(a_entry).terminator = Terminator::Return { value: Value::Func(c) };
+
+        add_call(&mut prog, main, Value::Func(a));
+        add_call(&mut prog, main, Value::Func(d));
+        add_call(&mut prog, main, Value::Undef(ty_void));
+        add_call(&mut prog, a, Value::Func(b));
+        add_call(&mut prog, b, Value::Func(a));
+        add_call(&mut prog, c, Value::Func(c));
+        add_call(&mut prog, d, Value::Func(a));
+        add_call(&mut prog, dead, Value::Func(a));
+
+        let graph = CallGraph::new(&prog, &UseInfo::new(&prog));
+
+        assert_eq!(main, graph.funcs()[0]);
+        assert_eq!(5, graph.funcs().len());
+        assert!(!graph.contains(dead));
+        let callers_a = graph.callers(a).collect::<Vec<_>>();
+        assert_eq!(3, callers_a.len());
+        assert!(callers_a.contains(&main) && callers_a.contains(&b) && callers_a.contains(&d));
+        assert_eq!(vec![b], graph.callees(a).collect::<Vec<_>>());
+
+        assert!(graph.has_unknown_callers(main));
+        assert!(graph.has_unknown_callers(c));
+        assert!(!graph.has_unknown_callers(a));
+        assert!(graph.has_indirect_calls(main));
+        assert!(!graph.has_indirect_calls(a));
+
+        assert!(graph.is_recursive(a) && graph.is_recursive(b) && graph.is_recursive(c));
+        assert!(!graph.is_recursive(d));
+        //main might call itself trough the indirect call
+        assert!(graph.is_recursive(main));
+        assert_eq!(2, graph.scc_of(a).len());
+        assert!(graph.scc_of(a).contains(&b));
+
+        let order = graph.bottom_up().collect::<Vec<_>>();
+        let pos = |func: Function| order.iter().position(|&f| f == func).unwrap();
+        assert!(pos(d) > pos(a) && pos(d) > pos(b));
+        assert!(pos(main) > pos(d) && pos(main) > pos(c));
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::mid::ir::{Block, Function, Program};
+
+/// The control flow graph of a function, only including the blocks reachable from the entry.
+/// This is a snapshot, it has to be rebuilt (see `CfgCache::invalidate`) when terminators change.
+#[derive(Debug)]
+pub struct Cfg {
+    pub func: Function,
+    /// The reachable blocks in reverse postorder, the entry block comes first.
+    rpo: Vec<Block>,
+    rpo_index: HashMap<Block, usize>,
+    successors: Vec<Vec<usize>>,
+    predecessors: Vec<Vec<usize>>,
+    loops: Vec<Loop>,
+}
+
+/// A natural loop, found trough the back edges to its header.
+#[derive(Debug)]
+pub struct Loop {
+    pub header: Block,
+    /// The blocks that jump back to the header.
+    pub latches: Vec<Block>,
+    /// All blocks in the loop including the header, in reverse postorder.
+    pub blocks: Vec<Block>,
+}
+
+impl Cfg {
+    pub fn new(prog: &Program, func: Function) -> Self {
+        let entry = prog.get_func(func).entry.block;
+
+        //iterative depth first search to get the postorder
+        let mut postorder = vec![];
+        let mut visited = HashSet::new();
+        let mut stack = vec![(entry, successors_of(prog, entry), 0)];
+        visited.insert(entry);
+
+        while let Some((block, succs, next)) = stack.last_mut() {
+            if let Some(&succ) = succs.get(*next) {
+                *next += 1;
+                if visited.insert(succ) {
+                    stack.push((succ, successors_of(prog, succ), 0));
+                }
+            } else {
+                postorder.push(*block);
+                stack.pop();
+            }
+        }
+
+        let rpo: Vec<Block> = postorder.into_iter().rev().collect();
+        let rpo_index: HashMap<Block, usize> = rpo.iter().enumerate().map(|(i, &block)| (block, i)).collect();
+
+        let successors: Vec<Vec<usize>> = rpo.iter()
+            .map(|&block| successors_of(prog, block).iter().map(|succ| rpo_index[succ]).collect())
+            .collect();
+        let mut predecessors = vec![vec![]; rpo.len()];
+        for (bi, succs) in successors.iter().enumerate() {
+            for &si in succs {
+                if !predecessors[si].contains(&bi) {
+                    predecessors[si].push(bi);
+                }
+            }
+        }
+
+        let mut cfg = Cfg { func, rpo, rpo_index, successors, predecessors, loops: vec![] };
+        cfg.loops = cfg.find_loops();
+        cfg
+    }
+
+    /// An edge `from -> to` is a back edge if `to` doesn't come after `from` in reverse postorder, in that case `to`
+    /// is a loop header. The loop consists of all blocks that can reach a latch without passing trough the header.
+    fn find_loops(&self) -> Vec<Loop> {
+        let mut latches_per_header: Vec<Vec<usize>> = vec![vec![]; self.rpo.len()];
+        for (bi, succs) in self.successors.iter().enumerate() {
+            for &si in succs {
+                if si <= bi && !latches_per_header[si].contains(&bi) {
+                    latches_per_header[si].push(bi);
+                }
+            }
+        }
+
+        latches_per_header.iter().enumerate()
+            .filter(|(_, latches)| !latches.is_empty())
+            .map(|(hi, latches)| {
+                let mut in_loop = vec![false; self.rpo.len()];
+                in_loop[hi] = true;
+
+                let mut todo = latches.clone();
+                while let Some(bi) = todo.pop() {
+                    if !in_loop[bi] {
+                        in_loop[bi] = true;
+                        todo.extend(&self.predecessors[bi]);
+                    }
+                }
+
+                Loop {
+                    header: self.rpo[hi],
+                    latches: latches.iter().map(|&li| self.rpo[li]).collect(),
+                    blocks: (0..self.rpo.len()).filter(|&i| in_loop[i]).map(|i| self.rpo[i]).collect(),
+                }
+            })
+            .collect()
+    }
+
+    pub fn entry(&self) -> Block {
+        self.rpo[0]
+    }
+
+    /// The reachable blocks in reverse postorder: every block comes before its successors, except for back edges.
+    pub fn reverse_postorder(&self) -> &[Block] {
+        &self.rpo
+    }
+
+    pub fn is_reachable(&self, block: Block) -> bool {
+        self.rpo_index.contains_key(&block)
+    }
+
+    pub fn successors(&self, block: Block) -> impl Iterator<Item=Block> + '_ {
+        self.successors[self.rpo_index[&block]].iter().map(move |&i| self.rpo[i])
+    }
+
+    /// The distinct reachable blocks that jump to `block`.
+    pub fn predecessors(&self, block: Block) -> impl Iterator<Item=Block> + '_ {
+        self.predecessors[self.rpo_index[&block]].iter().map(move |&i| self.rpo[i])
+    }
+
+    pub fn loops(&self) -> &[Loop] {
+        &self.loops
+    }
+
+    /// The innermost loop containing `block`, if any.
+    pub fn loop_of(&self, block: Block) -> Option<&Loop> {
+        self.loops.iter()
+            .filter(|l| l.blocks.contains(&block))
+            .min_by_key(|l| l.blocks.len())
+    }
+}
+
+/// The successors of `block`, in the order they appear in its terminator. A block is included multiple times if the
+/// terminator jumps to it multiple times.
+fn successors_of(prog: &Program, block: Block) -> Vec<Block> {
+    let mut result = vec![];
+    prog.get_block(block).terminator.for_each_successor(|succ| result.push(succ));
+    result
+}
+
+/// Keeps the `Cfg` of each function around until it's explicitly invalidated. Passes that change terminators must
+/// invalidate the function they changed.
+#[derive(Debug, Default)]
+pub struct CfgCache {
+    cfgs: HashMap<Function, Cfg>,
+}
+
+impl CfgCache {
+    pub fn get(&mut self, prog: &Program, func: Function) -> &Cfg {
+        self.cfgs.entry(func).or_insert_with(|| Cfg::new(prog, func))
+    }
+
+    pub fn invalidate(&mut self, func: Function) {
+        self.cfgs.remove(&func);
+    }
+
+    pub fn invalidate_all(&mut self) {
+        self.cfgs.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mid::analyse::cfg::CfgCache;
+    use crate::mid::ir::{Block, BlockInfo, Const, Program, Target, Terminator, Value};
+
+    fn jump(block: Block) -> Terminator {
+        Terminator::Jump { target: Target { block, phi_values: vec![] } }
+    }
+
+    #[test]
+    fn loop_with_exit() {
+        //entry -> header -> body -> header, header -> exit
+        let mut prog = Program::default();
+        let func = prog.main;
+        let entry = prog.get_func(func).entry.block;
+        let [header, body, exit] = [(); 3].map(|_| prog.define_block(BlockInfo::new()));
+
+        let cond = Value::Const(Const::new(prog.ty_bool(), 1));
+        prog.get_block_mut(entry).terminator = jump(header);
+        prog.get_block_mut(header).terminator = Terminator::Branch {
+            cond,
+            true_target: Target { block: body, phi_values: vec![] },
+            false_target: Target { block: exit, phi_values: vec![] },
+        };
+        prog.get_block_mut(body).terminator = jump(header);
+
+        let mut cache = CfgCache::default();
+        let cfg = cache.get(&prog, func);
+
+        let rpo = cfg.reverse_postorder();
+        assert_eq!(4, rpo.len());
+        assert_eq!(entry, rpo[0]);
+        assert_eq!(header, rpo[1]);
+
+        let mut preds = cfg.predecessors(header).collect::<Vec<_>>();
+        preds.sort_by_key(|&b| rpo.iter().position(|&r| r == b));
+        assert_eq!(vec![entry, body], preds);
+
+        assert_eq!(1, cfg.loops().len());
+        let l = &cfg.loops()[0];
+        assert_eq!(header, l.header);
+        assert_eq!(vec![body], l.latches);
+        assert_eq!(2, l.blocks.len());
+        assert!(cfg.loop_of(exit).is_none());
+
+        //removing the back edge only shows up after invalidating
+        prog.get_block_mut(body).terminator = Terminator::Unreachable;
+        assert_eq!(1, cache.get(&prog, func).loops().len());
+        cache.invalidate(func);
+        assert!(cache.get(&prog, func).loops().is_empty());
+    }
+}
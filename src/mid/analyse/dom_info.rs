@@ -47,8 +47,8 @@ impl DomInfo {
         loop {
             let mut changed = false;
 
-            for bi in 0..blocks.len() {
-                for si in successors[bi].ones() {
+            for (bi, block_succ) in successors.iter().enumerate() {
+                for si in block_succ.ones() {
                     //going trough won't change anything, so just skip it
                     if bi == si { continue; }
 
@@ -73,8 +73,8 @@ impl DomInfo {
         let dominates: Vec<FixedBitSet> = (0..blocks.len())
             .map(|bi| {
                 let mut dominates = FixedBitSet::with_capacity(blocks.len());
-                for di in 0..blocks.len() {
-                    dominates.set(di, dominated_by[di][bi]);
+                for (di, di_dominated_by) in dominated_by.iter().enumerate() {
+                    dominates.set(di, di_dominated_by[bi]);
                 }
                 dominates
             })
@@ -1,2 +1,5 @@
 pub mod use_info;
-pub mod dom_info;
\ No newline at end of file
+pub mod dom_info;
+pub mod cfg;
+pub mod callgraph;
+pub mod purity;
@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+
+use crate::mid::analyse::callgraph::CallGraph;
+use crate::mid::analyse::cfg::Cfg;
+use crate::mid::intrinsic::IntrinsicKind;
+use crate::mid::ir::{Function, InstructionInfo, Program, Value};
+
+/// The side effects of executing an instruction or calling a function.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub struct Purity {
+    pub reads_memory: bool,
+    pub writes_memory: bool,
+    /// Loops forever, aborts the program or otherwise doesn't return normally.
+    pub may_not_terminate: bool,
+}
+
+impl Purity {
+    pub const PURE: Purity = Purity { reads_memory: false, writes_memory: false, may_not_terminate: false };
+    pub const IMPURE: Purity = Purity { reads_memory: true, writes_memory: true, may_not_terminate: true };
+
+    pub fn union(self, other: Purity) -> Purity {
+        Purity {
+            reads_memory: self.reads_memory | other.reads_memory,
+            writes_memory: self.writes_memory | other.writes_memory,
+            may_not_terminate: self.may_not_terminate | other.may_not_terminate,
+        }
+    }
+
+    /// The result only depends on the operands, so executing it twice with the same operands gives the same result.
+    pub fn is_deterministic(self) -> bool {
+        !self.reads_memory && !self.writes_memory
+    }
+
+    /// Executing it has no observable effect other than computing the result, so it can be removed if the result is
+    /// not used.
+    pub fn is_removable(self) -> bool {
+        !self.writes_memory && !self.may_not_terminate
+    }
+}
+
+/// The side effects of every function reachable from main, propagated trough the call graph.
+///
+/// Memory accesses to the stack slots of a function itself don't count as effects of calling that function, since
+/// those slots are fresh for every call. Functions that contain loops or are (possibly) recursive may not terminate.
+#[derive(Debug)]
+pub struct PurityInfo {
+    funcs: HashMap<Function, Purity>,
+}
+
+impl PurityInfo {
+    pub fn new(prog: &Program, graph: &CallGraph) -> Self {
+        let mut funcs: HashMap<Function, Purity> = HashMap::new();
+
+        //the components are bottom-up, so all callees outside of the current component are already known
+        for scc in graph.sccs() {
+            let mut purity = Purity::PURE;
+
+            for &func in scc {
+                let func_info = prog.get_func(func);
+                if func_info.noreturn || graph.is_recursive(func) || !Cfg::new(prog, func).loops().is_empty() {
+                    purity.may_not_terminate = true;
+                }
+
+                prog.visit_blocks(func, |block| {
+                    for &instr in &prog.get_block(block).instructions {
+                        let instr_purity = match prog.get_instr(instr) {
+                            //the component as a whole is being computed right now
+                            InstructionInfo::Call { target: Value::Func(callee), .. } if scc.contains(callee) =>
+                                Purity::PURE,
+                            instr_info => purity_of_instr(&funcs, instr_info, true),
+                        };
+                        purity = purity.union(instr_purity);
+                    }
+                });
+            }
+
+            for &func in scc {
+                funcs.insert(func, purity);
+            }
+        }
+
+        PurityInfo { funcs }
+    }
+
+    /// The effects of calling `func`. Functions that are not reachable from main are assumed to do anything.
+    pub fn of_func(&self, func: Function) -> Purity {
+        self.funcs.get(&func).copied().unwrap_or(Purity::IMPURE)
+    }
+
+    /// The effects of executing `instr_info`, for calls this includes the effects of the callee.
+    pub fn of_instr(&self, instr_info: &InstructionInfo) -> Purity {
+        purity_of_instr(&self.funcs, instr_info, false)
+    }
+}
+
+/// If `ignore_slots` is set, accesses to memory that is directly addressed as a stack slot are not counted.
+fn purity_of_instr(funcs: &HashMap<Function, Purity>, instr_info: &InstructionInfo, ignore_slots: bool) -> Purity {
+    let visible = |addr: Value| !(ignore_slots && matches!(addr, Value::Slot(_)));
+    let reads = |addr: Value| Purity { reads_memory: visible(addr), ..Purity::PURE };
+    let writes = |addr: Value| Purity { writes_memory: visible(addr), ..Purity::PURE };
+
+    // match patterns in this function don't use .. since newly added fields could mean new side effects!
+    match instr_info {
+        &InstructionInfo::Load { addr, ty: _ } => reads(addr),
+        &InstructionInfo::Store { addr, ty: _, value: _ } => writes(addr),
+        &InstructionInfo::MemCopy { dest, source, ty: _ } => writes(dest).union(reads(source)),
+        &InstructionInfo::MemSet { dest, value: _, ty: _ } => writes(dest),
+        InstructionInfo::Call { target, args: _ } => match target {
+            Value::Func(func) => funcs.get(func).copied().unwrap_or(Purity::IMPURE),
+            _ => Purity::IMPURE,
+        },
+        InstructionInfo::Intrinsic { kind, args: _ } => match kind {
+            IntrinsicKind::Trap => Purity { may_not_terminate: true, ..Purity::PURE },
+            _ if kind.info().side_effects => Purity::IMPURE,
+            _ => Purity::PURE,
+        },
+        //the template could do anything
+        InstructionInfo::InlineAsm { template: _, inputs: _, outputs: _ } => Purity::IMPURE,
+        InstructionInfo::Arithmetic { kind: _, left: _, right: _ } |
+        InstructionInfo::Comparison { kind: _, left: _, right: _ } |
+        InstructionInfo::FloatArithmetic { kind: _, left: _, right: _ } |
+        InstructionInfo::FloatComparison { kind: _, left: _, right: _ } |
+        InstructionInfo::TupleFieldPtr { base: _, index: _, tuple_ty: _ } |
+        InstructionInfo::PointerOffSet { ty: _, base: _, index: _ } |
+        InstructionInfo::Cast { kind: _, ty: _, value: _ } |
+        InstructionInfo::Select { cond: _, true_value: _, false_value: _ } => Purity::PURE,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mid::analyse::callgraph::CallGraph;
+    use crate::mid::analyse::purity::{Purity, PurityInfo};
+    use crate::mid::analyse::use_info::UseInfo;
+    use crate::mid::builder::IrBuilder;
+    use crate::mid::ir::{ArithmeticOp, CallingConvention, Function, FunctionInfo, FunctionType, ParameterInfo, Program, Target, Type, Value};
+
+    fn define_func(prog: &mut Program, param_ty: Type) -> (Function, Value) {
+        let ty_int = prog.ty_int();
+        let func_ty = FunctionType { params: vec![param_ty], ret: ty_int, conv: CallingConvention::default() };
+        let mut info = FunctionInfo::new(func_ty, prog);
+        let param = prog.define_param(ParameterInfo { ty: param_ty });
+        info.params.push(param);
+        (prog.define_func(info), Value::Param(param))
+    }
+
+    #[test]
+    fn propagate_effects() {
+        let mut prog = Program::default();
+        let main = prog.main;
+        let ty_int = prog.ty_int();
+        let ty_ptr = prog.ty_ptr();
+
+        //only touches its own slot
+        let (square, x) = define_func(&mut prog, ty_int);
+        let mut builder = IrBuilder::at_entry(&mut prog, square);
+        let slot = builder.build_slot(square, ty_int);
+        builder.build_store(slot, x);
+        let loaded = builder.build_load(ty_int, slot);
+        let result = builder.build_binary(ArithmeticOp::Mul, loaded, x);
+        builder.build_return(result);
+
+        let (reader, p) = define_func(&mut prog, ty_ptr);
+        let mut builder = IrBuilder::at_entry(&mut prog, reader);
+        let loaded = builder.build_load(ty_int, p);
+        builder.build_return(loaded);
+
+        //writes trough its parameter by calling writer
+        let (writer, p) = define_func(&mut prog, ty_ptr);
+        let (calls_writer, q) = define_func(&mut prog, ty_ptr);
+        let mut builder = IrBuilder::at_entry(&mut prog, writer);
+        let zero = builder.const_int(ty_int, 0);
+        builder.build_store(p, zero);
+        builder.build_return(zero);
+        let mut builder = IrBuilder::at_entry(&mut prog, calls_writer);
+        let result = builder.build_call(Value::Func(writer), vec![q]);
+        builder.build_return(result);
+
+        let (looper, _) = define_func(&mut prog, ty_int);
+        let mut builder = IrBuilder::at_entry(&mut prog, looper);
+        let header = builder.new_block();
+        builder.build_jump(Target { block: header, phi_values: vec![] });
+        builder.position_at_end(header);
+        builder.build_jump(Target { block: header, phi_values: vec![] });
+
+        let mut builder = IrBuilder::at_entry(&mut prog, main);
+        let slot = builder.build_slot(main, ty_int);
+        let one = builder.const_int(ty_int, 1);
+        builder.build_call(Value::Func(square), vec![one]);
+        builder.build_call(Value::Func(reader), vec![slot]);
+        builder.build_call(Value::Func(calls_writer), vec![slot]);
+        let result = builder.build_call(Value::Func(looper), vec![one]);
+        builder.build_return(result);
+
+        let purity = PurityInfo::new(&prog, &CallGraph::new(&prog, &UseInfo::new(&prog)));
+
+        assert_eq!(Purity::PURE, purity.of_func(square));
+        assert_eq!(Purity { reads_memory: true, ..Purity::PURE }, purity.of_func(reader));
+        assert_eq!(Purity { writes_memory: true, ..Purity::PURE }, purity.of_func(writer));
+        assert_eq!(purity.of_func(writer), purity.of_func(calls_writer));
+        assert_eq!(Purity { may_not_terminate: true, ..Purity::PURE }, purity.of_func(looper));
+        assert_eq!(Purity::IMPURE, purity.of_func(main));
+
+        assert!(purity.of_func(square).is_deterministic() && purity.of_func(square).is_removable());
+        assert!(!purity.of_func(reader).is_deterministic() && purity.of_func(reader).is_removable());
+        assert!(!purity.of_func(looper).is_removable());
+    }
+}
@@ -42,11 +42,38 @@ pub enum Usage {
 
     //target of TupleFieldPtr
     TupleFieldPtrBase { pos: InstructionPos },
-    //target of ArrayIndexPtr
-    ArrayIndexPtrBase { pos: InstructionPos },
-    //index of ArrayIndexPtr
-
-    ArrayIndexPtrIndex { pos: InstructionPos },
+    //base of PointerOffSet
+    PointerOffSetBase { pos: InstructionPos },
+    //index of PointerOffSet
+    PointerOffSetIndex { pos: InstructionPos },
+
+    //value of Cast
+    CastValue { pos: InstructionPos },
+    //destination of MemCopy or MemSet
+    MemDest { pos: InstructionPos },
+    //source of MemCopy
+    MemCopySource { pos: InstructionPos },
+    //byte value of MemSet
+    MemSetValue { pos: InstructionPos },
+    //condition of Select
+    SelectCond { pos: InstructionPos },
+    //true or false value of Select
+    SelectValue { pos: InstructionPos },
+    //argument of Intrinsic
+    IntrinsicArgument {
+        pos: InstructionPos,
+        index: usize,
+    },
+    //input value of InlineAsm
+    AsmInput {
+        pos: InstructionPos,
+        index: usize,
+    },
+    //output pointer of InlineAsm
+    AsmOutput {
+        pos: InstructionPos,
+        index: usize,
+    },
     //values passed to target as phi value
 
     TargetPhiValue {
@@ -61,6 +88,12 @@ pub enum Usage {
         from_block: Block,
     },
 
+    //switch terminator uses value as the value to switch on
+    SwitchValue {
+        func: Function,
+        from_block: Block,
+    },
+
     //return terminator uses value as return value
     ReturnValue {
         func: Function,
@@ -74,11 +107,15 @@ pub enum TargetKind {
     Jump(Block),
     BranchTrue(Block),
     BranchFalse(Block),
+    SwitchCase(Block, usize),
+    SwitchDefault(Block),
 }
 
 #[derive(Debug)]
 pub struct UseInfo {
     usages: IndexMap<Value, Vec<Usage>>,
+    /// Functions encountered during `new` that still have to be visited.
+    found_funcs: Vec<Function>,
 }
 
 pub fn for_each_usage_in_instr<F: FnMut(Value, Usage)>(
@@ -102,7 +139,9 @@ pub fn for_each_usage_in_instr<F: FnMut(Value, Usage)>(
             }
         }
         &InstructionInfo::Arithmetic { kind: _, left, right } |
-        &InstructionInfo::Comparison { kind: _, left, right } => {
+        &InstructionInfo::Comparison { kind: _, left, right } |
+        &InstructionInfo::FloatArithmetic { kind: _, left, right } |
+        &InstructionInfo::FloatComparison { kind: _, left, right } => {
             f(left, Usage::BinaryOperand { pos });
             f(right, Usage::BinaryOperand { pos });
         }
@@ -110,15 +149,44 @@ pub fn for_each_usage_in_instr<F: FnMut(Value, Usage)>(
             f(base, Usage::TupleFieldPtrBase { pos });
         }
         &InstructionInfo::PointerOffSet { base, index, ty: _ } => {
-            f(base, Usage::ArrayIndexPtrBase { pos });
-            f(index, Usage::ArrayIndexPtrIndex { pos });
+            f(base, Usage::PointerOffSetBase { pos });
+            f(index, Usage::PointerOffSetIndex { pos });
+        }
+        &InstructionInfo::Cast { kind: _, ty: _, value } => {
+            f(value, Usage::CastValue { pos });
+        }
+        &InstructionInfo::MemCopy { dest, source, ty: _ } => {
+            f(dest, Usage::MemDest { pos });
+            f(source, Usage::MemCopySource { pos });
+        }
+        &InstructionInfo::MemSet { dest, value, ty: _ } => {
+            f(dest, Usage::MemDest { pos });
+            f(value, Usage::MemSetValue { pos });
+        }
+        &InstructionInfo::Select { cond, true_value, false_value } => {
+            f(cond, Usage::SelectCond { pos });
+            f(true_value, Usage::SelectValue { pos });
+            f(false_value, Usage::SelectValue { pos });
+        }
+        InstructionInfo::Intrinsic { kind: _, args } => {
+            for (index, &arg) in args.iter().enumerate() {
+                f(arg, Usage::IntrinsicArgument { pos, index });
+            }
+        }
+        InstructionInfo::InlineAsm { template: _, inputs, outputs } => {
+            for (index, input) in inputs.iter().enumerate() {
+                f(input.value, Usage::AsmInput { pos, index });
+            }
+            for (index, output) in outputs.iter().enumerate() {
+                f(output.value, Usage::AsmOutput { pos, index });
+            }
         }
     }
 }
 
 impl UseInfo {
     pub fn new(prog: &Program) -> Self {
-        let mut info = UseInfo { usages: Default::default() };
+        let mut info = UseInfo { usages: Default::default(), found_funcs: vec![] };
 
         info.add_usage(Value::Func(prog.main), Usage::Main);
 
@@ -150,11 +218,6 @@ impl UseInfo {
 
                         for_each_usage_in_instr(pos, instr_info, |value, usage| {
                             info.add_usage(value, usage);
-
-                            //if the usage is a function visit it too
-                            if let Value::Func(func) = value {
-                                todo_funcs.push_back(func);
-                            }
                         });
                     }
 
@@ -171,6 +234,15 @@ impl UseInfo {
                             info.add_target_usages(func, false_target, TargetKind::BranchFalse(block));
                             todo_blocks.push_back((func, false_target.block));
                         }
+                        Terminator::Switch { value, cases, default } => {
+                            info.add_usage(*value, Usage::SwitchValue { func, from_block: block });
+                            for (i, case) in cases.iter().enumerate() {
+                                info.add_target_usages(func, &case.target, TargetKind::SwitchCase(block, i));
+                                todo_blocks.push_back((func, case.target.block));
+                            }
+                            info.add_target_usages(func, default, TargetKind::SwitchDefault(block));
+                            todo_blocks.push_back((func, default.block));
+                        }
                         Terminator::Return { value } => {
                             info.add_usage(*value, Usage::ReturnValue { func, from_block: block });
                         }
@@ -178,6 +250,9 @@ impl UseInfo {
                     }
                 }
             }
+
+            //visit functions used in any way too, not just the ones used by instructions
+            todo_funcs.extend(info.found_funcs.drain(..));
         }
 
         info
@@ -187,6 +262,9 @@ impl UseInfo {
         //we don't care about const
         if let Value::Const(_) = value { return; }
 
+        if let Value::Func(func) = value {
+            self.found_funcs.push(func);
+        }
         self.usages.entry(value).or_default().push(usage);
     }
 
@@ -200,6 +278,11 @@ impl UseInfo {
         }
     }
 
+    /// Iterate over all used values and their usages, in the order they were first encountered.
+    pub fn iter(&self) -> impl Iterator<Item=(Value, &[Usage])> {
+        self.usages.iter().map(|(&value, usages)| (value, &usages[..]))
+    }
+
     //TODO figure out a way to make all of this a lot more typesafe
     pub fn replace_usages(&self, prog: &mut Program, old: Value, new: Value) -> usize {
         assert_ne!(old, new);
@@ -265,11 +348,13 @@ impl UseInfo {
                 Usage::BinaryOperand { pos } => {
                     match prog.get_instr_mut(pos.instr) {
                         InstructionInfo::Arithmetic { left, right, .. } |
-                        InstructionInfo::Comparison { left, right, .. } => {
-                            let mut replaced_any = false;
-                            replaced_any |= maybe_repl(count, left, old, new);
-                            replaced_any |= maybe_repl(count, right, old, new);
-                            assert!(replaced_any);
+                        InstructionInfo::Comparison { left, right, .. } |
+                        InstructionInfo::FloatArithmetic { left, right, .. } |
+                        InstructionInfo::FloatComparison { left, right, .. } => {
+                            //there is a separate usage for each operand, so only replace one of them
+                            if !maybe_repl(count, left, old, new) {
+                                repl(count, right, old, new);
+                            }
                         }
                         _ => unreachable!()
                     }
@@ -281,20 +366,87 @@ impl UseInfo {
                         _ => unreachable!()
                     }
                 }
-                Usage::ArrayIndexPtrBase { pos } => {
+                Usage::PointerOffSetBase { pos } => {
                     match prog.get_instr_mut(pos.instr) {
                         InstructionInfo::PointerOffSet { base, .. } =>
                             repl(count, base, old, new),
                         _ => unreachable!()
                     }
                 }
-                Usage::ArrayIndexPtrIndex { pos } => {
+                Usage::PointerOffSetIndex { pos } => {
                     match prog.get_instr_mut(pos.instr) {
                         InstructionInfo::PointerOffSet { index, .. } =>
                             repl(count, index, old, new),
                         _ => unreachable!()
                     }
                 }
+                Usage::CastValue { pos } => {
+                    match prog.get_instr_mut(pos.instr) {
+                        InstructionInfo::Cast { value, .. } =>
+                            repl(count, value, old, new),
+                        _ => unreachable!()
+                    }
+                }
+                Usage::MemDest { pos } => {
+                    match prog.get_instr_mut(pos.instr) {
+                        InstructionInfo::MemCopy { dest, .. } | InstructionInfo::MemSet { dest, .. } =>
+                            repl(count, dest, old, new),
+                        _ => unreachable!()
+                    }
+                }
+                Usage::MemCopySource { pos } => {
+                    match prog.get_instr_mut(pos.instr) {
+                        InstructionInfo::MemCopy { source, .. } =>
+                            repl(count, source, old, new),
+                        _ => unreachable!()
+                    }
+                }
+                Usage::MemSetValue { pos } => {
+                    match prog.get_instr_mut(pos.instr) {
+                        InstructionInfo::MemSet { value, .. } =>
+                            repl(count, value, old, new),
+                        _ => unreachable!()
+                    }
+                }
+                Usage::SelectCond { pos } => {
+                    match prog.get_instr_mut(pos.instr) {
+                        InstructionInfo::Select { cond, .. } =>
+                            repl(count, cond, old, new),
+                        _ => unreachable!()
+                    }
+                }
+                Usage::SelectValue { pos } => {
+                    match prog.get_instr_mut(pos.instr) {
+                        InstructionInfo::Select { true_value, false_value, .. } => {
+                            //there is a separate usage for each value, so only replace one of them
+                            if !maybe_repl(count, true_value, old, new) {
+                                repl(count, false_value, old, new);
+                            }
+                        }
+                        _ => unreachable!()
+                    }
+                }
+                Usage::IntrinsicArgument { pos, index } => {
+                    match prog.get_instr_mut(pos.instr) {
+                        InstructionInfo::Intrinsic { args, .. } =>
+                            repl(count, &mut args[index], old, new),
+                        _ => unreachable!()
+                    }
+                }
+                Usage::AsmInput { pos, index } => {
+                    match prog.get_instr_mut(pos.instr) {
+                        InstructionInfo::InlineAsm { inputs, .. } =>
+                            repl(count, &mut inputs[index].value, old, new),
+                        _ => unreachable!()
+                    }
+                }
+                Usage::AsmOutput { pos, index } => {
+                    match prog.get_instr_mut(pos.instr) {
+                        InstructionInfo::InlineAsm { outputs, .. } =>
+                            repl(count, &mut outputs[index].value, old, new),
+                        _ => unreachable!()
+                    }
+                }
                 Usage::TargetPhiValue { func, target_kind, phi_index: phi_idx } => {
                     let target = target_kind.get_target_mut(prog, func);
                     repl(count, &mut target.phi_values[phi_idx], old, new);
@@ -305,6 +457,12 @@ impl UseInfo {
                         _ => unreachable!()
                     }
                 }
+                Usage::SwitchValue { from_block, .. } => {
+                    match &mut prog.get_block_mut(from_block).terminator {
+                        Terminator::Switch { value, .. } => repl(count, value, old, new),
+                        _ => unreachable!()
+                    }
+                }
                 Usage::ReturnValue { from_block, .. } => {
                     match &mut prog.get_block_mut(from_block).terminator {
                         Terminator::Return { value, .. } => repl(count, value, old, new),
@@ -318,6 +476,15 @@ impl UseInfo {
     }
 }
 
+impl Program {
+    /// Replace all uses of `old` with `new` in the part of the program reachable from main and return the number of
+    /// replaced uses. This builds a new `UseInfo` every time, passes that replace many values should build it once and
+    /// use `UseInfo::replace_usages` instead.
+    pub fn replace_value(&mut self, old: Value, new: Value) -> usize {
+        UseInfo::new(self).replace_usages(self, old, new)
+    }
+}
+
 impl TargetKind {
     pub fn get_target(self, prog: &Program, func: Function) -> &Target {
         match self {
@@ -340,6 +507,18 @@ impl TargetKind {
                     _ => panic!("Expected to find Terminator::Branch for TargetKind::BranchFalse")
                 }
             }
+            TargetKind::SwitchCase(block, index) => {
+                match &prog.get_block(block).terminator {
+                    Terminator::Switch { cases, .. } => &cases[index].target,
+                    _ => panic!("Expected to find Terminator::Switch for TargetKind::SwitchCase")
+                }
+            }
+            TargetKind::SwitchDefault(block) => {
+                match &prog.get_block(block).terminator {
+                    Terminator::Switch { default, .. } => default,
+                    _ => panic!("Expected to find Terminator::Switch for TargetKind::SwitchDefault")
+                }
+            }
         }
     }
 
@@ -364,6 +543,18 @@ impl TargetKind {
                     _ => panic!("Expected to find Terminator::Branch for TargetKind::BranchFalse")
                 }
             }
+            TargetKind::SwitchCase(block, index) => {
+                match &mut prog.get_block_mut(block).terminator {
+                    Terminator::Switch { cases, .. } => &mut cases[index].target,
+                    _ => panic!("Expected to find Terminator::Switch for TargetKind::SwitchCase")
+                }
+            }
+            TargetKind::SwitchDefault(block) => {
+                match &mut prog.get_block_mut(block).terminator {
+                    Terminator::Switch { default, .. } => default,
+                    _ => panic!("Expected to find Terminator::Switch for TargetKind::SwitchDefault")
+                }
+            }
         }
     }
 }
@@ -376,4 +567,34 @@ impl std::ops::Index<Value> for UseInfo {
     fn index(&self, index: Value) -> &Self::Output {
         self.usages.get(&index).unwrap_or(&EMPTY_USAGE_VEC)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mid::ir::{ArithmeticOp, Const, InstructionInfo, Program, Terminator, Value};
+
+    #[test]
+    fn replace_value() {
+        let mut prog = Program::default();
+        let entry = prog.get_func(prog.main).entry.block;
+        let ty_int = prog.ty_int();
+
+        let one = Value::Const(Const::new(ty_int, 1));
+        let two = Value::Const(Const::new(ty_int, 2));
+        let add = prog.define_instr(InstructionInfo::Arithmetic { kind: ArithmeticOp::Add, left: one, right: one });
+        let mul = prog.define_instr(InstructionInfo::Arithmetic { kind: ArithmeticOp::Mul, left: Value::Instr(add), right: Value::Instr(add) });
+        prog.get_block_mut(entry).instructions.extend([add, mul]);
+        prog.get_block_mut(entry).terminator = Terminator::Return { value: Value::Instr(add) };
+
+        assert_eq!(3, prog.replace_value(Value::Instr(add), two));
+
+        match *prog.get_instr(mul) {
+            InstructionInfo::Arithmetic { left, right, .. } => assert_eq!((two, two), (left, right)),
+            _ => unreachable!(),
+        }
+        match prog.get_block(entry).terminator {
+            Terminator::Return { value } => assert_eq!(two, value),
+            _ => unreachable!(),
+        }
+    }
+}
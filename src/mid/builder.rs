@@ -0,0 +1,329 @@
+use crate::mid::intrinsic::{IntrinsicKind, IntrinsicSignature};
+use crate::mid::ir::{ArithmeticOp, Block, BlockInfo, CastKind, Const, Function, InstructionInfo, LogicalOp, Phi, PhiInfo, Program, StackSlotInfo, Target, Terminator, Type, Value};
+
+/// Where the next instruction built by an `IrBuilder` is inserted.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum InsertPoint {
+    /// Append to the end of the instructions of the block, before its terminator.
+    End(Block),
+    /// Insert before the instruction currently at `index`, the index is moved along as instructions are inserted.
+    Before(Block, usize),
+}
+
+impl InsertPoint {
+    pub fn block(self) -> Block {
+        match self {
+            InsertPoint::End(block) | InsertPoint::Before(block, _) => block,
+        }
+    }
+}
+
+/// A cursor into a `Program` that builds instructions and terminators at an insertion point.
+///
+/// The result types of the built instructions are derived from their operands. Operand types are checked when
+/// building, mismatches are bugs in the caller and cause a panic.
+pub struct IrBuilder<'p> {
+    pub prog: &'p mut Program,
+    point: InsertPoint,
+}
+
+impl<'p> IrBuilder<'p> {
+    /// Create a builder that appends to the end of `block`.
+    pub fn new(prog: &'p mut Program, block: Block) -> Self {
+        IrBuilder { prog, point: InsertPoint::End(block) }
+    }
+
+    /// Create a builder that appends to the end of the entry block of `func`.
+    pub fn at_entry(prog: &'p mut Program, func: Function) -> Self {
+        let block = prog.get_func(func).entry.block;
+        Self::new(prog, block)
+    }
+
+    pub fn insert_point(&self) -> InsertPoint {
+        self.point
+    }
+
+    /// The block instructions are currently inserted into.
+    pub fn block(&self) -> Block {
+        self.point.block()
+    }
+
+    pub fn position_at(&mut self, point: InsertPoint) {
+        self.point = point;
+    }
+
+    pub fn position_at_end(&mut self, block: Block) {
+        self.point = InsertPoint::End(block);
+    }
+
+    pub fn position_before(&mut self, block: Block, index: usize) {
+        assert!(index <= self.prog.get_block(block).instructions.len(), "insert index {} out of range for {:?}", index, block);
+        self.point = InsertPoint::Before(block, index);
+    }
+
+    /// Define a new empty block, the insertion point stays where it is.
+    pub fn new_block(&mut self) -> Block {
+        self.prog.define_block(BlockInfo::new())
+    }
+
+    /// Add a phi of type `ty` to the current block.
+    pub fn build_phi(&mut self, ty: Type) -> Phi {
+        let phi = self.prog.define_phi(PhiInfo { ty });
+        let block = self.block();
+        self.prog.get_block_mut(block).phis.push(phi);
+        phi
+    }
+
+    /// Define a stack slot holding a `inner_ty` in `func`.
+    pub fn build_slot(&mut self, func: Function, inner_ty: Type) -> Value {
+        let slot = self.prog.define_slot(StackSlotInfo { inner_ty });
+        self.prog.get_func_mut(func).slots.push(slot);
+        Value::Slot(slot)
+    }
+
+    pub fn const_int(&self, ty: Type, value: i32) -> Value {
+        assert!(self.prog.get_type(ty).unwrap_int().is_some(), "constant type {} is not an integer", self.prog.format_type(ty));
+        Value::Const(Const::new(ty, value))
+    }
+
+    /// Insert an arbitrary instruction at the insertion point, without any type checks.
+    pub fn build_instr(&mut self, info: InstructionInfo) -> Value {
+        let instr = self.prog.define_instr(info);
+
+        let block_info = self.prog.get_block_mut(self.point.block());
+        match &mut self.point {
+            InsertPoint::End(_) => block_info.instructions.push(instr),
+            InsertPoint::Before(_, index) => {
+                block_info.instructions.insert(*index, instr);
+                *index += 1;
+            }
+        }
+
+        Value::Instr(instr)
+    }
+
+    pub fn build_load(&mut self, ty: Type, addr: Value) -> Value {
+        self.expect_ptr(addr);
+        self.build_instr(InstructionInfo::Load { addr, ty })
+    }
+
+    /// Store `value` into `addr`, the stored type is the type of `value`.
+    pub fn build_store(&mut self, addr: Value, value: Value) -> Value {
+        self.expect_ptr(addr);
+        let ty = self.prog.type_of_value(value);
+        self.build_instr(InstructionInfo::Store { addr, ty, value })
+    }
+
+    /// Build `kind(left, right)`, using float arithmetic if the operands are floats.
+    pub fn build_binary(&mut self, kind: ArithmeticOp, left: Value, right: Value) -> Value {
+        let ty = self.expect_same_type(left, right);
+        let ty_info = self.prog.get_type(ty);
+
+        if ty_info.unwrap_int().is_some() {
+            self.build_instr(InstructionInfo::Arithmetic { kind, left, right })
+        } else if ty_info.unwrap_float().is_some() {
+            assert!(!matches!(kind, ArithmeticOp::Mod(_)), "float modulo is not supported");
+            self.build_instr(InstructionInfo::FloatArithmetic { kind, left, right })
+        } else {
+            panic!("arithmetic on non-numeric type {}", self.prog.format_type(ty))
+        }
+    }
+
+    /// Build the comparison `kind(left, right)`, using a float comparison if the operands are floats.
+    pub fn build_compare(&mut self, kind: LogicalOp, left: Value, right: Value) -> Value {
+        let ty = self.expect_same_type(left, right);
+
+        if self.prog.get_type(ty).unwrap_float().is_some() {
+            self.build_instr(InstructionInfo::FloatComparison { kind, left, right })
+        } else {
+            self.build_instr(InstructionInfo::Comparison { kind, left, right })
+        }
+    }
+
+    pub fn build_call(&mut self, target: Value, args: Vec<Value>) -> Value {
+        let target_ty = self.prog.type_of_value(target);
+        let func_ty = self.prog.get_type(target_ty).unwrap_func()
+            .unwrap_or_else(|| panic!("call target has non-function type {}", self.prog.format_type(target_ty)));
+        assert_eq!(func_ty.params.len(), args.len(), "wrong number of call arguments");
+        for (&param_ty, &arg) in func_ty.params.iter().zip(&args) {
+            self.expect_type(arg, param_ty);
+        }
+
+        self.build_instr(InstructionInfo::Call { target, args })
+    }
+
+    pub fn build_cast(&mut self, kind: CastKind, ty: Type, value: Value) -> Value {
+        self.build_instr(InstructionInfo::Cast { kind, ty, value })
+    }
+
+    pub fn build_select(&mut self, cond: Value, true_value: Value, false_value: Value) -> Value {
+        self.expect_type(cond, self.prog.ty_bool());
+        self.expect_same_type(true_value, false_value);
+        self.build_instr(InstructionInfo::Select { cond, true_value, false_value })
+    }
+
+    pub fn build_tuple_field_ptr(&mut self, tuple_ty: Type, base: Value, index: u32) -> Value {
+        self.expect_ptr(base);
+        self.build_instr(InstructionInfo::TupleFieldPtr { base, index, tuple_ty })
+    }
+
+    pub fn build_pointer_offset(&mut self, ty: Type, base: Value, index: Value) -> Value {
+        self.expect_ptr(base);
+        self.expect_type(index, self.prog.ty_int());
+        self.build_instr(InstructionInfo::PointerOffSet { ty, base, index })
+    }
+
+    /// Copy a value of type `ty` from `source` to `dest`.
+    pub fn build_mem_copy(&mut self, ty: Type, dest: Value, source: Value) -> Value {
+        self.expect_ptr(dest);
+        self.expect_ptr(source);
+        self.build_instr(InstructionInfo::MemCopy { dest, source, ty })
+    }
+
+    /// Fill the memory of a value of type `ty` at `dest` with the byte `value`.
+    pub fn build_mem_set(&mut self, ty: Type, dest: Value, value: Value) -> Value {
+        self.expect_ptr(dest);
+        let ty_byte = self.prog.define_type_int(8);
+        self.expect_type(value, ty_byte);
+        self.build_instr(InstructionInfo::MemSet { dest, value, ty })
+    }
+
+    pub fn build_intrinsic(&mut self, kind: IntrinsicKind, args: Vec<Value>) -> Value {
+        match kind.info().signature {
+            IntrinsicSignature::UnaryInt { whole_bytes } => {
+                assert_eq!(1, args.len(), "intrinsic {} expects a single argument", kind);
+                let ty = self.prog.type_of_value(args[0]);
+                let bits = self.prog.get_type(ty).unwrap_int()
+                    .unwrap_or_else(|| panic!("intrinsic {} on non-integer type {}", kind, self.prog.format_type(ty)));
+                assert!(!whole_bytes || bits.is_multiple_of(8), "intrinsic {} needs whole bytes, got {} bits", kind, bits);
+            }
+            IntrinsicSignature::Nullary => assert!(args.is_empty(), "intrinsic {} expects no arguments", kind),
+        }
+        self.build_instr(InstructionInfo::Intrinsic { kind, args })
+    }
+
+    /// Set the terminator of the current block.
+    pub fn build_terminator(&mut self, terminator: Terminator) {
+        let block = self.block();
+        self.prog.get_block_mut(block).terminator = terminator;
+    }
+
+    pub fn build_jump(&mut self, target: Target) {
+        self.build_terminator(Terminator::Jump { target })
+    }
+
+    pub fn build_branch(&mut self, cond: Value, true_target: Target, false_target: Target) {
+        self.expect_type(cond, self.prog.ty_bool());
+        self.build_terminator(Terminator::Branch { cond, true_target, false_target })
+    }
+
+    pub fn build_return(&mut self, value: Value) {
+        self.build_terminator(Terminator::Return { value })
+    }
+
+    fn expect_type(&self, value: Value, ty: Type) {
+        let actual = self.prog.type_of_value(value);
+        assert_eq!(
+            ty, actual,
+            "expected type {} for {}, got {}", self.prog.format_type(ty), self.prog.format_value(value), self.prog.format_type(actual)
+        );
+    }
+
+    fn expect_ptr(&self, value: Value) {
+        self.expect_type(value, self.prog.ty_ptr());
+    }
+
+    fn expect_same_type(&self, left: Value, right: Value) -> Type {
+        let ty = self.prog.type_of_value(left);
+        self.expect_type(right, ty);
+        ty
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mid::builder::IrBuilder;
+    use crate::mid::ir::{ArithmeticOp, InstructionInfo, LogicalOp, Program, Signedness, Target, Terminator, Value};
+    use crate::mid::verify::verify;
+
+    #[test]
+    fn build_loop() {
+        let mut prog = Program::default();
+        let main = prog.main;
+        let ty_int = prog.ty_int();
+        let mut builder = IrBuilder::at_entry(&mut prog, main);
+
+        let header = builder.new_block();
+        let exit = builder.new_block();
+        let zero = builder.const_int(ty_int, 0);
+        builder.build_jump(Target { block: header, phi_values: vec![zero] });
+
+        builder.position_at_end(header);
+        let i = Value::Phi(builder.build_phi(ty_int));
+        let one = builder.const_int(ty_int, 1);
+        let next = builder.build_binary(ArithmeticOp::Add, i, one);
+        let ten = builder.const_int(ty_int, 10);
+        let cond = builder.build_compare(LogicalOp::Lt(Signedness::Signed), next, ten);
+        builder.build_branch(cond, Target { block: header, phi_values: vec![next] }, Target { block: exit, phi_values: vec![] });
+
+        builder.position_at_end(exit);
+        builder.build_return(next);
+
+        verify(&prog).unwrap();
+        assert_eq!(ty_int, prog.type_of_value(next));
+        assert!(matches!(prog.get_block(exit).terminator, Terminator::Return { .. }));
+    }
+
+    #[test]
+    fn insert_before() {
+        let mut prog = Program::default();
+        let main = prog.main;
+        let ty_int = prog.ty_int();
+        let mut builder = IrBuilder::at_entry(&mut prog, main);
+
+        let two = builder.const_int(ty_int, 2);
+        let last = builder.build_binary(ArithmeticOp::Mul, two, two);
+        let block = builder.block();
+        builder.position_before(block, 0);
+        let first = builder.build_binary(ArithmeticOp::Add, two, two);
+        let second = builder.build_binary(ArithmeticOp::Sub, two, two);
+        builder.build_return(last);
+
+        let instrs: Vec<Value> = prog.get_block(block).instructions.iter().map(|&instr| Value::Instr(instr)).collect();
+        assert_eq!(vec![first, second, last], instrs);
+    }
+
+    #[test]
+    fn store_type() {
+        let mut prog = Program::default();
+        let main = prog.main;
+        let ty_bool = prog.ty_bool();
+        let mut builder = IrBuilder::at_entry(&mut prog, main);
+
+        let slot = builder.build_slot(main, ty_bool);
+        let yes = builder.const_int(ty_bool, 1);
+        let store = builder.build_store(slot, yes);
+
+        let block = builder.block();
+        let instr = prog.get_block(block).instructions[0];
+        assert_eq!(Value::Instr(instr), store);
+        match prog.get_instr(instr) {
+            InstructionInfo::Store { ty, .. } => assert_eq!(ty_bool, *ty),
+            info => panic!("unexpected instruction {:?}", info),
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "expected type")]
+    fn mismatched_operands() {
+        let mut prog = Program::default();
+        let main = prog.main;
+        let ty_int = prog.ty_int();
+        let ty_bool = prog.ty_bool();
+        let mut builder = IrBuilder::at_entry(&mut prog, main);
+
+        let one = builder.const_int(ty_int, 1);
+        let yes = builder.const_int(ty_bool, 1);
+        builder.build_binary(ArithmeticOp::Add, one, yes);
+    }
+}
@@ -0,0 +1,129 @@
+use std::io::Write;
+
+use crate::mid::ir::{Function, Program, Target, Terminator, Value};
+use crate::mid::names::Names;
+
+impl Program {
+    /// Write the control flow graph of `func` as a Graphviz digraph. Every reachable block becomes a node listing its
+    /// phis, instructions and terminator, every target becomes an edge labeled with the phi values it passes.
+    /// The names are the same as the ones used by `Display`. Render it with eg. `dot -Tsvg -O file.dot`.
+    pub fn write_dot(&self, func: Function, w: &mut impl Write) -> std::io::Result<()> {
+        let names = Names::new(self);
+        let func_info = self.get_func(func);
+        let name = func_info.debug_name.as_deref().or(func_info.global_name.as_deref()).unwrap_or("");
+
+        writeln!(w, "digraph \"{}\" {{", escape(&format!("{} {}", names.func(func), name)))?;
+        writeln!(w, "  node [shape=box, fontname=monospace];")?;
+        writeln!(w, "  entry [shape=plaintext, label=\"{}\"];", names.func(func))?;
+        write_edge(self, &names, w, "entry", "", &func_info.entry)?;
+
+        self.try_visit_blocks::<std::io::Error, _>(func, |block| {
+            let block_info = self.get_block(block);
+
+            //\l ends a left-justified line
+            let mut label = format!("{}\\l", names.block(block));
+            for &phi in &block_info.phis {
+                let ty = self.format_type(self.get_phi(phi).ty);
+                label += &escape(&format!("  {}: {}", names.value(self, Value::Phi(phi)), ty));
+                label += "\\l";
+            }
+            for &instr in &block_info.instructions {
+                let instr_info = self.get_instr(instr);
+                label += &escape(&format!("  {} = {}", names.value(self, Value::Instr(instr)), names.instr(self, instr_info)));
+                label += "\\l";
+            }
+            let terminator = match &block_info.terminator {
+                Terminator::Jump { .. } => "Jump".to_owned(),
+                Terminator::Branch { cond, .. } => format!("Branch {}", names.value(self, *cond)),
+                Terminator::Switch { value, .. } => format!("Switch {}", names.value(self, *value)),
+                Terminator::Return { value } => format!("Return {}", names.value(self, *value)),
+                Terminator::Unreachable => "Unreachable".to_owned(),
+            };
+            label += &escape(&format!("  {}", terminator));
+            label += "\\l";
+            writeln!(w, "  {} [label=\"{}\"];", names.block(block), label)?;
+
+            let from = names.block(block).to_string();
+            match &block_info.terminator {
+                Terminator::Jump { target } => write_edge(self, &names, w, &from, "", target)?,
+                Terminator::Branch { true_target, false_target, .. } => {
+                    write_edge(self, &names, w, &from, "true", true_target)?;
+                    write_edge(self, &names, w, &from, "false", false_target)?;
+                }
+                Terminator::Switch { cases, default, .. } => {
+                    for case in cases {
+                        write_edge(self, &names, w, &from, &case.value.to_string(), &case.target)?;
+                    }
+                    write_edge(self, &names, w, &from, "default", default)?;
+                }
+                Terminator::Return { .. } | Terminator::Unreachable => {}
+            }
+
+            Ok(())
+        })?;
+
+        writeln!(w, "}}")?;
+        Ok(())
+    }
+}
+
+/// Write an edge from the node `from` to the block of `target`, labeled with `kind` and the phi values.
+fn write_edge(prog: &Program, names: &Names, w: &mut impl Write, from: &str, kind: &str, target: &Target) -> std::io::Result<()> {
+    let mut label = kind.to_owned();
+    if !target.phi_values.is_empty() {
+        if !label.is_empty() {
+            label += "\\n";
+        }
+        label += &escape(&format!("[{}]", names.values(prog, &target.phi_values)));
+    }
+    writeln!(w, "  {} -> {} [label=\"{}\"];", from, names.block(target.block), label)
+}
+
+/// Escape `s` so it can be used in a quoted Graphviz string.
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mid::builder::IrBuilder;
+    use crate::mid::ir::{ArithmeticOp, LogicalOp, Program, Signedness, Target, Value};
+
+    #[test]
+    fn loop_edges() {
+        let mut prog = Program::default();
+        let main = prog.main;
+        let ty_int = prog.ty_int();
+        prog.get_func_mut(main).debug_name = Some("\"main\"".to_owned());
+        let mut builder = IrBuilder::at_entry(&mut prog, main);
+
+        let header = builder.new_block();
+        let exit = builder.new_block();
+        let zero = builder.const_int(ty_int, 0);
+        builder.build_jump(Target { block: header, phi_values: vec![zero] });
+
+        builder.position_at_end(header);
+        let i = Value::Phi(builder.build_phi(ty_int));
+        let one = builder.const_int(ty_int, 1);
+        let next = builder.build_binary(ArithmeticOp::Add, i, one);
+        let cond = builder.build_compare(LogicalOp::Lt(Signedness::Signed), next, one);
+        builder.build_branch(cond, Target { block: header, phi_values: vec![next] }, Target { block: exit, phi_values: vec![] });
+
+        builder.position_at_end(exit);
+        builder.build_return(next);
+
+        let mut output = vec![];
+        prog.write_dot(main, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        //main is func0, the entry block is block0, the header block1 and the exit block2, next is %1
+        assert!(output.starts_with("digraph \"func0 \\\"main\\\"\" {\n"));
+        assert!(output.ends_with("}\n"));
+        assert!(output.contains("  entry -> block0 [label=\"\"];\n"));
+        assert!(output.contains("  block0 -> block1 [label=\"[Const(0: i32)]\"];\n"));
+        assert!(output.contains("  block1 -> block1 [label=\"true\\n[%1]\"];\n"));
+        assert!(output.contains("  block1 -> block2 [label=\"false\"];\n"));
+        assert!(output.contains("  %1 = Arithmetic { kind: Add, left: %0, right: Const(1: i32) }\\l"));
+        assert!(output.contains("  Return %1\\l"));
+    }
+}
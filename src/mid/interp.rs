@@ -0,0 +1,1256 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt::{Display, Formatter};
+
+use crate::back::layout::{Layout, TupleLayout};
+use crate::mid::intrinsic::IntrinsicKind;
+use crate::mid::ir::{ArithmeticOp, BlockInfo, CastKind, Data, Extern, Function, Global, InstructionInfo, LogicalOp, Program, Signedness, Target, Terminator, Type, TypeInfo, Value};
+use crate::util::zip_eq;
+
+//each kind of address lives in its own region, address 0 is never valid
+const DATA_BASE: u32 = 0x0001_0000;
+const STACK_BASE: u32 = 0x1000_0000;
+const HEAP_BASE: u32 = 0x2000_0000;
+const FUNC_BASE: u32 = 0x4000_0000;
+const EXTERN_BASE: u32 = 0x5000_0000;
+const REGION_SIZE: u32 = 0x1000_0000;
+
+const STD_OUTPUT_HANDLE: i32 = -11;
+const STD_ERROR_HANDLE: i32 = -12;
+const STDOUT: i32 = 1;
+const STDERR: i32 = 2;
+const PROCESS_HEAP: i32 = 1;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum InterpError {
+    Unreachable,
+    InvalidAddress(u32),
+    InvalidCallTarget(u32),
+    DivideByZero,
+    DivideOverflow,
+    UnknownExtern(String),
+    InlineAsm,
+    /// The program executed the `Trap` intrinsic.
+    Trap,
+    StackOverflow,
+    /// The program ran for longer than `Interpreter::max_steps` instructions.
+    StepLimit,
+}
+
+impl Display for InterpError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InterpError::Unreachable => write!(f, "reached an unreachable terminator"),
+            InterpError::InvalidAddress(addr) => write!(f, "invalid memory access at {:#x}", addr),
+            InterpError::InvalidCallTarget(addr) => write!(f, "call to {:#x}, which is not a function", addr),
+            InterpError::DivideByZero => write!(f, "division by zero"),
+            InterpError::DivideOverflow => write!(f, "division overflow"),
+            InterpError::UnknownExtern(name) => write!(f, "call to extern `{}` without a hook", name),
+            InterpError::InlineAsm => write!(f, "inline assembly can't be interpreted"),
+            InterpError::Trap => write!(f, "trap"),
+            InterpError::StackOverflow => write!(f, "stack overflow"),
+            InterpError::StepLimit => write!(f, "step limit reached"),
+        }
+    }
+}
+
+/// The reasons execution can stop early.
+#[derive(Debug)]
+pub enum Stop {
+    /// The program called `ExitProcess` with the given exit code.
+    Exit(i32),
+    Error(InterpError),
+}
+
+impl From<InterpError> for Stop {
+    fn from(e: InterpError) -> Self {
+        Stop::Error(e)
+    }
+}
+
+/// The implementation of an extern function. Gets the arguments as raw little-endian bytes and returns the raw bytes
+/// of the return value.
+pub type ExternHook = fn(&mut Interpreter, &[Vec<u8>]) -> Result<Vec<u8>, Stop>;
+
+/// Run `main` and return its exit code.
+pub fn interpret(prog: &Program) -> Result<i32, InterpError> {
+    Interpreter::new(prog).run_main()
+}
+
+/// Executes a `Program` directly, using the same memory layout as the backend.
+/// Integers narrower than 32 bits are treated as unsigned and `i32` as signed, like constant folding in `sccp`.
+pub struct Interpreter<'p> {
+    prog: &'p Program,
+    pub max_steps: u64,
+    /// The maximum number of nested calls, deeper calls fail with `InterpError::StackOverflow`.
+    pub max_depth: usize,
+    /// Everything written to the standard output handle.
+    pub stdout: Vec<u8>,
+    /// Everything written to the standard error handle.
+    pub stderr: Vec<u8>,
+
+    steps: u64,
+    externs: HashMap<String, ExternHook>,
+
+    data: Vec<u8>,
+    stack: Vec<u8>,
+    heap: Vec<u8>,
+
+    data_addrs: HashMap<Data, u32>,
+    global_addrs: HashMap<Global, u32>,
+    funcs: Vec<Function>,
+    func_indices: HashMap<Function, usize>,
+    exts: Vec<Extern>,
+    ext_indices: HashMap<Extern, usize>,
+}
+
+/// The values defined so far by a function call.
+type Frame = HashMap<Value, Vec<u8>>;
+
+/// A function call that is being executed.
+struct CallFrame<'p> {
+    values: Frame,
+    block: &'p BlockInfo,
+    /// The index of the next instruction of `block` to execute. While a call instruction is being executed, this is
+    /// the index of that instruction.
+    next_instr: usize,
+    /// The size of `Interpreter::stack` before the slots of this call were allocated.
+    stack_start: usize,
+}
+
+impl<'p> Interpreter<'p> {
+    pub fn new(prog: &'p Program) -> Self {
+        //addresses are represented as u32 everywhere
+        assert_eq!(prog.data_layout.ptr_size, 4, "the interpreter only supports 4-byte pointers");
+
+        let mut data = vec![];
+        let mut data_addrs = HashMap::new();
+        for (d, info) in &prog.nodes.datas {
+            data.resize(align(data.len(), 4), 0);
+            data_addrs.insert(d, DATA_BASE + data.len() as u32);
+            data.extend_from_slice(&info.bytes);
+        }
+
+        //globals are also put in the data region, zeroed if they don't have an initializer
+        let mut global_addrs = HashMap::new();
+        for (global, info) in &prog.nodes.globals {
+            let layout = Layout::for_type(prog, info.inner_ty);
+            data.resize(align(data.len(), layout.alignment as usize), 0);
+            global_addrs.insert(global, DATA_BASE + data.len() as u32);
+            match &info.init {
+                Some(init) => data.extend_from_slice(init),
+                None => data.resize(data.len() + layout.size as usize, 0),
+            }
+        }
+
+        let funcs: Vec<Function> = prog.nodes.funcs.iter().map(|(func, _)| func).collect();
+        let exts: Vec<Extern> = prog.nodes.exts.iter().map(|(ext, _)| ext).collect();
+
+        let mut interp = Interpreter {
+            prog,
+            max_steps: 100_000_000,
+            max_depth: 10_000,
+            stdout: vec![],
+            stderr: vec![],
+            steps: 0,
+            externs: HashMap::new(),
+            data,
+            stack: vec![],
+            heap: vec![],
+            data_addrs,
+            global_addrs,
+            func_indices: funcs.iter().enumerate().map(|(i, &func)| (func, i)).collect(),
+            funcs,
+            ext_indices: exts.iter().enumerate().map(|(i, &ext)| (ext, i)).collect(),
+            exts,
+        };
+
+        interp.add_extern("_ExitProcess@4", |_, args| Err(Stop::Exit(arg_int(args, 0))));
+        interp.add_extern("_GetStdHandle@4", |_, args| {
+            let handle = match arg_int(args, 0) {
+                STD_OUTPUT_HANDLE => STDOUT,
+                STD_ERROR_HANDLE => STDERR,
+                _ => -1,
+            };
+            Ok(handle.to_le_bytes().to_vec())
+        });
+        interp.add_extern("_WriteFile@20", |interp, args| {
+            let (handle, buffer, len, written) = (arg_int(args, 0), arg_int(args, 1), arg_int(args, 2), arg_int(args, 3));
+            let bytes = interp.mem(buffer as u32, len as usize)?.to_vec();
+            match handle {
+                STDOUT => interp.stdout.extend(bytes),
+                STDERR => interp.stderr.extend(bytes),
+                _ => return Ok(0i32.to_le_bytes().to_vec()),
+            }
+            if written != 0 {
+                interp.mem(written as u32, 4)?.copy_from_slice(&len.to_le_bytes());
+            }
+            Ok(1i32.to_le_bytes().to_vec())
+        });
+        interp.add_extern("_GetProcessHeap@0", |_, _| Ok(PROCESS_HEAP.to_le_bytes().to_vec()));
+        interp.add_extern("_HeapAlloc@12", |interp, args| {
+            //memory is never actually freed, and it always starts out zeroed
+            let size = arg_int(args, 2) as usize;
+            let start = align(interp.heap.len(), 8);
+            interp.heap.resize(start + size, 0);
+            Ok((HEAP_BASE + start as u32).to_le_bytes().to_vec())
+        });
+        interp.add_extern("_HeapFree@12", |_, _| Ok(1i32.to_le_bytes().to_vec()));
+
+        interp
+    }
+
+    /// Use `hook` to implement calls to the extern with the given name, replacing any existing hook.
+    pub fn add_extern(&mut self, name: &str, hook: ExternHook) {
+        self.externs.insert(name.to_owned(), hook);
+    }
+
+    pub fn run_main(&mut self) -> Result<i32, InterpError> {
+        match self.call(self.prog.main, vec![]) {
+            Ok(result) => Ok(read_int(&result, 32) as i32),
+            Err(Stop::Exit(code)) => Ok(code),
+            Err(Stop::Error(e)) => Err(e),
+        }
+    }
+
+    /// Call `func` with the raw bytes of the given arguments and return the raw bytes of the result.
+    pub fn call(&mut self, func: Function, args: Vec<Vec<u8>>) -> Result<Vec<u8>, Stop> {
+        let stack_start = self.stack.len();
+        let mut calls = vec![];
+
+        let result = self.run(&mut calls, func, args);
+
+        self.stack.truncate(stack_start);
+        result
+    }
+
+    /// Run `func` until it returns. Calls between functions of the program push a `CallFrame` onto `calls` instead of
+    /// recursing, so deeply recursive programs hit `max_depth` instead of overflowing the stack of the interpreter.
+    fn run(&mut self, calls: &mut Vec<CallFrame<'p>>, func: Function, args: Vec<Vec<u8>>) -> Result<Vec<u8>, Stop> {
+        let prog = self.prog;
+        self.push_call(calls, func, args)?;
+
+        loop {
+            let call = calls.last_mut().unwrap();
+
+            let instr = match call.block.instructions.get(call.next_instr) {
+                Some(&instr) => instr,
+                None => {
+                    let target = match &call.block.terminator {
+                        Terminator::Jump { target } => target,
+                        Terminator::Branch { cond, true_target, false_target } => {
+                            if read_int(&self.eval(&call.values, *cond), 1) != 0 { true_target } else { false_target }
+                        }
+                        Terminator::Switch { value, cases, default } => {
+                            let value = self.eval_int(&call.values, *value);
+                            cases.iter()
+                                .find(|case| case.value as i64 == value)
+                                .map_or(default, |case| &case.target)
+                        }
+                        Terminator::Return { value } => {
+                            let result = self.eval(&call.values, *value);
+                            let returned = calls.pop().unwrap();
+                            self.stack.truncate(returned.stack_start);
+
+                            //the caller continues after its call instruction, which gets the result
+                            match calls.last_mut() {
+                                None => return Ok(result),
+                                Some(caller) => {
+                                    let call_instr = caller.block.instructions[caller.next_instr];
+                                    caller.values.insert(Value::Instr(call_instr), result);
+                                    caller.next_instr += 1;
+                                }
+                            }
+                            continue;
+                        }
+                        Terminator::Unreachable => return Err(InterpError::Unreachable.into()),
+                    };
+
+                    self.enter_block(call, target);
+                    continue;
+                }
+            };
+
+            self.steps += 1;
+            if self.steps > self.max_steps {
+                return Err(InterpError::StepLimit.into());
+            }
+
+            let result = match prog.get_instr(instr) {
+                InstructionInfo::Call { target, args } => {
+                    let target = self.eval_addr(&call.values, *target);
+                    let args: Vec<Vec<u8>> = args.iter().map(|&arg| self.eval(&call.values, arg)).collect();
+
+                    match self.func_at(target) {
+                        Some(func) => {
+                            self.push_call(calls, func, args)?;
+                            continue;
+                        }
+                        None => self.call_extern(target, &args)?,
+                    }
+                }
+                instr_info => self.execute(&call.values, instr_info)?,
+            };
+
+            let call = calls.last_mut().unwrap();
+            call.values.insert(Value::Instr(instr), result);
+            call.next_instr += 1;
+        }
+    }
+
+    /// Start a call to `func`: allocate its slots and enter its entry block.
+    fn push_call(&mut self, calls: &mut Vec<CallFrame<'p>>, func: Function, args: Vec<Vec<u8>>) -> Result<(), Stop> {
+        if calls.len() >= self.max_depth {
+            return Err(InterpError::StackOverflow.into());
+        }
+
+        let prog = self.prog;
+        let func_info = prog.get_func(func);
+        let stack_start = self.stack.len();
+        let mut values = Frame::new();
+
+        for (&param, arg) in zip_eq(&func_info.params, args) {
+            values.insert(Value::Param(param), arg);
+        }
+        for &slot in &func_info.slots {
+            let layout = Layout::for_type(prog, prog.get_slot(slot).inner_ty);
+            let start = align(self.stack.len(), layout.alignment as usize);
+            self.stack.resize(start + layout.size as usize, 0);
+            values.insert(Value::Slot(slot), (STACK_BASE + start as u32).to_le_bytes().to_vec());
+        }
+
+        let mut call = CallFrame { values, block: prog.get_block(func_info.entry.block), next_instr: 0, stack_start };
+        self.enter_block(&mut call, &func_info.entry);
+        calls.push(call);
+        Ok(())
+    }
+
+    /// Continue `call` at the start of the block of `target`, assigning its phi values.
+    fn enter_block(&self, call: &mut CallFrame<'p>, target: &Target) {
+        //all phi values are evaluated before any of them are assigned
+        let phi_values: Vec<Vec<u8>> = target.phi_values.iter().map(|&value| self.eval(&call.values, value)).collect();
+        let block_info = self.prog.get_block(target.block);
+        for (&phi, value) in zip_eq(&block_info.phis, phi_values) {
+            call.values.insert(Value::Phi(phi), value);
+        }
+
+        call.block = block_info;
+        call.next_instr = 0;
+    }
+
+    fn execute(&mut self, frame: &Frame, instr: &InstructionInfo) -> Result<Vec<u8>, Stop> {
+        let prog = self.prog;
+
+        let result = match instr {
+            &InstructionInfo::Load { addr, ty } => {
+                let addr = self.eval_addr(frame, addr);
+                self.mem(addr, self.size_of(ty))?.to_vec()
+            }
+            &InstructionInfo::Store { addr, ty: _, value } => {
+                let addr = self.eval_addr(frame, addr);
+                let value = self.eval(frame, value);
+                self.mem(addr, value.len())?.copy_from_slice(&value);
+                vec![]
+            }
+            InstructionInfo::Call { .. } => unreachable!("calls are executed by `run`"),
+            &InstructionInfo::Arithmetic { kind, left, right } => {
+                let ty = prog.type_of_value(left);
+                let bits = self.int_bits(ty);
+                let (left, right) = (self.eval_int(frame, left), self.eval_int(frame, right));
+
+                let result = match kind {
+                    ArithmeticOp::Add => left.wrapping_add(right),
+                    ArithmeticOp::Sub => left.wrapping_sub(right),
+                    ArithmeticOp::Mul => left.wrapping_mul(right),
+                    ArithmeticOp::Div(signedness) | ArithmeticOp::Mod(signedness) => {
+                        let left = signedness.extend(bits, left);
+                        let right = signedness.extend(bits, right);
+                        if right == 0 {
+                            return Err(InterpError::DivideByZero.into());
+                        }
+                        if signedness == Signedness::Signed && left == -(1 << (bits - 1)) && right == -1 {
+                            return Err(InterpError::DivideOverflow.into());
+                        }
+                        if let ArithmeticOp::Div(_) = kind { left / right } else { left % right }
+                    }
+                };
+
+                write_int(result & mask(bits), self.size_of(ty))
+            }
+            &InstructionInfo::Comparison { kind, left, right } => {
+                let ty = prog.type_of_value(left);
+                let (bits, left, right) = if self.is_pointer(ty) {
+                    (32, self.eval_addr(frame, left) as i64, self.eval_addr(frame, right) as i64)
+                } else {
+                    (self.int_bits(ty), self.eval_int(frame, left), self.eval_int(frame, right))
+                };
+                vec![kind.compare_ints(bits, left, right) as u8]
+            }
+            &InstructionInfo::FloatArithmetic { kind, left, right } => {
+                let ty = prog.type_of_value(left);
+                let (left, right) = (self.eval_float(frame, left), self.eval_float(frame, right));
+
+                let result = match kind {
+                    ArithmeticOp::Add => left + right,
+                    ArithmeticOp::Sub => left - right,
+                    ArithmeticOp::Mul => left * right,
+                    ArithmeticOp::Div(_) => left / right,
+                    ArithmeticOp::Mod(_) => unreachable!("float modulo is not supported"),
+                };
+                write_float(result, self.size_of(ty))
+            }
+            &InstructionInfo::FloatComparison { kind, left, right } => {
+                let (left, right) = (self.eval_float(frame, left), self.eval_float(frame, right));
+                let result = match kind {
+                    LogicalOp::Eq => left == right,
+                    LogicalOp::Neq => left != right,
+                    LogicalOp::Gt(_) => left > right,
+                    LogicalOp::Gte(_) => left >= right,
+                    LogicalOp::Lt(_) => left < right,
+                    LogicalOp::Lte(_) => left <= right,
+                };
+                vec![result as u8]
+            }
+            &InstructionInfo::TupleFieldPtr { base, index, tuple_ty } => {
+                let tuple_ty = prog.get_type(tuple_ty).unwrap_tuple().expect("TupleFieldPtr needs a tuple type");
+                let offset = TupleLayout::for_tuple(prog, tuple_ty).offsets[index as usize];
+                let base = self.eval_addr(frame, base);
+                base.wrapping_add(offset as u32).to_le_bytes().to_vec()
+            }
+            &InstructionInfo::PointerOffSet { ty, base, index } => {
+                let base = self.eval_addr(frame, base);
+                let index = self.eval_int(frame, index);
+                let offset = (index as i32).wrapping_mul(self.size_of(ty) as i32);
+                base.wrapping_add(offset as u32).to_le_bytes().to_vec()
+            }
+            &InstructionInfo::MemCopy { dest, source, ty } => {
+                let size = self.size_of(ty);
+                let (dest, source) = (self.eval_addr(frame, dest), self.eval_addr(frame, source));
+                let bytes = self.mem(source, size)?.to_vec();
+                self.mem(dest, size)?.copy_from_slice(&bytes);
+                vec![]
+            }
+            &InstructionInfo::MemSet { dest, value, ty } => {
+                let size = self.size_of(ty);
+                let dest = self.eval_addr(frame, dest);
+                let value = self.eval(frame, value)[0];
+                self.mem(dest, size)?.fill(value);
+                vec![]
+            }
+            &InstructionInfo::Select { cond, true_value, false_value } => {
+                let value = if read_int(&self.eval(frame, cond), 1) != 0 { true_value } else { false_value };
+                self.eval(frame, value)
+            }
+            &InstructionInfo::Cast { kind, ty, value } => {
+                let size = self.size_of(ty);
+                let before = self.eval(frame, value);
+
+                let result = match kind {
+                    CastKind::PointerToInt | CastKind::IntToPointer | CastKind::Truncate | CastKind::ZeroExtend => {
+                        let bits = self.int_bits(prog.type_of_value(value)).min(self.int_bits(ty));
+                        read_unsigned(&before) & mask(bits) as u64
+                    }
+                    CastKind::SignExtend => {
+                        let bits_before = self.int_bits(prog.type_of_value(value));
+                        let shift = 64 - bits_before;
+                        (((read_unsigned(&before) << shift) as i64) >> shift) as u64 & mask(self.int_bits(ty)) as u64
+                    }
+                };
+                result.to_le_bytes()[..size].to_vec()
+            }
+            InstructionInfo::Intrinsic { kind, args } => {
+                match kind {
+                    IntrinsicKind::Trap => return Err(InterpError::Trap.into()),
+                    IntrinsicKind::Ctlz | IntrinsicKind::Cttz | IntrinsicKind::Popcount | IntrinsicKind::Bswap => {
+                        let ty = prog.type_of_value(args[0]);
+                        let bits = self.int_bits(ty);
+                        let value = (self.eval_int(frame, args[0]) & mask(bits)) as u32;
+                        let result = kind.eval_unary_int(bits, value);
+                        write_int(result as i64, self.size_of(ty))
+                    }
+                }
+            }
+            InstructionInfo::InlineAsm { .. } => return Err(InterpError::InlineAsm.into()),
+        };
+
+        Ok(result)
+    }
+
+    /// The function of the program at `addr`, if any.
+    fn func_at(&self, addr: u32) -> Option<Function> {
+        let index = addr.wrapping_sub(FUNC_BASE) as usize;
+        if addr >= FUNC_BASE && index < self.funcs.len() { Some(self.funcs[index]) } else { None }
+    }
+
+    /// Call the hook of the extern at `addr`.
+    fn call_extern(&mut self, addr: u32, args: &[Vec<u8>]) -> Result<Vec<u8>, Stop> {
+        let index = addr.wrapping_sub(EXTERN_BASE) as usize;
+        if addr >= EXTERN_BASE && index < self.exts.len() {
+            let name = &self.prog.get_ext(self.exts[index]).name;
+            let hook = *self.externs.get(name)
+                .ok_or_else(|| InterpError::UnknownExtern(name.clone()))?;
+            return hook(self, args);
+        }
+
+        Err(InterpError::InvalidCallTarget(addr).into())
+    }
+
+    /// The raw little-endian bytes of `value`.
+    fn eval(&self, frame: &Frame, value: Value) -> Vec<u8> {
+        match value {
+            //undefined values can be anything, zero is as good as anything else
+            Value::Undef(ty) => vec![0; self.size_of(ty)],
+            Value::Const(cst) => write_int(cst.value as i64, self.size_of(cst.ty)),
+            Value::Func(func) => (FUNC_BASE + self.func_indices[&func] as u32).to_le_bytes().to_vec(),
+            Value::Extern(ext) => (EXTERN_BASE + self.ext_indices[&ext] as u32).to_le_bytes().to_vec(),
+            Value::Data(data) => self.data_addrs[&data].to_le_bytes().to_vec(),
+            Value::Global(global) => self.global_addrs[&global].to_le_bytes().to_vec(),
+            Value::Param(_) | Value::Slot(_) | Value::Phi(_) | Value::Instr(_) => {
+                frame.get(&value).unwrap_or_else(|| panic!("value {:?} used before it was defined", value)).clone()
+            }
+        }
+    }
+
+    fn eval_addr(&self, frame: &Frame, value: Value) -> u32 {
+        read_unsigned(&self.eval(frame, value)) as u32
+    }
+
+    fn eval_int(&self, frame: &Frame, value: Value) -> i64 {
+        let bits = self.int_bits(self.prog.type_of_value(value));
+        read_int(&self.eval(frame, value), bits)
+    }
+
+    fn eval_float(&self, frame: &Frame, value: Value) -> f64 {
+        let bytes = self.eval(frame, value);
+        match bytes.len() {
+            4 => f32::from_le_bytes(bytes.try_into().unwrap()) as f64,
+            8 => f64::from_le_bytes(bytes.try_into().unwrap()),
+            len => panic!("float with size {}", len),
+        }
+    }
+
+    /// The memory at `addr` with length `len`.
+    pub fn mem(&mut self, addr: u32, len: usize) -> Result<&mut [u8], InterpError> {
+        let (region, base) = match addr / REGION_SIZE * REGION_SIZE {
+            0 if addr >= DATA_BASE => (&mut self.data, DATA_BASE),
+            STACK_BASE => (&mut self.stack, STACK_BASE),
+            HEAP_BASE => (&mut self.heap, HEAP_BASE),
+            _ => return Err(InterpError::InvalidAddress(addr)),
+        };
+
+        let start = (addr - base) as usize;
+        region.get_mut(start..start + len).ok_or(InterpError::InvalidAddress(addr))
+    }
+
+    fn size_of(&self, ty: Type) -> usize {
+        Layout::for_type(self.prog, ty).size as usize
+    }
+
+    fn int_bits(&self, ty: Type) -> u32 {
+        match self.prog.get_type(ty) {
+            TypeInfo::Integer { bits } => *bits,
+            TypeInfo::Pointer | TypeInfo::Func(_) => 32,
+            _ => panic!("expected integer type, got {}", self.prog.format_type(ty)),
+        }
+    }
+
+    fn is_pointer(&self, ty: Type) -> bool {
+        matches!(self.prog.get_type(ty), TypeInfo::Pointer | TypeInfo::Func(_))
+    }
+}
+
+fn align(x: usize, alignment: usize) -> usize {
+    x.div_ceil(alignment) * alignment
+}
+
+/// The mask that keeps only the lowest `bits` bits of a value.
+fn mask(bits: u32) -> i64 {
+    if bits >= 64 { -1 } else { (1i64 << bits) - 1 }
+}
+
+fn read_unsigned(bytes: &[u8]) -> u64 {
+    let mut raw = [0; 8];
+    raw[..bytes.len()].copy_from_slice(bytes);
+    u64::from_le_bytes(raw)
+}
+
+/// Read an integer with the given number of bits, see `Interpreter` for the signedness.
+fn read_int(bytes: &[u8], bits: u32) -> i64 {
+    let value = read_unsigned(bytes) as i64 & mask(bits);
+    if bits == 32 { value as u32 as i32 as i64 } else { value }
+}
+
+fn write_int(value: i64, size: usize) -> Vec<u8> {
+    value.to_le_bytes()[..size].to_vec()
+}
+
+fn write_float(value: f64, size: usize) -> Vec<u8> {
+    match size {
+        4 => (value as f32).to_le_bytes().to_vec(),
+        8 => value.to_le_bytes().to_vec(),
+        _ => panic!("float with size {}", size),
+    }
+}
+
+/// Argument `index` of an extern call as an `i32`.
+pub fn arg_int(args: &[Vec<u8>], index: usize) -> i32 {
+    read_int(&args[index], 32) as i32
+}
+
+#[cfg(test)]
+mod test {
+    use crate::front::runtime::PANIC_EXIT_CODE;
+    use crate::mid::interp::{arg_int, interpret, Interpreter, InterpError, Stop};
+    use crate::mid::builder::IrBuilder;
+    use crate::mid::intrinsic::IntrinsicKind;
+    use crate::mid::ir::{ArithmeticOp, ArrayType, BlockInfo, CallingConvention, CastKind, Const, ExternInfo, FunctionType, GlobalInfo, InstructionInfo, LogicalOp, PhiInfo, Program, Signedness, Target, Terminator, Value};
+    use crate::back::target::I686Windows;
+    use crate::mid::opt::snapshot::{lower_error, lower_modules, lower_modules_error, lower_source};
+    use crate::mid::verify::verify;
+
+    #[test]
+    fn loop_sum() {
+        //for (i = 0; i < 5; i++) { sum += i; }; return sum;
+        let mut prog = Program::default();
+        let entry = prog.get_func(prog.main).entry.block;
+        let [header, body, exit] = [(); 3].map(|_| prog.define_block(BlockInfo::new()));
+
+        let ty_int = prog.ty_int();
+        let i = prog.define_phi(PhiInfo { ty: ty_int });
+        let sum = prog.define_phi(PhiInfo { ty: ty_int });
+        prog.get_block_mut(header).phis.extend([i, sum]);
+
+        let cond = prog.define_instr(InstructionInfo::Comparison { kind: LogicalOp::Lt(Signedness::Signed), left: Value::Phi(i), right: Value::Const(Const::new(ty_int, 5)) });
+        prog.get_block_mut(header).instructions.push(cond);
+        let next_i = prog.define_instr(InstructionInfo::Arithmetic { kind: ArithmeticOp::Add, left: Value::Phi(i), right: Value::Const(Const::new(ty_int, 1)) });
+        let next_sum = prog.define_instr(InstructionInfo::Arithmetic { kind: ArithmeticOp::Add, left: Value::Phi(sum), right: Value::Phi(i) });
+        prog.get_block_mut(body).instructions.extend([next_i, next_sum]);
+
+        let zero = Value::Const(Const::new(ty_int, 0));
+        prog.get_block_mut(entry).terminator = Terminator::Jump { target: Target { block: header, phi_values: vec![zero, zero] } };
+        prog.get_block_mut(header).terminator = Terminator::Branch {
+            cond: Value::Instr(cond),
+            true_target: Target { block: body, phi_values: vec![] },
+            false_target: Target { block: exit, phi_values: vec![] },
+        };
+        prog.get_block_mut(body).terminator = Terminator::Jump {
+            target: Target { block: header, phi_values: vec![Value::Instr(next_i), Value::Instr(next_sum)] },
+        };
+        prog.get_block_mut(exit).terminator = Terminator::Return { value: Value::Phi(sum) };
+
+        assert_eq!(Ok(10), interpret(&prog));
+    }
+
+    #[test]
+    fn slot_store_load() {
+        let mut prog = Program::default();
+        let main = prog.main;
+        let ty_int = prog.ty_int();
+        let mut builder = IrBuilder::at_entry(&mut prog, main);
+
+        let slot = builder.build_slot(main, ty_int);
+        let value = builder.const_int(ty_int, -7);
+        builder.build_store(slot, value);
+        let load = builder.build_load(ty_int, slot);
+        builder.build_return(load);
+
+        assert_eq!(Ok(-7), interpret(&prog));
+    }
+
+    #[test]
+    fn extern_hooks() {
+        //call a custom hook and then ExitProcess with its result
+        let mut prog = Program::default();
+        let main = prog.main;
+        let ty_int = prog.ty_int();
+        let ty_void = prog.ty_void();
+        let conv = CallingConvention::default();
+        let ty_double = prog.define_type_func(FunctionType { params: vec![ty_int], ret: ty_int, conv });
+        let ty_exit = prog.define_type_func(FunctionType { params: vec![ty_int], ret: ty_void, conv });
+        let double = prog.define_ext(ExternInfo { name: "double".to_owned(), ty: ty_double });
+        let exit = prog.define_ext(ExternInfo { name: "_ExitProcess@4".to_owned(), ty: ty_exit });
+
+        let mut builder = IrBuilder::at_entry(&mut prog, main);
+        let arg = builder.const_int(ty_int, 21);
+        let doubled = builder.build_call(Value::Extern(double), vec![arg]);
+        builder.build_call(Value::Extern(exit), vec![doubled]);
+        let zero = builder.const_int(ty_int, 0);
+        builder.build_return(zero);
+
+        assert_eq!(Err(InterpError::UnknownExtern("double".to_owned())), interpret(&prog));
+
+        let mut interp = Interpreter::new(&prog);
+        interp.add_extern("double", |_, args| Ok((2 * arg_int(args, 0)).to_le_bytes().to_vec()));
+        assert_eq!(Ok(42), interp.run_main());
+
+        let mut interp = Interpreter::new(&prog);
+        interp.add_extern("double", |_, _| Err(Stop::Error(InterpError::DivideByZero)));
+        assert_eq!(Err(InterpError::DivideByZero), interp.run_main());
+    }
+
+    #[test]
+    fn select() {
+        //(1 < 2 ? 3 : 4) * 10 + (2 < 1 ? 3 : 4)
+        let mut prog = Program::default();
+        let main = prog.main;
+        let ty_int = prog.ty_int();
+        let mut builder = IrBuilder::at_entry(&mut prog, main);
+
+        let [one, two, three, four, ten] = [1, 2, 3, 4, 10].map(|value| builder.const_int(ty_int, value));
+        let yes = builder.build_compare(LogicalOp::Lt(Signedness::Signed), one, two);
+        let no = builder.build_compare(LogicalOp::Lt(Signedness::Signed), two, one);
+        let first = builder.build_select(yes, three, four);
+        let second = builder.build_select(no, three, four);
+        let scaled = builder.build_binary(ArithmeticOp::Mul, first, ten);
+        let result = builder.build_binary(ArithmeticOp::Add, scaled, second);
+        builder.build_return(result);
+
+        assert_eq!(Ok(34), interpret(&prog));
+    }
+
+    #[test]
+    fn division_signedness() {
+        //(-7 /s 2) * 10 + (-7 /u 2) % 10
+        let mut prog = Program::default();
+        let main = prog.main;
+        let ty_int = prog.ty_int();
+        let mut builder = IrBuilder::at_entry(&mut prog, main);
+
+        let [minus_seven, two, ten] = [-7, 2, 10].map(|value| builder.const_int(ty_int, value));
+        let signed = builder.build_binary(ArithmeticOp::Div(Signedness::Signed), minus_seven, two);
+        let signed = builder.build_binary(ArithmeticOp::Mul, signed, ten);
+        let unsigned = builder.build_binary(ArithmeticOp::Div(Signedness::Unsigned), minus_seven, two);
+        let unsigned = builder.build_binary(ArithmeticOp::Mod(Signedness::Unsigned), unsigned, ten);
+        let result = builder.build_binary(ArithmeticOp::Add, signed, unsigned);
+        builder.build_return(result);
+
+        //0xFFFFFFF9 / 2 = 2147483644
+        assert_eq!(Ok(-30 + 4), interpret(&prog));
+    }
+
+    #[test]
+    fn divide_by_zero() {
+        let mut prog = Program::default();
+        let main = prog.main;
+        let ty_int = prog.ty_int();
+        let mut builder = IrBuilder::at_entry(&mut prog, main);
+
+        let one = builder.const_int(ty_int, 1);
+        let zero = builder.const_int(ty_int, 0);
+        let div = builder.build_binary(ArithmeticOp::Div(Signedness::Signed), one, zero);
+        builder.build_return(div);
+
+        assert_eq!(Err(InterpError::DivideByZero), interpret(&prog));
+    }
+
+    #[test]
+    fn mem_copy_set() {
+        //a = [0x01; 4]; b = a; return b[2]
+        let mut prog = Program::default();
+        let main = prog.main;
+        let ty_int = prog.ty_int();
+        let ty_byte = prog.define_type_int(8);
+        let ty_array = prog.define_type_array(ArrayType { inner: ty_int, length: 4 });
+        let mut builder = IrBuilder::at_entry(&mut prog, main);
+
+        let a = builder.build_slot(main, ty_array);
+        let b = builder.build_slot(main, ty_array);
+        let byte = builder.const_int(ty_byte, 0x01);
+        builder.build_mem_set(ty_array, a, byte);
+        builder.build_mem_copy(ty_array, b, a);
+
+        let two = builder.const_int(ty_int, 2);
+        let ptr = builder.build_pointer_offset(ty_int, b, two);
+        let load = builder.build_load(ty_int, ptr);
+        builder.build_return(load);
+
+        assert_eq!(Ok(0x01010101), interpret(&prog));
+    }
+
+    #[test]
+    fn intrinsics() {
+        //ctlz(0x10: i8) * 100 + cttz(0x30: i16) * 10 + popcount(0x83: i8) + bswap(0x12345678)
+        let mut prog = Program::default();
+        let main = prog.main;
+        let ty_int = prog.ty_int();
+        let ty_byte = prog.define_type_int(8);
+        let ty_short = prog.define_type_int(16);
+        let mut builder = IrBuilder::at_entry(&mut prog, main);
+
+        let widened = |builder: &mut IrBuilder, kind: IntrinsicKind, value: Value| {
+            let result = builder.build_intrinsic(kind, vec![value]);
+            builder.build_cast(CastKind::ZeroExtend, ty_int, result)
+        };
+        let [x10, x83] = [0x10, 0x83].map(|value| builder.const_int(ty_byte, value));
+        let x30 = builder.const_int(ty_short, 0x30);
+        let ctlz = widened(&mut builder, IntrinsicKind::Ctlz, x10);
+        let cttz = widened(&mut builder, IntrinsicKind::Cttz, x30);
+        let popcount = widened(&mut builder, IntrinsicKind::Popcount, x83);
+        let [hundred, ten, word] = [100, 10, 0x12345678].map(|value| builder.const_int(ty_int, value));
+        let bswap = builder.build_intrinsic(IntrinsicKind::Bswap, vec![word]);
+
+        let result = builder.build_binary(ArithmeticOp::Mul, ctlz, hundred);
+        let cttz = builder.build_binary(ArithmeticOp::Mul, cttz, ten);
+        let result = builder.build_binary(ArithmeticOp::Add, result, cttz);
+        let result = builder.build_binary(ArithmeticOp::Add, result, popcount);
+        let result = builder.build_binary(ArithmeticOp::Add, result, bswap);
+        builder.build_return(result);
+
+        verify(&prog).unwrap();
+        assert_eq!(Ok(0x78563412 + 343), interpret(&prog));
+    }
+
+    #[test]
+    fn trap() {
+        let mut prog = Program::default();
+        let main = prog.main;
+        let ty_int = prog.ty_int();
+        let mut builder = IrBuilder::at_entry(&mut prog, main);
+
+        builder.build_intrinsic(IntrinsicKind::Trap, vec![]);
+        let zero = builder.const_int(ty_int, 0);
+        builder.build_return(zero);
+
+        assert_eq!(Err(InterpError::Trap), interpret(&prog));
+    }
+
+    #[test]
+    fn globals() {
+        //counter starts zeroed and is incremented by the initial value of step twice
+        let mut prog = Program::default();
+        let main = prog.main;
+        let ty_int = prog.ty_int();
+        let counter = Value::Global(prog.define_global(GlobalInfo { inner_ty: ty_int, mutable: true, init: None, debug_name: None }));
+        let step = 5i32.to_le_bytes().to_vec();
+        let step = Value::Global(prog.define_global(GlobalInfo { inner_ty: ty_int, mutable: false, init: Some(step), debug_name: None }));
+        let mut builder = IrBuilder::at_entry(&mut prog, main);
+
+        for _ in 0..2 {
+            let before = builder.build_load(ty_int, counter);
+            let step = builder.build_load(ty_int, step);
+            let after = builder.build_binary(ArithmeticOp::Add, before, step);
+            builder.build_store(counter, after);
+        }
+        let result = builder.build_load(ty_int, counter);
+        builder.build_return(result);
+
+        verify(&prog).unwrap();
+        assert_eq!(Ok(10), interpret(&prog));
+    }
+
+    #[test]
+    fn deep_recursion() {
+        let prog = lower_source("
+            fun count(n: int) -> int {
+                if (n == 0) { return 0; }
+                return 1 + count(n - 1);
+            }
+            fun main() -> int { return count(20000); }
+        ");
+
+        //calls don't recurse in the interpreter itself, so only the depth limit stops them
+        let mut interp = Interpreter::new(&prog);
+        assert_eq!(Err(InterpError::StackOverflow), interp.run_main());
+
+        let mut interp = Interpreter::new(&prog);
+        interp.max_depth = 30_000;
+        assert_eq!(Ok(20000), interp.run_main());
+    }
+
+    #[test]
+    fn nested_sibling_functions() {
+        //sibling nested functions can call each other, even before they're declared
+        let prog = lower_source("
+            fun main() -> int {
+                fun a(n: int) -> int { if (n == 0) { return 1; } return b(n - 1) + 1; }
+                fun b(n: int) -> int { return a(n) * 2; }
+                return b(2);
+            }
+        ");
+
+        assert_eq!(Ok(14), interpret(&prog));
+    }
+
+    #[test]
+    fn exhaustive_match_arms_return() {
+        //a match without wildcard covers every variant, so there is no path that misses a return
+        let prog = lower_source("
+            enum Two { A, B }
+            enum Three { A, B(int), C }
+            fun two(e: Two) -> int { match e { Two::A => { return 1; } Two::B => { return 2; } } }
+            fun three(e: Three) -> int {
+                match e {
+                    Three::A => { return 10; }
+                    Three::B(x) => { return x; }
+                    Three::C => { return 30; }
+                }
+            }
+            fun main() -> int { return two(Two::A) + two(Two::B) + three(Three::B(20)) + three(Three::C); }
+        ");
+
+        assert_eq!(Ok(53), interpret(&prog));
+    }
+
+    #[test]
+    fn float_literal_comparison() {
+        //nothing constrains the type of these literals, so they default to f64
+        let prog = lower_source("
+            fun main() -> int {
+                let r = 0;
+                if (-0.0 == 0.0) { r = r + 1; }
+                if (0.5 < 1.5) { r = r + 2; }
+                if (1.0 == 2.0) { r = r + 4; }
+                return r;
+            }
+        ");
+
+        assert_eq!(Ok(3), interpret(&prog));
+    }
+
+    #[test]
+    fn closure_captures_by_reference() {
+        //the closure sees the value of the variable when it's called, not when it was created
+        let prog = lower_source("
+            fun apply(f: |int| -> int, x: int) -> int { return f(x); }
+            fun main() -> int {
+                let k = 1;
+                let add = |x: int| -> int { return x + k; };
+                k = 10;
+                return apply(add, 5);
+            }
+        ");
+
+        assert_eq!(Ok(15), interpret(&prog));
+    }
+
+    #[test]
+    fn closure_writes_captured() {
+        let prog = lower_source("
+            fun main() -> int {
+                let total = 1;
+                let add = |n: int| { total = total + n; };
+                add(2);
+                add(3);
+                return total;
+            }
+        ");
+
+        assert_eq!(Ok(6), interpret(&prog));
+    }
+
+    #[test]
+    fn escaping_closure_rejected() {
+        let sources = [
+            //returned from the function that created it
+            "fun make() -> |int| -> int { let k = 1; return |x: int| -> int { return x + k; }; }
+            fun main() -> int { return make()(1); }",
+            //returned from a closure, inside a tuple
+            "fun main() -> int { let f = || -> (int, || -> int) { return (1, || -> int { return 2; }); }; return 0; }",
+            //stored behind a pointer
+            "fun store(p: &|| -> int, f: || -> int) { *p = f; } fun main() -> int { return 0; }",
+            //stored in a struct
+            "struct S { f: || -> int } fun main() -> int { return 0; }",
+        ];
+
+        for src in sources {
+            let error = lower_error(src);
+            assert!(error.starts_with("error: closures can't outlive the function that created them"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn closure_calls_nested_function() {
+        let prog = lower_source("
+            fun main() -> int {
+                let k = 3;
+                fun double(n: int) -> int { return 2 * n; }
+                let f = |x: int| -> int { return double(x) + k; };
+                return f(5);
+            }
+        ");
+
+        assert_eq!(Ok(13), interpret(&prog));
+    }
+
+    #[test]
+    fn array_index_bounds_check() {
+        let src = "
+            fun get(i: int) -> int {
+                let a: [int; 3] = [10; 3];
+                a[1] = 20;
+                a[2] = 30;
+                return a[i];
+            }
+            fun main() -> int { return get(0) + get(2) + get(ARG); }
+        ";
+
+        let prog = lower_source(&src.replace("ARG", "1"));
+        assert_eq!(Ok(60), interpret(&prog));
+
+        for index in ["3", "-1"] {
+            let prog = lower_source(&src.replace("ARG", index));
+            let mut interp = Interpreter::new(&prog);
+            assert_eq!(Ok(PANIC_EXIT_CODE), interp.run_main());
+            let stderr = String::from_utf8(interp.stderr).unwrap();
+            assert!(stderr.ends_with("index out of bounds\n"), "{}", stderr);
+        }
+    }
+
+    #[test]
+    fn array_repeat() {
+        //a short array is filled with separate stores, a long one with a loop
+        let prog = lower_source("
+            fun main() -> int {
+                let a = [3; 4];
+                let b = [2 as byte; 100];
+                return a[0] + a[3] + b[0] as int + b[99] as int;
+            }
+        ");
+        assert_eq!(Ok(10), interpret(&prog));
+
+        let error = lower_error("fun main() -> int { let a: [int; 3] = [0; 4]; return 0; }");
+        assert!(error.starts_with("error: type mismatch"), "{}", error);
+    }
+
+    #[test]
+    fn named_arguments() {
+        let prog = lower_source("
+            fun area(width: int, height: int, depth: int) -> int { return (width - height) * depth; }
+            fun main() -> int { return area(height: 3, width: 10, depth: 1) + area(20, depth: 2, height: 5); }
+        ");
+        assert_eq!(Ok(37), interpret(&prog));
+
+        let cases = [
+            ("f(y: 1, 2)", "error: positional argument after named argument"),
+            ("f(z: 1)", "error: unknown argument `z`"),
+            ("f(1, x: 2)", "error: argument given twice"),
+            ("f(y: 2)", "error: missing argument for parameter 0"),
+            ("g(x: 1, y: 2)", "error: named arguments are only allowed when calling a function directly"),
+        ];
+        for (call, expected) in cases {
+            let src = format!("fun f(x: int, y: int) -> int {{ return x; }} fun main() -> int {{ let g = f; return {}; }}", call);
+            let error = lower_error(&src);
+            assert!(error.starts_with(expected), "{}", error);
+        }
+    }
+
+    #[test]
+    fn operator_overloading() {
+        let prog = lower_source("
+            struct V { x: int, y: int }
+            fun operator+(a: V, b: V) -> V { return V { x: a.x + b.x, y: a.y + b.y }; }
+            fun operator==(a: V, b: V) -> bool { if (a.x != b.x) { return false; } return a.y == b.y; }
+            fun main() -> int {
+                let v = V { x: 1, y: 2 } + V { x: 10, y: 20 };
+                return v.x + v.y + (v == V { x: 11, y: 22 }) as int;
+            }
+        ");
+        assert_eq!(Ok(34), interpret(&prog));
+
+        let error = lower_error("fun operator+(a: int, b: int) -> int { return 0; } fun main() -> int { return 0; }");
+        assert!(error.starts_with("error: invalid operator function `operator+`"), "{}", error);
+        let error = lower_error("
+            struct V { x: int }
+            fun operator+(a: V, b: V) -> V { return a; }
+            fun operator+(a: V, b: V) -> V { return b; }
+            fun main() -> int { return 0; }
+        ");
+        assert!(error.starts_with("error: operator `operator+` declared twice for these types"), "{}", error);
+    }
+
+    #[test]
+    fn infinite_loop() {
+        //a loop without break diverges, so the function doesn't need a return after it
+        let prog = lower_source("
+            fun first_square_above(n: int) -> int {
+                let i = 0;
+                loop {
+                    if (i * i > n) { return i; }
+                    i = i + 1;
+                }
+            }
+            fun main() -> int {
+                let x = 0;
+                loop {
+                    x = x + 3;
+                    if (x > 10) { break; }
+                }
+                return first_square_above(50) + x;
+            }
+        ");
+        assert_eq!(Ok(20), interpret(&prog));
+
+        let error = lower_error("fun f() -> int { loop { break; } } fun main() -> int { return f(); }");
+        assert!(error.starts_with("error: missing return in function `f`"), "{}", error);
+    }
+
+    #[test]
+    fn do_while_loop() {
+        //the body runs once even though the condition is false, `continue` jumps to the condition
+        let prog = lower_source("
+            fun main() -> int {
+                let once = 0;
+                do { once = once + 1; } while (false);
+                let i = 0;
+                let sum = 0;
+                do {
+                    i = i + 1;
+                    if (i == 2) { continue; }
+                    sum = sum + i;
+                } while (i < 4);
+                return 10 * once + sum;
+            }
+        ");
+        assert_eq!(Ok(18), interpret(&prog));
+
+        let error = lower_error("fun main() -> int { let n: int = 1; do { } while (n); return 0; }");
+        assert!(error.starts_with("error: type mismatch"), "{}", error);
+    }
+
+    #[test]
+    fn for_over_array() {
+        let prog = lower_source("
+            fun main() -> int {
+                let a = [2; 4];
+                a[3] = 5;
+                let total = 0;
+                for x in a { total = total + x; }
+                for x in a[1..] {
+                    if (x == 5) { continue; }
+                    total = total + 10 * x;
+                }
+                return total;
+            }
+        ");
+        assert_eq!(Ok(51), interpret(&prog));
+
+        let error = lower_error("fun main() -> int { for x in 5 { } return 0; }");
+        assert!(error.starts_with("error: cannot iterate over type `int`"), "{}", error);
+        let error = lower_error("fun main() -> int { let a = [1; 3]; let p: &int = &a[0]; for x in p { } return 0; }");
+        assert!(error.starts_with("error: cannot iterate over type `&int`"), "{}", error);
+    }
+
+    #[test]
+    fn range_for_step() {
+        //the direction of a step that's only known at runtime is checked at runtime
+        let prog = lower_source("
+            fun sum(start: int, end: int, by: int) -> int {
+                let total = 0;
+                for i in start..end step by { total = total + i; }
+                return total;
+            }
+            fun main() -> int {
+                let up = 0;
+                for i in 0..10 step 3 { up = up + i; }
+                let down = 0;
+                for i in 10..0 step -2 { down = down + i; }
+                return up + 100 * down + 10000 * sum(5, 0, -1) + 1000000 * sum(0, 5, -1);
+            }
+        ");
+        assert_eq!(Ok(153018), interpret(&prog));
+
+        let error = lower_error("fun main() -> int { let n: int = 10; for i in 0..n step true { } return 0; }");
+        assert!(error.starts_with("error: type mismatch"), "{}", error);
+    }
+
+    #[test]
+    fn block_value() {
+        let prog = lower_source("
+            fun main() -> int {
+                let a = {
+                    let x = 3;
+                    let y = { x * 2 };
+                    x + y
+                };
+                let b: int = { a + 1 };
+                return 10 * a + b;
+            }
+        ");
+        assert_eq!(Ok(100), interpret(&prog));
+
+        //variables declared in the block are not visible after it
+        let error = lower_error("fun main() -> int { let a = { let x = 3; x }; return x; }");
+        assert!(error.starts_with("error: undeclared identifier `x`"), "{}", error);
+        //a block that ends in a statement has no value
+        let error = lower_error("fun main() -> int { let n: int = 3; let a: int = { n; }; return a; }");
+        assert!(error.starts_with("error: type mismatch"), "{}", error);
+    }
+
+    #[test]
+    fn pointer_int_casts() {
+        let prog = lower_source("
+            fun main() -> int {
+                let a = [1; 2];
+                a[1] = 7;
+                let addr = &a[0] as int;
+                let second = (addr + 4) as &int;
+                return *second + (&a[1] as int - addr);
+            }
+        ");
+        assert_eq!(Ok(11), interpret(&prog));
+
+        let error = lower_error("fun main() -> int { let x = 1; let b = &x as byte; return 0; }");
+        assert!(error.starts_with("error: cannot cast `&int` to `byte`"), "{}", error);
+    }
+
+    #[test]
+    fn int_width_casts() {
+        //the values come from parameters so the casts aren't folded away
+        let prog = lower_source("
+            fun convert(x: int, c: bool) -> int {
+                let b = x as byte;
+                return (b as int) + 1000 * (c as int) + 10000 * ((c as byte) as int);
+            }
+            fun main() -> int { return convert(300, true) + convert(-1, false); }
+        ");
+        assert_eq!(Ok(11044 + 255), interpret(&prog));
+
+        let error = lower_error("fun main() -> int { let x: int = 1; let b = x as bool; return 0; }");
+        assert!(error.starts_with("error: cannot cast to `bool`"), "{}", error);
+        let error = lower_error("fun main() -> int { let x: byte = 1 as byte; let p = x as &int; return 0; }");
+        assert!(error.starts_with("error: cannot cast `byte` to `&int`"), "{}", error);
+    }
+
+    #[test]
+    fn string_concatenation() {
+        let modules = |main| [
+            ("main", main),
+            ("string", include_str!("../../lib/string.ll")),
+            ("win32::memory", include_str!("../../lib/win32/memory.ll")),
+        ];
+
+        let main = "
+            fun main() -> int {
+                let s = \"ab\" + \"\" + \"cde\";
+                let last = *(s.ptr + 4);
+                return 10 * s.len + (last == \"e\".ptr[0]) as int;
+            }
+        ";
+        let prog = lower_modules(&modules(main), &I686Windows);
+        assert_eq!(Ok(51), interpret(&prog));
+
+        //only + is overloaded for strings
+        let error = lower_modules_error(&modules("fun main() -> int { let s = \"ab\" - \"cd\"; return 0; }"));
+        assert!(error.starts_with("error: operator `-` can't be applied to type `str`"), "{}", error);
+    }
+
+    #[test]
+    fn assert_statement() {
+        let src = "
+            fun check(x: int) -> int { assert(x < 10); return x; }
+            fun main() -> int { return check(3) + check(ARG); }
+        ";
+
+        let prog = lower_source(&src.replace("ARG", "4"));
+        assert_eq!(Ok(7), interpret(&prog));
+
+        let prog = lower_source(&src.replace("ARG", "10"));
+        let mut interp = Interpreter::new(&prog);
+        assert_eq!(Ok(PANIC_EXIT_CODE), interp.run_main());
+        let stderr = String::from_utf8(interp.stderr).unwrap();
+        assert_eq!("panicked at main.ll:2:40: assertion failed\n", stderr);
+
+        let error = lower_error("fun main() -> int { let x: int = 1; assert(x); return 0; }");
+        assert!(error.starts_with("error: type mismatch"), "{}", error);
+    }
+}
@@ -0,0 +1,104 @@
+use std::fmt::{Display, Formatter};
+
+/// Operations that are too rare to each deserve their own `InstructionInfo` variant, used through
+/// `InstructionInfo::Intrinsic`. Their signature and side effects are described by `IntrinsicKind::info`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum IntrinsicKind {
+    /// Count the leading zero bits, `bits` for zero.
+    Ctlz,
+    /// Count the trailing zero bits, `bits` for zero.
+    Cttz,
+    /// Count the bits that are set.
+    Popcount,
+    /// Reverse the order of the bytes.
+    Bswap,
+    /// Abort the program immediately.
+    Trap,
+}
+
+/// The argument and result types an intrinsic accepts.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum IntrinsicSignature {
+    /// `(iN) -> iN`, if `whole_bytes` is set `N` must be a multiple of 8.
+    UnaryInt { whole_bytes: bool },
+    /// `() -> void`
+    Nullary,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct IntrinsicInfo {
+    pub name: &'static str,
+    pub signature: IntrinsicSignature,
+    /// Whether the intrinsic does anything besides computing its result, if not it can be folded or removed.
+    pub side_effects: bool,
+}
+
+impl IntrinsicKind {
+    pub const ALL: &'static [IntrinsicKind] = &[
+        IntrinsicKind::Ctlz,
+        IntrinsicKind::Cttz,
+        IntrinsicKind::Popcount,
+        IntrinsicKind::Bswap,
+        IntrinsicKind::Trap,
+    ];
+
+    pub fn info(self) -> IntrinsicInfo {
+        let (name, signature, side_effects) = match self {
+            IntrinsicKind::Ctlz => ("ctlz", IntrinsicSignature::UnaryInt { whole_bytes: false }, false),
+            IntrinsicKind::Cttz => ("cttz", IntrinsicSignature::UnaryInt { whole_bytes: false }, false),
+            IntrinsicKind::Popcount => ("popcount", IntrinsicSignature::UnaryInt { whole_bytes: false }, false),
+            IntrinsicKind::Bswap => ("bswap", IntrinsicSignature::UnaryInt { whole_bytes: true }, false),
+            IntrinsicKind::Trap => ("trap", IntrinsicSignature::Nullary, true),
+        };
+        IntrinsicInfo { name, signature, side_effects }
+    }
+
+    pub fn from_name(name: &str) -> Option<IntrinsicKind> {
+        IntrinsicKind::ALL.iter().copied().find(|kind| kind.info().name == name)
+    }
+
+    /// Evaluate an intrinsic with signature `UnaryInt` on the zero-extended `bits`-bit integer `value`.
+    /// The result is zero-extended as well.
+    pub fn eval_unary_int(self, bits: u32, value: u32) -> u32 {
+        assert!(bits <= 32, "integers wider than 32 bits are not supported");
+        match self {
+            IntrinsicKind::Ctlz => value.leading_zeros() - (32 - bits),
+            IntrinsicKind::Cttz => value.trailing_zeros().min(bits),
+            IntrinsicKind::Popcount => value.count_ones(),
+            IntrinsicKind::Bswap => value.swap_bytes() >> (32 - bits),
+            IntrinsicKind::Trap => panic!("{:?} is not a unary integer intrinsic", self),
+        }
+    }
+}
+
+impl Display for IntrinsicKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.info().name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mid::intrinsic::IntrinsicKind;
+
+    #[test]
+    fn names() {
+        for &kind in IntrinsicKind::ALL {
+            assert_eq!(Some(kind), IntrinsicKind::from_name(kind.info().name));
+        }
+        assert_eq!(None, IntrinsicKind::from_name("memcpy"));
+    }
+
+    #[test]
+    fn eval_unary_int() {
+        assert_eq!(8, IntrinsicKind::Ctlz.eval_unary_int(8, 0));
+        assert_eq!(3, IntrinsicKind::Ctlz.eval_unary_int(8, 0x10));
+        assert_eq!(31, IntrinsicKind::Ctlz.eval_unary_int(32, 1));
+        assert_eq!(16, IntrinsicKind::Cttz.eval_unary_int(16, 0));
+        assert_eq!(4, IntrinsicKind::Cttz.eval_unary_int(16, 0x30));
+        assert_eq!(3, IntrinsicKind::Popcount.eval_unary_int(8, 0x83));
+        assert_eq!(0x3412, IntrinsicKind::Bswap.eval_unary_int(16, 0x1234));
+        assert_eq!(0x78563412, IntrinsicKind::Bswap.eval_unary_int(32, 0x12345678));
+        assert_eq!(0xAB, IntrinsicKind::Bswap.eval_unary_int(8, 0xAB));
+    }
+}
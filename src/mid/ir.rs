@@ -1,7 +1,11 @@
-use std::collections::{HashSet, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::{Debug, Display, Formatter};
 use std::hash::Hash;
+use std::path::PathBuf;
 
+use crate::back::layout::DataLayout;
+use crate::mid::intrinsic::{IntrinsicKind, IntrinsicSignature};
+use crate::mid::names::Names;
 use crate::util::arena::{Arena, ArenaSet};
 
 macro_rules! gen_node_and_program_accessors {
@@ -52,13 +56,13 @@ gen_node_and_program_accessors![
     [Instruction, InstructionInfo, define_instr, get_instr, get_instr_mut, instrs],
     [Extern, ExternInfo, define_ext, get_ext, get_ext_mut, exts],
     [Data, DataInfo, define_data, get_data, get_data_mut, datas],
+    [Global, GlobalInfo, define_global, get_global, get_global_mut, globals],
 ];
 
 new_index_type!(pub Type);
 
 #[derive(Debug)]
 pub struct Program {
-    //TODO we've lost distinct indices! is there an easy way to get that back?
     //all values that may be used multiple times are stored as nodes
     pub nodes: Arenas,
     //TODO maybe look into adding a cell here so we can modify this when we have a &Program for usability
@@ -74,6 +78,28 @@ pub struct Program {
     //TODO change program to have multiple possible entries with arbitrary signatures instead
     //  partly for elegance but also because this is too limiting, all extern functions should be considered entry points
     pub main: Function,
+
+    /// The target dependent parts of type layouts, used by `back::layout`.
+    pub data_layout: DataLayout,
+
+    /// The paths of the source files, indexed by `SourceLocation::file`.
+    pub source_files: Vec<PathBuf>,
+    /// The location in the source code each instruction was generated from, if known.
+    pub instr_locations: HashMap<Instruction, SourceLocation>,
+}
+
+/// A position in the source code, lines and columns start at 1.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct SourceLocation {
+    pub file: usize,
+    pub line: u32,
+    pub col: u32,
+}
+
+impl Debug for SourceLocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}]{}:{}", self.file, self.line, self.col)
+    }
 }
 
 impl Default for Program {
@@ -87,7 +113,7 @@ impl Default for Program {
         let ty_bool = types.push(TypeInfo::Integer { bits: 1 });
         let ty_int = types.push(TypeInfo::Integer { bits: 32 });
 
-        let main_func_ty = FunctionType { params: Vec::new(), ret: ty_int };
+        let main_func_ty = FunctionType { params: Vec::new(), ret: ty_int, conv: CallingConvention::default() };
         let main_ty = types.push(TypeInfo::Func(main_func_ty.clone()));
 
         let block = nodes.blocks.push(BlockInfo::new());
@@ -95,7 +121,12 @@ impl Default for Program {
         let main_info = FunctionInfo::new_given_parts(main_func_ty, main_ty, entry);
         let main = nodes.funcs.push(main_info);
 
-        Program { nodes, types, ty_void, ty_ptr, ty_bool, ty_int, main }
+        Program {
+            nodes, types, ty_void, ty_ptr, ty_bool, ty_int, main,
+            data_layout: DataLayout::default(),
+            source_files: Vec::new(),
+            instr_locations: HashMap::new(),
+        }
     }
 }
 
@@ -108,6 +139,10 @@ impl Program {
         self.define_type(TypeInfo::Integer { bits })
     }
 
+    pub fn define_type_float(&mut self, bits: u32) -> Type {
+        self.define_type(TypeInfo::Float { bits })
+    }
+
     pub fn define_type_func(&mut self, func_ty: FunctionType) -> Type {
         self.types.push(TypeInfo::Func(func_ty))
     }
@@ -120,6 +155,10 @@ impl Program {
         self.types.push(TypeInfo::Array(array_ty))
     }
 
+    pub fn define_type_union(&mut self, union_ty: UnionType) -> Type {
+        self.types.push(TypeInfo::Union(union_ty))
+    }
+
     pub fn ty_void(&self) -> Type {
         self.ty_void
     }
@@ -132,6 +171,10 @@ impl Program {
         self.ty_bool
     }
 
+    pub fn ty_int(&self) -> Type {
+        self.ty_int
+    }
+
     pub fn get_type(&self, ty: Type) -> &TypeInfo {
         &self.types[ty]
     }
@@ -147,6 +190,7 @@ impl Program {
             Value::Instr(instr) => self.get_instr(instr).ty(self),
             Value::Extern(ext) => self.get_ext(ext).ty,
             Value::Data(data) => self.get_data(data).ty,
+            Value::Global(_) => self.ty_ptr,
         }
     }
 }
@@ -155,21 +199,59 @@ impl Program {
 pub enum TypeInfo {
     Void,
     Integer { bits: u32 },
+    Float { bits: u32 },
     Pointer,
     Func(FunctionType),
     Tuple(TupleType),
     Array(ArrayType),
+    Union(UnionType),
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub struct FunctionType {
     pub params: Vec<Type>,
     pub ret: Type,
+    pub conv: CallingConvention,
+}
+
+/// How arguments are passed to a function and who removes them from the stack afterwards.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default)]
+pub enum CallingConvention {
+    /// Arguments are pushed right to left and popped by the callee. This is the default.
+    #[default]
+    Stdcall,
+    /// Arguments are pushed right to left and popped by the caller, as expected by the C runtime.
+    Cdecl,
+    /// The System V i386 ABI used by C code on Linux: every argument gets its own 4-byte aligned stack slot, the
+    /// caller pops the arguments and keeps the stack 16-byte aligned at calls, tuples, arrays and unions are returned
+    /// trough a hidden pointer and floats are returned in `st0`.
+    SysV,
+}
+
+impl CallingConvention {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "stdcall" => Some(CallingConvention::Stdcall),
+            "cdecl" => Some(CallingConvention::Cdecl),
+            "sysv" => Some(CallingConvention::SysV),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CallingConvention::Stdcall => "stdcall",
+            CallingConvention::Cdecl => "cdecl",
+            CallingConvention::SysV => "sysv",
+        }
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub struct TupleType {
     pub fields: Vec<Type>,
+    /// Lay out the fields without any padding in between.
+    pub packed: bool,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
@@ -178,6 +260,13 @@ pub struct ArrayType {
     pub length: u32,
 }
 
+/// A blob of memory large enough to hold a value of any of the variant types, all stored at offset 0.
+/// The size of the blob is determined by the backend.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct UnionType {
+    pub variants: Vec<Type>,
+}
+
 impl TypeInfo {
     pub fn unwrap_int(&self) -> Option<u32> {
         match self {
@@ -186,6 +275,13 @@ impl TypeInfo {
         }
     }
 
+    pub fn unwrap_float(&self) -> Option<u32> {
+        match self {
+            &TypeInfo::Float { bits } => Some(bits),
+            _ => None,
+        }
+    }
+
     pub fn is_ptr(&self) -> bool {
         matches!(self, TypeInfo::Pointer)
     }
@@ -210,6 +306,13 @@ impl TypeInfo {
             _ => None,
         }
     }
+
+    pub fn unwrap_union(&self) -> Option<&UnionType> {
+        match self {
+            TypeInfo::Union(ty) => Some(ty),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -218,6 +321,10 @@ pub struct FunctionInfo {
     pub func_ty: FunctionType,
     pub global_name: Option<String>,
     pub debug_name: Option<String>,
+    /// Hint that this function should be inlined into its callers.
+    pub inline: bool,
+    /// Calls to this function never return.
+    pub noreturn: bool,
     pub entry: Target,
     pub params: Vec<Parameter>,
     pub slots: Vec<StackSlot>,
@@ -239,6 +346,8 @@ impl FunctionInfo {
             func_ty,
             global_name: None,
             debug_name: None,
+            inline: false,
+            noreturn: false,
             entry,
             params: Vec::new(),
             slots: Vec::new(),
@@ -263,6 +372,12 @@ pub struct BlockInfo {
     pub terminator: Terminator,
 }
 
+impl Default for BlockInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl BlockInfo {
     /// Create a new empty block with unreachable terminator.
     pub fn new() -> BlockInfo {
@@ -279,7 +394,7 @@ pub struct PhiInfo {
     pub ty: Type,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum InstructionInfo {
     /// Load a value of type `ty` from `addr`.
     ///
@@ -303,9 +418,21 @@ pub enum InstructionInfo {
 
     /// Perform binary comparison operation `kind(left, right)`;
     ///
-    /// `Comparison { kind, left: iN, right: iN } -> i1`
+    /// `Comparison { kind, left: iN, right: iN } -> i1` or `Comparison { kind, left: ptr, right: ptr } -> i1`
     Comparison { kind: LogicalOp, left: Value, right: Value },
 
+    /// Perform binary float arithmetic operation `kind(left, right)`. `ArithmeticOp::Mod` is not supported and the
+    /// signedness of `ArithmeticOp::Div` is ignored.
+    ///
+    /// `FloatArithmetic { kind, left: fN, right: fN } -> fN`
+    FloatArithmetic { kind: ArithmeticOp, left: Value, right: Value },
+
+    /// Perform binary float comparison operation `kind(left, right)`. Comparisons involving NaN are false,
+    /// except for `LogicalOp::Neq` which is true. The signedness of ordered comparisons is ignored.
+    ///
+    /// `FloatComparison { kind, left: fN, right: fN } -> i1`
+    FloatComparison { kind: LogicalOp, left: Value, right: Value },
+
     /// Compute the pointer to a tuple field at `index` in `tuple_ty` from a pointer to containing tuple `base`.
     ///
     /// `TupleFieldPtr { base: &, index=1, tuple_ty=(A, B, C) } -> &`
@@ -317,27 +444,130 @@ pub enum InstructionInfo {
     ///
     /// `PointerOffSet { ty=T, base: &, index: i32 } -> &`
     PointerOffSet { ty: Type, base: Value, index: Value },
+
+    /// Convert `value` to type `ty` as described by `kind`.
+    ///
+    /// `Cast { kind, ty=U, value: T } -> U`
+    Cast { kind: CastKind, ty: Type, value: Value },
+
+    /// Copy a value of type `ty` from `source` to `dest`, without loading it as a whole. The two regions must either
+    /// be the same or not overlap at all. The size and alignment are given by the layout of `ty`.
+    ///
+    /// `MemCopy { dest: &, source: &, ty=T } -> void`
+    MemCopy { dest: Value, source: Value, ty: Type },
+
+    /// Set every byte of the value of type `ty` at `dest` to `value`.
+    ///
+    /// `MemSet { dest: &, value: i8, ty=T } -> void`
+    MemSet { dest: Value, value: Value, ty: Type },
+
+    /// Pick `true_value` if `cond` is true and `false_value` otherwise, without branching.
+    ///
+    /// `Select { cond: bool, true_value: T, false_value: T } -> T`
+    Select { cond: Value, true_value: Value, false_value: Value },
+
+    /// Apply the intrinsic `kind` to `args`, the allowed argument and result types are given by `kind.info()`.
+    ///
+    /// `Intrinsic { kind, args: [A, B] } -> R`
+    Intrinsic { kind: IntrinsicKind, args: Vec<Value> },
+
+    /// Inline assembly. The `inputs` are put in their registers, then `template` is emitted verbatim by the backend,
+    /// so it has to be in the syntax of the assembly output, and finally the output registers are stored to the
+    /// pointers given by `outputs`.
+    ///
+    /// `InlineAsm { template, inputs: [T], outputs: [&] } -> void`
+    InlineAsm { template: String, inputs: Vec<AsmOperand>, outputs: Vec<AsmOperand> },
 }
 
-//TODO what about signed and unsigned? type or operation?
-#[derive(Debug, Copy, Clone)]
+/// An operand of an `InlineAsm` instruction, bound to the register named by `constraint`.
+/// For inputs `value` has type `ty`, for outputs `value` is a pointer to `ty`.
+#[derive(Debug, Clone)]
+pub struct AsmOperand {
+    pub constraint: String,
+    pub ty: Type,
+    pub value: Value,
+}
+
+/// The conversion performed by a `Cast` instruction.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum CastKind {
+    /// `ptr -> i32`, the bits stay the same. Only the lower bits are kept if pointers are larger.
+    PointerToInt,
+    /// `i32 -> ptr`, the bits stay the same. The upper bits are zero if pointers are larger.
+    IntToPointer,
+    /// `iN -> iM` with `M < N`, the upper bits are dropped.
+    Truncate,
+    /// `iN -> iM` with `M > N`, the new upper bits are zero.
+    ZeroExtend,
+    /// `iN -> iM` with `M > N`, the new upper bits are copied from the sign bit.
+    SignExtend,
+}
+
+/// Only division and remainder depend on the signedness, the other operations give the same bits either way.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum ArithmeticOp {
     Add,
     Sub,
     Mul,
-    Div,
-    Mod,
+    /// Division rounding towards zero.
+    Div(Signedness),
+    /// Remainder of `Div`, with the sign of the dividend if signed.
+    Mod(Signedness),
 }
 
-//TODO what about signed and unsigned? type or operation?
-#[derive(Debug, Copy, Clone)]
+/// Whether an integer operation interprets its operands as two's complement or as unsigned numbers.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Signedness {
+    Signed,
+    Unsigned,
+}
+
+impl Signedness {
+    /// Interpret the lowest `bits` bits of `value` as an integer with this signedness.
+    pub fn extend(self, bits: u32, value: i64) -> i64 {
+        assert!((1..=32).contains(&bits), "integer with {} bits", bits);
+
+        let value = value & ((1 << bits) - 1);
+        let negative = value >> (bits - 1) != 0;
+        if self == Signedness::Signed && negative { value - (1 << bits) } else { value }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum LogicalOp {
     Eq,
     Neq,
-    Gt,
-    Gte,
-    Lt,
-    Lte,
+    Gt(Signedness),
+    Gte(Signedness),
+    Lt(Signedness),
+    Lte(Signedness),
+}
+
+impl LogicalOp {
+    /// The signedness of an ordered comparison, `None` for `Eq` and `Neq`.
+    pub fn signedness(self) -> Option<Signedness> {
+        match self {
+            LogicalOp::Eq | LogicalOp::Neq => None,
+            LogicalOp::Gt(s) | LogicalOp::Gte(s) | LogicalOp::Lt(s) | LogicalOp::Lte(s) => Some(s),
+        }
+    }
+
+    /// Evaluate this comparison for integers with `bits` bits. Only the lowest `bits` bits of the operands are used,
+    /// so both sign- and zero-extended operands give the right result.
+    pub fn compare_ints(self, bits: u32, left: i64, right: i64) -> bool {
+        //equality doesn't depend on the signedness
+        let signedness = self.signedness().unwrap_or(Signedness::Unsigned);
+        let (left, right) = (signedness.extend(bits, left), signedness.extend(bits, right));
+
+        match self {
+            LogicalOp::Eq => left == right,
+            LogicalOp::Neq => left != right,
+            LogicalOp::Gt(_) => left > right,
+            LogicalOp::Gte(_) => left >= right,
+            LogicalOp::Lt(_) => left < right,
+            LogicalOp::Lte(_) => left <= right,
+        }
+    }
 }
 
 impl InstructionInfo {
@@ -355,13 +585,165 @@ impl InstructionInfo {
             }
             InstructionInfo::Arithmetic { left, .. } => prog.type_of_value(*left),
             InstructionInfo::Comparison { .. } => prog.ty_bool,
+            InstructionInfo::FloatArithmetic { left, .. } => prog.type_of_value(*left),
+            InstructionInfo::FloatComparison { .. } => prog.ty_bool,
             InstructionInfo::TupleFieldPtr { tuple_ty, index, .. } => {
-                *prog.get_type(*tuple_ty).unwrap_tuple()
+                //check that the index is valid, the result itself is always a pointer
+                prog.get_type(*tuple_ty).unwrap_tuple()
                     .expect("tuple_ty should be a tuple type")
                     .fields.get(*index as usize)
-                    .unwrap_or_else(|| panic!("tuple index {} out of range for {:?} {}", index, tuple_ty, prog.format_type(*tuple_ty)))
+                    .unwrap_or_else(|| panic!("tuple index {} out of range for {:?} {}", index, tuple_ty, prog.format_type(*tuple_ty)));
+                prog.ty_ptr
             },
             InstructionInfo::PointerOffSet { .. } => prog.ty_ptr,
+            InstructionInfo::Cast { ty, .. } => *ty,
+            InstructionInfo::Select { true_value, .. } => prog.type_of_value(*true_value),
+            InstructionInfo::MemCopy { .. } => prog.ty_ptr(),
+            InstructionInfo::MemSet { .. } => prog.ty_ptr(),
+            InstructionInfo::Intrinsic { kind, args } => match kind.info().signature {
+                IntrinsicSignature::UnaryInt { .. } =>
+                    prog.type_of_value(*args.first().expect("unary intrinsic should have an argument")),
+                IntrinsicSignature::Nullary => prog.ty_ptr(),
+            },
+            InstructionInfo::InlineAsm { .. } => prog.ty_ptr(),
+        }
+    }
+
+    /// Call `f` for every value this instruction uses, in the order they appear in the definition.
+    /// Values used multiple times are visited multiple times.
+    pub fn for_each_operand<F: FnMut(Value)>(&self, mut f: F) {
+        // match patterns in this function don't use .. since newly added fields could mean newly added operands!
+        match self {
+            InstructionInfo::Load { addr, ty: _ } => f(*addr),
+            InstructionInfo::Store { addr, ty: _, value } => {
+                f(*addr);
+                f(*value);
+            }
+            InstructionInfo::Call { target, args } => {
+                f(*target);
+                args.iter().copied().for_each(f);
+            }
+            InstructionInfo::Arithmetic { kind: _, left, right } |
+            InstructionInfo::Comparison { kind: _, left, right } |
+            InstructionInfo::FloatArithmetic { kind: _, left, right } |
+            InstructionInfo::FloatComparison { kind: _, left, right } => {
+                f(*left);
+                f(*right);
+            }
+            InstructionInfo::TupleFieldPtr { base, index: _, tuple_ty: _ } => f(*base),
+            InstructionInfo::PointerOffSet { ty: _, base, index } => {
+                f(*base);
+                f(*index);
+            }
+            InstructionInfo::Cast { kind: _, ty: _, value } => f(*value),
+            InstructionInfo::MemCopy { dest, source, ty: _ } => {
+                f(*dest);
+                f(*source);
+            }
+            InstructionInfo::MemSet { dest, value, ty: _ } => {
+                f(*dest);
+                f(*value);
+            }
+            InstructionInfo::Select { cond, true_value, false_value } => {
+                f(*cond);
+                f(*true_value);
+                f(*false_value);
+            }
+            InstructionInfo::Intrinsic { kind: _, args } => args.iter().copied().for_each(f),
+            InstructionInfo::InlineAsm { template: _, inputs, outputs } => {
+                for operand in inputs.iter().chain(outputs) {
+                    f(operand.value);
+                }
+            }
+        }
+    }
+
+    /// If executing this instruction is undefined behaviour no matter what the undefined operands turn out to be,
+    /// return the reason. This is the case for:
+    /// * loads, stores, copies and sets trough an undefined address
+    /// * calls to an undefined target
+    /// * division or remainder by zero or by an undefined value
+    ///
+    /// Code after such an instruction can be assumed to be unreachable.
+    pub fn immediate_ub(&self) -> Option<&'static str> {
+        let undef = |value: Value| matches!(value, Value::Undef(_));
+
+        // match patterns in this function don't use .. since newly added fields could mean new kinds of UB!
+        match self {
+            &InstructionInfo::Load { addr, ty: _ } |
+            &InstructionInfo::Store { addr, ty: _, value: _ } if undef(addr) => Some("memory access trough undefined address"),
+            &InstructionInfo::MemCopy { dest, source, ty: _ } if undef(dest) || undef(source) =>
+                Some("memory access trough undefined address"),
+            &InstructionInfo::MemSet { dest, value: _, ty: _ } if undef(dest) => Some("memory access trough undefined address"),
+            &InstructionInfo::Call { target, args: _ } if undef(target) => Some("call to undefined target"),
+            &InstructionInfo::Arithmetic { kind: ArithmeticOp::Div(_) | ArithmeticOp::Mod(_), left: _, right } => match right {
+                Value::Undef(_) => Some("division by undefined value"),
+                Value::Const(cst) if cst.value == 0 => Some("division by zero"),
+                _ => None,
+            },
+            InstructionInfo::Load { addr: _, ty: _ } |
+            InstructionInfo::Store { addr: _, ty: _, value: _ } |
+            InstructionInfo::MemCopy { dest: _, source: _, ty: _ } |
+            InstructionInfo::MemSet { dest: _, value: _, ty: _ } |
+            InstructionInfo::Call { target: _, args: _ } |
+            InstructionInfo::Arithmetic { kind: _, left: _, right: _ } |
+            InstructionInfo::Comparison { kind: _, left: _, right: _ } |
+            InstructionInfo::FloatArithmetic { kind: _, left: _, right: _ } |
+            InstructionInfo::FloatComparison { kind: _, left: _, right: _ } |
+            InstructionInfo::TupleFieldPtr { base: _, index: _, tuple_ty: _ } |
+            InstructionInfo::PointerOffSet { ty: _, base: _, index: _ } |
+            InstructionInfo::Cast { kind: _, ty: _, value: _ } |
+            InstructionInfo::Select { cond: _, true_value: _, false_value: _ } |
+            InstructionInfo::Intrinsic { kind: _, args: _ } |
+            InstructionInfo::InlineAsm { template: _, inputs: _, outputs: _ } => None,
+        }
+    }
+
+    /// The same as `for_each_operand`, but allows replacing the operands.
+    pub fn for_each_operand_mut<F: FnMut(&mut Value)>(&mut self, mut f: F) {
+        // match patterns in this function don't use .. since newly added fields could mean newly added operands!
+        match self {
+            InstructionInfo::Load { addr, ty: _ } => f(addr),
+            InstructionInfo::Store { addr, ty: _, value } => {
+                f(addr);
+                f(value);
+            }
+            InstructionInfo::Call { target, args } => {
+                f(target);
+                args.iter_mut().for_each(f);
+            }
+            InstructionInfo::Arithmetic { kind: _, left, right } |
+            InstructionInfo::Comparison { kind: _, left, right } |
+            InstructionInfo::FloatArithmetic { kind: _, left, right } |
+            InstructionInfo::FloatComparison { kind: _, left, right } => {
+                f(left);
+                f(right);
+            }
+            InstructionInfo::TupleFieldPtr { base, index: _, tuple_ty: _ } => f(base),
+            InstructionInfo::PointerOffSet { ty: _, base, index } => {
+                f(base);
+                f(index);
+            }
+            InstructionInfo::Cast { kind: _, ty: _, value } => f(value),
+            InstructionInfo::MemCopy { dest, source, ty: _ } => {
+                f(dest);
+                f(source);
+            }
+            InstructionInfo::MemSet { dest, value, ty: _ } => {
+                f(dest);
+                f(value);
+            }
+            InstructionInfo::Select { cond, true_value, false_value } => {
+                f(cond);
+                f(true_value);
+                f(false_value);
+            }
+            InstructionInfo::Intrinsic { kind: _, args } => args.iter_mut().for_each(f),
+            InstructionInfo::InlineAsm { template: _, inputs, outputs } => {
+                for operand in inputs.iter_mut().chain(outputs) {
+                    f(&mut operand.value);
+                }
+            }
         }
     }
 }
@@ -370,6 +752,8 @@ impl InstructionInfo {
 pub enum Terminator {
     Jump { target: Target },
     Branch { cond: Value, true_target: Target, false_target: Target },
+    /// Jump to the target of the first case whose value equals `value`, or to `default` if there is no such case.
+    Switch { value: Value, cases: Vec<SwitchCase>, default: Target },
     Return { value: Value },
     Unreachable,
 }
@@ -380,6 +764,12 @@ pub struct Target {
     pub phi_values: Vec<Value>,
 }
 
+#[derive(Debug, Clone)]
+pub struct SwitchCase {
+    pub value: i32,
+    pub target: Target,
+}
+
 impl Terminator {
     pub fn for_each_target_mut<F: FnMut(&mut Target)>(&mut self, mut f: F) {
         match self {
@@ -388,6 +778,12 @@ impl Terminator {
                 f(true_target);
                 f(false_target);
             }
+            Terminator::Switch { cases, default, .. } => {
+                for case in cases {
+                    f(&mut case.target);
+                }
+                f(default);
+            }
             Terminator::Return { .. } => {}
             Terminator::Unreachable => {}
         }
@@ -400,6 +796,12 @@ impl Terminator {
                 f(true_target);
                 f(false_target);
             }
+            Terminator::Switch { cases, default, .. } => {
+                for case in cases {
+                    f(&case.target);
+                }
+                f(default);
+            }
             Terminator::Return { .. } => {}
             Terminator::Unreachable => {}
         }
@@ -408,11 +810,46 @@ impl Terminator {
     pub fn for_each_successor<F: FnMut(Block)>(&self, mut f: F) {
         self.for_each_target(|target| f(target.block))
     }
+
+    /// Call `f` for every value this terminator uses, including the phi values passed to its targets.
+    pub fn for_each_operand<F: FnMut(Value)>(&self, mut f: F) {
+        match self {
+            Terminator::Branch { cond: value, .. } |
+            Terminator::Switch { value, .. } |
+            Terminator::Return { value } => f(*value),
+            Terminator::Jump { .. } | Terminator::Unreachable => {}
+        }
+        self.for_each_target(|target| target.phi_values.iter().copied().for_each(&mut f));
+    }
+
+    /// Like `InstructionInfo::immediate_ub`, branching or switching on an undefined value is undefined behaviour.
+    pub fn immediate_ub(&self) -> Option<&'static str> {
+        match self {
+            Terminator::Branch { cond: Value::Undef(_), .. } => Some("branch on undefined condition"),
+            Terminator::Switch { value: Value::Undef(_), .. } => Some("switch on undefined value"),
+            Terminator::Jump { .. } | Terminator::Branch { .. } | Terminator::Switch { .. } |
+            Terminator::Return { .. } | Terminator::Unreachable => None,
+        }
+    }
+
+    /// The same as `for_each_operand`, but allows replacing the operands.
+    pub fn for_each_operand_mut<F: FnMut(&mut Value)>(&mut self, mut f: F) {
+        match self {
+            Terminator::Branch { cond: value, .. } |
+            Terminator::Switch { value, .. } |
+            Terminator::Return { value } => f(value),
+            Terminator::Jump { .. } | Terminator::Unreachable => {}
+        }
+        self.for_each_target_mut(|target| target.phi_values.iter_mut().for_each(&mut f));
+    }
 }
 
 //TODO maybe this enum could implement From to make all the wrapping easier?
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum Value {
+    /// An arbitrary value of the given type. Every use can observe a different value, so passes are free to pick
+    /// whatever value is most convenient for each use separately. Using it where the choice matters for control flow
+    /// or memory safety is immediate undefined behaviour, see `InstructionInfo::immediate_ub`.
     Undef(Type),
     Const(Const),
     Func(Function),
@@ -422,6 +859,7 @@ pub enum Value {
     Instr(Instruction),
     Extern(Extern),
     Data(Data),
+    Global(Global),
 }
 
 //TODO should this be represented in the type system instead?
@@ -437,6 +875,7 @@ impl Value {
             Value::Instr(_) => false,
             Value::Extern(_) => true,
             Value::Data(_) => true,
+            Value::Global(_) => true,
         }
     }
 }
@@ -448,6 +887,17 @@ pub struct DataInfo {
     pub bytes: Vec<u8>,
 }
 
+/// A global variable holding a value of type `inner_ty`. It is used through `Value::Global`, which is a pointer to
+/// the variable. Storing to a global that is not `mutable` is undefined behaviour.
+#[derive(Debug)]
+pub struct GlobalInfo {
+    pub inner_ty: Type,
+    pub mutable: bool,
+    /// The initial bytes of the variable, as long as the size of `inner_ty`. The variable starts zeroed if `None`.
+    pub init: Option<Vec<u8>>,
+    pub debug_name: Option<String>,
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct Const {
     pub ty: Type,
@@ -545,16 +995,29 @@ impl Program {
                         write!(f, "void"),
                     TypeInfo::Integer { bits } =>
                         write!(f, "i{}", bits),
+                    TypeInfo::Float { bits } =>
+                        write!(f, "f{}", bits),
                     TypeInfo::Pointer =>
                         write!(f, "&"),
-                    TypeInfo::Tuple(TupleType { fields }) =>
-                        self.prog.write_tuple(f, fields),
-                    TypeInfo::Func(FunctionType { params, ret }) => {
+                    TypeInfo::Tuple(TupleType { fields, packed }) => {
+                        if *packed { write!(f, "packed ")?; }
+                        self.prog.write_tuple(f, fields)
+                    }
+                    TypeInfo::Func(FunctionType { params, ret, conv }) => {
+                        if *conv != CallingConvention::default() { write!(f, "{} ", conv.name())?; }
                         self.prog.write_tuple(f, params)?;
                         write!(f, " -> {}", self.prog.format_type(*ret))
                     }
                     TypeInfo::Array(ArrayType { inner, length }) =>
                         write!(f, "[{}; {}]", self.prog.format_type(*inner), length),
+                    TypeInfo::Union(UnionType { variants }) => {
+                        write!(f, "union(")?;
+                        for (i, &variant) in variants.iter().enumerate() {
+                            if i > 0 { write!(f, " | ")?; }
+                            write!(f, "{}", self.prog.format_type(variant))?;
+                        }
+                        write!(f, ")")
+                    }
                 }
             }
         }
@@ -604,6 +1067,8 @@ impl Program {
                         write!(f, "Extern({:?} -> {}: {})", ext.0, self.prog.get_ext(ext).name, ty),
                     Value::Data(data) =>
                         write!(f, "Data({:?}: {})", data.0, ty),
+                    Value::Global(global) =>
+                        write!(f, "Global({:?}: {})", global.0, ty),
                 }
             }
         }
@@ -613,17 +1078,64 @@ impl Program {
 }
 
 impl Display for Program {
+    /// Print the program using the local names from `Names`, the definitions are followed by the arena index they
+    /// stand for.
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "Program (nodes: {}) {{", self.nodes.total_node_count())?;
-        writeln!(f, "  main: {:?}", self.main)?;
+        self.fmt_ir(f, true)
+    }
+}
 
-        writeln!(f, "  types:")?;
-        for (ty, _) in &self.types {
-            writeln!(f, "    {:?}: {}", ty, self.format_type(ty))?
+/// See `Program::display_stable`.
+pub struct StableDisplay<'a>(&'a Program);
+
+impl Display for StableDisplay<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt_ir(f, false)
+    }
+}
+
+impl Program {
+    /// Display the program without the arena indices, the list of types and the source locations, so the output only
+    /// changes if the IR itself changes. This is the format snapshot tests compare against.
+    pub fn display_stable(&self) -> StableDisplay<'_> {
+        StableDisplay(self)
+    }
+
+    fn fmt_ir(&self, f: &mut Formatter<'_>, indices: bool) -> std::fmt::Result {
+        let names = Names::new(self);
+        let index = |node: &dyn Debug| if indices { format!(" ({:?})", node) } else { String::new() };
+
+        if indices {
+            writeln!(f, "Program (nodes: {}) {{", self.nodes.total_node_count())?;
+        } else {
+            writeln!(f, "Program {{")?;
+        }
+        writeln!(f, "  main: {}", names.func(self.main))?;
+
+        if indices {
+            writeln!(f, "  types:")?;
+            for (ty, _) in &self.types {
+                writeln!(f, "    {:?}: {}", ty, self.format_type(ty))?
+            }
+        }
+
+        if self.nodes.globals.len() > 0 {
+            writeln!(f, "  globals:")?;
+            for (global, global_info) in &self.nodes.globals {
+                let mutable = if global_info.mutable { "mut " } else { "" };
+                write!(f, "    {}{}: {}{}", names.value(self, Value::Global(global)), index(&global), mutable, self.format_type(global_info.inner_ty))?;
+                if let Some(debug_name) = &global_info.debug_name {
+                    write!(f, " ({})", debug_name)?;
+                }
+                match &global_info.init {
+                    Some(init) => writeln!(f, " = {:?}", init)?,
+                    None => writeln!(f)?,
+                }
+            }
         }
 
         for (func, func_info) in &self.nodes.funcs {
-            writeln!(f, "  {:?}: {} {{", func, self.format_type(func_info.ty))?;
+            writeln!(f, "  {}{}: {} {{", names.func(func), index(&func), self.format_type(func_info.ty))?;
 
             if let Some(global_name) = &func_info.global_name {
                 writeln!(f, "    global_name: {}", global_name)?;
@@ -631,54 +1143,74 @@ impl Display for Program {
             if let Some(debug_name) = &func_info.debug_name {
                 writeln!(f, "    debug_name: {}", debug_name)?;
             }
+            if func_info.inline {
+                writeln!(f, "    inline")?;
+            }
+            if func_info.noreturn {
+                writeln!(f, "    noreturn")?;
+            }
 
             if !func_info.params.is_empty() {
                 writeln!(f, "    params:")?;
                 for &param in &func_info.params {
                     let param_info = self.get_param(param);
-                    writeln!(f, "      {:?}: {}", param, self.format_type(param_info.ty))?;
+                    writeln!(f, "      {}{}: {}", names.value(self, Value::Param(param)), index(&param), self.format_type(param_info.ty))?;
                 }
             }
             if !func_info.slots.is_empty() {
                 writeln!(f, "    slots:")?;
                 for &slot in &func_info.slots {
                     let slot_info = self.get_slot(slot);
-                    writeln!(f, "      {:?}: &{}", slot, self.format_type(slot_info.inner_ty))?;
+                    writeln!(f, "      {}{}: &{}", names.value(self, Value::Slot(slot)), index(&slot), self.format_type(slot_info.inner_ty))?;
                 }
             }
-            writeln!(f, "    entry: {:?}", func_info.entry)?;
+            writeln!(f, "    entry: {}", names.target(self, &func_info.entry))?;
 
             self.try_visit_blocks(func, |block| {
                 let block_info = self.get_block(block);
-                writeln!(f, "    {:?} {{", block)?;
+                writeln!(f, "    {}{} {{", names.block(block), index(&block))?;
 
                 if !block_info.phis.is_empty() {
                     writeln!(f, "      phis:")?;
                     for &phi in &block_info.phis {
                         let phi_info = self.get_phi(phi);
-                        writeln!(f, "        {:?}: {}", phi, self.format_type(phi_info.ty))?;
+                        writeln!(f, "        {}{}: {}", names.value(self, Value::Phi(phi)), index(&phi), self.format_type(phi_info.ty))?;
                     }
                 }
 
                 for &instr in &block_info.instructions {
                     let instr_info = self.get_instr(instr);
-                    writeln!(f, "      {:?}: {:?}", instr, instr_info)?;
+                    write!(f, "      {}{} = {}", names.value(self, Value::Instr(instr)), index(&instr), names.instr(self, instr_info))?;
+                    match self.instr_locations.get(&instr) {
+                        Some(location) if indices => writeln!(f, " @ {:?}", location)?,
+                        _ => writeln!(f)?,
+                    }
                 }
 
                 match &block_info.terminator {
                     Terminator::Jump { target } => {
-                        writeln!(f, "      Jump {{")?;
-                        writeln!(f, "        {:?}", target)?;
-                        writeln!(f, "      }}")?;
+                        writeln!(f, "      Jump {}", names.target(self, target))?;
                     }
                     Terminator::Branch { cond, true_target, false_target } => {
                         writeln!(f, "      Branch {{")?;
-                        writeln!(f, "        cond: {:?}", cond)?;
-                        writeln!(f, "        true:  {:?}", true_target)?;
-                        writeln!(f, "        false: {:?}", false_target)?;
+                        writeln!(f, "        cond: {}", names.value(self, *cond))?;
+                        writeln!(f, "        true:  {}", names.target(self, true_target))?;
+                        writeln!(f, "        false: {}", names.target(self, false_target))?;
+                        writeln!(f, "      }}")?;
+                    }
+                    Terminator::Switch { value, cases, default } => {
+                        writeln!(f, "      Switch {{")?;
+                        writeln!(f, "        value: {}", names.value(self, *value))?;
+                        for case in cases {
+                            writeln!(f, "        {}: {}", case.value, names.target(self, &case.target))?;
+                        }
+                        writeln!(f, "        default: {}", names.target(self, default))?;
                         writeln!(f, "      }}")?;
                     }
-                    term => writeln!(f, "      {:?}", term)?,
+                    Terminator::Return { value } => {
+                        writeln!(f, "      Return {}", names.value(self, *value))?;
+                    }
+                    Terminator::Unreachable => writeln!(f, "      Unreachable")?,
                 }
 
                 writeln!(f, "    }}")?;
@@ -691,4 +1223,63 @@ impl Display for Program {
         writeln!(f, "}}")?;
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mid::ir::{Const, InstructionInfo, LogicalOp, Program, Target, Terminator, Value};
+    use crate::mid::ir::Signedness::{Signed, Unsigned};
+
+    #[test]
+    fn extend() {
+        assert_eq!(-1, Signed.extend(8, 0xFF));
+        assert_eq!(0xFF, Unsigned.extend(8, -1));
+        assert_eq!(i32::MIN as i64, Signed.extend(32, 0x8000_0000));
+        assert_eq!(0x8000_0000, Unsigned.extend(32, i32::MIN as i64));
+        assert_eq!(0x7F, Signed.extend(8, 0x17F));
+    }
+
+    #[test]
+    fn compare_ints() {
+        assert!(LogicalOp::Lt(Signed).compare_ints(32, -1, 0));
+        assert!(!LogicalOp::Lt(Unsigned).compare_ints(32, -1, 0));
+        assert!(LogicalOp::Gte(Unsigned).compare_ints(32, i32::MIN as i64, i32::MAX as i64));
+
+        //narrow integers can be given either zero- or sign-extended
+        assert!(LogicalOp::Lt(Signed).compare_ints(8, 0xFF, 1));
+        assert!(LogicalOp::Gt(Unsigned).compare_ints(8, 0xFF, 1));
+        assert!(LogicalOp::Eq.compare_ints(8, 0xFF, -1));
+        assert!(LogicalOp::Lte(Signed).compare_ints(16, -0x8000, 0x8000));
+    }
+
+    #[test]
+    fn operands() {
+        let prog = Program::default();
+        let ty_int = prog.ty_int();
+        let [a, b, c] = [1, 2, 3].map(|value| Value::Const(Const::new(ty_int, value)));
+        let block = prog.get_func(prog.main).entry.block;
+
+        let mut select = InstructionInfo::Select { cond: a, true_value: b, false_value: b };
+        let mut operands = vec![];
+        select.for_each_operand(|value| operands.push(value));
+        assert_eq!(vec![a, b, b], operands);
+
+        select.for_each_operand_mut(|value| if *value == b { *value = c });
+        assert!(matches!(select, InstructionInfo::Select { cond, true_value, false_value } if cond == a && true_value == c && false_value == c));
+
+        //the phi values passed to targets are operands too
+        let mut branch = Terminator::Branch {
+            cond: a,
+            true_target: Target { block, phi_values: vec![b] },
+            false_target: Target { block, phi_values: vec![c, a] },
+        };
+        let mut operands = vec![];
+        branch.for_each_operand(|value| operands.push(value));
+        assert_eq!(vec![a, b, c, a], operands);
+
+        branch.for_each_operand_mut(|value| *value = c);
+        let mut operands = vec![];
+        branch.for_each_operand(|value| operands.push(value));
+        assert_eq!(vec![c; 4], operands);
+    }
+}
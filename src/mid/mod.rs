@@ -1,3 +1,9 @@
 pub mod ir;
+pub mod intrinsic;
 pub mod analyse;
-pub mod opt;
\ No newline at end of file
+pub mod opt;
+pub mod verify;
+pub mod interp;
+pub mod builder;
+pub mod dot;
+pub mod names;
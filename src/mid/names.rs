@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+use crate::mid::ir::{Block, Data, Function, Global, InstructionInfo, Program, Target, Value};
+
+/// Short names for printing the program that don't depend on the arena indices, which are global and shift whenever
+/// anything is defined. Functions, globals and data are numbered trough the whole program (`func2`, `global0`),
+/// blocks (`block3`) and values (`%5`) are numbered per function in the order they're visited.
+///
+/// This is also the side table to map names back to the nodes they stand for.
+#[derive(Debug, Default)]
+pub struct Names {
+    funcs: Vec<Function>,
+    func_numbers: HashMap<Function, usize>,
+    globals: HashMap<Global, usize>,
+    datas: HashMap<Data, usize>,
+
+    blocks: HashMap<Function, Vec<Block>>,
+    block_numbers: HashMap<Block, usize>,
+    values: HashMap<Function, Vec<Value>>,
+    value_numbers: HashMap<Value, usize>,
+}
+
+impl Names {
+    pub fn new(prog: &Program) -> Self {
+        let mut names = Names::default();
+
+        for (func, _) in &prog.nodes.funcs {
+            names.func_numbers.insert(func, names.funcs.len());
+            names.funcs.push(func);
+        }
+        names.globals = prog.nodes.globals.iter().enumerate().map(|(i, (global, _))| (global, i)).collect();
+        names.datas = prog.nodes.datas.iter().enumerate().map(|(i, (data, _))| (data, i)).collect();
+
+        for (func, func_info) in &prog.nodes.funcs {
+            let mut blocks = vec![];
+            let mut values = vec![];
+
+            values.extend(func_info.params.iter().map(|&param| Value::Param(param)));
+            values.extend(func_info.slots.iter().map(|&slot| Value::Slot(slot)));
+            prog.visit_blocks(func, |block| {
+                let block_info = prog.get_block(block);
+                blocks.push(block);
+                values.extend(block_info.phis.iter().map(|&phi| Value::Phi(phi)));
+                values.extend(block_info.instructions.iter().map(|&instr| Value::Instr(instr)));
+            });
+
+            names.block_numbers.extend(blocks.iter().enumerate().map(|(i, &block)| (block, i)));
+            names.value_numbers.extend(values.iter().enumerate().map(|(i, &value)| (value, i)));
+            names.blocks.insert(func, blocks);
+            names.values.insert(func, values);
+        }
+
+        names
+    }
+
+    /// The function called `func{number}`.
+    pub fn func_by_number(&self, number: usize) -> Option<Function> {
+        self.funcs.get(number).copied()
+    }
+
+    /// The block called `block{number}` in `func`.
+    pub fn block_by_number(&self, func: Function, number: usize) -> Option<Block> {
+        self.blocks.get(&func)?.get(number).copied()
+    }
+
+    /// The parameter, slot, phi or instruction called `%{number}` in `func`.
+    pub fn value_by_number(&self, func: Function, number: usize) -> Option<Value> {
+        self.values.get(&func)?.get(number).copied()
+    }
+
+    /// Nodes without a name (because they're not reachable) are printed with their arena index instead.
+    pub fn func(&self, func: Function) -> impl Display + '_ {
+        Name { prefix: "func", number: self.func_numbers.get(&func).copied(), fallback: format!("{:?}", func) }
+    }
+
+    pub fn block(&self, block: Block) -> impl Display + '_ {
+        Name { prefix: "block", number: self.block_numbers.get(&block).copied(), fallback: format!("{:?}", block) }
+    }
+
+    pub fn value<'a>(&'a self, prog: &'a Program, value: Value) -> impl Display + 'a {
+        NamedValue { names: self, prog, value }
+    }
+
+    /// A comma separated list of values.
+    pub fn values<'a>(&'a self, prog: &'a Program, values: &'a [Value]) -> impl Display + 'a {
+        NamedValues { names: self, prog, values }
+    }
+
+    pub fn target<'a>(&'a self, prog: &'a Program, target: &'a Target) -> impl Display + 'a {
+        NamedTarget { names: self, prog, target }
+    }
+
+    pub fn instr<'a>(&'a self, prog: &'a Program, instr_info: &'a InstructionInfo) -> impl Display + 'a {
+        NamedInstr { names: self, prog, instr_info }
+    }
+}
+
+struct Name {
+    prefix: &'static str,
+    number: Option<usize>,
+    fallback: String,
+}
+
+impl Display for Name {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.number {
+            Some(number) => write!(f, "{}{}", self.prefix, number),
+            None => write!(f, "{}", self.fallback),
+        }
+    }
+}
+
+struct NamedValue<'a> {
+    names: &'a Names,
+    prog: &'a Program,
+    value: Value,
+}
+
+impl Display for NamedValue<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let names = self.names;
+        match self.value {
+            Value::Undef(ty) => write!(f, "Undef({})", self.prog.format_type(ty)),
+            Value::Const(cst) => write!(f, "Const({}: {})", cst.value, self.prog.format_type(cst.ty)),
+            Value::Func(func) => write!(f, "{}", names.func(func)),
+            Value::Extern(ext) => write!(f, "Extern({})", self.prog.get_ext(ext).name),
+            Value::Data(data) => match names.datas.get(&data) {
+                Some(number) => write!(f, "data{}", number),
+                None => write!(f, "{:?}", data),
+            },
+            Value::Global(global) => match names.globals.get(&global) {
+                Some(number) => write!(f, "global{}", number),
+                None => write!(f, "{:?}", global),
+            },
+            Value::Param(_) | Value::Slot(_) | Value::Phi(_) | Value::Instr(_) => {
+                match names.value_numbers.get(&self.value) {
+                    Some(number) => write!(f, "%{}", number),
+                    None => write!(f, "{:?}", self.value),
+                }
+            }
+        }
+    }
+}
+
+struct NamedTarget<'a> {
+    names: &'a Names,
+    prog: &'a Program,
+    target: &'a Target,
+}
+
+impl Display for NamedTarget<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.names.block(self.target.block))?;
+        if !self.target.phi_values.is_empty() {
+            write!(f, " [{}]", self.names.values(self.prog, &self.target.phi_values))?;
+        }
+        Ok(())
+    }
+}
+
+struct NamedValues<'a> {
+    names: &'a Names,
+    prog: &'a Program,
+    values: &'a [Value],
+}
+
+impl Display for NamedValues<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, &value) in self.values.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", self.names.value(self.prog, value))?;
+        }
+        Ok(())
+    }
+}
+
+struct NamedInstr<'a> {
+    names: &'a Names,
+    prog: &'a Program,
+    instr_info: &'a InstructionInfo,
+}
+
+impl Display for NamedInstr<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let prog = self.prog;
+        let v = |value: Value| self.names.value(prog, value);
+        let t = |ty| prog.format_type(ty);
+
+        match self.instr_info {
+            &InstructionInfo::Load { addr, ty } =>
+                write!(f, "Load {{ addr: {}, ty: {} }}", v(addr), t(ty)),
+            &InstructionInfo::Store { addr, ty, value } =>
+                write!(f, "Store {{ addr: {}, ty: {}, value: {} }}", v(addr), t(ty), v(value)),
+            InstructionInfo::Call { target, args } =>
+                write!(f, "Call {{ target: {}, args: [{}] }}", v(*target), self.names.values(prog, args)),
+            &InstructionInfo::Arithmetic { kind, left, right } =>
+                write!(f, "Arithmetic {{ kind: {:?}, left: {}, right: {} }}", kind, v(left), v(right)),
+            &InstructionInfo::Comparison { kind, left, right } =>
+                write!(f, "Comparison {{ kind: {:?}, left: {}, right: {} }}", kind, v(left), v(right)),
+            &InstructionInfo::FloatArithmetic { kind, left, right } =>
+                write!(f, "FloatArithmetic {{ kind: {:?}, left: {}, right: {} }}", kind, v(left), v(right)),
+            &InstructionInfo::FloatComparison { kind, left, right } =>
+                write!(f, "FloatComparison {{ kind: {:?}, left: {}, right: {} }}", kind, v(left), v(right)),
+            &InstructionInfo::TupleFieldPtr { base, index, tuple_ty } =>
+                write!(f, "TupleFieldPtr {{ base: {}, index: {}, tuple_ty: {} }}", v(base), index, t(tuple_ty)),
+            &InstructionInfo::PointerOffSet { ty, base, index } =>
+                write!(f, "PointerOffSet {{ ty: {}, base: {}, index: {} }}", t(ty), v(base), v(index)),
+            &InstructionInfo::Cast { kind, ty, value } =>
+                write!(f, "Cast {{ kind: {:?}, ty: {}, value: {} }}", kind, t(ty), v(value)),
+            &InstructionInfo::MemCopy { dest, source, ty } =>
+                write!(f, "MemCopy {{ dest: {}, source: {}, ty: {} }}", v(dest), v(source), t(ty)),
+            &InstructionInfo::MemSet { dest, value, ty } =>
+                write!(f, "MemSet {{ dest: {}, value: {}, ty: {} }}", v(dest), v(value), t(ty)),
+            &InstructionInfo::Select { cond, true_value, false_value } =>
+                write!(f, "Select {{ cond: {}, true_value: {}, false_value: {} }}", v(cond), v(true_value), v(false_value)),
+            InstructionInfo::Intrinsic { kind, args } =>
+                write!(f, "Intrinsic {{ kind: {}, args: [{}] }}", kind, self.names.values(prog, args)),
+            InstructionInfo::InlineAsm { template, inputs, outputs } => {
+                write!(f, "InlineAsm {{ template: {:?}, inputs: [", template)?;
+                for (i, input) in inputs.iter().enumerate() {
+                    if i != 0 { write!(f, ", ")?; }
+                    write!(f, "{:?}: {}", input.constraint, v(input.value))?;
+                }
+                write!(f, "], outputs: [")?;
+                for (i, output) in outputs.iter().enumerate() {
+                    if i != 0 { write!(f, ", ")?; }
+                    write!(f, "{:?}: {}", output.constraint, v(output.value))?;
+                }
+                write!(f, "] }}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mid::builder::IrBuilder;
+    use crate::mid::ir::{ArithmeticOp, Const, InstructionInfo, Program, Target, Value};
+    use crate::mid::names::Names;
+
+    #[test]
+    fn local_numbers() {
+        let mut prog = Program::default();
+        let main = prog.main;
+        let ty_int = prog.ty_int();
+
+        //shift the arena indices, this must not change the names
+        let one = Value::Const(Const::new(ty_int, 1));
+        for _ in 0..3 {
+            prog.define_instr(InstructionInfo::Arithmetic { kind: ArithmeticOp::Add, left: one, right: one });
+        }
+
+        let mut builder = IrBuilder::at_entry(&mut prog, main);
+        let slot = builder.build_slot(main, ty_int);
+        let next = builder.new_block();
+        builder.build_jump(Target { block: next, phi_values: vec![one] });
+        builder.position_at_end(next);
+        let phi = Value::Phi(builder.build_phi(ty_int));
+        let sum = builder.build_binary(ArithmeticOp::Add, phi, one);
+        builder.build_store(slot, sum);
+        builder.build_return(sum);
+
+        let names = Names::new(&prog);
+        assert_eq!("func0", names.func(main).to_string());
+        assert_eq!("block1", names.block(next).to_string());
+        assert_eq!("%0", names.value(&prog, slot).to_string());
+        assert_eq!("%1", names.value(&prog, phi).to_string());
+        assert_eq!("%2", names.value(&prog, sum).to_string());
+        assert_eq!("Const(1: i32)", names.value(&prog, one).to_string());
+
+        assert_eq!(Some(main), names.func_by_number(0));
+        assert_eq!(Some(next), names.block_by_number(main, 1));
+        assert_eq!(Some(sum), names.value_by_number(main, 2));
+        assert_eq!(None, names.value_by_number(main, 4));
+
+        let sum_instr = prog.get_block(next).instructions[0];
+        assert_eq!(
+            "Arithmetic { kind: Add, left: %1, right: Const(1: i32) }",
+            names.instr(&prog, prog.get_instr(sum_instr)).to_string(),
+        );
+        assert!(prog.to_string().contains(&format!("  %2 ({:?}) = Arithmetic", sum_instr)));
+    }
+}
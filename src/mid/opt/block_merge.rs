@@ -0,0 +1,285 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::mid::analyse::cfg::Cfg;
+use crate::mid::analyse::use_info::{TargetKind, Usage, UseInfo};
+use crate::mid::ir::{Block, Function, Phi, Program, Target, Terminator, Value};
+
+/// Merge blocks into their unique predecessor if that predecessor unconditionally jumps to them, and thread jumps
+/// through blocks that only branch on one of their phis when the value passed for that phi is known.
+pub fn block_merge(prog: &mut Program) -> bool {
+    let mut merged_count = 0;
+    let mut threaded_count = 0;
+
+    loop {
+        let merged = merge_blocks(prog);
+        let threaded = thread_jumps(prog);
+
+        merged_count += merged;
+        threaded_count += threaded;
+        if merged == 0 && threaded == 0 { break; }
+    }
+
+    println!("block_merge merged {} blocks and threaded {} jumps", merged_count, threaded_count);
+    merged_count != 0 || threaded_count != 0
+}
+
+/// Merge each block that is only jumped to by a single `Jump` into the block containing that jump.
+/// Returns the number of merged blocks.
+fn merge_blocks(prog: &mut Program) -> usize {
+    let use_info = UseInfo::new(prog);
+    let funcs = used_funcs(prog, &use_info);
+
+    //find (pred, block) pairs to merge, each block is part of at most one pair so they can be merged independently
+    let mut pairs: Vec<(Block, Block)> = vec![];
+    for func in funcs {
+        let cfg = Cfg::new(prog, func);
+        let mut used = HashSet::new();
+
+        for &block in cfg.reverse_postorder() {
+            if block == cfg.entry() { continue; }
+
+            let mut preds = cfg.predecessors(block);
+            let pred = match (preds.next(), preds.next()) {
+                (Some(pred), None) => pred,
+                _ => continue,
+            };
+
+            let is_jump = matches!(prog.get_block(pred).terminator, Terminator::Jump { .. });
+            if is_jump && pred != block && !used.contains(&pred) && !used.contains(&block) {
+                used.insert(pred);
+                used.insert(block);
+                pairs.push((pred, block));
+            }
+        }
+    }
+
+    if pairs.is_empty() { return 0; }
+
+    //the phis of the merged blocks are replaced by the values passed by the jump
+    let mut replacements: HashMap<Phi, Value> = HashMap::new();
+    for &(pred, block) in &pairs {
+        let phi_values = match &prog.get_block(pred).terminator {
+            Terminator::Jump { target } => &target.phi_values,
+            _ => unreachable!(),
+        };
+        for (&phi, &value) in prog.get_block(block).phis.iter().zip(phi_values) {
+            replacements.insert(phi, value);
+        }
+    }
+
+    for &phi in replacements.keys() {
+        let new = resolve(&replacements, phi);
+        if new != Value::Phi(phi) {
+            use_info.replace_usages(prog, Value::Phi(phi), new);
+        }
+    }
+
+    //move the instructions and the terminator over, the merged block becomes unreachable
+    for &(pred, block) in &pairs {
+        let block_info = prog.get_block_mut(block);
+        block_info.phis.clear();
+        let instructions = std::mem::take(&mut block_info.instructions);
+        let terminator = std::mem::replace(&mut block_info.terminator, Terminator::Unreachable);
+
+        let pred_info = prog.get_block_mut(pred);
+        pred_info.instructions.extend(instructions);
+        pred_info.terminator = terminator;
+    }
+
+    pairs.len()
+}
+
+/// Follow the replacements starting at `phi`. A cycle of replacements means the blocks can never be reached, in that
+/// case the phi is left alone.
+fn resolve(replacements: &HashMap<Phi, Value>, phi: Phi) -> Value {
+    let mut seen = HashSet::new();
+    let mut curr = Value::Phi(phi);
+
+    while let Value::Phi(curr_phi) = curr {
+        match replacements.get(&curr_phi) {
+            Some(&next) if seen.insert(curr_phi) => curr = next,
+            Some(_) => return Value::Phi(phi),
+            None => break,
+        }
+    }
+
+    curr
+}
+
+/// A block without instructions that branches on one of its own phis, which aren't used anywhere else.
+struct Threadable {
+    cond_index: usize,
+    phis: Vec<Phi>,
+    true_target: Target,
+    false_target: Target,
+}
+
+/// Redirect targets that jump to a `Threadable` block with a constant condition straight to the branch target.
+/// Returns the number of redirected targets.
+fn thread_jumps(prog: &mut Program) -> usize {
+    let use_info = UseInfo::new(prog);
+
+    let mut blocks: Vec<Block> = vec![];
+    for func in used_funcs(prog, &use_info) {
+        prog.visit_blocks(func, |block| blocks.push(block));
+    }
+
+    let threadable: HashMap<Block, Threadable> = blocks.iter()
+        .filter_map(|&block| {
+            let info = prog.get_block(block);
+            if !info.instructions.is_empty() { return None; }
+            let (cond, true_target, false_target) = match &info.terminator {
+                Terminator::Branch { cond: Value::Phi(cond), true_target, false_target } => (*cond, true_target, false_target),
+                _ => return None,
+            };
+            let cond_index = info.phis.iter().position(|&phi| phi == cond)?;
+
+            //if the phis are used in other blocks those would no longer be dominated by their definition
+            let only_used_here = info.phis.iter().all(|&phi| {
+                use_info[Value::Phi(phi)].iter().all(|usage| used_by_terminator_of(usage, block))
+            });
+            if !only_used_here { return None; }
+
+            Some((block, Threadable { cond_index, phis: info.phis.clone(), true_target: true_target.clone(), false_target: false_target.clone() }))
+        })
+        .collect();
+
+    if threadable.is_empty() { return 0; }
+
+    let mut count = 0;
+
+    for block in blocks {
+        prog.get_block_mut(block).terminator.for_each_target_mut(|target| {
+            if target.block == block { return; }
+            let info = match threadable.get(&target.block) {
+                Some(info) => info,
+                None => return,
+            };
+
+            let next = match target.phi_values[info.cond_index] {
+                Value::Const(cst) => if cst.value != 0 { &info.true_target } else { &info.false_target },
+                _ => return,
+            };
+            if next.block == target.block { return; }
+
+            //the phis of the skipped block are replaced by the values passed to them
+            let phi_values = next.phi_values.iter()
+                .map(|&value| match value {
+                    Value::Phi(phi) => info.phis.iter().position(|&p| p == phi)
+                        .map_or(value, |i| target.phi_values[i]),
+                    _ => value,
+                })
+                .collect();
+
+            *target = Target { block: next.block, phi_values };
+            count += 1;
+        });
+    }
+
+    count
+}
+
+/// The functions reachable from main, `UseInfo` doesn't know about the values in the other ones.
+fn used_funcs(prog: &Program, use_info: &UseInfo) -> Vec<Function> {
+    prog.nodes.funcs.iter()
+        .map(|(func, _)| func)
+        .filter(|&func| !use_info[Value::Func(func)].is_empty())
+        .collect()
+}
+
+fn used_by_terminator_of(usage: &Usage, block: Block) -> bool {
+    match *usage {
+        Usage::BranchCond { from_block, .. } => from_block == block,
+        Usage::TargetPhiValue { target_kind, .. } => match target_kind {
+            TargetKind::Jump(from) | TargetKind::BranchTrue(from) | TargetKind::BranchFalse(from) |
+            TargetKind::SwitchCase(from, _) | TargetKind::SwitchDefault(from) => from == block,
+            TargetKind::Entry => false,
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mid::ir::{BlockInfo, Const, PhiInfo, Program, Target, Terminator, Value};
+    use crate::mid::opt::block_merge::block_merge;
+    use crate::mid::verify::verify;
+
+    #[test]
+    fn merge_and_thread() {
+        //entry -> cond(phi = true) -> branch on phi to left or right, both return
+        let mut prog = Program::default();
+        let entry = prog.get_func(prog.main).entry.block;
+        let [cond, left, right] = [(); 3].map(|_| prog.define_block(BlockInfo::new()));
+
+        let ty_bool = prog.ty_bool();
+        let ty_int = prog.ty_int();
+        let phi = prog.define_phi(PhiInfo { ty: ty_bool });
+        prog.get_block_mut(cond).phis.push(phi);
+
+        let one = Value::Const(Const::new(ty_int, 1));
+        let two = Value::Const(Const::new(ty_int, 2));
+        prog.get_block_mut(entry).terminator = Terminator::Jump {
+            target: Target { block: cond, phi_values: vec![Value::Const(Const::new(ty_bool, 1))] },
+        };
+        prog.get_block_mut(cond).terminator = Terminator::Branch {
+            cond: Value::Phi(phi),
+            true_target: Target { block: left, phi_values: vec![] },
+            false_target: Target { block: right, phi_values: vec![] },
+        };
+        prog.get_block_mut(left).terminator = Terminator::Return { value: one };
+        prog.get_block_mut(right).terminator = Terminator::Return { value: two };
+
+        assert!(block_merge(&mut prog));
+        verify(&prog).unwrap();
+
+        //cond gets merged into entry, folding the branch itself is left to flow_simplify
+        assert!(prog.get_block(cond).phis.is_empty());
+        match &prog.get_block(entry).terminator {
+            Terminator::Branch { cond, true_target, .. } => {
+                assert_eq!(Value::Const(Const::new(ty_bool, 1)), *cond);
+                assert_eq!(left, true_target.block);
+            }
+            term => panic!("unexpected terminator {:?}", term),
+        }
+        assert!(!block_merge(&mut prog));
+    }
+
+    #[test]
+    fn thread_jump() {
+        //entry and other both jump to cond, which only branches on its phi
+        let mut prog = Program::default();
+        let entry = prog.get_func(prog.main).entry.block;
+        let [other, cond, left, right] = [(); 4].map(|_| prog.define_block(BlockInfo::new()));
+
+        let ty_bool = prog.ty_bool();
+        let ty_int = prog.ty_int();
+        let phi = prog.define_phi(PhiInfo { ty: ty_bool });
+        prog.get_block_mut(cond).phis.push(phi);
+        let left_phi = prog.define_phi(PhiInfo { ty: ty_bool });
+        prog.get_block_mut(left).phis.push(left_phi);
+
+        let unknown = Value::Undef(ty_bool);
+        prog.get_block_mut(entry).terminator = Terminator::Branch {
+            cond: unknown,
+            true_target: Target { block: cond, phi_values: vec![Value::Const(Const::new(ty_bool, 0))] },
+            false_target: Target { block: other, phi_values: vec![] },
+        };
+        prog.get_block_mut(other).terminator = Terminator::Jump { target: Target { block: cond, phi_values: vec![unknown] } };
+        prog.get_block_mut(cond).terminator = Terminator::Branch {
+            cond: Value::Phi(phi),
+            true_target: Target { block: left, phi_values: vec![Value::Phi(phi)] },
+            false_target: Target { block: right, phi_values: vec![] },
+        };
+        prog.get_block_mut(left).terminator = Terminator::Return { value: Value::Const(Const::new(ty_int, 1)) };
+        prog.get_block_mut(right).terminator = Terminator::Return { value: Value::Const(Const::new(ty_int, 2)) };
+
+        assert!(block_merge(&mut prog));
+        verify(&prog).unwrap();
+
+        match &prog.get_block(entry).terminator {
+            Terminator::Branch { true_target, .. } => assert_eq!(right, true_target.block),
+            term => panic!("unexpected terminator {:?}", term),
+        }
+    }
+}
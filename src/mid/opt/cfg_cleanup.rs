@@ -0,0 +1,125 @@
+use std::collections::HashSet;
+
+use crate::mid::ir::{Block, Function, Program, Target, Terminator};
+
+/// Redirect jumps to blocks that don't do anything except jumping somewhere else straight to their final destination,
+/// and delete all blocks that are no longer reachable from the entry of any function afterwards. The phi values passed
+/// by unreachable blocks are part of their terminators, so they disappear together with the blocks.
+pub fn cfg_cleanup(prog: &mut Program) -> bool {
+    let funcs: Vec<Function> = prog.nodes.funcs.iter().map(|(func, _)| func).collect();
+    let mut skipped = 0;
+
+    for &func in &funcs {
+        let mut entry = prog.get_func(func).entry.clone();
+        if skip_jump_blocks(prog, &mut entry) {
+            skipped += 1;
+        }
+        prog.get_func_mut(func).entry = entry;
+
+        let mut blocks = vec![];
+        prog.visit_blocks(func, |block| blocks.push(block));
+
+        for block in blocks {
+            let mut terminator = std::mem::replace(&mut prog.get_block_mut(block).terminator, Terminator::Unreachable);
+            terminator.for_each_target_mut(|target| {
+                if skip_jump_blocks(prog, target) {
+                    skipped += 1;
+                }
+            });
+            prog.get_block_mut(block).terminator = terminator;
+        }
+    }
+
+    let mut reachable: HashSet<Block> = HashSet::new();
+    for &func in &funcs {
+        prog.visit_blocks(func, |block| { reachable.insert(block); });
+    }
+
+    let before_count = prog.nodes.blocks.len();
+    prog.nodes.blocks.retain(|block, _| reachable.contains(&block));
+    let removed = before_count - prog.nodes.blocks.len();
+
+    println!("cfg_cleanup skipped {} jumps and removed {} blocks", skipped, removed);
+    skipped != 0 || removed != 0
+}
+
+/// Follow the chain of blocks without phis and instructions that end in a `Jump` starting from `target`, and replace
+/// `target` with the last jump in that chain. The values passed to the final block are still available, since the
+/// skipped blocks don't define any values. Returns whether `target` changed.
+fn skip_jump_blocks(prog: &Program, target: &mut Target) -> bool {
+    let mut seen = HashSet::new();
+    let mut changed = false;
+
+    loop {
+        let info = prog.get_block(target.block);
+        if !info.phis.is_empty() || !info.instructions.is_empty() { break; }
+
+        let next = match &info.terminator {
+            Terminator::Jump { target } => target,
+            _ => break,
+        };
+
+        //don't get stuck in empty infinite loops
+        if !seen.insert(target.block) || seen.contains(&next.block) { break; }
+
+        *target = next.clone();
+        changed = true;
+    }
+
+    changed
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mid::interp::interpret;
+    use crate::mid::opt::cfg_cleanup::cfg_cleanup;
+    use crate::mid::opt::PassKind::{CfgCleanup, SlotToPhi};
+    use crate::mid::opt::snapshot::{check, lower_source, run_passes};
+
+    #[test]
+    fn skip_empty_blocks() {
+        let src = "
+            fun f(b: bool) -> int {
+                let x = 1;
+                if (b) {} else { x = 2; }
+                while (b) { x = 3; }
+                return x;
+            }
+            fun main() -> int { return f(false); }
+        ";
+        check(&run_passes(src, &[]), "CHECK: true:  block1\nCHECK: block1 {\nCHECK-NEXT: Jump block3");
+        //the branch goes straight to the loop header, block names are renumbered when printing
+        check(&run_passes(src, &[CfgCleanup]), "
+            CHECK: true:  block1
+            CHECK: block1 {
+            CHECK-NEXT: Load
+            CHECK-NEXT: Branch
+            CHECK: block2 {
+            CHECK-NEXT: Store
+            CHECK-NEXT: Jump block1
+        ");
+
+        let mut prog = lower_source(src);
+        assert!(cfg_cleanup(&mut prog));
+        assert_eq!(interpret(&prog), Ok(2));
+
+        //a second run has nothing left to do
+        assert!(!cfg_cleanup(&mut prog));
+    }
+
+    #[test]
+    fn keep_blocks_with_phis() {
+        let src = "fun f(b: bool) -> int { let x = 1; if (b) {} else { x = 2; } return x; } fun main() -> int { return f(false); }";
+        check(&run_passes(src, &[SlotToPhi, CfgCleanup]), "CHECK: true:  block1\nCHECK: block1 {\nCHECK-NEXT: phis:");
+    }
+
+    #[test]
+    fn remove_unreachable_blocks() {
+        let src = "fun main() -> int { let x = 1; return x; x = 2; return x; }";
+        let mut prog = lower_source(src);
+        let before = prog.nodes.blocks.len();
+        assert!(cfg_cleanup(&mut prog));
+        assert!(prog.nodes.blocks.len() < before);
+        assert_eq!(interpret(&prog), Ok(1));
+    }
+}
@@ -0,0 +1,87 @@
+use crate::mid::analyse::callgraph::CallGraph;
+use crate::mid::analyse::purity::PurityInfo;
+use crate::mid::analyse::use_info::UseInfo;
+use crate::mid::ir::{Block, Program, Value};
+
+/// Remove instructions whose result is never used and that have no other effects. This includes calls to functions
+/// that don't write memory and always return, as determined by `PurityInfo`.
+pub fn dce(prog: &mut Program) -> bool {
+    let mut removed_count = 0;
+
+    //removing an instruction can make its operands unused, so keep going until nothing changes
+    loop {
+        let use_info = UseInfo::new(prog);
+        let graph = CallGraph::new(prog, &use_info);
+        let purity = PurityInfo::new(prog, &graph);
+
+        let mut blocks: Vec<Block> = vec![];
+        for &func in graph.funcs() {
+            prog.visit_blocks(func, |block| blocks.push(block));
+        }
+
+        let mut removed_any = false;
+        for block in blocks {
+            let before = prog.get_block(block).instructions.len();
+
+            let instructions = std::mem::take(&mut prog.get_block_mut(block).instructions);
+            let kept = instructions.into_iter()
+                .filter(|&instr| {
+                    !use_info[Value::Instr(instr)].is_empty() || !purity.of_instr(prog.get_instr(instr)).is_removable()
+                })
+                .collect::<Vec<_>>();
+            prog.get_block_mut(block).instructions = kept;
+
+            let removed = before - prog.get_block(block).instructions.len();
+            removed_count += removed;
+            removed_any |= removed != 0;
+        }
+
+        if !removed_any { break; }
+    }
+
+    println!("dce removed {} instructions", removed_count);
+    removed_count != 0
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mid::builder::IrBuilder;
+    use crate::mid::ir::{ArithmeticOp, CallingConvention, FunctionInfo, FunctionType, GlobalInfo, Program, Value};
+    use crate::mid::opt::dce::dce;
+    use crate::mid::verify::verify;
+
+    #[test]
+    fn unused_calls() {
+        let mut prog = Program::default();
+        let main = prog.main;
+        let ty_int = prog.ty_int();
+        let func_ty = FunctionType { params: vec![], ret: ty_int, conv: CallingConvention::default() };
+        let global = Value::Global(prog.define_global(GlobalInfo { inner_ty: ty_int, mutable: true, init: None, debug_name: None }));
+
+        let info = FunctionInfo::new(func_ty.clone(), &mut prog);
+        let reader = prog.define_func(info);
+        let mut builder = IrBuilder::at_entry(&mut prog, reader);
+        let loaded = builder.build_load(ty_int, global);
+        builder.build_return(loaded);
+
+        let info = FunctionInfo::new(func_ty, &mut prog);
+        let writer = prog.define_func(info);
+        let mut builder = IrBuilder::at_entry(&mut prog, writer);
+        let one = builder.const_int(ty_int, 1);
+        builder.build_store(global, one);
+        builder.build_return(one);
+
+        //the sum only uses the call to reader, so both can go
+        let mut builder = IrBuilder::at_entry(&mut prog, main);
+        let read = builder.build_call(Value::Func(reader), vec![]);
+        builder.build_binary(ArithmeticOp::Add, read, read);
+        let written = builder.build_call(Value::Func(writer), vec![]);
+        builder.build_return(one);
+        let entry = builder.block();
+
+        assert!(dce(&mut prog));
+        verify(&prog).unwrap();
+        assert_eq!(vec![written], prog.get_block(entry).instructions.iter().map(|&i| Value::Instr(i)).collect::<Vec<_>>());
+        assert!(!dce(&mut prog));
+    }
+}
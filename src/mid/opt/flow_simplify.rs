@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+
 use crate::mid::ir::{Block, Program, Terminator, Value};
 
 //TODO also implement jump threading
@@ -7,9 +9,7 @@ pub fn flow_simplify(prog: &mut Program) -> bool {
     let mut count = 0;
 
     for block in blocks {
-        let info = prog.get_block_mut(block);
-
-        let old_term = std::mem::replace(&mut info.terminator, Terminator::Unreachable);
+        let old_term = std::mem::replace(&mut prog.get_block_mut(block).terminator, Terminator::Unreachable);
 
         let new_term = match old_term {
             Terminator::Jump { .. } => old_term,
@@ -24,16 +24,104 @@ pub fn flow_simplify(prog: &mut Program) -> bool {
                         let target = if cst.value != 0 { true_target } else { false_target };
                         Terminator::Jump { target }
                     }
+                    //a branch to a block that can't be reached can only go the other way
+                    _ if is_unreachable_block(prog, block, true_target.block) => {
+                        count += 1;
+                        Terminator::Jump { target: false_target }
+                    }
+                    _ if is_unreachable_block(prog, block, false_target.block) => {
+                        count += 1;
+                        Terminator::Jump { target: true_target }
+                    }
                     _ => Terminator::Branch { cond, true_target, false_target },
                 }
             }
+            Terminator::Switch { value, cases, default } => {
+                match &value {
+                    Value::Undef(_) => {
+                        count += 1;
+                        Terminator::Unreachable
+                    }
+                    Value::Const(cst) => {
+                        count += 1;
+                        let target = cases.into_iter()
+                            .find(|case| case.value == cst.value)
+                            .map_or(default, |case| case.target);
+                        Terminator::Jump { target }
+                    }
+                    _ => Terminator::Switch { value, cases, default },
+                }
+            }
             Terminator::Return { .. } => old_term,
             Terminator::Unreachable => old_term,
         };
 
-        info.terminator = new_term;
+        prog.get_block_mut(block).terminator = new_term;
     }
 
     println!("flow_simplify replaced {} terminators", count);
     count != 0
-}
\ No newline at end of file
+}
+
+/// Whether `target` is an empty block that ends in `Unreachable`, possibly through a chain of empty blocks that just jump
+/// onwards, so control flow can never actually go there.
+/// `block` itself is skipped since its terminator is temporarily replaced.
+fn is_unreachable_block(prog: &Program, block: Block, target: Block) -> bool {
+    let mut seen = HashSet::new();
+    let mut curr = target;
+
+    loop {
+        //stop at the current block and at empty infinite loops
+        if curr == block || !seen.insert(curr) {
+            return false;
+        }
+
+        let info = prog.get_block(curr);
+        if !info.instructions.is_empty() {
+            return false;
+        }
+
+        match &info.terminator {
+            Terminator::Unreachable => return true,
+            Terminator::Jump { target } => curr = target.block,
+            _ => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mid::interp::{interpret, InterpError};
+    use crate::mid::opt::PassKind::{FlowSimplify, SlotToPhi};
+    use crate::mid::opt::snapshot::{check, lower_source, run_passes};
+
+    const CHOOSE: &str = "
+        fun choose(b: bool) -> int {
+            if (b) { return 1; }
+            unreachable();
+        }
+        fun main() -> int { return choose(true); }
+    ";
+
+    #[test]
+    fn branch_into_unreachable() {
+        check(&run_passes(CHOOSE, &[SlotToPhi]), "CHECK: Branch\nCHECK: Unreachable");
+        check(&run_passes(CHOOSE, &[SlotToPhi, FlowSimplify]), "CHECK-NOT: Branch\nCHECK: Return Const(1: i32)");
+        assert_eq!(interpret(&lower_source(CHOOSE)), Ok(1));
+    }
+
+    #[test]
+    fn reachable_branch_is_kept() {
+        let src = "
+            fun choose(b: bool) -> int {
+                if (b) { return 1; }
+                return 2;
+            }
+            fun main() -> int { return choose(false); }
+        ";
+        check(&run_passes(src, &[SlotToPhi, FlowSimplify]), "CHECK: Branch");
+
+        let src = CHOOSE.replace("choose(true)", "choose(false)");
+        assert_eq!(interpret(&lower_source(&src)), Err(InterpError::Unreachable));
+    }
+}
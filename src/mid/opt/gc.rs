@@ -1,6 +1,5 @@
 use std::collections::{HashSet, VecDeque};
 
-use crate::mid::analyse::use_info::{for_each_usage_in_instr, InstructionPos};
 use crate::mid::ir::{Block, BlockInfo, Function, FunctionInfo, Program, Value};
 
 #[derive(Default)]
@@ -20,7 +19,7 @@ impl Visited {
                     self.funcs.push_back(func)
                 }
                 Value::Undef(_) | Value::Const(_) | Value::Param(_) | Value::Slot(_) |
-                Value::Instr(_) | Value::Extern(_) | Value::Data(_) | Value::Phi(_) => {
+                Value::Instr(_) | Value::Extern(_) | Value::Data(_) | Value::Global(_) | Value::Phi(_) => {
                     //there are only tracked as values
                 }
             }
@@ -41,7 +40,7 @@ fn collect_used(prog: &Program) -> Visited {
     while let Some(func) = todo.funcs.pop_front() {
         let FunctionInfo {
             entry, params, slots,
-            ty: _, func_ty: _, global_name: _, debug_name: _
+            ty: _, func_ty: _, global_name: _, debug_name: _, inline: _, noreturn: _
         } = prog.get_func(func);
 
         todo.add_block(entry.block);
@@ -61,29 +60,25 @@ fn collect_used(prog: &Program) -> Visited {
 
             for &instr in instructions {
                 todo.add_value(Value::Instr(instr));
-
-                let pos = InstructionPos { func, block, instr };
-                for_each_usage_in_instr(pos, &prog.get_instr(instr), |value, _| {
-                    todo.add_value(value);
-                });
+                prog.get_instr(instr).for_each_operand(|value| todo.add_value(value));
             }
 
-            terminator.for_each_target(|target| {
-                todo.add_block(target.block);
-                for &value in &target.phi_values {
-                    todo.add_value(value);
-                }
-            });
+            terminator.for_each_operand(|value| todo.add_value(value));
+            terminator.for_each_successor(|succ| todo.add_block(succ));
         }
     }
 
     todo
 }
 
+/// Remove all nodes that can't be reached from main. This includes functions that are never referenced, so no code is
+/// generated for them, and externs that are only referenced by those functions.
 pub fn gc(prog: &mut Program) -> bool {
     let visited = collect_used(prog);
 
     let before_count = prog.nodes.total_node_count();
+    let before_funcs = prog.nodes.funcs.len();
+    let before_exts = prog.nodes.exts.len();
 
     prog.nodes.funcs.retain(|n, _| visited.used_values.contains(&Value::Func(n)));
     prog.nodes.params.retain(|n, _| visited.used_values.contains(&Value::Param(n)));
@@ -91,11 +86,85 @@ pub fn gc(prog: &mut Program) -> bool {
     prog.nodes.blocks.retain(|n, _| visited.used_blocks.contains(&n));
     prog.nodes.phis.retain(|n, _| visited.used_values.contains(&Value::Phi(n)));
     prog.nodes.instrs.retain(|n, _| visited.used_values.contains(&Value::Instr(n)));
+    prog.instr_locations.retain(|n, _| visited.used_values.contains(&Value::Instr(*n)));
     prog.nodes.exts.retain(|n, _| visited.used_values.contains(&Value::Extern(n)));
     prog.nodes.datas.retain(|n, _| visited.used_values.contains(&Value::Data(n)));
+    prog.nodes.globals.retain(|n, _| visited.used_values.contains(&Value::Global(n)));
 
     let after_count = prog.nodes.total_node_count();
 
-    println!("gc removed {}/{} nodes", before_count - after_count, before_count);
+    println!(
+        "gc removed {}/{} nodes, including {} functions and {} externs",
+        before_count - after_count, before_count,
+        before_funcs - prog.nodes.funcs.len(), before_exts - prog.nodes.exts.len(),
+    );
     before_count != after_count
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mid::ir::{CallingConvention, Const, ExternInfo, FunctionInfo, FunctionType, InstructionInfo, Program, SourceLocation, Terminator, Value};
+    use crate::mid::opt::gc::gc;
+    use crate::mid::verify::verify;
+
+    #[test]
+    fn dead_func_and_extern() {
+        let mut prog = Program::default();
+        let ty_int = prog.ty_int();
+        let int_func_ty = FunctionType { params: vec![], ret: ty_int, conv: CallingConvention::default() };
+
+        //only referenced as the return value of `get`
+        let target_info = FunctionInfo::new(int_func_ty.clone(), &mut prog);
+        let target_ty = target_info.ty;
+        let target = prog.define_func(target_info);
+        let target_entry = prog.get_func(target).entry.block;
+        prog.get_block_mut(target_entry).terminator = Terminator::Return { value: Value::Undef(ty_int) };
+
+        let get_info = FunctionInfo::new(FunctionType { params: vec![], ret: target_ty, conv: CallingConvention::default() }, &mut prog);
+        let get = prog.define_func(get_info);
+        let get_entry = prog.get_func(get).entry.block;
+        prog.get_block_mut(get_entry).terminator = Terminator::Return { value: Value::Func(target) };
+
+        //never referenced, and neither is the extern it calls
+        let dead_info = FunctionInfo::new(int_func_ty, &mut prog);
+        let ext = prog.define_ext(ExternInfo { name: "_dead@0".to_owned(), ty: dead_info.ty });
+        let dead = prog.define_func(dead_info);
+        let dead_entry = prog.get_func(dead).entry.block;
+        let dead_call = prog.define_instr(InstructionInfo::Call { target: Value::Extern(ext), args: vec![] });
+        prog.get_block_mut(dead_entry).instructions.push(dead_call);
+        prog.get_block_mut(dead_entry).terminator = Terminator::Return { value: Value::Instr(dead_call) };
+
+        //main calls the function returned by get
+        let main_entry = prog.get_func(prog.main).entry.block;
+        let call_get = prog.define_instr(InstructionInfo::Call { target: Value::Func(get), args: vec![] });
+        let call_target = prog.define_instr(InstructionInfo::Call { target: Value::Instr(call_get), args: vec![] });
+        prog.get_block_mut(main_entry).instructions.extend([call_get, call_target]);
+        prog.get_block_mut(main_entry).terminator = Terminator::Return { value: Value::Instr(call_target) };
+
+        assert!(gc(&mut prog));
+        verify(&prog).unwrap();
+
+        assert!(prog.nodes.funcs.contains(target));
+        assert!(prog.nodes.funcs.contains(get));
+        assert!(!prog.nodes.funcs.contains(dead));
+        assert!(!prog.nodes.exts.contains(ext));
+        assert!(!gc(&mut prog));
+    }
+
+    #[test]
+    fn dead_instr_location() {
+        let mut prog = Program::default();
+        let one = Value::Const(Const::new(prog.ty_int(), 1));
+        let location = SourceLocation { file: 0, line: 3, col: 5 };
+
+        //the instruction is defined but not part of any block
+        let dead = prog.define_instr(InstructionInfo::Call { target: Value::Func(prog.main), args: vec![] });
+        prog.instr_locations.insert(dead, location);
+        let main_entry = prog.get_func(prog.main).entry.block;
+        prog.get_block_mut(main_entry).terminator = Terminator::Return { value: one };
+
+        assert!(gc(&mut prog));
+        assert!(!prog.nodes.instrs.contains(dead));
+        assert!(prog.instr_locations.is_empty());
+    }
+}
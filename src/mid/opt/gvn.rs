@@ -0,0 +1,138 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::mid::analyse::callgraph::CallGraph;
+use crate::mid::analyse::dom_info::DomInfo;
+use crate::mid::analyse::purity::PurityInfo;
+use crate::mid::analyse::use_info::UseInfo;
+use crate::mid::intrinsic::IntrinsicKind;
+use crate::mid::ir::{ArithmeticOp, Block, CastKind, Function, Instruction, InstructionInfo, LogicalOp, Program, Type, Value};
+
+/// Everything that determines the result of an instruction that doesn't depend on memory.
+#[derive(Debug, Eq, PartialEq, Hash)]
+enum Key {
+    Arithmetic(ArithmeticOp, Value, Value),
+    Comparison(LogicalOp, Value, Value),
+    FloatArithmetic(ArithmeticOp, Value, Value),
+    FloatComparison(LogicalOp, Value, Value),
+    TupleFieldPtr(Value, u32, Type),
+    PointerOffSet(Type, Value, Value),
+    Cast(CastKind, Type, Value),
+    Select(Value, Value, Value),
+    Intrinsic(IntrinsicKind, Vec<Value>),
+    Call(Function, Vec<Value>),
+}
+
+/// Replace instructions that compute the same value as an instruction that dominates them by that instruction.
+/// Calls are included if the callee doesn't access memory, as determined by `PurityInfo`.
+pub fn gvn(prog: &mut Program) -> bool {
+    let use_info = UseInfo::new(prog);
+    let graph = CallGraph::new(prog, &use_info);
+    let purity = PurityInfo::new(prog, &graph);
+
+    let mut removed: HashSet<Instruction> = HashSet::new();
+
+    for &func in graph.funcs() {
+        let dom_info = DomInfo::new(prog, func);
+        let mut available: HashMap<Key, Vec<(Block, Instruction)>> = HashMap::new();
+
+        //dominators are visited before the blocks they dominate
+        for &block in &dom_info.blocks {
+            for instr in prog.get_block(block).instructions.clone() {
+                //this is built after earlier replacements, so the operands are already numbered
+                let key = match key_of(&purity, prog.get_instr(instr)) {
+                    Some(key) => key,
+                    None => continue,
+                };
+
+                let candidates = available.entry(key).or_default();
+                let leader = candidates.iter()
+                    .find(|&&(leader_block, _)| dom_info.is_dominator(leader_block, block))
+                    .map(|&(_, leader)| leader);
+
+                match leader {
+                    Some(leader) => {
+                        use_info.replace_usages(prog, Value::Instr(instr), Value::Instr(leader));
+                        removed.insert(instr);
+                    }
+                    None => candidates.push((block, instr)),
+                }
+            }
+        }
+
+        for &block in &dom_info.blocks {
+            prog.get_block_mut(block).instructions.retain(|instr| !removed.contains(instr));
+        }
+    }
+
+    println!("gvn removed {} instructions", removed.len());
+    !removed.is_empty()
+}
+
+fn key_of(purity: &PurityInfo, instr_info: &InstructionInfo) -> Option<Key> {
+    let key = match instr_info {
+        &InstructionInfo::Arithmetic { kind, left, right } => Key::Arithmetic(kind, left, right),
+        &InstructionInfo::Comparison { kind, left, right } => Key::Comparison(kind, left, right),
+        &InstructionInfo::FloatArithmetic { kind, left, right } => Key::FloatArithmetic(kind, left, right),
+        &InstructionInfo::FloatComparison { kind, left, right } => Key::FloatComparison(kind, left, right),
+        &InstructionInfo::TupleFieldPtr { base, index, tuple_ty } => Key::TupleFieldPtr(base, index, tuple_ty),
+        &InstructionInfo::PointerOffSet { ty, base, index } => Key::PointerOffSet(ty, base, index),
+        &InstructionInfo::Cast { kind, ty, value } => Key::Cast(kind, ty, value),
+        &InstructionInfo::Select { cond, true_value, false_value } => Key::Select(cond, true_value, false_value),
+        InstructionInfo::Intrinsic { kind, args } if !kind.info().side_effects => Key::Intrinsic(*kind, args.clone()),
+        InstructionInfo::Call { target: Value::Func(func), args } if purity.of_func(*func).is_deterministic() =>
+            Key::Call(*func, args.clone()),
+        InstructionInfo::Load { .. } | InstructionInfo::Store { .. } | InstructionInfo::Call { .. } |
+        InstructionInfo::MemCopy { .. } | InstructionInfo::MemSet { .. } | InstructionInfo::Intrinsic { .. } |
+        InstructionInfo::InlineAsm { .. } => return None,
+    };
+    Some(key)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mid::builder::IrBuilder;
+    use crate::mid::ir::{ArithmeticOp, CallingConvention, FunctionInfo, FunctionType, ParameterInfo, Program, Value};
+    use crate::mid::opt::gvn::gvn;
+    use crate::mid::verify::verify;
+
+    #[test]
+    fn pure_calls_and_loads() {
+        let mut prog = Program::default();
+        let main = prog.main;
+        let ty_int = prog.ty_int();
+
+        let func_ty = FunctionType { params: vec![ty_int], ret: ty_int, conv: CallingConvention::default() };
+        let mut info = FunctionInfo::new(func_ty, &mut prog);
+        let x = prog.define_param(ParameterInfo { ty: ty_int });
+        info.params.push(x);
+        let square = prog.define_func(info);
+        let mut builder = IrBuilder::at_entry(&mut prog, square);
+        let result = builder.build_binary(ArithmeticOp::Mul, Value::Param(x), Value::Param(x));
+        builder.build_return(result);
+
+        //the calls and the additions are duplicates, the loads aren't since the store could change memory
+        let mut builder = IrBuilder::at_entry(&mut prog, main);
+        let slot = builder.build_slot(main, ty_int);
+        let three = builder.const_int(ty_int, 3);
+        let first_call = builder.build_call(Value::Func(square), vec![three]);
+        let second_call = builder.build_call(Value::Func(square), vec![three]);
+        let first_load = builder.build_load(ty_int, slot);
+        builder.build_store(slot, first_call);
+        let second_load = builder.build_load(ty_int, slot);
+        let first_sum = builder.build_binary(ArithmeticOp::Add, first_call, first_load);
+        let second_sum = builder.build_binary(ArithmeticOp::Add, second_call, first_load);
+        let total = builder.build_binary(ArithmeticOp::Add, first_sum, second_sum);
+        let result = builder.build_binary(ArithmeticOp::Add, total, second_load);
+        builder.build_return(result);
+        let entry = builder.block();
+
+        assert!(gvn(&mut prog));
+        verify(&prog).unwrap();
+
+        let instrs: Vec<Value> = prog.get_block(entry).instructions.iter().map(|&instr| Value::Instr(instr)).collect();
+        assert_eq!(7, instrs.len());
+        assert!(!instrs.contains(&second_call) && !instrs.contains(&second_sum));
+        assert!(instrs.contains(&second_load));
+        assert!(!gvn(&mut prog));
+    }
+}
@@ -0,0 +1,310 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::mid::analyse::cfg::{Cfg, Loop};
+use crate::mid::ir::{ArithmeticOp, Block, BlockInfo, Function, InstructionInfo, PhiInfo, Program, Target, Terminator, Value};
+use crate::mid::opt::Pass;
+
+/// The default maximum size of a fully unrolled loop, see `loop_unroll_with_budget`.
+pub const DEFAULT_UNROLL_BUDGET: usize = 64;
+
+pub fn loop_unroll(prog: &mut Program) -> bool {
+    loop_unroll_with_budget(prog, DEFAULT_UNROLL_BUDGET)
+}
+
+/// `loop_unroll_with_budget` as a pass.
+pub struct LoopUnroll {
+    pub budget: usize,
+}
+
+impl Pass for LoopUnroll {
+    fn name(&self) -> &str {
+        "loop_unroll"
+    }
+
+    fn run(&self, prog: &mut Program) -> bool {
+        loop_unroll_with_budget(prog, self.budget)
+    }
+}
+
+/// Fully unroll loops that exit trough a comparison between an induction variable and a constant in the header, if
+/// the trip count is known and the copies of the loop contain at most `budget` instructions and terminators in total.
+/// The loop is copied once per iteration, the original header stays around to run the final exit check.
+pub fn loop_unroll_with_budget(prog: &mut Program, budget: usize) -> bool {
+    let funcs: Vec<Function> = prog.nodes.funcs.iter().map(|(func, _)| func).collect();
+    let mut count = 0;
+
+    for func in funcs {
+        //unrolling changes the cfg, so rebuild it before looking for the next loop
+        while let Some(candidate) = find_candidate(prog, &Cfg::new(prog, func), budget) {
+            unroll(prog, &candidate);
+            count += 1;
+        }
+    }
+
+    println!("loop_unroll unrolled {} loops", count);
+    count != 0
+}
+
+struct Candidate {
+    header: Block,
+    blocks: Vec<Block>,
+    /// The blocks outside of the loop that jump to the header.
+    entering: Vec<Block>,
+    /// The target of the header branch that stays inside of the loop.
+    body_target: Target,
+    /// The target of the header branch that leaves the loop.
+    exit_target: Target,
+    trip_count: usize,
+}
+
+/// Find the smallest loop that can be unrolled, so inner loops are unrolled before the loops containing them.
+fn find_candidate(prog: &Program, cfg: &Cfg, budget: usize) -> Option<Candidate> {
+    let mut loops: Vec<&Loop> = cfg.loops().iter().collect();
+    loops.sort_by_key(|l| l.blocks.len());
+    loops.into_iter().find_map(|l| analyse_loop(prog, cfg, l, budget))
+}
+
+fn analyse_loop(prog: &Program, cfg: &Cfg, l: &Loop, budget: usize) -> Option<Candidate> {
+    let header = l.header;
+    if header == cfg.entry() { return None; }
+    let in_loop: HashSet<Block> = l.blocks.iter().copied().collect();
+
+    //the header branch is the only way out of the loop
+    let header_info = prog.get_block(header);
+    let (cond, true_target, false_target) = match &header_info.terminator {
+        Terminator::Branch { cond, true_target, false_target } => (*cond, true_target, false_target),
+        _ => return None,
+    };
+    let (body_target, exit_target, continue_if) = match (in_loop.contains(&true_target.block), in_loop.contains(&false_target.block)) {
+        (true, false) => (true_target, false_target, true),
+        (false, true) => (false_target, true_target, false),
+        _ => return None,
+    };
+    let other_exits = l.blocks.iter()
+        .any(|&block| block != header && cfg.successors(block).any(|succ| !in_loop.contains(&succ)));
+    if other_exits { return None; }
+
+    //the condition compares an induction variable with a constant
+    let (kind, left, right) = match cond {
+        Value::Instr(instr) => match *prog.get_instr(instr) {
+            InstructionInfo::Comparison { kind, left, right } => (kind, left, right),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let (iv, bound, iv_left) = match (left, right) {
+        (Value::Phi(phi), Value::Const(cst)) => (phi, cst.value, true),
+        (Value::Const(cst), Value::Phi(phi)) => (phi, cst.value, false),
+        _ => return None,
+    };
+    let iv_index = header_info.phis.iter().position(|&phi| phi == iv)?;
+    //constants are stored as i32, so only simulate those to get the wrapping right
+    if prog.get_type(prog.get_phi(iv).ty).unwrap_int() != Some(32) { return None; }
+
+    //every entering edge passes the same constant, every back edge adds the same constant
+    let mut entering = vec![];
+    let mut init_values = vec![];
+    let mut step_values = vec![];
+    for pred in cfg.predecessors(header) {
+        if !in_loop.contains(&pred) {
+            entering.push(pred);
+        }
+        prog.get_block(pred).terminator.for_each_target(|target| {
+            if target.block == header {
+                let values = if in_loop.contains(&pred) { &mut step_values } else { &mut init_values };
+                values.push(target.phi_values[iv_index]);
+            }
+        });
+    }
+
+    let init = match all_equal(&init_values)? {
+        Value::Const(cst) => cst.value,
+        _ => return None,
+    };
+    let delta = match all_equal(&step_values)? {
+        Value::Instr(instr) => match *prog.get_instr(instr) {
+            InstructionInfo::Arithmetic { kind: ArithmeticOp::Add, left: Value::Phi(phi), right: Value::Const(cst) } |
+            InstructionInfo::Arithmetic { kind: ArithmeticOp::Add, left: Value::Const(cst), right: Value::Phi(phi) }
+            if phi == iv => cst.value,
+            InstructionInfo::Arithmetic { kind: ArithmeticOp::Sub, left: Value::Phi(phi), right: Value::Const(cst) }
+            if phi == iv => cst.value.wrapping_neg(),
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    //simulate the induction variable until the loop exits or the budget runs out
+    let size: usize = l.blocks.iter().map(|&block| prog.get_block(block).instructions.len() + 1).sum();
+    let mut value = init;
+    let mut trip_count = 0;
+    loop {
+        let (left, right) = if iv_left { (value, bound) } else { (bound, value) };
+        if kind.compare_ints(32, left as i64, right as i64) != continue_if { break; }
+
+        trip_count += 1;
+        if trip_count * size > budget { return None; }
+        value = value.wrapping_add(delta);
+    }
+
+    Some(Candidate {
+        header,
+        blocks: l.blocks.clone(),
+        entering,
+        body_target: body_target.clone(),
+        exit_target: exit_target.clone(),
+        trip_count,
+    })
+}
+
+fn all_equal(values: &[Value]) -> Option<Value> {
+    let first = *values.first()?;
+    values.iter().all(|&value| value == first).then_some(first)
+}
+
+fn unroll(prog: &mut Program, candidate: &Candidate) {
+    let header = candidate.header;
+
+    //build the copies starting from the last iteration, the back edges of each copy go to the header of the next one
+    let mut next_header = header;
+    for _ in 0..candidate.trip_count {
+        next_header = copy_iteration(prog, candidate, next_header);
+    }
+
+    for &pred in &candidate.entering {
+        prog.get_block_mut(pred).terminator.for_each_target_mut(|target| {
+            if target.block == header {
+                target.block = next_header;
+            }
+        });
+    }
+
+    //the original body is now unreachable
+    prog.get_block_mut(header).terminator = Terminator::Jump { target: candidate.exit_target.clone() };
+}
+
+/// Copy the blocks of the loop, except that the header jumps straight into the body and the back edges go to
+/// `next_header`. Returns the header of the copy.
+fn copy_iteration(prog: &mut Program, candidate: &Candidate, next_header: Block) -> Block {
+    let header = candidate.header;
+    let mut block_map: HashMap<Block, Block> = HashMap::new();
+    let mut value_map: HashMap<Value, Value> = HashMap::new();
+
+    //define all new nodes first, instructions can use values defined further along in the list of blocks
+    for &block in &candidate.blocks {
+        block_map.insert(block, prog.define_block(BlockInfo::new()));
+
+        let block_info = prog.get_block(block);
+        let phis = block_info.phis.clone();
+        let instructions = block_info.instructions.clone();
+
+        for phi in phis {
+            let ty = prog.get_phi(phi).ty;
+            value_map.insert(Value::Phi(phi), Value::Phi(prog.define_phi(PhiInfo { ty })));
+        }
+        for instr in instructions {
+            let info = prog.get_instr(instr).clone();
+            let new_instr = prog.define_instr(info);
+            if let Some(&location) = prog.instr_locations.get(&instr) {
+                prog.instr_locations.insert(new_instr, location);
+            }
+            value_map.insert(Value::Instr(instr), Value::Instr(new_instr));
+        }
+    }
+
+    let map_value = |value: &mut Value| {
+        if let Some(&new) = value_map.get(value) {
+            *value = new;
+        }
+    };
+    let map_target = |target: &mut Target| {
+        target.block = if target.block == header { next_header } else { block_map[&target.block] };
+    };
+
+    for &block in &candidate.blocks {
+        let block_info = prog.get_block(block);
+
+        let phis = block_info.phis.iter()
+            .map(|&phi| match value_map[&Value::Phi(phi)] {
+                Value::Phi(phi) => phi,
+                _ => unreachable!(),
+            })
+            .collect();
+        let instructions: Vec<_> = block_info.instructions.iter()
+            .map(|&instr| match value_map[&Value::Instr(instr)] {
+                Value::Instr(instr) => instr,
+                _ => unreachable!(),
+            })
+            .collect();
+
+        let mut terminator = if block == header {
+            Terminator::Jump { target: candidate.body_target.clone() }
+        } else {
+            block_info.terminator.clone()
+        };
+        terminator.for_each_operand_mut(map_value);
+        terminator.for_each_target_mut(map_target);
+
+        for &instr in &instructions {
+            prog.get_instr_mut(instr).for_each_operand_mut(map_value);
+        }
+
+        *prog.get_block_mut(block_map[&block]) = BlockInfo { phis, instructions, terminator };
+    }
+
+    block_map[&header]
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mid::analyse::cfg::Cfg;
+    use crate::mid::ir::{ArithmeticOp, BlockInfo, Const, InstructionInfo, LogicalOp, PhiInfo, Program, Signedness, Target, Terminator, Value};
+    use crate::mid::opt::loop_unroll::loop_unroll_with_budget;
+    use crate::mid::verify::verify;
+
+    /// `for (i = 0; i < 3; i++) {}; return i;`
+    fn counting_loop() -> Program {
+        let mut prog = Program::default();
+        let entry = prog.get_func(prog.main).entry.block;
+        let [header, body, exit] = [(); 3].map(|_| prog.define_block(BlockInfo::new()));
+
+        let ty_int = prog.ty_int();
+        let i = prog.define_phi(PhiInfo { ty: ty_int });
+        prog.get_block_mut(header).phis.push(i);
+
+        let cond = prog.define_instr(InstructionInfo::Comparison { kind: LogicalOp::Lt(Signedness::Signed), left: Value::Phi(i), right: Value::Const(Const::new(ty_int, 3)) });
+        prog.get_block_mut(header).instructions.push(cond);
+        let next = prog.define_instr(InstructionInfo::Arithmetic { kind: ArithmeticOp::Add, left: Value::Phi(i), right: Value::Const(Const::new(ty_int, 1)) });
+        prog.get_block_mut(body).instructions.push(next);
+
+        prog.get_block_mut(entry).terminator = Terminator::Jump { target: Target { block: header, phi_values: vec![Value::Const(Const::new(ty_int, 0))] } };
+        prog.get_block_mut(header).terminator = Terminator::Branch {
+            cond: Value::Instr(cond),
+            true_target: Target { block: body, phi_values: vec![] },
+            false_target: Target { block: exit, phi_values: vec![] },
+        };
+        prog.get_block_mut(body).terminator = Terminator::Jump { target: Target { block: header, phi_values: vec![Value::Instr(next)] } };
+        prog.get_block_mut(exit).terminator = Terminator::Return { value: Value::Phi(i) };
+
+        prog
+    }
+
+    #[test]
+    fn unroll_counting_loop() {
+        let mut prog = counting_loop();
+        assert!(loop_unroll_with_budget(&mut prog, 64));
+        verify(&prog).unwrap();
+
+        //three copies of the header and body followed by the original header
+        let cfg = Cfg::new(&prog, prog.main);
+        assert!(cfg.loops().is_empty());
+        assert_eq!(1 + 3 * 2 + 2, cfg.reverse_postorder().len());
+    }
+
+    #[test]
+    fn unroll_budget() {
+        //each iteration costs 2 instructions and 2 terminators
+        let mut prog = counting_loop();
+        assert!(!loop_unroll_with_budget(&mut prog, 11));
+        assert!(loop_unroll_with_budget(&mut prog, 12));
+    }
+}
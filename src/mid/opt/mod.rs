@@ -1,4 +1,290 @@
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+use indexmap::map::IndexMap;
+
+use crate::mid::ir::Program;
+use crate::mid::verify::verify;
+
 pub mod gc;
 pub mod slot_to_phi;
 pub mod sccp;
-pub mod flow_simplify;
\ No newline at end of file
+pub mod flow_simplify;
+pub mod cfg_cleanup;
+pub mod phi_simplify;
+pub mod block_merge;
+pub mod loop_unroll;
+pub mod dce;
+pub mod gvn;
+pub mod undef_fold;
+
+#[cfg(test)]
+pub mod snapshot;
+
+pub trait Pass {
+    /// The name used for statistics and verification failures.
+    fn name(&self) -> &str;
+
+    /// Run the pass and return whether it changed anything.
+    fn run(&self, prog: &mut Program) -> bool;
+}
+
+/// A pass without any settings, implemented as a single function.
+pub struct FnPass {
+    pub name: &'static str,
+    pub f: fn(&mut Program) -> bool,
+}
+
+impl Pass for FnPass {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn run(&self, prog: &mut Program) -> bool {
+        (self.f)(prog)
+    }
+}
+
+/// Settings for the passes that have them.
+#[derive(Debug, Copy, Clone)]
+pub struct OptSettings {
+    pub unroll_budget: usize,
+}
+
+impl Default for OptSettings {
+    fn default() -> Self {
+        OptSettings { unroll_budget: loop_unroll::DEFAULT_UNROLL_BUDGET }
+    }
+}
+
+/// The passes that can be selected on the command line.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PassKind {
+    Gc,
+    SlotToPhi,
+    PhiSimplify,
+    Sccp,
+    FlowSimplify,
+    CfgCleanup,
+    BlockMerge,
+    LoopUnroll,
+    Dce,
+    Gvn,
+    UndefFold,
+}
+
+impl PassKind {
+    pub const ALL: &'static [PassKind] = &[
+        PassKind::Gc, PassKind::SlotToPhi, PassKind::PhiSimplify, PassKind::Sccp,
+        PassKind::FlowSimplify, PassKind::CfgCleanup, PassKind::BlockMerge, PassKind::LoopUnroll, PassKind::Dce, PassKind::Gvn,
+        PassKind::UndefFold,
+    ];
+
+    /// The name used for this pass on the command line and in statistics.
+    pub fn name(self) -> &'static str {
+        match self {
+            PassKind::Gc => "gc",
+            PassKind::SlotToPhi => "slot_to_phi",
+            PassKind::PhiSimplify => "phi_simplify",
+            PassKind::Sccp => "sccp",
+            PassKind::FlowSimplify => "flow_simplify",
+            PassKind::CfgCleanup => "cfg_cleanup",
+            PassKind::BlockMerge => "block_merge",
+            PassKind::LoopUnroll => "loop_unroll",
+            PassKind::Dce => "dce",
+            PassKind::Gvn => "gvn",
+            PassKind::UndefFold => "undef_fold",
+        }
+    }
+
+    pub fn create(self, settings: &OptSettings) -> Box<dyn Pass> {
+        let f: fn(&mut Program) -> bool = match self {
+            PassKind::Gc => gc::gc,
+            PassKind::SlotToPhi => slot_to_phi::slot_to_phi,
+            PassKind::PhiSimplify => phi_simplify::phi_simplify,
+            PassKind::Sccp => sccp::sccp,
+            PassKind::FlowSimplify => flow_simplify::flow_simplify,
+            PassKind::CfgCleanup => cfg_cleanup::cfg_cleanup,
+            PassKind::BlockMerge => block_merge::block_merge,
+            PassKind::Dce => dce::dce,
+            PassKind::Gvn => gvn::gvn,
+            PassKind::UndefFold => undef_fold::undef_fold,
+            PassKind::LoopUnroll => return Box::new(loop_unroll::LoopUnroll { budget: settings.unroll_budget }),
+        };
+        Box::new(FnPass { name: self.name(), f })
+    }
+}
+
+impl std::str::FromStr for PassKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PassKind::ALL.iter().copied()
+            .find(|kind| kind.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = PassKind::ALL.iter().map(|kind| kind.name()).collect();
+                format!("unknown pass '{}', expected one of {}", s, names.join(", "))
+            })
+    }
+}
+
+/// Preconfigured pipelines, from no optimizations at all to everything.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum OptLevel {
+    O0,
+    O1,
+    O2,
+}
+
+impl OptLevel {
+    pub fn passes(self) -> &'static [PassKind] {
+        use PassKind::*;
+
+        match self {
+            OptLevel::O0 => &[],
+            OptLevel::O1 => &[Gc, SlotToPhi, PhiSimplify, Gc, Sccp, UndefFold, Dce, FlowSimplify, CfgCleanup],
+            OptLevel::O2 => &[Gc, SlotToPhi, PhiSimplify, Gc, Sccp, UndefFold, Gvn, Dce, FlowSimplify, CfgCleanup, BlockMerge, LoopUnroll],
+        }
+    }
+}
+
+impl std::str::FromStr for OptLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" => Ok(OptLevel::O0),
+            "1" => Ok(OptLevel::O1),
+            "2" => Ok(OptLevel::O2),
+            _ => Err(format!("unknown optimization level '{}', expected one of 0, 1, 2", s)),
+        }
+    }
+}
+
+/// A list of passes that is run repeatedly until none of the passes change anything anymore.
+pub struct Pipeline {
+    pub passes: Vec<Box<dyn Pass>>,
+    /// Check that the IR is well-formed after each pass, panicking if it isn't.
+    pub verify: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct PassStats {
+    pub runs: usize,
+    pub changes: usize,
+    pub time: Duration,
+}
+
+#[derive(Debug, Default)]
+pub struct PipelineStats {
+    /// The number of times the full list of passes was run.
+    pub iterations: usize,
+    /// The statistics for each pass, combined for passes that appear multiple times in the pipeline.
+    pub passes: IndexMap<String, PassStats>,
+}
+
+impl Pipeline {
+    pub fn new(kinds: &[PassKind], settings: &OptSettings, verify: bool) -> Self {
+        Pipeline {
+            passes: kinds.iter().map(|kind| kind.create(settings)).collect(),
+            verify,
+        }
+    }
+
+    pub fn run(&self, prog: &mut Program) -> PipelineStats {
+        let mut stats = PipelineStats::default();
+
+        loop {
+            stats.iterations += 1;
+            let mut changed = false;
+
+            for pass in &self.passes {
+                let start = Instant::now();
+                let pass_changed = pass.run(prog);
+                let time = start.elapsed();
+
+                let pass_stats = stats.passes.entry(pass.name().to_owned()).or_default();
+                pass_stats.runs += 1;
+                pass_stats.changes += pass_changed as usize;
+                pass_stats.time += time;
+                changed |= pass_changed;
+
+                if self.verify {
+                    if let Err(e) = verify(prog) {
+                        panic!("IR verification failed after {}: {}", pass.name(), e);
+                    }
+                }
+            }
+
+            if !changed { break; }
+        }
+
+        stats
+    }
+}
+
+impl Display for PipelineStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Ran passes to fixpoint in {} iterations", self.iterations)?;
+        for (name, stats) in &self.passes {
+            writeln!(f, "  {:<16} runs: {:>3}  changes: {:>3}  time: {:?}", name, stats.runs, stats.changes, stats.time)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+
+    use crate::mid::ir::Program;
+    use crate::mid::opt::{FnPass, OptLevel, OptSettings, Pass, PassKind, Pipeline};
+
+    /// Reports a change the first `left` times it runs.
+    struct Countdown {
+        left: Cell<usize>,
+    }
+
+    impl Pass for Countdown {
+        fn name(&self) -> &str {
+            "countdown"
+        }
+
+        fn run(&self, _: &mut Program) -> bool {
+            let left = self.left.get();
+            self.left.set(left.saturating_sub(1));
+            left != 0
+        }
+    }
+
+    #[test]
+    fn fixpoint() {
+        let pipeline = Pipeline {
+            passes: vec![
+                Box::new(Countdown { left: Cell::new(2) }),
+                Box::new(FnPass { name: "nothing", f: |_| false }),
+                Box::new(Countdown { left: Cell::new(0) }),
+            ],
+            verify: true,
+        };
+
+        let stats = pipeline.run(&mut Program::default());
+        assert_eq!(3, stats.iterations);
+        assert_eq!(6, stats.passes["countdown"].runs);
+        assert_eq!(2, stats.passes["countdown"].changes);
+        assert_eq!(3, stats.passes["nothing"].runs);
+    }
+
+    #[test]
+    fn pass_names() {
+        for &kind in PassKind::ALL {
+            assert_eq!(Ok(kind), kind.name().parse());
+            assert_eq!(kind.name(), kind.create(&OptSettings::default()).name());
+        }
+        assert!("mem2reg".parse::<PassKind>().is_err());
+
+        assert_eq!(Ok(OptLevel::O1), "1".parse());
+        assert!(OptLevel::O0.passes().is_empty());
+        assert!("3".parse::<OptLevel>().is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::mid::analyse::use_info::UseInfo;
+use crate::mid::ir::{Block, Function, Phi, Program, Target, Value};
+
+/// Remove phis that always get the same value, ignoring the phi itself, and replace their uses with that value.
+/// A phi that only ever gets itself can't be reached and is replaced by undef.
+pub fn phi_simplify(prog: &mut Program) -> bool {
+    let mut removed_count = 0;
+
+    //replacing a phi can make other phis trivial, so keep going until nothing changes
+    loop {
+        let funcs: Vec<Function> = prog.nodes.funcs.iter().map(|(func, _)| func).collect();
+        let trivial: HashMap<Phi, Value> = funcs.iter()
+            .flat_map(|&func| find_trivial_phis(prog, func))
+            .collect();
+
+        if trivial.is_empty() { break; }
+        removed_count += trivial.len();
+
+        //replace all uses, including the phi values passed to the removed phis themselves
+        let use_info = UseInfo::new(prog);
+        for &phi in trivial.keys() {
+            let new = resolve(prog, &trivial, phi);
+            use_info.replace_usages(prog, Value::Phi(phi), new);
+        }
+
+        for func in funcs {
+            remove_phis(prog, func, &trivial);
+        }
+    }
+
+    println!("phi_simplify removed {} phis", removed_count);
+    removed_count != 0
+}
+
+/// Find the phis in `func` that always get the same value, together with that value.
+fn find_trivial_phis(prog: &Program, func: Function) -> Vec<(Phi, Value)> {
+    //collect the values passed to each phi by every target
+    let mut incoming: HashMap<Phi, Vec<Value>> = HashMap::new();
+    let mut add_target = |target: &Target| {
+        let phis = &prog.get_block(target.block).phis;
+        for (&phi, &value) in phis.iter().zip(&target.phi_values) {
+            incoming.entry(phi).or_default().push(value);
+        }
+    };
+
+    add_target(&prog.get_func(func).entry);
+    prog.visit_blocks(func, |block| {
+        prog.get_block(block).terminator.for_each_target(&mut add_target);
+    });
+
+    incoming.into_iter()
+        .filter_map(|(phi, values)| {
+            let mut others = values.into_iter().filter(|&value| value != Value::Phi(phi));
+            match others.next() {
+                None => Some((phi, Value::Undef(prog.get_phi(phi).ty))),
+                Some(first) if others.all(|value| value == first) => Some((phi, first)),
+                Some(_) => None,
+            }
+        })
+        .collect()
+}
+
+/// Follow the chain of trivial phis starting at `phi` to the value that replaces all of them.
+/// Trivial phis that only get each other are never actually reached, they become undef.
+fn resolve(prog: &Program, trivial: &HashMap<Phi, Value>, phi: Phi) -> Value {
+    let mut seen = HashSet::new();
+    let mut curr = Value::Phi(phi);
+
+    while let Value::Phi(curr_phi) = curr {
+        match trivial.get(&curr_phi) {
+            Some(&next) => {
+                if !seen.insert(curr_phi) {
+                    return Value::Undef(prog.get_phi(phi).ty);
+                }
+                curr = next;
+            }
+            None => break,
+        }
+    }
+
+    curr
+}
+
+/// Remove the given phis from the blocks of `func` and the phi values passed to them from the targets.
+fn remove_phis(prog: &mut Program, func: Function, removed: &HashMap<Phi, Value>) {
+    let mut blocks: Vec<Block> = vec![];
+    prog.visit_blocks(func, |block| blocks.push(block));
+
+    //which phi indices to keep for each block, computed before any of the blocks are changed
+    let keep: HashMap<Block, Vec<bool>> = blocks.iter()
+        .map(|&block| {
+            let keep = prog.get_block(block).phis.iter().map(|phi| !removed.contains_key(phi)).collect();
+            (block, keep)
+        })
+        .collect();
+
+    let retain_values = |target: &mut Target| {
+        let keep = &keep[&target.block];
+        let mut i = 0;
+        target.phi_values.retain(|_| {
+            i += 1;
+            keep[i - 1]
+        });
+    };
+
+    let mut entry = prog.get_func(func).entry.clone();
+    retain_values(&mut entry);
+    prog.get_func_mut(func).entry = entry;
+
+    for &block in &blocks {
+        let block_info = prog.get_block_mut(block);
+        block_info.terminator.for_each_target_mut(retain_values);
+        block_info.phis.retain(|phi| !removed.contains_key(phi));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mid::ir::{BlockInfo, Const, PhiInfo, Program, Target, Terminator, Value};
+    use crate::mid::opt::phi_simplify::phi_simplify;
+    use crate::mid::verify::verify;
+
+    #[test]
+    fn loop_phi() {
+        //entry -> header(phi = 5) -> header(phi = phi), so the phi is always 5
+        let mut prog = Program::default();
+        let entry = prog.get_func(prog.main).entry.block;
+        let header = prog.define_block(BlockInfo::new());
+        let exit = prog.define_block(BlockInfo::new());
+
+        let ty_int = prog.ty_int();
+        let five = Value::Const(Const::new(ty_int, 5));
+        let phi = prog.define_phi(PhiInfo { ty: ty_int });
+        prog.get_block_mut(header).phis.push(phi);
+
+        let cond = Value::Const(Const::new(prog.ty_bool(), 1));
+        prog.get_block_mut(entry).terminator = Terminator::Jump { target: Target { block: header, phi_values: vec![five] } };
+        prog.get_block_mut(header).terminator = Terminator::Branch {
+            cond,
+            true_target: Target { block: header, phi_values: vec![Value::Phi(phi)] },
+            false_target: Target { block: exit, phi_values: vec![] },
+        };
+        prog.get_block_mut(exit).terminator = Terminator::Return { value: Value::Phi(phi) };
+
+        assert!(phi_simplify(&mut prog));
+        verify(&prog).unwrap();
+
+        assert!(prog.get_block(header).phis.is_empty());
+        match &prog.get_block(exit).terminator {
+            Terminator::Return { value } => assert_eq!(five, *value),
+            _ => unreachable!(),
+        }
+        assert!(!phi_simplify(&mut prog));
+    }
+}
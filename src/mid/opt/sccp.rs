@@ -3,7 +3,8 @@ use std::collections::{HashSet, VecDeque};
 use indexmap::map::IndexMap;
 
 use crate::mid::analyse::use_info::{for_each_usage_in_instr, InstructionPos, Usage, UseInfo};
-use crate::mid::ir::{ArithmeticOp, Block, Const, Function, Instruction, InstructionInfo, LogicalOp, Program, Target, Terminator, Type, Value};
+use crate::mid::intrinsic::IntrinsicSignature;
+use crate::mid::ir::{ArithmeticOp, Block, CastKind, Const, Function, Instruction, InstructionInfo, Program, SwitchCase, Target, Terminator, Type, Value};
 use crate::util::zip_eq;
 
 ///Try to prove values are constant and replace them
@@ -59,7 +60,7 @@ impl LatticeMap {
         match value {
             Value::Undef(_) =>
                 Lattice::Undef,
-            Value::Const(_) | Value::Func(_) | Value::Extern(_) | Value::Data(_) =>
+            Value::Const(_) | Value::Func(_) | Value::Extern(_) | Value::Data(_) | Value::Global(_) =>
                 Lattice::Const(value),
             Value::Param(_) | Value::Phi(_) | Value::Instr(_) =>
                 *self.values.get(&value).unwrap_or(&Lattice::Undef),
@@ -124,13 +125,16 @@ fn compute_lattice_map(prog: &mut Program, use_info: &UseInfo) -> LatticeMap {
 
                     //visit each instr
                     for &instr in &block_info.instructions {
-                        visit_instr(&prog, &mut map, &mut todo, instr);
+                        visit_instr(prog, &mut map, &mut todo, instr);
                         let pos = InstructionPos { func, block, instr };
 
                         //since it's the first time we check for usage of functions as generic operands
                         for_each_usage_in_instr(pos, prog.get_instr(instr), |value, usage| {
                             if let Value::Func(func) = value {
                                 if !matches!(usage, Usage::CallTarget {..}) {
+                                    //the function can be called indirectly, so it's reachable
+                                    todo.push_back(Todo::FunctionInit(func));
+
                                     // mark function parameters as overdefined
                                     for &param in &prog.get_func(func).params {
                                         map.values.insert(Value::Param(param), Lattice::Overdef);
@@ -147,6 +151,8 @@ fn compute_lattice_map(prog: &mut Program, use_info: &UseInfo) -> LatticeMap {
                         }
                         Terminator::Branch { cond, true_target, false_target } =>
                             visit_branch(prog, &mut map, &mut todo, func, cond, true_target, false_target),
+                        Terminator::Switch { value, cases, default } =>
+                            visit_switch(prog, &mut map, &mut todo, func, value, cases, default),
                         &Terminator::Return { value } => {
                             map.merge_func_return(&mut todo, func, map.eval(value))
                         }
@@ -159,18 +165,26 @@ fn compute_lattice_map(prog: &mut Program, use_info: &UseInfo) -> LatticeMap {
             Todo::ValueUsers(value) => {
                 for &usage in &use_info[value] {
                     match usage {
-                        Usage::Main | Usage::CallTarget { .. } =>
+                        Usage::Main =>
                             unreachable!("this value should never change: {:?}", usage),
 
+                        //indirect calls are always overdefined
+                        Usage::CallTarget { .. } => {}
+
                         //don't need to visit because their lattice value doesn't get affected by this operand
                         Usage::LoadAddr { .. } | Usage::StoreAddr { .. } => {}
+                        Usage::MemDest { .. } | Usage::MemCopySource { .. } => {}
                         Usage::TupleFieldPtrBase { .. } => {}
-                        Usage::ArrayIndexPtrBase { .. } | Usage::ArrayIndexPtrIndex { .. } => {}
+                        Usage::PointerOffSetBase { .. } | Usage::PointerOffSetIndex { .. } => {}
 
                         //don't need to visit because result is void
                         Usage::StoreValue { .. } => {}
+                        Usage::MemSetValue { .. } => {}
+                        Usage::AsmInput { .. } | Usage::AsmOutput { .. } => {}
 
-                        Usage::BinaryOperand { pos } => {
+                        Usage::BinaryOperand { pos } | Usage::CastValue { pos } |
+                        Usage::SelectCond { pos } | Usage::SelectValue { pos } |
+                        Usage::IntrinsicArgument { pos, .. } => {
                             visit_instr(prog, &mut map, &mut todo, pos.instr);
                         }
                         Usage::TargetPhiValue { func, target_kind, phi_index } => {
@@ -203,6 +217,14 @@ fn compute_lattice_map(prog: &mut Program, use_info: &UseInfo) -> LatticeMap {
                                 _ => unreachable!()
                             }
                         }
+                        Usage::SwitchValue { func, from_block } => {
+                            match &prog.get_block(from_block).terminator {
+                                Terminator::Switch { value, cases, default } => {
+                                    visit_switch(prog, &mut map, &mut todo, func, value, cases, default)
+                                }
+                                _ => unreachable!()
+                            }
+                        }
                         Usage::ReturnValue { func, from_block } => {
                             match &prog.get_block(from_block).terminator {
                                 &Terminator::Return { value } => {
@@ -233,8 +255,8 @@ fn compute_lattice_map(prog: &mut Program, use_info: &UseInfo) -> LatticeMap {
 
 fn visit_branch(
     prog: &Program,
-    mut map: &mut LatticeMap,
-    mut todo: &mut VecDeque<Todo>,
+    map: &mut LatticeMap,
+    todo: &mut VecDeque<Todo>,
     func: Function,
     cond: &Value,
     true_target: &Target,
@@ -244,10 +266,40 @@ fn visit_branch(
     let (visit_true, visit_false) = evaluate_branch_condition(prog, cond);
 
     if visit_true {
-        update_target_reachable(prog, &mut map, &mut todo, func, true_target);
+        update_target_reachable(prog, map, todo, func, true_target);
     }
     if visit_false {
-        update_target_reachable(prog, &mut map, &mut todo, func, false_target);
+        update_target_reachable(prog, map, todo, func, false_target);
+    }
+}
+
+fn visit_switch(
+    prog: &Program,
+    map: &mut LatticeMap,
+    todo: &mut VecDeque<Todo>,
+    func: Function,
+    value: &Value,
+    cases: &[SwitchCase],
+    default: &Target,
+) {
+    match map.eval(*value) {
+        Lattice::Undef => {
+            //undefined behaviour, don't mark anything
+        }
+        Lattice::Const(Value::Const(cst)) => {
+            //only the matching target can be taken
+            let target = cases.iter()
+                .find(|case| case.value == cst.value)
+                .map_or(default, |case| &case.target);
+            update_target_reachable(prog, map, todo, func, target);
+        }
+        Lattice::Const(_) | Lattice::Overdef => {
+            //any target could be taken
+            for case in cases {
+                update_target_reachable(prog, map, todo, func, &case.target);
+            }
+            update_target_reachable(prog, map, todo, func, default);
+        }
     }
 }
 
@@ -294,7 +346,12 @@ fn visit_instr(prog: &Program, map: &mut LatticeMap, todo: &mut VecDeque<Todo>,
         InstructionInfo::Load { .. } => Lattice::Overdef,
         InstructionInfo::TupleFieldPtr { .. } => Lattice::Overdef,
         InstructionInfo::PointerOffSet { .. } => Lattice::Overdef,
+        //TODO fold float operations once there are float constants
+        InstructionInfo::FloatArithmetic { .. } => Lattice::Overdef,
+        InstructionInfo::FloatComparison { .. } => Lattice::Overdef,
         InstructionInfo::Store { .. } => Lattice::Undef,
+        InstructionInfo::MemCopy { .. } | InstructionInfo::MemSet { .. } => Lattice::Undef,
+        InstructionInfo::InlineAsm { .. } => Lattice::Undef,
         InstructionInfo::Call { target, args } => {
             if let Value::Func(target) = *target {
                 //mark reachable
@@ -317,43 +374,93 @@ fn visit_instr(prog: &Program, map: &mut LatticeMap, todo: &mut VecDeque<Todo>,
                 Lattice::Const(Value::Const(left)),
                 Lattice::Const(Value::Const(right))
             ) = (map.eval(left), map.eval(right)) {
-                //TODO this probably doesn't handle wrapping correctly yet
                 assert_eq!(left.ty, right.ty);
                 let ty = left.ty;
+                let bits = prog.get_type(ty).unwrap_int().unwrap();
                 let (left, right) = (left.value, right.value);
 
                 let result = match kind {
-                    ArithmeticOp::Add => left + right,
-                    ArithmeticOp::Sub => left - right,
-                    ArithmeticOp::Mul => left * right,
-                    //TODO are x/0 and x%0 undefined?
-                    ArithmeticOp::Div => left / right,
-                    ArithmeticOp::Mod => left % right,
+                    ArithmeticOp::Add => Some(left.wrapping_add(right)),
+                    ArithmeticOp::Sub => Some(left.wrapping_sub(right)),
+                    ArithmeticOp::Mul => Some(left.wrapping_mul(right)),
+                    //leave division by zero to happen at runtime
+                    ArithmeticOp::Div(_) | ArithmeticOp::Mod(_) if right == 0 => None,
+                    ArithmeticOp::Div(signedness) | ArithmeticOp::Mod(signedness) => {
+                        let left = signedness.extend(bits, left as i64);
+                        let right = signedness.extend(bits, right as i64);
+                        let result = if let ArithmeticOp::Div(_) = kind { left / right } else { left % right };
+                        Some(result as i32)
+                    }
                 };
 
-                Lattice::Const(Value::Const(Const { ty, value: result }))
+                match result {
+                    //constants are stored zero-extended
+                    Some(result) => Lattice::Const(Value::Const(Const { ty, value: result & mask(bits) })),
+                    None => Lattice::Overdef,
+                }
             } else {
                 //TODO sometimes this can be inferred as well, eg "0 * x"
                 Lattice::Overdef
             }
         }
+        &InstructionInfo::Select { cond, true_value, false_value } => {
+            match map.eval(cond) {
+                Lattice::Const(Value::Const(cst)) =>
+                    map.eval(if cst.value != 0 { true_value } else { false_value }),
+                Lattice::Undef => Lattice::Undef,
+                Lattice::Const(_) | Lattice::Overdef => {
+                    //the result is still known if both values are the same
+                    let true_value = map.eval(true_value);
+                    if true_value == map.eval(false_value) { true_value } else { Lattice::Overdef }
+                }
+            }
+        }
+        InstructionInfo::Intrinsic { kind, args } => {
+            match kind.info().signature {
+                IntrinsicSignature::UnaryInt { .. } => match map.eval(args[0]) {
+                    Lattice::Const(Value::Const(cst)) => {
+                        let bits = prog.get_type(cst.ty).unwrap_int().unwrap();
+                        let result = kind.eval_unary_int(bits, cst.value as u32);
+                        Lattice::Const(Value::Const(Const { ty: cst.ty, value: result as i32 }))
+                    }
+                    Lattice::Const(_) | Lattice::Overdef => Lattice::Overdef,
+                    Lattice::Undef => Lattice::Undef,
+                },
+                //the result is void
+                IntrinsicSignature::Nullary => Lattice::Undef,
+            }
+        }
+        &InstructionInfo::Cast { kind, ty, value } => {
+            match map.eval(value) {
+                Lattice::Const(Value::Const(cst)) => {
+                    let bits_before = prog.get_type(cst.ty).unwrap_int();
+                    let bits_after = prog.get_type(ty).unwrap_int();
+
+                    let result = match kind {
+                        CastKind::PointerToInt | CastKind::IntToPointer => cst.value,
+                        CastKind::Truncate | CastKind::ZeroExtend =>
+                            cst.value & mask(bits_after.unwrap().min(bits_before.unwrap())),
+                        CastKind::SignExtend => {
+                            let shift = 32 - bits_before.unwrap();
+                            (cst.value << shift) >> shift
+                        }
+                    };
+
+                    Lattice::Const(Value::Const(Const { ty, value: result }))
+                }
+                Lattice::Const(_) | Lattice::Overdef => Lattice::Overdef,
+                Lattice::Undef => Lattice::Undef,
+            }
+        }
         &InstructionInfo::Comparison { kind, left, right } => {
             if let (
                 Lattice::Const(Value::Const(left)),
                 Lattice::Const(Value::Const(right))
             ) = (map.eval(left), map.eval(right)) {
-                //TODO this probably doesn't handle wrapping correctly yet
                 assert_eq!(left.ty, right.ty);
-                let (left, right) = (left.value, right.value);
-
-                let result = match kind {
-                    LogicalOp::Eq => left == right,
-                    LogicalOp::Neq => left != right,
-                    LogicalOp::Gte => left >= right,
-                    LogicalOp::Gt => left > right,
-                    LogicalOp::Lte => left <= right,
-                    LogicalOp::Lt => left < right,
-                };
+                //pointer constants are 32 bits as well
+                let bits = prog.get_type(left.ty).unwrap_int().unwrap_or(32);
+                let result = kind.compare_ints(bits, left.value as i64, right.value as i64);
 
                 Lattice::Const(Value::Const(Const { ty: prog.ty_bool(), value: result as i32 }))
             } else {
@@ -366,6 +473,11 @@ fn visit_instr(prog: &Program, map: &mut LatticeMap, todo: &mut VecDeque<Todo>,
     map.merge_value(todo, Value::Instr(instr), result)
 }
 
+/// The mask that keeps only the lowest `bits` bits of a value.
+fn mask(bits: u32) -> i32 {
+    if bits >= 32 { -1 } else { ((1u32 << bits) - 1) as i32 }
+}
+
 fn apply_lattice_simplifications(prog: &mut Program, use_info: &UseInfo, lattice_map: &LatticeMap) -> usize {
     let mut count = 0;
 
@@ -106,7 +106,7 @@ fn slot_to_phi_fun(prog: &mut Program, use_info: &UseInfo, func: Function) -> us
 
     //remove the now unused slots
     prog.get_func_mut(func).slots
-        .retain(|slot| !replaced_slots.contains(&slot));
+        .retain(|slot| !replaced_slots.contains(slot));
 
     replaced_slots.len()
 }
@@ -123,6 +123,7 @@ fn is_load_or_store_addr_with_type(prog: &Program, usage: &Usage, expected_ty: T
 
 /// This function is the heart of this pass: it recursively calls itself to figure out the value of
 /// a slot at a given program position, inserting phi nodes along the way.
+#[allow(clippy::too_many_arguments)]
 fn get_value_for_slot(
     prog: &mut Program,
     dom_info: &DomInfo,
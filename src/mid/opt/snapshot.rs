@@ -0,0 +1,288 @@
+//! Helpers for testing passes on small programs: lower some source code, run a few passes and compare the resulting
+//! IR against expectations, either with FileCheck-style `CHECK` lines or against a full golden file.
+//!
+//! The IR is compared in the format of `Program::display_stable`, so the expectations don't depend on arena indices.
+
+use std::path::{Path, PathBuf};
+
+use crate::back::target::{I686Windows, Target};
+use crate::front;
+use crate::front::diag::{Renderer, Report};
+use crate::front::error::WarningLevels;
+use crate::front::pos::Files;
+use crate::mid::ir::Program;
+use crate::mid::opt::{OptSettings, PassKind};
+use crate::mid::verify::verify;
+
+/// Set this environment variable to overwrite golden files with the actual output instead of comparing with them.
+const BLESS_VAR: &str = "LLLANG_BLESS";
+
+/// Lower `src` as the module `main` of a program without the standard library.
+pub fn lower_source(src: &str) -> Program {
+    lower_source_for(src, &I686Windows)
+}
+
+/// The same as `lower_source`, but for the given target.
+pub fn lower_source_for(src: &str, target: &dyn Target) -> Program {
+    lower_modules(&[("main", src)], target)
+}
+
+/// Lower a program consisting of the given modules, each with its `::`-separated path and its source.
+pub fn lower_modules(modules: &[(&str, &str)], target: &dyn Target) -> Program {
+    let (prog, _) = try_lower(modules, target).unwrap_or_else(|e| panic!("failed to compile:\n{}", e));
+
+    verify(&prog).unwrap_or_else(|e| panic!("IR verification failed after lowering: {}", e));
+    prog
+}
+
+/// Lower `src` as the module `main`, expecting it to fail. Returns the rendered error.
+pub fn lower_error(src: &str) -> String {
+    lower_modules_error(&[("main", src)])
+}
+
+/// The same as `lower_error`, but for a program consisting of the given modules.
+pub fn lower_modules_error(modules: &[(&str, &str)]) -> String {
+    match try_lower(modules, &I686Windows) {
+        Ok(_) => panic!("expected an error while compiling:\n{:?}", modules),
+        Err(e) => e,
+    }
+}
+
+/// Lower `src` as the module `main`. Returns the rendered warnings, with the default warning levels.
+pub fn lower_warnings(src: &str) -> Vec<String> {
+    let (_, warnings) = try_lower(&[("main", src)], &I686Windows).unwrap_or_else(|e| panic!("failed to compile:\n{}", e));
+    warnings
+}
+
+/// Lower the given modules for `target`. Returns the program and its rendered warnings, or the rendered error.
+fn try_lower(modules: &[(&str, &str)], target: &dyn Target) -> Result<(Program, Vec<String>), String> {
+    let mut files = Files::default();
+    let mut ast_program = front::Program::default();
+
+    for &(path, src) in modules {
+        let id = files.add(PathBuf::from(path.replace("::", "/")).with_extension("ll"), src.to_owned());
+        let module = front::parser::parse_module(id, &files[id].src)
+            .map_err(|e| render(&files, &Report::from(&e)))?;
+        let path = path.split("::").map(str::to_owned).collect();
+        ast_program.add_module(path, module).unwrap();
+    }
+
+    let resolved = front::resolve::resolve(&ast_program)
+        .map_err(|e| render(&files, &Report::from(&e)))?;
+    let settings = front::lower::LowerSettings {
+        files: &files,
+        check_asserts: true,
+        solver_stats: false,
+        data_layout: target.data_layout(),
+        system_api: target.system_api(),
+    };
+    let (prog, warnings) = front::lower::lower(resolved, &settings)
+        .map_err(|e| render(&files, &Report::from(&e)))?;
+
+    let warnings = WarningLevels::default().diagnose(warnings).iter()
+        .map(|diagnostic| render(&files, &Report::from(diagnostic)))
+        .collect();
+    Ok((prog, warnings))
+}
+
+fn render(files: &Files, report: &Report) -> String {
+    Renderer { files, color: false }.render(report)
+}
+
+/// Lower `src` and run each of `passes` once, in order. Returns the resulting IR.
+pub fn run_passes(src: &str, passes: &[PassKind]) -> String {
+    let mut prog = lower_source(src);
+    for &kind in passes {
+        let pass = kind.create(&OptSettings::default());
+        pass.run(&mut prog);
+        verify(&prog).unwrap_or_else(|e| panic!("IR verification failed after {}: {}", pass.name(), e));
+    }
+    prog.display_stable().to_string()
+}
+
+/// Check `output` against the directives in `checks`, one per line. Other lines are ignored.
+/// * `CHECK: text` finds the first line containing `text` after the line matched by the previous directive
+/// * `CHECK-NEXT: text` requires the line right after the previous match to contain `text`
+/// * `CHECK-NOT: text` requires that no line between the surrounding matches contains `text`
+pub fn check(output: &str, checks: &str) {
+    let lines: Vec<&str> = output.lines().collect();
+    let fail = |message: String| -> ! { panic!("{}\nin output:\n{}", message, output) };
+
+    //the index of the first line after the last match
+    let mut next = 0;
+    let mut forbidden: Vec<&str> = vec![];
+
+    let check_forbidden = |forbidden: &mut Vec<&str>, range: std::ops::Range<usize>| {
+        for text in forbidden.drain(..) {
+            if let Some(i) = lines[range.clone()].iter().position(|line| line.contains(text)) {
+                fail(format!("CHECK-NOT: '{}' found on line {}: '{}'", text, range.start + i + 1, lines[range.start + i]));
+            }
+        }
+    };
+
+    for directive in checks.lines().map(str::trim) {
+        if let Some(text) = directive.strip_prefix("CHECK:") {
+            let text = text.trim();
+            let found = lines[next..].iter().position(|line| line.contains(text))
+                .unwrap_or_else(|| fail(format!("CHECK: '{}' not found after line {}", text, next)));
+            check_forbidden(&mut forbidden, next..next + found);
+            next += found + 1;
+        } else if let Some(text) = directive.strip_prefix("CHECK-NEXT:") {
+            let text = text.trim();
+            if !lines.get(next).is_some_and(|line| line.contains(text)) {
+                fail(format!("CHECK-NEXT: '{}' not found on line {}", text, next + 1));
+            }
+            check_forbidden(&mut forbidden, next..next);
+            next += 1;
+        } else if let Some(text) = directive.strip_prefix("CHECK-NOT:") {
+            forbidden.push(text.trim());
+        }
+    }
+
+    check_forbidden(&mut forbidden, next..lines.len());
+}
+
+/// Lower `snapshots/<name>.ll`, run `passes` and compare the result with `snapshots/<name>.ir`.
+pub fn check_golden(name: &str, passes: &[PassKind]) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/mid/opt/snapshots");
+    let src = std::fs::read_to_string(dir.join(name).with_extension("ll"))
+        .unwrap_or_else(|e| panic!("failed to read input for snapshot '{}': {}", name, e));
+    let expected_path = dir.join(name).with_extension("ir");
+
+    let actual = run_passes(&src, passes);
+
+    if std::env::var_os(BLESS_VAR).is_some() {
+        std::fs::write(&expected_path, &actual).unwrap();
+        return;
+    }
+
+    let expected = std::fs::read_to_string(&expected_path)
+        .unwrap_or_else(|e| panic!("failed to read snapshot '{}', run with {}=1 to create it: {}", name, BLESS_VAR, e));
+    if expected != actual {
+        panic!(
+            "snapshot '{}' does not match, run with {}=1 to update it\nexpected:\n{}\nactual:\n{}",
+            name, BLESS_VAR, expected, actual,
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mid::opt::PassKind::*;
+    use crate::mid::interp::interpret;
+    use crate::mid::opt::snapshot::{check, check_golden, lower_source, run_passes};
+
+    #[test]
+    fn check_directives() {
+        let output = "a\nb x\nc\nd x\n";
+        check(output, "CHECK: b\nCHECK-NEXT: c\nCHECK-NOT: e\nCHECK: d");
+        check(output, "ignored line\nCHECK: x\nCHECK: x");
+        assert!(std::panic::catch_unwind(|| check(output, "CHECK: c\nCHECK: b")).is_err());
+        assert!(std::panic::catch_unwind(|| check(output, "CHECK: a\nCHECK-NEXT: c")).is_err());
+        assert!(std::panic::catch_unwind(|| check(output, "CHECK: a\nCHECK-NOT: x\nCHECK: c")).is_err());
+        assert!(std::panic::catch_unwind(|| check(output, "CHECK: c\nCHECK-NOT: x")).is_err());
+    }
+
+    const COUNTER: &str = "fun main() -> int { let x = 1; x = x + 2; return x; }";
+
+    #[test]
+    fn slot_to_phi_removes_memory() {
+        check(&run_passes(COUNTER, &[]), "CHECK: slots:\nCHECK: Store\nCHECK: Load");
+        check(&run_passes(COUNTER, &[SlotToPhi]), "CHECK-NOT: slots:\nCHECK-NOT: Load\nCHECK-NOT: Store\nCHECK: Return");
+    }
+
+    #[test]
+    fn sccp_folds_constants() {
+        let output = run_passes(COUNTER, &[SlotToPhi, Sccp]);
+        check(&output, "CHECK: Return Const(3: i32)");
+    }
+
+    #[test]
+    fn undef_fold_picks_zero() {
+        let src = "fun main() -> int { let x: int; return x * 3; }";
+        check(&run_passes(src, &[SlotToPhi, PhiSimplify, UndefFold]), "CHECK-NOT: Arithmetic\nCHECK: Return Const(0: i32)");
+    }
+
+    #[test]
+    fn gvn_merges_expressions() {
+        let src = "fun f(a: int) -> int { return (a + 1) * (a + 1); } fun main() -> int { return f(2); }";
+        let output = run_passes(src, &[SlotToPhi, Gvn]);
+        check(&output, "
+            CHECK: Arithmetic { kind: Add
+            CHECK-NOT: Arithmetic { kind: Add
+            CHECK: Arithmetic { kind: Mul
+        ");
+    }
+
+    #[test]
+    fn golden_loop() {
+        check_golden("loop", &[Gc, SlotToPhi, PhiSimplify, Sccp, FlowSimplify, CfgCleanup]);
+    }
+
+    /// A program where `f` returns `10 * n` if condition `n` on `x` and `y` is the first one that holds, called with
+    /// `x = 1` and `y = 2`.
+    fn if_chain_source(conds: &[&str]) -> String {
+        //there is no `else if`, each else block contains the next if statement
+        let chain = conds.iter().enumerate().rev()
+            .fold("return 0;".to_owned(), |rest, (i, cond)| format!("if ({}) {{ return {}; }} else {{ {} }}", cond, 10 * (i + 1), rest));
+        format!("fun f(x: int, y: int) -> int {{ {} }} fun main() -> int {{ return f(1, 2); }}", chain)
+    }
+
+    fn if_chain(conds: &[&str]) -> String {
+        run_passes(&if_chain_source(conds), &[])
+    }
+
+    #[test]
+    fn dense_if_chain_becomes_switch() {
+        let output = if_chain(&["x == 1", "x == 2", "3 == x", "x == 4"]);
+        check(&output, "
+            CHECK: Switch {
+            CHECK-NEXT: value:
+            CHECK-NEXT: 1:
+            CHECK-NEXT: 2:
+            CHECK-NEXT: 3:
+            CHECK-NEXT: 4:
+            CHECK-NEXT: default:
+        ");
+        check(&output, "CHECK-NOT: Comparison");
+    }
+
+    #[test]
+    fn sparse_or_mixed_if_chain_stays_branches() {
+        for conds in [
+            ["x == 1", "x == 10", "x == 100", "x == 1000"],
+            ["x == 1", "y == 2", "x == 3", "x == 4"],
+            ["x == 1", "x == 2", "x < 3", "x == 4"],
+        ] {
+            let output = if_chain(&conds);
+            check(&output, "CHECK-NOT: Switch");
+            check(&output, "CHECK: Comparison\nCHECK: Branch");
+        }
+    }
+
+    #[test]
+    fn if_chain_switch_density_boundaries() {
+        //at least 4 distinct cases, spanning at most twice as many values
+        check(&if_chain(&["x == 1", "x == 2", "x == 3"]), "CHECK-NOT: Switch");
+        check(&if_chain(&["x == 1", "x == 2", "x == 3", "x == 8"]), "CHECK: Switch");
+        check(&if_chain(&["x == 1", "x == 2", "x == 3", "x == 9"]), "CHECK-NOT: Switch");
+        check(&if_chain(&["x == 1", "x == 2", "x == 3", "x == 1"]), "CHECK-NOT: Switch");
+    }
+
+    #[test]
+    fn duplicate_if_chain_case_keeps_first_arm() {
+        let conds = ["x == 1", "x == 2", "x == 1", "x == 3", "x == 4"];
+        check(&if_chain(&conds), "
+            CHECK: Switch {
+            CHECK-NEXT: value:
+            CHECK-NEXT: 1:
+            CHECK-NEXT: 2:
+            CHECK-NEXT: 3:
+            CHECK-NEXT: 4:
+            CHECK-NEXT: default:
+        ");
+
+        //x = 1 takes the first arm for that value
+        assert_eq!(Ok(10), interpret(&lower_source(&if_chain_source(&conds))));
+    }
+}
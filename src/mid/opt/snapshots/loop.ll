@@ -0,0 +1,12 @@
+fun main() -> int {
+    let sum = 0;
+    let i = 0;
+    while i < 10 {
+        sum = sum + i;
+        i = i + 1;
+    }
+    if false {
+        sum = 5;
+    }
+    return sum;
+}
@@ -0,0 +1,162 @@
+use crate::mid::analyse::dom_info::DomInfo;
+use crate::mid::analyse::use_info::UseInfo;
+use crate::mid::ir::{ArithmeticOp, CastKind, Const, Function, InstructionInfo, LogicalOp, Program, Terminator, Value};
+
+/// Fold instructions with undefined operands, following the semantics documented on `Value::Undef`: every use of
+/// undef can pick its own value, so each instruction is replaced by the result of the most convenient choice.
+///
+/// Instructions and terminators that are immediate undefined behaviour can't be executed, so the block is cut off
+/// there and ends in `Terminator::Unreachable` instead.
+pub fn undef_fold(prog: &mut Program) -> bool {
+    let use_info = UseInfo::new(prog);
+    let mut folded_count = 0;
+    let mut ub_count = 0;
+
+    let funcs: Vec<Function> = prog.nodes.funcs.iter().map(|(func, _)| func).collect();
+    for func in funcs {
+        let dom_info = DomInfo::new(prog, func);
+
+        //dominators are visited first, so operands are already folded when their users are visited
+        for &block in &dom_info.blocks {
+            let mut kept = vec![];
+            let mut is_ub = false;
+
+            for instr in prog.get_block(block).instructions.clone() {
+                let instr_info = prog.get_instr(instr);
+                if instr_info.immediate_ub().is_some() {
+                    //everything after this is dead, including the uses of the removed instructions
+                    is_ub = true;
+                    break;
+                }
+
+                match fold(prog, instr_info) {
+                    Some(value) => {
+                        use_info.replace_usages(prog, Value::Instr(instr), value);
+                        folded_count += 1;
+                    }
+                    None => kept.push(instr),
+                }
+            }
+
+            let block_info = prog.get_block_mut(block);
+            block_info.instructions = kept;
+            if is_ub || block_info.terminator.immediate_ub().is_some() {
+                block_info.terminator = Terminator::Unreachable;
+                ub_count += 1;
+            }
+        }
+    }
+
+    println!("undef_fold folded {} instructions and cut off {} blocks", folded_count, ub_count);
+    folded_count != 0 || ub_count != 0
+}
+
+/// The value `instr_info` can be replaced with, if any of its operands is undefined.
+fn fold(prog: &Program, instr_info: &InstructionInfo) -> Option<Value> {
+    let undef = |value: Value| matches!(value, Value::Undef(_));
+    let zero = |ty| Value::Const(Const::new(ty, 0));
+
+    match *instr_info {
+        InstructionInfo::Arithmetic { kind, left, right } if undef(left) || undef(right) => {
+            let ty = prog.type_of_value(left);
+            match kind {
+                //any result is possible, whatever the other operand is
+                ArithmeticOp::Add | ArithmeticOp::Sub => Some(Value::Undef(ty)),
+                //pick zero for the undefined operand, dividing by it is immediate UB and never gets here
+                ArithmeticOp::Mul | ArithmeticOp::Div(_) | ArithmeticOp::Mod(_) => Some(zero(ty)),
+            }
+        }
+        InstructionInfo::Comparison { kind, left, right } if undef(left) || undef(right) => {
+            //pick the undefined operand equal to the other one
+            let result = matches!(kind, LogicalOp::Eq | LogicalOp::Gte(_) | LogicalOp::Lte(_));
+            Some(Value::Const(Const::new(prog.ty_bool(), result as i32)))
+        }
+        InstructionInfo::Select { cond, true_value, false_value } => {
+            if undef(cond) || undef(false_value) {
+                Some(true_value)
+            } else if undef(true_value) {
+                Some(false_value)
+            } else {
+                None
+            }
+        }
+        InstructionInfo::Cast { kind, ty, value: Value::Undef(_) } => match kind {
+            CastKind::Truncate | CastKind::PointerToInt | CastKind::IntToPointer => Some(Value::Undef(ty)),
+            //the extended bits are fixed by the cast, so pick zero
+            CastKind::ZeroExtend | CastKind::SignExtend => Some(zero(ty)),
+        },
+        InstructionInfo::TupleFieldPtr { base: Value::Undef(_), .. } |
+        InstructionInfo::PointerOffSet { base: Value::Undef(_), .. } => Some(Value::Undef(prog.ty_ptr())),
+        InstructionInfo::PointerOffSet { base, index: Value::Undef(_), .. } => Some(base),
+        //floats, memory, calls and intrinsics are left alone
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mid::builder::IrBuilder;
+    use crate::mid::ir::{ArithmeticOp, CastKind, Const, InstructionInfo, LogicalOp, Program, Signedness, Target, Terminator, Value};
+    use crate::mid::opt::undef_fold::undef_fold;
+    use crate::mid::verify::verify_defined;
+
+    #[test]
+    fn fold_and_cut_off() {
+        let mut prog = Program::default();
+        let main = prog.main;
+        let ty_int = prog.ty_int();
+        let ty_bool = prog.ty_bool();
+        let ty_ptr = prog.ty_ptr();
+        let ty_byte = prog.define_type_int(8);
+
+        let mut builder = IrBuilder::at_entry(&mut prog, main);
+        let entry = builder.block();
+        let slot = builder.build_slot(main, ty_int);
+        let then_block = builder.new_block();
+        let else_block = builder.new_block();
+        let one = builder.const_int(ty_int, 1);
+        let sum = builder.build_binary(ArithmeticOp::Add, Value::Undef(ty_int), one);
+        let product = builder.build_binary(ArithmeticOp::Mul, sum, one);
+        let cmp = builder.build_compare(LogicalOp::Lt(Signedness::Signed), sum, one);
+        let byte = builder.build_cast(CastKind::Truncate, ty_byte, sum);
+        let extended = builder.build_cast(CastKind::ZeroExtend, ty_int, byte);
+        let offset = builder.build_pointer_offset(ty_int, slot, sum);
+        let loaded = builder.build_load(ty_int, offset);
+        let selected = builder.build_select(Value::Undef(ty_bool), extended, loaded);
+        let total = builder.build_binary(ArithmeticOp::Add, product, loaded);
+        let store = builder.build_store(slot, selected);
+        builder.build_branch(cmp, Target { block: then_block, phi_values: vec![] }, Target { block: else_block, phi_values: vec![] });
+
+        //storing trough undef means the return can't be reached
+        builder.position_at_end(then_block);
+        builder.build_store(Value::Undef(ty_ptr), total);
+        builder.build_return(total);
+
+        builder.position_at_end(else_block);
+        builder.build_branch(
+            Value::Undef(ty_bool),
+            Target { block: then_block, phi_values: vec![] },
+            Target { block: else_block, phi_values: vec![] },
+        );
+
+        assert!(undef_fold(&mut prog));
+        verify_defined(&prog).unwrap();
+
+        let zero = Value::Const(Const::new(ty_int, 0));
+        let instrs: Vec<Value> = prog.get_block(entry).instructions.iter().map(|&instr| Value::Instr(instr)).collect();
+        assert_eq!(vec![loaded, total, store], instrs);
+        let instr_info = |value: Value| match value {
+            Value::Instr(instr) => prog.get_instr(instr),
+            _ => unreachable!(),
+        };
+        assert!(matches!(instr_info(loaded), &InstructionInfo::Load { addr, .. } if addr == slot));
+        assert!(matches!(instr_info(total), &InstructionInfo::Arithmetic { left, .. } if left == zero));
+        assert!(matches!(instr_info(store), &InstructionInfo::Store { value, .. } if value == zero));
+        assert!(matches!(&prog.get_block(entry).terminator, &Terminator::Branch { cond, .. } if cond == Value::Const(Const::new(ty_bool, 0))));
+
+        assert!(prog.get_block(then_block).instructions.is_empty());
+        assert!(matches!(prog.get_block(then_block).terminator, Terminator::Unreachable));
+        assert!(matches!(prog.get_block(else_block).terminator, Terminator::Unreachable));
+        assert!(!undef_fold(&mut prog));
+    }
+}
@@ -0,0 +1,514 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+use crate::back::layout::Layout;
+use crate::mid::analyse::dom_info::DomInfo;
+use crate::mid::intrinsic::IntrinsicSignature;
+use crate::mid::ir::{Block, CastKind, Function, FunctionInfo, Global, Instruction, InstructionInfo, Phi, Program, Target, Terminator, Type, TypeInfo, Value};
+
+/// The place in the program where a `VerifyError` was found.
+#[derive(Debug, Copy, Clone)]
+pub enum Location {
+    Function(Function),
+    Block(Function, Block),
+    Instr(Function, Block, Instruction),
+    Terminator(Function, Block),
+    Global(Global),
+}
+
+/// An invariant of the IR that doesn't hold.
+#[derive(Debug)]
+pub struct VerifyError {
+    pub location: Location,
+    pub message: String,
+}
+
+type Result<T = ()> = std::result::Result<T, VerifyError>;
+
+fn error<T>(location: Location, message: String) -> Result<T> {
+    Err(VerifyError { location, message })
+}
+
+/// Check the structural invariants of the IR:
+/// * every block is part of a single function and only jumps to blocks that exist
+/// * targets pass a value of the right type for each phi of the target block
+/// * instruction and terminator operands have the types the instructions expect
+/// * values are only used in the function that defines them, and only where their definition dominates the use
+/// * global initializers have the size of their type
+///
+/// Only the blocks that are reachable from the entry of their function are checked.
+pub fn verify(prog: &Program) -> Result {
+    let mut block_funcs: HashMap<Block, Function> = HashMap::new();
+    for (func, _) in &prog.nodes.funcs {
+        prog.try_visit_blocks(func, |block| {
+            if !prog.nodes.blocks.contains(block) {
+                return error(Location::Function(func), format!("{:?} does not exist", block));
+            }
+            match block_funcs.insert(block, func) {
+                Some(other) => error(Location::Block(func, block), format!("block is also part of {:?}", other)),
+                None => Ok(()),
+            }
+        })?;
+    }
+
+    for (func, _) in &prog.nodes.funcs {
+        FuncVerifier::new(prog, func).verify()?;
+    }
+
+    for (global, global_info) in &prog.nodes.globals {
+        if let Some(init) = &global_info.init {
+            let size = Layout::for_type(prog, global_info.inner_ty).size;
+            if init.len() != size as usize {
+                return error(Location::Global(global), format!(
+                    "initializer has {} bytes but {} has size {}", init.len(), prog.format_type(global_info.inner_ty), size,
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Check the invariants of `verify`, and additionally that no reachable instruction or terminator is immediate
+/// undefined behaviour as defined by `InstructionInfo::immediate_ub` and `Terminator::immediate_ub`.
+///
+/// Programs can contain such code and still be valid, this is only guaranteed to hold after the `undef_fold` pass.
+pub fn verify_defined(prog: &Program) -> Result {
+    verify(prog)?;
+
+    for (func, _) in &prog.nodes.funcs {
+        prog.try_visit_blocks(func, |block| {
+            let block_info = prog.get_block(block);
+            for &instr in &block_info.instructions {
+                if let Some(reason) = prog.get_instr(instr).immediate_ub() {
+                    return error(Location::Instr(func, block, instr), format!("undefined behaviour: {}", reason));
+                }
+            }
+            match block_info.terminator.immediate_ub() {
+                Some(reason) => error(Location::Terminator(func, block), format!("undefined behaviour: {}", reason)),
+                None => Ok(()),
+            }
+        })?;
+    }
+
+    Ok(())
+}
+
+struct FuncVerifier<'a> {
+    prog: &'a Program,
+    func: Function,
+    func_info: &'a FunctionInfo,
+    dom_info: DomInfo,
+
+    /// The block that defines each instruction together with its index in that block.
+    instr_defs: HashMap<Instruction, (Block, usize)>,
+    phi_defs: HashMap<Phi, Block>,
+}
+
+impl<'a> FuncVerifier<'a> {
+    fn new(prog: &'a Program, func: Function) -> Self {
+        let dom_info = DomInfo::new(prog, func);
+
+        let mut instr_defs = HashMap::new();
+        let mut phi_defs = HashMap::new();
+        for &block in &dom_info.blocks {
+            let block_info = prog.get_block(block);
+            for (i, &instr) in block_info.instructions.iter().enumerate() {
+                instr_defs.entry(instr).or_insert((block, i));
+            }
+            for &phi in &block_info.phis {
+                phi_defs.entry(phi).or_insert(block);
+            }
+        }
+
+        FuncVerifier { prog, func, func_info: prog.get_func(func), dom_info, instr_defs, phi_defs }
+    }
+
+    fn verify(&self) -> Result {
+        let func = self.func;
+        let func_ty = &self.func_info.func_ty;
+
+        if self.prog.get_type(self.func_info.ty).unwrap_func() != Some(func_ty) {
+            return error(Location::Function(func), "type does not match the function type".to_owned());
+        }
+        if self.func_info.params.len() != func_ty.params.len() {
+            return error(Location::Function(func), format!(
+                "has {} params but its type expects {}", self.func_info.params.len(), func_ty.params.len(),
+            ));
+        }
+        for (&param, &ty) in self.func_info.params.iter().zip(&func_ty.params) {
+            self.expect_type(Location::Function(func), "param", Value::Param(param), ty)?;
+        }
+
+        self.check_target(Location::Function(func), None, &self.func_info.entry)?;
+
+        for &block in &self.dom_info.blocks {
+            let block_info = self.prog.get_block(block);
+
+            for (i, &instr) in block_info.instructions.iter().enumerate() {
+                let location = Location::Instr(func, block, instr);
+                if self.instr_defs[&instr] != (block, i) {
+                    return error(location, "instruction appears multiple times".to_owned());
+                }
+                self.check_instr(location, block, i)?;
+            }
+
+            let location = Location::Terminator(func, block);
+            self.check_terminator(location, block, &block_info.terminator)?;
+        }
+
+        Ok(())
+    }
+
+    fn check_instr(&self, location: Location, block: Block, index: usize) -> Result {
+        let instr = self.prog.get_block(block).instructions[index];
+        let operand = |value: Value| self.check_use(location, Some((block, index)), value);
+
+        match self.prog.get_instr(instr) {
+            &InstructionInfo::Load { addr, ty: _ } => {
+                operand(addr)?;
+                self.expect_ptr(location, "load address", addr)
+            }
+            &InstructionInfo::Store { addr, ty, value } => {
+                operand(addr)?;
+                operand(value)?;
+                self.expect_ptr(location, "store address", addr)?;
+                self.expect_type(location, "stored value", value, ty)
+            }
+            InstructionInfo::Call { target, args } => {
+                operand(*target)?;
+                for &arg in args {
+                    operand(arg)?;
+                }
+
+                let target_ty = self.prog.type_of_value(*target);
+                let func_ty = match self.prog.get_type(target_ty).unwrap_func() {
+                    Some(func_ty) => func_ty,
+                    None => return error(location, format!("call target has non-function type {}", self.prog.format_type(target_ty))),
+                };
+                if args.len() != func_ty.params.len() {
+                    return error(location, format!("call has {} arguments but the target expects {}", args.len(), func_ty.params.len()));
+                }
+                for (&arg, &param_ty) in args.iter().zip(&func_ty.params) {
+                    self.expect_type(location, "call argument", arg, param_ty)?;
+                }
+                Ok(())
+            }
+            &InstructionInfo::Arithmetic { kind: _, left, right } |
+            &InstructionInfo::Comparison { kind: _, left, right } |
+            &InstructionInfo::FloatArithmetic { kind: _, left, right } |
+            &InstructionInfo::FloatComparison { kind: _, left, right } => {
+                operand(left)?;
+                operand(right)?;
+
+                let left_ty = self.prog.type_of_value(left);
+                let (allowed, expected) = match self.prog.get_instr(instr) {
+                    InstructionInfo::Arithmetic { .. } => (self.is_int(left_ty), "an integer"),
+                    InstructionInfo::Comparison { .. } => (self.is_int(left_ty) || self.is_ptr(left_ty), "an integer or pointer"),
+                    _ => (self.prog.get_type(left_ty).unwrap_float().is_some(), "a float"),
+                };
+                if !allowed {
+                    return error(location, format!("left operand should be {}, got {}", expected, self.prog.format_type(left_ty)));
+                }
+                self.expect_type(location, "right operand", right, left_ty)
+            }
+            &InstructionInfo::TupleFieldPtr { base, index, tuple_ty } => {
+                operand(base)?;
+                self.expect_ptr(location, "tuple base", base)?;
+                match self.prog.get_type(tuple_ty).unwrap_tuple() {
+                    Some(tuple) if (index as usize) < tuple.fields.len() => Ok(()),
+                    Some(_) => error(location, format!("index {} out of range for {}", index, self.prog.format_type(tuple_ty))),
+                    None => error(location, format!("expected tuple type, got {}", self.prog.format_type(tuple_ty))),
+                }
+            }
+            &InstructionInfo::PointerOffSet { ty: _, base, index } => {
+                operand(base)?;
+                operand(index)?;
+                self.expect_ptr(location, "offset base", base)?;
+                self.expect_type(location, "offset index", index, self.prog.ty_int())
+            }
+            &InstructionInfo::Cast { kind, ty, value } => {
+                operand(value)?;
+
+                let value_ty = self.prog.type_of_value(value);
+                let from_bits = self.prog.get_type(value_ty).unwrap_int();
+                let to_bits = self.prog.get_type(ty).unwrap_int();
+                let valid = match kind {
+                    CastKind::PointerToInt => self.is_ptr(value_ty) && to_bits.is_some(),
+                    CastKind::IntToPointer => from_bits.is_some() && self.is_ptr(ty),
+                    CastKind::Truncate => matches!((from_bits, to_bits), (Some(from), Some(to)) if to < from),
+                    CastKind::ZeroExtend | CastKind::SignExtend => matches!((from_bits, to_bits), (Some(from), Some(to)) if to > from),
+                };
+                if !valid {
+                    return error(location, format!(
+                        "invalid {:?} cast from {} to {}", kind, self.prog.format_type(value_ty), self.prog.format_type(ty),
+                    ));
+                }
+                Ok(())
+            }
+            &InstructionInfo::MemCopy { dest, source, ty: _ } => {
+                operand(dest)?;
+                operand(source)?;
+                self.expect_ptr(location, "copy destination", dest)?;
+                self.expect_ptr(location, "copy source", source)
+            }
+            &InstructionInfo::MemSet { dest, value, ty: _ } => {
+                operand(dest)?;
+                operand(value)?;
+                self.expect_ptr(location, "set destination", dest)?;
+                let ty_byte = self.prog.type_of_value(value);
+                if self.prog.get_type(ty_byte).unwrap_int() != Some(8) {
+                    return error(location, format!("set value should be i8, got {}", self.prog.format_type(ty_byte)));
+                }
+                Ok(())
+            }
+            &InstructionInfo::Select { cond, true_value, false_value } => {
+                operand(cond)?;
+                operand(true_value)?;
+                operand(false_value)?;
+                self.expect_type(location, "select condition", cond, self.prog.ty_bool())?;
+                self.expect_type(location, "select false value", false_value, self.prog.type_of_value(true_value))
+            }
+            InstructionInfo::Intrinsic { kind, args } => {
+                for &arg in args {
+                    operand(arg)?;
+                }
+
+                match kind.info().signature {
+                    IntrinsicSignature::UnaryInt { whole_bytes } => {
+                        if args.len() != 1 {
+                            return error(location, format!("intrinsic {} has {} arguments but expects 1", kind, args.len()));
+                        }
+                        let arg_ty = self.prog.type_of_value(args[0]);
+                        match self.prog.get_type(arg_ty).unwrap_int() {
+                            Some(bits) if !whole_bytes || bits.is_multiple_of(8) => Ok(()),
+                            _ => error(location, format!("invalid argument type {} for intrinsic {}", self.prog.format_type(arg_ty), kind)),
+                        }
+                    }
+                    IntrinsicSignature::Nullary => {
+                        if !args.is_empty() {
+                            return error(location, format!("intrinsic {} has {} arguments but expects none", kind, args.len()));
+                        }
+                        Ok(())
+                    }
+                }
+            }
+            InstructionInfo::InlineAsm { template: _, inputs, outputs } => {
+                for input in inputs {
+                    operand(input.value)?;
+                    self.expect_type(location, "asm input", input.value, input.ty)?;
+                }
+                for output in outputs {
+                    operand(output.value)?;
+                    self.expect_ptr(location, "asm output", output.value)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn check_terminator(&self, location: Location, block: Block, terminator: &Terminator) -> Result {
+        //values used by the terminator must be available at the end of the block
+        let end = Some((block, self.prog.get_block(block).instructions.len()));
+
+        match terminator {
+            &Terminator::Branch { cond, .. } => {
+                self.check_use(location, end, cond)?;
+                self.expect_type(location, "branch condition", cond, self.prog.ty_bool())?;
+            }
+            &Terminator::Switch { value, .. } => {
+                self.check_use(location, end, value)?;
+                let ty = self.prog.type_of_value(value);
+                if !self.is_int(ty) {
+                    return error(location, format!("switch value should be an integer, got {}", self.prog.format_type(ty)));
+                }
+            }
+            &Terminator::Return { value } => {
+                self.check_use(location, end, value)?;
+                self.expect_type(location, "return value", value, self.func_info.func_ty.ret)?;
+            }
+            Terminator::Jump { .. } | Terminator::Unreachable => {}
+        }
+
+        let mut result = Ok(());
+        terminator.for_each_target(|target| {
+            if result.is_ok() {
+                result = self.check_target(location, end, target);
+            }
+        });
+        result
+    }
+
+    /// Check that `target` passes the right values to the phis of its block. `at` is the position the values are used
+    /// at, `None` for the entry of the function.
+    fn check_target(&self, location: Location, at: Option<(Block, usize)>, target: &Target) -> Result {
+        let block_info = self.prog.get_block(target.block);
+        if target.phi_values.len() != block_info.phis.len() {
+            return error(location, format!(
+                "target passes {} phi values but {:?} has {} phis", target.phi_values.len(), target.block, block_info.phis.len(),
+            ));
+        }
+
+        for (&value, &phi) in target.phi_values.iter().zip(&block_info.phis) {
+            self.check_use(location, at, value)?;
+            self.expect_type(location, "phi value", value, self.prog.get_phi(phi).ty)?;
+        }
+
+        Ok(())
+    }
+
+    /// Check that `value` can be used at position `at` in this function, `None` is the entry of the function.
+    fn check_use(&self, location: Location, at: Option<(Block, usize)>, value: Value) -> Result {
+        let valid = match value {
+            Value::Undef(_) | Value::Const(_) => true,
+            Value::Func(func) => self.prog.nodes.funcs.contains(func),
+            Value::Extern(ext) => self.prog.nodes.exts.contains(ext),
+            Value::Data(data) => self.prog.nodes.datas.contains(data),
+            Value::Global(global) => self.prog.nodes.globals.contains(global),
+            Value::Param(param) => self.func_info.params.contains(&param),
+            Value::Slot(slot) => self.func_info.slots.contains(&slot),
+            Value::Phi(phi) => match (self.phi_defs.get(&phi), at) {
+                (Some(&def), Some((block, _))) => self.dom_info.is_dominator(def, block),
+                (_, _) => false,
+            },
+            Value::Instr(instr) => match (self.instr_defs.get(&instr), at) {
+                (Some(&(def, def_index)), Some((block, index))) if def == block => def_index < index,
+                (Some(&(def, _)), Some((block, _))) => self.dom_info.is_dominator(def, block),
+                (_, _) => false,
+            },
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            error(location, format!("{:?} is not available here", value))
+        }
+    }
+
+    fn expect_type(&self, location: Location, what: &str, value: Value, expected: Type) -> Result {
+        let actual = self.prog.type_of_value(value);
+
+        //void values don't hold any data, so any value can stand in for them
+        if actual == expected || self.prog.get_type(expected) == &TypeInfo::Void {
+            Ok(())
+        } else {
+            error(location, format!(
+                "{} should have type {}, got {}", what, self.prog.format_type(expected), self.prog.format_type(actual),
+            ))
+        }
+    }
+
+    fn expect_ptr(&self, location: Location, what: &str, value: Value) -> Result {
+        self.expect_type(location, what, value, self.prog.ty_ptr())
+    }
+
+    fn is_int(&self, ty: Type) -> bool {
+        self.prog.get_type(ty).unwrap_int().is_some()
+    }
+
+    fn is_ptr(&self, ty: Type) -> bool {
+        self.prog.get_type(ty).is_ptr()
+    }
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.location {
+            Location::Function(func) => write!(f, "{:?}", func)?,
+            Location::Block(func, block) => write!(f, "{:?}, {:?}", func, block)?,
+            Location::Instr(func, block, instr) => write!(f, "{:?}, {:?}, {:?}", func, block, instr)?,
+            Location::Terminator(func, block) => write!(f, "{:?}, {:?}, terminator", func, block)?,
+            Location::Global(global) => write!(f, "{:?}", global)?,
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mid::ir::{ArithmeticOp, BlockInfo, Const, GlobalInfo, InstructionInfo, Program, Signedness, Target, Terminator, Value};
+    use crate::mid::verify::{verify, verify_defined};
+
+    #[test]
+    fn default_program() {
+        verify(&Program::default()).unwrap();
+    }
+
+    #[test]
+    fn operand_types() {
+        let mut prog = Program::default();
+        let entry = prog.get_func(prog.main).entry.block;
+
+        let one = Value::Const(Const::new(prog.ty_int(), 1));
+        let yes = Value::Const(Const::new(prog.ty_bool(), 1));
+        let add = prog.define_instr(InstructionInfo::Arithmetic { kind: ArithmeticOp::Add, left: one, right: yes });
+        prog.get_block_mut(entry).instructions.push(add);
+        prog.get_block_mut(entry).terminator = Terminator::Return { value: Value::Instr(add) };
+
+        let err = verify(&prog).unwrap_err();
+        assert_eq!("right operand should have type i32, got i1", err.message);
+    }
+
+    #[test]
+    fn use_before_def() {
+        let mut prog = Program::default();
+        let entry = prog.get_func(prog.main).entry.block;
+        let ty_int = prog.ty_int();
+        let one = Value::Const(Const::new(ty_int, 1));
+
+        //the second block uses an instruction from the first block, which doesn't dominate it
+        let block = prog.define_block(BlockInfo::new());
+        let other = prog.define_block(BlockInfo::new());
+        let add = prog.define_instr(InstructionInfo::Arithmetic { kind: ArithmeticOp::Add, left: one, right: one });
+        prog.get_block_mut(block).instructions.push(add);
+        prog.get_block_mut(block).terminator = Terminator::Return { value: Value::Instr(add) };
+        prog.get_block_mut(other).terminator = Terminator::Return { value: Value::Instr(add) };
+
+        let cond = Value::Const(Const::new(prog.ty_bool(), 1));
+        prog.get_block_mut(entry).terminator = Terminator::Branch {
+            cond,
+            true_target: Target { block, phi_values: vec![] },
+            false_target: Target { block: other, phi_values: vec![] },
+        };
+
+        let err = verify(&prog).unwrap_err();
+        assert!(err.message.ends_with("is not available here"), "{}", err);
+    }
+
+    #[test]
+    fn global_init_size() {
+        let mut prog = Program::default();
+        let ty_int = prog.ty_int();
+        prog.define_global(GlobalInfo { inner_ty: ty_int, mutable: false, init: Some(vec![1, 2]), debug_name: None });
+
+        let err = verify(&prog).unwrap_err();
+        assert_eq!("initializer has 2 bytes but i32 has size 4", err.message);
+    }
+
+    #[test]
+    fn immediate_ub() {
+        let mut prog = Program::default();
+        let entry = prog.get_func(prog.main).entry.block;
+        let ty_int = prog.ty_int();
+
+        //dividing by zero is valid IR, but undefined behaviour once it's executed
+        let one = Value::Const(Const::new(ty_int, 1));
+        let zero = Value::Const(Const::new(ty_int, 0));
+        let div = prog.define_instr(InstructionInfo::Arithmetic { kind: ArithmeticOp::Div(Signedness::Signed), left: one, right: zero });
+        prog.get_block_mut(entry).instructions.push(div);
+        prog.get_block_mut(entry).terminator = Terminator::Return { value: Value::Instr(div) };
+
+        verify(&prog).unwrap();
+        let err = verify_defined(&prog).unwrap_err();
+        assert_eq!("undefined behaviour: division by zero", err.message);
+
+        prog.get_block_mut(entry).instructions.clear();
+        prog.get_block_mut(entry).terminator = Terminator::Branch {
+            cond: Value::Undef(prog.ty_bool()),
+            true_target: Target { block: entry, phi_values: vec![] },
+            false_target: Target { block: entry, phi_values: vec![] },
+        };
+        let err = verify_defined(&prog).unwrap_err();
+        assert_eq!("undefined behaviour: branch on undefined condition", err.message);
+    }
+}
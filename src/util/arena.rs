@@ -87,6 +87,10 @@ impl<K: IndexType, T> Arena<K, T> {
         self.map.len()
     }
 
+    pub fn contains(&self, index: K) -> bool {
+        self.map.contains_key(&index.idx().i)
+    }
+
     pub fn iter(&self) -> impl Iterator<Item=(K, &T)> {
         self.into_iter()
     }
@@ -99,7 +103,7 @@ impl<K: IndexType, T> Arena<K, T> {
 impl<K: IndexType, T> Index<K> for Arena<K, T> {
     type Output = T;
     fn index(&self, index: K) -> &Self::Output {
-        &self.map.get(&index.idx().i)
+        self.map.get(&index.idx().i)
             .unwrap_or_else(|| panic!("Value {:?} not found", index))
     }
 }